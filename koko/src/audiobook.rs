@@ -0,0 +1,456 @@
+//! `koko audiobook`: an EPUB or Markdown book in, one audiobook file with chapter markers
+//! out. Each chapter is synthesized to its own WAV file first, kept next to the output so
+//! an interrupted run picks up where it stopped, and the chapters are then joined into an
+//! Opus file (chapters as `CHAPTERxxx` comments) or, through ffmpeg, an M4B.
+//!
+//! Chapters are the book's own (EPUB spine documents, Markdown headings), or those of an
+//! explicit chapter list: one title per line, the book being cut at the lines that read
+//! like them.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+use kokoros::input::epub::read_epub;
+use kokoros::input::markdown::{split_chapters, strip_markdown};
+use kokoros::tts::koko::TTSKoko;
+use kokoros::utils::audio;
+use kokoros::utils::opus::OggOpusWriter;
+use kokoros::utils::wav::BitDepth;
+
+use crate::batch::{SynthSettings, is_up_to_date, segment_key, synthesize_audio};
+use crate::epub::{slugify, wav_duration_sec};
+use crate::manifest::{MANIFEST_NAME, Manifest};
+use crate::progress::Progress;
+use crate::report::ItemReport;
+use crate::write_wav_file;
+
+/// Loudness the chapters are brought to without `--loudness`, in LUFS
+pub const DEFAULT_LOUDNESS: f32 = -19.0;
+
+/// Silence at the end of every chapter, in seconds
+const CHAPTER_GAP_SEC: f32 = 1.5;
+
+/// Bitrate of Opus output, plenty for a single voice
+const OPUS_BITRATE: u32 = 48_000;
+
+/// Title and author of the book, over what the book itself says
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// Chapter titles to cut the book at, over its own chapters
+    pub chapters: Option<Vec<String>>,
+}
+
+/// Reads a chapter list, one title per line; blank lines are skipped
+pub fn read_chapter_list(path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let titles: Vec<String> = fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if titles.is_empty() {
+        return Err(format!("no chapters listed in {}", path.display()).into());
+    }
+    Ok(titles)
+}
+
+/// Cuts `text` at the lines that read like `titles`, in order, ignoring case and Markdown
+/// heading marks. Text before the first is a chapter titled `untitled` when there is any.
+fn split_at_titles(
+    text: &str,
+    titles: &[String],
+    untitled: &str,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let mut starts = Vec::with_capacity(titles.len());
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let Some(title) = titles.get(starts.len()) else {
+            break;
+        };
+        if line
+            .trim()
+            .trim_start_matches('#')
+            .trim()
+            .eq_ignore_ascii_case(title)
+        {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    if let Some(missing) = titles.get(starts.len()) {
+        return Err(match starts.len() {
+            0 => format!("chapter \"{}\" not found in the book", missing),
+            n => format!(
+                "chapter \"{}\" not found in the book after \"{}\"",
+                missing,
+                titles[n - 1]
+            ),
+        }
+        .into());
+    }
+
+    let mut chapters = vec![(untitled.to_string(), text[..starts[0]].to_string())];
+    for (i, (start, title)) in starts.iter().zip(titles).enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(text.len());
+        chapters.push((title.clone(), text[*start..end].to_string()));
+    }
+    Ok(chapters)
+}
+
+/// A chapter once its audio is on disk
+struct Chapter {
+    title: String,
+    wav: PathBuf,
+    start_sec: f32,
+    duration_sec: f32,
+}
+
+/// The book's title, if it has one, and its chapters as titles and plain text
+type Book = (Option<String>, Vec<(String, String)>);
+
+fn read_book(path: &Path, titles: Option<&[String]>) -> Result<Book, Box<dyn std::error::Error>> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if extension == "epub" {
+        let book = read_epub(path)?;
+        let chapters = match titles {
+            Some(titles) => {
+                let text = book
+                    .chapters
+                    .iter()
+                    .map(|c| c.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                split_at_titles(&text, titles, "Introduction")?
+                    .into_iter()
+                    .filter(|(_, text)| !text.trim().is_empty())
+                    .collect()
+            }
+            None => book
+                .chapters
+                .into_iter()
+                .map(|c| (c.title, c.text))
+                .collect(),
+        };
+        return Ok((book.title, chapters));
+    }
+    let text = fs::read_to_string(path)?;
+    let chapters = match titles {
+        Some(titles) => split_at_titles(&text, titles, "Introduction")?,
+        None => split_chapters(&text, "Introduction"),
+    };
+    let chapters = chapters
+        .into_iter()
+        .map(|(title, markdown)| (title, strip_markdown(&markdown)))
+        .filter(|(_, text)| !text.is_empty())
+        .collect();
+    Ok((None, chapters))
+}
+
+/// `seconds` as `HH:MM:SS.mmm`, as chapter comments have it
+fn timestamp(seconds: f32) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// The Vorbis comments of an Opus audiobook, chapters included
+fn opus_tags(title: &str, author: Option<&str>, chapters: &[Chapter]) -> Vec<(String, String)> {
+    let mut tags = vec![
+        ("TITLE".to_string(), title.to_string()),
+        ("ALBUM".to_string(), title.to_string()),
+        ("GENRE".to_string(), "Audiobook".to_string()),
+        ("ENCODER".to_string(), "Kokoros TTS".to_string()),
+    ];
+    if let Some(author) = author {
+        tags.push(("ARTIST".to_string(), author.to_string()));
+    }
+    for (i, chapter) in chapters.iter().enumerate() {
+        tags.push((format!("CHAPTER{:03}", i + 1), timestamp(chapter.start_sec)));
+        tags.push((format!("CHAPTER{:03}NAME", i + 1), chapter.title.clone()));
+    }
+    tags
+}
+
+/// Escapes a value for an ffmpeg metadata file
+fn ffmetadata_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// An ffmpeg metadata file with the book's tags and chapters
+fn ffmetadata(title: &str, author: Option<&str>, chapters: &[Chapter]) -> String {
+    let mut metadata = format!(
+        ";FFMETADATA1\ntitle={}\nalbum={}\ngenre=Audiobook\n",
+        ffmetadata_escape(title),
+        ffmetadata_escape(title)
+    );
+    if let Some(author) = author {
+        metadata += &format!("artist={}\n", ffmetadata_escape(author));
+    }
+    for chapter in chapters {
+        metadata += &format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (chapter.start_sec * 1000.0).round() as u64,
+            ((chapter.start_sec + chapter.duration_sec) * 1000.0).round() as u64,
+            ffmetadata_escape(&chapter.title)
+        );
+    }
+    metadata
+}
+
+fn write_opus(
+    output: &Path,
+    tags: &[(String, String)],
+    chapters: &[Chapter],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tags: Vec<(&str, &str)> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let mut writer = OggOpusWriter::new(
+        BufWriter::new(File::create(output)?),
+        24_000,
+        OPUS_BITRATE,
+        &tags,
+    )?;
+    for chapter in chapters {
+        let mut reader = hound::WavReader::open(&chapter.wav)?;
+        let channels = reader.spec().channels as usize;
+        let samples: Vec<f32> = reader
+            .samples::<f32>()
+            .step_by(channels)
+            .collect::<Result<_, _>>()?;
+        writer.write(&samples)?;
+    }
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+fn write_m4b(
+    output: &Path,
+    metadata: &str,
+    chapters: &[Chapter],
+    work_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let list_path = work_dir.join("concat.txt");
+    let mut list = String::new();
+    for chapter in chapters {
+        let wav = fs::canonicalize(&chapter.wav)?;
+        list += &format!("file '{}'\n", wav.to_string_lossy().replace('\'', "'\\''"));
+    }
+    fs::write(&list_path, list)?;
+    let metadata_path = work_dir.join("metadata.txt");
+    fs::write(&metadata_path, metadata)?;
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-f", "concat", "-safe", "0", "-i"])
+        .arg(&list_path)
+        .arg("-i")
+        .arg(&metadata_path)
+        .args([
+            "-map", "0:a", "-map_metadata", "1", "-map_chapters", "1", "-c:a", "aac", "-b:a",
+            "64k", "-ac", "1", "-movflags", "+faststart",
+        ])
+        .arg(output)
+        .status()
+        .map_err(|e| {
+            format!(
+                "M4B output needs ffmpeg on the PATH ({}); write an .opus file instead to do without",
+                e
+            )
+        })?;
+    if !status.success() {
+        return Err(format!("ffmpeg failed with {}", status).into());
+    }
+    Ok(())
+}
+
+/// Synthesizes the book at `input` into the audiobook `output`, `.m4b` or `.opus`. Chapter
+/// WAV files go to a `<output>.chapters` directory, with a manifest of those that were
+/// completed, and are reused unless `force` is set.
+pub fn run(
+    tts: &TTSKoko,
+    input: &Path,
+    output: &Path,
+    settings: &SynthSettings,
+    metadata: &Metadata,
+    force: bool,
+    quiet: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let extension = output
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let m4b = match extension.as_str() {
+        "m4b" | "m4a" => true,
+        "opus" | "ogg" => false,
+        _ => return Err("the audiobook must be an .m4b or .opus file".into()),
+    };
+
+    let (book_title, texts) = read_book(input, metadata.chapters.as_deref())?;
+    if texts.is_empty() {
+        return Err(format!("no chapters found in {}", input.display()).into());
+    }
+    let title = metadata.title.clone().or(book_title).unwrap_or_else(|| {
+        input
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    });
+    eprintln!("Found {} chapters in {}", texts.len(), title);
+
+    let work_dir = output.with_extension("chapters");
+    fs::create_dir_all(&work_dir)?;
+    let manifest = Manifest::load(&work_dir.join(MANIFEST_NAME))?;
+    let progress = Progress::new(
+        texts.len(),
+        texts.iter().map(|(_, text)| text.chars().count()).sum(),
+        quiet,
+    );
+    let started = Instant::now();
+    let mut chapters = Vec::with_capacity(texts.len());
+    let mut start_sec = 0.0;
+    for (i, (chapter_title, text)) in texts.iter().enumerate() {
+        let chapter_started = Instant::now();
+        let item = progress.item(text.chars().count());
+        let settings = &SynthSettings {
+            progress: Some(&item),
+            ..*settings
+        };
+        let wav = work_dir.join(format!("{:03}_{}.wav", i + 1, slugify(chapter_title)));
+        let key = segment_key(text, &wav, settings)?;
+        let up_to_date = !force
+            && manifest
+                .completed(&wav, &key)
+                .unwrap_or_else(|| is_up_to_date(input, &wav));
+        let duration_sec = if up_to_date {
+            progress.message(&format!(
+                "[{}/{}] {} (up to date, skipped)",
+                i + 1,
+                texts.len(),
+                chapter_title
+            ));
+            wav_duration_sec(&wav)?
+        } else {
+            progress.message(&format!("[{}/{}] {}", i + 1, texts.len(), chapter_title));
+            manifest.start(&wav, &key);
+            let mut samples = synthesize_audio(tts, text, settings)?;
+            samples.extend(audio::silence(CHAPTER_GAP_SEC, 24_000));
+            // Float, as the chapters are encoded again
+            write_wav_file(
+                &wav.to_string_lossy(),
+                &samples,
+                24_000,
+                true,
+                BitDepth::Float32,
+            )?;
+            manifest.finish(&wav, &key, true);
+            samples.len() as f32 / 24_000.0
+        };
+        item.finish();
+        if let Some(report) = settings.report {
+            let chapter_input = format!("{}#{}", input.display(), i + 1);
+            report.emit(if up_to_date {
+                ItemReport::skipped(chapter_input, &wav, Some(duration_sec))
+            } else {
+                ItemReport::done(chapter_input, &wav, duration_sec, chapter_started.elapsed())
+            });
+        }
+        chapters.push(Chapter {
+            title: chapter_title.clone(),
+            wav,
+            start_sec,
+            duration_sec,
+        });
+        start_sec += duration_sec;
+    }
+    progress.finish();
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let author = metadata.author.as_deref();
+    if m4b {
+        write_m4b(
+            output,
+            &ffmetadata(&title, author, &chapters),
+            &chapters,
+            &work_dir,
+        )?;
+    } else {
+        write_opus(output, &opus_tags(&title, author, &chapters), &chapters)?;
+    }
+    if let Some(report) = settings.report {
+        report.emit(ItemReport::done(
+            input.display().to_string(),
+            output,
+            start_sec,
+            started.elapsed(),
+        ));
+    }
+    eprintln!(
+        "Audiobook saved to {} ({} chapters, {})",
+        output.display(),
+        chapters.len(),
+        timestamp(start_sec)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chapter_metadata() {
+        assert_eq!(timestamp(3725.5), "01:02:05.500");
+        let chapters = [
+            Chapter {
+                title: "One".to_string(),
+                wav: PathBuf::from("001_one.wav"),
+                start_sec: 0.0,
+                duration_sec: 61.25,
+            },
+            Chapter {
+                title: "Two; the end".to_string(),
+                wav: PathBuf::from("002_two-the-end.wav"),
+                start_sec: 61.25,
+                duration_sec: 10.0,
+            },
+        ];
+        let tags = opus_tags("Book", Some("Ann"), &chapters);
+        assert!(tags.contains(&("CHAPTER002".to_string(), "00:01:01.250".to_string())));
+        assert!(tags.contains(&("CHAPTER002NAME".to_string(), "Two; the end".to_string())));
+        assert!(tags.contains(&("ARTIST".to_string(), "Ann".to_string())));
+
+        let metadata = ffmetadata("Book", None, &chapters);
+        assert!(metadata.starts_with(";FFMETADATA1\ntitle=Book\n"));
+        assert!(metadata.contains("START=61250\nEND=71250\ntitle=Two\\; the end\n"));
+
+        let titles = ["One".to_string(), "Two".to_string()];
+        let text = "Preface.\n## one\nFirst.\nTwo\nSecond.\n";
+        let split = split_at_titles(text, &titles, "Introduction").unwrap();
+        assert_eq!(
+            split[0],
+            ("Introduction".to_string(), "Preface.\n".to_string())
+        );
+        assert_eq!(split[2], ("Two".to_string(), "Two\nSecond.\n".to_string()));
+        assert!(split_at_titles("Two\nOne\n", &titles, "Introduction").is_err());
+    }
+}
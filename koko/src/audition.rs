@@ -0,0 +1,57 @@
+use std::path::Path;
+
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::voices::voice_info;
+
+use crate::batch::{self, SynthSettings};
+use crate::playback::Player;
+
+/// Synthesizes `text` with every loaded voice into `output_dir/<voice>.wav`, each in the
+/// voice's own language. With a player, the samples are played one after another.
+pub fn run(
+    tts: &TTSKoko,
+    text: &str,
+    output_dir: &Path,
+    settings: &SynthSettings,
+    player: Option<&Player>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let voices = tts.get_available_voices();
+    let total = voices.len();
+
+    for (i, voice) in voices.iter().enumerate() {
+        let info = voice_info(voice);
+        let lan = if info.lan.is_empty() {
+            settings.lan
+        } else {
+            info.lan
+        };
+        let sample = SynthSettings {
+            lan,
+            timestamps: false,
+            read_along: false,
+            ..settings.for_voice(voice)
+        };
+
+        let save_path = output_dir.join(format!("{}.wav", voice));
+        eprintln!(
+            "[{}/{}] {} ({}, {})",
+            i + 1,
+            total,
+            voice,
+            info.language,
+            info.gender
+        );
+        match batch::synthesize_audio_to_file(tts, text, &save_path, &sample) {
+            Ok(audio) => {
+                if let Some(player) = player {
+                    player.append(&audio, 24_000);
+                    player.wait();
+                }
+            }
+            Err(e) => eprintln!("Error synthesizing {}: {}", voice, e),
+        }
+    }
+
+    eprintln!("Samples saved to {}", output_dir.display());
+    Ok(())
+}
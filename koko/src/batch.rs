@@ -0,0 +1,1030 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use kokoros::input::censor::Censor;
+use kokoros::input::dialogue::split_quotes;
+use kokoros::input::markdown::strip_markdown;
+use kokoros::input::prosody::{Prosody, SpanKind, prosody_spans};
+use kokoros::input::rows::read_rows;
+use kokoros::input::sounds::{Piece, Sound, has_sounds, split_sounds};
+use kokoros::tts::chunker;
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::normalize::TextOptions;
+use kokoros::tts::parallel::ChunkPool;
+use kokoros::tts::synthesis::{SynthProgress, SynthRequest};
+use kokoros::utils::assembler::{AudioAssembler, Join};
+use kokoros::utils::cache::CacheKey;
+use kokoros::utils::watermark::Watermark;
+use kokoros::utils::wav::BitDepth;
+use kokoros::utils::{audio, loudness};
+
+use crate::config::VoiceDefaults;
+use crate::manifest::Manifest;
+use crate::progress::{Item, Progress};
+use crate::report::{ItemReport, Reporter};
+use crate::split;
+use crate::template::{self, TemplateVars};
+use crate::{derive_tsv_path_from_wav, write_read_along, write_tsv, write_wav_file};
+
+/// File extensions picked up by directory batch processing
+const INPUT_EXTENSIONS: &[&str] = &["txt", "md"];
+
+/// Voice settings shared by every file in a batch run
+pub struct SynthSettings<'a> {
+    pub lan: &'a str,
+    pub style: &'a str,
+    pub speed: f32,
+    pub initial_silence: Option<usize>,
+    /// Trim leading and trailing silence
+    pub trim: bool,
+    /// Filter out DC offset and rumble below `audio::HIGH_PASS_HZ`
+    pub high_pass: bool,
+    /// Integrated loudness target in LUFS
+    pub loudness: Option<f32>,
+    /// Seconds of silence between synthesis chunks, replacing the model's own
+    pub pause: Option<f32>,
+    /// Voice for quoted speech, with narration in `style`
+    pub quote_voice: Option<&'a str>,
+    /// Speed for quoted speech
+    pub quote_speed: Option<f32>,
+    /// Pauses and rate changes around quotes and parentheticals
+    pub prosody: Option<Prosody>,
+    /// Words to bleep, silence or mask
+    pub censor: Option<&'a Censor>,
+    /// Instances to synthesize the chunks of one text on at once; empty (or a single
+    /// instance) for one chunk after another
+    pub pool: &'a [TTSKoko],
+    /// Bytes of audio that parallel synthesis may hold for chunks finished out of order
+    pub memory_budget: usize,
+    /// Settings that go with particular voices, from the config file
+    pub voice_defaults: &'a HashMap<String, VoiceDefaults>,
+    pub mono: bool,
+    /// Sample format of the WAV files written
+    pub bit_depth: BitDepth,
+    pub timestamps: bool,
+    /// Write a read-along JSON file next to the timestamps
+    pub read_along: bool,
+    /// Split outputs into numbered parts of at most this many samples
+    pub max_samples: Option<usize>,
+    /// Synthesize sentences that occur more than once in a text only once
+    pub dedupe: bool,
+    /// The engines' rewrites of the text, for telling outputs of other rewrites apart
+    pub text_options: &'a TextOptions,
+    /// Watermark to mark finished audio with
+    pub watermark: Option<&'a Watermark>,
+    /// Where to report each item processed, with `--json`
+    pub report: Option<&'a Reporter>,
+    /// The progress bar item to move as chunks are synthesized
+    pub progress: Option<&'a Item<'a>>,
+}
+
+impl<'a> SynthSettings<'a> {
+    /// These settings with `style` as the voice, plus its defaults from the config file.
+    /// The current voice's defaults were applied at startup, under any command line flags.
+    pub fn for_voice(&self, style: &'a str) -> SynthSettings<'a> {
+        let mut settings = SynthSettings { style, ..*self };
+        if style != self.style
+            && let Some(defaults) = self.voice_defaults.get(style)
+        {
+            settings.speed = defaults.speed.unwrap_or(settings.speed);
+            settings.trim = defaults.trim.unwrap_or(settings.trim);
+            settings.high_pass = defaults.high_pass.unwrap_or(settings.high_pass);
+            settings.loudness = defaults.loudness.or(settings.loudness);
+            settings.pause = defaults.pause.or(settings.pause);
+        }
+        settings
+    }
+
+    /// Whether synthesis takes more than a plain `tts_raw_audio` call: several instances,
+    /// quotes read in another voice, filtered words, or audio changed after the fact
+    pub fn post_processes(&self) -> bool {
+        self.pool.len() > 1
+            || self.trim
+            || self.high_pass
+            || self.loudness.is_some()
+            || self.pause.is_some()
+            || self.quote_voice.is_some()
+            || self.quote_speed.is_some()
+            || self.prosody.is_some()
+            || self.censor.is_some()
+            || self.watermark.is_some()
+            || self.dedupe
+    }
+
+    /// Settings for quoted speech
+    fn for_quotes(&self) -> SynthSettings<'a> {
+        let settings = self.for_voice(self.quote_voice.unwrap_or(self.style));
+        SynthSettings {
+            speed: self.quote_speed.unwrap_or(settings.speed),
+            ..settings
+        }
+    }
+}
+
+/// Synthesizes `text` with the quote voice, prosody, pause, trim and loudness settings
+/// applied
+pub fn synthesize_audio(
+    tts: &TTSKoko,
+    text: &str,
+    settings: &SynthSettings,
+) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    let censored = settings.censor.map(|censor| censor.apply(text));
+    let text = censored.as_deref().unwrap_or(text);
+    let mut audio = if has_sounds(text) {
+        // Each run of text is spoken as if on its own, and the whole is processed after
+        let spoken = SynthSettings {
+            trim: false,
+            high_pass: false,
+            loudness: None,
+            censor: None,
+            watermark: None,
+            ..*settings
+        };
+        let mut audio = Vec::new();
+        for piece in split_sounds(text) {
+            match piece {
+                Piece::Text(text) => audio.extend(synthesize_audio(tts, &text, &spoken)?),
+                Piece::Sound(Sound::Pause(pause)) => {
+                    audio.extend(audio::silence(pause.as_secs_f32(), 24_000))
+                }
+                Piece::Sound(Sound::Beep) => audio.extend(audio::beep(24_000)),
+                Piece::Sound(Sound::Clip(path)) => audio.extend(
+                    read_clip(&path)
+                        .map_err(|e| format!("failed to read sound {}: {}", path.display(), e))?,
+                ),
+            }
+        }
+        audio
+    } else if settings.quote_voice.is_some()
+        || settings.quote_speed.is_some()
+        || settings.prosody.is_some()
+    {
+        let quotes = settings.for_quotes();
+        let spans = match settings.prosody {
+            Some(_) => prosody_spans(text),
+            None => split_quotes(text)
+                .into_iter()
+                .map(|(quoted, span)| {
+                    let kind = if quoted {
+                        SpanKind::Quote
+                    } else {
+                        SpanKind::Narration
+                    };
+                    (kind, span)
+                })
+                .collect(),
+        };
+        let gap = settings
+            .prosody
+            .map(|prosody| audio::silence(prosody.pause, 24_000));
+        let mut audio = Vec::new();
+        for (kind, span) in spans {
+            let base = if kind == SpanKind::Quote {
+                &quotes
+            } else {
+                settings
+            };
+            let span_settings = SynthSettings {
+                speed: base.speed * settings.prosody.map_or(1.0, |prosody| prosody.rate(kind)),
+                ..*base
+            };
+            tracing::debug!(
+                "{:?} with {} at {:.2}: '{}'",
+                kind,
+                span_settings.style,
+                span_settings.speed,
+                span
+            );
+            let set_off = kind != SpanKind::Narration;
+            if let Some(gap) = gap.as_ref().filter(|_| set_off && !audio.is_empty()) {
+                audio.extend_from_slice(gap);
+            }
+            audio.extend(synthesize_span(tts, &span, &span_settings)?);
+            if let Some(gap) = gap.as_ref().filter(|_| set_off) {
+                audio.extend_from_slice(gap);
+            }
+        }
+        audio
+    } else {
+        synthesize_span(tts, text, settings)?
+    };
+    post_process(&mut audio, &mut [], settings);
+    Ok(audio)
+}
+
+/// A WAV file as mono samples at 24 kHz, for `[sfx:...]` tokens
+pub fn read_clip(path: &Path) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono: Vec<f32> = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+    Ok(audio::resample(&mono, spec.sample_rate, 24_000))
+}
+
+/// Synthesizes `text` in one voice, with the pause setting applied, and its repeated
+/// sentences only once with the dedupe setting
+fn synthesize_span(
+    tts: &TTSKoko,
+    text: &str,
+    settings: &SynthSettings,
+) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    if !settings.dedupe {
+        return synthesize_chunks(tts, text, settings);
+    }
+    let join = settings.pause.map_or(Join::Butt, Join::Silence);
+    let (audio, _) = synthesize_deduped(text, join, |segment| {
+        Ok((synthesize_chunks(tts, segment, settings)?, Vec::new()))
+    })?;
+    Ok(audio)
+}
+
+/// Audio with the words in it and their start and end in seconds
+type Timed = (Vec<f32>, Vec<(String, f32, f32)>);
+
+/// Synthesizes `text` with word timestamps, before post-processing, and its repeated
+/// sentences only once with the dedupe setting
+pub fn synthesize_timestamped(
+    tts: &TTSKoko,
+    text: &str,
+    settings: &SynthSettings,
+) -> Result<Timed, Box<dyn std::error::Error>> {
+    let synthesize = |text: &str| -> Result<_, Box<dyn std::error::Error>> {
+        let chars = text.chars().count();
+        let request = SynthRequest {
+            lan: settings.lan.to_string(),
+            speed: settings.speed,
+            initial_silence: settings.initial_silence,
+            ..SynthRequest::new(text, settings.style)
+        };
+        let result = tts.synthesize_with_progress(&request, None, |synth| {
+            if let Some(item) = settings.progress {
+                item.chunk(chars, synth);
+            }
+        })?;
+        let rows = result
+            .word_timings
+            .into_iter()
+            .map(|w| (w.word, w.start_sec, w.end_sec))
+            .collect();
+        Ok((result.samples, rows))
+    };
+    if settings.dedupe {
+        synthesize_deduped(text, Join::Butt, synthesize)
+    } else {
+        synthesize(text)
+    }
+}
+
+/// Synthesizes `text` one segment at a time with `synthesize`, a sentence that recurs only
+/// the first time, and joins the audio with `join` between segments, moving the word
+/// timings of each segment to where it lands
+fn synthesize_deduped(
+    text: &str,
+    join: Join,
+    mut synthesize: impl FnMut(&str) -> Result<Timed, Box<dyn std::error::Error>>,
+) -> Result<Timed, Box<dyn std::error::Error>> {
+    let mut done: HashMap<String, Timed> = HashMap::new();
+    let mut assembler = AudioAssembler::new(24_000);
+    let mut words = Vec::new();
+    let mut reused = 0;
+    for segment in chunker::split_repeats(text) {
+        let key = chunker::sentence_key(segment.text);
+        if segment.repeated {
+            if done.contains_key(&key) {
+                reused += 1;
+            } else {
+                done.insert(key.clone(), synthesize(segment.text)?);
+            }
+        }
+        let synthesized;
+        let (samples, segment_words) = match done.get(&key) {
+            Some(done) => done,
+            None => {
+                synthesized = synthesize(segment.text)?;
+                &synthesized
+            }
+        };
+        if samples.is_empty() {
+            continue;
+        }
+        let placement = assembler.push(samples, join);
+        words.extend(segment_words.iter().map(|(word, start, end)| {
+            (
+                word.clone(),
+                placement.to_track(*start),
+                placement.to_track(*end),
+            )
+        }));
+    }
+    if reused > 0 {
+        tracing::debug!("Reused the audio of {} repeated sentences", reused);
+    }
+    Ok((assembler.into_samples(), words))
+}
+
+/// Synthesizes `text` in one voice, with the pause setting applied, on all of
+/// `settings.pool` at once if there is more than one instance in it
+fn synthesize_chunks(
+    tts: &TTSKoko,
+    text: &str,
+    settings: &SynthSettings,
+) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    let mut assembler = AudioAssembler::new(24_000);
+    let add_chunk = |chunk: Vec<f32>| {
+        match settings.pause {
+            // The pause replaces the silence the model leaves around each chunk
+            Some(pause) => {
+                let speech = &chunk[audio::speech_range(&chunk, 24_000)];
+                if !speech.is_empty() {
+                    assembler.push(speech, Join::Silence(pause));
+                }
+            }
+            None => {
+                assembler.push(&chunk, Join::Butt);
+            }
+        }
+        Ok(())
+    };
+    let chars = text.chars().count();
+    let on_progress = |synth: &SynthProgress| {
+        if let Some(item) = settings.progress {
+            item.chunk(chars, synth);
+        }
+    };
+    if settings.pool.len() > 1 {
+        let pool = ChunkPool {
+            instances: settings.pool,
+            memory_budget: settings.memory_budget,
+            progress: Some(&on_progress),
+        };
+        pool.tts_raw_audio_streaming(
+            text,
+            settings.lan,
+            settings.style,
+            settings.speed,
+            settings.initial_silence,
+            add_chunk,
+        )?;
+    } else {
+        let request = SynthRequest {
+            lan: settings.lan.to_string(),
+            speed: settings.speed,
+            initial_silence: settings.initial_silence,
+            ..SynthRequest::new(text, settings.style)
+        };
+        tts.synthesize_streaming(&request, add_chunk, on_progress)?;
+    }
+    Ok(assembler.into_samples())
+}
+
+/// Applies the high-pass, trim, loudness and watermark settings to finished audio, moving
+/// word timestamps to match the trimmed start
+pub fn post_process(
+    samples: &mut Vec<f32>,
+    words: &mut [(String, f32, f32)],
+    settings: &SynthSettings,
+) {
+    if settings.high_pass {
+        audio::high_pass(samples, 24_000, audio::HIGH_PASS_HZ);
+    }
+    if settings.trim {
+        let offset = audio::trim_silence(samples, 24_000) as f32 / 24_000.0;
+        for (_, start, end) in words.iter_mut() {
+            *start = (*start - offset).max(0.0);
+            *end = (*end - offset).max(0.0);
+        }
+    }
+    if let Some(loudness) = settings.loudness {
+        loudness::normalize(samples, 24_000, loudness);
+    }
+    if let Some(watermark) = settings.watermark {
+        watermark.embed(samples);
+    }
+}
+
+/// Where a job's text comes from
+pub enum JobText {
+    /// A `.txt`/`.md` file, read when the job runs
+    File(PathBuf),
+    Inline(String),
+}
+
+/// One output file to synthesize, with optional overrides of the shared settings
+pub struct Job {
+    /// Human-readable origin of the text, used in progress and summary output
+    pub source: String,
+    /// File the text was read from, used to tell whether `output` is up to date
+    pub input: PathBuf,
+    pub text: JobText,
+    pub output: PathBuf,
+    pub voice: Option<String>,
+    pub speed: Option<f32>,
+    pub lan: Option<String>,
+}
+
+struct JobOutcome {
+    index: usize,
+    source: String,
+    output: PathBuf,
+    /// Seconds of audio written, or the reason the job failed
+    result: Result<f32, String>,
+}
+
+/// Recursively collects all `.txt`/`.md` files below `dir`, sorted for deterministic ordering
+pub fn collect_inputs(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| INPUT_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+            {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// `input` with its extension replaced by `wav`, or added to it (`b.md.wav`) when another
+/// of `inputs` differs from it only in its extension, so the two don't write one file
+pub fn wav_path(input: &Path, inputs: &[PathBuf]) -> PathBuf {
+    let stem = input.with_extension("");
+    let collides = inputs
+        .iter()
+        .any(|other| other != input && other.with_extension("") == stem);
+    if collides {
+        let mut name = input.as_os_str().to_owned();
+        name.push(".wav");
+        PathBuf::from(name)
+    } else {
+        input.with_extension("wav")
+    }
+}
+
+/// Maps `input_dir/a/b.md` to `output_dir/a/b.wav`, or `output_dir/a/b.md.wav` if `inputs`
+/// also has a `b.txt`
+pub fn mirrored_output_path(
+    input_dir: &Path,
+    output_dir: &Path,
+    input: &Path,
+    inputs: &[PathBuf],
+) -> PathBuf {
+    let output = wav_path(input, inputs);
+    output_dir.join(output.strip_prefix(input_dir).unwrap_or(&output))
+}
+
+/// Reads an input file, reducing Markdown to plain text
+pub fn read_input_text(path: &Path) -> std::io::Result<String> {
+    let content = fs::read_to_string(path)?;
+    let is_markdown = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("md"));
+
+    Ok(if is_markdown {
+        strip_markdown(&content)
+    } else {
+        content
+    })
+}
+
+/// Synthesizes `text` into a WAV file (plus TSV sidecar when requested), returning the audio duration.
+/// With `max_samples` set, the output is split into numbered parts instead.
+pub fn synthesize_to_file(
+    tts: &TTSKoko,
+    text: &str,
+    save_path: &Path,
+    settings: &SynthSettings,
+) -> Result<f32, Box<dyn std::error::Error>> {
+    if let Some(max_samples) = settings.max_samples {
+        let parts = split::synthesize_parts(tts, text, save_path, settings, max_samples)?;
+        return Ok(parts.iter().map(|(_, duration)| duration).sum());
+    }
+    let audio = synthesize_audio_to_file(tts, text, save_path, settings)?;
+    Ok(audio.len() as f32 / 24_000.0)
+}
+
+/// Like [`synthesize_to_file`], but hands back the samples that were written
+pub fn synthesize_audio_to_file(
+    tts: &TTSKoko,
+    text: &str,
+    save_path: &Path,
+    settings: &SynthSettings,
+) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    if let Some(parent) = save_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let save_path = save_path.to_string_lossy();
+
+    let audio = if settings.timestamps {
+        let (mut audio, mut rows) = synthesize_timestamped(tts, text, settings)?;
+        post_process(&mut audio, &mut rows, settings);
+        write_tsv(&derive_tsv_path_from_wav(&save_path), &rows)?;
+        if settings.read_along {
+            write_read_along(&save_path, text, &rows, &audio)?;
+        }
+        audio
+    } else {
+        synthesize_audio(tts, text, settings)?
+    };
+
+    write_wav_file(
+        &save_path,
+        &audio,
+        24_000,
+        settings.mono,
+        settings.bit_depth,
+    )?;
+    Ok(audio)
+}
+
+/// Builds one job per `.txt`/`.md` file under `input_dir`, mirrored into `output_dir`
+pub fn directory_jobs(input_dir: &Path, output_dir: &Path) -> std::io::Result<Vec<Job>> {
+    let inputs = collect_inputs(input_dir)?;
+    Ok(inputs
+        .iter()
+        .cloned()
+        .map(|input| Job {
+            source: input.display().to_string(),
+            input: input.clone(),
+            output: mirrored_output_path(input_dir, output_dir, &input, &inputs),
+            text: JobText::File(input),
+            voice: None,
+            speed: None,
+            lan: None,
+        })
+        .collect())
+}
+
+/// Refuses a row's output path that would be written outside the output directory: an
+/// absolute one, or one going up with `..`
+fn inside_output_dir(output: &str) -> Result<(), String> {
+    let escapes = Path::new(output).components().any(|component| {
+        !matches!(
+            component,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    });
+    if escapes {
+        Err(format!(
+            "output '{}' is outside the output directory",
+            output
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Builds one job per row of a CSV/JSONL script. Each row's `output` is an output path
+/// template resolved against `output_dir`; rows without one are written to `row_{line}.wav`.
+pub fn row_jobs(
+    script: &Path,
+    output_dir: &Path,
+    settings: &SynthSettings,
+) -> Result<Vec<Job>, Box<dyn std::error::Error>> {
+    let mut jobs = Vec::new();
+    for (i, row) in read_rows(script)?.into_iter().enumerate() {
+        let row_settings = settings.for_voice(row.voice.as_deref().unwrap_or(settings.style));
+        let vars = TemplateVars {
+            n: i + 1,
+            line: row.line,
+            text: &row.text,
+            settings: &SynthSettings {
+                lan: row.lan.as_deref().unwrap_or(settings.lan),
+                speed: row.speed.unwrap_or(row_settings.speed),
+                ..row_settings
+            },
+        };
+        let output = template::render(row.output.as_deref().unwrap_or("row_{line}.wav"), &vars)
+            .and_then(|output| inside_output_dir(&output).map(|()| output))
+            .map_err(|e| format!("{}:{}: {}", script.display(), row.line, e))?;
+
+        jobs.push(Job {
+            source: format!("{}:{}", script.display(), row.line),
+            input: script.to_path_buf(),
+            output: output_dir.join(output),
+            text: JobText::Inline(row.text),
+            voice: row.voice,
+            speed: row.speed,
+            lan: row.lan,
+        });
+    }
+    Ok(jobs)
+}
+
+/// Modification time of `path`, if it exists
+pub fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// True when `output` exists and is at least as new as `input`. An output that was
+/// split into numbered parts is judged by its first part.
+pub fn is_up_to_date(input: &Path, output: &Path) -> bool {
+    let output = modified(output).or_else(|| modified(&split::part_path(output, 1)));
+    match (modified(input), output) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+/// Reports a job left out of a run because its output is already there
+fn report_skipped(report: Option<&Reporter>, job: &Job) {
+    if let Some(report) = report {
+        report.emit(ItemReport::skipped(&job.source, &job.output, None));
+    }
+}
+
+/// Digest of what the audio of `text` written to `output` with `settings` depends on, the
+/// model included, for telling in a [`Manifest`] whether an output is of the same text and
+/// settings
+pub fn segment_key(text: &str, output: &Path, settings: &SynthSettings) -> Result<String, String> {
+    let format = output
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let processing = format!(
+        "trim={} high_pass={} pause={:?} quote_voice={:?} quote_speed={:?} prosody={:?} \
+         censor={:?} mono={} bit_depth={:?} max_samples={:?} dedupe={} watermark={:?} \
+         text={}",
+        settings.trim,
+        settings.high_pass,
+        settings.pause,
+        settings.quote_voice,
+        settings.quote_speed,
+        settings.prosody,
+        settings.censor,
+        settings.mono,
+        settings.bit_depth,
+        settings.max_samples,
+        settings.dedupe,
+        settings.watermark.map(Watermark::id),
+        settings.text_options.digest(),
+    );
+    Ok(CacheKey {
+        text,
+        voice: settings.style,
+        lan: settings.lan,
+        speed: settings.speed,
+        initial_silence: settings.initial_silence,
+        format: &format,
+        loudness: settings.loudness,
+        model: template::model_version()?,
+        scope: &processing,
+    }
+    .digest())
+}
+
+/// Drops jobs that the manifest has as completed with the same text and settings, so an
+/// interrupted run can be resumed. Jobs it doesn't know are dropped if their output
+/// already exists and is newer than their input.
+pub fn skip_up_to_date(jobs: Vec<Job>, settings: &SynthSettings, manifest: &Manifest) -> Vec<Job> {
+    let total = jobs.len();
+    let remaining: Vec<Job> = jobs
+        .into_iter()
+        .filter(|job| {
+            let completed = prepare(job, settings).ok().and_then(|(text, settings)| {
+                let key = segment_key(&text, &job.output, &settings).ok()?;
+                manifest.completed(&job.output, &key)
+            });
+            let up_to_date = completed.unwrap_or_else(|| is_up_to_date(&job.input, &job.output));
+            if up_to_date {
+                report_skipped(settings.report, job);
+            }
+            !up_to_date
+        })
+        .collect();
+    if remaining.len() < total {
+        eprintln!(
+            "Skipping {} up-to-date outputs (use --force to regenerate them)",
+            total - remaining.len()
+        );
+    }
+    remaining
+}
+
+/// Drops jobs whose output already exists, however old, for outputs named by their content
+pub fn skip_existing(jobs: Vec<Job>, report: Option<&Reporter>) -> Vec<Job> {
+    let total = jobs.len();
+    let remaining: Vec<Job> = jobs
+        .into_iter()
+        .filter(|job| {
+            let exists = job.output.exists() || split::part_path(&job.output, 1).exists();
+            if exists {
+                report_skipped(report, job);
+            }
+            !exists
+        })
+        .collect();
+    if remaining.len() < total {
+        eprintln!(
+            "Skipping {} outputs that already exist",
+            total - remaining.len()
+        );
+    }
+    remaining
+}
+
+/// The text of a job and the settings it is synthesized with
+fn prepare<'a>(
+    job: &'a Job,
+    settings: &SynthSettings<'a>,
+) -> Result<(String, SynthSettings<'a>), String> {
+    let text = match &job.text {
+        JobText::File(path) => read_input_text(path).map_err(|e| e.to_string())?,
+        JobText::Inline(text) => text.clone(),
+    };
+    if text.trim().is_empty() {
+        return Err("no text to synthesize".to_string());
+    }
+
+    let settings = settings.for_voice(job.voice.as_deref().unwrap_or(settings.style));
+    let settings = SynthSettings {
+        lan: job.lan.as_deref().unwrap_or(settings.lan),
+        speed: job.speed.unwrap_or(settings.speed),
+        ..settings
+    };
+    Ok((text, settings))
+}
+
+fn run_job(
+    tts: &TTSKoko,
+    job: &Job,
+    settings: &SynthSettings,
+    manifest: Option<&Manifest>,
+) -> Result<f32, String> {
+    let (text, settings) = prepare(job, settings)?;
+    let key = manifest
+        .map(|_| segment_key(&text, &job.output, &settings))
+        .transpose()?;
+    if let (Some(manifest), Some(key)) = (manifest, &key) {
+        manifest.start(&job.output, key);
+    }
+    let result = synthesize_to_file(tts, &text, &job.output, &settings).map_err(|e| e.to_string());
+    if let (Some(manifest), Some(key)) = (manifest, &key) {
+        manifest.finish(&job.output, key, result.is_ok());
+    }
+    result
+}
+
+/// Approximate number of characters a job will synthesize, for progress reporting
+fn job_size(job: &Job) -> usize {
+    match &job.text {
+        JobText::File(path) => fs::metadata(path).map_or(0, |m| m.len() as usize),
+        JobText::Inline(text) => text.chars().count(),
+    }
+}
+
+/// Runs all jobs, spreading them across the given TTS instances, recording each in
+/// `manifest` if given, and prints a summary report. Returns the number of failed jobs.
+pub fn run(
+    instances: &[TTSKoko],
+    jobs: &[Job],
+    settings: &SynthSettings,
+    manifest: Option<&Manifest>,
+    quiet: bool,
+) -> usize {
+    let total = jobs.len();
+    if total == 0 {
+        eprintln!("Nothing to synthesize");
+        return 0;
+    }
+
+    let sizes: Vec<usize> = jobs.iter().map(job_size).collect();
+    let progress = Progress::new(total, sizes.iter().sum(), quiet);
+    progress.message(&format!(
+        "Batch processing {} items with {} instance(s)",
+        total,
+        instances.len()
+    ));
+
+    let start = Instant::now();
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new(Vec::with_capacity(total));
+
+    std::thread::scope(|scope| {
+        for tts in instances {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(index) else {
+                        break;
+                    };
+
+                    let started = Instant::now();
+                    let item = progress.item(sizes[index]);
+                    let job_settings = SynthSettings {
+                        progress: Some(&item),
+                        ..*settings
+                    };
+                    let result = run_job(tts, job, &job_settings, manifest);
+                    if let Some(report) = settings.report {
+                        report.emit(match &result {
+                            Ok(duration_sec) => ItemReport::done(
+                                &job.source,
+                                &job.output,
+                                *duration_sec,
+                                started.elapsed(),
+                            )
+                            .with_split_parts(settings.max_samples),
+                            Err(e) => ItemReport::failed(&job.source, &job.output, e),
+                        });
+                    }
+                    match &result {
+                        Ok(_) => progress.message(&format!(
+                            "[{}/{}] {} -> {}",
+                            index + 1,
+                            total,
+                            job.source,
+                            job.output.display()
+                        )),
+                        Err(e) => progress.error(&format!(
+                            "[{}/{}] {} failed: {}",
+                            index + 1,
+                            total,
+                            job.source,
+                            e
+                        )),
+                    }
+                    item.finish();
+
+                    outcomes.lock().unwrap().push(JobOutcome {
+                        index,
+                        source: job.source.clone(),
+                        output: job.output.clone(),
+                        result,
+                    });
+                }
+            });
+        }
+    });
+
+    progress.finish();
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|o| o.index);
+    let summary = summary(&outcomes, start.elapsed().as_secs_f32());
+    // stdout is kept for the JSON lines with --json
+    if settings.report.is_some() {
+        eprint!("{}", summary);
+    } else {
+        print!("{}", summary);
+    }
+
+    outcomes.iter().filter(|o| o.result.is_err()).count()
+}
+
+fn summary(outcomes: &[JobOutcome], elapsed_sec: f32) -> String {
+    use std::fmt::Write;
+
+    let failed: Vec<&JobOutcome> = outcomes.iter().filter(|o| o.result.is_err()).collect();
+    let audio_sec: f32 = outcomes.iter().filter_map(|o| o.result.as_ref().ok()).sum();
+
+    let mut summary = String::new();
+    let _ = writeln!(summary, "==========================================");
+    let _ = writeln!(
+        summary,
+        "Batch complete: {} items, {} succeeded, {} failed",
+        outcomes.len(),
+        outcomes.len() - failed.len(),
+        failed.len()
+    );
+    let _ = writeln!(
+        summary,
+        "Audio generated: {:.1}s in {:.1}s",
+        audio_sec, elapsed_sec
+    );
+    if audio_sec > 0.0 {
+        let _ = writeln!(summary, "Real-time factor: {:.3}", elapsed_sec / audio_sec);
+    }
+    for outcome in failed {
+        if let Err(e) = &outcome.result {
+            let _ = writeln!(
+                summary,
+                "  FAILED {} -> {}: {}",
+                outcome.source,
+                outcome.output.display(),
+                e
+            );
+        }
+    }
+    let _ = writeln!(summary, "==========================================");
+    summary
+}
+
+/// Plain settings for tests, a voice and nothing changed after synthesis
+#[cfg(test)]
+pub(crate) fn test_settings<'a>(
+    voice_defaults: &'a HashMap<String, VoiceDefaults>,
+    text_options: &'a TextOptions,
+) -> SynthSettings<'a> {
+    SynthSettings {
+        lan: "en-us",
+        style: "af_sky",
+        speed: 1.0,
+        initial_silence: None,
+        trim: false,
+        high_pass: false,
+        loudness: None,
+        pause: None,
+        quote_voice: None,
+        quote_speed: None,
+        prosody: None,
+        censor: None,
+        pool: &[],
+        memory_budget: 0,
+        voice_defaults,
+        mono: false,
+        bit_depth: BitDepth::default(),
+        timestamps: false,
+        read_along: false,
+        max_samples: None,
+        dedupe: false,
+        text_options,
+        watermark: None,
+        report: None,
+        progress: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_path() {
+        let inputs = [
+            PathBuf::from("in/a.txt"),
+            PathBuf::from("in/a.md"),
+            PathBuf::from("in/b.md"),
+            PathBuf::from("in/sub/a.md"),
+        ];
+        assert_eq!(wav_path(&inputs[0], &inputs), Path::new("in/a.txt.wav"));
+        assert_eq!(wav_path(&inputs[1], &inputs), Path::new("in/a.md.wav"));
+        assert_eq!(wav_path(&inputs[2], &inputs), Path::new("in/b.wav"));
+        assert_eq!(
+            mirrored_output_path(Path::new("in"), Path::new("out"), &inputs[3], &inputs),
+            Path::new("out/sub/a.wav")
+        );
+        assert_eq!(
+            mirrored_output_path(Path::new("in"), Path::new("out"), &inputs[1], &inputs),
+            Path::new("out/a.md.wav")
+        );
+    }
+
+    #[test]
+    fn test_inside_output_dir() {
+        assert!(inside_output_dir("a.wav").is_ok());
+        assert!(inside_output_dir("./voices/af_sky/1.wav").is_ok());
+        assert!(inside_output_dir("/etc/a.wav").is_err());
+        assert!(inside_output_dir("../a.wav").is_err());
+        assert!(inside_output_dir("sub/../../a.wav").is_err());
+    }
+
+    #[test]
+    fn test_segment_key() {
+        template::set_test_model();
+        let voice_defaults = HashMap::new();
+        let text_options = TextOptions::default();
+        let settings = test_settings(&voice_defaults, &text_options);
+        let output = Path::new("out/a.wav");
+        let key = segment_key("Hello", output, &settings).unwrap();
+        assert_eq!(segment_key("Hello", output, &settings).unwrap(), key);
+        assert_ne!(segment_key("Hello!", output, &settings).unwrap(), key);
+
+        let spelled = TextOptions {
+            spell_out: true,
+            ..TextOptions::default()
+        };
+        let settings = SynthSettings {
+            text_options: &spelled,
+            ..settings
+        };
+        assert_ne!(segment_key("Hello", output, &settings).unwrap(), key);
+    }
+}
@@ -0,0 +1,324 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use kokoros::tts::koko::{Provider, SessionOptions, TTSKoko};
+use kokoros::tts::synthesis::{StageTimings, SynthRequest};
+use kokoros::utils::mp3::pcm_to_mp3;
+use kokoros::utils::wav::{self, WavHeader};
+
+use crate::batch::SynthSettings;
+
+/// Fixed corpus so results are comparable between machines and releases
+const CORPUS: &[&str] = &[
+    "Hello, world.",
+    "The quick brown fox jumps over the lazy dog.",
+    "On March 3rd, 2021, the committee approved a budget of $4.5 million for 12 new projects.",
+    "It was the best of times, it was the worst of times, it was the age of wisdom, it was the age of foolishness, it was the epoch of belief, it was the epoch of incredulity.",
+    "Please call me back at your earliest convenience; I'll be in the office until five o'clock.",
+    "Kokoro is an open-weight text to speech model with eighty two million parameters. Despite its lightweight architecture, it delivers quality comparable to larger models while being significantly faster and more cost-efficient.",
+];
+
+/// Time spent in each stage over a run, summed over the instances
+#[derive(Default)]
+struct Stages {
+    synthesis: StageTimings,
+    wav: Duration,
+    mp3: Duration,
+}
+
+struct Stats {
+    wall_sec: f32,
+    audio_sec: f32,
+    failures: usize,
+    stages: Stages,
+}
+
+/// The memory figure `field` of this process in MiB, where the platform reports it
+fn status_mib(field: &str) -> Option<f32> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with(field))?;
+    let kib: f32 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib / 1024.0)
+}
+
+fn mib(mib: Option<f32>) -> String {
+    mib.map_or_else(|| "n/a".to_string(), |mib| format!("{:.0}", mib))
+}
+
+/// Encodes `samples` as a WAV file and as MP3 in memory, adding the time each took to
+/// `stages`
+fn encode(samples: &[f32], sample_rate: u32, settings: &SynthSettings, stages: &mut Stages) {
+    let start = Instant::now();
+    let mut bytes = Vec::with_capacity(44 + samples.len() * 4);
+    let header = WavHeader::new(1, sample_rate, settings.bit_depth.bits());
+    let written = header
+        .write_header(&mut bytes)
+        .and_then(|()| wav::write_samples(&mut bytes, samples, settings.bit_depth));
+    stages.wav += start.elapsed();
+    let start = Instant::now();
+    let encoded = written.and_then(|()| pcm_to_mp3(samples, sample_rate));
+    stages.mp3 += start.elapsed();
+    if let Err(e) = encoded {
+        eprintln!("Encoding failed: {}", e);
+    }
+}
+
+fn run_config(instances: &[TTSKoko], iterations: usize, settings: &SynthSettings) -> Stats {
+    let work: Vec<&str> = (0..iterations)
+        .flat_map(|_| CORPUS.iter().copied())
+        .collect();
+    let next = AtomicUsize::new(0);
+    let audio_sec = Mutex::new(0.0f32);
+    let stages = Mutex::new(Stages::default());
+    let failures = AtomicUsize::new(0);
+
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for tts in instances {
+            scope.spawn(|| {
+                while let Some(text) = work.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let request = SynthRequest {
+                        lan: settings.lan.to_string(),
+                        speed: settings.speed,
+                        initial_silence: settings.initial_silence,
+                        ..SynthRequest::new(*text, settings.style)
+                    };
+                    match tts.synthesize(&request) {
+                        Ok(result) => {
+                            let mut timed = Stages {
+                                synthesis: result.timings,
+                                ..Stages::default()
+                            };
+                            encode(&result.samples, result.sample_rate, settings, &mut timed);
+                            *audio_sec.lock().unwrap() += result.duration_sec();
+                            let mut stages = stages.lock().unwrap();
+                            stages.synthesis += timed.synthesis;
+                            stages.wav += timed.wav;
+                            stages.mp3 += timed.mp3;
+                        }
+                        Err(e) => {
+                            failures.fetch_add(1, Ordering::Relaxed);
+                            eprintln!("Synthesis failed: {}", e);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    Stats {
+        wall_sec: start.elapsed().as_secs_f32(),
+        audio_sec: audio_sec.into_inner().unwrap(),
+        failures: failures.into_inner(),
+        stages: stages.into_inner().unwrap(),
+    }
+}
+
+/// The session options to compare: `base` with each of `providers`, `intra_threads` and
+/// `inter_threads` in turn, every combination of them; an empty list keeps the setting
+/// of `base`
+pub fn sweep(
+    base: &SessionOptions,
+    providers: &[Provider],
+    intra_threads: &[usize],
+    inter_threads: &[usize],
+) -> Vec<SessionOptions> {
+    let providers: Vec<Option<Provider>> = match providers {
+        [] => vec![base.provider],
+        providers => providers.iter().copied().map(Some).collect(),
+    };
+    let intra: Vec<Option<usize>> = match intra_threads {
+        [] => vec![base.intra_threads],
+        threads => threads.iter().copied().map(Some).collect(),
+    };
+    let inter: Vec<Option<usize>> = match inter_threads {
+        [] => vec![base.inter_threads],
+        threads => threads.iter().copied().map(Some).collect(),
+    };
+    let mut sweep = Vec::with_capacity(providers.len() * intra.len() * inter.len());
+    for &provider in &providers {
+        for &intra_threads in &intra {
+            for &inter_threads in &inter {
+                sweep.push(SessionOptions {
+                    provider,
+                    intra_threads,
+                    inter_threads,
+                    ..base.clone()
+                });
+            }
+        }
+    }
+    sweep
+}
+
+fn threads(threads: Option<usize>) -> String {
+    threads.map_or_else(|| "default".to_string(), |n| n.to_string())
+}
+
+/// Milliseconds per pass over the corpus
+fn per_pass_ms(time: Duration, iterations: usize) -> f32 {
+    time.as_secs_f32() * 1000.0 / iterations as f32
+}
+
+/// A benchmark of the built-in corpus, run with one table per session configuration
+pub struct Bench<'a> {
+    iterations: usize,
+    settings: &'a SynthSettings<'a>,
+    chars: usize,
+    tokens: usize,
+    /// Of the last configuration run, for the seconds per token
+    audio_sec: f32,
+    /// Resident memory once each instance of the next configuration was loaded
+    resident: Vec<Option<f32>>,
+}
+
+impl<'a> Bench<'a> {
+    /// Phonemizes the corpus as `tts` would, prints how long that took and what the
+    /// corpus is, to run it `iterations` times in each configuration
+    pub fn new(
+        tts: &TTSKoko,
+        iterations: usize,
+        settings: &'a SynthSettings<'a>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let chars: usize = CORPUS.iter().map(|t| t.chars().count()).sum::<usize>() * iterations;
+
+        // Phonemization runs under a global espeak lock, so it is measured on its own
+        let start = Instant::now();
+        for text in CORPUS {
+            tts.text_options().spoken_text(text, settings.lan)?;
+        }
+        let normalize_sec = start.elapsed().as_secs_f32();
+        let start = Instant::now();
+        let mut tokens = 0;
+        for text in CORPUS {
+            let chunks =
+                TTSKoko::phonemize(text, settings.lan, tts.text_options(), tts.model_version())?;
+            tokens += chunks.iter().map(|c| c.tokens.len()).sum::<usize>();
+        }
+        let phonemize_sec = (start.elapsed().as_secs_f32() - normalize_sec).max(0.0);
+        let tokens = tokens * iterations;
+
+        println!("==========================================");
+        println!(
+            "Corpus: {} sentences x {} iterations, {} chars, {} tokens",
+            CORPUS.len(),
+            iterations,
+            chars,
+            tokens
+        );
+        println!(
+            "Normalization: {:.1} ms, phonemization: {:.1} ms per corpus pass ({:.0} chars/s)",
+            normalize_sec * 1000.0,
+            phonemize_sec * 1000.0,
+            (chars / iterations) as f32 / phonemize_sec.max(1e-6)
+        );
+        Ok(Self {
+            iterations,
+            settings,
+            chars,
+            tokens,
+            audio_sec: 0.0,
+            resident: Vec::new(),
+        })
+    }
+
+    /// Records the resident memory once another instance for the next [`run`](Self::run)
+    /// is loaded, to show how much each one adds
+    pub fn loaded(&mut self) {
+        self.resident.push(status_mib("VmRSS:"));
+    }
+
+    /// Runs the corpus on each number of `instances` in `configs` and prints a table of
+    /// the timings and of the memory resident once that many were loaded, headed by the
+    /// `session` they were loaded with. `instances` must hold at least as many loaded
+    /// instances as the largest config.
+    pub fn run(&mut self, instances: &[TTSKoko], configs: &[usize], session: &SessionOptions) {
+        let (iterations, settings) = (self.iterations, self.settings);
+        println!();
+        println!(
+            "Session: {} provider, {} intra-op threads, {} inter-op threads",
+            instances[0].execution_provider(),
+            threads(session.intra_threads),
+            threads(session.inter_threads)
+        );
+        eprintln!("Warming up...");
+        run_config(&instances[..1], 1, settings);
+
+        println!(
+            "{:>9} {:>9} {:>9} {:>7} {:>10} {:>9} | {:>9} {:>9} {:>9} {:>7} {:>7} | {:>7}",
+            "instances",
+            "wall_s",
+            "audio_s",
+            "rtf",
+            "tokens/s",
+            "chars/s",
+            "norm_ms",
+            "phon_ms",
+            "infer_ms",
+            "wav_ms",
+            "mp3_ms",
+            "mem_mib"
+        );
+        let resident = std::mem::take(&mut self.resident);
+        for &n in configs {
+            let stats = run_config(&instances[..n], iterations, settings);
+            self.audio_sec = stats.audio_sec;
+            let stages = &stats.stages;
+            println!(
+                "{:>9} {:>9.2} {:>9.2} {:>7.3} {:>10.0} {:>9.0} | {:>9.1} {:>9.1} {:>9.1} {:>7.1} {:>7.1} | {:>7}{}",
+                n,
+                stats.wall_sec,
+                stats.audio_sec,
+                stats.wall_sec / stats.audio_sec.max(1e-6),
+                self.tokens as f32 / stats.wall_sec,
+                self.chars as f32 / stats.wall_sec,
+                per_pass_ms(stages.synthesis.normalize, iterations),
+                per_pass_ms(stages.synthesis.phonemize, iterations),
+                per_pass_ms(stages.synthesis.inference, iterations),
+                per_pass_ms(stages.wav, iterations),
+                per_pass_ms(stages.mp3, iterations),
+                mib(resident.get(n - 1).copied().flatten()),
+                if stats.failures > 0 {
+                    format!("  ({} failed)", stats.failures)
+                } else {
+                    String::new()
+                }
+            );
+        }
+    }
+
+    /// Prints the seconds of audio per token of the last configuration and the peak memory
+    pub fn finish(&self) {
+        println!();
+        // The calibration `--estimate` uses, normalized to speed 1.0
+        println!(
+            "Seconds per token: {:.4}",
+            self.audio_sec * self.settings.speed / self.tokens.max(1) as f32
+        );
+        println!("Peak memory: {} MiB", mib(status_mib("VmHWM:")));
+        println!("==========================================");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let base = SessionOptions {
+            intra_threads: Some(4),
+            ..SessionOptions::default()
+        };
+        assert_eq!(sweep(&base, &[], &[], &[]), vec![base.clone()]);
+
+        let points = sweep(&base, &[Provider::Cpu], &[1, 2], &[1, 2, 4]);
+        assert_eq!(points.len(), 6);
+        assert!(points.iter().all(|p| p.provider == Some(Provider::Cpu)));
+        assert_eq!(points[1].intra_threads, Some(1));
+        assert_eq!(points[1].inter_threads, Some(2));
+        assert_eq!(points[5].intra_threads, Some(2));
+        assert_eq!(points[5].inter_threads, Some(4));
+    }
+}
@@ -0,0 +1,73 @@
+//! `koko chat`: asks an OpenAI-compatible chat endpoint and speaks the answer while it is
+//! being generated, printing its text as it arrives (`chat` feature)
+
+use std::io::Write;
+
+use kokoros::tts::chat::{ChatEvent, ChatMessage, ChatRequest, voice_chat};
+use kokoros::tts::koko::TTSKoko;
+
+use crate::batch::SynthSettings;
+use crate::playback::Player;
+use crate::write_wav_file;
+
+/// The request for `prompt`. Unless `system` replaces it, the system prompt asks for plain
+/// prose, since markdown and lists read badly aloud.
+pub fn request(
+    endpoint: &str,
+    model: &str,
+    api_key: Option<String>,
+    system: Option<&str>,
+    prompt: &str,
+) -> ChatRequest {
+    let system = system.unwrap_or(
+        "You are a helpful assistant. Your answers are read aloud, so write plain spoken \
+         sentences, without markdown, lists or code.",
+    );
+    ChatRequest {
+        endpoint: endpoint.to_string(),
+        api_key: api_key.or_else(|| std::env::var("OPENAI_API_KEY").ok()),
+        model: model.to_string(),
+        messages: vec![
+            ChatMessage::new("system", system),
+            ChatMessage::new("user", prompt),
+        ],
+    }
+}
+
+/// Speaks the answer to `request` on `player` and/or writes it to `save_path`
+pub async fn run(
+    tts: &TTSKoko,
+    request: ChatRequest,
+    settings: &SynthSettings<'_>,
+    player: Option<&Player>,
+    save_path: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut answer = voice_chat(tts, request, settings.lan, settings.style, settings.speed);
+    let mut audio = Vec::new();
+    let mut stdout = std::io::stdout();
+    while let Some(event) = answer.next().await {
+        match event? {
+            ChatEvent::Text(text) => {
+                stdout.write_all(text.as_bytes())?;
+                stdout.flush()?;
+            }
+            ChatEvent::Audio(chunk) => {
+                if let Some(player) = player {
+                    player.append(&chunk, 24_000);
+                }
+                if save_path.is_some() {
+                    audio.extend(chunk);
+                }
+            }
+        }
+    }
+    println!();
+    if let Some(save_path) = save_path {
+        write_wav_file(save_path, &audio, 24_000, settings.mono, settings.bit_depth)?;
+        eprintln!("Audio saved to {}", save_path);
+    }
+    if let Some(player) = player {
+        player.wait();
+    }
+    Ok(())
+}
@@ -0,0 +1,567 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use kokoros::input::censor::CensorMode;
+use kokoros::tts::koko::{Balance, OptimizationLevel, Provider};
+use kokoros::tts::normalize::{Compounds, Replacement};
+use kokoros::tts::vocab::ModelVersion;
+use kokoros::utils::wav::BitDepth;
+use serde::Deserialize;
+
+use crate::Cli;
+
+/// Defaults read from `config.toml`; any flag given on the command line wins
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub model: Option<String>,
+    pub data: Option<String>,
+    pub voices_dir: Option<String>,
+    #[serde(alias = "voice")]
+    pub style: Option<String>,
+    pub speed: Option<f32>,
+    pub lan: Option<String>,
+    pub mono: Option<bool>,
+    pub bit_depth: Option<BitDepth>,
+    pub initial_silence: Option<usize>,
+    pub trim: Option<bool>,
+    pub high_pass: Option<bool>,
+    pub loudness: Option<f32>,
+    pub watermark_key: Option<String>,
+    pub watermark_strength: Option<f32>,
+    pub pause: Option<f32>,
+    pub spell_out: Option<bool>,
+    pub verbalize_math: Option<bool>,
+    pub verbalize_code: Option<bool>,
+    pub compounds: Option<Compounds>,
+    pub quote_voice: Option<String>,
+    pub quote_speed: Option<f32>,
+    pub prosody: Option<bool>,
+    pub prosody_pause: Option<f32>,
+    pub quote_rate: Option<f32>,
+    pub aside_rate: Option<f32>,
+    pub plugins: Option<Vec<String>>,
+    pub censor_words: Option<String>,
+    pub censor: Option<CensorMode>,
+    pub timestamps: Option<bool>,
+    pub read_along: Option<bool>,
+    pub instances: Option<usize>,
+    pub warmup: Option<bool>,
+    pub seconds_per_token: Option<f32>,
+    pub max_input_chars: Option<usize>,
+    pub reproducible: Option<bool>,
+    pub skip_existing: Option<bool>,
+    pub dedupe: Option<bool>,
+    pub intra_op_threads: Option<usize>,
+    pub inter_op_threads: Option<usize>,
+    pub no_spinning: Option<bool>,
+    pub parallel_execution: Option<bool>,
+    pub cpu_arena: Option<bool>,
+    /// In bytes
+    pub gpu_memory_limit: Option<u64>,
+    pub gpu_arena_exact: Option<bool>,
+    pub gpu_devices: Option<Vec<i32>>,
+    /// `round-robin` or `least-loaded`
+    pub gpu_balance: Option<Balance>,
+    /// `cpu`, `cuda` or `tract`
+    pub provider: Option<Provider>,
+    /// `disable`, `basic`, `extended` or `all`
+    pub graph_optimization: Option<OptimizationLevel>,
+    pub optimized_model_dir: Option<PathBuf>,
+    pub ort_profile: Option<PathBuf>,
+    pub model_version: Option<ModelVersion>,
+    pub checksums: Option<String>,
+    pub espeak_data: Option<String>,
+    /// A level or `RUST_LOG`-style filter, e.g. `warn,kokoros_openai=debug`
+    pub log_level: Option<String>,
+    // Still parsed without the `server` feature, so one config file fits every build
+    #[serde(default)]
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub server: ServerConfig,
+    /// `[voices.<name>]` tables of settings that go with a voice
+    #[serde(default)]
+    pub voices: HashMap<String, VoiceDefaults>,
+    /// `[[replacements]]` rules rewriting the text before anything else, in order
+    #[serde(default)]
+    pub replacements: Vec<ReplacementRule>,
+}
+
+/// A regex and what to replace its matches with, which may refer to its groups as `$1`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplacementRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub replacement: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+pub struct ServerConfig {
+    pub ip: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub api_key: Option<String>,
+    pub cache_dir: Option<PathBuf>,
+    pub split_long_inputs: Option<bool>,
+    pub job_store: Option<PathBuf>,
+    pub metrics_headers: Option<bool>,
+    /// `[server.keys.<name>]` tables of the keys speech requests must use
+    #[serde(default)]
+    pub keys: HashMap<String, KeyConfig>,
+    pub usage_file: Option<PathBuf>,
+    /// `[server.voice_aliases]` names requests may give for voices or blends
+    #[serde(default)]
+    pub voice_aliases: HashMap<String, String>,
+}
+
+/// An API key for speech requests, its character quotas, and what else the tenant using
+/// it is kept to
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+pub struct KeyConfig {
+    pub key: String,
+    pub daily_chars: Option<u64>,
+    pub monthly_chars: Option<u64>,
+    /// Voices its requests may use; any if empty
+    #[serde(default)]
+    pub voices: Vec<String>,
+    /// `[[server.keys.<name>.lexicon]]` rules rewriting only its requests' text
+    #[serde(default)]
+    pub lexicon: Vec<ReplacementRule>,
+    /// Voices only its requests can use, on top of the server's
+    pub voices_dir: Option<PathBuf>,
+}
+
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+impl KeyConfig {
+    /// The `lexicon` rules, compiled
+    pub fn lexicon(&self) -> Result<Vec<Replacement>, String> {
+        compile(&self.lexicon)
+    }
+}
+
+fn compile(rules: &[ReplacementRule]) -> Result<Vec<Replacement>, String> {
+    rules
+        .iter()
+        .map(|rule| {
+            Replacement::new(&rule.pattern, &rule.replacement)
+                .map_err(|e| format!("invalid replacement pattern {:?}: {}", rule.pattern, e))
+        })
+        .collect()
+}
+
+/// Settings tuned for one voice, used whenever it is chosen in place of the general ones.
+/// Flags given on the command line still win.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VoiceDefaults {
+    pub speed: Option<f32>,
+    pub trim: Option<bool>,
+    pub high_pass: Option<bool>,
+    pub loudness: Option<f32>,
+    pub pause: Option<f32>,
+}
+
+/// `$XDG_CONFIG_HOME/koko/config.toml`, falling back to `~/.config/koko/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("koko").join("config.toml"))
+}
+
+/// Loads the config file given with `--config`, or the default one if it exists
+pub fn load(path: Option<&str>) -> Result<Config, Box<dyn std::error::Error>> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => match default_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| format!("failed to read config {}: {}", path.display(), e))?;
+    let config = toml::from_str(&content)
+        .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+    tracing::debug!("Loaded config from {}", path.display());
+    Ok(config)
+}
+
+/// Lets every option of `command` and its subcommands also be given as a `KOKO_*`
+/// environment variable named after its flag, e.g. `KOKO_MAX_INPUT_CHARS` for
+/// `--max-input-chars`
+pub fn with_env(command: Command) -> Command {
+    let subcommands: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    let command = command.mut_args(|arg| match arg.get_long() {
+        Some(long) if !matches!(long, "help" | "version") => {
+            let name = format!("KOKO_{}", long.to_ascii_uppercase().replace('-', "_"));
+            arg.env(name)
+        }
+        _ => arg,
+    });
+    subcommands.iter().fold(command, |command, name| {
+        command.mut_subcommand(name, with_env)
+    })
+}
+
+/// Whether an option was set by a flag or its environment variable, either of which
+/// wins over the config file
+fn given(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+fn set<T>(matches: &ArgMatches, id: &str, target: &mut T, value: Option<T>) {
+    if let Some(value) = value
+        && !given(matches, id)
+    {
+        *target = value;
+    }
+}
+
+impl Config {
+    /// The `[[replacements]]` rules, compiled
+    pub fn replacements(&self) -> Result<Vec<Replacement>, String> {
+        compile(&self.replacements)
+    }
+
+    /// Fills in every option that wasn't given explicitly on the command line, with the
+    /// chosen voice's defaults taking precedence over the general ones. Returns the
+    /// per-voice defaults, for voices chosen later (e.g. per batch row).
+    pub fn apply(self, cli: &mut Cli, matches: &ArgMatches) -> HashMap<String, VoiceDefaults> {
+        set(matches, "model_path", &mut cli.model_path, self.model);
+        set(matches, "data_path", &mut cli.data_path, self.data);
+        set(
+            matches,
+            "voices_dir",
+            &mut cli.voices_dir,
+            self.voices_dir.map(Some),
+        );
+        set(matches, "style", &mut cli.style, self.style);
+        set(matches, "speed", &mut cli.speed, self.speed);
+        set(matches, "lan", &mut cli.lan, self.lan);
+        set(matches, "mono", &mut cli.mono, self.mono);
+        set(matches, "bit_depth", &mut cli.bit_depth, self.bit_depth);
+        set(matches, "timestamps", &mut cli.timestamps, self.timestamps);
+        set(matches, "read_along", &mut cli.read_along, self.read_along);
+        set(matches, "instances", &mut cli.instances, self.instances);
+        set(matches, "warmup", &mut cli.warmup, self.warmup);
+        set(
+            matches,
+            "seconds_per_token",
+            &mut cli.seconds_per_token,
+            self.seconds_per_token,
+        );
+        set(
+            matches,
+            "max_input_chars",
+            &mut cli.max_input_chars,
+            self.max_input_chars.map(Some),
+        );
+        set(
+            matches,
+            "reproducible",
+            &mut cli.reproducible,
+            self.reproducible,
+        );
+        set(
+            matches,
+            "skip_existing",
+            &mut cli.skip_existing,
+            self.skip_existing,
+        );
+        set(matches, "dedupe", &mut cli.dedupe, self.dedupe);
+        set(
+            matches,
+            "intra_op_threads",
+            &mut cli.intra_op_threads,
+            self.intra_op_threads.map(Some),
+        );
+        set(
+            matches,
+            "inter_op_threads",
+            &mut cli.inter_op_threads,
+            self.inter_op_threads.map(Some),
+        );
+        set(
+            matches,
+            "no_spinning",
+            &mut cli.no_spinning,
+            self.no_spinning,
+        );
+        set(
+            matches,
+            "parallel_execution",
+            &mut cli.parallel_execution,
+            self.parallel_execution,
+        );
+        set(matches, "cpu_arena", &mut cli.cpu_arena, self.cpu_arena);
+        set(
+            matches,
+            "gpu_memory_limit",
+            &mut cli.gpu_memory_limit,
+            self.gpu_memory_limit.map(Some),
+        );
+        set(
+            matches,
+            "gpu_arena_exact",
+            &mut cli.gpu_arena_exact,
+            self.gpu_arena_exact,
+        );
+        set(
+            matches,
+            "gpu_devices",
+            &mut cli.gpu_devices,
+            self.gpu_devices,
+        );
+        set(
+            matches,
+            "gpu_balance",
+            &mut cli.gpu_balance,
+            self.gpu_balance,
+        );
+        set(
+            matches,
+            "provider",
+            &mut cli.provider,
+            self.provider.map(Some),
+        );
+        set(
+            matches,
+            "graph_optimization",
+            &mut cli.graph_optimization,
+            self.graph_optimization.map(Some),
+        );
+        set(
+            matches,
+            "optimized_model_dir",
+            &mut cli.optimized_model_dir,
+            self.optimized_model_dir.map(Some),
+        );
+        set(
+            matches,
+            "ort_profile",
+            &mut cli.ort_profile,
+            self.ort_profile.map(Some),
+        );
+        set(
+            matches,
+            "model_version",
+            &mut cli.model_version,
+            self.model_version.map(Some),
+        );
+        set(
+            matches,
+            "initial_silence",
+            &mut cli.initial_silence,
+            self.initial_silence.map(Some),
+        );
+        set(matches, "trim", &mut cli.trim, self.trim);
+        set(matches, "high_pass", &mut cli.high_pass, self.high_pass);
+        set(
+            matches,
+            "loudness",
+            &mut cli.loudness,
+            self.loudness.map(Some),
+        );
+        set(
+            matches,
+            "watermark_key",
+            &mut cli.watermark_key,
+            self.watermark_key.map(Some),
+        );
+        set(
+            matches,
+            "watermark_strength",
+            &mut cli.watermark_strength,
+            self.watermark_strength,
+        );
+        set(matches, "pause", &mut cli.pause, self.pause.map(Some));
+        set(matches, "spell_out", &mut cli.spell_out, self.spell_out);
+        set(
+            matches,
+            "verbalize_math",
+            &mut cli.verbalize_math,
+            self.verbalize_math,
+        );
+        set(
+            matches,
+            "verbalize_code",
+            &mut cli.verbalize_code,
+            self.verbalize_code,
+        );
+        set(matches, "compounds", &mut cli.compounds, self.compounds);
+        set(
+            matches,
+            "quote_voice",
+            &mut cli.quote_voice,
+            self.quote_voice.map(Some),
+        );
+        set(
+            matches,
+            "quote_speed",
+            &mut cli.quote_speed,
+            self.quote_speed.map(Some),
+        );
+        set(matches, "prosody", &mut cli.prosody, self.prosody);
+        set(
+            matches,
+            "prosody_pause",
+            &mut cli.prosody_pause,
+            self.prosody_pause.map(Some),
+        );
+        set(
+            matches,
+            "quote_rate",
+            &mut cli.quote_rate,
+            self.quote_rate.map(Some),
+        );
+        set(
+            matches,
+            "aside_rate",
+            &mut cli.aside_rate,
+            self.aside_rate.map(Some),
+        );
+        set(matches, "plugins", &mut cli.plugins, self.plugins);
+        set(
+            matches,
+            "censor_words",
+            &mut cli.censor_words,
+            self.censor_words.map(Some),
+        );
+        set(matches, "censor", &mut cli.censor, self.censor);
+        set(
+            matches,
+            "checksums",
+            &mut cli.checksums,
+            self.checksums.map(Some),
+        );
+        set(
+            matches,
+            "espeak_data",
+            &mut cli.espeak_data,
+            self.espeak_data.map(Some),
+        );
+
+        #[cfg(feature = "server")]
+        if let (
+            crate::Mode::OpenAI {
+                ip,
+                port,
+                api_key,
+                cache_dir,
+                split_long_inputs,
+                job_store,
+                usage_file,
+                metrics_headers,
+                ..
+            },
+            Some(sub),
+        ) = (&mut cli.mode, matches.subcommand_matches("openai"))
+        {
+            set(sub, "ip", ip, self.server.ip);
+            set(sub, "port", port, self.server.port);
+            set(sub, "api_key", api_key, self.server.api_key.map(Some));
+            set(sub, "cache_dir", cache_dir, self.server.cache_dir.map(Some));
+            set(
+                sub,
+                "split_long_inputs",
+                split_long_inputs,
+                self.server.split_long_inputs,
+            );
+            set(sub, "job_store", job_store, self.server.job_store.map(Some));
+            set(
+                sub,
+                "usage_file",
+                usage_file,
+                self.server.usage_file.map(Some),
+            );
+            set(
+                sub,
+                "metrics_headers",
+                metrics_headers,
+                self.server.metrics_headers,
+            );
+        }
+
+        if let Some(defaults) = self.voices.get(&cli.style) {
+            set(matches, "speed", &mut cli.speed, defaults.speed);
+            set(matches, "trim", &mut cli.trim, defaults.trim);
+            set(matches, "high_pass", &mut cli.high_pass, defaults.high_pass);
+            set(
+                matches,
+                "loudness",
+                &mut cli.loudness,
+                defaults.loudness.map(Some),
+            );
+            set(matches, "pause", &mut cli.pause, defaults.pause.map(Some));
+        }
+        self.voices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(
+            "model = \"models/kokoro.onnx\"\nvoice = \"af_sky\"\nspeed = 1.1\n\n[server]\nport = 8080\n",
+        )
+        .unwrap();
+        assert_eq!(config.model.as_deref(), Some("models/kokoro.onnx"));
+        assert_eq!(config.style.as_deref(), Some("af_sky"));
+        assert_eq!(config.server.port, Some(8080));
+        assert!(toml::from_str::<Config>("colour = \"red\"").is_err());
+
+        let config: Config =
+            toml::from_str("speed = 1.1\n\n[voices.bm_george]\nspeed = 0.9\npause = 0.4\n")
+                .unwrap();
+        assert_eq!(config.voices["bm_george"].speed, Some(0.9));
+        assert_eq!(config.voices["bm_george"].pause, Some(0.4));
+        assert!(toml::from_str::<Config>("[voices.bm_george]\npitch = 2").is_err());
+
+        let config: Config = toml::from_str(
+            "[[replacements]]\npattern = 'JIRA-(\\d+)'\nreplacement = 'ticket $1'\n\n[[replacements]]\npattern = '^Sent from my phone$'\n",
+        )
+        .unwrap();
+        assert_eq!(config.replacements().unwrap().len(), 2);
+        assert_eq!(config.replacements[1].replacement, "");
+    }
+
+    #[test]
+    fn test_with_env() {
+        use clap::CommandFactory;
+
+        let command = with_env(Cli::command());
+        let env = |command: &Command, id: &str| {
+            let arg = command.get_arguments().find(|arg| arg.get_id() == id);
+            arg.and_then(|arg| arg.get_env())
+                .map(|env| env.to_os_string())
+        };
+        assert_eq!(env(&command, "model_path"), Some("KOKO_MODEL".into()));
+        assert_eq!(
+            env(&command, "max_input_chars"),
+            Some("KOKO_MAX_INPUT_CHARS".into())
+        );
+        assert_eq!(env(&command, "help"), None);
+        #[cfg(feature = "server")]
+        {
+            let server = command.find_subcommand("openai").unwrap();
+            assert_eq!(env(server, "port"), Some("KOKO_PORT".into()));
+        }
+    }
+}
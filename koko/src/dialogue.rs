@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use kokoros::input::dialogue::parse_dialogue;
+use kokoros::tts::koko::TTSKoko;
+use kokoros::utils::{audio, wav};
+
+use crate::batch::{self, SynthSettings};
+use crate::write_wav_file;
+
+const SAMPLE_RATE: u32 = 24_000;
+
+/// How one `[speaker]` of a script is voiced, from `--speaker NAME=VOICE[,speed=S][,pan=P]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Speaker {
+    pub name: String,
+    pub voice: String,
+    pub speed: Option<f32>,
+    /// -1 is fully left, 1 fully right
+    pub pan: f32,
+}
+
+pub fn parse_speaker(spec: &str) -> Result<Speaker, String> {
+    let (name, rest) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VOICE, got '{}'", spec))?;
+    let mut options = rest.split(',');
+    let mut speaker = Speaker {
+        name: name.trim().to_string(),
+        voice: options.next().unwrap_or_default().trim().to_string(),
+        speed: None,
+        pan: 0.0,
+    };
+    for option in options {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got '{}'", option))?;
+        let value: f32 = value
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid number in '{}'", option))?;
+        match key.trim() {
+            "speed" => speaker.speed = Some(value),
+            "pan" if (-1.0..=1.0).contains(&value) => speaker.pan = value,
+            "pan" => return Err(format!("pan must be between -1 and 1, got {}", value)),
+            other => return Err(format!("unknown speaker option '{}'", other)),
+        }
+    }
+    if speaker.name.is_empty() || speaker.voice.is_empty() {
+        return Err(format!("expected NAME=VOICE, got '{}'", spec));
+    }
+    Ok(speaker)
+}
+
+/// Left and right gains for `pan`, keeping the total power constant
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// Synthesizes a `[speaker] text` script into one WAV file, turn after turn with `gap`
+/// seconds of silence between them. Tags that aren't mapped with `--speaker` must be voice
+/// names; text before the first tag is read with the default voice.
+pub fn run(
+    tts: &TTSKoko,
+    input_path: &Path,
+    save_path: &Path,
+    speakers: &[Speaker],
+    gap: f32,
+    settings: &SynthSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let turns = parse_dialogue(&fs::read_to_string(input_path)?);
+    if turns.is_empty() {
+        return Err(format!("no text found in {}", input_path.display()).into());
+    }
+
+    let voices = tts.get_available_voices();
+    let narrator = Speaker {
+        name: String::new(),
+        voice: settings.style.to_string(),
+        speed: None,
+        pan: 0.0,
+    };
+    let mut cast: HashMap<&str, Speaker> = HashMap::new();
+    for turn in &turns {
+        let Some(name) = turn.speaker.as_deref() else {
+            continue;
+        };
+        if cast.contains_key(name) {
+            continue;
+        }
+        let speaker = match speakers.iter().find(|s| s.name == name) {
+            Some(speaker) => speaker.clone(),
+            None if voices.iter().any(|v| v == name) => Speaker {
+                voice: name.to_string(),
+                ..narrator.clone()
+            },
+            None => {
+                return Err(format!(
+                    "unknown speaker [{}], map it to a voice with --speaker {}=VOICE",
+                    name, name
+                )
+                .into());
+            }
+        };
+        eprintln!("[{}] is {}", name, speaker.voice);
+        cast.insert(name, speaker);
+    }
+
+    // Samples with the pan of their speaker; panning is ignored with --mono
+    let mut track: Vec<(f32, f32)> = Vec::new();
+    let silence = audio::silence(gap, SAMPLE_RATE);
+    for (i, turn) in turns.iter().enumerate() {
+        let speaker = turn
+            .speaker
+            .as_deref()
+            .map_or(&narrator, |name| &cast[name]);
+        let voiced = settings.for_voice(&speaker.voice);
+        // Speakers are tagged explicitly, so quotes need no voice of their own. The
+        // watermark goes on the whole track, in one pass.
+        let voiced = SynthSettings {
+            speed: speaker.speed.unwrap_or(voiced.speed),
+            quote_voice: None,
+            quote_speed: None,
+            watermark: None,
+            ..voiced
+        };
+        tracing::debug!("Turn {}: {} says '{}'", i + 1, speaker.voice, turn.text);
+
+        if i > 0 {
+            track.extend(silence.iter().map(|&s| (s, 0.0)));
+        }
+        let samples = batch::synthesize_audio(tts, &turn.text, &voiced)?;
+        track.extend(samples.into_iter().map(|s| (s, speaker.pan)));
+    }
+    if let Some(watermark) = settings.watermark {
+        let mut samples: Vec<f32> = track.iter().map(|&(s, _)| s).collect();
+        watermark.embed(&mut samples);
+        for ((s, _), marked) in track.iter_mut().zip(samples) {
+            *s = marked;
+        }
+    }
+
+    if let Some(parent) = save_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let duration = track.len() as f32 / SAMPLE_RATE as f32;
+    if settings.mono {
+        let mono: Vec<f32> = track.iter().map(|&(s, _)| s).collect();
+        write_wav_file(
+            &save_path.to_string_lossy(),
+            &mono,
+            SAMPLE_RATE,
+            true,
+            settings.bit_depth,
+        )?;
+    } else {
+        let stereo = track.iter().flat_map(|&(sample, pan)| {
+            let (left, right) = pan_gains(pan);
+            [sample * left, sample * right]
+        });
+        wav::write_interleaved(save_path, stereo, SAMPLE_RATE, 2, settings.bit_depth)?;
+        crate::reproducible::tag(save_path)?;
+    }
+    eprintln!(
+        "Audio saved to {} ({} turns, {:.1}s)",
+        save_path.display(),
+        turns.len(),
+        duration
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_speaker() {
+        assert_eq!(
+            parse_speaker("sarah=af_sarah,speed=1.1,pan=-0.5").unwrap(),
+            Speaker {
+                name: "sarah".to_string(),
+                voice: "af_sarah".to_string(),
+                speed: Some(1.1),
+                pan: -0.5,
+            }
+        );
+        assert_eq!(parse_speaker("tom=am_adam").unwrap().pan, 0.0);
+        assert!(parse_speaker("tom").is_err());
+        assert!(parse_speaker("tom=am_adam,pan=3").is_err());
+
+        let (left, right) = pan_gains(0.0);
+        assert!((left - right).abs() < 1e-6);
+        assert!(pan_gains(-1.0).1.abs() < 1e-6);
+    }
+}
@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use kokoros::utils::checksum::{self, LOCKFILE_NAME};
+use kokoros::utils::fileio::{self, MODEL_FILE, VOICES_FILE};
+
+/// Downloads the model and voices files from a Hugging Face repository into `dir`,
+/// skipping files that are already present unless `force` is set. The files are then
+/// checked against `dir/SHA256SUMS`, which is created on the first download.
+pub async fn run(
+    repo: &str,
+    revision: &str,
+    dir: &Path,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for file in [MODEL_FILE, VOICES_FILE] {
+        let path = dir.join(file);
+        if path.exists() && !force {
+            eprintln!(
+                "{} already exists, skipping (use --force to re-download)",
+                path.display()
+            );
+            continue;
+        }
+        let url = fileio::hugging_face_url(repo, revision, file);
+        fileio::download_file_from_url(&url, &path.to_string_lossy())
+            .await
+            .map_err(|e| format!("downloading {}: {}", url, e))?;
+    }
+
+    let model = dir.join(MODEL_FILE);
+    let voices = dir.join(VOICES_FILE);
+    let lockfile = dir.join(LOCKFILE_NAME);
+    if lockfile.exists() {
+        checksum::verify_files(&lockfile, &[&model, &voices])?;
+        eprintln!("Checksums match {}", lockfile.display());
+    } else {
+        checksum::write_lockfile(&lockfile, &[&model, &voices])?;
+        eprintln!("Checksums recorded in {}", lockfile.display());
+    }
+
+    println!("Model:  {}", dir.join(MODEL_FILE).display());
+    println!("Voices: {}", dir.join(VOICES_FILE).display());
+    if dir == fileio::cache_dir() {
+        println!("These are used automatically when the default -m/-d paths don't exist.");
+    } else {
+        println!(
+            "Use them with -m {} -d {}",
+            dir.join(MODEL_FILE).display(),
+            dir.join(VOICES_FILE).display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,275 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use kokoros::input::epub::read_epub;
+use kokoros::input::media_overlay::{
+    ChapterAudio, Clip, TextBlock, read_overlay_chapters, write_overlay_epub,
+};
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::readalong;
+use kokoros::utils::assembler::{AudioAssembler, Join};
+use kokoros::utils::mp3::pcm_to_mp3;
+use serde_json::json;
+
+use crate::batch::{
+    SynthSettings, is_up_to_date, post_process, synthesize_audio, synthesize_timestamped,
+    synthesize_to_file,
+};
+use crate::progress::Progress;
+use crate::report::ItemReport;
+use crate::{split, write_wav_file};
+
+/// Silence between the blocks of a chapter synthesized for its media overlay
+const BLOCK_PAUSE_SEC: f32 = 0.5;
+
+/// Turns a chapter title into a short, filesystem-safe file name component
+pub fn slugify(title: &str) -> String {
+    let slug: String = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let slug = slug
+        .split('-')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    slug.chars().take(48).collect()
+}
+
+/// Duration of an existing WAV file, for chapters that are not re-synthesized
+pub fn wav_duration_sec(path: &Path) -> Result<f32, Box<dyn std::error::Error>> {
+    let reader = hound::WavReader::open(path)?;
+    Ok(reader.duration() as f32 / reader.spec().sample_rate as f32)
+}
+
+/// Synthesizes `text` with word timestamps, as `--timestamps` does, with where each of its
+/// sentences is read in the audio
+fn synthesize_sentences(
+    tts: &TTSKoko,
+    text: &str,
+    settings: &SynthSettings,
+) -> Result<(Vec<f32>, Vec<readalong::Sentence>), Box<dyn std::error::Error>> {
+    let censored = settings.censor.map(|censor| censor.apply(text));
+    let text = censored.as_deref().unwrap_or(text);
+    let (mut audio, mut words) = synthesize_timestamped(tts, text, settings)?;
+    post_process(&mut audio, &mut words, settings);
+    let doc = readalong::read_along(text, &words, audio.len() as f32 / 24_000.0);
+    Ok((audio, doc.sentences))
+}
+
+/// Synthesizes `blocks` one after another into one track, with where each one is in it,
+/// or each of its sentences where the model gives the timings to place them
+fn synthesize_blocks(
+    tts: &TTSKoko,
+    blocks: &[TextBlock],
+    settings: &SynthSettings,
+) -> Result<(Vec<f32>, Vec<Clip>), Box<dyn std::error::Error>> {
+    let mut assembler = AudioAssembler::new(24_000);
+    let mut clips = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let (audio, sentences) = if !block.sentences.is_empty() && tts.timestamped() {
+            synthesize_sentences(tts, &block.text, settings)?
+        } else {
+            (synthesize_audio(tts, &block.text, settings)?, Vec::new())
+        };
+        let placement = assembler.push(&audio, Join::Silence(BLOCK_PAUSE_SEC));
+        if !sentences.is_empty() && sentences.len() == block.sentences.len() {
+            clips.extend(
+                block
+                    .sentences
+                    .iter()
+                    .zip(&sentences)
+                    .map(|(id, sentence)| Clip {
+                        id: id.clone(),
+                        start_sec: placement.to_track(sentence.start_sec),
+                        end_sec: placement.to_track(sentence.end_sec),
+                    }),
+            );
+        } else {
+            clips.push(Clip {
+                id: block.id.clone(),
+                start_sec: placement.start_sec(),
+                end_sec: placement.end_sec(),
+            });
+        }
+    }
+    Ok((assembler.into_samples(), clips))
+}
+
+/// Synthesizes each chapter of an EPUB to its own WAV file and writes `manifest.json`
+/// describing the chapters next to them. Unless `force` is set, chapters whose WAV file
+/// is already newer than the EPUB are kept as they are.
+///
+/// With `media_overlays`, chapters are synthesized block by block instead, every time, and
+/// a copy of the EPUB with their audio and EPUB 3 media overlays is written there.
+pub fn run(
+    tts: &TTSKoko,
+    epub_path: &Path,
+    output_dir: &Path,
+    settings: &SynthSettings,
+    force: bool,
+    quiet: bool,
+    media_overlays: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if media_overlays.is_some() && settings.max_samples.is_some() {
+        return Err("--media-overlays can't be combined with --max-duration or --max-size".into());
+    }
+    let book = read_epub(epub_path)?;
+    fs::create_dir_all(output_dir)?;
+    let overlay_chapters = match media_overlays {
+        Some(_) => read_overlay_chapters(epub_path)?,
+        None => Vec::new(),
+    };
+    let mut narrated = Vec::new();
+
+    let total = book.chapters.len();
+    eprintln!(
+        "Found {} chapters in {}",
+        total,
+        book.title.as_deref().unwrap_or("EPUB")
+    );
+
+    let progress = Progress::new(
+        total,
+        book.chapters.iter().map(|c| c.text.chars().count()).sum(),
+        quiet,
+    );
+    let mut entries = Vec::with_capacity(total);
+    let mut offset_sec = 0.0f32;
+    for (i, chapter) in book.chapters.iter().enumerate() {
+        let started = Instant::now();
+        let item = progress.item(chapter.text.chars().count());
+        let settings = &SynthSettings {
+            progress: Some(&item),
+            ..*settings
+        };
+        let file_name = format!("{:03}_{}.wav", i + 1, slugify(&chapter.title));
+        let save_path = output_dir.join(&file_name);
+
+        // With a size or duration limit a chapter becomes book_001.wav, book_002.wav, ...
+        let files: Vec<PathBuf> = match settings.max_samples {
+            Some(_) => split::existing_parts(&save_path),
+            None => vec![save_path.clone()],
+        };
+        let up_to_date = !force
+            && files
+                .first()
+                .is_some_and(|first| is_up_to_date(epub_path, first))
+            && files.iter().all(|f| f.exists());
+
+        let blocks = overlay_chapters
+            .iter()
+            .find(|overlay| overlay.href == chapter.href)
+            .map(|overlay| &overlay.blocks);
+
+        let (files, duration_sec) = if let Some(blocks) = blocks {
+            progress.message(&format!("[{}/{}] {}", i + 1, total, chapter.title));
+            let (audio, clips) = synthesize_blocks(tts, blocks, settings)?;
+            write_wav_file(
+                &save_path.to_string_lossy(),
+                &audio,
+                24_000,
+                settings.mono,
+                settings.bit_depth,
+            )?;
+            progress.message(&format!("Audio saved to {}", save_path.display()));
+            let duration_sec = audio.len() as f32 / 24_000.0;
+            narrated.push(ChapterAudio {
+                href: chapter.href.clone(),
+                mp3: pcm_to_mp3(&audio, 24_000)?,
+                duration_sec,
+                clips,
+            });
+            (vec![save_path.clone()], duration_sec)
+        } else if up_to_date {
+            progress.message(&format!(
+                "[{}/{}] {} (up to date, skipped)",
+                i + 1,
+                total,
+                chapter.title
+            ));
+            let mut duration_sec = 0.0;
+            for file in &files {
+                duration_sec += wav_duration_sec(file)?;
+            }
+            (files, duration_sec)
+        } else {
+            progress.message(&format!("[{}/{}] {}", i + 1, total, chapter.title));
+            let parts = match settings.max_samples {
+                Some(max_samples) => {
+                    split::synthesize_parts(tts, &chapter.text, &save_path, settings, max_samples)?
+                }
+                None => vec![(
+                    save_path.clone(),
+                    synthesize_to_file(tts, &chapter.text, &save_path, settings)?,
+                )],
+            };
+            for (path, _) in &parts {
+                progress.message(&format!("Audio saved to {}", path.display()));
+            }
+            let duration_sec = parts.iter().map(|(_, d)| d).sum();
+            (
+                parts.into_iter().map(|(path, _)| path).collect(),
+                duration_sec,
+            )
+        };
+        item.finish();
+        if let Some(report) = settings.report {
+            let input = format!("{}#{}", epub_path.display(), i + 1);
+            let mut item = if up_to_date && blocks.is_none() {
+                ItemReport::skipped(input, &save_path, Some(duration_sec))
+            } else {
+                ItemReport::done(input, &save_path, duration_sec, started.elapsed())
+            };
+            if settings.max_samples.is_some() {
+                item.parts = files.clone();
+            }
+            report.emit(item);
+        }
+
+        let file_names: Vec<String> = files
+            .iter()
+            .filter_map(|f| f.file_name())
+            .map(|f| f.to_string_lossy().into_owned())
+            .collect();
+        entries.push(json!({
+            "index": i + 1,
+            "title": chapter.title,
+            "source": chapter.href,
+            "file": file_names.first().cloned().unwrap_or(file_name),
+            "files": file_names,
+            "characters": chapter.text.chars().count(),
+            "start_sec": offset_sec,
+            "duration_sec": duration_sec,
+        }));
+        offset_sec += duration_sec;
+    }
+
+    progress.finish();
+
+    let manifest = json!({
+        "title": book.title,
+        "source": epub_path.to_string_lossy(),
+        "voice": settings.style,
+        "speed": settings.speed,
+        "total_duration_sec": offset_sec,
+        "chapters": entries,
+    });
+    let manifest_path = output_dir.join("manifest.json");
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    eprintln!("Chapter manifest saved to {}", manifest_path.display());
+
+    if let Some(dest) = media_overlays {
+        write_overlay_epub(epub_path, dest, &narrated)?;
+        eprintln!("EPUB with media overlays saved to {}", dest.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::path::Path;
+
+use kokoros::input::epub::read_epub;
+use kokoros::input::rows::{is_rows_file, read_rows};
+use kokoros::tts::estimate::{Calibration, DurationEstimate, estimate_duration};
+use kokoros::tts::normalize::TextOptions;
+use kokoros::tts::vocab::ModelVersion;
+
+use crate::Mode;
+use crate::batch::{collect_inputs, read_input_text};
+use crate::progress::format_duration;
+
+/// One piece of a job that is synthesized on its own
+struct Item {
+    source: String,
+    text: String,
+    lan: Option<String>,
+    speed: Option<f32>,
+}
+
+impl Item {
+    fn new(source: String, text: String) -> Self {
+        Self {
+            source,
+            text,
+            lan: None,
+            speed: None,
+        }
+    }
+}
+
+/// The texts `mode` would synthesize, split up the same way
+fn items(mode: &Mode) -> Result<Vec<Item>, Box<dyn std::error::Error>> {
+    Ok(match mode {
+        Mode::Text { text, .. } => vec![Item::new("text".to_string(), text.clone())],
+        Mode::File { input_path, .. } => fs::read_to_string(input_path)?
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(i, line)| Item::new(format!("{}:{}", input_path, i + 1), line.to_string()))
+            .collect(),
+        Mode::Batch { input_path, .. } if is_rows_file(Path::new(input_path)) => {
+            read_rows(Path::new(input_path))?
+                .into_iter()
+                .map(|row| Item {
+                    source: format!("{}:{}", input_path, row.line),
+                    text: row.text,
+                    lan: row.lan,
+                    speed: row.speed,
+                })
+                .collect()
+        }
+        Mode::Batch { input_path, .. } => collect_inputs(Path::new(input_path))?
+            .into_iter()
+            .map(|path| {
+                let text = read_input_text(&path)?;
+                Ok(Item::new(path.display().to_string(), text))
+            })
+            .collect::<std::io::Result<_>>()?,
+        Mode::Epub { input_path, .. } => read_epub(Path::new(input_path))?
+            .chapters
+            .into_iter()
+            .map(|chapter| Item::new(chapter.title, chapter.text))
+            .collect(),
+        _ => return Err("--estimate works with the text, file, batch and epub modes".into()),
+    })
+}
+
+/// Prints how long the audio of `mode` will be at `speed`, with the text rewritten by
+/// `options` and tokenized for `version`, item by item and in total, without loading the
+/// model
+pub fn run(
+    mode: &Mode,
+    lan: &str,
+    speed: f32,
+    calibration: &Calibration,
+    options: &TextOptions,
+    version: ModelVersion,
+    quiet: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let items = items(mode)?;
+    let mut total = DurationEstimate::default();
+    for item in &items {
+        let estimate = estimate_duration(
+            &item.text,
+            item.lan.as_deref().unwrap_or(lan),
+            item.speed.unwrap_or(speed),
+            calibration,
+            options,
+            version,
+        )?;
+        if !quiet && items.len() > 1 {
+            println!(
+                "{:>9}  {:>6} tokens  {}",
+                format_duration(estimate.seconds),
+                estimate.tokens,
+                item.source
+            );
+        }
+        total += estimate;
+    }
+    let items = if items.len() > 1 {
+        format!("{} items, ", items.len())
+    } else {
+        String::new()
+    };
+    println!(
+        "Estimated duration: {} ({:.1} s) for {}{} chunks, {} tokens at {:.3} s/token",
+        format_duration(total.seconds),
+        total.seconds,
+        items,
+        total.chunks,
+        total.tokens,
+        calibration.seconds_per_token
+    );
+    Ok(())
+}
@@ -0,0 +1,292 @@
+//! `koko icecast`: a source client for an Icecast mount, for automated news or weather
+//! streams. Each line of stdin is a segment, spoken into one continuous MP3 stream at
+//! real-time pace with silence in between, and the mount's metadata is set to the
+//! segment's title as it starts.
+
+use std::io::{self, BufRead, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use kokoros::tts::koko::TTSKoko;
+use kokoros::utils::mp3::Mp3StreamEncoder;
+use serde::Deserialize;
+
+use crate::batch::SynthSettings;
+use crate::pacing::Pacer;
+
+/// Seconds of audio sent ahead of the clock, which listeners' players buffer anyway
+const PREBUFFER_SECONDS: f32 = 2.0;
+
+/// Silence sent at a time while there is nothing to say
+const SILENCE: Duration = Duration::from_millis(100);
+
+/// Longest title taken from a segment's text when it has none of its own
+const TITLE_CHARS: usize = 80;
+
+pub struct IcecastConfig {
+    pub host: String,
+    pub port: u16,
+    /// Mount point, e.g. `/news`
+    pub mount: String,
+    pub user: String,
+    pub password: String,
+    /// Station name, description and genre shown in directories and players
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub genre: Option<String>,
+    /// MP3 bitrate in kbps
+    pub bitrate: u32,
+}
+
+/// A line of input: plain text, or JSON like {"text": "...", "title": "Weather"}
+#[derive(Deserialize)]
+struct Segment {
+    text: String,
+    /// Metadata while the segment plays (default: the start of its text)
+    title: Option<String>,
+    voice: Option<String>,
+    speed: Option<f32>,
+}
+
+fn parse_segment(line: &str) -> Segment {
+    serde_json::from_str(line).unwrap_or_else(|_| Segment {
+        text: line.to_string(),
+        title: None,
+        voice: None,
+        speed: None,
+    })
+}
+
+/// The start of `text`, cut at a word if it is too long for a title
+fn default_title(text: &str) -> String {
+    let text = text.trim();
+    if text.chars().count() <= TITLE_CHARS {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(TITLE_CHARS).collect();
+    let cut = cut.rfind(' ').map_or(cut.as_str(), |space| &cut[..space]);
+    format!("{}…", cut.trim_end())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for group in bytes.chunks(3) {
+        let n = group
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// `text` for a URL query, with everything but unreserved characters percent-encoded
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl IcecastConfig {
+    fn authorization(&self) -> String {
+        format!(
+            "Basic {}",
+            base64(format!("{}:{}", self.user, self.password).as_bytes())
+        )
+    }
+
+    /// Sends `request` on a new connection and checks the status of the response, which
+    /// must be one of `accepted`
+    fn request(&self, request: &str, accepted: &[u16]) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.write_all(request.as_bytes())?;
+        // Only the status line matters; read up to the end of the headers
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") && head.len() < 8192 {
+            if stream.read(&mut byte)? == 0 {
+                break;
+            }
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status_line = head.lines().next().unwrap_or_default();
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok());
+        match status {
+            Some(status) if accepted.contains(&status) => Ok(stream),
+            _ => Err(io::Error::other(format!(
+                "Icecast at {}:{} answered {:?}",
+                self.host, self.port, status_line
+            ))),
+        }
+    }
+
+    /// Connects to the mount as its source
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut request = format!(
+            "PUT {} HTTP/1.1\r\nHost: {}:{}\r\nAuthorization: {}\r\nUser-Agent: koko/{}\r\n\
+             Content-Type: audio/mpeg\r\nIce-Public: 0\r\n\
+             Ice-Audio-Info: channels=1;samplerate=24000;bitrate={}\r\nExpect: 100-continue\r\n",
+            self.mount,
+            self.host,
+            self.port,
+            self.authorization(),
+            env!("CARGO_PKG_VERSION"),
+            self.bitrate
+        );
+        for (header, value) in [
+            ("Ice-Name", &self.name),
+            ("Ice-Description", &self.description),
+            ("Ice-Genre", &self.genre),
+        ] {
+            if let Some(value) = value {
+                request.push_str(&format!("{}: {}\r\n", header, value));
+            }
+        }
+        request.push_str("\r\n");
+        self.request(&request, &[100, 200])
+    }
+
+    /// Sets the title listeners see, through the admin interface
+    fn update_metadata(&self, title: &str) -> io::Result<()> {
+        let request = format!(
+            "GET /admin/metadata?mount={}&mode=updinfo&song={} HTTP/1.0\r\nHost: {}:{}\r\n\
+             Authorization: {}\r\nUser-Agent: koko/{}\r\n\r\n",
+            percent_encode(&self.mount),
+            percent_encode(title),
+            self.host,
+            self.port,
+            self.authorization(),
+            env!("CARGO_PKG_VERSION")
+        );
+        self.request(&request, &[200]).map(drop)
+    }
+}
+
+enum Item {
+    /// A segment starts
+    Title(String),
+    Audio(Vec<f32>),
+}
+
+/// Encodes what arrives on `items` and sends it as the mount's stream, with silence
+/// whenever nothing does, until `items` is closed
+fn send_stream(
+    config: &IcecastConfig,
+    mut stream: TcpStream,
+    items: mpsc::Receiver<Item>,
+) -> io::Result<()> {
+    let mut encoder = Mp3StreamEncoder::new(24_000, config.bitrate)?;
+    let mut pacer = Pacer::new(24_000, PREBUFFER_SECONDS);
+    let silence = vec![0.0; (24_000.0 * SILENCE.as_secs_f32()) as usize];
+    let mut send = |audio: &[f32]| -> io::Result<()> {
+        pacer.write(audio, |frame| stream.write_all(&encoder.encode(frame)?))
+    };
+    loop {
+        match items.try_recv() {
+            Ok(Item::Title(title)) => {
+                tracing::info!("Now playing: {}", title);
+                if let Err(e) = config.update_metadata(&title) {
+                    tracing::warn!("Could not update the stream metadata: {}", e);
+                }
+            }
+            Ok(Item::Audio(audio)) => send(&audio)?,
+            Err(mpsc::TryRecvError::Empty) => send(&silence)?,
+            Err(mpsc::TryRecvError::Disconnected) => break,
+        }
+    }
+    stream.write_all(&encoder.finish()?)?;
+    stream.flush()
+}
+
+/// Streams each line of stdin to the mount until stdin ends
+pub fn run(
+    tts: &TTSKoko,
+    config: &IcecastConfig,
+    settings: &SynthSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stream = config.connect()?;
+    tracing::info!(
+        "Streaming to http://{}:{}{}",
+        config.host,
+        config.port,
+        config.mount
+    );
+
+    // Bounded, so synthesis stays a little ahead of the stream rather than far ahead
+    let (sender, receiver) = mpsc::sync_channel::<Item>(8);
+    std::thread::scope(|scope| {
+        let sending = scope.spawn(move || send_stream(config, stream, receiver));
+
+        let result = io::stdin().lock().lines().try_for_each(
+            |line| -> Result<(), Box<dyn std::error::Error>> {
+                let segment = parse_segment(&line?);
+                if segment.text.trim().is_empty() {
+                    return Ok(());
+                }
+                let title = segment
+                    .title
+                    .unwrap_or_else(|| default_title(&segment.text));
+                let closed = || -> Box<dyn std::error::Error> { "the Icecast stream ended".into() };
+                sender.send(Item::Title(title)).map_err(|_| closed())?;
+                let result = tts.tts_raw_audio_streaming(
+                    &segment.text,
+                    settings.lan,
+                    segment.voice.as_deref().unwrap_or(settings.style),
+                    segment.speed.unwrap_or(settings.speed),
+                    settings.initial_silence,
+                    None,
+                    None,
+                    None,
+                    |audio| sender.send(Item::Audio(audio)).map_err(|_| closed()),
+                );
+                // A segment that fails is skipped; the stream goes on
+                if let Err(e) = result {
+                    tracing::error!("Failed to synthesize a segment: {}", e);
+                }
+                Ok(())
+            },
+        );
+        drop(sender);
+        // A failed connection is the more useful error, as it also ends the input
+        sending.join().expect("the Icecast sender panicked")?;
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_icecast_encoding() {
+        assert_eq!(base64(b"source:hackme"), "c291cmNlOmhhY2ttZQ==");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"abc"), "YWJj");
+        assert_eq!(percent_encode("/news"), "%2Fnews");
+        assert_eq!(percent_encode("Rain & wind"), "Rain%20%26%20wind");
+
+        let segment = parse_segment(r#"{"text": "Sunny all day.", "title": "Weather"}"#);
+        assert_eq!(segment.title.as_deref(), Some("Weather"));
+        assert_eq!(parse_segment("Sunny all day.").text, "Sunny all day.");
+        let long = "word ".repeat(30);
+        assert!(default_title(&long).ends_with("word…"));
+        assert!(default_title(&long).chars().count() <= TITLE_CHARS + 1);
+    }
+}
@@ -1,16 +1,65 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use kokoros::{
-    tts::koko::{TTSKoko, TTSOpts},
-    utils::wav::{WavHeader, write_audio_chunk},
+    input::censor::{Censor, CensorMode, parse_word_list},
+    input::prosody::Prosody,
+    input::sounds::has_sounds,
+    tts::koko::{
+        Balance, GpuOptions, OptimizationLevel, Provider, SessionOptions, TTSKoko, TTSOpts,
+    },
+    tts::normalize::{Compounds, TextOptions},
+    tts::readalong,
+    tts::vocab::ModelVersion,
+    utils::opus::DEFAULT_VOICE_BITRATE,
+    utils::rtp::{RtpCodec, RtpSink},
+    utils::watermark::Watermark,
+    utils::wav::{self, BitDepth, WavHeader, write_audio_chunk},
 };
-use std::net::{IpAddr, SocketAddr};
 use std::{
     fs::{self},
-    io::Write,
+    io::{IsTerminal, Write},
     path::Path,
 };
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::prelude::*;
+
+mod audiobook;
+mod audition;
+mod batch;
+mod bench;
+#[cfg(feature = "chat")]
+mod chat;
+mod config;
+mod dialogue;
+mod download;
+mod epub;
+mod estimate;
+mod icecast;
+mod manifest;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod pacing;
+mod phonemize;
+mod playback;
+mod progress;
+#[cfg(feature = "whisper")]
+mod qa;
+mod reload;
+mod repl;
+mod report;
+mod reproducible;
+mod rtp;
+mod say;
+mod speechd;
+mod split;
+mod subtitles;
+mod systemd;
+mod template;
+mod verify;
+mod voices;
+mod watch;
+mod watermark;
+mod wyoming;
 
 /// Custom Unix timestamp formatter for tracing logs
 struct UnixTimestampFormatter;
@@ -25,6 +74,29 @@ impl FormatTime for UnixTimestampFormatter {
     }
 }
 
+#[derive(Subcommand, Debug)]
+enum VoicesCommand {
+    /// Blend and/or scale voices into a new named voice, saved as <name>.npy in the user
+    /// voices directory
+    Mix {
+        /// Voices to blend in the --style syntax, e.g. af_sky.4+af_nicole.6
+        recipe: String,
+
+        /// Name of the new voice
+        #[arg(long = "name")]
+        name: String,
+
+        /// Multiply the style vector by this factor; above 1 exaggerates the voice, below 1
+        /// flattens it
+        #[arg(long = "scale", default_value_t = 1.0)]
+        scale: f32,
+
+        /// Directory to save the voice in (default: ~/.local/share/kokoros/voices)
+        #[arg(long = "dir")]
+        dir: Option<String>,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 enum Mode {
     /// Generate speech for a string of text
@@ -38,7 +110,7 @@ enum Mode {
         )]
         text: String,
 
-        /// Path to output the WAV file to on the filesystem
+        /// Path to output the WAV file to on the filesystem; may use the {voice}, {lan} and {hash} placeholders
         #[arg(
             short = 'o',
             long = "output",
@@ -54,7 +126,8 @@ enum Mode {
         /// Filesystem path to read lines from
         input_path: String,
 
-        /// Format for the output path of each WAV file, where {line} will be replaced with the line number
+        /// Format for the output path of each WAV file, where {line} will be replaced with the line number;
+        /// {n}, {voice}, {lan} and {hash} are also available, with widths like {n:04}
         #[arg(
             short = 'o',
             long = "output",
@@ -64,28 +137,601 @@ enum Mode {
         save_path_format: String,
     },
 
+    /// Synthesize every .txt/.md file in a directory tree, or every row of a CSV/JSONL
+    /// script with per-row `text`, `voice`, `speed`, `lan` and `output` fields
+    #[command(alias = "b")]
+    Batch {
+        /// Directory to search recursively for .txt and .md files, or a .csv/.jsonl file
+        input_path: String,
+
+        /// Directory to write the WAV files to (mirroring the input directory layout,
+        /// or resolving each row's relative output path)
+        #[arg(
+            short = 'o',
+            long = "output",
+            value_name = "OUTPUT_DIR",
+            default_value = "tmp/batch"
+        )]
+        output_dir: String,
+
+        /// Regenerate outputs that already exist and are newer than their input
+        #[arg(long = "force", default_value_t = false)]
+        force: bool,
+    },
+
+    /// Watch a directory and synthesize new or changed .txt/.md files to WAV files next to them
+    #[command(alias = "w")]
+    Watch {
+        /// Directory to watch recursively
+        dir: String,
+
+        /// Seconds between scans of the directory
+        #[arg(long = "interval", value_name = "SECONDS", default_value_t = 2.0)]
+        interval: f32,
+    },
+
+    /// Synthesize each chapter of an EPUB book to its own WAV file, plus a chapter manifest
+    #[command(long_flag_alias = "epub")]
+    Epub {
+        /// Filesystem path of the EPUB file
+        input_path: String,
+
+        /// Directory to write the chapter WAV files and manifest.json to
+        #[arg(
+            short = 'o',
+            long = "output",
+            value_name = "OUTPUT_DIR",
+            default_value = "tmp/epub"
+        )]
+        output_dir: String,
+
+        /// Regenerate chapters whose WAV file is already newer than the EPUB
+        #[arg(long = "force", default_value_t = false)]
+        force: bool,
+
+        /// Also write a copy of the EPUB with the narration and EPUB 3 media overlays to
+        /// this path, so readers highlight each paragraph as it is read. Chapters are then
+        /// synthesized paragraph by paragraph, and always.
+        #[arg(long = "media-overlays", value_name = "EPUB")]
+        media_overlays: Option<String>,
+    },
+
+    /// Turn an EPUB or Markdown book into one audiobook file with chapter markers: M4B
+    /// (through ffmpeg) or Opus, with every chapter brought to the same loudness
+    Audiobook {
+        /// Filesystem path of the EPUB or Markdown book
+        input_path: String,
+
+        /// The audiobook to write, .m4b or .opus; the chapters' WAV files go to a
+        /// `.chapters` directory next to it
+        #[arg(
+            short = 'o',
+            long = "output",
+            value_name = "OUTPUT_PATH",
+            default_value = "tmp/audiobook.opus"
+        )]
+        save_path: String,
+
+        /// Title of the book, over the one in the EPUB or the file name
+        #[arg(long = "title", value_name = "TITLE")]
+        title: Option<String>,
+
+        /// Author of the book
+        #[arg(long = "author", value_name = "AUTHOR")]
+        author: Option<String>,
+
+        /// File listing the chapters, one title per line, to cut the book at instead of
+        /// its own chapters; each starts at the first line after the previous one that
+        /// reads like its title
+        #[arg(long = "chapters", value_name = "FILE")]
+        chapters: Option<String>,
+
+        /// Regenerate chapters whose WAV file is already newer than the book
+        #[arg(long = "force", default_value_t = false)]
+        force: bool,
+    },
+
+    /// Synthesize an SRT/VTT subtitle file into a single track aligned to the cue timings
+    #[command(aliases = ["srt", "vtt"], long_flag_aliases = ["srt", "vtt"])]
+    Subtitles {
+        /// Filesystem path of the .srt or .vtt file
+        input_path: String,
+
+        /// Path to output the WAV file to on the filesystem
+        #[arg(
+            short = 'o',
+            long = "output",
+            value_name = "OUTPUT_PATH",
+            default_value = "tmp/subtitles.wav"
+        )]
+        save_path: String,
+
+        /// Speed up cues whose audio is longer than the cue duration so they fit
+        #[arg(long = "fit", default_value_t = false)]
+        fit: bool,
+
+        /// Maximum speed that --fit may raise a cue to
+        #[arg(long = "max-speed", value_name = "MAX_SPEED", default_value_t = 1.5)]
+        max_speed: f32,
+    },
+
+    /// Synthesize a multi-speaker script such as `[sarah] Hello. [tom] Hi!` into one file
+    #[command(alias = "script")]
+    Dialogue {
+        /// Filesystem path of the script
+        input_path: String,
+
+        /// Path to output the WAV file to on the filesystem
+        #[arg(
+            short = 'o',
+            long = "output",
+            value_name = "OUTPUT_PATH",
+            default_value = "tmp/dialogue.wav"
+        )]
+        save_path: String,
+
+        /// Voice for a speaker tag, with optional speed and stereo pan (-1 left to 1 right),
+        /// e.g. `--speaker sarah=af_sarah,pan=-0.4`; repeat for every speaker. Tags that are
+        /// voice names need no mapping.
+        #[arg(long = "speaker", value_name = "NAME=VOICE[,speed=S][,pan=P]", value_parser = dialogue::parse_speaker)]
+        speakers: Vec<dialogue::Speaker>,
+
+        /// Seconds of silence between turns
+        #[arg(long = "gap", value_name = "SECONDS", default_value_t = 0.3)]
+        gap: f32,
+    },
+
+    /// Print the chunks, phonemes and token counts for some text without running the model
+    #[command(alias = "ph")]
+    Phonemize {
+        /// Text to phonemize; read from stdin when omitted
+        text: Option<String>,
+
+        /// Read the text from a file instead (Markdown is stripped first)
+        #[arg(
+            short = 'i',
+            long = "input",
+            value_name = "INPUT_PATH",
+            conflicts_with = "text"
+        )]
+        input_path: Option<String>,
+
+        /// Also print the token ids of each chunk
+        #[arg(long = "tokens", default_value_t = false)]
+        tokens: bool,
+    },
+
+    /// Check the phonemes of the words and sentences in a golden TSV file (text, phonemes
+    /// and optionally a language per line) without loading the model, failing when any
+    /// differ
+    Verify {
+        /// Filesystem path of the golden file
+        #[arg(long = "golden", value_name = "GOLDEN_TSV")]
+        golden: String,
+
+        /// Rewrite the golden file with the phonemes the cases have now, after a change
+        /// that was meant to alter them
+        #[arg(long = "update", default_value_t = false)]
+        update: bool,
+    },
+
+    /// Check WAV files for the watermark of --watermark-key, without loading the model,
+    /// failing when any lack it
+    #[command(name = "detect-watermark")]
+    DetectWatermark {
+        /// WAV files to check
+        #[arg(value_name = "WAV", required = true)]
+        inputs: Vec<String>,
+    },
+
+    /// Download the model and voices files from Hugging Face into the cache directory
+    Download {
+        /// Hugging Face repository to download from
+        #[arg(long = "repo", default_value = kokoros::utils::fileio::DEFAULT_MODEL_REPO)]
+        repo: String,
+
+        /// Branch, tag or commit to download, for pinning an exact version
+        #[arg(long = "revision", default_value = "main")]
+        revision: String,
+
+        /// Directory to download to (defaults to $XDG_CACHE_HOME/kokoros)
+        #[arg(long = "dir", value_name = "DIR")]
+        dir: Option<String>,
+
+        /// Download again even if the files already exist
+        #[arg(long = "force", default_value_t = false)]
+        force: bool,
+    },
+
+    /// List the voices in the voices data file with their language and gender, or create
+    /// new ones from them
+    #[command(alias = "voice")]
+    Voices {
+        #[command(subcommand)]
+        command: Option<VoicesCommand>,
+    },
+
+    /// Synthesize a short sample with every voice, to compare them quickly
+    Audition {
+        /// Text to read with each voice
+        #[arg(
+            long = "text",
+            default_value = "Hello! This is how I sound. The quick brown fox jumps over the lazy dog."
+        )]
+        text: String,
+
+        /// Directory to write one <voice>.wav file per voice to
+        #[arg(
+            short = 'o',
+            long = "output",
+            value_name = "OUTPUT_DIR",
+            default_value = "tmp/audition"
+        )]
+        output_dir: String,
+    },
+
+    /// Benchmark synthesis on a built-in corpus, reporting real-time factor and throughput
+    Bench {
+        /// Comma-separated numbers of parallel TTS instances to compare
+        #[arg(
+            long = "configs",
+            value_name = "INSTANCES",
+            value_delimiter = ',',
+            default_value = "1"
+        )]
+        configs: Vec<usize>,
+
+        /// How many times to run the corpus per configuration
+        #[arg(long = "iterations", default_value_t = 3)]
+        iterations: usize,
+
+        /// Comma-separated ONNX Runtime intra-op thread counts to compare (default: the
+        /// --intra-op-threads setting)
+        #[arg(long = "intra-threads", value_name = "THREADS", value_delimiter = ',')]
+        intra_threads: Vec<usize>,
+
+        /// Comma-separated ONNX Runtime inter-op thread counts to compare (default: the
+        /// --inter-op-threads setting)
+        #[arg(long = "inter-threads", value_name = "THREADS", value_delimiter = ',')]
+        inter_threads: Vec<usize>,
+
+        /// Comma-separated execution providers to compare, cpu or cuda (default: the one
+        /// the build prefers)
+        #[arg(long = "providers", value_name = "PROVIDERS", value_delimiter = ',')]
+        providers: Vec<Provider>,
+    },
+
+    /// Interactive session that keeps the model loaded and synthesizes each line typed,
+    /// with `:voice`, `:speed` and `:lan` commands to change settings on the fly
+    #[command(alias = "i")]
+    Repl {
+        /// Format for the output path of each utterance, where {n} will be replaced with the utterance number;
+        /// {voice}, {lan} and {hash} are also available, with widths like {n:04}
+        #[arg(
+            short = 'o',
+            long = "output",
+            value_name = "OUTPUT_PATH_FORMAT",
+            default_value = "tmp/repl_{n}.wav"
+        )]
+        save_path_format: String,
+    },
+
+    /// Speak some text aloud on the default output device without saving it, e.g. from a
+    /// hotkey with --clipboard (requires the `playback` feature)
+    Say {
+        /// Text to speak; read from stdin when omitted
+        text: Option<String>,
+
+        /// Speak the contents of the system clipboard instead
+        #[arg(long = "clipboard", default_value_t = false, conflicts_with = "text")]
+        clipboard: bool,
+    },
+
+    /// Run as a Speech Dispatcher output module on stdin/stdout, so screen readers such as
+    /// Orca can use Kokoros (requires the `playback` feature)
+    #[command(alias = "speech-dispatcher")]
+    Speechd {
+        /// Module configuration file passed by Speech Dispatcher; not used
+        #[arg(hide = true)]
+        _config: Option<String>,
+    },
+
     /// Continuously read from stdin to generate speech, outputting to stdout, for each line
     #[command(aliases = ["stdio", "stdin", "-"], long_flag_aliases = ["stdio", "stdin"])]
-    Stream,
+    Stream {
+        /// Write the audio out at the rate it plays (1x real time) instead of as fast as it
+        /// is synthesized, for live consumers such as Icecast or telephony
+        #[arg(long = "realtime", default_value_t = false)]
+        realtime: bool,
+
+        /// With --realtime, seconds of audio to send ahead of the clock
+        #[arg(
+            long = "prebuffer",
+            value_name = "SECONDS",
+            default_value_t = 0.5,
+            requires = "realtime"
+        )]
+        prebuffer: f32,
+    },
+
+    /// Serve the Wyoming protocol over TCP, for Home Assistant's Wyoming integration
+    #[command(long_flag_alias = "wyoming")]
+    Wyoming {
+        /// IP address to bind to (typically 127.0.0.1 or 0.0.0.0)
+        #[arg(long, default_value_t = [0, 0, 0, 0].into())]
+        ip: std::net::IpAddr,
+
+        /// Port to listen on
+        #[arg(long, default_value_t = 10200)]
+        port: u16,
+    },
+
+    /// Stream each line of stdin to an Icecast mount as its source, as one continuous MP3
+    /// stream with the title updated per line, e.g. for automated news or weather radio
+    Icecast {
+        /// Icecast server host
+        #[arg(long, default_value = "localhost")]
+        host: String,
+
+        /// Icecast server port
+        #[arg(long, default_value_t = 8000)]
+        port: u16,
+
+        /// Mount point to stream to
+        #[arg(long, default_value = "/kokoros")]
+        mount: String,
+
+        /// Source username
+        #[arg(long, default_value = "source")]
+        user: String,
+
+        /// Source password, as set in the server's icecast.xml
+        #[arg(long)]
+        password: String,
+
+        /// Station name shown in directories and players
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Station description
+        #[arg(long)]
+        description: Option<String>,
+
+        /// Station genre
+        #[arg(long)]
+        genre: Option<String>,
+
+        /// MP3 bitrate in kbps
+        #[arg(long, value_name = "KBPS", default_value_t = 64)]
+        bitrate: u32,
+    },
+
+    /// Send speech as RTP to a call leg, e.g. IVR prompts for Asterisk or FreeSWITCH
+    Rtp {
+        /// Address and port the call leg receives RTP on, e.g. 10.0.0.5:16384
+        destination: std::net::SocketAddr,
+
+        /// Text to speak; each line of stdin is spoken as it arrives when omitted
+        text: Option<String>,
+
+        /// Payload format: pcmu (G.711 μ-law), pcma (G.711 A-law) or opus
+        #[arg(long, default_value = "pcmu")]
+        codec: RtpCodec,
+
+        /// Milliseconds of audio per packet; Opus only supports 20
+        #[arg(long, value_name = "MS", default_value_t = 20)]
+        ptime: u32,
+
+        /// Opus bitrate in bits per second, 8000 to 24000
+        #[arg(long, value_name = "BPS", default_value_t = DEFAULT_VOICE_BITRATE)]
+        bitrate: u32,
+    },
+
+    /// Speak text messages published to an MQTT topic, publishing the audio as WAV to
+    /// another topic and/or playing it with --play (requires the `mqtt` feature)
+    #[cfg(feature = "mqtt")]
+    Mqtt {
+        /// MQTT broker host
+        #[arg(long, default_value = "localhost")]
+        host: String,
+
+        /// MQTT broker port
+        #[arg(long, default_value_t = 1883)]
+        port: u16,
+
+        /// Topic to read text from; payloads are plain text, or JSON like
+        /// {"text": "...", "voice": "af_sky", "speed": 1.0}
+        #[arg(long, default_value = "kokoros/say")]
+        topic: String,
+
+        /// Topic to publish the synthesized WAV audio to
+        #[arg(long)]
+        audio_topic: Option<String>,
+
+        /// Username for the broker
+        #[arg(long)]
+        username: Option<String>,
+
+        /// Password for the broker
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Ask an OpenAI-compatible chat endpoint and speak the answer as it streams in,
+    /// sentence by sentence (requires the `chat` feature)
+    #[cfg(feature = "chat")]
+    Chat {
+        /// What to ask; read from stdin when omitted
+        prompt: Option<String>,
+
+        /// Base URL of the chat completions API, e.g. http://localhost:11434/v1 for Ollama
+        #[arg(long, default_value = "https://api.openai.com/v1")]
+        endpoint: String,
+
+        /// Model to ask
+        #[arg(long, default_value = "gpt-4o-mini")]
+        model: String,
+
+        /// Bearer token for the endpoint (default: $OPENAI_API_KEY)
+        #[arg(long)]
+        api_key: Option<String>,
+
+        /// System prompt, replacing the default one that asks for answers fit to be read
+        /// aloud
+        #[arg(long)]
+        system: Option<String>,
+
+        /// Write the spoken answer to this WAV file instead of playing it (or as well, with
+        /// --play)
+        #[arg(short = 'o', long = "output", value_name = "OUTPUT_PATH")]
+        save_path: Option<String>,
+    },
+
+    /// Synthesize a text file sentence by sentence and transcribe it back with a local
+    /// Whisper model, reporting the word error rate of each sentence and failing when any
+    /// look garbled (requires the `whisper` feature)
+    #[cfg(feature = "whisper")]
+    Qa {
+        /// Filesystem path of the text to check
+        input_path: String,
+
+        /// ggml Whisper model of whisper.cpp, e.g. ggml-base.en.bin
+        #[arg(long = "whisper-model", value_name = "MODEL_PATH")]
+        whisper_model: String,
+
+        /// Flag sentences whose word error rate is above this (0 is a perfect match)
+        #[arg(long = "max-wer", value_name = "RATE", default_value_t = 0.3)]
+        max_wer: f32,
+
+        /// Write a TSV report of every sentence, its transcript and word error rate
+        #[arg(short = 'o', long = "report", value_name = "REPORT_PATH")]
+        report_path: Option<String>,
+    },
 
     /// Start an OpenAI-compatible HTTP server
+    #[cfg(feature = "server")]
     #[command(name = "openai", alias = "oai", long_flag_aliases = ["oai", "openai"])]
     OpenAI {
         /// IP address to bind to (typically 127.0.0.1 or 0.0.0.0)
         #[arg(long, default_value_t = [0, 0, 0, 0].into())]
-        ip: IpAddr,
+        ip: std::net::IpAddr,
 
         /// Port to expose the HTTP server on
         #[arg(long, default_value_t = 3000)]
         port: u16,
+
+        /// Bearer token required to upload voices with POST /v1/audio/voices; uploads are
+        /// disabled without one
+        #[arg(long)]
+        api_key: Option<String>,
+
+        /// Cache the audio of non-streaming requests in memory, up to this size (e.g. 256MB),
+        /// and answer identical requests from it
+        #[arg(long, value_name = "SIZE", value_parser = split::parse_size)]
+        cache_size: Option<u64>,
+
+        /// Also keep the cache in this directory, so it survives restarts (256MB unless
+        /// --cache-size is given)
+        #[arg(long, value_name = "DIR")]
+        cache_dir: Option<std::path::PathBuf>,
+
+        /// Stop answering from cached audio this long after it was synthesized (e.g. 24h)
+        #[arg(long, value_name = "DURATION", value_parser = split::parse_duration)]
+        cache_ttl: Option<f32>,
+
+        /// Accept non-streaming requests over --max-input-chars as a job, synthesized in
+        /// parts in the background and fetched from /v1/audio/jobs/{id}, instead of
+        /// refusing them
+        #[arg(long, default_value_t = false)]
+        split_long_inputs: bool,
+
+        /// Keep background jobs and their audio in this SQLite database, so they are still
+        /// there after a restart (requires the `sqlite` feature)
+        #[arg(long, value_name = "PATH")]
+        job_store: Option<std::path::PathBuf>,
+
+        /// Save how much of their quotas the `[server.keys]` of the config file have used
+        /// to this file, so a restart doesn't reset it
+        #[arg(long, value_name = "PATH")]
+        usage_file: Option<std::path::PathBuf>,
+
+        /// Delete finished jobs this long after they were accepted (e.g. 24h)
+        #[arg(long, value_name = "DURATION", value_parser = split::parse_duration, default_value = "168h")]
+        job_retention: f32,
+
+        /// Add x-koko-* headers with the latency, real-time factor, token count and cache
+        /// hit of each non-streaming request to its response
+        #[arg(long, default_value_t = false)]
+        metrics_headers: bool,
     },
 }
 
+impl Mode {
+    /// Whether the mode speaks with --style and --lan, rather than voices chosen per request
+    fn uses_cli_voice(&self) -> bool {
+        match self {
+            Mode::Wyoming { .. } | Mode::Speechd { .. } => false,
+            #[cfg(feature = "server")]
+            Mode::OpenAI { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// Whether the mode streams speech for someone waiting to hear it, so it starts with a
+    /// short first chunk unless --no-fast-start is given
+    fn is_interactive(&self) -> bool {
+        match self {
+            Mode::Say { .. }
+            | Mode::Speechd { .. }
+            | Mode::Stream { .. }
+            | Mode::Wyoming { .. }
+            | Mode::Rtp { .. } => true,
+            #[cfg(feature = "chat")]
+            Mode::Chat { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the mode keeps running to serve others, so a SIGHUP reloads the config
+    /// (see [`reload`]) instead of ending it
+    #[cfg(unix)]
+    fn runs_as_service(&self) -> bool {
+        match self {
+            Mode::Watch { .. }
+            | Mode::Speechd { .. }
+            | Mode::Stream { .. }
+            | Mode::Wyoming { .. }
+            | Mode::Icecast { .. }
+            | Mode::Rtp { .. } => true,
+            #[cfg(feature = "mqtt")]
+            Mode::Mqtt { .. } => true,
+            #[cfg(feature = "server")]
+            Mode::OpenAI { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the mode plays its audio even without --play
+    fn always_plays(&self) -> bool {
+        match self {
+            Mode::Say { .. } | Mode::Speechd { .. } => true,
+            #[cfg(feature = "chat")]
+            Mode::Chat { save_path, .. } => save_path.is_none(),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "kokoros")]
 #[command(version = "0.1")]
 #[command(author = "Lucas Jin")]
 struct Cli {
+    /// TOML file with defaults for these options and the server (default:
+    /// ~/.config/koko/config.toml); flags given on the command line take precedence
+    #[arg(long = "config", value_name = "CONFIG_PATH")]
+    config: Option<String>,
+
     /// A language identifier from
     /// https://github.com/espeak-ng/espeak-ng/blob/master/docs/languages.md
     #[arg(
@@ -105,7 +751,8 @@ struct Cli {
     )]
     model_path: String,
 
-    /// Path to the voices data file on the filesystem
+    /// Path to the voices data file on the filesystem: an npz archive such as voices-v1.0.bin,
+    /// a single .npy or raw .bin voice, or a directory of them
     #[arg(
         short = 'd',
         long = "data",
@@ -114,6 +761,22 @@ struct Cli {
     )]
     data_path: String,
 
+    /// Directory of extra voices (individual voice files or packs) merged with the voices
+    /// file at startup, replacing voices of the same name (default:
+    /// ~/.local/share/kokoros/voices, if it exists)
+    #[arg(long = "voices-dir", value_name = "DIR")]
+    voices_dir: Option<String>,
+
+    /// SHA-256 lockfile (`sha256sum` format) to verify the model and voices files against;
+    /// defaults to a SHA256SUMS file next to the model, if there is one
+    #[arg(long = "checksums", value_name = "LOCKFILE")]
+    checksums: Option<String>,
+
+    /// espeak-ng data directory (`espeak-ng-data`, or the directory containing it) to
+    /// phonemize with, in place of the system installation or the bundled data
+    #[arg(long = "espeak-data", value_name = "DIR")]
+    espeak_data: Option<String>,
+
     /// Which single voice to use or voices to combine to serve as the style of speech
     #[arg(
         short = 's',
@@ -125,6 +788,17 @@ struct Cli {
     )]
     style: String,
 
+    /// Change gradually from one voice to another over the course of the text, e.g.
+    /// `af_sky:am_adam:0..1` for am_adam's share to go from 0 to 1; the blend changes
+    /// with every synthesis chunk (a few sentences)
+    #[arg(
+        long = "morph",
+        value_name = "FROM:TO:START..END",
+        conflicts_with = "style",
+        value_parser = parse_morph
+    )]
+    morph: Option<String>,
+
     /// Rate of speech, as a coefficient of the default
     /// (i.e. 0.0 to 1.0 is slower than default,
     /// whereas 1.0 and beyond is faster than default)
@@ -144,18 +818,284 @@ struct Cli {
     #[arg(long = "initial-silence", value_name = "INITIAL_SILENCE")]
     initial_silence: Option<usize>,
 
+    /// Trim leading and trailing silence from the output
+    #[arg(long = "trim", default_value_t = false)]
+    trim: bool,
+
+    /// Filter DC offset and rumble below 60 Hz out of the output, for voices whose low
+    /// end thumps on small speakers
+    #[arg(long = "high-pass", default_value_t = false)]
+    high_pass: bool,
+
+    /// Scale the output to this integrated loudness in LUFS (EBU R128), e.g. -16 for
+    /// podcasts or -23 for broadcast, as far as it can go without clipping
+    #[arg(long = "loudness", value_name = "LUFS", allow_hyphen_values = true)]
+    loudness: Option<f32>,
+
+    /// Mark the audio written to files with an inaudible watermark made from this key, so
+    /// it can be identified later with `koko detect-watermark`
+    #[arg(long = "watermark-key", value_name = "KEY")]
+    watermark_key: Option<String>,
+
+    /// Level of the watermark relative to the speech; higher survives more processing but
+    /// may be heard as a faint hiss
+    #[arg(
+        long = "watermark-strength",
+        value_name = "STRENGTH",
+        default_value_t = kokoros::utils::watermark::DEFAULT_STRENGTH
+    )]
+    watermark_strength: f32,
+
+    /// Read quoted speech ("...", “...”, «...») in this voice or blend, and everything
+    /// else in --style, as in a narrated audiobook
+    #[arg(long = "quote-voice", value_name = "STYLE")]
+    quote_voice: Option<String>,
+
+    /// Speed for quoted speech (default: the quote voice's or --speed)
+    #[arg(long = "quote-speed", value_name = "SPEED")]
+    quote_speed: Option<f32>,
+
+    /// Set off quotes and parentheticals with a short pause either side, reading quotes a
+    /// little slower and parentheticals a little faster than the narration
+    #[arg(long = "prosody", default_value_t = false)]
+    prosody: bool,
+
+    /// Seconds of silence around quotes and parentheticals with --prosody [default: 0.15]
+    #[arg(long = "prosody-pause", value_name = "SECONDS", requires = "prosody")]
+    prosody_pause: Option<f32>,
+
+    /// Speed of quotes relative to the narration with --prosody [default: 0.97]
+    #[arg(long = "quote-rate", value_name = "FACTOR", requires = "prosody")]
+    quote_rate: Option<f32>,
+
+    /// Speed of parentheticals relative to the narration with --prosody [default: 1.06]
+    #[arg(long = "aside-rate", value_name = "FACTOR", requires = "prosody")]
+    aside_rate: Option<f32>,
+
+    /// Run the text through this plugin before anything else: a shared library, or a
+    /// WebAssembly module ending in .wasm (repeatable, run in order)
+    #[arg(long = "plugin", value_name = "PATH")]
+    plugins: Vec<String>,
+
+    /// Filter the words listed in FILE, one per line (`#` starts a comment, and `word*`
+    /// also matches anything starting with the word)
+    #[arg(long = "censor-words", value_name = "FILE")]
+    censor_words: Option<String>,
+
+    /// What filtered words become: bleep (a tone), silence, or mask (read as "bleep")
+    #[arg(long = "censor", value_name = "MODE", default_value = "bleep")]
+    censor: CensorMode,
+
+    /// Seconds of silence between sentences, in place of the model's own pauses
+    #[arg(long = "pause", value_name = "SECONDS")]
+    pause: Option<f32>,
+
+    /// Spell out identifiers and serial numbers (e.g. AB12-XY9) and short all-caps
+    /// acronyms letter by letter, using English letter names
+    #[arg(long = "spell-out", default_value_t = false)]
+    spell_out: bool,
+
+    /// Read out math: operators ("3 + 4 = 7"), powers ("x^2"), percentages and basic
+    /// LaTeX such as `$\frac{1}{2}$`, e.g. for lecture narration
+    #[arg(long = "verbalize-math", default_value_t = false)]
+    verbalize_math: bool,
+
+    /// Read the text as source code: symbols by name ("=>" as "fat arrow"), identifiers
+    /// split into words ("camelCase", "snake_case"), indentation changes announced, and
+    /// unified diffs line by line as added or removed, e.g. for reviewing code by ear
+    #[arg(long = "verbalize-code", default_value_t = false)]
+    verbalize_code: bool,
+
+    /// Hyphenated compounds ("state-of-the-art") and dashes around asides: split (each
+    /// part a word, with a pause at dashes), join (one word, e.g. "email") or keep (left
+    /// to espeak)
+    #[arg(long = "compounds", value_name = "MODE", default_value = "split")]
+    compounds: Compounds,
+
     /// Also output a sidecar TSV file with word-level timestamps
     #[arg(long = "timestamps", default_value_t = false, global = true)]
     timestamps: bool,
 
-    /// Number of TTS instances for parallel processing
+    /// Also output a read-along JSON file with the text and its sentence and word timings,
+    /// for highlighting text during playback; implies --timestamps
+    #[arg(long = "read-along", default_value_t = false, global = true)]
+    read_along: bool,
+
+    /// Split long outputs into numbered files of at most this duration (e.g. 30m, 1h30m, 90s),
+    /// cutting at sentence boundaries (text, batch and epub modes)
+    #[arg(long = "max-duration", value_name = "DURATION", value_parser = split::parse_duration)]
+    max_duration: Option<f32>,
+
+    /// Split long outputs into numbered files of at most this size (e.g. 100MB)
+    #[arg(long = "max-size", value_name = "SIZE", value_parser = split::parse_size)]
+    max_size: Option<u64>,
+
+    /// Print how long the audio will be instead of synthesizing it, estimated from token
+    /// counts without loading the model (text, file, batch and epub modes)
+    #[arg(long = "estimate", default_value_t = false)]
+    estimate: bool,
+
+    /// Seconds of audio per token at speed 1.0 for --estimate; `koko bench` measures it
+    /// for a voice
+    #[arg(
+        long = "seconds-per-token",
+        value_name = "SECONDS",
+        default_value_t = kokoros::tts::estimate::DEFAULT_SECONDS_PER_TOKEN
+    )]
+    seconds_per_token: f32,
+
+    /// Refuse inputs longer than this many characters: the text in text mode, each line in
+    /// file and stream modes, and each request to the OpenAI server
+    #[arg(long = "max-input-chars", value_name = "CHARS")]
+    max_input_chars: Option<usize>,
+
+    /// Don't start streamed speech (stream, say, speechd, wyoming and chat modes) with a
+    /// short first chunk of about a second, which gets audio out sooner at the cost of a
+    /// seam after it
+    #[arg(long = "no-fast-start", default_value_t = false)]
+    no_fast_start: bool,
+
+    /// Make runs repeatable for comparing against golden files: deterministic ONNX Runtime
+    /// kernels on one thread per instance, and the model and voices hashes recorded in
+    /// every WAV file written
+    #[arg(long = "reproducible", default_value_t = false)]
+    reproducible: bool,
+
+    /// Don't synthesize outputs whose file already exists (file, text and batch modes).
+    /// Meant for output paths named by `{key}`, which changes whenever the audio would.
+    #[arg(long = "skip-existing", default_value_t = false)]
+    skip_existing: bool,
+
+    /// Synthesize sentences that occur more than once in a text (boilerplate in templated
+    /// reports) only once, reusing their audio wherever they recur
+    #[arg(long = "dedupe", default_value_t = false)]
+    dedupe: bool,
+
+    /// Log more detail: -v for debug messages, -vv to also time each stage of every chunk
+    /// (phonemization, tokenization, inference, encoding). RUST_LOG takes precedence.
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Don't show progress or per-item messages, only errors and summaries
+    #[arg(short = 'q', long = "quiet", default_value_t = false)]
+    quiet: bool,
+
+    /// Print a JSON line on stdout for each item processed (input, output, duration, RTF
+    /// and warnings) and nothing else there, in the text, file, batch, epub and audiobook
+    /// modes
+    #[arg(long = "json", default_value_t = false)]
+    json: bool,
+
+    /// Also play the audio on the default output device (text, file, repl, audition and stream modes)
+    #[arg(long = "play", default_value_t = false)]
+    play: bool,
+
+    /// Number of TTS instances for parallel processing (OpenAI server and batch mode, and
+    /// other modes with --parallel)
     #[arg(long = "instances", value_name = "INSTANCES", default_value_t = 2)]
     instances: usize,
 
+    /// Synthesize the chunks of long texts on --instances instances at once and put them
+    /// back in order (text, file, epub, dialogue, repl and audition modes)
+    #[arg(long = "parallel", default_value_t = false)]
+    parallel: bool,
+
+    /// With --parallel, how much audio may be held for chunks finished ahead of their turn
+    /// before the instances wait for the one that is due
+    #[arg(long = "memory-budget", value_name = "SIZE", value_parser = split::parse_size, default_value = "256MB")]
+    memory_budget: u64,
+
+    /// Run a few dummy inferences on every TTS instance at startup, so the first real
+    /// synthesis doesn't pay for lazy initialization
+    #[arg(long = "warmup", default_value_t = false)]
+    warmup: bool,
+
+    /// ONNX Runtime threads per instance for the work within one operator (default: one
+    /// per physical core). With several instances, give each a share of the cores.
+    #[arg(long = "intra-op-threads", value_name = "THREADS")]
+    intra_op_threads: Option<usize>,
+
+    /// ONNX Runtime threads per instance for running independent operators at once (with
+    /// --parallel-execution)
+    #[arg(long = "inter-op-threads", value_name = "THREADS")]
+    inter_op_threads: Option<usize>,
+
+    /// Let idle ONNX Runtime threads sleep instead of busy-waiting for work, freeing the
+    /// cores for other instances at some cost in latency
+    #[arg(long = "no-spinning", default_value_t = false)]
+    no_spinning: bool,
+
+    /// Run independent branches of the model at once instead of one operator at a time
+    #[arg(long = "parallel-execution", default_value_t = false)]
+    parallel_execution: bool,
+
+    /// Keep freed CPU memory in ONNX Runtime's arena for reuse instead of returning it;
+    /// faster, at the cost of holding each instance's peak memory
+    #[arg(long = "cpu-arena", default_value_t = false)]
+    cpu_arena: bool,
+
+    /// Most GPU memory each instance's CUDA arena may take (e.g. 2GB), so other models can
+    /// use the same GPU (CUDA builds)
+    #[arg(long = "gpu-memory-limit", value_name = "SIZE", value_parser = split::parse_size)]
+    gpu_memory_limit: Option<u64>,
+
+    /// Grow the CUDA arena by exactly what each allocation needs instead of doubling it
+    /// (CUDA builds)
+    #[arg(long = "gpu-arena-exact", default_value_t = false)]
+    gpu_arena_exact: bool,
+
+    /// Comma-separated CUDA devices to spread each instance's sessions over, one session
+    /// per device (CUDA builds)
+    #[arg(long = "gpu-devices", value_name = "IDS", value_delimiter = ',')]
+    gpu_devices: Vec<i32>,
+
+    /// Which device gets a chunk when several are free: round-robin or least-loaded
+    #[arg(
+        long = "gpu-balance",
+        value_name = "STRATEGY",
+        default_value = "round-robin"
+    )]
+    gpu_balance: Balance,
+
+    /// Where the model runs: cpu, cuda (CUDA builds) or tract (tract builds), a pure-Rust
+    /// engine instead of ONNX Runtime; the one the build prefers by default
+    #[arg(long = "provider", value_name = "PROVIDER")]
+    provider: Option<Provider>,
+
+    /// How far ONNX Runtime optimizes the model's graph: disable, basic, extended or all
+    /// (the default). Lower levels start faster and may run slower.
+    #[arg(long = "graph-optimization", value_name = "LEVEL")]
+    graph_optimization: Option<OptimizationLevel>,
+
+    /// Save the optimized graph to this directory and load it from there on the next
+    /// start, skipping most of the optimization time
+    #[arg(long = "optimized-model-dir", value_name = "DIR")]
+    optimized_model_dir: Option<std::path::PathBuf>,
+
+    /// Profile every session with ONNX Runtime's profiler, writing the time each operator
+    /// took to <PREFIX>-<n>_<date>.json on exit (for chrome://tracing)
+    #[arg(long = "ort-profile", value_name = "PREFIX")]
+    ort_profile: Option<std::path::PathBuf>,
+
+    /// Release of the model (0.19 or 1.0), when its metadata and file name don't say;
+    /// otherwise detected
+    #[arg(long = "model-version", value_name = "VERSION")]
+    model_version: Option<ModelVersion>,
+
+    /// Sample format of WAV outputs: 16- or 24-bit integer PCM, or 32-bit float
+    #[arg(long = "bit-depth", value_name = "BITS", default_value = "32")]
+    bit_depth: BitDepth,
+
     #[command(subcommand)]
     mode: Mode,
 }
 
+fn parse_morph(spec: &str) -> Result<String, String> {
+    kokoros::tts::voices::Morph::parse(spec)
+        .map(|_| spec.to_string())
+        .map_err(|e| e.to_string())
+}
+
 fn derive_tsv_path_from_wav(path: &str) -> String {
     let p = Path::new(path);
     if let Some(stem) = p.file_stem().and_then(|s| s.to_str()) {
@@ -176,56 +1116,17 @@ fn write_wav_file(
     samples: &[f32],
     sample_rate: u32,
     mono: bool,
+    bit_depth: BitDepth,
 ) -> std::io::Result<()> {
-    use std::fs::File;
-    use std::io::Write;
-
-    let channels: u16 = if mono { 1 } else { 2 };
-    let bits_per_sample: u16 = 32; // f32
-    let bytes_per_sample: u32 = (bits_per_sample as u32) / 8;
-    let block_align: u16 = channels * bits_per_sample / 8;
-    let byte_rate: u32 = sample_rate * (block_align as u32);
-
-    // Data size in bytes
-    let num_frames: usize = samples.len();
-    let total_samples_to_write: usize = if mono { num_frames } else { num_frames * 2 };
-    let data_size: u32 = (total_samples_to_write as u32) * bytes_per_sample;
-    let riff_chunk_size: u32 = 36 + data_size; // 4 + (8+16) + (8+data)
-
-    let mut f = File::create(path)?;
-
-    // RIFF header
-    f.write_all(b"RIFF")?;
-    f.write_all(&riff_chunk_size.to_le_bytes())?;
-    f.write_all(b"WAVE")?;
-
-    // fmt chunk
-    f.write_all(b"fmt ")?;
-    f.write_all(&(16u32).to_le_bytes())?; // PCM fmt chunk size
-    f.write_all(&(3u16).to_le_bytes())?; // IEEE float = 3
-    f.write_all(&channels.to_le_bytes())?;
-    f.write_all(&sample_rate.to_le_bytes())?;
-    f.write_all(&byte_rate.to_le_bytes())?;
-    f.write_all(&block_align.to_le_bytes())?;
-    f.write_all(&bits_per_sample.to_le_bytes())?;
-
-    // data chunk
-    f.write_all(b"data")?;
-    f.write_all(&data_size.to_le_bytes())?;
-
-    // write samples
-    if mono {
-        for &s in samples {
-            f.write_all(&s.to_le_bytes())?;
-        }
-    } else {
-        for &s in samples {
-            f.write_all(&s.to_le_bytes())?; // left
-            f.write_all(&s.to_le_bytes())?; // right (duplicate for simple stereo)
-        }
-    }
-
-    Ok(())
+    let start = std::time::Instant::now();
+    let channels = if mono { 1 } else { 2 };
+    wav::write_wav(Path::new(path), samples, sample_rate, channels, bit_depth)?;
+    tracing::trace!(
+        "encoding {}: {:.1} ms",
+        path,
+        start.elapsed().as_secs_f64() * 1000.0
+    );
+    reproducible::tag(Path::new(path))
 }
 
 fn write_tsv(path: &str, alignments: &[(String, f32, f32)]) -> std::io::Result<()> {
@@ -241,124 +1142,676 @@ fn write_tsv(path: &str, alignments: &[(String, f32, f32)]) -> std::io::Result<(
     Ok(())
 }
 
+/// Writes the read-along JSON of `text`, spoken as `alignments` in `audio`, next to the
+/// WAV file at `wav_path`; returns its path
+fn write_read_along(
+    wav_path: &str,
+    text: &str,
+    alignments: &[(String, f32, f32)],
+    audio: &[f32],
+) -> std::io::Result<String> {
+    let path = Path::new(wav_path).with_extension("json");
+    let doc = readalong::read_along(text, alignments, audio.len() as f32 / 24_000.0);
+    fs::write(&path, serde_json::to_string_pretty(&doc)?)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Why `text` can't be synthesized with `--max-input-chars limit`, if it can't
+fn input_too_long(what: &str, text: &str, limit: Option<usize>) -> Option<String> {
+    let length = text.chars().count();
+    limit.filter(|&limit| length > limit).map(|limit| {
+        format!(
+            "{} is {} characters, more than --max-input-chars {}",
+            what, length, limit
+        )
+    })
+}
+
+/// Loads the model and voices file, plus the user voices directory if there is one, and
+/// warms the model up if asked to
+async fn load_tts(
+    model_path: &str,
+    data_path: &str,
+    voices_dir: Option<&Path>,
+    session: &SessionOptions,
+    gpus: &GpuOptions,
+    model_version: Option<ModelVersion>,
+    warmup: bool,
+) -> Result<TTSKoko, Box<dyn std::error::Error>> {
+    let mut builder = TTSKoko::builder(model_path, data_path)
+        .session(session.clone())
+        .gpu_devices(gpus.devices.iter().copied())
+        .balance(gpus.balance);
+    if let Some(version) = model_version {
+        builder = builder.model_version(version);
+    }
+    let mut tts = builder.build().await?;
+    if let Some(dir) = voices_dir {
+        tts.add_voices_dir(dir)
+            .map_err(|e| format!("could not load voices from {}: {}", dir.display(), e))?;
+    }
+    if warmup {
+        let elapsed = tts.warmup(&kokoros::tts::koko::WARMUP_TOKEN_LENGTHS)?;
+        tracing::info!(
+            "Warmed up in {:.1}s on {}",
+            elapsed.as_secs_f32(),
+            tts.execution_provider()
+        );
+    }
+    Ok(tts)
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing with Unix timestamp format and environment-based log level
-    tracing_subscriber::fmt()
-        .with_timer(UnixTimestampFormatter)
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+    let matches = config::with_env(Cli::command()).get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // Initialize tracing with Unix timestamp format and environment-based log level.
+    // -v/-vv only raise our own crates, so ORT's internals don't flood the output.
+    let default_filter = match cli.verbose {
+        0 => "info",
+        1 => "info,koko=debug,kokoros=debug,kokoros_openai=debug",
+        _ => "info,koko=trace,kokoros=trace,kokoros_openai=trace",
+    };
+    // stdout carries the protocol in speechd mode
+    let log_to_stderr = matches!(cli.mode, Mode::Speechd { .. });
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env().ok();
+    // The config file's log_level only applies when neither -v nor RUST_LOG sets one
+    let log_level_fixed = cli.verbose > 0 || env_filter.is_some();
+    let (log_filter, log_filter_handle) = tracing_subscriber::reload::Layer::new(
+        env_filter.unwrap_or_else(|| tracing_subscriber::EnvFilter::new(default_filter)),
+    );
+    tracing_subscriber::registry()
+        .with(log_filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(move || -> Box<dyn std::io::Write> {
+                    if log_to_stderr {
+                        Box::new(std::io::stderr())
+                    } else {
+                        Box::new(std::io::stdout())
+                    }
+                })
+                .with_timer(UnixTimestampFormatter),
         )
         .init();
 
+    let config = config::load(cli.config.as_deref())?;
+    let replacements = config.replacements()?;
+    #[cfg(feature = "server")]
+    let api_keys = config.server.keys.clone();
+    #[cfg(feature = "server")]
+    let voice_aliases = config.server.voice_aliases.clone();
+    let reloadable = reload::Reloadable {
+        config_path: cli.config.clone(),
+        log_filter: (!log_level_fixed).then_some(log_filter_handle),
+        default_log_level: default_filter,
+        replacements: replacements.into(),
+        #[cfg(feature = "server")]
+        server: Default::default(),
+    };
+    #[cfg(feature = "server")]
+    let server_reload = std::sync::Arc::clone(&reloadable.server);
+    reloadable.set_log_level(&config)?;
+    let voice_defaults = config.apply(&mut cli, &matches);
+    // Safety: no other threads have been started yet
+    let espeak_data = unsafe {
+        kokoros::tts::espeak_data::set_data_dir(cli.espeak_data.as_deref().map(Path::new))?
+    };
+    if let Some(dir) = espeak_data {
+        tracing::debug!("Using espeak-ng data from {}", dir.display());
+    }
+
     let rt = tokio::runtime::Runtime::new()?;
     rt.block_on(async {
         let Cli {
+            config: _,
             lan,
             model_path,
             data_path,
+            voices_dir,
+            checksums,
+            espeak_data: _,
             style,
+            morph,
             speed,
             initial_silence,
+            trim,
+            high_pass,
+            loudness,
+            watermark_key,
+            watermark_strength,
+            pause,
+            spell_out,
+            verbalize_math,
+            verbalize_code,
+            compounds,
+            quote_voice,
+            quote_speed,
+            prosody,
+            prosody_pause,
+            quote_rate,
+            aside_rate,
+            plugins,
+            censor_words,
+            censor,
             mono,
             timestamps,
+            read_along,
+            max_duration,
+            max_size,
+            estimate,
+            seconds_per_token,
+            max_input_chars,
+            no_fast_start,
+            reproducible,
+            skip_existing,
+            dedupe,
+            verbose: _,
+            quiet,
+            json,
+            play,
             instances,
+            parallel,
+            memory_budget,
+            warmup,
+            intra_op_threads,
+            inter_op_threads,
+            no_spinning,
+            parallel_execution,
+            cpu_arena,
+            gpu_memory_limit,
+            gpu_arena_exact,
+            gpu_devices,
+            gpu_balance,
+            provider,
+            graph_optimization,
+            optimized_model_dir,
+            ort_profile,
+            model_version,
+            bit_depth,
             mode,
-        } = Cli::parse();
+        } = cli;
+        let gpus = GpuOptions {
+            devices: gpu_devices,
+            balance: gpu_balance,
+        };
+        let session = SessionOptions {
+            intra_threads: intra_op_threads,
+            inter_threads: inter_op_threads,
+            spinning: no_spinning.then_some(false),
+            parallel_execution,
+            cpu_arena,
+            gpu_memory_limit: gpu_memory_limit.map(|limit| limit as usize),
+            gpu_arena_exact,
+            gpu_device: None,
+            deterministic: false,
+            provider,
+            optimization: graph_optimization,
+            optimized_model_dir,
+            profiling: ort_profile,
+        };
+        // For the JSON lines, which are read by programs rather than people
+        let mut warnings = Vec::new();
+        let session = if reproducible {
+            if intra_op_threads.is_some_and(|n| n != 1)
+                || inter_op_threads.is_some_and(|n| n != 1)
+                || parallel_execution
+            {
+                let warning = "--reproducible runs on one thread; the thread options are ignored";
+                tracing::warn!("{}", warning);
+                warnings.push(warning.to_string());
+            }
+            session.reproducible()
+        } else {
+            session
+        };
+        // Morphs go wherever a voice name or blend does
+        let style = morph.unwrap_or(style);
+
+        // Dry-run modes that don't need the model
+        // Refuse over-long input before spending time on loading the model
+        match &mode {
+            Mode::Text { text, .. } => {
+                if let Some(e) = input_too_long("the text", text, max_input_chars) {
+                    return Err(e.into());
+                }
+            }
+            Mode::File { input_path, .. } => {
+                for (i, line) in fs::read_to_string(input_path)?.lines().enumerate() {
+                    let what = format!("line {} of {}", i + 1, input_path);
+                    if let Some(e) = input_too_long(&what, line.trim(), max_input_chars) {
+                        return Err(e.into());
+                    }
+                }
+            }
+            #[cfg(feature = "server")]
+            Mode::OpenAI {
+                split_long_inputs: true,
+                ..
+            } if max_input_chars.is_none() => {
+                return Err("--split-long-inputs needs a --max-input-chars limit".into());
+            }
+            _ => {}
+        }
+
+        let plugins = plugins
+            .iter()
+            .map(|path| kokoros::tts::plugin::load(Path::new(path)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let text_options = TextOptions {
+            plugins: plugins.into(),
+            replacements: reloadable.replacements.clone(),
+            spell_out,
+            verbalize_math,
+            verbalize_code,
+            compounds,
+            normalize: None,
+        };
+        if let Mode::Verify { golden, update } = &mode {
+            return verify::run(Path::new(golden), &lan, &text_options, *update);
+        }
+        let watermark = watermark_key
+            .as_deref()
+            .map(|key| Watermark::new(key, watermark_strength));
+        if let Mode::DetectWatermark { inputs } = &mode {
+            let watermark = watermark.ok_or("detect-watermark needs --watermark-key")?;
+            return watermark::run(&watermark, inputs);
+        }
+        // The modes below don't load the model, so its metadata can't say which release it is
+        let dry_run_version = model_version
+            .or_else(|| {
+                let file_name = Path::new(&model_path).file_name().unwrap_or_default();
+                ModelVersion::detect(None, &file_name.to_string_lossy())
+            })
+            .unwrap_or_default();
+        if estimate {
+            let calibration = kokoros::tts::estimate::Calibration { seconds_per_token };
+            return estimate::run(
+                &mode,
+                &lan,
+                speed,
+                &calibration,
+                &text_options,
+                dry_run_version,
+                quiet,
+            );
+        }
+        if let Mode::Phonemize {
+            text,
+            input_path,
+            tokens,
+        } = &mode
+        {
+            let text = match (text, input_path) {
+                (Some(text), _) => text.clone(),
+                (None, Some(path)) => batch::read_input_text(Path::new(path))?,
+                (None, None) => std::io::read_to_string(std::io::stdin())?,
+            };
+            phonemize::run(&text, &lan, &text_options, dry_run_version, *tokens)?;
+            return Ok(());
+        }
+        if let Mode::Download {
+            repo,
+            revision,
+            dir,
+            force,
+        } = &mode
+        {
+            let dir = dir
+                .as_ref()
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(kokoros::utils::fileio::cache_dir);
+            return download::run(repo, revision, &dir, *force).await;
+        }
 
-        let tts = TTSKoko::new(&model_path, &data_path).await;
+        // Fall back to files fetched by `koko download` when the given paths don't exist
+        let model_path = kokoros::utils::fileio::resolve_cached(&model_path);
+        let data_path = kokoros::utils::fileio::resolve_cached(&data_path);
+        let voices_dir = voices_dir.map(std::path::PathBuf::from).or_else(|| {
+            let default = kokoros::utils::fileio::voices_dir();
+            default.is_dir().then_some(default)
+        });
+
+        if let Mode::Voices { command } = mode {
+            return match command {
+                None => voices::run(&data_path, voices_dir.as_deref()),
+                Some(VoicesCommand::Mix {
+                    recipe,
+                    name,
+                    scale,
+                    dir,
+                }) => {
+                    let dir = dir
+                        .map(std::path::PathBuf::from)
+                        .unwrap_or_else(kokoros::utils::fileio::voices_dir);
+                    voices::mix(&data_path, &recipe, &name, scale, &dir)
+                }
+            };
+        }
+
+        let lockfile = checksums.map(std::path::PathBuf::from).or_else(|| {
+            let default =
+                Path::new(&model_path).with_file_name(kokoros::utils::checksum::LOCKFILE_NAME);
+            default.exists().then_some(default)
+        });
+        if let Some(lockfile) = lockfile {
+            kokoros::utils::checksum::verify_files(
+                &lockfile,
+                &[Path::new(&model_path), Path::new(&data_path)],
+            )?;
+        }
+        if reproducible {
+            reproducible::record(Path::new(&model_path), Path::new(&data_path))?;
+        }
+        template::set_model(Path::new(&model_path));
+
+        let player = if play || mode.always_plays() {
+            Some(playback::Player::new()?)
+        } else {
+            None
+        };
+
+        let max_samples = split::max_samples(max_duration, max_size, mono);
+        let timestamps = timestamps || read_along;
+        let tts = load_tts(
+            &model_path,
+            &data_path,
+            voices_dir.as_deref(),
+            &session,
+            &gpus,
+            model_version,
+            warmup,
+        )
+        .await?;
+        let tts = if mode.is_interactive() && !no_fast_start {
+            tts.with_first_chunk_tokens(Some(kokoros::tts::chunker::FIRST_CHUNK_TOKENS))
+        } else {
+            tts
+        };
+        let tts = tts.with_text_options(text_options.clone());
+        #[cfg(unix)]
+        if mode.runs_as_service() {
+            reload::watch(reloadable)?;
+        }
+        if mode.uses_cli_voice() {
+            let registry = voices::registry(
+                &data_path,
+                voices_dir.as_deref(),
+                &tts.get_available_voices(),
+            )?;
+            if let Some(warning) = registry.check_language(&style, &lan) {
+                tracing::warn!("{}", warning);
+                warnings.push(warning.to_string());
+            }
+        }
+        // Batch mode spreads whole files over the instances instead
+        let mut pool = Vec::new();
+        if parallel && instances > 1 && !matches!(mode, Mode::Batch { .. }) {
+            pool.push(tts.clone());
+            for i in 1..instances {
+                tracing::info!(
+                    "Initializing TTS instance [{}] ({}/{})",
+                    format!("{:02x}", i),
+                    i + 1,
+                    instances
+                );
+                pool.push(
+                    load_tts(
+                        &model_path,
+                        &data_path,
+                        voices_dir.as_deref(),
+                        &session,
+                        &gpus,
+                        model_version,
+                        warmup,
+                    )
+                    .await?
+                    .with_text_options(text_options.clone()),
+                );
+            }
+        }
+        let censor = match &censor_words {
+            Some(path) => {
+                let list = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read censor words {}: {}", path, e))?;
+                Censor::new(&parse_word_list(&list), censor)
+            }
+            None => None,
+        };
+        let reporter = json.then(|| report::Reporter::new(warnings));
+        let settings = batch::SynthSettings {
+            lan: &lan,
+            style: &style,
+            speed,
+            initial_silence,
+            trim,
+            high_pass,
+            loudness,
+            pause,
+            quote_voice: quote_voice.as_deref(),
+            quote_speed,
+            prosody: prosody.then(|| {
+                let defaults = Prosody::default();
+                Prosody {
+                    pause: prosody_pause.unwrap_or(defaults.pause),
+                    quote_rate: quote_rate.unwrap_or(defaults.quote_rate),
+                    aside_rate: aside_rate.unwrap_or(defaults.aside_rate),
+                }
+            }),
+            censor: censor.as_ref(),
+            pool: &pool,
+            memory_budget: memory_budget as usize,
+            voice_defaults: &voice_defaults,
+            mono,
+            bit_depth,
+            timestamps,
+            read_along,
+            max_samples,
+            watermark: watermark.as_ref(),
+            dedupe,
+            text_options: &text_options,
+            report: reporter.as_ref(),
+            progress: None,
+        };
 
         match mode {
             Mode::File {
                 input_path,
                 save_path_format,
             } => {
-                let file_content = fs::read_to_string(input_path)?;
-                for (i, line) in file_content.lines().enumerate() {
-                    let stripped_line = line.trim();
-                    if stripped_line.is_empty() {
-                        continue;
-                    }
+                let file_content = fs::read_to_string(&input_path)?;
+                let lines: Vec<(usize, &str)> = file_content
+                    .lines()
+                    .map(str::trim)
+                    .enumerate()
+                    .filter(|(_, line)| !line.is_empty())
+                    .collect();
+                let progress = progress::Progress::new(
+                    lines.len(),
+                    lines.iter().map(|(_, line)| line.chars().count()).sum(),
+                    quiet,
+                );
 
-                    let save_path = save_path_format.replace("{line}", &i.to_string());
-                    if timestamps {
-                        match tts.tts_timestamped_raw_audio(
-                            stripped_line,
-                            &lan,
-                            &style,
-                            speed,
-                            initial_silence,
-                            None,
-                            None,
+                for (i, stripped_line) in lines {
+                    let started = std::time::Instant::now();
+                    let item = progress.item(stripped_line.chars().count());
+                    let settings = batch::SynthSettings {
+                        progress: Some(&item),
+                        ..settings
+                    };
+                    let source = format!("{}:{}", input_path, i + 1);
+                    let save_path = template::render_path(
+                        &save_path_format,
+                        &template::TemplateVars {
+                            n: i + 1,
+                            line: i,
+                            text: stripped_line,
+                            settings: &settings,
+                        },
+                    )?;
+                    let report = |item: report::ItemReport| {
+                        if let Some(report) = settings.report {
+                            report.emit(item);
+                        }
+                    };
+                    if skip_existing && Path::new(&save_path).exists() {
+                        progress.message(&format!("Skipping {}, it already exists", save_path));
+                        report(report::ItemReport::skipped(
+                            &source,
+                            Path::new(&save_path),
                             None,
-                        ) {
-                            Ok(Some((audio, words))) => {
+                        ));
+                    } else if timestamps {
+                        match batch::synthesize_timestamped(&tts, stripped_line, &settings) {
+                            Ok((mut audio, mut rows)) => {
+                                batch::post_process(&mut audio, &mut rows, &settings);
+
                                 // Write WAV
                                 // Note: current engine uses 24kHz
-                                write_wav_file(&save_path, &audio, 24_000, mono)?;
+                                write_wav_file(&save_path, &audio, 24_000, mono, bit_depth)?;
 
                                 // Write TSV sidecar
                                 let tsv_path = derive_tsv_path_from_wav(&save_path);
-                                let rows: Vec<(String, f32, f32)> = words
-                                    .into_iter()
-                                    .map(|w| (w.word, w.start_sec, w.end_sec))
-                                    .collect();
                                 write_tsv(&tsv_path, &rows)?;
-                                eprintln!("Audio saved to {}", save_path);
-                                eprintln!("Timestamps saved to {}", tsv_path);
-                            }
-                            Ok(None) => {
-                                eprintln!("No audio produced for line {}", i + 1);
+                                progress.message(&format!("Audio saved to {}", save_path));
+                                progress.message(&format!("Timestamps saved to {}", tsv_path));
+                                if read_along {
+                                    let json_path =
+                                        write_read_along(&save_path, stripped_line, &rows, &audio)?;
+                                    progress.message(&format!("Read-along saved to {}", json_path));
+                                }
+                                if let Some(player) = &player {
+                                    player.append(&audio, 24_000);
+                                }
+                                report(report::ItemReport::done(
+                                    &source,
+                                    Path::new(&save_path),
+                                    audio.len() as f32 / 24_000.0,
+                                    started.elapsed(),
+                                ));
                             }
                             Err(e) => {
-                                eprintln!("Error processing line {}: {}", i + 1, e);
+                                progress.error(&format!("Error processing line {}: {}", i + 1, e));
+                                report(report::ItemReport::failed(
+                                    &source,
+                                    Path::new(&save_path),
+                                    e,
+                                ));
                             }
                         }
                     } else {
-                        tts.tts(TTSOpts {
-                            txt: stripped_line,
-                            lan: &lan,
-                            style_name: &style,
-                            save_path: &save_path,
-                            mono,
-                            speed,
-                            initial_silence,
-                        })?;
+                        let audio = batch::synthesize_audio(&tts, stripped_line, &settings)?;
+                        write_wav_file(&save_path, &audio, 24_000, mono, bit_depth)?;
+                        progress.message(&format!("Audio saved to {}", save_path));
+                        if let Some(player) = &player {
+                            player.append(&audio, 24_000);
+                        }
+                        report(report::ItemReport::done(
+                            &source,
+                            Path::new(&save_path),
+                            audio.len() as f32 / 24_000.0,
+                            started.elapsed(),
+                        ));
                     }
+                    item.finish();
+                }
+                progress.finish();
+                if let Some(player) = &player {
+                    player.wait();
                 }
             }
 
             Mode::Text { text, save_path } => {
+                let save_path = template::render_path(
+                    &save_path,
+                    &template::TemplateVars {
+                        n: 1,
+                        line: 0,
+                        text: &text,
+                        settings: &settings,
+                    },
+                )?;
                 let s = std::time::Instant::now();
-                if timestamps {
-                    match tts.tts_timestamped_raw_audio(
+                if skip_existing && Path::new(&save_path).exists() {
+                    eprintln!("Skipping {}, it already exists", save_path);
+                    if let Some(report) = settings.report {
+                        report.emit(report::ItemReport::skipped(
+                            &text,
+                            Path::new(&save_path),
+                            None,
+                        ));
+                    }
+                    return Ok(());
+                }
+                // A bar for long texts, moving with every chunk, when nothing else is
+                // written to stderr in the meantime
+                let show_progress =
+                    !quiet && settings.report.is_none() && std::io::stderr().is_terminal();
+                let progress = progress::Progress::new(1, text.chars().count(), !show_progress);
+                let item = progress.item(text.chars().count());
+                let settings = batch::SynthSettings {
+                    progress: Some(&item),
+                    ..settings
+                };
+                // Seconds of audio written, or why there is none
+                let outcome: Result<f32, String> = if let Some(max_samples) = max_samples {
+                    let parts = split::synthesize_parts(
+                        &tts,
                         &text,
-                        &lan,
-                        &style,
-                        speed,
-                        initial_silence,
-                        None,
-                        None,
-                        None,
-                    ) {
-                        Ok(Some((audio, words))) => {
-                            write_wav_file(&save_path, &audio, 24_000, mono)?;
+                        Path::new(&save_path),
+                        &settings,
+                        max_samples,
+                    );
+                    progress.finish();
+                    let parts = parts?;
+                    for (path, duration) in &parts {
+                        eprintln!(
+                            "Audio saved to {} ({})",
+                            path.display(),
+                            progress::format_duration(*duration)
+                        );
+                    }
+                    Ok(parts.iter().map(|(_, duration)| duration).sum())
+                } else if timestamps {
+                    let timed = batch::synthesize_timestamped(&tts, &text, &settings);
+                    progress.finish();
+                    match timed {
+                        Ok((mut audio, mut rows)) => {
+                            batch::post_process(&mut audio, &mut rows, &settings);
+                            write_wav_file(&save_path, &audio, 24_000, mono, bit_depth)?;
                             let tsv_path = derive_tsv_path_from_wav(&save_path);
-                            let rows: Vec<(String, f32, f32)> = words
-                                .into_iter()
-                                .map(|w| (w.word, w.start_sec, w.end_sec))
-                                .collect();
                             write_tsv(&tsv_path, &rows)?;
                             eprintln!("Audio saved to {}", save_path);
                             eprintln!("Timestamps saved to {}", tsv_path);
-                        }
-                        Ok(None) => {
-                            eprintln!("No audio produced for input text");
+                            if read_along {
+                                let json_path = write_read_along(&save_path, &text, &rows, &audio)?;
+                                eprintln!("Read-along saved to {}", json_path);
+                            }
+                            if let Some(player) = &player {
+                                player.append(&audio, 24_000);
+                            }
+                            Ok(audio.len() as f32 / 24_000.0)
                         }
                         Err(e) => {
                             eprintln!("Error processing input text: {}", e);
+                            Err(e.to_string())
                         }
                     }
+                } else if player.is_some()
+                    || settings.post_processes()
+                    || reproducible
+                    || has_sounds(&text)
+                    || show_progress
+                {
+                    let audio = batch::synthesize_audio(&tts, &text, &settings);
+                    progress.finish();
+                    let audio = audio?;
+                    write_wav_file(&save_path, &audio, 24_000, mono, bit_depth)?;
+                    eprintln!("Audio saved to {}", save_path);
+                    if let Some(player) = &player {
+                        player.append(&audio, 24_000);
+                    }
+                    Ok(audio.len() as f32 / 24_000.0)
                 } else {
                     tts.tts(TTSOpts {
                         txt: &text,
@@ -368,15 +1821,373 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         mono,
                         speed,
                         initial_silence,
+                        bit_depth,
                     })?;
+                    Ok(epub::wav_duration_sec(Path::new(&save_path)).unwrap_or_default())
+                };
+                if let Some(report) = settings.report {
+                    let output = Path::new(&save_path);
+                    report.emit(match outcome {
+                        Ok(duration_sec) => {
+                            report::ItemReport::done(&text, output, duration_sec, s.elapsed())
+                                .with_split_parts(max_samples)
+                        }
+                        Err(e) => report::ItemReport::failed(&text, output, e),
+                    });
+                } else {
+                    println!("Time taken: {:?}", s.elapsed());
+                    let words_per_second =
+                        text.split_whitespace().count() as f32 / s.elapsed().as_secs_f32();
+                    println!("Words per second: {:.2}", words_per_second);
+                }
+                if let Some(player) = &player {
+                    player.wait();
                 }
-                println!("Time taken: {:?}", s.elapsed());
-                let words_per_second =
-                    text.split_whitespace().count() as f32 / s.elapsed().as_secs_f32();
-                println!("Words per second: {:.2}", words_per_second);
             }
 
-            Mode::OpenAI { ip, port } => {
+            Mode::Batch {
+                input_path,
+                output_dir,
+                force,
+            } => {
+                let input_path = Path::new(&input_path);
+                let output_dir = Path::new(&output_dir);
+                let jobs = if kokoros::input::rows::is_rows_file(input_path) {
+                    batch::row_jobs(input_path, output_dir, &settings)?
+                } else {
+                    batch::directory_jobs(input_path, output_dir)?
+                };
+                fs::create_dir_all(output_dir)?;
+                let manifest = manifest::Manifest::load(&output_dir.join(manifest::MANIFEST_NAME))?;
+                let jobs = if force {
+                    jobs
+                } else {
+                    batch::skip_up_to_date(jobs, &settings, &manifest)
+                };
+                let jobs = if skip_existing {
+                    batch::skip_existing(jobs, settings.report)
+                } else {
+                    jobs
+                };
+
+                // The already-loaded instance serves as the first worker
+                let mut tts_instances = vec![tts];
+                for i in 1..instances.max(1) {
+                    tracing::info!(
+                        "Initializing TTS instance [{}] ({}/{})",
+                        format!("{:02x}", i),
+                        i + 1,
+                        instances
+                    );
+                    tts_instances.push(
+                        load_tts(
+                            &model_path,
+                            &data_path,
+                            voices_dir.as_deref(),
+                            &session,
+                            &gpus,
+                            model_version,
+                            warmup,
+                        )
+                        .await?
+                        .with_text_options(text_options.clone()),
+                    );
+                }
+
+                let failed = batch::run(&tts_instances, &jobs, &settings, Some(&manifest), quiet);
+                if failed > 0 {
+                    std::process::exit(1);
+                }
+            }
+
+            Mode::Watch { dir, interval } => {
+                watch::run(
+                    &tts,
+                    Path::new(&dir),
+                    std::time::Duration::from_secs_f32(interval.max(0.1)),
+                    &settings,
+                )?;
+            }
+
+            Mode::Epub {
+                input_path,
+                output_dir,
+                force,
+                media_overlays,
+            } => {
+                epub::run(
+                    &tts,
+                    Path::new(&input_path),
+                    Path::new(&output_dir),
+                    &settings,
+                    force,
+                    quiet,
+                    media_overlays.as_deref().map(Path::new),
+                )?;
+            }
+
+            Mode::Audiobook {
+                input_path,
+                save_path,
+                title,
+                author,
+                chapters,
+                force,
+            } => {
+                let chapters = chapters
+                    .map(|path| audiobook::read_chapter_list(Path::new(&path)))
+                    .transpose()?;
+                let settings = batch::SynthSettings {
+                    loudness: loudness.or(Some(audiobook::DEFAULT_LOUDNESS)),
+                    ..settings
+                };
+                audiobook::run(
+                    &tts,
+                    Path::new(&input_path),
+                    Path::new(&save_path),
+                    &settings,
+                    &audiobook::Metadata {
+                        title,
+                        author,
+                        chapters,
+                    },
+                    force,
+                    quiet,
+                )?;
+            }
+
+            Mode::Subtitles {
+                input_path,
+                save_path,
+                fit,
+                max_speed,
+            } => {
+                let overruns = subtitles::run(
+                    &tts,
+                    Path::new(&input_path),
+                    Path::new(&save_path),
+                    &settings,
+                    &subtitles::FitOptions {
+                        auto_speed: fit,
+                        max_speed,
+                    },
+                )?;
+                if overruns > 0 {
+                    eprintln!("{} cue(s) exceeded their duration", overruns);
+                }
+            }
+
+            Mode::Dialogue {
+                input_path,
+                save_path,
+                speakers,
+                gap,
+            } => {
+                dialogue::run(
+                    &tts,
+                    Path::new(&input_path),
+                    Path::new(&save_path),
+                    &speakers,
+                    gap,
+                    &settings,
+                )?;
+            }
+
+            Mode::Audition { text, output_dir } => {
+                audition::run(
+                    &tts,
+                    &text,
+                    Path::new(&output_dir),
+                    &settings,
+                    player.as_ref(),
+                )?;
+            }
+
+            Mode::Bench {
+                configs,
+                iterations,
+                intra_threads,
+                inter_threads,
+                providers,
+            } => {
+                let configs: Vec<usize> = configs.into_iter().map(|n| n.max(1)).collect();
+                let max_instances = configs.iter().copied().max().unwrap_or(1);
+                let mut bench = bench::Bench::new(&tts, iterations.max(1), &settings)?;
+                for options in bench::sweep(&session, &providers, &intra_threads, &inter_threads) {
+                    // The model loaded at startup serves the configuration it was loaded with
+                    let mut tts_instances = Vec::with_capacity(max_instances);
+                    if options == session {
+                        tts_instances.push(tts.clone());
+                        bench.loaded();
+                    }
+                    while tts_instances.len() < max_instances {
+                        tracing::info!(
+                            "Initializing TTS instance [{}]",
+                            format!("{:02x}", tts_instances.len())
+                        );
+                        tts_instances.push(
+                            load_tts(
+                                &model_path,
+                                &data_path,
+                                voices_dir.as_deref(),
+                                &options,
+                                &gpus,
+                                model_version,
+                                warmup,
+                            )
+                            .await?,
+                        );
+                        bench.loaded();
+                    }
+                    bench.run(&tts_instances, &configs, &options);
+                }
+                bench.finish();
+            }
+
+            Mode::Say { text, clipboard } => {
+                let text = say::input_text(text, clipboard)?;
+                let player = player.as_ref().expect("say mode always creates a player");
+                say::run(&tts, &text, &settings, player)?;
+            }
+
+            #[cfg(feature = "chat")]
+            Mode::Chat {
+                prompt,
+                endpoint,
+                model,
+                api_key,
+                system,
+                save_path,
+            } => {
+                let prompt = say::input_text(prompt, false)?;
+                let request = chat::request(&endpoint, &model, api_key, system.as_deref(), &prompt);
+                chat::run(
+                    &tts,
+                    request,
+                    &settings,
+                    player.as_ref(),
+                    save_path.as_deref(),
+                )
+                .await?;
+            }
+
+            #[cfg(feature = "whisper")]
+            Mode::Qa {
+                input_path,
+                whisper_model,
+                max_wer,
+                report_path,
+            } => {
+                let transcriber = qa::Transcriber::new(Path::new(&whisper_model))?;
+                qa::run(
+                    &tts,
+                    &transcriber,
+                    Path::new(&input_path),
+                    report_path.as_deref().map(Path::new),
+                    &settings,
+                    max_wer,
+                    quiet,
+                )?;
+            }
+
+            Mode::Speechd { .. } => {
+                let player = player
+                    .as_ref()
+                    .expect("speechd mode always creates a player");
+                speechd::run(&tts, &settings, player)?;
+            }
+
+            Mode::Repl { save_path_format } => {
+                repl::run(&tts, &save_path_format, &settings, player.as_ref())?;
+            }
+
+            Mode::Phonemize { .. }
+            | Mode::Verify { .. }
+            | Mode::DetectWatermark { .. }
+            | Mode::Download { .. }
+            | Mode::Voices { .. } => {
+                unreachable!("handled before the model is loaded")
+            }
+
+            #[cfg(feature = "mqtt")]
+            Mode::Mqtt {
+                host,
+                port,
+                topic,
+                audio_topic,
+                username,
+                password,
+            } => {
+                let config = mqtt::MqttConfig {
+                    host,
+                    port,
+                    topic,
+                    audio_topic,
+                    username,
+                    password,
+                };
+                mqtt::run(&tts, &config, &settings, player.as_ref())?;
+            }
+
+            Mode::Wyoming { ip, port } => {
+                wyoming::run(&tts, std::net::SocketAddr::from((ip, port)), &settings)?;
+            }
+
+            Mode::Icecast {
+                host,
+                port,
+                mount,
+                user,
+                password,
+                name,
+                description,
+                genre,
+                bitrate,
+            } => {
+                let config = icecast::IcecastConfig {
+                    host,
+                    port,
+                    mount,
+                    user,
+                    password,
+                    name,
+                    description,
+                    genre,
+                    bitrate,
+                };
+                icecast::run(&tts, &config, &settings)?;
+            }
+
+            Mode::Rtp {
+                destination,
+                text,
+                codec,
+                ptime,
+                bitrate,
+            } => {
+                let codec = match codec {
+                    RtpCodec::Opus { .. } => RtpCodec::Opus { bitrate },
+                    codec => codec,
+                };
+                let sink = RtpSink::new(destination, codec, 24_000, ptime)?;
+                tracing::info!("Sending RTP from {} to {}", sink.local_addr()?, destination);
+                rtp::run(&tts, text, &settings, sink)?;
+            }
+
+            #[cfg(feature = "server")]
+            Mode::OpenAI {
+                ip,
+                port,
+                api_key,
+                cache_size,
+                cache_dir,
+                cache_ttl,
+                split_long_inputs,
+                job_store,
+                job_retention,
+                usage_file,
+                metrics_headers,
+            } => {
                 // Create multiple independent TTS instances for parallel processing
                 let mut tts_instances = Vec::new();
                 for i in 0..instances {
@@ -386,17 +2197,106 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         i + 1,
                         instances
                     );
-                    let instance = TTSKoko::new(&model_path, &data_path).await;
+                    let instance = load_tts(
+                        &model_path,
+                        &data_path,
+                        voices_dir.as_deref(),
+                        &session,
+                        &gpus,
+                        model_version,
+                        warmup,
+                    )
+                    .await?
+                    .with_text_options(text_options.clone());
                     tts_instances.push(instance);
                 }
-                let app = kokoros_openai::create_server(tts_instances).await;
-                let addr = SocketAddr::from((ip, port));
-                let binding = tokio::net::TcpListener::bind(&addr).await?;
-                tracing::info!("Starting OpenAI-compatible HTTP server on {}", addr);
-                kokoros_openai::serve(binding, app.into_make_service()).await?;
+                const DEFAULT_CACHE_SIZE: u64 = 256 * 1024 * 1024;
+                let cache_ttl = cache_ttl.map(std::time::Duration::from_secs_f32);
+                let cache = match (cache_size, &cache_dir) {
+                    (size, Some(dir)) => Some(kokoros::utils::cache::AudioCache::persistent(
+                        dir,
+                        size.unwrap_or(DEFAULT_CACHE_SIZE),
+                        cache_ttl,
+                    )?),
+                    (Some(size), None) => {
+                        Some(kokoros::utils::cache::AudioCache::new(size, cache_ttl))
+                    }
+                    (None, None) => None,
+                };
+                let voice_registry = voices::registry(
+                    &data_path,
+                    voices_dir.as_deref(),
+                    &tts.get_available_voices(),
+                )?;
+                let tenants = reload::tenants(&api_keys, &tts_instances)?;
+                let quotas = if api_keys.is_empty() {
+                    None
+                } else {
+                    let keys = reload::api_keys(&api_keys);
+                    let quotas = kokoros_openai::Quotas::new(keys, usage_file)?;
+                    Some(std::sync::Arc::new(quotas))
+                };
+                let job_retention = Some(std::time::Duration::from_secs_f32(job_retention));
+                let jobs = match job_store {
+                    #[cfg(feature = "sqlite")]
+                    Some(path) => kokoros_openai::Jobs::open(&path, job_retention)?,
+                    #[cfg(not(feature = "sqlite"))]
+                    Some(_) => return Err("--job-store requires the `sqlite` feature".into()),
+                    None => kokoros_openai::Jobs::new(job_retention),
+                };
+                // Part of the cache keys, so cached audio isn't served for another model
+                let model_version = match &cache {
+                    Some(_) => kokoros::utils::checksum::model_version(Path::new(&model_path))?,
+                    None => String::new(),
+                };
+                let options = kokoros_openai::ServerOptions {
+                    api_key,
+                    // Uploaded voices go where they are loaded from on the next start
+                    voices_dir: Some(
+                        voices_dir
+                            .clone()
+                            .unwrap_or_else(kokoros::utils::fileio::voices_dir),
+                    ),
+                    cache: cache.map(std::sync::Arc::new),
+                    max_input_chars,
+                    split_long_inputs,
+                    voice_registry: Some(std::sync::Arc::new(voice_registry)),
+                    jobs: Some(std::sync::Arc::new(jobs)),
+                    quotas: quotas.clone(),
+                    tenants: kokoros_openai::Swappable::new(tenants),
+                    voice_aliases: kokoros_openai::Swappable::new(voice_aliases),
+                    model_version,
+                    metrics_headers,
+                };
+                // Swapped in place when the config is reloaded
+                let _ = server_reload.set(reload::ServerReload {
+                    quotas: options.quotas.clone(),
+                    tenants: options.tenants.clone(),
+                    voice_aliases: options.voice_aliases.clone(),
+                    engines: tts_instances.clone(),
+                });
+                let app = kokoros_openai::create_server_with_options(tts_instances, options).await;
+                let listener = systemd::listener(std::net::SocketAddr::from((ip, port)))?;
+                listener.set_nonblocking(true)?;
+                let binding = tokio::net::TcpListener::from_std(listener)?;
+                tracing::info!(
+                    "Starting OpenAI-compatible HTTP server on {}",
+                    binding.local_addr()?
+                );
+                systemd::notify_ready();
+                kokoros_openai::serve(binding, app.into_make_service())
+                    .with_graceful_shutdown(systemd::shutdown_signal())
+                    .await?;
+                // Requests since the last background save would be lost otherwise
+                if let Some(quotas) = quotas {
+                    quotas.flush();
+                }
             }
 
-            Mode::Stream => {
+            Mode::Stream {
+                realtime,
+                prebuffer,
+            } => {
                 let stdin = tokio::io::stdin();
                 let reader = BufReader::new(stdin);
                 let mut lines = reader.lines();
@@ -412,16 +2312,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let header = WavHeader::new(1, 24000, 32);
                 header.write_header(&mut stdout)?;
                 stdout.flush()?;
+                let mut pacer = realtime.then(|| pacing::Pacer::new(24_000, prebuffer));
 
                 while let Some(line) = lines.next_line().await? {
                     let stripped_line = line.trim();
                     if stripped_line.is_empty() {
                         continue;
                     }
+                    if let Some(e) = input_too_long("the line", stripped_line, max_input_chars) {
+                        eprintln!("Error processing line: {}", e);
+                        continue;
+                    }
 
-                    // Process the line and get audio data
-                    match tts.tts_raw_audio(
-                        &stripped_line,
+                    // Each chunk is written out as soon as it is synthesized
+                    let result = tts.tts_raw_audio_streaming(
+                        stripped_line,
                         &lan,
                         &style,
                         speed,
@@ -429,16 +2334,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         None,
                         None,
                         None,
-                    ) {
-                        Ok(raw_audio) => {
-                            // Write the raw audio samples directly
-                            write_audio_chunk(&mut stdout, &raw_audio)?;
-                            stdout.flush()?;
-                            eprintln!("Audio written to stdout. Ready for another line of text.");
+                        |raw_audio| {
+                            match &mut pacer {
+                                Some(pacer) => pacer.write(&raw_audio, |frame| {
+                                    write_audio_chunk(&mut stdout, frame)?;
+                                    stdout.flush()
+                                })?,
+                                None => {
+                                    write_audio_chunk(&mut stdout, &raw_audio)?;
+                                    stdout.flush()?;
+                                }
+                            }
+                            if let Some(player) = &player {
+                                player.append(&raw_audio, 24_000);
+                            }
+                            Ok(())
+                        },
+                    );
+                    match result {
+                        Ok(()) => {
+                            eprintln!("Audio written to stdout. Ready for another line of text.")
                         }
                         Err(e) => eprintln!("Error processing line: {}", e),
                     }
                 }
+                // Input ended, but the last line may still be playing
+                if let Some(player) = &player {
+                    player.wait();
+                }
             }
         }
 
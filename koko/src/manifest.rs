@@ -0,0 +1,287 @@
+//! Manifests of batch and audiobook runs: the status of every segment and a hash of what
+//! it wrote, saved as each one starts and ends, so running the same command again after a
+//! crash or Ctrl-C picks up where the run stopped instead of starting over. A segment is
+//! only skipped if it finished, with the same text and settings, and its output is still
+//! what was written; one that was being written when the run stopped is synthesized again.
+//!
+//! The manifest is a JSON Lines file that every start and end is appended to, so saving
+//! costs the same however many segments a run has. The last record of an output wins, and
+//! loading rewrites the file with one line per output.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use kokoros::utils::checksum::sha256_file;
+use serde::{Deserialize, Serialize};
+
+use crate::split;
+
+/// File name of the manifest in a batch's output directory or an audiobook's chapter one
+pub const MANIFEST_NAME: &str = "koko-manifest.jsonl";
+
+/// How far a segment got
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SegmentStatus {
+    /// Being synthesized, or the run stopped while it was
+    Started,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Segment {
+    status: SegmentStatus,
+    /// Digest of what its audio depends on, see [`CacheKey`](kokoros::utils::cache::CacheKey)
+    key: String,
+    /// SHA-256 of its output, or of each of its parts joined with `+`, once written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_hash: Option<String>,
+}
+
+/// One line of the manifest
+#[derive(Serialize, Deserialize)]
+struct Record {
+    /// Relative to the manifest's directory
+    output: String,
+    #[serde(flatten)]
+    segment: Segment,
+}
+
+struct State {
+    segments: BTreeMap<String, Segment>,
+    /// The manifest, open for appending once the first record is saved
+    file: Option<File>,
+}
+
+/// The segments of a run by output, relative to the manifest's directory
+pub struct Manifest {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+/// The segments recorded in the manifest `json_lines`, and how many lines it has. A last
+/// line that isn't valid is taken to have been cut short by a crash and left out.
+fn parse(json_lines: &str) -> Result<(BTreeMap<String, Segment>, usize), String> {
+    let mut segments = BTreeMap::new();
+    let lines: Vec<&str> = json_lines
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .collect();
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str::<Record>(line) {
+            Ok(record) => {
+                segments.insert(record.output, record.segment);
+            }
+            Err(_) if i + 1 == lines.len() => {
+                tracing::warn!("Ignoring the incomplete last line of the manifest");
+            }
+            Err(e) => return Err(format!("line {}: {}", i + 1, e)),
+        }
+    }
+    Ok((segments, lines.len()))
+}
+
+/// Replaces the manifest at `path` with one line per segment, written aside and renamed
+/// so a crash can't leave half of it
+fn compact(path: &Path, segments: &BTreeMap<String, Segment>) -> std::io::Result<()> {
+    let mut json_lines = Vec::new();
+    for (output, segment) in segments {
+        let record = Record {
+            output: output.clone(),
+            segment: segment.clone(),
+        };
+        serde_json::to_writer(&mut json_lines, &record)?;
+        json_lines.push(b'\n');
+    }
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, json_lines)?;
+    fs::rename(&temp, path)
+}
+
+/// Hash of the file written for `output`, or of its parts if it was split
+fn output_hash(output: &Path) -> Option<String> {
+    if output.exists() {
+        return sha256_file(output).ok();
+    }
+    let parts = split::existing_parts(output);
+    if parts.is_empty() {
+        return None;
+    }
+    let hashes: Vec<String> = parts
+        .iter()
+        .map(|part| sha256_file(part))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    Some(hashes.join("+"))
+}
+
+impl Manifest {
+    /// The manifest at `path`, empty if it doesn't exist yet, compacted to a line per
+    /// output if it has more
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let segments = if path.exists() {
+            let json_lines = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+            let (segments, lines) = parse(&json_lines)
+                .map_err(|e| format!("invalid manifest {}: {}", path.display(), e))?;
+            if lines != segments.len() || !json_lines.ends_with('\n') {
+                compact(path, &segments)
+                    .map_err(|e| format!("failed to rewrite {}: {}", path.display(), e))?;
+            }
+            segments
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            state: Mutex::new(State {
+                segments,
+                file: None,
+            }),
+        })
+    }
+
+    fn name(&self, output: &Path) -> String {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        output
+            .strip_prefix(dir)
+            .unwrap_or(output)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Whether `output` was completed with `key` and is unchanged since, or `None` if the
+    /// manifest doesn't know it, e.g. for outputs of runs before there were manifests
+    pub fn completed(&self, output: &Path, key: &str) -> Option<bool> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let segment = state.segments.get(&self.name(output))?;
+        Some(
+            segment.status == SegmentStatus::Done
+                && segment.key == key
+                && segment.output_hash.is_some()
+                && segment.output_hash == output_hash(output),
+        )
+    }
+
+    /// Records that `output` is being synthesized with `key`
+    pub fn start(&self, output: &Path, key: &str) {
+        self.record(output, key, SegmentStatus::Started);
+    }
+
+    /// Records how synthesizing `output` with `key` ended, hashing what was written
+    pub fn finish(&self, output: &Path, key: &str, done: bool) {
+        let status = if done {
+            SegmentStatus::Done
+        } else {
+            SegmentStatus::Failed
+        };
+        self.record(output, key, status);
+    }
+
+    fn record(&self, output: &Path, key: &str, status: SegmentStatus) {
+        let output_hash = match status {
+            SegmentStatus::Done => output_hash(output),
+            _ => None,
+        };
+        let record = Record {
+            output: self.name(output),
+            segment: Segment {
+                status,
+                key: key.to_string(),
+                output_hash,
+            },
+        };
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        // One write per line, so a crash can at most cut the last one short
+        let saved = serde_json::to_vec(&record)
+            .map_err(std::io::Error::other)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                if state.file.is_none() {
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&self.path)?;
+                    state.file = Some(file);
+                }
+                let file = state.file.as_mut().expect("opened above");
+                file.write_all(&line)?;
+                file.flush()
+            });
+        state.segments.insert(record.output, record.segment);
+        if let Err(e) = saved {
+            tracing::warn!("Could not save the manifest {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let dir = std::env::temp_dir().join(format!("koko-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(MANIFEST_NAME);
+        let output = dir.join("001.wav");
+
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.completed(&output, "k1"), None);
+        manifest.start(&output, "k1");
+        fs::write(&output, b"audio").unwrap();
+        // Interrupted while writing
+        assert_eq!(
+            Manifest::load(&path).unwrap().completed(&output, "k1"),
+            Some(false)
+        );
+        manifest.finish(&output, "k1", true);
+
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.completed(&output, "k1"), Some(true));
+        assert_eq!(manifest.completed(&output, "k2"), Some(false));
+        fs::write(&output, b"other audio").unwrap();
+        assert_eq!(manifest.completed(&output, "k1"), Some(false));
+        // Compacted to the finished record when it was loaded
+        let json_lines = fs::read_to_string(&path).unwrap();
+        assert_eq!(json_lines.lines().count(), 1);
+        assert!(json_lines.contains("\"output\":\"001.wav\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_manifest() {
+        let dir = std::env::temp_dir().join(format!("koko-manifest-cut-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(MANIFEST_NAME);
+        let output = dir.join("001.wav");
+        fs::write(&output, b"audio").unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        manifest.start(&output, "k1");
+        manifest.finish(&output, "k1", true);
+        manifest.start(&dir.join("002.wav"), "k2");
+        drop(manifest);
+
+        // A crash cut the last record short
+        let mut json_lines = fs::read_to_string(&path).unwrap();
+        json_lines.truncate(json_lines.len() - 10);
+        fs::write(&path, &json_lines).unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.completed(&output, "k1"), Some(true));
+        assert_eq!(manifest.completed(&dir.join("002.wav"), "k2"), None);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        // Records after it are appended to a whole line
+        manifest.start(&dir.join("002.wav"), "k2");
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.completed(&dir.join("002.wav"), "k2"), Some(false));
+
+        fs::write(&path, "{}\n{\"output\":\"001.wav\"}\n").unwrap();
+        assert!(Manifest::load(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
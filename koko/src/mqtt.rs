@@ -0,0 +1,139 @@
+//! MQTT client mode, for intercom setups built around a broker: text published to a topic
+//! is synthesized and published back as WAV and/or played locally (`mqtt` feature)
+
+use std::time::Duration;
+
+use kokoros::tts::koko::TTSKoko;
+use kokoros::utils::wav::{WavHeader, write_audio_chunk};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+
+use crate::batch::SynthSettings;
+use crate::playback::Player;
+
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub topic: String,
+    pub audio_topic: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// A JSON message; plain text payloads are spoken with the default settings
+#[derive(Deserialize)]
+struct Request {
+    text: String,
+    voice: Option<String>,
+    speed: Option<f32>,
+}
+
+fn parse_request(payload: &[u8]) -> Request {
+    let payload = String::from_utf8_lossy(payload);
+    serde_json::from_str(&payload).unwrap_or_else(|_| Request {
+        text: payload.into_owned(),
+        voice: None,
+        speed: None,
+    })
+}
+
+fn to_wav(audio: &[f32]) -> std::io::Result<Vec<u8>> {
+    let mut wav = Vec::new();
+    WavHeader::new(1, 24_000, 32).write_header(&mut wav)?;
+    write_audio_chunk(&mut wav, audio)?;
+    Ok(wav)
+}
+
+/// Subscribes to `config.topic` and speaks every message published there until the
+/// process is stopped, reconnecting to the broker as needed
+pub fn run(
+    tts: &TTSKoko,
+    config: &MqttConfig,
+    settings: &SynthSettings,
+    player: Option<&Player>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if config.audio_topic.is_none() && player.is_none() {
+        return Err("nothing to do with the audio, pass --audio-topic and/or --play".into());
+    }
+
+    let mut options = MqttOptions::new(
+        format!("kokoros-{}", std::process::id()),
+        &config.host,
+        config.port,
+    );
+    options.set_keep_alive(Duration::from_secs(30));
+    // Audio is far larger than the 10 KiB rumqttc allows by default
+    options.set_max_packet_size(1024 * 1024, 64 * 1024 * 1024);
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+    }
+
+    let (client, mut connection) = Client::new(options, 10);
+    tracing::info!(
+        "Connecting to MQTT broker at {}:{}",
+        config.host,
+        config.port
+    );
+
+    for notification in connection.iter() {
+        match notification {
+            // Subscribe on every connect, since the broker forgets us on reconnect
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                tracing::info!("Connected, listening on '{}'", config.topic);
+                client.subscribe(&config.topic, QoS::AtLeastOnce)?;
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let request = parse_request(&publish.payload);
+                if request.text.trim().is_empty() {
+                    continue;
+                }
+                let style = request.voice.as_deref().unwrap_or(settings.style);
+                tracing::debug!("Speaking {} bytes with {}", request.text.len(), style);
+                let audio = match tts.tts_raw_audio(
+                    &request.text,
+                    settings.lan,
+                    style,
+                    request.speed.unwrap_or(settings.speed),
+                    settings.initial_silence,
+                    None,
+                    None,
+                    None,
+                ) {
+                    Ok(audio) => audio,
+                    Err(e) => {
+                        tracing::error!("Failed to synthesize MQTT message: {}", e);
+                        continue;
+                    }
+                };
+                if let Some(audio_topic) = &config.audio_topic {
+                    client.publish(audio_topic, QoS::AtLeastOnce, false, to_wav(&audio)?)?;
+                }
+                if let Some(player) = player {
+                    player.append(&audio, 24_000);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!("MQTT connection error: {}, reconnecting", e);
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = parse_request(br#"{"text": "Dinner is ready", "voice": "bf_emma"}"#);
+        assert_eq!(request.text, "Dinner is ready");
+        assert_eq!(request.voice.as_deref(), Some("bf_emma"));
+
+        let request = parse_request(b"Someone is at the door");
+        assert_eq!(request.text, "Someone is at the door");
+        assert!(request.voice.is_none());
+    }
+}
@@ -0,0 +1,77 @@
+//! `stream --realtime`: audio is written out at the rate it plays, rather than as fast as
+//! it is synthesized, for consumers that expect a live source (Icecast, telephony). A
+//! pre-buffer of audio goes out ahead of the clock, to cover the consumer's jitter and
+//! synthesis stalls.
+
+use std::time::{Duration, Instant};
+
+/// Frames the audio is written in, so pacing is smooth even for long chunks
+const FRAME: Duration = Duration::from_millis(20);
+
+pub struct Pacer {
+    sample_rate: u32,
+    prebuffer: Duration,
+    /// When the audio written so far will have finished playing, if it hasn't already
+    played_until: Option<Instant>,
+}
+
+impl Pacer {
+    pub fn new(sample_rate: u32, prebuffer_seconds: f32) -> Self {
+        Self {
+            sample_rate,
+            prebuffer: Duration::from_secs_f32(prebuffer_seconds.max(0.0)),
+            played_until: None,
+        }
+    }
+
+    /// How long to wait at `now` before writing `duration` more audio. Once the consumer
+    /// has run out (between lines, or after a stall), the clock starts over and the
+    /// pre-buffer is sent again at once.
+    fn delay(&mut self, now: Instant, duration: Duration) -> Duration {
+        let played_until = self.played_until.filter(|&t| t > now).unwrap_or(now);
+        let wait = (played_until - now).saturating_sub(self.prebuffer);
+        self.played_until = Some(played_until + duration);
+        wait
+    }
+
+    /// Hands `audio` to `write` in 20 ms frames, each no earlier than real time allows
+    pub fn write(
+        &mut self,
+        audio: &[f32],
+        mut write: impl FnMut(&[f32]) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        let frame = (self.sample_rate as usize * FRAME.as_millis() as usize / 1000).max(1);
+        for samples in audio.chunks(frame) {
+            let duration = Duration::from_secs_f64(samples.len() as f64 / self.sample_rate as f64);
+            let wait = self.delay(Instant::now(), duration);
+            if !wait.is_zero() {
+                std::thread::sleep(wait);
+            }
+            write(samples)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let mut pacer = Pacer::new(24_000, 0.5);
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        // The pre-buffer goes out at once, then one second per second
+        assert_eq!(pacer.delay(start, second), Duration::ZERO);
+        assert_eq!(pacer.delay(start, second), Duration::from_millis(500));
+        assert_eq!(
+            pacer.delay(start + second, second),
+            Duration::from_millis(500)
+        );
+        // After falling behind, the clock starts over
+        let later = start + 10 * second;
+        assert_eq!(pacer.delay(later, second), Duration::ZERO);
+        assert_eq!(pacer.delay(later, second), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,47 @@
+use kokoros::error::KokorosError;
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::normalize::TextOptions;
+use kokoros::tts::vocab::ModelVersion;
+
+/// Prints the text after the rewrites of `options`, and the chunks, phonemes and token
+/// counts synthesis on a model of `version` would use for it, without loading the model
+pub fn run(
+    text: &str,
+    lan: &str,
+    options: &TextOptions,
+    version: ModelVersion,
+    show_tokens: bool,
+) -> Result<(), KokorosError> {
+    let chunks = TTSKoko::phonemize(text, lan, options, version)?;
+
+    let spoken: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+    println!("Normalized: {}", spoken.join(" "));
+
+    let mut total_chars = 0;
+    let mut total_tokens = 0;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let chars = chunk.text.chars().count();
+        total_chars += chars;
+        total_tokens += chunk.tokens.len();
+
+        println!(
+            "Chunk {} ({} chars, {} tokens)",
+            i + 1,
+            chars,
+            chunk.tokens.len()
+        );
+        println!("  text:     {}", chunk.text);
+        println!("  phonemes: {}", chunk.phonemes);
+        if show_tokens {
+            println!("  tokens:   {:?}", chunk.tokens);
+        }
+    }
+    println!(
+        "Total: {} chunks, {} chars, {} tokens ({} vocabulary)",
+        chunks.len(),
+        total_chars,
+        total_tokens,
+        version
+    );
+    Ok(())
+}
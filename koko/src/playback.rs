@@ -0,0 +1,71 @@
+//! Playback of synthesized audio on the default output device (`playback` feature)
+
+#[cfg(feature = "playback")]
+pub struct Player {
+    // The stream must outlive the sink, or playback stops
+    _stream: rodio::OutputStream,
+    sink: rodio::Sink,
+}
+
+#[cfg(feature = "playback")]
+impl Player {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let (stream, handle) = rodio::OutputStream::try_default()?;
+        let sink = rodio::Sink::try_new(&handle)?;
+        Ok(Self {
+            _stream: stream,
+            sink,
+        })
+    }
+
+    /// Queues mono samples for playback and returns immediately
+    pub fn append(&self, samples: &[f32], sample_rate: u32) {
+        self.sink.append(rodio::buffer::SamplesBuffer::new(
+            1,
+            sample_rate,
+            samples.to_vec(),
+        ));
+    }
+
+    /// Blocks until everything queued so far has been played
+    pub fn wait(&self) {
+        self.sink.sleep_until_end();
+    }
+
+    /// Whether everything queued so far has been played
+    pub fn is_done(&self) -> bool {
+        self.sink.empty()
+    }
+
+    /// Drops everything queued; audio appended afterwards plays as usual
+    pub fn stop(&self) {
+        self.sink.stop();
+    }
+
+    /// Playback volume, where 1.0 is unchanged
+    pub fn set_volume(&self, volume: f32) {
+        self.sink.set_volume(volume);
+    }
+}
+
+#[cfg(not(feature = "playback"))]
+pub struct Player;
+
+#[cfg(not(feature = "playback"))]
+impl Player {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        Err("koko was built without playback support, rebuild with `--features playback`".into())
+    }
+
+    pub fn append(&self, _samples: &[f32], _sample_rate: u32) {}
+
+    pub fn wait(&self) {}
+
+    pub fn is_done(&self) -> bool {
+        true
+    }
+
+    pub fn stop(&self) {}
+
+    pub fn set_volume(&self, _volume: f32) {}
+}
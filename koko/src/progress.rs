@@ -0,0 +1,227 @@
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use kokoros::tts::synthesis::SynthProgress;
+
+const BAR_WIDTH: usize = 24;
+
+struct State {
+    done_items: usize,
+    done_chars: usize,
+    /// Whether the last thing written to stderr is an unfinished bar line
+    bar_drawn: bool,
+}
+
+/// Progress bar with elapsed time, throughput and ETA for long runs, drawn on stderr.
+/// Progress is measured in characters of input text, so a long chapter counts for more
+/// than a short one, and moves with every synthesized chunk of an [`Item`], so it also
+/// shows for a single long text. When stderr is not a terminal, one status line is
+/// printed per update.
+pub struct Progress {
+    total_items: usize,
+    total_chars: usize,
+    quiet: bool,
+    tty: bool,
+    start: Instant,
+    state: Mutex<State>,
+}
+
+/// Formats seconds as `mm:ss`, or `h:mm:ss` once past an hour
+pub fn format_duration(secs: f32) -> String {
+    let secs = secs.max(0.0).round() as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+impl Progress {
+    pub fn new(total_items: usize, total_chars: usize, quiet: bool) -> Self {
+        Self {
+            total_items,
+            total_chars,
+            quiet,
+            tty: std::io::stderr().is_terminal(),
+            start: Instant::now(),
+            state: Mutex::new(State {
+                done_items: 0,
+                done_chars: 0,
+                bar_drawn: false,
+            }),
+        }
+    }
+
+    fn clear_bar(&self, state: &mut State) {
+        if state.bar_drawn {
+            eprint!("\r\x1b[2K");
+            state.bar_drawn = false;
+        }
+    }
+
+    fn status_line(&self, state: &State) -> String {
+        let elapsed = self.start.elapsed().as_secs_f32();
+        let fraction = if self.total_chars > 0 {
+            (state.done_chars as f32 / self.total_chars as f32).min(1.0)
+        } else {
+            state.done_items as f32 / self.total_items.max(1) as f32
+        };
+        let chars_per_sec = if elapsed > 0.0 {
+            state.done_chars as f32 / elapsed
+        } else {
+            0.0
+        };
+        let eta = if chars_per_sec > 0.0 {
+            format_duration(
+                self.total_chars.saturating_sub(state.done_chars) as f32 / chars_per_sec,
+            )
+        } else {
+            "--:--".to_string()
+        };
+
+        let filled = (fraction * BAR_WIDTH as f32) as usize;
+        format!(
+            "[{}{}] {:3.0}% {}/{} | {} elapsed | {:.0} chars/s | ETA {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            fraction * 100.0,
+            state.done_items,
+            self.total_items,
+            format_duration(elapsed),
+            chars_per_sec,
+            eta
+        )
+    }
+
+    /// Prints an informational line above the bar; suppressed in quiet mode
+    pub fn message(&self, msg: &str) {
+        if self.quiet {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        self.clear_bar(&mut state);
+        eprintln!("{}", msg);
+    }
+
+    /// Prints an error line above the bar, even in quiet mode
+    pub fn error(&self, msg: &str) {
+        let mut state = self.state.lock().unwrap();
+        self.clear_bar(&mut state);
+        eprintln!("{}", msg);
+    }
+
+    /// An item of `chars` characters about to be synthesized, to report its chunks to
+    pub fn item(&self, chars: usize) -> Item<'_> {
+        Item {
+            progress: self,
+            chars,
+            counted: Mutex::new(Counted::default()),
+        }
+    }
+
+    /// Records `items` finished items and `chars` more characters done, and redraws the bar
+    fn add(&self, items: usize, chars: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.done_items += items;
+        state.done_chars += chars;
+        if self.quiet {
+            return;
+        }
+
+        let line = self.status_line(&state);
+        if self.tty {
+            eprint!("\r\x1b[2K{}", line);
+            let _ = std::io::stderr().flush();
+            state.bar_drawn = true;
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+
+    /// Ends the bar line so later output starts on a fresh line
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.bar_drawn {
+            eprintln!();
+            state.bar_drawn = false;
+        }
+    }
+}
+
+/// Characters of an [`Item`] already added to its [`Progress`]
+#[derive(Default)]
+struct Counted {
+    /// In the pieces of it synthesized in full
+    finished: usize,
+    /// Up to the last chunk reported
+    reported: usize,
+}
+
+/// One item of a [`Progress`] that is being synthesized, moving the bar as its chunks are
+/// done instead of only once all of it is
+pub struct Item<'a> {
+    progress: &'a Progress,
+    chars: usize,
+    counted: Mutex<Counted>,
+}
+
+impl Item<'_> {
+    /// Records how far the synthesis of a piece of the item `chars` characters long has
+    /// got, the whole item if it is synthesized at once
+    pub fn chunk(&self, chars: usize, synth: &SynthProgress) {
+        let mut counted = self.counted.lock().unwrap();
+        let done = counted.finished + (chars as f32 * synth.fraction()) as usize;
+        if synth.chunks_done == synth.total_chunks {
+            counted.finished += chars;
+        }
+        let done = done.min(self.chars);
+        if done > counted.reported {
+            let added = done - std::mem::replace(&mut counted.reported, done);
+            self.progress.add(0, added);
+        }
+    }
+
+    /// Records that the item is done, counting whatever of it its chunks didn't
+    pub fn finish(&self) {
+        let reported = std::mem::replace(&mut self.counted.lock().unwrap().reported, self.chars);
+        self.progress.add(1, self.chars - reported);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0.0), "00:00");
+        assert_eq!(format_duration(75.4), "01:15");
+        assert_eq!(format_duration(3725.0), "1:02:05");
+    }
+
+    #[test]
+    fn test_item_chunks() {
+        let progress = Progress::new(2, 100, true);
+        let done_chars = || progress.state.lock().unwrap().done_chars;
+        let synth = |done, total| SynthProgress {
+            chunks_done: done,
+            total_chunks: total,
+            seconds_synthesized: 0.0,
+            elapsed: Default::default(),
+            eta: None,
+        };
+
+        let item = progress.item(60);
+        // Two pieces of the item, 40 and 20 characters long
+        item.chunk(40, &synth(1, 4));
+        assert_eq!(done_chars(), 10);
+        item.chunk(40, &synth(4, 4));
+        assert_eq!(done_chars(), 40);
+        item.chunk(20, &synth(1, 2));
+        assert_eq!(done_chars(), 50);
+        item.finish();
+        assert_eq!(done_chars(), 60);
+        assert_eq!(progress.state.lock().unwrap().done_items, 1);
+    }
+}
@@ -0,0 +1,161 @@
+//! `koko qa`: synthesizes a text sentence by sentence, transcribes each sentence back
+//! with a local Whisper model (whisper.cpp) and reports its word error rate, so sentences
+//! that came out garbled are caught without listening to a whole bulk job.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use kokoros::tts::chunker;
+use kokoros::tts::koko::TTSKoko;
+use kokoros::utils::{audio, wer};
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+
+use crate::batch::{SynthSettings, synthesize_audio};
+use crate::progress::Progress;
+
+/// Sample rate Whisper models take
+const WHISPER_SAMPLE_RATE: u32 = 16_000;
+
+pub struct Transcriber {
+    context: WhisperContext,
+}
+
+impl Transcriber {
+    /// Loads a ggml Whisper model, such as `ggml-base.en.bin`
+    pub fn new(model_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        // whisper.cpp logs every step of loading and decoding to stderr otherwise
+        whisper_rs::install_logging_hooks();
+        let context = WhisperContext::new_with_params(
+            &model_path.to_string_lossy(),
+            WhisperContextParameters::default(),
+        )
+        .map_err(|e| format!("failed to load {}: {}", model_path.display(), e))?;
+        Ok(Self { context })
+    }
+
+    /// What Whisper hears in `samples` of 24 kHz audio, in `language` (`en-us` is read as
+    /// `en`)
+    pub fn transcribe(
+        &self,
+        samples: &[f32],
+        language: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let samples = audio::resample(samples, 24_000, WHISPER_SAMPLE_RATE);
+        if samples.is_empty() {
+            return Ok(String::new());
+        }
+        let language = language.split(['-', '_']).next().unwrap_or(language);
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_language(Some(language));
+        params.set_no_context(true);
+        params.set_single_segment(true);
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_special(false);
+        params.set_print_timestamps(false);
+
+        let mut state = self.context.create_state()?;
+        state.full(params, &samples)?;
+        let mut text = String::new();
+        for segment in state.as_iter() {
+            text.push_str(&segment.to_str_lossy()?);
+        }
+        Ok(text.trim().to_string())
+    }
+}
+
+/// One sentence of the input and how it came back
+struct Segment {
+    text: String,
+    transcript: String,
+    wer: f32,
+}
+
+/// Checks every sentence of the text file at `input_path`, writing a TSV report to
+/// `report_path` if given. Sentences whose word error rate is above `max_wer` are flagged,
+/// and make the run fail once all are checked.
+pub fn run(
+    tts: &TTSKoko,
+    transcriber: &Transcriber,
+    input_path: &Path,
+    report_path: Option<&Path>,
+    settings: &SynthSettings,
+    max_wer: f32,
+    quiet: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(input_path)?;
+    let sentences: Vec<&str> = text
+        .lines()
+        .flat_map(chunker::sentences)
+        .filter(|s| !wer::words(s).is_empty())
+        .collect();
+
+    let progress = Progress::new(
+        sentences.len(),
+        sentences.iter().map(|s| s.chars().count()).sum(),
+        quiet,
+    );
+    let mut segments = Vec::with_capacity(sentences.len());
+    for (i, sentence) in sentences.iter().enumerate() {
+        let item = progress.item(sentence.chars().count());
+        let samples = synthesize_audio(
+            tts,
+            sentence,
+            &SynthSettings {
+                progress: Some(&item),
+                ..*settings
+            },
+        )?;
+        let transcript = transcriber.transcribe(&samples, settings.lan)?;
+        let wer = wer::word_error_rate(sentence, &transcript);
+        if wer > max_wer {
+            progress.error(&format!(
+                "[{}/{}] WER {:.2}: {:?} was heard as {:?}",
+                i + 1,
+                sentences.len(),
+                wer,
+                sentence,
+                transcript
+            ));
+        }
+        item.finish();
+        segments.push(Segment {
+            text: sentence.to_string(),
+            transcript,
+            wer,
+        });
+    }
+    progress.finish();
+
+    if let Some(report_path) = report_path {
+        let mut report = String::from("segment\twer\tflagged\ttext\ttranscript\n");
+        for (i, segment) in segments.iter().enumerate() {
+            writeln!(
+                report,
+                "{}\t{:.3}\t{}\t{}\t{}",
+                i + 1,
+                segment.wer,
+                segment.wer > max_wer,
+                segment.text.replace(['\t', '\n'], " "),
+                segment.transcript.replace(['\t', '\n'], " ")
+            )?;
+        }
+        fs::write(report_path, report)?;
+        eprintln!("Report saved to {}", report_path.display());
+    }
+
+    let flagged = segments.iter().filter(|s| s.wer > max_wer).count();
+    let average = segments.iter().map(|s| s.wer).sum::<f32>() / segments.len().max(1) as f32;
+    eprintln!(
+        "{} sentences checked, average WER {:.3}, {} above {:.2}",
+        segments.len(),
+        average,
+        flagged,
+        max_wer
+    );
+    if flagged > 0 {
+        return Err(format!("{} of {} sentences look garbled", flagged, segments.len()).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! Reloading the config file on SIGHUP, for the modes that keep running. Only what can
+//! change under requests already being served is applied: the `log_level` and the
+//! `[[replacements]]` rules, and for the server its keys, their quotas and tenant settings,
+//! and the voice aliases. The rest of the file takes effect on the next start, and a file
+//! that no longer loads leaves everything as it was.
+
+#[cfg(feature = "server")]
+use std::collections::HashMap;
+#[cfg(feature = "server")]
+use std::sync::{Arc, OnceLock};
+
+use kokoros::tts::normalize::Replacements;
+use tracing_subscriber::{EnvFilter, Registry, reload};
+
+#[cfg(feature = "server")]
+use crate::config::KeyConfig;
+use crate::config::{self, Config};
+#[cfg(feature = "server")]
+use kokoros::tts::koko::TTSKoko;
+#[cfg(feature = "server")]
+use kokoros_openai::{ApiKey, Quotas, Swappable, Tenant};
+
+/// Swaps the filter of the running logger
+pub type LogFilter = reload::Handle<EnvFilter, Registry>;
+
+/// The settings a reload changes
+pub struct Reloadable {
+    pub config_path: Option<String>,
+    /// `None` when the level is fixed by `-v` or `RUST_LOG`, which win over the config
+    pub log_filter: Option<LogFilter>,
+    /// The level without a `log_level` in the config
+    pub default_log_level: &'static str,
+    pub replacements: Replacements,
+    /// Set once the server is up, in the `openai` mode
+    #[cfg(feature = "server")]
+    pub server: Arc<OnceLock<ServerReload>>,
+}
+
+/// The settings of a running server a reload changes
+#[cfg(feature = "server")]
+pub struct ServerReload {
+    /// `None` when the server started without keys; turning keys on or off takes a restart
+    pub quotas: Option<Arc<Quotas>>,
+    pub tenants: Swappable<HashMap<String, Tenant>>,
+    pub voice_aliases: Swappable<HashMap<String, String>>,
+    /// The engines tenants are cloned from
+    pub engines: Vec<TTSKoko>,
+}
+
+/// The tenants among `keys`: keys with voices, a lexicon or a voice pack of their own,
+/// served by clones of `engines` with those loaded
+#[cfg(feature = "server")]
+pub fn tenants(
+    keys: &HashMap<String, KeyConfig>,
+    engines: &[TTSKoko],
+) -> Result<HashMap<String, Tenant>, Box<dyn std::error::Error>> {
+    let mut tenants = HashMap::new();
+    for (name, key) in keys {
+        if key.voices.is_empty() && key.lexicon.is_empty() && key.voices_dir.is_none() {
+            continue;
+        }
+        let tenant = Tenant::new(
+            key.voices.clone(),
+            key.lexicon()?,
+            key.voices_dir.as_deref(),
+            engines,
+        )?;
+        tenants.insert(name.clone(), tenant);
+    }
+    Ok(tenants)
+}
+
+/// The bearer tokens and quotas of `keys`
+#[cfg(feature = "server")]
+pub fn api_keys(keys: &HashMap<String, KeyConfig>) -> HashMap<String, ApiKey> {
+    keys.iter()
+        .map(|(name, key)| {
+            let key = ApiKey {
+                key: key.key.clone(),
+                daily_chars: key.daily_chars,
+                monthly_chars: key.monthly_chars,
+            };
+            (name.clone(), key)
+        })
+        .collect()
+}
+
+impl Reloadable {
+    /// Uses the log level of `config`, or the default if it has none
+    pub fn set_log_level(&self, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(log_filter) = &self.log_filter {
+            let level = config
+                .log_level
+                .as_deref()
+                .unwrap_or(self.default_log_level);
+            let filter = EnvFilter::try_new(level)
+                .map_err(|e| format!("invalid log_level {:?}: {}", level, e))?;
+            log_filter.reload(filter)?;
+        }
+        Ok(())
+    }
+
+    fn reload(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let config = config::load(self.config_path.as_deref())?;
+        // Everything is checked before anything changes
+        let replacements = config.replacements()?;
+        #[cfg(feature = "server")]
+        let tenants = match self.server.get() {
+            Some(server) => Some(tenants(&config.server.keys, &server.engines)?),
+            None => None,
+        };
+        self.set_log_level(&config)?;
+        tracing::info!(
+            "Reloaded the config: {} replacement rules",
+            replacements.len()
+        );
+        self.replacements.set(replacements);
+
+        #[cfg(feature = "server")]
+        if let (Some(server), Some(tenants)) = (self.server.get(), tenants) {
+            let keys = &config.server.keys;
+            match (&server.quotas, keys.is_empty()) {
+                (Some(quotas), false) => {
+                    quotas.set_keys(api_keys(keys));
+                    server.tenants.set(tenants);
+                }
+                (None, true) => {}
+                _ => tracing::warn!(
+                    "Turning API keys on or off takes a restart, keeping the current keys"
+                ),
+            }
+            tracing::info!(
+                "Reloaded the server: {} keys, {} voice aliases",
+                keys.len(),
+                config.server.voice_aliases.len()
+            );
+            server.voice_aliases.set(config.server.voice_aliases);
+        }
+        Ok(())
+    }
+}
+
+/// Reloads the config whenever the process gets a SIGHUP, until it exits
+#[cfg(unix)]
+pub fn watch(reloadable: Reloadable) -> std::io::Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            if let Err(e) = reloadable.reload() {
+                tracing::error!("Could not reload the config, keeping the old one: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
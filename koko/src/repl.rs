@@ -0,0 +1,180 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use kokoros::tts::koko::TTSKoko;
+
+use crate::batch::{self, SynthSettings};
+use crate::playback::Player;
+use crate::template::{self, TemplateVars};
+
+const HELP: &str = "Type text and press Enter to synthesize it. Commands:
+  :voice <name>   switch voice (mixes like af_sky.4+af_nicole.6 work too)
+  :speed <value>  switch speed
+  :lan <code>     switch language
+  :voices         list available voices
+  :show           print the current settings
+  :help           print this help
+  :quit           exit (Ctrl+D also works)";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Voice(String),
+    Speed(f32),
+    Lan(String),
+    Voices,
+    Show,
+    Help,
+    Quit,
+}
+
+/// Parses a `:command` line. Returns `Ok(None)` for lines that should be synthesized.
+fn parse_command(line: &str) -> Result<Option<Command>, String> {
+    let Some(rest) = line.strip_prefix(':') else {
+        return Ok(None);
+    };
+    let (name, arg) = match rest.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (rest, ""),
+    };
+    let required = |what: &str| {
+        if arg.is_empty() {
+            Err(format!(":{} needs a {}", name, what))
+        } else {
+            Ok(arg.to_string())
+        }
+    };
+
+    let command = match name {
+        "voice" | "v" => Command::Voice(required("voice name")?),
+        "speed" | "s" => {
+            let value = required("value")?;
+            match value.parse::<f32>() {
+                Ok(speed) if speed > 0.0 => Command::Speed(speed),
+                _ => return Err(format!("invalid speed '{}'", value)),
+            }
+        }
+        "lan" | "l" => Command::Lan(required("language code")?),
+        "voices" => Command::Voices,
+        "show" => Command::Show,
+        "help" | "h" | "?" => Command::Help,
+        "quit" | "q" | "exit" => Command::Quit,
+        _ => return Err(format!("unknown command ':{}', try :help", name)),
+    };
+    Ok(Some(command))
+}
+
+/// Checks every voice in a (possibly mixed) style name against the loaded voices
+fn unknown_voice(name: &str, available: &[String]) -> Option<String> {
+    name.split('+')
+        .map(|part| part.split('.').next().unwrap_or(part))
+        .find(|voice| !available.iter().any(|v| v == voice))
+        .map(String::from)
+}
+
+/// Reads lines from stdin, synthesizing text lines to the `save_path_format` template and
+/// applying `:commands` to the session settings. With a player, each utterance is also
+/// queued for playback as soon as it is ready.
+pub fn run(
+    tts: &TTSKoko,
+    save_path_format: &str,
+    settings: &SynthSettings,
+    player: Option<&Player>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let available = tts.get_available_voices();
+    let mut voice = settings.style.to_string();
+    let mut lan = settings.lan.to_string();
+    let mut speed = settings.speed;
+    let mut count = 0;
+
+    eprintln!("{}", HELP);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        eprint!("koko [{} {} x{}]> ", voice, lan, speed);
+        io::stderr().flush()?;
+        let Some(line) = lines.next() else {
+            eprintln!();
+            break;
+        };
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match parse_command(line) {
+            Ok(Some(Command::Voice(name))) => match unknown_voice(&name, &available) {
+                Some(missing) => eprintln!("Unknown voice '{}', see :voices", missing),
+                None => voice = name,
+            },
+            Ok(Some(Command::Speed(value))) => speed = value,
+            Ok(Some(Command::Lan(code))) => lan = code,
+            Ok(Some(Command::Voices)) => eprintln!("{}", available.join(", ")),
+            Ok(Some(Command::Show)) => {
+                eprintln!("voice: {}, speed: {}, language: {}", voice, speed, lan)
+            }
+            Ok(Some(Command::Help)) => eprintln!("{}", HELP),
+            Ok(Some(Command::Quit)) => break,
+            Ok(None) => {
+                count += 1;
+                let utterance = SynthSettings {
+                    lan: &lan,
+                    speed,
+                    ..settings.for_voice(&voice)
+                };
+                let vars = TemplateVars {
+                    n: count,
+                    line: count,
+                    text: line,
+                    settings: &utterance,
+                };
+                let save_path = match template::render_path(save_path_format, &vars) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        eprintln!("Invalid output path: {}", e);
+                        continue;
+                    }
+                };
+                match batch::synthesize_audio_to_file(tts, line, Path::new(&save_path), &utterance)
+                {
+                    Ok(audio) => {
+                        eprintln!(
+                            "Saved {:.1}s of audio to {}",
+                            audio.len() as f32 / 24_000.0,
+                            save_path
+                        );
+                        if let Some(player) = player {
+                            player.append(&audio, 24_000);
+                        }
+                    }
+                    Err(e) => eprintln!("Error processing line: {}", e),
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    if let Some(player) = player {
+        player.wait();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("Hello there"), Ok(None));
+        assert_eq!(
+            parse_command(":voice af_sky"),
+            Ok(Some(Command::Voice("af_sky".into())))
+        );
+        assert_eq!(parse_command(":s 1.25"), Ok(Some(Command::Speed(1.25))));
+        assert_eq!(parse_command(":q"), Ok(Some(Command::Quit)));
+        assert!(parse_command(":speed fast").is_err());
+        assert!(parse_command(":lan").is_err());
+        assert!(parse_command(":nope").is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! `--json`: a JSON line on stdout for every item a run processes, with where its audio
+//! went, how long it is and how fast it was synthesized, so `koko` can be driven from
+//! shell pipelines and build systems. Messages meant for people stay on stderr.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::split;
+
+/// What became of an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Its output was already there and up to date
+    Skipped,
+    Error,
+}
+
+/// One line of output
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ItemReport {
+    pub status: Status,
+    /// The text, file or script row the item came from
+    pub input: String,
+    pub output: PathBuf,
+    /// Files written, when the output was split into parts
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_sec: Option<f32>,
+    /// Synthesis time over audio duration, below 1 when faster than real time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtf: Option<f32>,
+    pub warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ItemReport {
+    fn new(status: Status, input: impl Into<String>, output: &Path) -> Self {
+        Self {
+            status,
+            input: input.into(),
+            output: output.to_path_buf(),
+            parts: Vec::new(),
+            duration_sec: None,
+            rtf: None,
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// An item synthesized into `duration_sec` seconds of audio in `elapsed`
+    pub fn done(
+        input: impl Into<String>,
+        output: &Path,
+        duration_sec: f32,
+        elapsed: Duration,
+    ) -> Self {
+        Self {
+            duration_sec: Some(duration_sec),
+            rtf: (duration_sec > 0.0).then(|| elapsed.as_secs_f32() / duration_sec),
+            ..Self::new(Status::Ok, input, output)
+        }
+    }
+
+    /// An item whose output was kept as it was, of `duration_sec` seconds if known
+    pub fn skipped(input: impl Into<String>, output: &Path, duration_sec: Option<f32>) -> Self {
+        Self {
+            duration_sec,
+            ..Self::new(Status::Skipped, input, output)
+        }
+    }
+
+    pub fn failed(input: impl Into<String>, output: &Path, error: impl ToString) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::new(Status::Error, input, output)
+        }
+    }
+
+    /// Lists the parts the output was split into, when it was split with `--max-duration`
+    /// or `--max-size`
+    pub fn with_split_parts(mut self, max_samples: Option<usize>) -> Self {
+        if max_samples.is_some() {
+            self.parts = split::existing_parts(&self.output);
+        }
+        self
+    }
+}
+
+/// Writes the lines of a run, adding the warnings that concern all of its items to each
+pub struct Reporter {
+    warnings: Vec<String>,
+}
+
+impl Reporter {
+    pub fn new(warnings: Vec<String>) -> Self {
+        Self { warnings }
+    }
+
+    pub fn emit(&self, mut item: ItemReport) {
+        item.warnings.splice(0..0, self.warnings.iter().cloned());
+        let Ok(line) = serde_json::to_string(&item) else {
+            return;
+        };
+        // One write per line, so lines from batch threads don't interleave; a reader that
+        // went away is no reason to stop synthesizing
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", line).and_then(|_| stdout.flush());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_report() {
+        let item = ItemReport::done("a.txt", Path::new("a.wav"), 4.0, Duration::from_secs(1));
+        assert_eq!(
+            serde_json::to_string(&item).unwrap(),
+            r#"{"status":"ok","input":"a.txt","output":"a.wav","duration_sec":4.0,"rtf":0.25,"warnings":[]}"#
+        );
+        let item = ItemReport::failed("b.txt", Path::new("b.wav"), "no text to synthesize");
+        let json = serde_json::to_value(&item).unwrap();
+        assert_eq!(json["status"], "error");
+        assert_eq!(json["error"], "no text to synthesize");
+        assert!(json.get("rtf").is_none());
+    }
+}
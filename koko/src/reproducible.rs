@@ -0,0 +1,37 @@
+//! `--reproducible`: the same input gives the same audio, sample for sample, on every run,
+//! so CI can diff generated files against golden ones. The model runs with deterministic
+//! kernels on one thread per instance, and every WAV file written records what made it:
+//! the koko version and the SHA-256 of the model and voices files.
+
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use kokoros::utils::checksum::sha256_file;
+use kokoros::utils::wav;
+
+/// The comment written into WAV outputs, set once reproducible mode is on
+static PROVENANCE: OnceLock<String> = OnceLock::new();
+
+const SOFTWARE: &str = concat!("koko ", env!("CARGO_PKG_VERSION"));
+
+/// Hashes the model and voices files, to be recorded in every WAV output from now on
+pub fn record(model: &Path, voices: &Path) -> io::Result<()> {
+    let comment = format!(
+        "model sha256:{} voices sha256:{}",
+        sha256_file(model)?,
+        sha256_file(voices)?
+    );
+    tracing::info!("Reproducible mode: {}", comment);
+    let _ = PROVENANCE.set(comment);
+    Ok(())
+}
+
+/// Adds the recorded provenance to the WAV file at `path`; does nothing outside
+/// reproducible mode
+pub fn tag(path: &Path) -> io::Result<()> {
+    match PROVENANCE.get() {
+        Some(comment) => wav::append_info(path, &[(*b"ISFT", SOFTWARE), (*b"ICMT", comment)]),
+        None => Ok(()),
+    }
+}
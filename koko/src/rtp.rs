@@ -0,0 +1,68 @@
+//! `koko rtp`: speaks text straight into a call leg as RTP, e.g. IVR prompts for Asterisk
+//! or FreeSWITCH. Packets go out at real-time pace from their own thread, so the next
+//! chunk is synthesized while the current one is being sent.
+
+use std::io::BufRead;
+use std::sync::mpsc;
+
+use kokoros::tts::koko::TTSKoko;
+use kokoros::utils::rtp::RtpSink;
+
+use crate::batch::SynthSettings;
+
+/// Speaks `text`, or each line of stdin as it arrives if there is none, into `sink`. The
+/// connection is not torn down between lines; the silence in between is simply not sent.
+pub fn run(
+    tts: &TTSKoko,
+    text: Option<String>,
+    settings: &SynthSettings,
+    mut sink: RtpSink,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // `None` ends a talkspurt
+    let (sender, receiver) = mpsc::sync_channel::<Option<Vec<f32>>>(4);
+    std::thread::scope(|scope| {
+        let sending = scope.spawn(move || -> std::io::Result<()> {
+            for audio in receiver {
+                match audio {
+                    Some(audio) => sink.send(&audio)?,
+                    None => sink.finish()?,
+                }
+            }
+            Ok(())
+        });
+
+        let speak = |text: &str| -> Result<(), Box<dyn std::error::Error>> {
+            tts.tts_raw_audio_streaming(
+                text,
+                settings.lan,
+                settings.style,
+                settings.speed,
+                settings.initial_silence,
+                None,
+                None,
+                None,
+                |audio| {
+                    sender
+                        .send(Some(audio))
+                        .map_err(|_| "the RTP sender stopped".into())
+                },
+            )?;
+            sender.send(None).map_err(|_| "the RTP sender stopped")?;
+            Ok(())
+        };
+        let result = match &text {
+            Some(text) => speak(text),
+            None => std::io::stdin().lock().lines().try_for_each(|line| {
+                let line = line?;
+                match line.trim() {
+                    "" => Ok(()),
+                    line => speak(line),
+                }
+            }),
+        };
+        drop(sender);
+        // A failed send is the more useful error, as it also stops synthesis
+        sending.join().expect("the RTP sender panicked")?;
+        result
+    })
+}
@@ -0,0 +1,101 @@
+use std::io::Read;
+use std::process::Command;
+
+use kokoros::tts::koko::TTSKoko;
+
+use crate::batch::SynthSettings;
+use crate::playback::Player;
+
+/// Clipboard readers to try, in order, for the current platform
+fn clipboard_commands() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbpaste", &[])]
+    } else if cfg!(target_os = "windows") {
+        vec![("powershell", &["-NoProfile", "-Command", "Get-Clipboard"])]
+    } else {
+        let mut commands: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            commands.push(("wl-paste", &["--no-newline"]));
+        }
+        commands.push(("xclip", &["-selection", "clipboard", "-o"]));
+        commands.push(("xsel", &["--clipboard", "--output"]));
+        commands
+    }
+}
+
+/// Reads the text currently on the system clipboard using the platform's clipboard tool
+pub fn read_clipboard() -> Result<String, Box<dyn std::error::Error>> {
+    let commands = clipboard_commands();
+    for (program, args) in &commands {
+        match Command::new(program).args(*args).output() {
+            Ok(output) if output.status.success() => {
+                return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+            }
+            Ok(output) => {
+                tracing::debug!(
+                    "{} failed: {}",
+                    program,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            // Not installed, try the next one
+            Err(_) => {}
+        }
+    }
+
+    let tried: Vec<&str> = commands.iter().map(|(program, _)| *program).collect();
+    Err(format!(
+        "could not read the clipboard, install one of: {}",
+        tried.join(", ")
+    )
+    .into())
+}
+
+/// Text to speak: the clipboard, the given text, or else all of stdin
+pub fn input_text(
+    text: Option<String>,
+    clipboard: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let text = match text {
+        _ if clipboard => read_clipboard()?,
+        Some(text) => text,
+        None => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+    if text.trim().is_empty() {
+        return Err(if clipboard {
+            "the clipboard is empty".into()
+        } else {
+            "nothing to say".into()
+        });
+    }
+    Ok(text)
+}
+
+/// Speaks `text` on the default output device, starting as soon as the first chunk is ready
+pub fn run(
+    tts: &TTSKoko,
+    text: &str,
+    settings: &SynthSettings,
+    player: &Player,
+) -> Result<(), Box<dyn std::error::Error>> {
+    tts.tts_raw_audio_streaming(
+        text,
+        settings.lan,
+        settings.style,
+        settings.speed,
+        settings.initial_silence,
+        None,
+        None,
+        None,
+        |audio| {
+            player.append(&audio, 24_000);
+            Ok(())
+        },
+    )?;
+    player.wait();
+    Ok(())
+}
@@ -0,0 +1,362 @@
+//! Speech Dispatcher output module, so screen readers such as Orca can speak with Kokoros.
+//!
+//! Speech Dispatcher drives the module over stdin/stdout with line-based commands (`INIT`,
+//! `SET`, `SPEAK`, `STOP`, `PAUSE`, ...) and expects asynchronous `7xx` events back as the
+//! speech progresses. Messages arrive as SSML; `<mark/>` tags are reported as index marks
+//! once the audio before them has been played, and `PAUSE` stops at the next mark so
+//! Speech Dispatcher can resume from there.
+
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::time::Duration;
+
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::normalize::spell_out;
+use kokoros::tts::voices::voice_info;
+
+use crate::batch::SynthSettings;
+use crate::playback::Player;
+
+lazy_static::lazy_static! {
+    static ref MARK: regex::Regex = regex::Regex::new(r#"<mark\s+name="([^"]*)"\s*/>"#).unwrap();
+    static ref TAG: regex::Regex = regex::Regex::new(r"<[^>]*>").unwrap();
+    static ref SAY_AS_CHARACTERS: regex::Regex = regex::Regex::new(
+        r#"(?s)<say-as\s[^>]*interpret-as="(?:characters|spell-out|verbatim)"[^>]*>(.*?)</say-as>"#
+    )
+    .unwrap();
+}
+
+/// Voice settings changed with `SET`
+#[derive(Clone)]
+struct Voice {
+    style: String,
+    lan: String,
+    speed: f32,
+    volume: f32,
+}
+
+struct Message {
+    /// Text segments, each followed by the index mark that ends it (if any)
+    segments: Vec<(String, Option<String>)>,
+    voice: Voice,
+}
+
+/// Splits an SSML message into plain-text segments at its `<mark/>` tags
+fn parse_ssml(ssml: &str) -> Vec<(String, Option<String>)> {
+    let mut segments = Vec::new();
+    let mut rest = 0;
+    for mark in MARK.captures_iter(ssml) {
+        let whole = mark.get(0).unwrap();
+        segments.push((
+            plain_text(&ssml[rest..whole.start()]),
+            Some(mark[1].to_string()),
+        ));
+        rest = whole.end();
+    }
+    segments.push((plain_text(&ssml[rest..]), None));
+    segments
+}
+
+/// The text of `ssml`, with `<say-as interpret-as="characters">` content spelled out
+fn plain_text(ssml: &str) -> String {
+    let ssml = SAY_AS_CHARACTERS.replace_all(ssml, |caps: &regex::Captures| {
+        spell_out(&unescape(&TAG.replace_all(&caps[1], "")))
+    });
+    unescape(&TAG.replace_all(&ssml, ""))
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Speech Dispatcher rates go from -100 to 100; map them onto half to double speed
+fn rate_to_speed(rate: f32) -> f32 {
+    2f32.powf(rate.clamp(-100.0, 100.0) / 100.0)
+}
+
+/// Kokoros voice for a Speech Dispatcher voice type such as `FEMALE1` or `MALE2`,
+/// preferring one that speaks `lan`
+fn voice_for_type(voices: &[String], voice_type: &str, lan: &str) -> Option<String> {
+    let gender = if voice_type.contains("FEMALE") {
+        "female"
+    } else {
+        "male"
+    };
+    let matching = |name: &&String| voice_info(name).gender == gender;
+    voices
+        .iter()
+        .filter(matching)
+        .find(|name| voice_info(name).lan == lan)
+        .or_else(|| voices.iter().find(matching))
+        .cloned()
+}
+
+struct Module {
+    voices: Vec<String>,
+    voice: Mutex<Voice>,
+    stop: AtomicBool,
+    pause: AtomicBool,
+    out: Mutex<std::io::Stdout>,
+}
+
+impl Module {
+    fn reply(&self, lines: &[&str]) {
+        let mut out = self.out.lock().unwrap();
+        for line in lines {
+            let _ = writeln!(out, "{}", line);
+        }
+        let _ = out.flush();
+    }
+
+    /// Lines of a data block, up to the terminating `.`
+    fn read_block(&self, input: &mut impl BufRead) -> std::io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line == "." {
+                break;
+            }
+            // A leading dot is escaped by doubling it
+            lines.push(line.strip_prefix('.').unwrap_or(line).to_string());
+        }
+        Ok(lines)
+    }
+
+    fn set(&self, key: &str, value: &str) {
+        let mut voice = self.voice.lock().unwrap();
+        match key {
+            "synthesis_voice" if self.voices.iter().any(|v| v == value) => {
+                voice.style = value.to_string();
+                let lan = voice_info(value).lan;
+                if !lan.is_empty() {
+                    voice.lan = lan.to_string();
+                }
+            }
+            "voice" | "voice_type" => {
+                if let Some(style) = voice_for_type(&self.voices, value, &voice.lan) {
+                    voice.style = style;
+                }
+            }
+            "language" if value != "NULL" => voice.lan = value.to_lowercase(),
+            "rate" => voice.speed = rate_to_speed(value.parse().unwrap_or(0.0)),
+            "volume" => voice.volume = (value.parse::<f32>().unwrap_or(0.0) + 100.0) / 100.0,
+            _ => tracing::trace!("Ignoring speechd setting {}={}", key, value),
+        }
+    }
+
+    /// Handles commands from Speech Dispatcher until `QUIT` or end of input, queueing
+    /// messages to speak on `messages`
+    fn read_commands(
+        &self,
+        input: &mut impl BufRead,
+        messages: mpsc::Sender<Message>,
+    ) -> std::io::Result<()> {
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let command = line.trim();
+            tracing::debug!("speechd command: {}", command);
+            match command {
+                "INIT" => self.reply(&["299-Kokoros: initialized", "299 OK LOADED SUCCESSFULLY"]),
+                "AUDIO" => {
+                    self.reply(&["207 OK RECEIVING AUDIO SETTINGS"]);
+                    // Audio is always played on the default device
+                    self.read_block(input)?;
+                    self.reply(&["203 OK AUDIO INITIALIZED"]);
+                }
+                "LOGLEVEL" => {
+                    self.reply(&["207 OK RECEIVING LOGLEVEL SETTINGS"]);
+                    self.read_block(input)?;
+                    self.reply(&["203 OK LOG LEVEL SET"]);
+                }
+                "SET" => {
+                    self.reply(&["203 OK RECEIVING SETTINGS"]);
+                    for setting in self.read_block(input)? {
+                        if let Some((key, value)) = setting.split_once('=') {
+                            self.set(key, value);
+                        }
+                    }
+                    self.reply(&["203 OK SETTINGS RECEIVED"]);
+                }
+                "LIST VOICES" => {
+                    let mut lines: Vec<String> = self
+                        .voices
+                        .iter()
+                        .map(|name| format!("200-{}\t{}\tnone", name, voice_info(name).lan))
+                        .collect();
+                    lines.push("200 OK VOICE LIST SENT".to_string());
+                    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+                    self.reply(&lines);
+                }
+                "SPEAK" | "CHAR" | "KEY" | "SOUND_ICON" => {
+                    self.reply(&["202 OK RECEIVING MESSAGE"]);
+                    let text = self.read_block(input)?.join("\n");
+                    let segments = match command {
+                        "SPEAK" => parse_ssml(&text),
+                        "KEY" => vec![(text.replace('_', " "), None)],
+                        // Sound icons have no text to speak
+                        "SOUND_ICON" => vec![(String::new(), None)],
+                        _ => vec![(text, None)],
+                    };
+                    // Cleared here rather than by the speaker, so a STOP sent right after
+                    // this message still applies to it
+                    self.stop.store(false, Ordering::SeqCst);
+                    self.pause.store(false, Ordering::SeqCst);
+                    self.reply(&["200 OK SPEAKING"]);
+                    let voice = self.voice.lock().unwrap().clone();
+                    if messages.send(Message { segments, voice }).is_err() {
+                        return Ok(());
+                    }
+                }
+                // Neither gets a reply; the speaker reports 703 STOPPED / 704 PAUSED
+                "STOP" => self.stop.store(true, Ordering::SeqCst),
+                "PAUSE" => self.pause.store(true, Ordering::SeqCst),
+                "QUIT" => {
+                    self.stop.store(true, Ordering::SeqCst);
+                    self.reply(&["210 OK QUIT"]);
+                    return Ok(());
+                }
+                "" => {}
+                _ => self.reply(&["300 ERR UNKNOWN COMMAND"]),
+            }
+        }
+    }
+
+    /// Blocks until the player has played everything, or until STOP; returns false if stopped
+    fn wait_for_playback(&self, player: &Player) -> bool {
+        while !player.is_done() {
+            if self.stop.load(Ordering::SeqCst) {
+                player.stop();
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        !self.stop.load(Ordering::SeqCst)
+    }
+
+    fn speak(
+        &self,
+        tts: &TTSKoko,
+        message: Message,
+        settings: &SynthSettings,
+        player: &Player,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let voice = &message.voice;
+        player.set_volume(voice.volume);
+        self.reply(&["701 BEGIN"]);
+
+        for (text, mark) in &message.segments {
+            if !text.trim().is_empty() {
+                let result = tts.tts_raw_audio_streaming(
+                    text,
+                    &voice.lan,
+                    &voice.style,
+                    voice.speed,
+                    settings.initial_silence,
+                    None,
+                    None,
+                    None,
+                    |audio| {
+                        if self.stop.load(Ordering::SeqCst) {
+                            return Err("stopped".into());
+                        }
+                        player.append(&audio, 24_000);
+                        Ok(())
+                    },
+                );
+                if let Err(e) = result
+                    && !self.stop.load(Ordering::SeqCst)
+                {
+                    self.reply(&["702 END"]);
+                    return Err(e.into());
+                }
+            }
+            if !self.wait_for_playback(player) {
+                self.reply(&["703 STOPPED"]);
+                return Ok(());
+            }
+            if let Some(mark) = mark {
+                self.reply(&[&format!("700-{}", mark), "700 INDEX MARK"]);
+                if self.pause.load(Ordering::SeqCst) {
+                    self.reply(&["704 PAUSED"]);
+                    return Ok(());
+                }
+            }
+        }
+
+        self.reply(&["702 END"]);
+        Ok(())
+    }
+}
+
+/// Runs as a Speech Dispatcher output module on stdin/stdout until `QUIT`
+pub fn run(
+    tts: &TTSKoko,
+    settings: &SynthSettings,
+    player: &Player,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let module = Module {
+        voices: tts.get_available_voices(),
+        voice: Mutex::new(Voice {
+            style: settings.style.to_string(),
+            lan: settings.lan.to_string(),
+            speed: settings.speed,
+            volume: 1.0,
+        }),
+        stop: AtomicBool::new(false),
+        pause: AtomicBool::new(false),
+        out: Mutex::new(std::io::stdout()),
+    };
+
+    // Commands are read on their own thread so STOP and PAUSE are seen mid-message;
+    // the player stays on this one
+    let (sender, messages) = mpsc::channel();
+    std::thread::scope(|scope| {
+        let module = &module;
+        scope.spawn(move || {
+            if let Err(e) = module.read_commands(&mut std::io::stdin().lock(), sender) {
+                tracing::error!("Failed to read from Speech Dispatcher: {}", e);
+            }
+        });
+        for message in messages {
+            if let Err(e) = module.speak(tts, message, settings, player) {
+                tracing::error!("speechd synthesis failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ssml() {
+        let segments = parse_ssml("<speak>Hello <mark name=\"1\"/>world &amp; <b>more</b></speak>");
+        assert_eq!(
+            segments,
+            vec![
+                ("Hello ".to_string(), Some("1".to_string())),
+                ("world & more".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            plain_text(r#"Code <say-as interpret-as="characters">A&amp;1</say-as>."#),
+            "Code ay, and, one."
+        );
+        assert_eq!(rate_to_speed(0.0), 1.0);
+        assert_eq!(rate_to_speed(100.0), 2.0);
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::synthesis::SynthRequest;
+
+use crate::batch::{self, SynthSettings};
+use crate::{derive_tsv_path_from_wav, write_read_along, write_tsv, write_wav_file};
+
+/// Parses durations like `30m`, `1h30m`, `90s`, `45` (seconds) or `1:30:00` into seconds
+pub fn parse_duration(value: &str) -> Result<f32, String> {
+    let value = value.trim();
+    let invalid = || {
+        format!(
+            "invalid duration '{}', expected e.g. 30m, 1h30m or 90s",
+            value
+        )
+    };
+
+    if value.contains(':') {
+        return value
+            .split(':')
+            .try_fold(0.0f32, |acc, part| {
+                part.parse::<f32>().map(|n| acc * 60.0 + n)
+            })
+            .map_err(|_| invalid());
+    }
+
+    let mut total = 0.0f32;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let n: f32 = number.parse().map_err(|_| invalid())?;
+        total += n * match c.to_ascii_lowercase() {
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => return Err(invalid()),
+        };
+        number.clear();
+    }
+    if !number.is_empty() {
+        total += number.parse::<f32>().map_err(|_| invalid())?;
+    }
+    if total <= 0.0 {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+/// Parses sizes like `100MB`, `1.5G`, `500k` or `1048576` (bytes) into bytes
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let invalid = || format!("invalid size '{}', expected e.g. 100MB or 2GB", value);
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let multiplier = match unit.trim().to_ascii_lowercase().trim_end_matches('b') {
+        "" => 1u64,
+        "k" | "ki" => 1 << 10,
+        "m" | "mi" => 1 << 20,
+        "g" | "gi" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    let bytes = (number * multiplier as f64) as u64;
+    if bytes == 0 {
+        return Err(invalid());
+    }
+    Ok(bytes)
+}
+
+/// Largest number of samples per part allowed by a duration and/or file size limit
+pub fn max_samples(
+    max_duration_sec: Option<f32>,
+    max_size_bytes: Option<u64>,
+    mono: bool,
+) -> Option<usize> {
+    let by_duration = max_duration_sec.map(|secs| (secs * 24_000.0) as usize);
+    // 32-bit float samples, duplicated for stereo, after the 44-byte WAV header
+    let bytes_per_frame = if mono { 4 } else { 8 };
+    let by_size = max_size_bytes.map(|bytes| (bytes.saturating_sub(44) / bytes_per_frame) as usize);
+    match (by_duration, by_size) {
+        (Some(a), Some(b)) => Some(a.min(b).max(1)),
+        (a, b) => a.or(b).map(|n| n.max(1)),
+    }
+}
+
+/// `out/book.wav` -> `out/book_003.wav`
+pub fn part_path(save_path: &Path, part: usize) -> PathBuf {
+    let stem = save_path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = save_path.extension().unwrap_or_default().to_string_lossy();
+    save_path.with_file_name(format!("{}_{:03}.{}", stem, part, extension))
+}
+
+/// Part files already written for `save_path`, in order
+pub fn existing_parts(save_path: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|n| part_path(save_path, n))
+        .take_while(|path| path.exists())
+        .collect()
+}
+
+struct Part {
+    audio: Vec<f32>,
+    words: Vec<(String, f32, f32)>,
+}
+
+/// Synthesizes `text` into as many numbered WAV files as needed to keep each below
+/// `max_samples`, cutting only between synthesis chunks, which end at sentence boundaries.
+/// A single chunk longer than the limit gets a part of its own. Returns each part's
+/// path and duration.
+pub fn synthesize_parts(
+    tts: &TTSKoko,
+    text: &str,
+    save_path: &Path,
+    settings: &SynthSettings,
+    max_samples: usize,
+) -> Result<Vec<(PathBuf, f32)>, Box<dyn std::error::Error>> {
+    if let Some(parent) = save_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut parts: Vec<(PathBuf, f32)> = Vec::new();
+    let mut current = Part {
+        audio: Vec::new(),
+        words: Vec::new(),
+    };
+
+    let mut flush = |part: &mut Part| -> Result<(), Box<dyn std::error::Error>> {
+        if part.audio.is_empty() {
+            return Ok(());
+        }
+        batch::post_process(&mut part.audio, &mut part.words, settings);
+        let path = part_path(save_path, parts.len() + 1);
+        let path_str = path.to_string_lossy();
+        write_wav_file(
+            &path_str,
+            &part.audio,
+            24_000,
+            settings.mono,
+            settings.bit_depth,
+        )?;
+        if settings.timestamps {
+            write_tsv(&derive_tsv_path_from_wav(&path_str), &part.words)?;
+        }
+        if settings.read_along {
+            // The words of the part are all there is of its text, with the punctuation
+            // they were spoken with
+            let mut text = String::new();
+            for (word, _, _) in &part.words {
+                if !text.is_empty() && word.starts_with(char::is_alphanumeric) {
+                    text.push(' ');
+                }
+                text.push_str(word);
+            }
+            write_read_along(&path_str, &text, &part.words, &part.audio)?;
+        }
+        parts.push((path.clone(), part.audio.len() as f32 / 24_000.0));
+        part.audio.clear();
+        part.words.clear();
+        Ok(())
+    };
+
+    let mut add_chunk = |audio: Vec<f32>,
+                         words: Vec<(String, f32, f32)>|
+     -> Result<(), Box<dyn std::error::Error>> {
+        if !current.audio.is_empty() && current.audio.len() + audio.len() > max_samples {
+            flush(&mut current)?;
+        }
+        let offset = current.audio.len() as f32 / 24_000.0;
+        current.words.extend(
+            words
+                .into_iter()
+                .map(|(w, s, e)| (w, s + offset, e + offset)),
+        );
+        current.audio.extend_from_slice(&audio);
+        Ok(())
+    };
+
+    if settings.timestamps {
+        tts.tts_timestamped_raw_audio_streaming(
+            text,
+            settings.lan,
+            settings.style,
+            settings.speed,
+            settings.initial_silence,
+            None,
+            None,
+            None,
+            |(audio, words)| {
+                let words = words
+                    .into_iter()
+                    .map(|w| (w.word, w.start_sec, w.end_sec))
+                    .collect();
+                add_chunk(audio, words)
+            },
+        )?;
+    } else {
+        let chars = text.chars().count();
+        let request = SynthRequest {
+            lan: settings.lan.to_string(),
+            speed: settings.speed,
+            initial_silence: settings.initial_silence,
+            ..SynthRequest::new(text, settings.style)
+        };
+        tts.synthesize_streaming(
+            &request,
+            |audio| add_chunk(audio, Vec::new()),
+            |synth| {
+                if let Some(item) = settings.progress {
+                    item.chunk(chars, synth);
+                }
+            },
+        )?;
+    }
+    flush(&mut current)?;
+
+    // Leftovers from an earlier run that needed more parts would otherwise look current
+    for stale in existing_parts(save_path).into_iter().skip(parts.len()) {
+        fs::remove_file(&stale)?;
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limits() {
+        assert_eq!(parse_duration("30m"), Ok(1800.0));
+        assert_eq!(parse_duration("1h30m"), Ok(5400.0));
+        assert_eq!(parse_duration("1:00:30"), Ok(3630.0));
+        assert_eq!(parse_duration("45"), Ok(45.0));
+        assert!(parse_duration("soon").is_err());
+        assert_eq!(parse_size("100MB"), Ok(100 << 20));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert!(parse_size("3 parsecs").is_err());
+        assert_eq!(
+            part_path(Path::new("out/book.wav"), 3),
+            Path::new("out/book_003.wav")
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::Path;
+
+use kokoros::input::subtitles::parse_subtitles;
+use kokoros::tts::koko::TTSKoko;
+
+use crate::batch::SynthSettings;
+use crate::write_wav_file;
+
+const SAMPLE_RATE: usize = 24_000;
+
+/// How audio that is longer than its cue is handled
+pub struct FitOptions {
+    /// Re-synthesize overlong cues faster so they fit their slot
+    pub auto_speed: bool,
+    /// Upper bound for the automatically increased speed
+    pub max_speed: f32,
+}
+
+/// Synthesizes every cue of an SRT/VTT file and writes a single track in which each cue
+/// starts at its subtitle timestamp. Returns the number of cues that overran their slot.
+pub fn run(
+    tts: &TTSKoko,
+    input_path: &Path,
+    save_path: &Path,
+    settings: &SynthSettings,
+    fit: &FitOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let cues = parse_subtitles(&fs::read_to_string(input_path)?)?;
+    if cues.is_empty() {
+        return Err(format!("no cues found in {}", input_path.display()).into());
+    }
+
+    let synth = |text: &str, speed: f32| {
+        tts.tts_raw_audio(
+            text,
+            settings.lan,
+            settings.style,
+            speed,
+            settings.initial_silence,
+            None,
+            None,
+            None,
+        )
+    };
+
+    let mut track: Vec<f32> = Vec::new();
+    let mut overruns = 0;
+
+    for (i, cue) in cues.iter().enumerate() {
+        let slot_sec = cue.duration_sec();
+        let mut audio = synth(&cue.text, settings.speed)?;
+        let mut audio_sec = audio.len() as f32 / SAMPLE_RATE as f32;
+
+        if audio_sec > slot_sec && fit.auto_speed && slot_sec > 0.0 {
+            let fitted_speed = (settings.speed * audio_sec / slot_sec).min(fit.max_speed);
+            if fitted_speed > settings.speed {
+                tracing::debug!(
+                    "Cue {}: re-synthesizing at speed {:.2} to fit {:.2}s slot",
+                    i + 1,
+                    fitted_speed,
+                    slot_sec
+                );
+                audio = synth(&cue.text, fitted_speed)?;
+                audio_sec = audio.len() as f32 / SAMPLE_RATE as f32;
+            }
+        }
+
+        if audio_sec > slot_sec {
+            overruns += 1;
+            eprintln!(
+                "Warning: cue {} ({:.3}s) audio is {:.2}s but the cue lasts {:.2}s",
+                i + 1,
+                cue.start_sec,
+                audio_sec,
+                slot_sec
+            );
+        }
+
+        // Never overlap the previous cue; if it ran long, this one starts late
+        let cue_start = (cue.start_sec * SAMPLE_RATE as f32).round() as usize;
+        let start = cue_start.max(track.len());
+        if start > cue_start {
+            eprintln!(
+                "Warning: cue {} delayed by {:.2}s because the previous cue overran",
+                i + 1,
+                (start - cue_start) as f32 / SAMPLE_RATE as f32
+            );
+        }
+        track.resize(start, 0.0);
+        track.extend_from_slice(&audio);
+    }
+
+    if let Some(parent) = save_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_wav_file(
+        &save_path.to_string_lossy(),
+        &track,
+        SAMPLE_RATE as u32,
+        settings.mono,
+        settings.bit_depth,
+    )?;
+    eprintln!(
+        "Audio saved to {} ({} cues, {:.1}s)",
+        save_path.display(),
+        cues.len(),
+        track.len() as f32 / SAMPLE_RATE as f32
+    );
+
+    Ok(overruns)
+}
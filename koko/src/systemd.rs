@@ -0,0 +1,131 @@
+//! systemd integration for the servers: listening on a socket systemd has bound and passes
+//! in (socket activation), so restarts don't refuse connections, and telling it once the
+//! model is loaded and warmed up, so a `Type=notify` unit only counts as started when it
+//! can answer. Outside systemd both do nothing.
+
+use std::io;
+use std::net::TcpListener;
+
+/// The first socket passed in by systemd (`LISTEN_FDS`), if this process was socket
+/// activated
+#[cfg(unix)]
+pub fn activated_listener() -> io::Result<Option<TcpListener>> {
+    use std::os::fd::FromRawFd;
+
+    /// The first file descriptor systemd passes
+    const LISTEN_FDS_START: i32 = 3;
+
+    let var = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+    };
+    // The variables are inherited by children, which mustn't take the sockets as theirs
+    if var("LISTEN_PID") != Some(std::process::id()) || var("LISTEN_FDS").unwrap_or(0) < 1 {
+        return Ok(None);
+    }
+    // Safety: systemd passes the sockets open from descriptor 3 up, for this process
+    let listener = unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    if listener.local_addr().is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the socket passed by systemd is not a TCP socket (ListenStream= needs a port)",
+        ));
+    }
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+pub fn activated_listener() -> io::Result<Option<TcpListener>> {
+    Ok(None)
+}
+
+/// The listener for `addr`, or the one systemd passed in instead
+pub fn listener(addr: std::net::SocketAddr) -> io::Result<TcpListener> {
+    match activated_listener()? {
+        Some(listener) => {
+            tracing::info!("Using the socket passed by systemd");
+            Ok(listener)
+        }
+        None => TcpListener::bind(addr),
+    }
+}
+
+/// Tells systemd the service is ready (`READY=1`), if it is waiting to hear
+pub fn notify_ready() {
+    #[cfg(unix)]
+    if let Some(socket) = std::env::var_os("NOTIFY_SOCKET")
+        && let Err(e) = notify(&socket, "READY=1")
+    {
+        tracing::warn!("Could not notify systemd: {}", e);
+    }
+}
+
+/// Resolves when the process is asked to stop: on Ctrl+C, or the SIGTERM systemd stops a
+/// service with
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Could not listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutting down");
+}
+
+/// Sends `state` to the notification socket at `path`, which starts with `@` when it's
+/// in the abstract namespace
+#[cfg(unix)]
+fn notify(path: &std::ffi::OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::*;
+
+    #[test]
+    fn test_notify() {
+        let path = std::env::temp_dir().join(format!("koko-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        notify(path.as_os_str(), "READY=1").unwrap();
+        let mut message = [0; 16];
+        let len = systemd.recv(&mut message).unwrap();
+        assert_eq!(&message[..len], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+
+        // Not started by systemd
+        assert!(activated_listener().unwrap().is_none());
+    }
+}
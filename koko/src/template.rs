@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use kokoros::utils::checksum;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use crate::batch::{self, SynthSettings};
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r"\{(\w+)(?::(\d+))?\}").unwrap();
+}
+
+/// Model file `{key}`s are derived from, set at startup
+static MODEL: OnceLock<PathBuf> = OnceLock::new();
+
+/// Version of the model, read the first time a `{key}` is rendered
+static MODEL_VERSION: OnceLock<String> = OnceLock::new();
+
+/// Sets the model file that `{key}` placeholders identify
+pub fn set_model(path: &Path) {
+    let _ = MODEL.set(path.to_path_buf());
+}
+
+/// Short identity of the model set with [`set_model`], read once
+pub(crate) fn model_version() -> Result<&'static str, String> {
+    if let Some(version) = MODEL_VERSION.get() {
+        return Ok(version);
+    }
+    let path = MODEL.get().ok_or("{key} needs the model file")?;
+    let version = checksum::model_version(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    Ok(MODEL_VERSION.get_or_init(|| version))
+}
+
+/// Values available to `{placeholder}`s in output path templates
+pub struct TemplateVars<'a> {
+    /// 1-based index of the utterance, `{n}`
+    pub n: usize,
+    /// `{line}`, kept for the file mode's original 0-based line numbers
+    pub line: usize,
+    /// Text being synthesized, used for `{hash}` and `{key}`
+    pub text: &'a str,
+    /// What the text is synthesized with, for `{voice}`, `{lan}` and `{key}`
+    pub settings: &'a SynthSettings<'a>,
+}
+
+/// Short, stable hash of the text (FNV-1a, 8 hex digits) so reruns map to the same names
+pub fn text_hash(text: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)[..8].to_string()
+}
+
+/// Hash of everything the audio of an output depends on, as for a [`batch::segment_key`]
+/// with the template's extension as the format. Two outputs with the same key have the
+/// same audio, so one that already exists needn't be synthesized again.
+fn content_key(template: &str, vars: &TemplateVars) -> Result<String, String> {
+    let key = batch::segment_key(vars.text, Path::new(template), vars.settings)?;
+    Ok(key[..16].to_string())
+}
+
+/// Expands `{n}`, `{line}`, `{voice}`, `{lan}`, `{hash}` and `{key}` in an output path
+/// template. Numbers accept a zero-padded width, e.g. `{n:04}`.
+pub fn render(template: &str, vars: &TemplateVars) -> Result<String, String> {
+    let mut error = None;
+    let rendered = PLACEHOLDER.replace_all(template, |caps: &Captures| {
+        let width: usize = caps.get(2).map_or(0, |w| w.as_str().parse().unwrap_or(0));
+        match &caps[1] {
+            "n" => format!("{:0width$}", vars.n, width = width),
+            "line" => format!("{:0width$}", vars.line, width = width),
+            "voice" => vars.settings.style.to_string(),
+            "lan" => vars.settings.lan.to_string(),
+            "hash" => text_hash(vars.text),
+            "key" => content_key(template, vars).unwrap_or_else(|e| {
+                error.get_or_insert(e);
+                String::new()
+            }),
+            other => {
+                error.get_or_insert_with(|| format!("unknown output placeholder '{{{}}}'", other));
+                String::new()
+            }
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(rendered.into_owned()),
+    }
+}
+
+/// Renders an output path template and creates the directories it points into
+pub fn render_path(
+    template: &str,
+    vars: &TemplateVars,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let path = render(template, vars)?;
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(path)
+}
+
+/// Sets a model for tests, the same one in every test as only the first is used
+#[cfg(test)]
+pub(crate) fn set_test_model() {
+    set_model(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/Cargo.toml"
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kokoros::tts::normalize::TextOptions;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_render() {
+        let voice_defaults = HashMap::new();
+        let text_options = TextOptions::default();
+        let settings = batch::test_settings(&voice_defaults, &text_options);
+        let vars = TemplateVars {
+            n: 7,
+            line: 6,
+            text: "Hello",
+            settings: &settings,
+        };
+        assert_eq!(
+            render("out/{voice}/{n:04}_{lan}.wav", &vars).unwrap(),
+            "out/af_sky/0007_en-us.wav"
+        );
+        assert_eq!(
+            render("tmp/output_{line}.wav", &vars).unwrap(),
+            "tmp/output_6.wav"
+        );
+        assert_eq!(render("{hash}", &vars).unwrap(), text_hash("Hello"));
+        assert_ne!(text_hash("Hello"), text_hash("Hello!"));
+        assert!(render("{name}.wav", &vars).is_err());
+
+        set_test_model();
+        let key = render("{key}.wav", &vars).unwrap();
+        assert_eq!(key.len(), 16 + 4);
+        assert_eq!(
+            render("out/{key}.wav", &vars).unwrap(),
+            format!("out/{}", key)
+        );
+        assert_ne!(render("{key}.mp3", &vars).unwrap(), key);
+        let faster = SynthSettings {
+            speed: 1.2,
+            ..settings
+        };
+        let faster_vars = TemplateVars {
+            settings: &faster,
+            ..vars
+        };
+        assert_ne!(render("{key}.wav", &faster_vars).unwrap(), key);
+        let leveled = SynthSettings {
+            loudness: Some(-16.0),
+            ..settings
+        };
+        let leveled_vars = TemplateVars {
+            settings: &leveled,
+            ..vars
+        };
+        assert_ne!(render("{key}.wav", &leveled_vars).unwrap(), key);
+        let spelled_options = TextOptions {
+            spell_out: true,
+            ..TextOptions::default()
+        };
+        let spelled = SynthSettings {
+            text_options: &spelled_options,
+            ..settings
+        };
+        let spelled_vars = TemplateVars {
+            settings: &spelled,
+            ..vars
+        };
+        assert_ne!(render("{key}.wav", &spelled_vars).unwrap(), key);
+    }
+}
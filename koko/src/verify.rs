@@ -0,0 +1,55 @@
+use std::fs;
+use std::path::Path;
+
+use kokoros::tts::golden::{first_difference, parse_golden, update_golden, verify};
+use kokoros::tts::normalize::TextOptions;
+
+/// Checks the golden pronunciation file at `golden_path` with the text options synthesis
+/// would use, printing every case that came out differently. With `update`, the file is
+/// rewritten with the phonemes each case has now instead of failing.
+pub fn run(
+    golden_path: &Path,
+    lan: &str,
+    options: &TextOptions,
+    update: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(golden_path)?;
+    let cases = parse_golden(&content).map_err(|e| format!("{}: {}", golden_path.display(), e))?;
+    let mismatches = verify(&cases, lan, options)?;
+
+    for mismatch in &mismatches {
+        let case = mismatch.case;
+        println!(
+            "{}:{}: {:?} ({})",
+            golden_path.display(),
+            case.line,
+            case.text,
+            case.lan.as_deref().unwrap_or(lan)
+        );
+        println!("  expected: {}", case.phonemes);
+        println!("  actual:   {}", mismatch.actual);
+        if let Some(at) = first_difference(&case.phonemes, &mismatch.actual) {
+            println!("            {}^", " ".repeat(at));
+        }
+    }
+    println!(
+        "{} cases, {} passed, {} differ",
+        cases.len(),
+        cases.len() - mismatches.len(),
+        mismatches.len()
+    );
+
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    if update {
+        fs::write(golden_path, update_golden(&content, &mismatches))?;
+        eprintln!(
+            "Updated {} cases in {}",
+            mismatches.len(),
+            golden_path.display()
+        );
+        return Ok(());
+    }
+    Err(format!("{} of {} cases differ", mismatches.len(), cases.len()).into())
+}
@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use kokoros::tts::voices::{self, VoiceRegistry, list_voice_names};
+
+/// Metadata of `names`, with the sidecars of the voices file and voices directory applied
+pub fn registry(
+    data_path: &str,
+    voices_dir: Option<&Path>,
+    names: &[String],
+) -> Result<VoiceRegistry, Box<dyn std::error::Error>> {
+    let mut registry = VoiceRegistry::load(Path::new(data_path), names)?;
+    if let Some(dir) = voices_dir {
+        registry.load_sidecar(dir)?;
+    }
+    Ok(registry)
+}
+
+/// Prints every voice in the voices file and voices directory with its language and
+/// gender (and any sidecar metadata), plus blending examples
+pub fn run(data_path: &str, voices_dir: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut names = list_voice_names(data_path)
+        .map_err(|e| format!("could not read voices file {}: {}", data_path, e))?;
+    let builtin = names.len();
+    if let Some(dir) = voices_dir {
+        let dir_path = dir.to_string_lossy();
+        names.extend(
+            list_voice_names(&dir_path)
+                .map_err(|e| format!("could not read voices from {}: {}", dir_path, e))?,
+        );
+        names.sort();
+        names.dedup();
+    }
+    let registry = registry(data_path, voices_dir, &names)?;
+
+    println!(
+        "{:<16} {:<22} {:<8} {:<6} NOTES",
+        "VOICE", "LANGUAGE", "GENDER", "--lan"
+    );
+    for name in &names {
+        let Some(metadata) = registry.get(name) else {
+            continue;
+        };
+        let mut notes = metadata.description.clone().unwrap_or_default();
+        if let Some(speed) = metadata.speed {
+            notes = format!("{} (best at --speed {})", notes, speed)
+                .trim()
+                .to_string();
+        }
+        let line = format!(
+            "{:<16} {:<22} {:<8} {:<6} {}",
+            name, metadata.language, metadata.gender, metadata.lan, notes
+        );
+        println!("{}", line.trim_end());
+    }
+
+    println!();
+    match voices_dir {
+        Some(dir) => println!(
+            "{} voices in {} and {} more in {}",
+            builtin,
+            data_path,
+            names.len() - builtin,
+            dir.display()
+        ),
+        None => println!("{} voices in {}", names.len(), data_path),
+    }
+    if let [first, second, ..] = names.as_slice() {
+        println!(
+            "Use one with `--style {}`, or blend several with weights:",
+            first
+        );
+        println!(
+            "  --style {}.4+{}.6   (40% {}, 60% {})",
+            first, second, first, second
+        );
+    }
+    Ok(())
+}
+
+/// Builds a voice from `recipe` (the `--style` blending syntax), scales it and saves it
+/// as `<dir>/<name>.npy`
+pub fn mix(
+    data_path: &str,
+    recipe: &str,
+    name: &str,
+    scale: f32,
+    dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let styles = voices::load_voices(Path::new(data_path))
+        .map_err(|e| format!("could not read voices file {}: {}", data_path, e))?;
+    let voice = styles.blend(&voices::parse_blend(recipe)?)?.scale(scale);
+    let path = voice.save_as(dir, name)?;
+    println!("Saved voice {} to {}", name, path.display());
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use kokoros::tts::koko::TTSKoko;
+
+use crate::batch::{self, SynthSettings};
+
+/// Polls `dir` for new or changed `.txt`/`.md` files and synthesizes each one to a WAV file
+/// next to it. A file is only picked up once its modification time has been stable for one
+/// poll, so half-written files are not read. Runs until the process is interrupted.
+pub fn run(
+    tts: &TTSKoko,
+    dir: &Path,
+    interval: Duration,
+    settings: &SynthSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()).into());
+    }
+    eprintln!(
+        "Watching {} for .txt/.md files every {:?}. Press Ctrl+C to stop.",
+        dir.display(),
+        interval
+    );
+
+    // Modification time of each changed file as of the previous poll
+    let mut pending: HashMap<PathBuf, SystemTime> = HashMap::new();
+    // Files that failed, so they are only retried once they change again
+    let mut failed: HashMap<PathBuf, SystemTime> = HashMap::new();
+
+    loop {
+        let inputs = batch::collect_inputs(dir)?;
+        for input in &inputs {
+            let output = batch::wav_path(input, &inputs);
+            let Some(mtime) = batch::modified(input) else {
+                continue;
+            };
+            if batch::is_up_to_date(input, &output) || failed.get(input) == Some(&mtime) {
+                pending.remove(input);
+                continue;
+            }
+            if pending.insert(input.clone(), mtime) != Some(mtime) {
+                continue;
+            }
+            pending.remove(input);
+
+            let result = batch::read_input_text(input)
+                .map_err(|e| e.into())
+                .and_then(|text| batch::synthesize_to_file(tts, &text, &output, settings));
+            match result {
+                Ok(duration) => {
+                    failed.remove(input);
+                    eprintln!(
+                        "{} -> {} ({:.1}s)",
+                        input.display(),
+                        output.display(),
+                        duration
+                    );
+                }
+                Err(e) => {
+                    failed.insert(input.clone(), mtime);
+                    eprintln!("{} failed: {}", input.display(), e);
+                }
+            }
+        }
+
+        std::thread::sleep(interval);
+    }
+}
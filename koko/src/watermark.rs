@@ -0,0 +1,33 @@
+use std::path::Path;
+
+use kokoros::utils::watermark::Watermark;
+
+use crate::batch::read_clip;
+
+/// Prints how strongly each WAV file in `inputs` carries `watermark`, failing when any
+/// doesn't
+pub fn run(watermark: &Watermark, inputs: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut missing = 0;
+    for input in inputs {
+        let samples =
+            read_clip(Path::new(input)).map_err(|e| format!("failed to read {}: {}", input, e))?;
+        let detection = watermark.detect(&samples);
+        println!(
+            "{}\t{:.1}\t{}",
+            input,
+            detection.score,
+            if detection.detected {
+                "marked"
+            } else {
+                "not marked"
+            }
+        );
+        if !detection.detected {
+            missing += 1;
+        }
+    }
+    if missing > 0 {
+        return Err(format!("{} of {} files are not marked", missing, inputs.len()).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,248 @@
+//! Wyoming protocol server, so Home Assistant can use Kokoros as a local TTS provider.
+//!
+//! Every event is a JSON header line, optionally followed by `data_length` bytes of JSON
+//! data and `payload_length` bytes of binary payload. This implements `describe` (answered
+//! with `info`) and `synthesize` (answered with `audio-start`, `audio-chunk`s of 16-bit
+//! PCM and `audio-stop`).
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::voices::voice_info;
+use serde_json::{Value, json};
+
+use crate::batch::SynthSettings;
+
+const SAMPLE_RATE: u32 = 24_000;
+
+pub struct Event {
+    pub kind: String,
+    pub data: Value,
+    pub payload: Vec<u8>,
+}
+
+/// Reads the next event, or `None` once the client has closed the connection
+pub fn read_event(reader: &mut impl BufRead) -> Result<Option<Event>, Box<dyn std::error::Error>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let header: Value = serde_json::from_str(&line)?;
+    let kind = header["type"]
+        .as_str()
+        .ok_or("event header without a type")?
+        .to_string();
+
+    // Data may be inline in the header, in a separate block, or both
+    let mut data = header.get("data").cloned().unwrap_or_else(|| json!({}));
+    if let Some(len) = header["data_length"].as_u64().filter(|&len| len > 0) {
+        let mut buf = vec![0; len as usize];
+        reader.read_exact(&mut buf)?;
+        if let (Value::Object(data), Value::Object(extra)) =
+            (&mut data, serde_json::from_slice(&buf)?)
+        {
+            data.extend(extra);
+        }
+    }
+    let mut payload = vec![0; header["payload_length"].as_u64().unwrap_or(0) as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Some(Event {
+        kind,
+        data,
+        payload,
+    }))
+}
+
+pub fn write_event(
+    writer: &mut impl Write,
+    kind: &str,
+    data: &Value,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let data = serde_json::to_vec(data)?;
+    let header = json!({
+        "type": kind,
+        "data_length": data.len(),
+        "payload_length": payload.len(),
+    });
+    writer.write_all(format!("{}\n", header).as_bytes())?;
+    writer.write_all(&data)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Home Assistant style language code for an espeak-ng one, e.g. `en-us` -> `en_US`
+fn wyoming_language(lan: &str) -> String {
+    match lan {
+        "cmn" => "zh_CN".to_string(),
+        _ => match lan.split_once('-') {
+            Some((language, region)) => format!("{}_{}", language, region.to_uppercase()),
+            None => lan.to_string(),
+        },
+    }
+}
+
+fn info(tts: &TTSKoko) -> Value {
+    let attribution =
+        json!({"name": "hexgrad", "url": "https://huggingface.co/hexgrad/Kokoro-82M"});
+    let voices: Vec<Value> = tts
+        .get_available_voices()
+        .into_iter()
+        .map(|name| {
+            let info = voice_info(&name);
+            json!({
+                "name": name,
+                "description": format!("{} ({}, {})", name, info.language, info.gender),
+                "attribution": attribution,
+                "installed": true,
+                "version": null,
+                "languages": if info.lan.is_empty() { vec![] } else { vec![wyoming_language(info.lan)] },
+            })
+        })
+        .collect();
+
+    json!({
+        "tts": [{
+            "name": "kokoros",
+            "description": "Kokoro text to speech",
+            "attribution": attribution,
+            "installed": true,
+            "version": env!("CARGO_PKG_VERSION"),
+            "voices": voices,
+        }]
+    })
+}
+
+fn synthesize(
+    tts: &TTSKoko,
+    data: &Value,
+    settings: &SynthSettings,
+    writer: &mut impl Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let text = data["text"].as_str().unwrap_or_default();
+    let voice = data["voice"]["name"]
+        .as_str()
+        .filter(|name| tts.get_available_voices().iter().any(|v| v == name))
+        .unwrap_or(settings.style);
+    let lan = match voice_info(voice).lan {
+        "" => settings.lan,
+        lan => lan,
+    };
+    tracing::debug!("Wyoming synthesize with {}: '{}'", voice, text);
+
+    let format = json!({"rate": SAMPLE_RATE, "width": 2, "channels": 1});
+    write_event(writer, "audio-start", &format, &[])?;
+    tts.tts_raw_audio_streaming(
+        text,
+        lan,
+        voice,
+        settings.speed,
+        settings.initial_silence,
+        None,
+        None,
+        None,
+        |audio| {
+            let pcm: Vec<u8> = audio
+                .iter()
+                .flat_map(|s| ((s * 32767.0).clamp(-32768.0, 32767.0) as i16).to_le_bytes())
+                .collect();
+            write_event(writer, "audio-chunk", &format, &pcm)?;
+            Ok(())
+        },
+    )?;
+    write_event(writer, "audio-stop", &json!({}), &[])?;
+    Ok(())
+}
+
+fn handle_connection(
+    tts: &TTSKoko,
+    stream: TcpStream,
+    settings: &SynthSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    while let Some(event) = read_event(&mut reader)? {
+        match event.kind.as_str() {
+            "describe" => write_event(&mut writer, "info", &info(tts), &[])?,
+            "synthesize" => {
+                if let Err(e) = synthesize(tts, &event.data, settings, &mut writer) {
+                    tracing::error!("Wyoming synthesis failed: {}", e);
+                    write_event(&mut writer, "error", &json!({"text": e.to_string()}), &[])?;
+                }
+            }
+            other => tracing::debug!(
+                "Ignoring Wyoming event '{}' ({} byte payload)",
+                other,
+                event.payload.len()
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Serves the Wyoming protocol on `addr`, one thread per connection
+pub fn run(
+    tts: &TTSKoko,
+    addr: SocketAddr,
+    settings: &SynthSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = crate::systemd::listener(addr)?;
+    tracing::info!(
+        "Starting Wyoming server on tcp://{}",
+        listener.local_addr()?
+    );
+    crate::systemd::notify_ready();
+
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to accept Wyoming connection: {}", e);
+                    continue;
+                }
+            };
+            scope.spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = handle_connection(tts, stream, settings) {
+                    tracing::warn!("Wyoming connection {:?} closed: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_roundtrip() {
+        let mut buf = Vec::new();
+        write_event(
+            &mut buf,
+            "audio-chunk",
+            &json!({"rate": 24000, "width": 2, "channels": 1}),
+            &[1, 2, 3, 4],
+        )
+        .unwrap();
+        buf.extend_from_slice(b"{\"type\": \"describe\"}\n");
+
+        let mut reader = &buf[..];
+        let event = read_event(&mut reader).unwrap().unwrap();
+        assert_eq!(event.kind, "audio-chunk");
+        assert_eq!(event.data["rate"], 24000);
+        assert_eq!(event.payload, vec![1, 2, 3, 4]);
+
+        let event = read_event(&mut reader).unwrap().unwrap();
+        assert_eq!(event.kind, "describe");
+        assert!(event.payload.is_empty());
+        assert!(read_event(&mut reader).unwrap().is_none());
+
+        assert_eq!(wyoming_language("en-us"), "en_US");
+        assert_eq!(wyoming_language("it"), "it");
+    }
+}
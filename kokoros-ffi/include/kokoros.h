@@ -0,0 +1,99 @@
+#ifndef KOKOROS_H
+#define KOKOROS_H
+
+/* Generated by cbindgen from kokoros-ffi/src/lib.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Success
+#define KOKOROS_OK 0
+
+// A required pointer was null or a string was not valid UTF-8
+#define KOKOROS_ERR_INVALID_ARGUMENT -1
+
+// Synthesis failed, e.g. because of an unknown voice
+#define KOKOROS_ERR_SYNTHESIS -2
+
+// The streaming callback returned non-zero and synthesis was stopped
+#define KOKOROS_ERR_CANCELLED -3
+
+// The engine panicked; it should not be used again
+#define KOKOROS_ERR_PANIC -4
+
+// Sample rate of all audio produced, in Hz (mono, 32-bit float)
+#define KOKOROS_SAMPLE_RATE 24000
+
+// A loaded model and voices file. Thread-safe: one engine may be shared by several
+// threads, though synthesis calls on it run one at a time.
+typedef struct KokorosEngine KokorosEngine;
+
+// Called with each chunk of audio as soon as it is synthesized. `samples` is only valid
+// for the duration of the call. Return 0 to continue or non-zero to stop synthesis.
+typedef int32_t (*KokorosChunkCallback)(const float *samples, size_t len, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message for the last error on this thread, or NULL if there was none. The string is
+// owned by the library and valid until the next failing call on the same thread.
+const char *kokoros_last_error(void);
+
+// Loads the ONNX model and voices file, downloading them to the given paths if they
+// don't exist. Returns NULL on failure; see `kokoros_last_error`.
+//
+// # Safety
+// `model_path` and `voices_path` must be null or valid NUL-terminated strings.
+struct KokorosEngine *kokoros_init(const char *model_path, const char *voices_path);
+
+// Frees an engine returned by `kokoros_init`. Passing NULL is a no-op.
+//
+// # Safety
+// `engine` must be null or a pointer from `kokoros_init` that has not been freed.
+void kokoros_free(struct KokorosEngine *engine);
+
+// Synthesizes `text` into a newly allocated buffer of mono samples at
+// `KOKOROS_SAMPLE_RATE`, stored in `*samples` and `*len`. Free it with
+// `kokoros_free_samples`. `lan` is an espeak language code such as "en-us" and
+// `voice` a voice name such as "af_sky", or a mix like "af_sky.4+af_nicole.6".
+//
+// # Safety
+// `engine` must come from `kokoros_init`; the strings must be valid NUL-terminated
+// strings; `samples` and `len` must be valid for writes.
+int32_t kokoros_synth(const struct KokorosEngine *engine,
+                      const char *text,
+                      const char *voice,
+                      const char *lan,
+                      float speed,
+                      float **samples,
+                      size_t *len);
+
+// Frees a buffer returned by `kokoros_synth`. Passing NULL is a no-op.
+//
+// # Safety
+// `samples` and `len` must be exactly as returned by `kokoros_synth`, freed only once.
+void kokoros_free_samples(float *samples, size_t len);
+
+// Synthesizes `text` sentence by sentence, calling `callback` with each chunk of audio
+// as soon as it is ready, so playback can start before the whole text is done.
+// Returns `KOKOROS_ERR_CANCELLED` if the callback asked to stop.
+//
+// # Safety
+// As for `kokoros_synth`; `callback` must be safe to call with `user_data`.
+int32_t kokoros_synth_streaming(const struct KokorosEngine *engine,
+                                const char *text,
+                                const char *voice,
+                                const char *lan,
+                                float speed,
+                                KokorosChunkCallback callback,
+                                void *user_data);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* KOKOROS_H */
@@ -0,0 +1,268 @@
+//! C interface to the Kokoros engine, so C, C++ and C# applications can embed it
+//! without spawning the CLI. See `include/kokoros.h` (generated by cbindgen on build).
+//!
+//! Functions returning `int32_t` return `KOKOROS_OK` (0) on success and a negative
+//! error code otherwise; `kokoros_last_error` then describes what went wrong.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::ptr;
+
+use kokoros::tts::koko::TTSKoko;
+
+/// Success
+pub const KOKOROS_OK: i32 = 0;
+/// A required pointer was null or a string was not valid UTF-8
+pub const KOKOROS_ERR_INVALID_ARGUMENT: i32 = -1;
+/// Synthesis failed, e.g. because of an unknown voice
+pub const KOKOROS_ERR_SYNTHESIS: i32 = -2;
+/// The streaming callback returned non-zero and synthesis was stopped
+pub const KOKOROS_ERR_CANCELLED: i32 = -3;
+/// The engine panicked; it should not be used again
+pub const KOKOROS_ERR_PANIC: i32 = -4;
+
+/// Sample rate of all audio produced, in Hz (mono, 32-bit float)
+pub const KOKOROS_SAMPLE_RATE: u32 = 24_000;
+
+/// A loaded model and voices file. Thread-safe: one engine may be shared by several
+/// threads, though synthesis calls on it run one at a time.
+pub struct KokorosEngine {
+    tts: TTSKoko,
+}
+
+/// Called with each chunk of audio as soon as it is synthesized. `samples` is only valid
+/// for the duration of the call. Return 0 to continue or non-zero to stop synthesis.
+pub type KokorosChunkCallback =
+    Option<unsafe extern "C" fn(samples: *const f32, len: usize, user_data: *mut c_void) -> i32>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: impl Into<String>) {
+    let msg = CString::new(msg.into().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// Runs `f`, turning errors and panics into error codes and recording the message
+fn guard(f: impl FnOnce() -> Result<(), (i32, String)>) -> i32 {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => KOKOROS_OK,
+        Ok(Err((code, msg))) => {
+            set_last_error(msg);
+            code
+        }
+        Err(_) => {
+            set_last_error("kokoros panicked");
+            KOKOROS_ERR_PANIC
+        }
+    }
+}
+
+/// Borrows a C string as `&str`, naming the argument in the error
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, (i32, String)> {
+    if ptr.is_null() {
+        return Err((
+            KOKOROS_ERR_INVALID_ARGUMENT,
+            format!("{} must not be null", name),
+        ));
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_str().map_err(|_| {
+        (
+            KOKOROS_ERR_INVALID_ARGUMENT,
+            format!("{} is not valid UTF-8", name),
+        )
+    })
+}
+
+unsafe fn engine_arg<'a>(engine: *const KokorosEngine) -> Result<&'a KokorosEngine, (i32, String)> {
+    unsafe { engine.as_ref() }.ok_or((
+        KOKOROS_ERR_INVALID_ARGUMENT,
+        "engine must not be null".to_string(),
+    ))
+}
+
+/// Message for the last error on this thread, or NULL if there was none. The string is
+/// owned by the library and valid until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn kokoros_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |msg| msg.as_ptr()))
+}
+
+/// Loads the ONNX model and voices file, downloading them to the given paths if they
+/// don't exist. Returns NULL on failure; see `kokoros_last_error`.
+///
+/// # Safety
+/// `model_path` and `voices_path` must be null or valid NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kokoros_init(
+    model_path: *const c_char,
+    voices_path: *const c_char,
+) -> *mut KokorosEngine {
+    let mut engine = ptr::null_mut();
+    guard(|| {
+        let model_path = unsafe { str_arg(model_path, "model_path") }?;
+        let voices_path = unsafe { str_arg(voices_path, "voices_path") }?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| (KOKOROS_ERR_SYNTHESIS, e.to_string()))?;
+        let tts = runtime
+            .block_on(TTSKoko::builder(model_path, voices_path).build())
+            .map_err(|e| (KOKOROS_ERR_SYNTHESIS, e.to_string()))?;
+        engine = Box::into_raw(Box::new(KokorosEngine { tts }));
+        Ok(())
+    });
+    engine
+}
+
+/// Frees an engine returned by `kokoros_init`. Passing NULL is a no-op.
+///
+/// # Safety
+/// `engine` must be null or a pointer from `kokoros_init` that has not been freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kokoros_free(engine: *mut KokorosEngine) {
+    if !engine.is_null() {
+        drop(unsafe { Box::from_raw(engine) });
+    }
+}
+
+/// Synthesizes `text` into a newly allocated buffer of mono samples at
+/// `KOKOROS_SAMPLE_RATE`, stored in `*samples` and `*len`. Free it with
+/// `kokoros_free_samples`. `lan` is an espeak language code such as "en-us" and
+/// `voice` a voice name such as "af_sky", or a mix like "af_sky.4+af_nicole.6".
+///
+/// # Safety
+/// `engine` must come from `kokoros_init`; the strings must be valid NUL-terminated
+/// strings; `samples` and `len` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kokoros_synth(
+    engine: *const KokorosEngine,
+    text: *const c_char,
+    voice: *const c_char,
+    lan: *const c_char,
+    speed: f32,
+    samples: *mut *mut f32,
+    len: *mut usize,
+) -> i32 {
+    guard(|| {
+        let engine = unsafe { engine_arg(engine) }?;
+        let text = unsafe { str_arg(text, "text") }?;
+        let voice = unsafe { str_arg(voice, "voice") }?;
+        let lan = unsafe { str_arg(lan, "lan") }?;
+        if samples.is_null() || len.is_null() {
+            return Err((
+                KOKOROS_ERR_INVALID_ARGUMENT,
+                "samples and len must not be null".to_string(),
+            ));
+        }
+
+        let audio = engine
+            .tts
+            .tts_raw_audio(text, lan, voice, speed, None, None, None, None)
+            .map_err(|e| (KOKOROS_ERR_SYNTHESIS, e.to_string()))?;
+        let audio = audio.into_boxed_slice();
+        unsafe {
+            *len = audio.len();
+            *samples = Box::into_raw(audio) as *mut f32;
+        }
+        Ok(())
+    })
+}
+
+/// Frees a buffer returned by `kokoros_synth`. Passing NULL is a no-op.
+///
+/// # Safety
+/// `samples` and `len` must be exactly as returned by `kokoros_synth`, freed only once.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kokoros_free_samples(samples: *mut f32, len: usize) {
+    if !samples.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(samples, len)) });
+    }
+}
+
+/// Synthesizes `text` sentence by sentence, calling `callback` with each chunk of audio
+/// as soon as it is ready, so playback can start before the whole text is done.
+/// Returns `KOKOROS_ERR_CANCELLED` if the callback asked to stop.
+///
+/// # Safety
+/// As for `kokoros_synth`; `callback` must be safe to call with `user_data`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kokoros_synth_streaming(
+    engine: *const KokorosEngine,
+    text: *const c_char,
+    voice: *const c_char,
+    lan: *const c_char,
+    speed: f32,
+    callback: KokorosChunkCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    guard(|| {
+        let engine = unsafe { engine_arg(engine) }?;
+        let text = unsafe { str_arg(text, "text") }?;
+        let voice = unsafe { str_arg(voice, "voice") }?;
+        let lan = unsafe { str_arg(lan, "lan") }?;
+        let callback = callback.ok_or((
+            KOKOROS_ERR_INVALID_ARGUMENT,
+            "callback must not be null".to_string(),
+        ))?;
+
+        let mut cancelled = false;
+        let result = engine.tts.tts_raw_audio_streaming(
+            text,
+            lan,
+            voice,
+            speed,
+            None,
+            None,
+            None,
+            None,
+            |audio| {
+                if unsafe { callback(audio.as_ptr(), audio.len(), user_data) } != 0 {
+                    cancelled = true;
+                    return Err("cancelled by callback".into());
+                }
+                Ok(())
+            },
+        );
+        match result {
+            Ok(()) => Ok(()),
+            Err(_) if cancelled => Err((
+                KOKOROS_ERR_CANCELLED,
+                "synthesis cancelled by callback".to_string(),
+            )),
+            Err(e) => Err((KOKOROS_ERR_SYNTHESIS, e.to_string())),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_arguments() {
+        let engine = unsafe { kokoros_init(ptr::null(), ptr::null()) };
+        assert!(engine.is_null());
+        let error = unsafe { CStr::from_ptr(kokoros_last_error()) };
+        assert_eq!(error.to_str().unwrap(), "model_path must not be null");
+
+        let mut samples = ptr::null_mut();
+        let mut len = 0;
+        let code = unsafe {
+            kokoros_synth(
+                ptr::null(),
+                c"Hello".as_ptr(),
+                c"af_sky".as_ptr(),
+                c"en-us".as_ptr(),
+                1.0,
+                &mut samples,
+                &mut len,
+            )
+        };
+        assert_eq!(code, KOKOROS_ERR_INVALID_ARGUMENT);
+        assert!(samples.is_null());
+        unsafe { kokoros_free(ptr::null_mut()) };
+    }
+}
@@ -0,0 +1,197 @@
+//! Node.js bindings for the Kokoros engine (napi-rs), so Electron apps and Node backends
+//! can embed it instead of shelling out to the CLI. Synthesis runs on the libuv thread
+//! pool, off the JavaScript thread.
+
+use std::sync::Arc;
+
+use kokoros::tts::koko::{KokorosError, TTSKoko};
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{
+    ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{JsFunction, Task};
+use napi_derive::napi;
+
+fn to_napi_error(e: KokorosError) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct SynthesizeOptions {
+    /// Voice name such as "af_sky", or a mix like "af_sky.4+af_nicole.6"
+    pub voice: Option<String>,
+    pub speed: Option<f64>,
+    /// espeak-ng language code, e.g. "en-us"
+    pub lan: Option<String>,
+}
+
+struct Settings {
+    voice: String,
+    speed: f32,
+    lan: String,
+}
+
+impl From<Option<SynthesizeOptions>> for Settings {
+    fn from(options: Option<SynthesizeOptions>) -> Self {
+        let options = options.unwrap_or_default();
+        Self {
+            voice: options.voice.unwrap_or_else(|| "af_sky".to_string()),
+            speed: options.speed.unwrap_or(1.0) as f32,
+            lan: options.lan.unwrap_or_else(|| "en-us".to_string()),
+        }
+    }
+}
+
+pub struct LoadTask {
+    model_path: String,
+    voices_path: String,
+}
+
+impl Task for LoadTask {
+    type Output = TTSKoko;
+    type JsValue = Kokoro;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        runtime
+            .block_on(TTSKoko::builder(&self.model_path, &self.voices_path).build())
+            .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, tts: Self::Output) -> Result<Self::JsValue> {
+        Ok(Kokoro { tts: Arc::new(tts) })
+    }
+}
+
+pub struct SynthesizeTask {
+    tts: Arc<TTSKoko>,
+    text: String,
+    settings: Settings,
+}
+
+impl Task for SynthesizeTask {
+    type Output = Vec<f32>;
+    type JsValue = Float32Array;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let s = &self.settings;
+        self.tts
+            .tts_raw_audio(
+                &self.text, &s.lan, &s.voice, s.speed, None, None, None, None,
+            )
+            .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, audio: Self::Output) -> Result<Self::JsValue> {
+        Ok(Float32Array::new(audio))
+    }
+}
+
+pub struct SynthesizeStreamTask {
+    tts: Arc<TTSKoko>,
+    text: String,
+    settings: Settings,
+    on_chunk: ThreadsafeFunction<Vec<f32>, ErrorStrategy::Fatal>,
+}
+
+impl Task for SynthesizeStreamTask {
+    type Output = ();
+    type JsValue = ();
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let s = &self.settings;
+        self.tts
+            .tts_raw_audio_streaming(
+                &self.text,
+                &s.lan,
+                &s.voice,
+                s.speed,
+                None,
+                None,
+                None,
+                None,
+                |audio| {
+                    self.on_chunk
+                        .call(audio, ThreadsafeFunctionCallMode::Blocking);
+                    Ok(())
+                },
+            )
+            .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, _output: Self::Output) -> Result<Self::JsValue> {
+        Ok(())
+    }
+}
+
+/// A loaded Kokoro model and voices file
+#[napi]
+pub struct Kokoro {
+    tts: Arc<TTSKoko>,
+}
+
+#[napi]
+impl Kokoro {
+    /// Loads the ONNX model and voices file, downloading them to the given paths if they
+    /// don't exist yet
+    #[napi(ts_return_type = "Promise<Kokoro>")]
+    pub fn load(model_path: String, voices_path: String) -> AsyncTask<LoadTask> {
+        AsyncTask::new(LoadTask {
+            model_path,
+            voices_path,
+        })
+    }
+
+    /// Synthesizes `text` into mono samples at `sampleRate`
+    #[napi(ts_return_type = "Promise<Float32Array>")]
+    pub fn synthesize(
+        &self,
+        text: String,
+        options: Option<SynthesizeOptions>,
+    ) -> AsyncTask<SynthesizeTask> {
+        AsyncTask::new(SynthesizeTask {
+            tts: Arc::clone(&self.tts),
+            text,
+            settings: options.into(),
+        })
+    }
+
+    /// Synthesizes `text` sentence by sentence, calling `onChunk` with each chunk of audio
+    /// as soon as it is ready. Resolves once the whole text is done.
+    #[napi(
+        ts_args_type = "text: string, options: SynthesizeOptions | undefined | null, onChunk: (chunk: Float32Array) => void",
+        ts_return_type = "Promise<void>"
+    )]
+    pub fn synthesize_stream(
+        &self,
+        text: String,
+        options: Option<SynthesizeOptions>,
+        on_chunk: JsFunction,
+    ) -> Result<AsyncTask<SynthesizeStreamTask>> {
+        let on_chunk = on_chunk
+            .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<Vec<f32>>| {
+                Ok(vec![Float32Array::new(ctx.value)])
+            })?;
+        Ok(AsyncTask::new(SynthesizeStreamTask {
+            tts: Arc::clone(&self.tts),
+            text,
+            settings: options.into(),
+            on_chunk,
+        }))
+    }
+
+    /// Names of the voices in the loaded voices file
+    #[napi]
+    pub fn voices(&self) -> Vec<String> {
+        self.tts.get_available_voices()
+    }
+
+    /// Sample rate of the audio produced, in Hz
+    #[napi(getter)]
+    pub fn sample_rate(&self) -> u32 {
+        24_000
+    }
+}
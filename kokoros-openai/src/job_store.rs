@@ -0,0 +1,181 @@
+//! A SQLite database behind [`Jobs`](crate::jobs::Jobs), so the status and audio of jobs
+//! outlive restarts and a client can still fetch its results after a deploy
+
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::jobs::{JobState, JobStatus};
+
+pub(crate) struct JobStore {
+    connection: Mutex<Connection>,
+}
+
+impl JobStore {
+    /// Opens the database at `path`, creating it if needed. Jobs that were still queued
+    /// or running when the server stopped are failed, as nothing will finish them.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id TEXT PRIMARY KEY,
+                status TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                parts INTEGER NOT NULL,
+                parts_done INTEGER NOT NULL,
+                error TEXT,
+                content_type TEXT,
+                audio BLOB,
+                owner TEXT
+            );
+            UPDATE jobs SET status = 'failed', error = 'interrupted by a server restart'
+                WHERE status IN ('queued', 'running');",
+        )?;
+        // Stores from before jobs had owners
+        let has_owner: bool = connection.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('jobs') WHERE name = 'owner'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_owner {
+            connection.execute_batch("ALTER TABLE jobs ADD COLUMN owner TEXT;")?;
+        }
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The status and owner of every job
+    pub fn load(&self) -> rusqlite::Result<Vec<(JobStatus, Option<String>)>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT id, status, created_at, parts, parts_done, error, owner FROM jobs
+                ORDER BY created_at",
+        )?;
+        statement
+            .query_map([], |row| {
+                let status: String = row.get(1)?;
+                let (parts, parts_done): (usize, usize) = (row.get(3)?, row.get(4)?);
+                let status = JobStatus {
+                    id: row.get(0)?,
+                    object: "audio.job",
+                    status: JobState::parse(&status).unwrap_or(JobState::Failed),
+                    created_at: row.get(2)?,
+                    parts,
+                    parts_done,
+                    progress: parts_done as f32 / parts.max(1) as f32,
+                    seconds_synthesized: 0.0,
+                    eta_seconds: None,
+                    error: row.get(5)?,
+                };
+                Ok((status, row.get(6)?))
+            })?
+            .collect()
+    }
+
+    /// Records `status` and the job's owner, and its content type and audio once it has them
+    pub fn save(
+        &self,
+        status: &JobStatus,
+        owner: Option<&str>,
+        audio: Option<(&str, &[u8])>,
+    ) -> rusqlite::Result<()> {
+        let (content_type, audio) = audio.unzip();
+        self.connection().execute(
+            "INSERT INTO jobs
+                (id, status, created_at, parts, parts_done, error, content_type, audio, owner)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                ON CONFLICT (id) DO UPDATE SET
+                    status = excluded.status,
+                    parts_done = excluded.parts_done,
+                    error = excluded.error,
+                    content_type = COALESCE(excluded.content_type, content_type),
+                    audio = COALESCE(excluded.audio, audio)",
+            params![
+                status.id,
+                status.status.as_str(),
+                status.created_at,
+                status.parts,
+                status.parts_done,
+                status.error,
+                content_type,
+                audio,
+                owner,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The content type and audio of a completed job
+    pub fn audio(&self, id: &str) -> rusqlite::Result<Option<(String, Vec<u8>)>> {
+        self.connection()
+            .query_row(
+                "SELECT content_type, audio FROM jobs WHERE id = ?1 AND audio IS NOT NULL",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+    }
+
+    /// Deletes the finished jobs accepted before `created_before` (Unix time)
+    pub fn prune(&self, created_before: u64) -> rusqlite::Result<usize> {
+        self.connection().execute(
+            "DELETE FROM jobs WHERE created_at < ?1 AND status IN ('completed', 'failed')",
+            [created_before],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_store() {
+        let path = std::env::temp_dir().join(format!("koko-jobs-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut status = JobStatus {
+            id: "job_1".to_string(),
+            object: "audio.job",
+            status: JobState::Completed,
+            created_at: 100,
+            parts: 2,
+            parts_done: 2,
+            progress: 1.0,
+            seconds_synthesized: 0.0,
+            eta_seconds: None,
+            error: None,
+        };
+        let store = JobStore::open(&path).unwrap();
+        store
+            .save(&status, Some("acme"), Some(("audio/wav", b"RIFF")))
+            .unwrap();
+        status.id = "job_2".to_string();
+        status.status = JobState::Running;
+        status.created_at = 50;
+        store.save(&status, None, None).unwrap();
+        drop(store);
+
+        // After a restart, the running job has failed and the audio is still there
+        let store = JobStore::open(&path).unwrap();
+        let jobs = store.load().unwrap();
+        assert_eq!(jobs[0].0.id, "job_2");
+        assert_eq!(jobs[0].0.status, JobState::Failed);
+        assert_eq!(jobs[1].1.as_deref(), Some("acme"));
+        assert_eq!(
+            store.audio("job_1").unwrap(),
+            Some(("audio/wav".to_string(), b"RIFF".to_vec()))
+        );
+        assert_eq!(store.audio("job_2").unwrap(), None);
+        assert_eq!(store.prune(60).unwrap(), 1);
+        assert_eq!(store.load().unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,412 @@
+//! `/v1/audio/jobs` - speech synthesized in the background, for inputs too long to answer
+//! in one request
+//!
+//! With `ServerOptions::split_long_inputs`, a non-streaming speech request over the input
+//! limit is split into parts within the limit and accepted as a job instead of refused.
+//! Its status is polled at `/v1/audio/jobs/{id}`, and the audio fetched from
+//! `/v1/audio/jobs/{id}/content` once it is completed. Jobs are kept in memory, or with the
+//! `sqlite` feature in a database that outlives restarts, and finished ones are deleted
+//! once they are older than the retention period.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Extension, Json,
+    extract::Path,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use kokoros::tts::chunker::split_into_chunks;
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::koko_async::SynthRequest;
+use kokoros::tts::synthesis::SynthProgress;
+use kokoros::utils::loudness;
+use serde::Serialize;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{AudioFormat, ServerOptions, SpeechError, encode_audio, tenant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+impl JobState {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Completed => "completed",
+            Self::Failed => "failed",
+        }
+    }
+
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        [Self::Queued, Self::Running, Self::Completed, Self::Failed]
+            .into_iter()
+            .find(|state| state.as_str() == s)
+    }
+
+    fn is_finished(self) -> bool {
+        matches!(self, Self::Completed | Self::Failed)
+    }
+}
+
+/// What `GET /v1/audio/jobs/{id}` answers
+#[derive(Clone, Debug, Serialize)]
+pub struct JobStatus {
+    pub id: String,
+    pub object: &'static str,
+    pub status: JobState,
+    /// Unix time the job was accepted
+    pub created_at: u64,
+    /// Parts the input was split into, and how many of them are synthesized
+    pub parts: usize,
+    pub parts_done: usize,
+    /// Share of the job done, from 0 to 1, following the part in progress chunk by chunk
+    pub progress: f32,
+    /// Seconds of audio synthesized so far
+    pub seconds_synthesized: f32,
+    /// Seconds the job will likely take until it is completed, while it runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_seconds: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct Job {
+    status: JobStatus,
+    /// Name of the key the job was submitted with, the only one that may see it, when the
+    /// server has keys
+    owner: Option<String>,
+    /// Content type and encoded audio, once completed; for jobs loaded from the store,
+    /// only there
+    audio: Option<(&'static str, Vec<u8>)>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The jobs of a server
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Job>>,
+    #[cfg(feature = "sqlite")]
+    store: Option<crate::job_store::JobStore>,
+    /// How long finished jobs are kept after being accepted; forever if `None`
+    retention: Option<Duration>,
+}
+
+impl Jobs {
+    /// Jobs kept in memory, deleted `retention` after being accepted once finished
+    pub fn new(retention: Option<Duration>) -> Self {
+        Self {
+            retention,
+            ..Self::default()
+        }
+    }
+
+    /// Jobs kept in the SQLite database at `path` as well, so that they are still there
+    /// after a restart. Jobs the previous server didn't finish are failed.
+    #[cfg(feature = "sqlite")]
+    pub fn open(path: &std::path::Path, retention: Option<Duration>) -> Result<Self, String> {
+        let error = |e: rusqlite::Error| format!("job store {}: {}", path.display(), e);
+        let store = crate::job_store::JobStore::open(path).map_err(error)?;
+        let jobs = store
+            .load()
+            .map_err(error)?
+            .into_iter()
+            .map(|(status, owner)| {
+                (
+                    status.id.clone(),
+                    Job {
+                        status,
+                        owner,
+                        audio: None,
+                    },
+                )
+            })
+            .collect();
+        let jobs = Self {
+            jobs: Mutex::new(jobs),
+            store: Some(store),
+            retention,
+        };
+        jobs.prune();
+        Ok(jobs)
+    }
+
+    /// The status of job `id`, if it was submitted with key `owner`
+    pub fn status(&self, id: &str, owner: Option<&str>) -> Option<JobStatus> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(id)
+            .filter(|job| job.owner.as_deref() == owner)
+            .map(|job| job.status.clone())
+    }
+
+    /// The content type and audio of a job submitted with key `owner`, or its state if it
+    /// isn't completed
+    fn content(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Option<Result<(String, Vec<u8>), JobState>> {
+        let state = {
+            let jobs = self.jobs.lock().unwrap();
+            let job = jobs.get(id).filter(|job| job.owner.as_deref() == owner)?;
+            if let Some((content_type, audio_data)) = &job.audio {
+                return Some(Ok((content_type.to_string(), audio_data.clone())));
+            }
+            job.status.status
+        };
+        #[cfg(feature = "sqlite")]
+        if state == JobState::Completed
+            && let Some(store) = &self.store
+        {
+            match store.audio(id) {
+                Ok(Some(audio)) => return Some(Ok(audio)),
+                Ok(None) => {}
+                Err(e) => error!("Could not read job {} from the store: {}", id, e),
+            }
+        }
+        Some(Err(state))
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            f(job);
+            self.save(job);
+        }
+    }
+
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn save(&self, job: &Job) {
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &self.store {
+            let audio = job
+                .audio
+                .as_ref()
+                .map(|(content_type, audio_data)| (*content_type, &audio_data[..]));
+            if let Err(e) = store.save(&job.status, job.owner.as_deref(), audio) {
+                error!("Could not store job {}: {}", job.status.id, e);
+            }
+        }
+    }
+
+    /// Deletes the finished jobs past the retention period
+    fn prune(&self) {
+        let Some(retention) = self.retention else {
+            return;
+        };
+        let created_before = now().saturating_sub(retention.as_secs());
+        self.jobs.lock().unwrap().retain(|_, job| {
+            !(job.status.status.is_finished() && job.status.created_at < created_before)
+        });
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &self.store
+            && let Err(e) = store.prune(created_before)
+        {
+            error!("Could not delete old jobs from the store: {}", e);
+        }
+    }
+
+    /// Splits `request`'s text into parts of at most `max_chars` characters and synthesizes
+    /// them one after another in the background, encoding the joined audio as `format`.
+    /// Only requests with key `owner` see the job.
+    pub(crate) fn submit(
+        self: &Arc<Self>,
+        tts: TTSKoko,
+        request: SynthRequest,
+        format: AudioFormat,
+        max_chars: usize,
+        owner: Option<&str>,
+    ) -> JobStatus {
+        self.prune();
+        let parts = split_into_chunks(&request.text, max_chars, |part| part.chars().count());
+        let status = JobStatus {
+            id: format!("job_{}", Uuid::new_v4().simple()),
+            object: "audio.job",
+            status: JobState::Queued,
+            created_at: now(),
+            parts: parts.len(),
+            parts_done: 0,
+            progress: 0.0,
+            seconds_synthesized: 0.0,
+            eta_seconds: None,
+            error: None,
+        };
+        let id = status.id.clone();
+        let job = Job {
+            status: status.clone(),
+            owner: owner.map(str::to_string),
+            audio: None,
+        };
+        self.save(&job);
+        self.jobs.lock().unwrap().insert(id.clone(), job);
+
+        let jobs = Arc::clone(self);
+        tokio::spawn(async move {
+            jobs.update(&id, |job| job.status.status = JobState::Running);
+            let started = Instant::now();
+            let total = parts.len();
+            let mut raw_audio = Vec::new();
+            for (index, part) in parts.into_iter().enumerate() {
+                // The parts are leveled together once they are joined
+                let part_request = SynthRequest {
+                    text: part,
+                    loudness: None,
+                    ..request.clone()
+                };
+                let seconds_before = raw_audio.len() as f32 / tts.sample_rate() as f32;
+                let on_progress = {
+                    let (jobs, id) = (Arc::clone(&jobs), id.clone());
+                    move |progress: &SynthProgress| {
+                        let done = (index as f32 + progress.fraction()) / total as f32;
+                        let elapsed = started.elapsed().as_secs_f32();
+                        jobs.update(&id, |job| {
+                            job.status.progress = done;
+                            job.status.seconds_synthesized =
+                                seconds_before + progress.seconds_synthesized;
+                            job.status.eta_seconds =
+                                (done > 0.0).then(|| elapsed * (1.0 - done) / done);
+                        });
+                    }
+                };
+                match tts
+                    .synth_async_with_progress(part_request, on_progress)
+                    .await
+                {
+                    Ok(result) => raw_audio.extend(result.samples),
+                    Err(e) => {
+                        error!("Job {} failed: {}", id, e);
+                        jobs.update(&id, |job| {
+                            job.status.status = JobState::Failed;
+                            job.status.error = Some(e.to_string());
+                        });
+                        return;
+                    }
+                }
+                jobs.update(&id, |job| job.status.parts_done += 1);
+            }
+            if let Some(loudness) = request.loudness {
+                loudness::normalize(&mut raw_audio, tts.sample_rate(), loudness);
+            }
+            match encode_audio(&raw_audio, &format) {
+                Ok((content_type, audio_data, _)) => {
+                    info!("Job {} completed - {} bytes", id, audio_data.len());
+                    jobs.update(&id, |job| {
+                        job.status.status = JobState::Completed;
+                        job.status.progress = 1.0;
+                        job.status.eta_seconds = None;
+                        job.audio = Some((content_type, audio_data));
+                    });
+                }
+                Err(e) => {
+                    error!("Job {} failed: {}", id, e);
+                    jobs.update(&id, |job| {
+                        job.status.status = JobState::Failed;
+                        job.status.error = Some("the audio could not be encoded".to_string());
+                    });
+                }
+            }
+        });
+        status
+    }
+}
+
+fn not_found(id: &str) -> Response {
+    (StatusCode::NOT_FOUND, format!("no job '{}'", id)).into_response()
+}
+
+pub(crate) async fn handle_job(
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Extension(options): Extension<ServerOptions>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, SpeechError> {
+    // Another key's jobs are answered as if they didn't exist
+    let owner = tenant::key_name(&options, &headers)?;
+    Ok(match jobs.status(&id, owner.as_deref()) {
+        Some(status) => Json(status).into_response(),
+        None => not_found(&id),
+    })
+}
+
+pub(crate) async fn handle_job_content(
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Extension(options): Extension<ServerOptions>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, SpeechError> {
+    let owner = tenant::key_name(&options, &headers)?;
+    Ok(match jobs.content(&id, owner.as_deref()) {
+        Some(Ok((content_type, audio_data))) => {
+            ([(header::CONTENT_TYPE, content_type)], audio_data).into_response()
+        }
+        Some(Err(state)) => (
+            StatusCode::CONFLICT,
+            format!("job '{}' is {}, not completed", id, state.as_str()),
+        )
+            .into_response(),
+        None => not_found(&id),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_input_length;
+
+    #[test]
+    fn test_job_status() {
+        assert!(check_input_length("Hello", Some(5)).is_ok());
+        assert!(check_input_length("Hello!", Some(5)).is_err());
+        assert!(Jobs::default().status("job_missing", None).is_none());
+
+        let status = JobStatus {
+            id: "job_1".to_string(),
+            object: "audio.job",
+            status: JobState::Running,
+            created_at: 0,
+            parts: 3,
+            parts_done: 1,
+            progress: 0.5,
+            seconds_synthesized: 12.0,
+            eta_seconds: Some(3.0),
+            error: None,
+        };
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["status"], "running");
+        assert_eq!(json["eta_seconds"], 3.0);
+        assert!(json.get("error").is_none());
+        assert_eq!(JobState::Completed.as_str(), "completed");
+        assert_eq!(JobState::parse("failed"), Some(JobState::Failed));
+
+        // Only the key that submitted a job sees it
+        let jobs = Jobs::default();
+        jobs.jobs.lock().unwrap().insert(
+            status.id.clone(),
+            Job {
+                status,
+                owner: Some("acme".to_string()),
+                audio: Some(("audio/wav", b"RIFF".to_vec())),
+            },
+        );
+        assert!(jobs.status("job_1", Some("acme")).is_some());
+        assert!(jobs.status("job_1", Some("globex")).is_none());
+        assert!(jobs.status("job_1", None).is_none());
+        assert!(matches!(jobs.content("job_1", Some("acme")), Some(Ok(_))));
+        assert!(jobs.content("job_1", Some("globex")).is_none());
+    }
+}
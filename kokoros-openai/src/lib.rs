@@ -5,10 +5,17 @@
 //!
 //! ## Implemented Features
 //! - `/v1/audio/speech` - Text-to-speech generation with streaming support
-//! - `/v1/audio/voices` - List available voices
+//! - `/v1/audio/voices` - List available voices, or register a new one with `POST`
 //! - `/v1/models` - List available models (static dummy list)
 //! - Multiple audio formats: MP3, WAV, PCM, OPUS, AAC, FLAC
 //! - Streaming audio generation for low-latency responses
+//! - `/v1/audio/speech/webrtc` - Speech as a live WebRTC audio track (`webrtc` feature)
+//! - `/v1/audio/speech/ws` - Speech as low-bitrate Opus frames over a WebSocket
+//! - `/v1/audio/jobs/{id}` - Status and audio of long inputs synthesized in the background
+//! - `/v1/audio/speech/file` - Speech for an uploaded text, Markdown or EPUB file
+//! - `/admin/stats` - Queue depth, sessions, requests per voice, RTF and cache hits as JSON
+//! - `x-koko-*` headers with the latency, RTF, tokens and cache hit of each non-streaming
+//!   request, when `ServerOptions::metrics_headers` is set
 //!
 //! ## OpenAI API Compatibility Limitations
 //! - `return_download_link`: Not implemented (files are streamed directly)
@@ -18,22 +25,26 @@
 //! - `normalization_options`: Not implemented (basic text processing only)
 //! - Streaming only supports PCM format (other formats fall back to PCM)
 
-use std::error::Error;
-use std::io;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
 use std::time::Instant;
 
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
     body::Body,
-    extract::{Path, State},
-    http::{StatusCode, header},
+    extract::{DefaultBodyLimit, Path, State},
+    http::{Extensions, HeaderMap, StatusCode, Version, header},
     response::{IntoResponse, Response},
     routing::{get, post},
 };
 use futures::stream::StreamExt;
 use kokoros::{
-    tts::koko::{InitConfig as TTSKokoInitConfig, TTSKoko},
+    tts::koko::{InitConfig as TTSKokoInitConfig, KokorosError, Priority, TTSKoko},
+    tts::koko_async::SynthRequest,
+    tts::normalize::TextOptions,
+    tts::voices::VoiceRegistry,
+    utils::cache::{AudioCache, CacheKey},
     utils::mp3::pcm_to_mp3,
     utils::opus::pcm_to_opus_ogg,
     utils::wav::{WavHeader, write_audio_chunk},
@@ -41,10 +52,32 @@ use kokoros::{
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::compression::{
+    CompressionLayer,
+    predicate::{DefaultPredicate, Predicate},
+};
 use tower_http::cors::CorsLayer;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, trace};
 use uuid::Uuid;
 
+#[cfg(feature = "sqlite")]
+mod job_store;
+mod jobs;
+mod metrics;
+mod quota;
+mod stats;
+mod tenant;
+mod upload;
+mod voices;
+#[cfg(feature = "webrtc")]
+mod webrtc;
+mod ws;
+
+/// Synthesized chunks a streaming response holds for a client that hasn't read them yet;
+/// beyond that, synthesis waits for the client
+const STREAM_BUFFER_CHUNKS: usize = 4;
+
 /// Break words used for chunk splitting
 const BREAK_WORDS: &[&str] = &[
     "and", "or", "but", "&", "because", "if", "since", "though", "although", "however", "which",
@@ -311,10 +344,62 @@ impl Default for Voice {
 }
 
 impl Voice {
-    /// Get the Kokoro voice name, mapping from OpenAI voice names if necessary
-    fn to_kokoro_voice(&self) -> String {
-        map_openai_voice_to_kokoro(&self.0).to_string()
+    /// Get the Kokoro voice name, mapping from the server's aliases or OpenAI voice names
+    /// if necessary
+    fn to_kokoro_voice(&self, options: &ServerOptions) -> String {
+        match options.voice_aliases.get().get(&self.0) {
+            Some(voice) => voice.clone(),
+            None => map_openai_voice_to_kokoro(&self.0).to_string(),
+        }
+    }
+}
+
+/// The languages of an `Accept-Language` header, most preferred first, without the ones
+/// refused with `q=0` or the `*` wildcard
+fn accepted_languages(header: &str) -> Vec<&str> {
+    let mut languages: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let language = parts.next().filter(|tag| !tag.is_empty() && *tag != "*")?;
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(1.0, |q| q.parse().unwrap_or(0.0));
+            (quality > 0.0).then_some((language, quality))
+        })
+        .collect();
+    // Stable, so languages of equal quality stay in the order given
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages
+        .into_iter()
+        .map(|(language, _)| language)
+        .collect()
+}
+
+/// The Kokoro voice and espeak-ng language for a request: the voice it names (read as
+/// English, as ever), or else the best voice for its `Accept-Language` in the language it
+/// was trained on, or else the default voice
+fn request_voice(
+    voice: Option<Voice>,
+    headers: &HeaderMap,
+    options: &ServerOptions,
+) -> (String, String) {
+    const ENGLISH: &str = "en-us";
+    if let Some(voice) = voice {
+        return (voice.to_kokoro_voice(options), ENGLISH.to_string());
     }
+    let default = Voice::default().to_kokoro_voice(options);
+    let chosen = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .zip(options.voice_registry.as_deref())
+        .and_then(|(header, registry)| {
+            accepted_languages(header).into_iter().find_map(|language| {
+                let voice = registry.voice_for_language(language, &default)?;
+                Some((voice.to_string(), registry.get(voice)?.lan.clone()))
+            })
+        });
+    chosen.unwrap_or_else(|| (default, ENGLISH.to_string()))
 }
 
 #[derive(Deserialize)]
@@ -334,8 +419,9 @@ struct SpeechRequest {
 
     input: String,
 
+    /// Chosen from the `Accept-Language` header when not given
     #[serde(default)]
-    voice: Voice,
+    voice: Option<Voice>,
 
     #[serde(default)]
     response_format: AudioFormat,
@@ -350,6 +436,16 @@ struct SpeechRequest {
     #[serde(default)]
     stream: Option<bool>,
 
+    /// `interactive` (default) or `batch`; batch requests yield to interactive ones
+    /// between chunks. Inputs split into jobs are batch unless they say otherwise.
+    #[serde(default)]
+    priority: Option<Priority>,
+
+    /// Integrated loudness in LUFS to level the audio to, e.g. -16 for podcasts or -23
+    /// for broadcast; streamed audio is sent as synthesized
+    #[serde(default)]
+    loudness: Option<f32>,
+
     // OpenAI API compatibility parameters - accepted but not implemented
     // These fields ensure request parsing compatibility with OpenAI clients
     /// Return download link after generation (not implemented)
@@ -384,9 +480,10 @@ struct TTSTask {
     id: usize,
     chunk: String,
     voice: String,
+    lan: String,
     speed: f32,
     initial_silence: Option<usize>,
-    result_tx: mpsc::UnboundedSender<(usize, Vec<u8>)>,
+    result_tx: mpsc::Sender<(usize, Vec<u8>)>,
 }
 
 /// Streaming session manager
@@ -441,7 +538,84 @@ struct ModelsResponse {
     data: Vec<ModelObject>,
 }
 
+/// A setting shared by every request that a config reload can swap for another; requests
+/// already running keep the one they started with
+pub struct Swappable<T>(Arc<RwLock<Arc<T>>>);
+
+impl<T> Swappable<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(value))))
+    }
+
+    /// The value in use
+    pub fn get(&self) -> Arc<T> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Uses `value` from now on, for every clone
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(value);
+    }
+}
+
+impl<T> Clone for Swappable<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T: Default> Default for Swappable<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Settings for the endpoints that go beyond the OpenAI API
+#[derive(Clone, Default)]
+pub struct ServerOptions {
+    /// Bearer token required by `POST /v1/audio/voices`; uploads are refused without one
+    pub api_key: Option<String>,
+    /// Where uploaded voices are saved, so they are loaded again on the next start
+    pub voices_dir: Option<PathBuf>,
+    /// Encoded audio of earlier non-streaming requests, returned again for identical ones
+    pub cache: Option<Arc<AudioCache>>,
+    /// Longest input in characters a speech request may have; longer ones are refused
+    /// with a 413
+    pub max_input_chars: Option<usize>,
+    /// Accept non-streaming requests over `max_input_chars` as a job instead, split into
+    /// parts within the limit
+    pub split_long_inputs: bool,
+    /// Metadata of the voices, for choosing one from `Accept-Language` when a request
+    /// doesn't name one; built from the voice names if not given
+    pub voice_registry: Option<Arc<VoiceRegistry>>,
+    /// Where background jobs are kept; in memory, for as long as the server runs, if not
+    /// given
+    pub jobs: Option<Arc<Jobs>>,
+    /// API keys that speech requests must use, with their character quotas; requests need
+    /// no key if not given
+    pub quotas: Option<Arc<Quotas>>,
+    /// Voices, lexicons and voice packs of some of the keys in `quotas`, by key name
+    pub tenants: Swappable<HashMap<String, Tenant>>,
+    /// Names requests may give for voices or blends, looked up before the OpenAI voices
+    pub voice_aliases: Swappable<HashMap<String, String>>,
+    /// Version of the model, part of every cache key and ETag so neither outlives a model
+    /// swap; see `kokoros::utils::checksum::model_version`
+    pub model_version: String,
+    /// Add `x-koko-*` headers with what each non-streaming request took to its response
+    pub metrics_headers: bool,
+}
+
 pub async fn create_server(tts_instances: Vec<TTSKoko>) -> Router {
+    create_server_with_options(tts_instances, ServerOptions::default()).await
+}
+
+pub async fn create_server_with_options(
+    tts_instances: Vec<TTSKoko>,
+    mut options: ServerOptions,
+) -> Router {
     info!("Starting TTS server with {} instances", tts_instances.len());
 
     // Use first instance for compatibility with non-streaming endpoints
@@ -449,56 +623,160 @@ pub async fn create_server(tts_instances: Vec<TTSKoko>) -> Router {
         .first()
         .cloned()
         .expect("At least one TTS instance required");
+    options.voice_registry.get_or_insert_with(|| {
+        Arc::new(VoiceRegistry::from_names(
+            &tts_single.get_available_voices(),
+        ))
+    });
 
-    Router::new()
+    let jobs = options.jobs.clone().unwrap_or_default();
+
+    let router = Router::new()
         .route("/", get(handle_home))
         .route("/v1/audio/speech", post(handle_tts))
-        .route("/v1/audio/voices", get(handle_voices))
+        .route("/v1/audio/speech/ws", get(ws::handle_ws))
+        .route(
+            "/v1/audio/speech/file",
+            post(upload::handle_speech_file).layer(DefaultBodyLimit::max(upload::MAX_UPLOAD_BYTES)),
+        )
+        .route(
+            "/v1/audio/voices",
+            get(handle_voices).post(voices::handle_add_voice),
+        )
+        .route("/v1/audio/jobs/{id}", get(jobs::handle_job))
+        .route("/v1/audio/jobs/{id}/content", get(jobs::handle_job_content))
         .route("/v1/models", get(handle_models))
         .route("/v1/models/{model}", get(handle_model))
+        .route("/admin/stats", get(stats::handle_stats));
+    #[cfg(feature = "webrtc")]
+    let router = router.route("/v1/audio/speech/webrtc", post(webrtc::handle_webrtc));
+
+    router
+        .layer(CatchPanicLayer::custom(handle_panic))
+        .layer(CompressionLayer::new().compress_when(DefaultPredicate::new().and(is_text)))
         .layer(axum::middleware::from_fn(request_id_middleware))
         .layer(CorsLayer::permissive())
+        .layer(Extension(options))
+        .layer(Extension(jobs))
+        .layer(Extension(Arc::new(stats::Stats::new())))
         .with_state((tts_single, tts_instances))
 }
 
+/// Whether a response is JSON or text, the only kinds worth compressing: audio is
+/// compressed already (or, as PCM, streamed), and WebSocket upgrades have no body
+fn is_text(_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| {
+            content_type.starts_with("application/json") || content_type.starts_with("text/")
+        })
+}
+
 pub use axum::serve;
+pub use jobs::Jobs;
+pub use quota::{ApiKey, Quotas};
+pub use tenant::Tenant;
 
+/// A failed speech request, answered with a status code that fits its cause
 #[derive(Debug)]
 enum SpeechError {
-    // Deciding to modify this example in order to see errors
-    // (e.g. with tracing) is up to the developer
-    #[allow(dead_code)]
-    Koko(Box<dyn Error>),
+    /// Something wrong with the request itself, e.g. malformed JSON or nothing to say
+    BadRequest(String),
 
-    #[allow(dead_code)]
-    Header(io::Error),
+    /// An input over the server's limit
+    TooLong(String),
 
-    #[allow(dead_code)]
-    Chunk(io::Error),
+    /// No API key, or not one of the server's
+    Unauthorized(String),
 
-    #[allow(dead_code)]
-    Mp3Conversion(std::io::Error),
+    /// A request for something its key may not use
+    Forbidden(String),
 
-    #[allow(dead_code)]
-    OpusConversion(std::io::Error),
+    /// A request that would take its key over a character quota
+    OverQuota(String),
+
+    Koko(KokorosError),
+
+    #[cfg(feature = "webrtc")]
+    WebRtc(::webrtc::Error),
+}
+
+impl SpeechError {
+    fn encode(e: impl std::fmt::Display) -> Self {
+        Self::Koko(KokorosError::Encode(e.to_string()))
+    }
+}
+
+impl From<KokorosError> for SpeechError {
+    fn from(e: KokorosError) -> Self {
+        Self::Koko(e)
+    }
 }
 
 impl std::fmt::Display for SpeechError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            SpeechError::BadRequest(e) => write!(f, "Bad request: {}", e),
+            SpeechError::TooLong(e) => write!(f, "Input too long: {}", e),
+            SpeechError::Unauthorized(e) => write!(f, "Unauthorized: {}", e),
+            SpeechError::Forbidden(e) => write!(f, "Forbidden: {}", e),
+            SpeechError::OverQuota(e) => write!(f, "Over quota: {}", e),
             SpeechError::Koko(e) => write!(f, "Koko TTS error: {}", e),
-            SpeechError::Header(e) => write!(f, "Header error: {}", e),
-            SpeechError::Chunk(e) => write!(f, "Chunk error: {}", e),
-            SpeechError::Mp3Conversion(e) => write!(f, "MP3 conversion error: {}", e),
-            SpeechError::OpusConversion(e) => write!(f, "Opus conversion error: {}", e),
+            #[cfg(feature = "webrtc")]
+            SpeechError::WebRtc(e) => write!(f, "WebRTC error: {}", e),
         }
     }
 }
 
 impl IntoResponse for SpeechError {
     fn into_response(self) -> Response {
-        // None of these errors make sense to expose to the user of the API
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        match self {
+            // The client can fix these, so they are told what is wrong
+            SpeechError::BadRequest(message) => (StatusCode::BAD_REQUEST, message).into_response(),
+            SpeechError::TooLong(message) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, message).into_response()
+            }
+            SpeechError::Unauthorized(message) => {
+                (StatusCode::UNAUTHORIZED, message).into_response()
+            }
+            SpeechError::Forbidden(message) => (StatusCode::FORBIDDEN, message).into_response(),
+            SpeechError::OverQuota(message) => {
+                (StatusCode::TOO_MANY_REQUESTS, message).into_response()
+            }
+            SpeechError::Koko(e @ KokorosError::InvalidVoice(_)) => {
+                (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+            }
+            // None of the others make sense to expose to the user of the API
+            e => {
+                error!("{}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+/// Answers a request whose handler panicked, e.g. on a pathological input, with a 500, so
+/// the panic only fails that request rather than the server
+fn handle_panic(panic: Box<dyn std::any::Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    error!("Request handler panicked: {}", message);
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+/// Refuses inputs over `limit` characters, stating the limit
+fn check_input_length(input: &str, limit: Option<usize>) -> Result<(), SpeechError> {
+    let length = input.chars().count();
+    match limit {
+        Some(limit) if length > limit => Err(SpeechError::TooLong(format!(
+            "input is {} characters, more than the limit of {}",
+            length, limit
+        ))),
+        _ => Ok(()),
     }
 }
 
@@ -521,17 +799,27 @@ async fn handle_tts(
     // OpenAI TTS always streams by default - client decides how to consume
     // Only send complete file when explicitly requested via stream: false
 
+    let options = request
+        .extensions()
+        .get::<ServerOptions>()
+        .cloned()
+        .unwrap_or_default();
+    let jobs = request.extensions().get::<Arc<jobs::Jobs>>().cloned();
+    let stats = request.extensions().get::<Arc<stats::Stats>>().cloned();
+    let cache = options.cache.clone();
+    let headers = request.headers().clone();
+
     // Parse the JSON body
     let bytes = axum::body::to_bytes(request.into_body(), usize::MAX)
         .await
         .map_err(|e| {
             error!("Error reading request body: {:?}", e);
-            SpeechError::Mp3Conversion(std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+            SpeechError::BadRequest(e.to_string())
         })?;
 
     let speech_request: SpeechRequest = serde_json::from_slice(&bytes).map_err(|e| {
         error!("JSON parsing error: {:?}", e);
-        SpeechError::Mp3Conversion(std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+        SpeechError::BadRequest(e.to_string())
     })?;
 
     let SpeechRequest {
@@ -541,11 +829,25 @@ async fn handle_tts(
         speed: Speed(speed),
         initial_silence,
         stream,
+        priority,
+        loudness,
         ..
     } = speech_request;
 
-    // Map OpenAI voice names to Kokoro voice names
-    let voice = voice.to_kokoro_voice();
+    if input.trim().is_empty() {
+        return Err(SpeechError::BadRequest("input is empty".to_string()));
+    }
+
+    let tenant = tenant::authorize(&options, &headers)?;
+    let tenant = tenant.as_ref();
+    let (voice, lan) = tenant::tenant_voice(voice, &headers, &options, tenant)?;
+    let (tts_single, tts_instances) = match tenant {
+        Some(tenant) => tenant.engines(tts_single, tts_instances),
+        None => (tts_single, tts_instances),
+    };
+    if let Some(stats) = &stats {
+        stats.request(&voice);
+    }
 
     // OpenAI-compliant behavior: Stream by default, only send complete file if stream: false
     let should_stream = stream.unwrap_or(false); // Default to not streaming
@@ -556,56 +858,192 @@ async fn handle_tts(
         colored_request_id, stream, should_stream
     );
 
+    if let Err(e) = check_input_length(&input, options.max_input_chars) {
+        if options.split_long_inputs
+            && !should_stream
+            && let Some(jobs) = jobs
+        {
+            quota::charge(&options, &headers, &input)?;
+            let job = jobs.submit(
+                tts_single,
+                SynthRequest {
+                    lan,
+                    speed,
+                    initial_silence,
+                    request_id: Some(request_id.clone()),
+                    priority: priority.unwrap_or(Priority::Batch),
+                    loudness,
+                    ..SynthRequest::new(input, voice)
+                },
+                response_format,
+                options.max_input_chars.unwrap_or_default(),
+                tenant::key_name(&options, &headers)?.as_deref(),
+            );
+            info!(
+                "{} Input too long, accepted as {} in {} parts",
+                colored_request_id, job.id, job.parts
+            );
+            let location = format!("/v1/audio/jobs/{}", job.id);
+            return Ok((
+                StatusCode::ACCEPTED,
+                [(header::LOCATION, location)],
+                Json(job),
+            )
+                .into_response());
+        }
+        return Err(e);
+    }
+    quota::charge(&options, &headers, &input)?;
+
+    let priority = priority.unwrap_or_default();
     if should_stream {
-        return handle_tts_streaming(
-            tts_instances,
-            input,
-            voice,
-            response_format,
+        let tts_instances = tts_instances
+            .iter()
+            .map(|instance| instance.with_priority(priority))
+            .collect();
+        let request = SynthRequest {
+            lan,
             speed,
             initial_silence,
-            request_id,
-            request_start,
-        )
-        .await;
+            request_id: Some(request_id),
+            ..SynthRequest::new(input, voice)
+        };
+        return handle_tts_streaming(tts_instances, request, response_format, request_start).await;
     }
 
-    // Non-streaming mode (existing implementation)
-    let raw_audio = tts_single
-        .tts_raw_audio(
-            &input,
-            "en-us",
-            &voice,
+    let format = format!("{:?}", response_format).to_lowercase();
+    let scope = cache_scope(tts_single.text_options(), tenant);
+    let cache_key = CacheKey {
+        text: &input,
+        voice: &voice,
+        lan: &lan,
+        speed,
+        initial_silence,
+        format: &format,
+        loudness,
+        model: &options.model_version,
+        scope: &scope,
+    };
+    // With a cache, the audio is tagged by its key, which is all it depends on, so a client
+    // holding the audio of an identical request needn't be sent it again
+    let etag = cache
+        .as_ref()
+        .map(|_| format!("\"{}\"", cache_key.digest()));
+    if let Some(etag) = &etag
+        && headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|tags| matches_etag(tags, etag))
+    {
+        info!("{} TTS not modified", colored_request_id);
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag.clone())]).into_response());
+    }
+    let cached = cache.as_ref().and_then(|cache| cache.get(&cache_key));
+    if let (Some(stats), Some(_)) = (&stats, &cache) {
+        stats.cache_lookup(cached.is_some());
+    }
+    let cache_hit = cache.as_ref().map(|_| cached.is_some());
+    if let Some(audio_data) = cached {
+        info!(
+            "{} TTS served from cache - {} bytes, {} format",
+            colored_request_id,
+            audio_data.len(),
+            format
+        );
+        let mut response = audio_response(
+            content_type(&response_format),
+            audio_data.to_vec(),
+            etag.as_deref(),
+        )?;
+        if options.metrics_headers {
+            metrics::RequestMetrics {
+                latency: request_start.elapsed(),
+                cache_hit,
+                ..Default::default()
+            }
+            .insert_headers(response.headers_mut());
+        }
+        return Ok(response);
+    }
+    // Non-streaming mode: synthesize on the blocking pool, so long texts don't hold up
+    // this worker thread. Synthesis stops if the client disconnects.
+    let synthesis_start = Instant::now();
+    let result = tts_single
+        .synth_async(SynthRequest {
+            lan: lan.clone(),
             speed,
             initial_silence,
-            Some(&request_id),
-            Some("00"),
-            None,
-        )
-        .map_err(SpeechError::Koko)?;
+            request_id: Some(request_id.clone()),
+            priority,
+            loudness,
+            ..SynthRequest::new(input.clone(), voice.clone())
+        })
+        .await?;
+    let synthesis = synthesis_start.elapsed();
+    if let Some(stats) = &stats {
+        stats.synthesized(result.samples.len(), synthesis);
+    }
+    let raw_audio = result.samples;
+
+    let encode_start = Instant::now();
+    let (content_type, audio_data, format_name) = encode_audio(&raw_audio, &response_format)?;
+    trace!(
+        "[{}] encoding {}: {:.1} ms",
+        request_id,
+        format_name,
+        encode_start.elapsed().as_secs_f64() * 1000.0
+    );
+
+    let colored_request_id = get_colored_request_id_with_relative(&request_id, request_start);
+    info!(
+        "{} TTS non-streaming completed - {} bytes, {} format",
+        colored_request_id,
+        audio_data.len(),
+        format_name
+    );
+
+    if let Some(Err(e)) = cache.map(|cache| cache.insert(&cache_key, audio_data.clone())) {
+        error!("{} Could not cache the audio: {}", colored_request_id, e);
+    }
+    let mut response = audio_response(content_type, audio_data, etag.as_deref())?;
+    if options.metrics_headers {
+        metrics::RequestMetrics {
+            latency: request_start.elapsed(),
+            synthesis: Some(synthesis),
+            audio_seconds: Some(result.duration.as_secs_f32()),
+            tokens: Some(result.chunks.iter().map(|chunk| chunk.tokens).sum()),
+            cache_hit,
+        }
+        .insert_headers(response.headers_mut());
+    }
+    Ok(response)
+}
 
+/// `raw_audio` encoded as `format`, with its content type and the format's name
+fn encode_audio(
+    raw_audio: &[f32],
+    format: &AudioFormat,
+) -> Result<(&'static str, Vec<u8>, &'static str), SpeechError> {
     let sample_rate = TTSKokoInitConfig::default().sample_rate;
 
-    let (content_type, audio_data, format_name) = match response_format {
+    Ok(match format {
         AudioFormat::Wav => {
             let mut wav_data = Vec::default();
             let header = WavHeader::new(1, sample_rate, 32);
             header
                 .write_header(&mut wav_data)
-                .map_err(SpeechError::Header)?;
-            write_audio_chunk(&mut wav_data, &raw_audio).map_err(SpeechError::Chunk)?;
+                .map_err(SpeechError::encode)?;
+            write_audio_chunk(&mut wav_data, raw_audio).map_err(SpeechError::encode)?;
 
             ("audio/wav", wav_data, "WAV")
         }
         AudioFormat::Opus => {
-            let opus_data = pcm_to_opus_ogg(&raw_audio, sample_rate)
-                .map_err(|e| SpeechError::OpusConversion(e))?;
+            let opus_data = pcm_to_opus_ogg(raw_audio, sample_rate).map_err(SpeechError::encode)?;
 
             ("audio/opus", opus_data, "OPUS")
         }
         AudioFormat::Mp3 => {
-            let mp3_data =
-                pcm_to_mp3(&raw_audio, sample_rate).map_err(|e| SpeechError::Mp3Conversion(e))?;
+            let mp3_data = pcm_to_mp3(raw_audio, sample_rate).map_err(SpeechError::encode)?;
 
             ("audio/mpeg", mp3_data, "MP3")
         }
@@ -613,7 +1051,7 @@ async fn handle_tts(
             // For PCM, we return the raw audio data directly
             // Convert f32 samples to 16-bit PCM
             let mut pcm_data = Vec::with_capacity(raw_audio.len() * 2);
-            for sample in raw_audio {
+            for &sample in raw_audio {
                 let pcm_sample = (sample * 32767.0).clamp(-32768.0, 32767.0) as i16;
                 pcm_data.extend_from_slice(&pcm_sample.to_le_bytes());
             }
@@ -621,27 +1059,62 @@ async fn handle_tts(
         }
         // For now, unsupported formats fall back to MP3
         _ => {
-            let mp3_data =
-                pcm_to_mp3(&raw_audio, sample_rate).map_err(|e| SpeechError::Mp3Conversion(e))?;
+            let mp3_data = pcm_to_mp3(raw_audio, sample_rate).map_err(SpeechError::encode)?;
 
             ("audio/mpeg", mp3_data, "MP3")
         }
-    };
+    })
+}
 
-    let colored_request_id = get_colored_request_id_with_relative(&request_id, request_start);
-    info!(
-        "{} TTS non-streaming completed - {} bytes, {} format",
-        colored_request_id,
-        audio_data.len(),
-        format_name
-    );
+/// Content type of the non-streaming response for `format`
+fn content_type(format: &AudioFormat) -> &'static str {
+    match format {
+        AudioFormat::Wav => "audio/wav",
+        AudioFormat::Opus => "audio/opus",
+        AudioFormat::Pcm => "audio/pcm",
+        // Unsupported formats fall back to MP3
+        _ => "audio/mpeg",
+    }
+}
 
-    Ok(Response::builder()
-        .header(header::CONTENT_TYPE, content_type)
+fn audio_response(
+    content_type: &str,
+    audio_data: Vec<u8>,
+    etag: Option<&str>,
+) -> Result<Response, SpeechError> {
+    let mut response = Response::builder().header(header::CONTENT_TYPE, content_type);
+    if let Some(etag) = etag {
+        response = response.header(header::ETAG, etag);
+    }
+    response
         .body(audio_data.into())
-        .map_err(|e| {
-            SpeechError::Mp3Conversion(std::io::Error::new(std::io::ErrorKind::Other, e))
-        })?)
+        .map_err(SpeechError::encode)
+}
+
+/// The token of an `Authorization: Bearer` header
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// What cached audio depends on besides the request: the engines' text rewrites, with the
+/// replacement rules in use now as they change on reload, and the tenant's lexicon and voices
+fn cache_scope(text_options: &TextOptions, tenant: Option<&Tenant>) -> String {
+    format!(
+        "{}\0{}",
+        text_options.digest(),
+        tenant.map_or("", Tenant::scope)
+    )
+}
+
+/// Whether an `If-None-Match` header lists `etag`, weakly compared as the header asks
+fn matches_etag(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag)
 }
 
 /// Handle streaming TTS requests with true async processing
@@ -650,14 +1123,20 @@ async fn handle_tts(
 /// Maintains speech order while allowing out-of-order chunk completion.
 async fn handle_tts_streaming(
     tts_instances: Vec<TTSKoko>,
-    input: String,
-    voice: String,
+    request: SynthRequest,
     response_format: AudioFormat,
-    speed: f32,
-    initial_silence: Option<usize>,
-    request_id: String,
     request_start: Instant,
 ) -> Result<Response, SpeechError> {
+    let SynthRequest {
+        text: input,
+        lan,
+        style: voice,
+        speed,
+        initial_silence,
+        request_id,
+        ..
+    } = request;
+    let request_id = request_id.unwrap_or_default();
     // Streaming implementation: PCM format for optimal performance
     let content_type = match response_format {
         AudioFormat::Pcm => "audio/pcm",
@@ -683,15 +1162,14 @@ async fn handle_tts_streaming(
     );
 
     if chunks.is_empty() {
-        return Err(SpeechError::Mp3Conversion(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "No text to process",
-        )));
+        return Err(SpeechError::BadRequest("No text to process".to_string()));
     }
 
     // Create channels for sequential chunk processing
     let (task_tx, mut task_rx) = mpsc::unbounded_channel::<TTSTask>();
-    let (audio_tx, audio_rx) = mpsc::unbounded_channel::<(usize, Vec<u8>)>(); // Tag chunks with order ID
+    // Tag chunks with order ID. Bounded, so a client reading slower than the chunks are
+    // synthesized holds up synthesis instead of letting the audio pile up in memory
+    let (audio_tx, audio_rx) = mpsc::channel::<(usize, Vec<u8>)>(STREAM_BUFFER_CHUNKS);
 
     // Track total bytes transferred
     let total_bytes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
@@ -714,6 +1192,7 @@ async fn handle_tts_streaming(
             id,
             chunk,
             voice: voice.clone(),
+            lan: lan.clone(),
             speed,
             initial_silence: if id == 0 { initial_silence } else { None },
             result_tx: audio_tx.clone(),
@@ -758,6 +1237,7 @@ async fn handle_tts_streaming(
                             worker_pool_clone.get_instance(chunk_counter);
                         let chunk_text = task.chunk.clone();
                         let voice = task.voice.clone();
+                        let lan = task.lan.clone();
                         let speed = task.speed;
                         let initial_silence = task.initial_silence;
                         let chunk_num = chunk_counter;
@@ -773,7 +1253,7 @@ async fn handle_tts_streaming(
                             let result = tokio::task::spawn_blocking(move || {
                                 let audio_result = tts_instance.tts_raw_audio(
                                     &chunk_text,
-                                    "en-us",
+                                    &lan,
                                     &voice,
                                     speed,
                                     initial_silence,
@@ -827,7 +1307,11 @@ async fn handle_tts_streaming(
                 if handle.is_finished() {
                     match handle.await {
                         Ok(Ok((task_id, pcm_data))) => {
-                            if let Err(_) = audio_tx_clone.send((task_id, pcm_data)) {
+                            // Empty data ends the stream, and a chunk with nothing to say
+                            // mustn't do that early; the end is signalled once all are sent
+                            if !pcm_data.is_empty()
+                                && audio_tx_clone.send((task_id, pcm_data)).await.is_err()
+                            {
                                 break;
                             }
                             next_to_send += 1;
@@ -903,7 +1387,9 @@ async fn handle_tts_streaming(
             // Only send chunks that are in the expected sequence (>= next_to_send)
             // This prevents duplicate sends while ensuring no valid chunks are skipped
             if chunk_id >= next_to_send {
-                let _ = audio_tx_clone.send((task_id, pcm_data));
+                if !pcm_data.is_empty() {
+                    let _ = audio_tx_clone.send((task_id, pcm_data)).await;
+                }
                 chunks_processed += 1;
             }
         }
@@ -922,13 +1408,13 @@ async fn handle_tts_streaming(
         );
 
         // Send termination signal
-        let _ = audio_tx.send((total_chunks, vec![])); // Empty data as termination signal
+        let _ = audio_tx.send((total_chunks, vec![])).await; // Empty data as termination signal
     });
 
     // No ordering needed - sequential processing guarantees order
 
     // Create immediate streaming - chunks are already sent in order from TTS processing
-    let stream = tokio_stream::wrappers::UnboundedReceiverStream::new(audio_rx)
+    let stream = tokio_stream::wrappers::ReceiverStream::new(audio_rx)
         .map(|(_chunk_id, data)| -> Result<Vec<u8>, std::io::Error> {
             // Check for termination signal (empty data)
             if data.is_empty() {
@@ -955,14 +1441,22 @@ async fn handle_tts_streaming(
         .header("Transfer-Encoding", "chunked") // Enable HTTP chunked transfer encoding
         .header("Access-Control-Allow-Origin", "*") // CORS for browser clients
         .body(body)
-        .map_err(|e| {
-            SpeechError::Mp3Conversion(std::io::Error::new(std::io::ErrorKind::Other, e))
-        })?)
+        .map_err(SpeechError::encode)?)
 }
 
 async fn handle_voices(
-    State((tts_single, _tts_instances)): State<(TTSKoko, Vec<TTSKoko>)>,
+    State((tts_single, tts_instances)): State<(TTSKoko, Vec<TTSKoko>)>,
+    Extension(options): Extension<ServerOptions>,
+    headers: HeaderMap,
 ) -> Json<VoicesResponse> {
+    // A tenant sees its own voice pack and only the voices it may use; a request without
+    // a valid key is still shown the server's
+    let tenant = tenant::authorize(&options, &headers).ok().flatten();
+    let tenant = tenant.as_ref();
+    let tts_single = match tenant {
+        Some(tenant) => tenant.engines(tts_single, tts_instances).0,
+        None => tts_single,
+    };
     let mut voices = tts_single.get_available_voices();
 
     // Add OpenAI voice names for compatibility
@@ -983,6 +1477,9 @@ async fn handle_voices(
     ];
 
     voices.extend(openai_voices);
+    if let Some(tenant) = tenant {
+        voices.retain(|voice| tenant.allows(map_openai_voice_to_kokoro(voice)));
+    }
     Json(VoicesResponse { voices })
 }
 
@@ -1091,3 +1588,50 @@ async fn request_id_middleware(
 
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_etag() {
+        assert!(matches_etag(r#"W/"a1", "b2""#, r#""b2""#));
+        assert!(matches_etag(r#""b2""#, r#""b2""#));
+        assert!(!matches_etag(r#""a1""#, r#""b2""#));
+    }
+
+    #[test]
+    fn test_cache_scope() {
+        let text_options = TextOptions::default();
+        let scope = cache_scope(&text_options, None);
+        assert_eq!(cache_scope(&text_options.clone(), None), scope);
+        let code = TextOptions {
+            verbalize_code: true,
+            ..text_options.clone()
+        };
+        assert_ne!(cache_scope(&code, None), scope);
+
+        // Rules swapped in on reload change the scope of every clone
+        let reloaded = text_options.clone();
+        text_options.replacements.set(vec![
+            kokoros::tts::normalize::Replacement::new("JIRA", "ticket").unwrap(),
+        ]);
+        assert_ne!(cache_scope(&reloaded, None), scope);
+    }
+
+    #[test]
+    fn test_is_text() {
+        let headers = |content_type: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+            headers
+        };
+        let is_text = |headers: &HeaderMap| {
+            is_text(StatusCode::OK, Version::HTTP_2, headers, &Extensions::new())
+        };
+        assert!(is_text(&headers("application/json")));
+        assert!(is_text(&headers("text/html; charset=utf-8")));
+        assert!(!is_text(&headers("audio/mpeg")));
+        assert!(!is_text(&HeaderMap::new()));
+    }
+}
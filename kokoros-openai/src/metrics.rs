@@ -0,0 +1,94 @@
+//! `x-koko-*` headers on non-streaming speech responses, with how long the request took and
+//! what it cost, so clients can watch the quality of service call by call
+//!
+//! Streamed responses go without them, as their headers are sent before synthesis starts.
+
+use std::time::Duration;
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+/// What a speech request took
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RequestMetrics {
+    /// From the request arriving until its audio was encoded
+    pub latency: Duration,
+    /// Time spent in the model, for audio that wasn't cached
+    pub synthesis: Option<Duration>,
+    /// Seconds of audio, for audio that wasn't cached
+    pub audio_seconds: Option<f32>,
+    /// Tokens the model read, for audio that wasn't cached
+    pub tokens: Option<usize>,
+    /// Whether the audio came from the cache, when the server has one
+    pub cache_hit: Option<bool>,
+}
+
+impl RequestMetrics {
+    /// Synthesis time over audio duration, below 1 when faster than real time
+    pub fn rtf(&self) -> Option<f32> {
+        let (synthesis, audio_seconds) = (self.synthesis?, self.audio_seconds?);
+        (audio_seconds > 0.0).then(|| synthesis.as_secs_f32() / audio_seconds)
+    }
+
+    /// Adds the metrics known to `headers`
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        let mut insert = |name: &'static str, value: String| {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        };
+        insert(
+            "x-koko-latency-ms",
+            format!("{:.1}", self.latency.as_secs_f64() * 1000.0),
+        );
+        if let Some(synthesis) = self.synthesis {
+            insert(
+                "x-koko-synthesis-ms",
+                format!("{:.1}", synthesis.as_secs_f64() * 1000.0),
+            );
+        }
+        if let Some(audio_seconds) = self.audio_seconds {
+            insert("x-koko-audio-seconds", format!("{:.3}", audio_seconds));
+        }
+        if let Some(rtf) = self.rtf() {
+            insert("x-koko-rtf", format!("{:.3}", rtf));
+        }
+        if let Some(tokens) = self.tokens {
+            insert("x-koko-tokens", tokens.to_string());
+        }
+        if let Some(hit) = self.cache_hit {
+            insert("x-koko-cache", if hit { "hit" } else { "miss" }.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_headers() {
+        let metrics = RequestMetrics {
+            latency: Duration::from_millis(250),
+            synthesis: Some(Duration::from_millis(200)),
+            audio_seconds: Some(2.0),
+            tokens: Some(42),
+            cache_hit: Some(false),
+        };
+        let mut headers = HeaderMap::new();
+        metrics.insert_headers(&mut headers);
+        assert_eq!(headers["x-koko-latency-ms"], "250.0");
+        assert_eq!(headers["x-koko-rtf"], "0.100");
+        assert_eq!(headers["x-koko-tokens"], "42");
+        assert_eq!(headers["x-koko-cache"], "miss");
+
+        // Cached audio has no synthesis to report
+        let mut headers = HeaderMap::new();
+        RequestMetrics {
+            cache_hit: Some(true),
+            ..Default::default()
+        }
+        .insert_headers(&mut headers);
+        assert_eq!(headers["x-koko-cache"], "hit");
+        assert!(headers.get("x-koko-rtf").is_none());
+    }
+}
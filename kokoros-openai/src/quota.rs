@@ -0,0 +1,331 @@
+//! API keys with daily and monthly character quotas, for servers shared with other teams
+//!
+//! With keys configured, every speech request needs one as a bearer token, and the
+//! characters of its input count against the key's quotas when it is accepted. A request
+//! that would go over one is refused with a 429 saying which quota and when it resets.
+//! Usage is tracked per UTC day and month, and saved to a file so restarts don't reset it:
+//! by a background thread at most once a second, and on [`Quotas::flush`] when the server
+//! stops, so requests never wait on the disk.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{ServerOptions, SpeechError, bearer_token};
+
+/// A key allowed to make speech requests, and how much it may ask for
+#[derive(Debug, Clone, Default)]
+pub struct ApiKey {
+    /// The bearer token
+    pub key: String,
+    /// Characters of input per UTC day; unlimited if `None`
+    pub daily_chars: Option<u64>,
+    /// Characters of input per UTC calendar month; unlimited if `None`
+    pub monthly_chars: Option<u64>,
+}
+
+/// Characters used by a key in the current day and month
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+struct Usage {
+    /// Days since the Unix epoch
+    day: u64,
+    day_chars: u64,
+    /// Months since the start of year 0
+    month: u64,
+    month_chars: u64,
+}
+
+/// How long usage may go unsaved, which batches the writes of a busy server
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Usage by key name, and where it is saved
+struct UsageLog {
+    usage: Mutex<HashMap<String, Usage>>,
+    /// Where usage is saved, as JSON by key name
+    file: Option<PathBuf>,
+    /// Whether usage changed since it was last saved
+    unsaved: AtomicBool,
+    /// Held while writing the file, so two saves don't interleave
+    writing: Mutex<()>,
+}
+
+impl UsageLog {
+    /// Writes the usage to the file if it changed since it was last saved
+    fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let _writing = self.writing.lock().unwrap_or_else(PoisonError::into_inner);
+        if !self.unsaved.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let json = {
+            let usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+            serde_json::to_vec_pretty(&*usage)
+        };
+        let saved = json.map_err(std::io::Error::other).and_then(|json| {
+            // Written aside and renamed, so a crash can't leave half a file
+            let temp = path.with_extension("tmp");
+            std::fs::write(&temp, json)?;
+            std::fs::rename(&temp, path)
+        });
+        if let Err(e) = saved {
+            error!("Could not save quota usage to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// The configured keys, by name, and their usage
+pub struct Quotas {
+    /// Swapped whole by [`Quotas::set_keys`], keeping the usage
+    keys: RwLock<HashMap<String, ApiKey>>,
+    log: Arc<UsageLog>,
+}
+
+/// Months since the start of year 0 for `days` since the Unix epoch, in the proleptic
+/// Gregorian calendar
+fn month_of(days: u64) -> u64 {
+    // Howard Hinnant's civil_from_days, shifted to count from March 1st, year 0
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    year * 12 + month - 1
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86_400)
+}
+
+impl Quotas {
+    /// Quotas for `keys`, by name, picking up the usage saved in `usage_file` if it exists
+    pub fn new(keys: HashMap<String, ApiKey>, usage_file: Option<PathBuf>) -> Result<Self, String> {
+        let usage = match &usage_file {
+            Some(path) if path.exists() => {
+                let json = std::fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+                serde_json::from_str(&json)
+                    .map_err(|e| format!("invalid usage file {}: {}", path.display(), e))?
+            }
+            _ => HashMap::new(),
+        };
+        let log = Arc::new(UsageLog {
+            usage: Mutex::new(usage),
+            file: usage_file,
+            unsaved: AtomicBool::new(false),
+            writing: Mutex::new(()),
+        });
+        if log.file.is_some() {
+            let log = Arc::clone(&log);
+            std::thread::spawn(move || {
+                // Until the quotas are dropped, when this is the last reference
+                while Arc::strong_count(&log) > 1 {
+                    std::thread::sleep(SAVE_INTERVAL);
+                    log.save();
+                }
+            });
+        }
+        Ok(Self {
+            keys: RwLock::new(keys),
+            log,
+        })
+    }
+
+    /// Saves the usage now if it changed since it was last saved, e.g. before the
+    /// server exits
+    pub fn flush(&self) {
+        self.log.save();
+    }
+
+    /// Uses `keys` from now on, e.g. with new quotas after a config reload. Usage is kept
+    /// for keys that stay.
+    pub fn set_keys(&self, keys: HashMap<String, ApiKey>) {
+        *self.keys.write().unwrap_or_else(PoisonError::into_inner) = keys;
+    }
+
+    /// The name of the key a request was made with
+    pub(crate) fn authorize(&self, headers: &HeaderMap) -> Result<String, SpeechError> {
+        let token = bearer_token(headers)
+            .ok_or_else(|| SpeechError::Unauthorized("an API key is required".to_string()))?;
+        self.keys
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .find(|(_, key)| key.key == token)
+            .map(|(name, _)| name.clone())
+            .ok_or_else(|| SpeechError::Unauthorized("invalid API key".to_string()))
+    }
+
+    /// Counts `chars` against the quotas of key `name`, unless that would go over one
+    pub(crate) fn charge(&self, name: &str, chars: usize) -> Result<(), SpeechError> {
+        self.charge_on(name, chars as u64, today())
+    }
+
+    fn charge_on(&self, name: &str, chars: u64, day: u64) -> Result<(), SpeechError> {
+        let Some(key) = self
+            .keys
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+        else {
+            return Err(SpeechError::Unauthorized("invalid API key".to_string()));
+        };
+        let mut usage = self
+            .log
+            .usage
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let entry = usage.entry(name.to_string()).or_default();
+        let month = month_of(day);
+        if entry.day != day {
+            entry.day = day;
+            entry.day_chars = 0;
+        }
+        if entry.month != month {
+            entry.month = month;
+            entry.month_chars = 0;
+        }
+        for (period, limit, used, resets) in [
+            ("daily", key.daily_chars, entry.day_chars, "at midnight UTC"),
+            (
+                "monthly",
+                key.monthly_chars,
+                entry.month_chars,
+                "on the 1st (UTC)",
+            ),
+        ] {
+            if let Some(limit) = limit
+                && used + chars > limit
+            {
+                return Err(SpeechError::OverQuota(format!(
+                    "the {} quota of {} characters for key '{}' would be exceeded: {} used, \
+                     this request has {}; it resets {}",
+                    period, limit, name, used, chars, resets
+                )));
+            }
+        }
+        entry.day_chars += chars;
+        entry.month_chars += chars;
+        self.log.unsaved.store(true, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl Drop for Quotas {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Checks that a request with `headers` may synthesize `input`, and counts it against its
+/// key; anything goes if the server has no keys
+pub(crate) fn charge(
+    options: &ServerOptions,
+    headers: &HeaderMap,
+    input: &str,
+) -> Result<(), SpeechError> {
+    match &options.quotas {
+        Some(quotas) => quotas.charge(&quotas.authorize(headers)?, input.chars().count()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::header;
+
+    #[test]
+    fn test_quotas() {
+        // 2026-10-14 and the last day of that month
+        assert_eq!(month_of(20_740), 2026 * 12 + 9);
+        assert_eq!(month_of(20_757), 2026 * 12 + 9);
+        assert_eq!(month_of(20_758), 2026 * 12 + 10);
+        assert_eq!(month_of(0), 1970 * 12);
+
+        let keys = HashMap::from([(
+            "team-a".to_string(),
+            ApiKey {
+                key: "secret".to_string(),
+                daily_chars: Some(10),
+                monthly_chars: Some(15),
+            },
+        )]);
+        let quotas = Quotas::new(keys, None).unwrap();
+        let mut headers = HeaderMap::new();
+        assert!(matches!(
+            quotas.authorize(&headers),
+            Err(SpeechError::Unauthorized(_))
+        ));
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert_eq!(quotas.authorize(&headers).unwrap(), "team-a");
+
+        assert!(quotas.charge_on("team-a", 8, 20_740).is_ok());
+        assert!(matches!(
+            quotas.charge_on("team-a", 3, 20_740),
+            Err(SpeechError::OverQuota(message)) if message.contains("daily quota of 10")
+        ));
+        // A new day, but the month is nearly used up
+        assert!(quotas.charge_on("team-a", 7, 20_741).is_ok());
+        assert!(quotas.charge_on("team-a", 1, 20_742).is_err());
+        assert!(quotas.charge_on("team-a", 10, 20_758).is_ok());
+
+        // New limits on a reload apply to what was used already
+        quotas.set_keys(HashMap::from([(
+            "team-a".to_string(),
+            ApiKey {
+                key: "rotated".to_string(),
+                daily_chars: Some(12),
+                monthly_chars: None,
+            },
+        )]));
+        assert!(quotas.authorize(&headers).is_err());
+        assert!(quotas.charge_on("team-a", 2, 20_758).is_ok());
+        assert!(quotas.charge_on("team-a", 1, 20_758).is_err());
+    }
+
+    #[test]
+    fn test_usage_file() {
+        let path = std::env::temp_dir().join(format!("koko-usage-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let keys = HashMap::from([(
+            "team-a".to_string(),
+            ApiKey {
+                key: "secret".to_string(),
+                daily_chars: Some(10),
+                monthly_chars: None,
+            },
+        )]);
+        let quotas = Quotas::new(keys.clone(), Some(path.clone())).unwrap();
+        assert!(quotas.charge_on("team-a", 8, 20_740).is_ok());
+        quotas.flush();
+        assert!(path.exists());
+        assert!(quotas.charge_on("team-a", 1, 20_740).is_ok());
+        drop(quotas);
+
+        // Dropping saved the last charge too
+        let quotas = Quotas::new(keys, Some(path.clone())).unwrap();
+        assert!(quotas.charge_on("team-a", 1, 20_740).is_ok());
+        assert!(quotas.charge_on("team-a", 1, 20_740).is_err());
+        drop(quotas);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
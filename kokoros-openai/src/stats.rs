@@ -0,0 +1,191 @@
+//! `GET /admin/stats` - how the server is doing, as JSON, for dashboards that can't scrape
+//! Prometheus
+//!
+//! Counts start from zero when the server starts. With `--api-key`, the endpoint needs it
+//! as a bearer token, as it tells which voices are used and how much.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use axum::{
+    Extension, Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use kokoros::tts::koko::TTSKoko;
+use serde::Serialize;
+
+use crate::{ServerOptions, bearer_token};
+
+/// Counters updated by the endpoints
+pub(crate) struct Stats {
+    sessions: AtomicUsize,
+    requests_by_voice: Mutex<BTreeMap<String, u64>>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    synthesis: Mutex<(Duration, Duration)>,
+    started: Instant,
+}
+
+/// Counts a WebSocket or WebRTC session as active while it is alive
+pub(crate) struct Session(Arc<Stats>);
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.0.sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            sessions: AtomicUsize::new(0),
+            requests_by_voice: Mutex::default(),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            synthesis: Mutex::default(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn session(self: &Arc<Self>) -> Session {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+        Session(Arc::clone(self))
+    }
+
+    pub fn request(&self, voice: &str) {
+        let mut requests = self
+            .requests_by_voice
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *requests.entry(voice.to_string()).or_default() += 1;
+    }
+
+    pub fn cache_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that `samples` of 24 kHz audio took `elapsed` to synthesize
+    pub fn synthesized(&self, samples: usize, elapsed: Duration) {
+        let mut synthesis = self
+            .synthesis
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        synthesis.0 += elapsed;
+        synthesis.1 += Duration::from_secs_f64(samples as f64 / 24_000.0);
+    }
+}
+
+#[derive(Serialize)]
+struct CacheStats {
+    hits: u64,
+    misses: u64,
+    /// Of the lookups, how many were hits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hit_rate: Option<f64>,
+}
+
+/// What `GET /admin/stats` answers
+#[derive(Serialize)]
+struct StatsResponse {
+    uptime_secs: u64,
+    /// Chunks being synthesized or waiting for a model, over every instance
+    queue_depth: usize,
+    /// Open WebSocket and WebRTC sessions
+    active_sessions: usize,
+    requests_by_voice: BTreeMap<String, u64>,
+    /// Synthesis time over audio duration of non-streaming requests, below 1 when faster
+    /// than real time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    average_rtf: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<CacheStats>,
+    /// Resident memory of the process, where the OS tells
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory_bytes: Option<u64>,
+}
+
+/// The process's resident set size, from `/proc/self/status` on Linux
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+fn ratio(part: f64, whole: f64) -> Option<f64> {
+    (whole > 0.0).then(|| part / whole)
+}
+
+pub(crate) async fn handle_stats(
+    State((_, tts_instances)): State<(TTSKoko, Vec<TTSKoko>)>,
+    Extension(options): Extension<ServerOptions>,
+    Extension(stats): Extension<Arc<Stats>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(api_key) = &options.api_key
+        && bearer_token(&headers) != Some(api_key.as_str())
+    {
+        return (StatusCode::UNAUTHORIZED, "invalid API key").into_response();
+    }
+
+    let (synthesis_time, audio_time) = *stats
+        .synthesis
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let hits = stats.cache_hits.load(Ordering::Relaxed);
+    let misses = stats.cache_misses.load(Ordering::Relaxed);
+    Json(StatsResponse {
+        uptime_secs: stats.started.elapsed().as_secs(),
+        queue_depth: tts_instances.iter().map(TTSKoko::queue_depth).sum(),
+        active_sessions: stats.sessions.load(Ordering::Relaxed),
+        requests_by_voice: stats
+            .requests_by_voice
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone(),
+        average_rtf: ratio(synthesis_time.as_secs_f64(), audio_time.as_secs_f64()),
+        cache: options.cache.as_ref().map(|_| CacheStats {
+            hits,
+            misses,
+            hit_rate: ratio(hits as f64, (hits + misses) as f64),
+        }),
+        memory_bytes: resident_memory(),
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let stats = Arc::new(Stats::new());
+        let session = stats.session();
+        assert_eq!(stats.sessions.load(Ordering::Relaxed), 1);
+        drop(session);
+        assert_eq!(stats.sessions.load(Ordering::Relaxed), 0);
+
+        stats.request("af_sky");
+        stats.request("af_sky");
+        assert_eq!(stats.requests_by_voice.lock().unwrap()["af_sky"], 2);
+        stats.synthesized(48_000, Duration::from_millis(500));
+        let (synthesis_time, audio_time) = *stats.synthesis.lock().unwrap();
+        assert_eq!(
+            ratio(synthesis_time.as_secs_f64(), audio_time.as_secs_f64()),
+            Some(0.25)
+        );
+        assert_eq!(ratio(1.0, 0.0), None);
+        #[cfg(target_os = "linux")]
+        assert!(resident_memory().is_some_and(|bytes| bytes > 0));
+    }
+}
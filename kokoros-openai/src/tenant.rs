@@ -0,0 +1,277 @@
+//! Tenants: one server shared by several products, each kept to what its key allows
+//!
+//! A tenant is named after its key in [`Quotas`](crate::Quotas), which holds its quotas. It
+//! may be limited to some voices, and may have a lexicon and a voice pack of its own; those
+//! are loaded into clones of the server's engines, sharing the model, so no other tenant
+//! hears its rewrites or sees its voices.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::http::HeaderMap;
+use kokoros::error::KokorosError;
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::normalize::{Replacement, apply_replacements};
+use kokoros::tts::plugin::TextPlugin;
+use kokoros::tts::voices::parse_blend;
+use kokoros::utils::checksum;
+
+use crate::{ServerOptions, SpeechError, Voice, request_voice};
+
+/// A tenant's `[[lexicon]]` rules, run after the server's plugins
+#[derive(Debug)]
+struct Lexicon(Vec<Replacement>);
+
+impl TextPlugin for Lexicon {
+    fn process(&self, text: &str) -> Result<String, String> {
+        Ok(apply_replacements(text, &self.0).into_owned())
+    }
+}
+
+/// Digests of the files of a voice pack, by name, so audio of a pack whose voices were
+/// replaced doesn't pass for that of the old ones
+fn pack_digest(dir: &Path) -> Result<String, KokorosError> {
+    let mut files = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    files.retain(|path| path.is_file());
+    files.sort();
+    let mut digest = String::new();
+    for file in files {
+        digest.push_str(&format!(
+            "{}\0{}\0",
+            file.file_name().unwrap_or_default().to_string_lossy(),
+            checksum::sha256_file(&file)?
+        ));
+    }
+    Ok(digest)
+}
+
+/// What one tenant may use
+#[derive(Clone, Default)]
+pub struct Tenant {
+    /// Voices its requests may use, named as Kokoro voices; any if empty
+    voices: Vec<String>,
+    /// Its clones of the server's engines, when it has a lexicon or voice pack
+    engines: Option<Vec<TTSKoko>>,
+    /// Its lexicon and voice pack, for the [`CacheKey`](kokoros::utils::cache::CacheKey)
+    /// of its audio; empty if it has neither
+    scope: String,
+}
+
+impl Tenant {
+    /// A tenant limited to `voices` (any if empty), rewriting its text with `lexicon` and
+    /// with the voices in `voices_dir` on top of the server's, on clones of `engines`
+    pub fn new(
+        voices: Vec<String>,
+        lexicon: Vec<Replacement>,
+        voices_dir: Option<&Path>,
+        engines: &[TTSKoko],
+    ) -> Result<Self, KokorosError> {
+        if lexicon.is_empty() && voices_dir.is_none() {
+            return Ok(Self {
+                voices,
+                engines: None,
+                scope: String::new(),
+            });
+        }
+        let mut scope = String::new();
+        for rule in &lexicon {
+            scope.push_str(&format!("{}\0{}\0", rule.pattern(), rule.replacement()));
+        }
+        if let Some(dir) = voices_dir {
+            scope.push_str(&format!("voices\0{}\0{}", dir.display(), pack_digest(dir)?));
+        }
+        let lexicon: Arc<dyn TextPlugin> = Arc::new(Lexicon(lexicon));
+        let engines = engines
+            .iter()
+            .map(|engine| {
+                let mut options = engine.text_options().clone();
+                options.plugins = options
+                    .plugins
+                    .iter()
+                    .cloned()
+                    .chain([Arc::clone(&lexicon)])
+                    .collect();
+                let mut engine = engine.with_text_options(options);
+                if let Some(dir) = voices_dir {
+                    engine.add_voices_dir(dir)?;
+                }
+                Ok(engine)
+            })
+            .collect::<Result<_, KokorosError>>()?;
+        Ok(Self {
+            voices,
+            engines: Some(engines),
+            scope,
+        })
+    }
+
+    /// What the tenant's audio depends on beyond the request, for caching it apart from
+    /// other tenants'
+    pub(crate) fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    /// Whether `voice`, or every voice of a blend, is one the tenant may use
+    pub(crate) fn allows(&self, voice: &str) -> bool {
+        self.voices.is_empty()
+            || parse_blend(voice).is_ok_and(|blend| {
+                blend
+                    .iter()
+                    .all(|(name, _)| self.voices.iter().any(|allowed| allowed == name))
+            })
+    }
+
+    /// Refuses a voice the tenant may not use with a 403
+    pub(crate) fn check_voice(&self, voice: &str) -> Result<(), SpeechError> {
+        if self.allows(voice) {
+            Ok(())
+        } else {
+            Err(SpeechError::Forbidden(format!(
+                "voice '{}' is not available with this key",
+                voice
+            )))
+        }
+    }
+
+    /// The voice a request that named none gets instead of `chosen`, when the tenant may
+    /// not use that one
+    pub(crate) fn fallback_voice(&self, chosen: &str) -> Option<&str> {
+        (!self.allows(chosen))
+            .then(|| self.voices.first().map(String::as_str))
+            .flatten()
+    }
+
+    /// The engines serving the tenant: its own, or the server's `single` and `instances`
+    pub(crate) fn engines(
+        &self,
+        single: TTSKoko,
+        instances: Vec<TTSKoko>,
+    ) -> (TTSKoko, Vec<TTSKoko>) {
+        match &self.engines {
+            Some(engines) => (engines[0].clone(), engines.clone()),
+            None => (single, instances),
+        }
+    }
+}
+
+/// The tenant a request with `headers` was made as, refusing it with a 401 if the server
+/// has keys and the request none of them; `None` if the server has no keys or the key no
+/// tenant settings
+pub(crate) fn authorize(
+    options: &ServerOptions,
+    headers: &HeaderMap,
+) -> Result<Option<Tenant>, SpeechError> {
+    let Some(name) = key_name(options, headers)? else {
+        return Ok(None);
+    };
+    Ok(options.tenants.get().get(&name).cloned())
+}
+
+/// The name of the key a request is made with, when the server has keys; a request without
+/// a valid one is refused with a 401
+pub(crate) fn key_name(
+    options: &ServerOptions,
+    headers: &HeaderMap,
+) -> Result<Option<String>, SpeechError> {
+    options
+        .quotas
+        .as_ref()
+        .map(|quotas| quotas.authorize(headers))
+        .transpose()
+}
+
+/// The voice and language for a request, chosen as [`request_voice`] does but kept to the
+/// voices of its tenant: one it didn't name falls back to the tenant's first voice, and
+/// one it named that the tenant may not use is refused
+pub(crate) fn tenant_voice(
+    voice: Option<Voice>,
+    headers: &HeaderMap,
+    options: &ServerOptions,
+    tenant: Option<&Tenant>,
+) -> Result<(String, String), SpeechError> {
+    let named = voice.is_some();
+    let (voice, lan) = request_voice(voice, headers, options);
+    let Some(tenant) = tenant else {
+        return Ok((voice, lan));
+    };
+    if !named && let Some(fallback) = tenant.fallback_voice(&voice) {
+        let lan = options
+            .voice_registry
+            .as_deref()
+            .and_then(|registry| registry.get(fallback))
+            .map_or_else(|| "en-us".to_string(), |metadata| metadata.lan.clone());
+        return Ok((fallback.to_string(), lan));
+    }
+    tenant.check_voice(&voice)?;
+    Ok((voice, lan))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kokoros::utils::cache::CacheKey;
+    use std::fs;
+
+    #[test]
+    fn test_allows() {
+        let tenant = Tenant {
+            voices: vec!["af_sky".to_string(), "am_adam".to_string()],
+            engines: None,
+            scope: String::new(),
+        };
+        assert!(tenant.allows("af_sky"));
+        assert!(tenant.allows("af_sky.4+am_adam.6"));
+        assert!(!tenant.allows("af_sky.4+bf_emma.6"));
+        assert!(matches!(
+            tenant.check_voice("bf_emma"),
+            Err(SpeechError::Forbidden(_))
+        ));
+        assert_eq!(tenant.fallback_voice("bf_emma"), Some("af_sky"));
+        assert_eq!(tenant.fallback_voice("am_adam"), None);
+        assert!(Tenant::default().allows("bf_emma"));
+    }
+
+    #[test]
+    fn test_scope() {
+        let tenant = |lexicon: &[(&str, &str)], voices_dir: Option<&Path>| {
+            let lexicon = lexicon
+                .iter()
+                .map(|(pattern, replacement)| Replacement::new(pattern, replacement).unwrap())
+                .collect();
+            Tenant::new(Vec::new(), lexicon, voices_dir, &[]).unwrap()
+        };
+        let key = |tenant: &Tenant| {
+            CacheKey {
+                text: "Call ACME.",
+                voice: "af_sky",
+                lan: "en-us",
+                speed: 1.0,
+                initial_silence: None,
+                format: "mp3",
+                loudness: None,
+                model: "1.0",
+                scope: tenant.scope(),
+            }
+            .digest()
+        };
+        let acme = tenant(&[("ACME", "Acme")], None);
+        let other = tenant(&[("ACME", "A C M E")], None);
+        assert_ne!(key(&acme), key(&other));
+        assert_ne!(key(&acme), key(&Tenant::default()));
+        let voices = std::env::temp_dir().join(format!("koko-tenant-{}", std::process::id()));
+        fs::create_dir_all(&voices).unwrap();
+        fs::write(voices.join("af_acme.bin"), b"one").unwrap();
+        let packed = key(&tenant(&[("ACME", "Acme")], Some(&voices)));
+        assert_ne!(key(&acme), packed);
+        assert_eq!(key(&tenant(&[("ACME", "Acme")], Some(&voices))), packed);
+        // Replacing the pack's voices changes the key, even in the same place
+        fs::write(voices.join("af_acme.bin"), b"two").unwrap();
+        assert_ne!(key(&tenant(&[("ACME", "Acme")], Some(&voices))), packed);
+        fs::remove_dir_all(&voices).unwrap();
+        assert_eq!(key(&acme), key(&tenant(&[("ACME", "Acme")], None)));
+        // Without a lexicon or voice pack, a tenant hears what everyone else does
+        assert_eq!(key(&tenant(&[], None)), key(&Tenant::default()));
+    }
+}
@@ -0,0 +1,265 @@
+//! `POST /v1/audio/speech/file` - speech for an uploaded text, Markdown or EPUB file
+//!
+//! The file comes as the `file` field of a multipart form, so clients don't have to inline
+//! megabytes of text into JSON; the other fields are those of a speech request (`voice`,
+//! `speed`, `response_format`, `initial_silence`, `priority`), and without a `voice` one
+//! is chosen from `Accept-Language` as for JSON requests. Files with more text than
+//! the input limit, or sent with `async=true`, are accepted as a job (see
+//! [`jobs`](crate::jobs)) and answered with a 202 and its id; others with the audio.
+
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    Extension, Json,
+    extract::{Multipart, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use kokoros::input::epub::read_epub_from;
+use kokoros::input::markdown::strip_markdown;
+use kokoros::tts::koko::{Priority, TTSKoko};
+use kokoros::tts::koko_async::SynthRequest;
+use tracing::info;
+
+use crate::stats::Stats;
+use crate::{
+    AudioFormat, ServerOptions, SpeechError, Voice, audio_response, encode_audio,
+    get_colored_request_id_with_relative, jobs::Jobs,
+};
+
+/// Largest upload accepted, in bytes
+pub(crate) const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// Characters per part when a file is split into a job without an input limit to go by
+const DEFAULT_PART_CHARS: usize = 4096;
+
+/// What an uploaded file is, from its name or else its content type
+#[derive(Debug, PartialEq)]
+enum FileKind {
+    Text,
+    Markdown,
+    Epub,
+}
+
+fn file_kind(file_name: Option<&str>, content_type: Option<&str>) -> Option<FileKind> {
+    let extension = file_name
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match (extension.as_deref(), content_type) {
+        (Some("txt"), _) => Some(FileKind::Text),
+        (Some("md" | "markdown"), _) => Some(FileKind::Markdown),
+        (Some("epub"), _) => Some(FileKind::Epub),
+        (_, Some("application/epub+zip")) => Some(FileKind::Epub),
+        (_, Some("text/markdown")) => Some(FileKind::Markdown),
+        (_, Some(content_type)) if content_type.starts_with("text/") => Some(FileKind::Text),
+        // Plain text is the likeliest when nothing says otherwise
+        (None, None) => Some(FileKind::Text),
+        _ => None,
+    }
+}
+
+/// The text to speak in an uploaded file
+fn file_text(kind: FileKind, bytes: Vec<u8>) -> Result<String, SpeechError> {
+    let text = |bytes: Vec<u8>| {
+        String::from_utf8(bytes)
+            .map_err(|_| SpeechError::BadRequest("the file is not UTF-8 text".to_string()))
+    };
+    Ok(match kind {
+        FileKind::Text => text(bytes)?,
+        FileKind::Markdown => strip_markdown(&text(bytes)?),
+        FileKind::Epub => read_epub_from(Cursor::new(bytes))
+            .map_err(|e| SpeechError::BadRequest(format!("unreadable EPUB: {}", e)))?
+            .chapters
+            .into_iter()
+            .map(|chapter| chapter.text)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+    })
+}
+
+fn bad_field(name: &str, value: &str) -> SpeechError {
+    SpeechError::BadRequest(format!("invalid {}: {:?}", name, value))
+}
+
+pub(crate) async fn handle_speech_file(
+    State((tts_single, _)): State<(TTSKoko, Vec<TTSKoko>)>,
+    Extension(options): Extension<ServerOptions>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Extension(stats): Extension<Arc<Stats>>,
+    request_id: Option<Extension<(String, Instant)>>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, SpeechError> {
+    let (request_id, request_start) = request_id
+        .map(|Extension(id)| id)
+        .unwrap_or_else(|| ("unknown".to_string(), Instant::now()));
+    let bad_form = |e: axum::extract::multipart::MultipartError| {
+        SpeechError::BadRequest(format!("malformed form: {}", e))
+    };
+
+    let mut text = None;
+    let mut voice = None;
+    let mut request = SynthRequest::new("", "");
+    let mut format = AudioFormat::default();
+    let mut in_background = false;
+    while let Some(field) = multipart.next_field().await.map_err(bad_form)? {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            let kind = file_kind(field.file_name(), field.content_type())
+                .ok_or_else(|| SpeechError::BadRequest("unsupported file type".to_string()))?;
+            let bytes = field.bytes().await.map_err(bad_form)?;
+            text = Some(file_text(kind, bytes.to_vec())?);
+            continue;
+        }
+        let value = field.text().await.map_err(bad_form)?;
+        match name.as_str() {
+            "voice" => voice = Some(Voice(value)),
+            "speed" => request.speed = value.parse().map_err(|_| bad_field("speed", &value))?,
+            "initial_silence" => {
+                request.initial_silence = Some(
+                    value
+                        .parse()
+                        .map_err(|_| bad_field("initial_silence", &value))?,
+                )
+            }
+            "response_format" => {
+                format = serde_json::from_value(serde_json::Value::String(value.clone()))
+                    .map_err(|_| bad_field("response_format", &value))?
+            }
+            "loudness" => {
+                request.loudness = Some(value.parse().map_err(|_| bad_field("loudness", &value))?)
+            }
+            "priority" => {
+                request.priority = value.parse::<Priority>().map_err(SpeechError::BadRequest)?
+            }
+            "async" => in_background = value == "true",
+            // Like the JSON endpoint, other OpenAI fields are accepted and ignored
+            _ => {}
+        }
+    }
+    let text = text.ok_or_else(|| SpeechError::BadRequest("no file uploaded".to_string()))?;
+    if text.trim().is_empty() {
+        return Err(SpeechError::BadRequest("the file has no text".to_string()));
+    }
+    let tenant = crate::tenant::authorize(&options, &headers)?;
+    let tenant = tenant.as_ref();
+    let (style, lan) = crate::tenant::tenant_voice(voice, &headers, &options, tenant)?;
+    crate::quota::charge(&options, &headers, &text)?;
+    let tts_single = match tenant {
+        Some(tenant) => tenant.engines(tts_single, Vec::new()).0,
+        None => tts_single,
+    };
+    stats.request(&style);
+    let request = SynthRequest {
+        text,
+        lan,
+        style,
+        request_id: Some(request_id.clone()),
+        ..request
+    };
+
+    let colored_request_id = get_colored_request_id_with_relative(&request_id, request_start);
+    let too_long = options
+        .max_input_chars
+        .is_some_and(|limit| request.text.chars().count() > limit);
+    if in_background || too_long {
+        let job = jobs.submit(
+            tts_single,
+            request,
+            format,
+            options.max_input_chars.unwrap_or(DEFAULT_PART_CHARS),
+            crate::tenant::key_name(&options, &headers)?.as_deref(),
+        );
+        info!(
+            "{} File accepted as {} in {} parts",
+            colored_request_id, job.id, job.parts
+        );
+        let location = format!("/v1/audio/jobs/{}", job.id);
+        return Ok((
+            StatusCode::ACCEPTED,
+            [(header::LOCATION, location)],
+            Json(job),
+        )
+            .into_response());
+    }
+
+    let synthesis_start = Instant::now();
+    let raw_audio = tts_single.synth_async(request).await?.samples;
+    stats.synthesized(raw_audio.len(), synthesis_start.elapsed());
+    let (content_type, audio_data, format_name) = encode_audio(&raw_audio, &format)?;
+    info!(
+        "{} TTS file completed - {} bytes, {} format",
+        colored_request_id,
+        audio_data.len(),
+        format_name
+    );
+    audio_response(content_type, audio_data, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kokoros::tts::voices::VoiceRegistry;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_file_text() {
+        assert_eq!(file_kind(Some("Notes.MD"), None), Some(FileKind::Markdown));
+        assert_eq!(
+            file_kind(Some("book"), Some("application/epub+zip")),
+            Some(FileKind::Epub)
+        );
+        assert_eq!(file_kind(None, Some("text/plain")), Some(FileKind::Text));
+        assert_eq!(file_kind(Some("cover.png"), Some("image/png")), None);
+
+        let text = file_text(
+            FileKind::Markdown,
+            b"# Title\n\nSome **bold** text.".to_vec(),
+        );
+        assert_eq!(
+            text.unwrap(),
+            strip_markdown("# Title\n\nSome **bold** text.")
+        );
+        assert!(file_text(FileKind::Text, vec![0xff, 0xfe]).is_err());
+        assert!(file_text(FileKind::Epub, b"not a zip".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_request_voice() {
+        assert_eq!(
+            crate::accepted_languages("fr-CH, fr;q=0.9, en;q=0.8, de;q=0, *;q=0.5"),
+            ["fr-CH", "fr", "en"]
+        );
+        let names = ["af_sky", "bf_emma", "ff_siwis"].map(String::from);
+        let options = ServerOptions {
+            voice_registry: Some(Arc::new(VoiceRegistry::from_names(&names))),
+            ..ServerOptions::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, "de-DE, fr;q=0.7".parse().unwrap());
+        let voice = |voice: Option<&str>, headers: &HeaderMap| {
+            crate::request_voice(voice.map(|v| Voice(v.to_string())), headers, &options)
+        };
+        assert_eq!(
+            voice(None, &headers),
+            ("ff_siwis".to_string(), "fr-fr".to_string())
+        );
+        assert_eq!(
+            voice(Some("nova"), &headers),
+            ("af_nova".to_string(), "en-us".to_string())
+        );
+        assert_eq!(
+            voice(None, &HeaderMap::new()),
+            ("af_sky".to_string(), "en-us".to_string())
+        );
+
+        // Aliases win over the OpenAI names, and a reload swaps them for every clone
+        options.clone().voice_aliases.set(HashMap::from([(
+            "nova".to_string(),
+            "bf_emma.6+af_sky.4".to_string(),
+        )]));
+        assert_eq!(voice(Some("nova"), &headers).0, "bf_emma.6+af_sky.4");
+    }
+}
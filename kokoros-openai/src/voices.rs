@@ -0,0 +1,157 @@
+//! `POST /v1/audio/voices` - registers a new voice for subsequent requests
+//!
+//! The voice is given either as raw style vectors or as a blend of existing voices, and is
+//! saved to the voices directory so it is loaded again on the next start. Requires the
+//! server's API key as a bearer token; without a configured key uploads are refused.
+
+use axum::{
+    Extension, Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use kokoros::tts::koko::TTSKoko;
+use kokoros::tts::voices::{self, Voice};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{ServerOptions, bearer_token, map_openai_voice_to_kokoro};
+
+/// Token counts covered by a voice built from a single style vector, as in the voices
+/// published with the model
+const STYLE_ROWS: usize = 510;
+
+#[derive(Deserialize)]
+pub(crate) struct AddVoiceRequest {
+    name: String,
+
+    /// Style vectors, 256 values per token count in order; a single 256-value vector is
+    /// used for every token count
+    #[serde(default)]
+    style: Option<Vec<f32>>,
+
+    /// Blend of loaded voices in the `--style` syntax, e.g. `af_sky.4+af_nicole.6`
+    #[serde(default)]
+    recipe: Option<String>,
+
+    /// Multiplies the style values, see `koko voices mix --scale`
+    #[serde(default)]
+    scale: Option<f32>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct AddVoiceResponse {
+    name: String,
+}
+
+type Rejection = (StatusCode, String);
+
+fn bad_request(message: impl ToString) -> Rejection {
+    (StatusCode::BAD_REQUEST, message.to_string())
+}
+
+fn check_auth(headers: &HeaderMap, api_key: Option<&str>) -> Result<(), Rejection> {
+    let Some(api_key) = api_key else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "voice uploads are disabled, start the server with --api-key".to_string(),
+        ));
+    };
+    if bearer_token(headers) != Some(api_key) {
+        return Err((StatusCode::UNAUTHORIZED, "invalid API key".to_string()));
+    }
+    Ok(())
+}
+
+/// Style vectors as sent in a request
+fn voice_from_values(values: &[f32]) -> Result<Voice, Rejection> {
+    if values.is_empty() || !values.len().is_multiple_of(256) {
+        return Err(bad_request(format!(
+            "style must have a multiple of 256 values, got {}",
+            values.len()
+        )));
+    }
+    let mut styles: voices::VoiceStyles = values
+        .chunks_exact(256)
+        .map(|chunk| [chunk.try_into().unwrap()])
+        .collect();
+    if styles.len() == 1 {
+        styles = vec![styles[0]; STYLE_ROWS];
+    }
+    Ok(Voice(styles))
+}
+
+pub(crate) async fn handle_add_voice(
+    State((tts_single, tts_instances)): State<(TTSKoko, Vec<TTSKoko>)>,
+    Extension(options): Extension<ServerOptions>,
+    headers: HeaderMap,
+    Json(request): Json<AddVoiceRequest>,
+) -> Result<(StatusCode, Json<AddVoiceResponse>), Rejection> {
+    check_auth(&headers, options.api_key.as_deref())?;
+
+    let name = request.name;
+    if !voices::is_valid_voice_name(&name) {
+        return Err(bad_request(format!(
+            "invalid voice name '{}', use letters, digits, _ and - only",
+            name
+        )));
+    }
+    // OpenAI names are mapped to Kokoro voices before lookup, so could never be used
+    if tts_single.styles().contains(&name) || map_openai_voice_to_kokoro(&name) != name {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("voice '{}' already exists", name),
+        ));
+    }
+
+    let voice = match (&request.style, &request.recipe) {
+        (Some(values), None) => voice_from_values(values)?,
+        (None, Some(recipe)) => {
+            let parts = voices::parse_blend(recipe).map_err(bad_request)?;
+            tts_single.styles().blend(&parts).map_err(bad_request)?
+        }
+        _ => return Err(bad_request("give either style or recipe")),
+    };
+    let voice = voice.scale(request.scale.unwrap_or(1.0));
+
+    if let Some(dir) = &options.voices_dir {
+        let path = voice
+            .save_as(dir, &name)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        info!("Saved voice '{}' to {}", name, path.display());
+    }
+    // Every instance loaded its own voices, so each needs the new one
+    for tts in &tts_instances {
+        tts.add_voice(&name, voice.clone())
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+
+    Ok((StatusCode::CREATED, Json(AddVoiceResponse { name })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::header;
+
+    #[test]
+    fn test_upload_checks() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            check_auth(&headers, None).unwrap_err().0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            check_auth(&headers, Some("secret")).unwrap_err().0,
+            StatusCode::UNAUTHORIZED
+        );
+        headers.insert(
+            header::AUTHORIZATION,
+            "Bearer secret".parse().unwrap(),
+        );
+        assert!(check_auth(&headers, Some("secret")).is_ok());
+
+        assert_eq!(voice_from_values(&[0.5; 256]).unwrap().0.len(), STYLE_ROWS);
+        assert_eq!(voice_from_values(&[0.5; 512]).unwrap().0.len(), 2);
+        assert!(voice_from_values(&[0.5; 100]).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! `/v1/audio/speech/webrtc` - speech as a live WebRTC audio track (`webrtc` feature)
+//!
+//! The client POSTs the text together with its SDP offer and gets the SDP answer back.
+//! Once the peer connection is up, audio is encoded to 20 ms Opus frames as each chunk is
+//! synthesized and sent over RTP at real-time pace, then the connection is closed.
+//! Signaling is a single request/response, so ICE candidates are gathered before
+//! answering (no trickle ICE).
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{Extension, Json, extract::State, http::HeaderMap};
+use kokoros::tts::koko::TTSKoko;
+use opus::{Application, Bitrate, Channels, Encoder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error, info};
+use webrtc::api::APIBuilder;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MIME_TYPE_OPUS, MediaEngine};
+use webrtc::interceptor::registry::Registry;
+use webrtc::media::Sample;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::track::track_local::TrackLocal;
+use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+
+use crate::stats::Stats;
+use crate::{ServerOptions, SpeechError, Speed, Voice, check_input_length};
+
+const SAMPLE_RATE: u32 = 24_000;
+const FRAME: Duration = Duration::from_millis(20);
+const FRAME_SAMPLES: usize = SAMPLE_RATE as usize / 50;
+/// How long the client has to complete the connection after getting the answer
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+pub(crate) struct WebRtcRequest {
+    input: String,
+
+    #[serde(default)]
+    voice: Voice,
+
+    #[serde(default)]
+    speed: Speed,
+
+    /// The client's SDP offer, which must include an audio transceiver
+    sdp: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct WebRtcResponse {
+    /// SDP answer to set as the remote description
+    sdp: String,
+}
+
+async fn new_peer_connection() -> Result<RTCPeerConnection, webrtc::Error> {
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs()?;
+    let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+    let api = APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build();
+    api.new_peer_connection(RTCConfiguration::default()).await
+}
+
+pub(crate) async fn handle_webrtc(
+    State((tts_single, _)): State<(TTSKoko, Vec<TTSKoko>)>,
+    Extension(options): Extension<ServerOptions>,
+    Extension(stats): Extension<Arc<Stats>>,
+    headers: HeaderMap,
+    Json(request): Json<WebRtcRequest>,
+) -> Result<Json<WebRtcResponse>, SpeechError> {
+    check_input_length(&request.input, options.max_input_chars)?;
+    let tenant = crate::tenant::authorize(&options, &headers)?;
+    let tenant = tenant.as_ref();
+    let voice = request.voice.to_kokoro_voice(&options);
+    if let Some(tenant) = tenant {
+        tenant.check_voice(&voice)?;
+    }
+    crate::quota::charge(&options, &headers, &request.input)?;
+    let tts_single = match tenant {
+        Some(tenant) => tenant.engines(tts_single, Vec::new()).0,
+        None => tts_single,
+    };
+    let peer_connection = Arc::new(new_peer_connection().await.map_err(SpeechError::WebRtc)?);
+
+    let track = Arc::new(TrackLocalStaticSample::new(
+        RTCRtpCodecCapability {
+            mime_type: MIME_TYPE_OPUS.to_owned(),
+            ..Default::default()
+        },
+        "audio".to_owned(),
+        "kokoros".to_owned(),
+    ));
+    let rtp_sender = peer_connection
+        .add_track(Arc::clone(&track) as Arc<dyn TrackLocal + Send + Sync>)
+        .await
+        .map_err(SpeechError::WebRtc)?;
+    // RTCP has to be read for the interceptors (NACK, reports) to work
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 1500];
+        while rtp_sender.read(&mut buf).await.is_ok() {}
+    });
+
+    let (state_tx, state_rx) = watch::channel(RTCPeerConnectionState::New);
+    peer_connection.on_peer_connection_state_change(Box::new(move |state| {
+        debug!("WebRTC peer connection state: {}", state);
+        let _ = state_tx.send(state);
+        Box::pin(async {})
+    }));
+
+    let offer = RTCSessionDescription::offer(request.sdp).map_err(SpeechError::WebRtc)?;
+    peer_connection
+        .set_remote_description(offer)
+        .await
+        .map_err(SpeechError::WebRtc)?;
+    let answer = peer_connection
+        .create_answer(None)
+        .await
+        .map_err(SpeechError::WebRtc)?;
+    let mut gathering_complete = peer_connection.gathering_complete_promise().await;
+    peer_connection
+        .set_local_description(answer)
+        .await
+        .map_err(SpeechError::WebRtc)?;
+    let _ = gathering_complete.recv().await;
+    let answer = peer_connection.local_description().await.ok_or_else(|| {
+        SpeechError::WebRtc(webrtc::Error::new(
+            "no local description after ICE gathering".to_owned(),
+        ))
+    })?;
+
+    let speed = request.speed.0;
+    stats.request(&voice);
+    let session = stats.session();
+    tokio::spawn(async move {
+        let _session = session;
+        if let Err(e) =
+            stream_speech(tts_single, request.input, voice, speed, &track, state_rx).await
+        {
+            error!("WebRTC stream failed: {}", e);
+        }
+        let _ = peer_connection.close().await;
+    });
+
+    Ok(Json(WebRtcResponse { sdp: answer.sdp }))
+}
+
+/// Waits for the peer to connect, then synthesizes `input` and streams it on `track`
+async fn stream_speech(
+    tts: TTSKoko,
+    input: String,
+    voice: String,
+    speed: f32,
+    track: &TrackLocalStaticSample,
+    mut state_rx: watch::Receiver<RTCPeerConnectionState>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let connected = tokio::time::timeout(
+        CONNECT_TIMEOUT,
+        state_rx.wait_for(|state| *state == RTCPeerConnectionState::Connected),
+    )
+    .await
+    .is_ok_and(|state| state.is_ok());
+    if !connected {
+        return Err("peer did not connect".into());
+    }
+    info!("WebRTC peer connected, streaming speech");
+
+    let (audio_tx, mut audio_rx) = mpsc::channel::<Vec<f32>>(4);
+    let synthesis = tokio::task::spawn_blocking(move || {
+        tts.tts_raw_audio_streaming(
+            &input,
+            "en-us",
+            &voice,
+            speed,
+            None,
+            None,
+            None,
+            None,
+            |chunk| {
+                audio_tx
+                    .blocking_send(chunk)
+                    .map_err(|_| "WebRTC stream closed".into())
+            },
+        )
+        .map_err(|e| e.to_string())
+    });
+
+    let mut encoder = Encoder::new(SAMPLE_RATE, Channels::Mono, Application::Audio)?;
+    encoder.set_bitrate(Bitrate::Bits(64000))?;
+    let mut pending: Vec<f32> = Vec::new();
+    let mut packet = vec![0u8; 4000];
+    let mut ticker = tokio::time::interval(FRAME);
+    // After waiting on synthesis, carry on at real-time pace rather than bursting
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut done = false;
+
+    while !done || !pending.is_empty() {
+        if pending.len() < FRAME_SAMPLES && !done {
+            match audio_rx.recv().await {
+                Some(chunk) => pending.extend(chunk),
+                None => done = true,
+            }
+            continue;
+        }
+        // Pad the last frame with silence
+        if pending.len() < FRAME_SAMPLES {
+            pending.resize(FRAME_SAMPLES, 0.0);
+        }
+        let frame: Vec<f32> = pending.drain(..FRAME_SAMPLES).collect();
+        let len = encoder.encode_float(&frame, &mut packet)?;
+
+        ticker.tick().await;
+        if matches!(
+            *state_rx.borrow(),
+            RTCPeerConnectionState::Disconnected
+                | RTCPeerConnectionState::Failed
+                | RTCPeerConnectionState::Closed
+        ) {
+            return Err("peer disconnected".into());
+        }
+        track
+            .write_sample(&Sample {
+                data: packet[..len].to_vec().into(),
+                duration: FRAME,
+                ..Default::default()
+            })
+            .await?;
+    }
+
+    synthesis.await??;
+    // Give the last packets time to arrive before closing
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_is_a_valid_opus_frame() {
+        let mut encoder = Encoder::new(SAMPLE_RATE, Channels::Mono, Application::Audio).unwrap();
+        let mut packet = vec![0u8; 4000];
+        let len = encoder
+            .encode_float(&[0.0; FRAME_SAMPLES], &mut packet)
+            .unwrap();
+        assert!(len > 0);
+    }
+}
@@ -0,0 +1,178 @@
+//! `/v1/audio/speech/ws` - speech as raw 20 ms Opus frames over a WebSocket, for voice
+//! assistant satellites (ESP32, Raspberry Pi) that decode packet by packet
+//!
+//! Each text message on the socket is a request, answered in turn: one binary message per
+//! Opus frame as the audio is synthesized, then `{"type": "done", "frames": N}`. A request
+//! that can't be served is answered with `{"type": "error", "message": "..."}` instead,
+//! and the connection stays open for the next one. Frames are sent only as fast as the
+//! client takes them, and synthesis stops when it goes away.
+
+use axum::{
+    Extension,
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use kokoros::tts::koko::{Priority, TTSKoko};
+use kokoros::tts::koko_async::SynthRequest;
+use kokoros::utils::opus::DEFAULT_VOICE_BITRATE;
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use std::sync::Arc;
+
+use crate::stats::Stats;
+use crate::tenant::Tenant;
+use crate::{ServerOptions, Speed, Voice, check_input_length};
+
+/// Chunks synthesized ahead of the frames the client has taken
+const BUFFER_CHUNKS: usize = 2;
+
+#[derive(Deserialize)]
+struct WsRequest {
+    input: String,
+
+    #[serde(default)]
+    voice: Voice,
+
+    #[serde(default)]
+    speed: Speed,
+
+    /// Opus bitrate in bits per second, 8000 to 24000
+    #[serde(default = "default_bitrate")]
+    bitrate: u32,
+
+    #[serde(default)]
+    priority: Priority,
+}
+
+fn default_bitrate() -> u32 {
+    DEFAULT_VOICE_BITRATE
+}
+
+pub(crate) async fn handle_ws(
+    State((tts_single, _)): State<(TTSKoko, Vec<TTSKoko>)>,
+    Extension(options): Extension<ServerOptions>,
+    Extension(stats): Extension<Arc<Stats>>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    // The key is checked on connecting, and each request charged to it
+    let key = match options
+        .quotas
+        .as_ref()
+        .map(|quotas| quotas.authorize(&headers))
+    {
+        Some(Ok(name)) => Some(name),
+        Some(Err(e)) => return e.into_response(),
+        None => None,
+    };
+    let tenant = key
+        .as_deref()
+        .and_then(|name| options.tenants.get().get(name).cloned());
+    let tts = match &tenant {
+        Some(tenant) => tenant.engines(tts_single, Vec::new()).0,
+        None => tts_single,
+    };
+    upgrade.on_upgrade(move |socket| serve_socket(socket, tts, options, stats, key, tenant))
+}
+
+async fn serve_socket(
+    mut socket: WebSocket,
+    tts: TTSKoko,
+    options: ServerOptions,
+    stats: Arc<Stats>,
+    key: Option<String>,
+    tenant: Option<Tenant>,
+) {
+    let _session = stats.session();
+    debug!("WebSocket client connected");
+    while let Some(Ok(message)) = socket.recv().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            // Pings are answered by axum; binary messages aren't requests
+            _ => continue,
+        };
+        let reply = match speak(
+            &mut socket,
+            &tts,
+            &options,
+            &stats,
+            key.as_deref(),
+            tenant.as_ref(),
+            &text,
+        )
+        .await
+        {
+            Ok(Some(frames)) => serde_json::json!({ "type": "done", "frames": frames }),
+            Ok(None) => break,
+            Err(message) => serde_json::json!({ "type": "error", "message": message }),
+        };
+        if socket
+            .send(Message::Text(reply.to_string().into()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    debug!("WebSocket client disconnected");
+}
+
+/// Sends the frames of `text`'s request, returning how many there were, or `None` if the
+/// client went away
+async fn speak(
+    socket: &mut WebSocket,
+    tts: &TTSKoko,
+    options: &ServerOptions,
+    stats: &Stats,
+    key: Option<&str>,
+    tenant: Option<&Tenant>,
+    text: &str,
+) -> Result<Option<usize>, String> {
+    let request: WsRequest =
+        serde_json::from_str(text).map_err(|e| format!("invalid request: {}", e))?;
+    check_input_length(&request.input, options.max_input_chars).map_err(|e| e.to_string())?;
+    if request.input.trim().is_empty() {
+        return Err("no text to process".to_string());
+    }
+    let voice = request.voice.to_kokoro_voice(options);
+    if let Some(tenant) = tenant {
+        tenant.check_voice(&voice).map_err(|e| e.to_string())?;
+    }
+    if let (Some(quotas), Some(key)) = (&options.quotas, key) {
+        quotas
+            .charge(key, request.input.chars().count())
+            .map_err(|e| e.to_string())?;
+    }
+    info!(
+        "WebSocket request: {} characters at {} bps",
+        request.input.chars().count(),
+        request.bitrate
+    );
+
+    stats.request(&voice);
+    let synth = SynthRequest {
+        speed: request.speed.0,
+        priority: request.priority,
+        ..SynthRequest::new(request.input, voice)
+    };
+    let mut frames = tts
+        .synth_stream_async(synth, BUFFER_CHUNKS)
+        .into_opus_frames(request.bitrate)
+        .map_err(|e| e.to_string())?;
+    let mut count = 0;
+    while let Some(frame) = frames.next().await {
+        let frame = frame.map_err(|e| e.to_string())?;
+        if socket.send(Message::Binary(frame.into())).await.is_err() {
+            // Dropping the frames stops the synthesis
+            return Ok(None);
+        }
+        count += 1;
+    }
+    Ok(Some(count))
+}
@@ -0,0 +1,21 @@
+from typing import Iterator
+
+import numpy as np
+import numpy.typing as npt
+
+SAMPLE_RATE: int
+
+class Kokoro:
+    sample_rate: int
+    def __init__(self, model_path: str, voices_path: str) -> None: ...
+    def synth(
+        self, text: str, voice: str = "af_sky", speed: float = 1.0, lan: str = "en-us"
+    ) -> npt.NDArray[np.float32]: ...
+    def synth_stream(
+        self, text: str, voice: str = "af_sky", speed: float = 1.0, lan: str = "en-us"
+    ) -> AudioStream: ...
+    def voices(self) -> list[str]: ...
+
+class AudioStream(Iterator[npt.NDArray[np.float32]]):
+    def __iter__(self) -> AudioStream: ...
+    def __next__(self) -> npt.NDArray[np.float32]: ...
@@ -0,0 +1,146 @@
+//! Python bindings for the Kokoros engine, built with maturin as the `kokoros` module.
+//!
+//! ```python
+//! import kokoros
+//!
+//! tts = kokoros.Kokoro("checkpoints/kokoro-v1.0.onnx", "data/voices-v1.0.bin")
+//! audio = tts.synth("Hello from Python", voice="af_sky")  # float32 numpy array
+//! for chunk in tts.synth_stream(long_text, voice="bm_george"):
+//!     play(chunk, kokoros.SAMPLE_RATE)
+//! ```
+
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, sync_channel};
+
+use numpy::{PyArray1, ToPyArray};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+
+use kokoros::tts::koko::TTSKoko;
+
+/// Sample rate of all audio produced, in Hz
+const SAMPLE_RATE: u32 = 24_000;
+
+/// A loaded Kokoro model and voices file
+#[pyclass(frozen)]
+struct Kokoro {
+    tts: Arc<TTSKoko>,
+}
+
+#[pymethods]
+impl Kokoro {
+    /// Loads the ONNX model and voices file, downloading them to the given paths if they
+    /// don't exist yet
+    #[new]
+    fn new(py: Python<'_>, model_path: &str, voices_path: &str) -> PyResult<Self> {
+        let tts = py
+            .allow_threads(|| -> Result<TTSKoko, String> {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                runtime
+                    .block_on(TTSKoko::builder(model_path, voices_path).build())
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(PyRuntimeError::new_err)?;
+        Ok(Self { tts: Arc::new(tts) })
+    }
+
+    /// Synthesizes `text` and returns the mono audio as a float32 numpy array at
+    /// `SAMPLE_RATE`. `voice` may be a mix like "af_sky.4+af_nicole.6".
+    #[pyo3(signature = (text, voice = "af_sky", speed = 1.0, lan = "en-us"))]
+    fn synth<'py>(
+        &self,
+        py: Python<'py>,
+        text: &str,
+        voice: &str,
+        speed: f32,
+        lan: &str,
+    ) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        let audio = py
+            .allow_threads(|| {
+                self.tts
+                    .tts_raw_audio(text, lan, voice, speed, None, None, None, None)
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(PyRuntimeError::new_err)?;
+        Ok(audio.to_pyarray(py))
+    }
+
+    /// Synthesizes `text` sentence by sentence in the background, yielding each chunk of
+    /// audio as a float32 numpy array as soon as it is ready
+    #[pyo3(signature = (text, voice = String::from("af_sky"), speed = 1.0, lan = String::from("en-us")))]
+    fn synth_stream(&self, text: String, voice: String, speed: f32, lan: String) -> AudioStream {
+        // A small buffer keeps synthesis a little ahead of the consumer without running away
+        let (sender, receiver) = sync_channel(2);
+        let tts = Arc::clone(&self.tts);
+        std::thread::spawn(move || {
+            let result = tts.tts_raw_audio_streaming(
+                &text,
+                &lan,
+                &voice,
+                speed,
+                None,
+                None,
+                None,
+                None,
+                |audio| {
+                    // The consumer went away, stop synthesizing
+                    sender
+                        .send(Ok(audio))
+                        .map_err(|_| "stream closed".to_string().into())
+                },
+            );
+            if let Err(e) = result {
+                let _ = sender.send(Err(e.to_string()));
+            }
+        });
+        AudioStream {
+            receiver: std::sync::Mutex::new(receiver),
+        }
+    }
+
+    /// Names of the voices in the loaded voices file
+    fn voices(&self) -> Vec<String> {
+        self.tts.get_available_voices()
+    }
+
+    /// Sample rate of the audio produced, in Hz
+    #[getter]
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+}
+
+/// Iterator over the audio chunks of `Kokoro.synth_stream`
+#[pyclass]
+struct AudioStream {
+    receiver: std::sync::Mutex<Receiver<Result<Vec<f32>, String>>>,
+}
+
+#[pymethods]
+impl AudioStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyArray1<f32>>>> {
+        let next = py.allow_threads(|| self.receiver.lock().unwrap().recv());
+        match next {
+            Ok(Ok(audio)) => Ok(Some(audio.to_pyarray(py))),
+            Ok(Err(e)) => Err(PyRuntimeError::new_err(e)),
+            // The synthesis thread finished
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "kokoros")]
+fn kokoros_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("SAMPLE_RATE", SAMPLE_RATE)?;
+    m.add_class::<Kokoro>()?;
+    m.add_class::<AudioStream>()?;
+    Ok(())
+}
@@ -0,0 +1,109 @@
+//! Swift and Kotlin bindings (UniFFI) for a mobile-friendly subset of the engine: load a
+//! bundled model, synthesize to a PCM buffer and list voices. Generate the bindings with
+//!
+//! ```text
+//! cargo run -p kokoros-uniffi --bin uniffi-bindgen -- generate \
+//!     --library target/release/libkokoros_uniffi.so --language swift --out-dir out
+//! ```
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use kokoros::tts::koko::TTSKoko;
+
+uniffi::setup_scaffolding!();
+
+/// Sample rate of all audio produced, in Hz (mono, 32-bit float)
+pub const SAMPLE_RATE: u32 = 24_000;
+
+#[derive(Debug, uniffi::Error)]
+pub enum KokorosError {
+    /// The model or voices file is not at the given path
+    FileNotFound { path: String },
+    /// Synthesis failed, e.g. because of an unknown voice
+    Synthesis { message: String },
+}
+
+impl fmt::Display for KokorosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FileNotFound { path } => write!(f, "file not found: {}", path),
+            Self::Synthesis { message } => write!(f, "synthesis failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for KokorosError {}
+
+/// A loaded Kokoro model and voices file; safe to share between threads
+#[derive(uniffi::Object)]
+pub struct KokorosEngine {
+    tts: TTSKoko,
+}
+
+#[uniffi::export]
+impl KokorosEngine {
+    /// Loads the model and voices bundled with the app. Unlike the CLI this never
+    /// downloads anything, so a missing file is reported as `FileNotFound`.
+    #[uniffi::constructor]
+    pub fn new(model_path: String, voices_path: String) -> Result<Arc<Self>, KokorosError> {
+        for path in [&model_path, &voices_path] {
+            if !Path::new(path).exists() {
+                return Err(KokorosError::FileNotFound { path: path.clone() });
+            }
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| KokorosError::Synthesis {
+                message: e.to_string(),
+            })?;
+        let tts = runtime
+            .block_on(TTSKoko::builder(&model_path, &voices_path).build())
+            .map_err(|e| KokorosError::Synthesis {
+                message: e.to_string(),
+            })?;
+        Ok(Arc::new(Self { tts }))
+    }
+
+    /// Synthesizes `text` into mono samples at `SAMPLE_RATE`. `lan` is an espeak-ng
+    /// language code such as "en-us".
+    pub fn synthesize(
+        &self,
+        text: String,
+        voice: String,
+        speed: f32,
+        lan: String,
+    ) -> Result<Vec<f32>, KokorosError> {
+        self.tts
+            .tts_raw_audio(&text, &lan, &voice, speed, None, None, None, None)
+            .map_err(|e| KokorosError::Synthesis {
+                message: e.to_string(),
+            })
+    }
+
+    /// Names of the voices in the loaded voices file
+    pub fn voices(&self) -> Vec<String> {
+        self.tts.get_available_voices()
+    }
+}
+
+/// Sample rate of the audio produced, in Hz
+#[uniffi::export]
+pub fn sample_rate() -> u32 {
+    SAMPLE_RATE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_model_is_reported() {
+        let err = KokorosEngine::new("missing.onnx".into(), "missing.bin".into())
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "file not found: missing.onnx");
+    }
+}
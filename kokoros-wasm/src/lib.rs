@@ -0,0 +1,101 @@
+//! WebAssembly build of the Kokoros tokenizer and voice styles, for running Kokoro in
+//! the browser with onnxruntime-web. espeak-ng is not available here, so phonemes have
+//! to come from a JavaScript phonemizer.
+//!
+//! Build with `wasm-pack build kokoros-wasm --target web`.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use kokoros::tts::tokenize::tokenize_for;
+use kokoros::tts::vocab::ModelVersion;
+use kokoros::tts::voices::{self, VoiceStyles};
+use wasm_bindgen::prelude::*;
+
+/// Sample rate of the audio the model produces, in Hz
+#[wasm_bindgen(js_name = sampleRate)]
+pub fn sample_rate() -> u32 {
+    24_000
+}
+
+/// Token ids for a phoneme string in the vocabulary of `version`, padded with the
+/// start/end token the model expects
+fn padded_tokens(phonemes: &str, version: ModelVersion) -> Vec<i64> {
+    let mut tokens = vec![0];
+    tokens.extend(tokenize_for(phonemes, version));
+    tokens.push(0);
+    tokens
+}
+
+/// Token ids for a phoneme string, padded with the start/end token the model expects,
+/// ready to use as the `input_ids` (int64) tensor of a model of release `version`
+/// (`"0.19"` or `"1.0"`, the default)
+#[wasm_bindgen(js_name = tokenize)]
+pub fn tokenize_phonemes(phonemes: &str, version: Option<String>) -> Result<Vec<i64>, JsError> {
+    let version = match version {
+        Some(version) => version.parse().map_err(|e: String| JsError::new(&e))?,
+        None => ModelVersion::default(),
+    };
+    Ok(padded_tokens(phonemes, version))
+}
+
+/// Style vectors of the voices in a voices file
+#[wasm_bindgen]
+pub struct Voices {
+    styles: HashMap<String, VoiceStyles>,
+}
+
+#[wasm_bindgen]
+impl Voices {
+    /// Parses the contents of a voices file such as `voices-v1.0.bin`
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8]) -> Result<Voices, JsError> {
+        let styles =
+            voices::read_voices(Cursor::new(bytes)).map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(Self { styles })
+    }
+
+    /// Voice names, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.styles.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The `style` tensor (256 floats) for `voice` and an utterance of `token_count`
+    /// tokens, not counting the padding, picked the way the native synthesis does
+    pub fn style(&self, voice: &str, token_count: usize) -> Result<Vec<f32>, JsError> {
+        let styles = self
+            .styles
+            .get(voice)
+            .ok_or_else(|| JsError::new(&format!("unknown voice: {}", voice)))?;
+        let style = voices::voice_style(styles, token_count)
+            .ok_or_else(|| JsError::new(&format!("voice {} has no styles", voice)))?;
+        Ok(style.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_pads() {
+        let tokens = tokenize_phonemes("hˈɛloʊ", None).unwrap();
+        assert_eq!(tokens.first(), Some(&0));
+        assert_eq!(tokens.last(), Some(&0));
+        assert_eq!(
+            &tokens[1..tokens.len() - 1],
+            tokenize_for("hˈɛloʊ", ModelVersion::V1_0).as_slice()
+        );
+    }
+
+    #[test]
+    fn test_tokenize_version() {
+        // Brackets are only in the v1.0 vocabulary
+        let v0_19 = tokenize_phonemes("(hˈɛloʊ)", Some("0.19".to_string())).unwrap();
+        let v1_0 = tokenize_phonemes("(hˈɛloʊ)", Some("1.0".to_string())).unwrap();
+        assert_eq!(v0_19, padded_tokens("(hˈɛloʊ)", ModelVersion::V0_19));
+        assert_eq!(v1_0.len(), v0_19.len() + 2);
+    }
+}
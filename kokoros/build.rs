@@ -0,0 +1,108 @@
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+/// Where espeak-ng data is commonly installed, tried when `ESPEAK_NG_DATA_DIR` isn't set
+const SYSTEM_DATA_DIRS: &[&str] = &[
+    "/usr/share/espeak-ng-data",
+    "/usr/lib/x86_64-linux-gnu/espeak-ng-data",
+    "/usr/lib/aarch64-linux-gnu/espeak-ng-data",
+    "/usr/local/share/espeak-ng-data",
+    "/opt/homebrew/share/espeak-ng-data",
+];
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=ESPEAK_NG_DATA_DIR");
+    if std::env::var_os("CARGO_FEATURE_BUNDLED_ESPEAK_DATA").is_some() {
+        bundle_espeak_data();
+    }
+}
+
+/// A directory of compiled espeak-ng data, recognized by its phoneme table
+fn is_data_dir(dir: &Path) -> bool {
+    dir.join("phontab").is_file()
+}
+
+/// The data espeak-rs-sys compiled along with the library, which is sure to be of the same
+/// version; it is installed into that crate's build output, next to ours
+fn espeak_rs_sys_data_dir(out_dir: &Path) -> Option<PathBuf> {
+    let build_dir = out_dir.parent()?.parent()?;
+    fs::read_dir(build_dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with("espeak-rs-sys-")
+        })
+        .map(|entry| entry.path().join("out/share/espeak-ng-data"))
+        .find(|dir| is_data_dir(dir))
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", dir.display(), e))
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// Writes `espeak_data.rs` with every file of the espeak-ng data directory embedded, and a
+/// hash of their contents that tells versions of the data apart
+fn bundle_espeak_data() {
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let data_dir = match std::env::var_os("ESPEAK_NG_DATA_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => espeak_rs_sys_data_dir(&out_dir)
+            .or_else(|| {
+                SYSTEM_DATA_DIRS
+                    .iter()
+                    .map(PathBuf::from)
+                    .find(|dir| is_data_dir(dir))
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "bundled-espeak-data: no espeak-ng data found, set ESPEAK_NG_DATA_DIR to \
+                     an espeak-ng-data directory"
+                )
+            }),
+    };
+    if !is_data_dir(&data_dir) {
+        panic!(
+            "bundled-espeak-data: {} is not an espeak-ng-data directory (no phontab)",
+            data_dir.display()
+        );
+    }
+    println!("cargo:rerun-if-changed={}", data_dir.display());
+
+    let mut files = Vec::new();
+    collect_files(&data_dir, &mut files);
+    let mut hasher = DefaultHasher::new();
+    let mut source = String::from("pub static FILES: &[(&str, &[u8])] = &[\n");
+    for path in &files {
+        let relative = path.strip_prefix(&data_dir).unwrap();
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        relative.hash(&mut hasher);
+        fs::read(path).unwrap().hash(&mut hasher);
+        source.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            relative,
+            path.display().to_string()
+        ));
+    }
+    source.push_str("];\n");
+    source.push_str(&format!(
+        "pub const VERSION: &str = \"{:016x}\";\n",
+        hasher.finish()
+    ));
+    fs::write(out_dir.join("espeak_data.rs"), source).unwrap();
+}
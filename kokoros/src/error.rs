@@ -0,0 +1,100 @@
+//! The error type of the library, so callers can tell failures apart by their cause
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum KokorosError {
+    /// The model or its session could not be loaded
+    ModelLoad(String),
+    /// espeak could not turn the text into phonemes
+    Phonemize(String),
+    /// The phonemes could not be turned into model input
+    Tokenize(String),
+    /// The model failed to run, or returned something unexpected
+    Inference(String),
+    /// The audio could not be encoded or written out
+    Encode(String),
+    /// An unknown voice, a malformed blend or morph, or an unreadable voice file
+    InvalidVoice(String),
+    /// Synthesis was stopped before it finished, e.g. because its consumer went away
+    Cancelled,
+    /// A callback handed the audio returned this error
+    Callback(String),
+    /// A chat endpoint couldn't be reached, refused the request, or sent a stream that
+    /// couldn't be read
+    Chat(String),
+    /// A text plugin failed on the input
+    Plugin(String),
+    /// Options that are out of range or don't go together, e.g. when building an engine
+    Config(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for KokorosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ModelLoad(e) => write!(f, "failed to load model: {}", e),
+            Self::Phonemize(e) => write!(f, "phonemization failed: {}", e),
+            Self::Tokenize(e) => write!(f, "tokenization failed: {}", e),
+            Self::Inference(e) => write!(f, "inference failed: {}", e),
+            Self::Encode(e) => write!(f, "encoding failed: {}", e),
+            Self::InvalidVoice(e) => write!(f, "invalid voice: {}", e),
+            Self::Cancelled => write!(f, "synthesis cancelled"),
+            Self::Callback(e) => write!(f, "{}", e),
+            Self::Chat(e) => write!(f, "chat completion failed: {}", e),
+            Self::Plugin(e) => write!(f, "text plugin failed: {}", e),
+            Self::Config(e) => write!(f, "invalid configuration: {}", e),
+            Self::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for KokorosError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KokorosError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(feature = "tts")]
+impl From<ort::Error> for KokorosError {
+    fn from(e: ort::Error) -> Self {
+        Self::Inference(e.to_string())
+    }
+}
+
+impl KokorosError {
+    /// The error a synthesis callback returned: passed through as is when it is already a
+    /// `KokorosError` (e.g. [`KokorosError::Cancelled`]), wrapped otherwise
+    pub fn from_callback(e: Box<dyn std::error::Error>) -> Self {
+        match e.downcast::<Self>() {
+            Ok(e) => *e,
+            Err(e) => Self::Callback(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_callback() {
+        let cancelled: Box<dyn std::error::Error> = Box::new(KokorosError::Cancelled);
+        assert!(matches!(
+            KokorosError::from_callback(cancelled),
+            KokorosError::Cancelled
+        ));
+        let other = KokorosError::from_callback("disk full".into());
+        assert!(matches!(&other, KokorosError::Callback(e) if e == "disk full"));
+    }
+}
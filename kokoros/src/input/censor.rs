@@ -0,0 +1,108 @@
+//! Profanity filtering for public kiosks and broadcast: configured words are bleeped,
+//! left out as silence, or read as "bleep" instead, by rewriting them into the
+//! [sound tokens](crate::input::sounds) the rest of the text is synthesized around.
+
+use std::borrow::Cow;
+
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+/// What a filtered word becomes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CensorMode {
+    /// A tone, as on broadcast
+    #[default]
+    Bleep,
+    /// Silence about as long as the word
+    Silence,
+    /// The word "bleep", spoken in the voice
+    Mask,
+}
+
+impl std::str::FromStr for CensorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bleep" => Ok(CensorMode::Bleep),
+            "silence" => Ok(CensorMode::Silence),
+            "mask" => Ok(CensorMode::Mask),
+            _ => Err(format!(
+                "unknown censor mode {:?}, expected bleep, silence or mask",
+                s
+            )),
+        }
+    }
+}
+
+/// Words to filter, matched whole and ignoring case
+#[derive(Debug, Clone)]
+pub struct Censor {
+    words: Regex,
+    mode: CensorMode,
+}
+
+/// The words of a word list: one per line, skipping blank lines and `#` comments
+pub fn parse_word_list(list: &str) -> Vec<&str> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+impl Censor {
+    /// A filter for `words`, or `None` if there are none. A word ending in `*` also
+    /// matches anything starting with it, e.g. `darn*` for "darned".
+    pub fn new<S: AsRef<str>>(words: &[S], mode: CensorMode) -> Option<Self> {
+        let alternatives: Vec<String> = words
+            .iter()
+            .map(|word| match word.as_ref().strip_suffix('*') {
+                Some(stem) => format!(r"{}\w*", regex::escape(stem)),
+                None => regex::escape(word.as_ref()),
+            })
+            .collect();
+        if alternatives.is_empty() {
+            return None;
+        }
+        let words = RegexBuilder::new(&format!(r"\b(?:{})\b", alternatives.join("|")))
+            .case_insensitive(true)
+            .build()
+            .ok()?;
+        Some(Self { words, mode })
+    }
+
+    /// `text` with every filtered word replaced
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.words
+            .replace_all(text, |caps: &regex::Captures| match self.mode {
+                CensorMode::Bleep => "[beep]".to_string(),
+                CensorMode::Silence => {
+                    let millis = (caps[0].chars().count() * 80).clamp(250, 800);
+                    format!("[pause:{}ms]", millis)
+                }
+                CensorMode::Mask => "bleep".to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_censor() {
+        let words = parse_word_list("# house rules\nheck\n\ndarn*\n");
+        assert_eq!(words, ["heck", "darn*"]);
+        let censor = Censor::new(&words, CensorMode::Bleep).unwrap();
+        assert_eq!(
+            censor.apply("What the Heck, darned check!"),
+            "What the [beep], [beep] check!"
+        );
+        let censor = Censor::new(&words, CensorMode::Silence).unwrap();
+        assert_eq!(censor.apply("oh heck"), "oh [pause:320ms]");
+        let censor = Censor::new(&words, CensorMode::Mask).unwrap();
+        assert_eq!(censor.apply("heck"), "bleep");
+        assert!(Censor::new::<&str>(&[], CensorMode::Bleep).is_none());
+    }
+}
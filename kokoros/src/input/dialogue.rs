@@ -0,0 +1,106 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// A quote in straight, curly or angle quotes, marks included
+pub(crate) const QUOTE_PATTERN: &str = r#""[^"]+"|“[^”]+”|«[^»]+»|„[^“”]+[“”]"#;
+
+lazy_static! {
+    static ref SPEAKER_RE: Regex = Regex::new(r"\[([A-Za-z0-9_-]+)\]").unwrap();
+    static ref QUOTE_RE: Regex = Regex::new(QUOTE_PATTERN).unwrap();
+}
+
+/// One speaker's turn in a dialogue script
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turn {
+    /// The `[speaker]` tag in effect, or `None` for text before the first tag
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+/// Splits a script such as `[sarah] Hello. [tom] Hi!` into turns at its speaker tags.
+/// A tag stays in effect until the next one, across line breaks; turns without any
+/// text are dropped.
+pub fn parse_dialogue(script: &str) -> Vec<Turn> {
+    let mut turns = Vec::new();
+    let mut speaker = None;
+    let mut rest = 0;
+    let mut push = |speaker: &Option<String>, text: &str| {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !text.is_empty() {
+            turns.push(Turn {
+                speaker: speaker.clone(),
+                text,
+            });
+        }
+    };
+    for tag in SPEAKER_RE.captures_iter(script) {
+        // `[beep]` is a sound in the line, not a speaker
+        if tag[1].eq_ignore_ascii_case("beep") {
+            continue;
+        }
+        let whole = tag.get(0).unwrap();
+        push(&speaker, &script[rest..whole.start()]);
+        speaker = Some(tag[1].to_string());
+        rest = whole.end();
+    }
+    push(&speaker, &script[rest..]);
+    turns
+}
+
+/// Splits prose into narration and quoted speech, as `(quoted, text)` pairs in order.
+/// Straight, curly and angle quotes are recognized; a quote left open runs into the
+/// narration.
+pub fn split_quotes(text: &str) -> Vec<(bool, String)> {
+    let mut spans = Vec::new();
+    let mut rest = 0;
+    let mut push = |quoted: bool, text: &str| {
+        let text = text.trim();
+        if text.chars().any(char::is_alphanumeric) {
+            spans.push((quoted, text.to_string()));
+        }
+    };
+    for quote in QUOTE_RE.find_iter(text) {
+        push(false, &text[rest..quote.start()]);
+        // Drop the quote marks, which aren't spoken anyway
+        let inner = quote.as_str();
+        let open = inner.chars().next().unwrap().len_utf8();
+        let close = inner.chars().last().unwrap().len_utf8();
+        push(true, &inner[open..inner.len() - close]);
+        rest = quote.end();
+    }
+    push(false, &text[rest..]);
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dialogue() {
+        let turns =
+            parse_dialogue("It was late.\n[sarah] Hello.\nAre you there? [tom] Hi! [sarah]");
+        let turn = |speaker: Option<&str>, text: &str| Turn {
+            speaker: speaker.map(String::from),
+            text: text.to_string(),
+        };
+        assert_eq!(
+            turns,
+            vec![
+                turn(None, "It was late."),
+                turn(Some("sarah"), "Hello. Are you there?"),
+                turn(Some("tom"), "Hi!"),
+            ]
+        );
+
+        assert_eq!(
+            split_quotes("\"Wait,\" she said. “Don't go.” He left."),
+            vec![
+                (true, "Wait,".to_string()),
+                (false, "she said.".to_string()),
+                (true, "Don't go.".to_string()),
+                (false, "He left.".to_string()),
+            ]
+        );
+    }
+}
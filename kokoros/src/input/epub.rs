@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use zip::ZipArchive;
+
+use crate::input::html::{decode_entities, strip_html};
+
+lazy_static! {
+    static ref ROOTFILE_RE: Regex = Regex::new(r#"<rootfile\s[^>]*full-path="([^"]+)""#).unwrap();
+    pub(crate) static ref ITEM_RE: Regex = Regex::new(r"<(?:opf:)?item\s[^>]*>").unwrap();
+    static ref ITEMREF_RE: Regex = Regex::new(r"<(?:opf:)?itemref\s[^>]*>").unwrap();
+    static ref ATTR_RE: Regex = Regex::new(r#"([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    static ref BOOK_TITLE_RE: Regex = Regex::new(r"(?s)<dc:title[^>]*>(.*?)</dc:title>").unwrap();
+    static ref HEADING_RE: Regex = Regex::new(r"(?is)<h[1-3][^>]*>(.*?)</h[1-3]>").unwrap();
+    static ref TITLE_RE: Regex = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+    static ref TAG_RE: Regex = Regex::new(r"<[^>]+>").unwrap();
+}
+
+/// A single chapter (spine document) of an EPUB, already reduced to plain text
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub title: String,
+    /// Path of the chapter document inside the EPUB container
+    pub href: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct EpubBook {
+    pub title: Option<String>,
+    pub chapters: Vec<Chapter>,
+}
+
+pub(crate) fn attributes(tag: &str) -> HashMap<String, String> {
+    ATTR_RE
+        .captures_iter(tag)
+        .map(|caps| {
+            let value = caps
+                .get(2)
+                .or_else(|| caps.get(3))
+                .map_or("", |m| m.as_str());
+            (caps[1].to_string(), decode_entities(value))
+        })
+        .collect()
+}
+
+pub(crate) fn read_entry<R: Read + std::io::Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|e| format!("EPUB entry '{}' not found: {}", name, e))?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Resolves an OPF-relative href (which may be percent-encoded) against the OPF's directory
+pub(crate) fn resolve_href(opf_dir: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or(href);
+    let href = percent_decode_str(href).decode_utf8_lossy();
+    let mut parts: Vec<&str> = opf_dir.split('/').filter(|p| !p.is_empty()).collect();
+    for segment in href.split('/') {
+        match segment {
+            "." | "" => {}
+            ".." => {
+                parts.pop();
+            }
+            s => parts.push(s),
+        }
+    }
+    parts.join("/")
+}
+
+pub(crate) fn inline_text(html: &str) -> String {
+    let text = decode_entities(&TAG_RE.replace_all(html, " "));
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn chapter_title(html: &str, fallback: usize) -> String {
+    HEADING_RE
+        .captures(html)
+        .or_else(|| TITLE_RE.captures(html))
+        .map(|caps| inline_text(&caps[1]))
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| format!("Chapter {}", fallback))
+}
+
+/// The package document of an EPUB and the documents of its spine
+pub(crate) struct Package {
+    /// Path of the package document (OPF) inside the container
+    pub path: String,
+    pub opf: String,
+    /// Paths of the spine's XHTML documents inside the container, in reading order
+    pub spine: Vec<String>,
+}
+
+impl Package {
+    /// Directory of the package document, which its hrefs are relative to
+    pub fn dir(&self) -> &str {
+        self.path.rsplit_once('/').map_or("", |(dir, _)| dir)
+    }
+}
+
+pub(crate) fn read_package<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+) -> Result<Package, Box<dyn Error>> {
+    let container = read_entry(archive, "META-INF/container.xml")?;
+    let path = ROOTFILE_RE
+        .captures(&container)
+        .map(|caps| caps[1].to_string())
+        .ok_or("EPUB container.xml does not reference a package document")?;
+    let opf = read_entry(archive, &path)?;
+    let opf_dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+
+    // Manifest: id -> href of every XHTML document
+    let mut manifest = HashMap::new();
+    for tag in ITEM_RE.find_iter(&opf) {
+        let attrs = attributes(tag.as_str());
+        if let (Some(id), Some(href)) = (attrs.get("id"), attrs.get("href")) {
+            let media_type = attrs.get("media-type").map_or("", |m| m.as_str());
+            if media_type.contains("html") {
+                manifest.insert(id.clone(), resolve_href(opf_dir, href));
+            }
+        }
+    }
+    let spine = ITEMREF_RE
+        .find_iter(&opf)
+        .filter_map(|tag| {
+            let attrs = attributes(tag.as_str());
+            attrs.get("idref").and_then(|id| manifest.get(id)).cloned()
+        })
+        .collect();
+    Ok(Package { path, opf, spine })
+}
+
+/// Reads an EPUB file and returns its spine documents, in reading order, as plain-text chapters.
+///
+/// Chapters without any text (covers, image-only pages) are skipped.
+pub fn read_epub(path: impl AsRef<Path>) -> Result<EpubBook, Box<dyn Error>> {
+    read_epub_from(File::open(path)?)
+}
+
+/// Like [`read_epub`], for an EPUB that isn't in a file, e.g. an upload held in memory
+pub fn read_epub_from(reader: impl Read + Seek) -> Result<EpubBook, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let package = read_package(&mut archive)?;
+
+    let title = BOOK_TITLE_RE
+        .captures(&package.opf)
+        .map(|caps| inline_text(&caps[1]))
+        .filter(|t| !t.is_empty());
+
+    let mut chapters = Vec::new();
+    for href in &package.spine {
+        let html = read_entry(&mut archive, href)?;
+        let text = strip_html(&html);
+        if text.trim().is_empty() {
+            continue;
+        }
+        chapters.push(Chapter {
+            title: chapter_title(&html, chapters.len() + 1),
+            href: href.clone(),
+            text,
+        });
+    }
+
+    if chapters.is_empty() {
+        return Err("EPUB contains no readable chapters".into());
+    }
+
+    Ok(EpubBook { title, chapters })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_href() {
+        assert_eq!(
+            resolve_href("OEBPS", "text/ch1.xhtml"),
+            "OEBPS/text/ch1.xhtml"
+        );
+        assert_eq!(
+            resolve_href("OEBPS/content", "../ch%202.xhtml#p1"),
+            "OEBPS/ch 2.xhtml"
+        );
+        assert_eq!(resolve_href("", "ch1.xhtml"), "ch1.xhtml");
+        assert_eq!(
+            resolve_href("OEBPS", "Kapitel%20%C3%BCber%20%28I%29.xhtml"),
+            "OEBPS/Kapitel über (I).xhtml"
+        );
+    }
+
+    #[test]
+    fn test_strip_chapter_markup() {
+        let html = r#"<html><head><title>One</title></head><body>
+            <h1>Chapter One</h1><p>It was a &ldquo;dark&rdquo;&nbsp;night.</p><p>The end</p></body></html>"#;
+        assert_eq!(chapter_title(html, 1), "Chapter One");
+        assert_eq!(
+            strip_html(html),
+            "Chapter One.\n\nIt was a \u{201C}dark\u{201D} night.\n\nThe end"
+        );
+    }
+}
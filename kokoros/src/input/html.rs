@@ -0,0 +1,106 @@
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+lazy_static! {
+    static ref HEAD_RE: Regex = Regex::new(r"(?is)<head[\s>].*?</head>").unwrap();
+    static ref SCRIPT_STYLE_RE: Regex =
+        Regex::new(r"(?is)<script[\s>].*?</script>|<style[\s>].*?</style>").unwrap();
+    static ref COMMENT_RE: Regex = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    static ref BLOCK_TAG_RE: Regex = Regex::new(
+        r"(?i)</?(?:p|div|br|h[1-6]|li|ul|ol|tr|table|section|article|blockquote|hr)(?:\s[^>]*)?/?>"
+    )
+    .unwrap();
+    static ref HEADING_END_RE: Regex = Regex::new(r"(?i)</h[1-6]\s*>").unwrap();
+    static ref TAG_RE: Regex = Regex::new(r"<[^>]+>").unwrap();
+    static ref ENTITY_RE: Regex = Regex::new(r"&(#x[0-9a-fA-F]+|#[0-9]+|[a-zA-Z]+);").unwrap();
+    static ref INLINE_SPACE_RE: Regex = Regex::new(r"[ \t\r\f\v\u{a0}]+").unwrap();
+    static ref BLANK_LINES_RE: Regex = Regex::new(r"\n\s*\n[\s\n]*").unwrap();
+}
+
+/// Marks the end of a heading until the text is split into lines, from the private use
+/// area so it can't occur in a document
+const HEADING_END: char = '\u{E000}';
+
+/// Decodes the named and numeric character references commonly found in (X)HTML
+pub fn decode_entities(text: &str) -> String {
+    ENTITY_RE
+        .replace_all(text, |caps: &Captures| {
+            let entity = &caps[1];
+            let decoded = if let Some(hex) = entity.strip_prefix("#x") {
+                u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+            } else if let Some(dec) = entity.strip_prefix('#') {
+                dec.parse::<u32>().ok().and_then(char::from_u32)
+            } else {
+                match entity {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    "nbsp" => Some(' '),
+                    "mdash" => Some('—'),
+                    "ndash" => Some('–'),
+                    "hellip" => Some('…'),
+                    "lsquo" => Some('\u{2018}'),
+                    "rsquo" => Some('\u{2019}'),
+                    "ldquo" => Some('\u{201C}'),
+                    "rdquo" => Some('\u{201D}'),
+                    _ => None,
+                }
+            };
+            decoded.map_or_else(|| caps[0].to_string(), |c| c.to_string())
+        })
+        .to_string()
+}
+
+/// Converts an (X)HTML document into plain text suitable for synthesis.
+///
+/// Block-level elements become line breaks (headings also get a period so they
+/// are read as a sentence), everything else is dropped and entities are decoded.
+pub fn strip_html(html: &str) -> String {
+    let text = HEAD_RE.replace_all(html, "");
+    let text = SCRIPT_STYLE_RE.replace_all(&text, "");
+    let text = COMMENT_RE.replace_all(&text, "");
+    let text = HEADING_END_RE.replace_all(&text, format!("{}\n", HEADING_END));
+    let text = BLOCK_TAG_RE.replace_all(&text, "\n");
+    let text = TAG_RE.replace_all(&text, "");
+    let text = decode_entities(&text);
+
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| INLINE_SPACE_RE.replace_all(line, " ").trim().to_string())
+        .map(|line| match line.strip_suffix(HEADING_END) {
+            // Headings without punctuation of their own get a period
+            Some(heading) => {
+                let heading = heading.trim_end();
+                if heading.is_empty() || heading.ends_with(['.', '!', '?', ':']) {
+                    heading.to_string()
+                } else {
+                    format!("{}.", heading)
+                }
+            }
+            None => line,
+        })
+        .collect();
+
+    BLANK_LINES_RE
+        .replace_all(&lines.join("\n"), "\n\n")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_html() {
+        let html = "<html><head><title>T</title></head><body>\
+                    <h1>Chapter One</h1><h2>Why?</h2><h3></h3>\
+                    <p>Wait...</p><p>Fish &amp; chips.</p></body></html>";
+        assert_eq!(
+            strip_html(html),
+            "Chapter One.\n\nWhy?\n\nWait...\n\nFish & chips."
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref FENCED_CODE_RE: Regex =
+        Regex::new(r"(?ms)^\s*(```|~~~).*?^\s*(```|~~~)\s*$").unwrap();
+    static ref HTML_TAG_RE: Regex = Regex::new(r"<[^>\n]+>").unwrap();
+    static ref IMAGE_RE: Regex = Regex::new(r"!\[([^\]]*)\]\([^)]*\)").unwrap();
+    static ref LINK_RE: Regex = Regex::new(r"\[([^\]]+)\]\([^)]*\)").unwrap();
+    static ref HEADING_RE: Regex = Regex::new(r"(?m)^\s{0,3}#{1,6}\s+").unwrap();
+    static ref BLOCKQUOTE_RE: Regex = Regex::new(r"(?m)^\s*>\s?").unwrap();
+    static ref LIST_MARKER_RE: Regex = Regex::new(r"(?m)^\s*(?:[-*+]|\d+[.)])\s+").unwrap();
+    static ref RULE_RE: Regex = Regex::new(r"(?m)^\s*(?:[-*_]\s*){3,}$").unwrap();
+    /// Paired emphasis and code delimiters, longest first, each only where it starts or
+    /// ends a word, so `snake_case` and `2*3*4` keep theirs
+    static ref EMPHASIS_RES: Vec<Regex> = ["`", r"\*\*\*", "___", r"\*\*", "__", "~~", r"\*", "_"]
+        .iter()
+        .map(|delimiter| {
+            Regex::new(&format!(
+                r"(^|[^\w]){d}(\S|\S.*?\S){d}($|[^\w])",
+                d = delimiter
+            ))
+            .unwrap()
+        })
+        .collect();
+    static ref BLANK_LINES_RE: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+/// Reduces Markdown to the plain text that should be spoken.
+///
+/// Code blocks, images and HTML tags are dropped, link targets are replaced by
+/// their text, and heading/list/emphasis markers are removed. Headings are
+/// terminated with a period so they are read as their own sentence.
+pub fn strip_markdown(text: &str) -> String {
+    let text = FENCED_CODE_RE.replace_all(text, "");
+    let text = HTML_TAG_RE.replace_all(&text, "");
+    let text = IMAGE_RE.replace_all(&text, "");
+    let text = LINK_RE.replace_all(&text, "$1");
+    let text = RULE_RE.replace_all(&text, "");
+
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let is_heading = HEADING_RE.is_match(line);
+        let line = HEADING_RE.replace(line, "");
+        let line = BLOCKQUOTE_RE.replace(&line, "");
+        let line = LIST_MARKER_RE.replace(&line, "");
+        let mut line = strip_emphasis(&line).trim().to_string();
+
+        if is_heading && !line.is_empty() && !line.ends_with(['.', '!', '?', ':']) {
+            line.push('.');
+        }
+        lines.push(line);
+    }
+
+    BLANK_LINES_RE
+        .replace_all(&lines.join("\n"), "\n\n")
+        .trim()
+        .to_string()
+}
+
+/// `line` without the delimiters of its emphasis and inline code
+fn strip_emphasis(line: &str) -> String {
+    let mut line = line.to_string();
+    for re in EMPHASIS_RES.iter() {
+        // A match takes the character after it, which may be where the next one starts
+        loop {
+            let stripped = re.replace_all(&line, "$1$2$3").into_owned();
+            if stripped == line {
+                break;
+            }
+            line = stripped;
+        }
+    }
+    line
+}
+
+/// The level of an ATX heading line (`# Title` is 1), with its text
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let trimmed = line.trim_start();
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let level = trimmed.chars().take_while(|&c| c == '#').count();
+    let rest = &trimmed[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim()))
+}
+
+/// Splits a Markdown book into chapters at its top-level headings: `#` headings, or `##`
+/// when there are none. Returns each chapter's title and Markdown, heading included; text
+/// before the first heading becomes a chapter of its own, titled `untitled`.
+pub fn split_chapters(text: &str, untitled: &str) -> Vec<(String, String)> {
+    let mut in_code = false;
+    let mut headings = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+        } else if !in_code && let Some((level, title)) = heading(line) {
+            headings.push((offset, level, title.to_string()));
+        }
+        offset += line.len();
+    }
+    let Some(top) = headings.iter().map(|(_, level, _)| *level).min() else {
+        return vec![(untitled.to_string(), text.to_string())];
+    };
+    let headings: Vec<_> = headings
+        .into_iter()
+        .filter(|(_, level, _)| *level == top)
+        .collect();
+
+    let mut chapters = Vec::new();
+    let preface = &text[..headings[0].0];
+    if !strip_markdown(preface).is_empty() {
+        chapters.push((untitled.to_string(), preface.to_string()));
+    }
+    for (i, (start, _, title)) in headings.iter().enumerate() {
+        let end = headings.get(i + 1).map_or(text.len(), |(next, _, _)| *next);
+        chapters.push((title.clone(), text[*start..end].to_string()));
+    }
+    chapters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_markdown() {
+        let md = "# Title\n\nSome *bold* and [a link](http://x.y).\n\n```rust\nlet x = 1;\n```\n\n- item one\n- item two";
+        assert_eq!(
+            strip_markdown(md),
+            "Title.\n\nSome bold and a link.\n\nitem one\nitem two"
+        );
+    }
+
+    #[test]
+    fn test_strip_emphasis() {
+        assert_eq!(
+            strip_emphasis("*a* _b_ **c d** __e__ ***f*** ~~g~~ `h`."),
+            "a b c d e f g h."
+        );
+        assert_eq!(
+            strip_emphasis("(*aside*), \"_quote_\""),
+            "(aside), \"quote\""
+        );
+        // Identifiers and arithmetic keep their symbols
+        assert_eq!(
+            strip_emphasis("Call snake_case_name or self.my_var_2 now"),
+            "Call snake_case_name or self.my_var_2 now"
+        );
+        assert_eq!(strip_emphasis("2*3*4 and 2 * 3 * 4"), "2*3*4 and 2 * 3 * 4");
+        assert_eq!(strip_emphasis("Run `my_func(a*b)`"), "Run my_func(a*b)");
+        assert_eq!(strip_emphasis("~/files and a_b"), "~/files and a_b");
+    }
+
+    #[test]
+    fn test_split_chapters() {
+        let md = "Foreword text.\n\n## One\n\nFirst.\n\n```\n## not a heading\n```\n\n### Part\n\n## Two ##\nSecond.\n";
+        let chapters = split_chapters(md, "Preface");
+        let titles: Vec<&str> = chapters.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(titles, ["Preface", "One", "Two"]);
+        assert!(chapters[1].1.contains("### Part"));
+        assert_eq!(chapters[2].1, "## Two ##\nSecond.\n");
+
+        assert_eq!(split_chapters("Just text.", "Book")[0].0, "Book");
+    }
+}
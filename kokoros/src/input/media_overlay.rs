@@ -0,0 +1,514 @@
+//! EPUB 3 media overlays: a copy of a book with its narration and, for every paragraph,
+//! heading and list item, the clip of the narration that reads it, so readers that
+//! support overlays (Apple Books, Thorium, ...) highlight the text as it is spoken.
+//!
+//! Each text block of a chapter gets an `id` (its own, if it has one) and is synthesized
+//! on its own. A block of several sentences also gets a `<span>` with an id around each
+//! one, so the reader highlights sentence by sentence, where the word timings of the
+//! read-along place them. The chapter's audio and SMIL file are stored next to the
+//! chapter, and the SMIL file pairs every id with its clip of the audio.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::input::epub::{
+    ITEM_RE, attributes, inline_text, read_entry, read_package, resolve_href,
+};
+use crate::input::html::decode_entities;
+use crate::tts::chunker;
+
+lazy_static! {
+    /// Elements whose text is highlighted as a whole. A block nested in another (a
+    /// paragraph in a list item) belongs to the outer one.
+    static ref BLOCK_RE: Regex = Regex::new(
+        r"(?is)<(p|h[1-6]|li|dt|dd|figcaption|caption|td|th)(\s[^>]*)?>(.*?)</(?:p|h[1-6]|li|dt|dd|figcaption|caption|td|th)\s*>"
+    )
+    .unwrap();
+    static ref TAG_RE: Regex = Regex::new(r"<(/?)([A-Za-z][\w:.-]*)[^>]*?(/?)>").unwrap();
+    static ref HEAD_END_RE: Regex = Regex::new(r"(?i)</head\s*>").unwrap();
+    static ref MANIFEST_END_RE: Regex = Regex::new(r"</(opf:)?manifest\s*>").unwrap();
+    static ref METADATA_END_RE: Regex = Regex::new(r"</(opf:)?metadata\s*>").unwrap();
+}
+
+/// Class readers put on the block being read
+pub const ACTIVE_CLASS: &str = "-epub-media-overlay-active";
+
+/// Elements without an end tag
+const VOID_ELEMENTS: &[&str] = &["br", "img", "wbr", "hr"];
+
+/// A paragraph, heading or other block of a chapter
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextBlock {
+    pub id: String,
+    pub text: String,
+    /// Ids of the spans around its sentences, in order; empty if it is read as one clip
+    pub sentences: Vec<String>,
+}
+
+/// Whether every element opened in `html` is closed in it, and none opened before it is
+fn balanced(html: &str) -> bool {
+    let mut open = Vec::new();
+    for caps in TAG_RE.captures_iter(html) {
+        let name = caps[2].to_ascii_lowercase();
+        if !caps[1].is_empty() {
+            if open.pop() != Some(name) {
+                return false;
+            }
+        } else if caps[3].is_empty() && !VOID_ELEMENTS.contains(&name.as_str()) {
+            open.push(name);
+        }
+    }
+    open.is_empty()
+}
+
+/// `inner`, the HTML of block `id` whose text is `text`, with a `<span>` around each of
+/// its sentences, and their ids, `{id}-s1`, `{id}-s2`, ... `None` for a block of one
+/// sentence, or one with a sentence that can't be wrapped without breaking the markup,
+/// e.g. one ending inside an `<em>`, which is read as one clip.
+fn mark_sentences(inner: &str, text: &str, id: &str) -> Option<(String, Vec<String>)> {
+    let sentences = chunker::sentences(text);
+    if sentences.len() < 2 {
+        return None;
+    }
+    // The text as `inline_text` reads it, with where each of its bytes comes from
+    let mut spoken = String::with_capacity(text.len());
+    let mut sources: Vec<(usize, usize)> = Vec::with_capacity(text.len());
+    let mut space = false;
+    let mut i = 0;
+    while i < inner.len() {
+        let rest = &inner[i..];
+        if rest.starts_with('<') {
+            i = rest.find('>').map_or(inner.len(), |end| i + end + 1);
+            space = true;
+            continue;
+        }
+        let entity = rest
+            .starts_with('&')
+            .then(|| rest[..rest.len().min(12)].find(';'))
+            .flatten()
+            .and_then(|semi| {
+                let decoded = decode_entities(&rest[..=semi]);
+                let c = decoded
+                    .chars()
+                    .next()
+                    .filter(|_| decoded != rest[..=semi])?;
+                Some((c, semi + 1))
+            });
+        let (c, len) = entity.unwrap_or_else(|| {
+            let c = rest.chars().next().unwrap_or_default();
+            (c, c.len_utf8())
+        });
+        if c.is_whitespace() {
+            space = true;
+        } else {
+            if space && !spoken.is_empty() {
+                spoken.push(' ');
+                sources.push((i, i));
+            }
+            space = false;
+            spoken.push(c);
+            sources.extend(std::iter::repeat_n((i, i + len), c.len_utf8()));
+        }
+        i += len;
+    }
+    if spoken != text {
+        return None;
+    }
+
+    let mut marked = String::with_capacity(inner.len() + sentences.len() * 32);
+    let mut ids = Vec::with_capacity(sentences.len());
+    let mut last = 0;
+    for (n, sentence) in sentences.iter().enumerate() {
+        let start = chunker::offset_in(text, sentence);
+        let from = sources[start].0;
+        let to = sources[start + sentence.len() - 1].1;
+        if !balanced(&inner[from..to]) {
+            return None;
+        }
+        let span_id = format!("{}-s{}", id, n + 1);
+        marked.push_str(&inner[last..from]);
+        marked.push_str(&format!(
+            "<span id=\"{}\">{}</span>",
+            span_id,
+            &inner[from..to]
+        ));
+        last = to;
+        ids.push(span_id);
+    }
+    marked.push_str(&inner[last..]);
+    Some((marked, ids))
+}
+
+/// A chapter of an EPUB as the blocks its overlay points at
+#[derive(Debug, Clone)]
+pub struct OverlayChapter {
+    /// Path of the chapter document inside the EPUB container
+    pub href: String,
+    pub blocks: Vec<TextBlock>,
+}
+
+/// Where the block with `id` is read in its chapter's audio
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clip {
+    pub id: String,
+    pub start_sec: f32,
+    pub end_sec: f32,
+}
+
+/// The narration of a chapter, as MP3, and where each of its blocks is in it
+#[derive(Debug, Clone)]
+pub struct ChapterAudio {
+    pub href: String,
+    pub mp3: Vec<u8>,
+    pub duration_sec: f32,
+    pub clips: Vec<Clip>,
+}
+
+/// `html` with an id on every block with text and spans around the sentences of the
+/// longer ones, and those blocks
+fn mark_blocks(html: &str) -> (String, Vec<TextBlock>) {
+    let mut marked = String::with_capacity(html.len());
+    let mut blocks = Vec::new();
+    let mut last = 0;
+    for caps in BLOCK_RE.captures_iter(html) {
+        let whole = caps.get(0).unwrap();
+        let inner = caps.get(3).unwrap();
+        let text = inline_text(inner.as_str());
+        if text.is_empty() {
+            continue;
+        }
+        let existing = caps
+            .get(2)
+            .and_then(|a| attributes(a.as_str()).remove("id"));
+        let id = existing
+            .clone()
+            .unwrap_or_else(|| format!("koko-{}", blocks.len() + 1));
+        marked.push_str(&html[last..whole.start()]);
+        let open = &html[whole.start()..inner.start()];
+        if existing.is_some() {
+            marked.push_str(open);
+        } else {
+            // Straight after the element name
+            let name_end = 1 + caps[1].len();
+            marked.push_str(&open[..name_end]);
+            marked.push_str(&format!(" id=\"{}\"", id));
+            marked.push_str(&open[name_end..]);
+        }
+        let (inner_marked, sentences) = mark_sentences(inner.as_str(), &text, &id)
+            .unwrap_or_else(|| (inner.as_str().to_string(), Vec::new()));
+        marked.push_str(&inner_marked);
+        marked.push_str(&html[inner.end()..whole.end()]);
+        last = whole.end();
+        blocks.push(TextBlock {
+            id,
+            text,
+            sentences,
+        });
+    }
+    marked.push_str(&html[last..]);
+    (marked, blocks)
+}
+
+/// The chapters of the EPUB at `path`, in reading order, as the blocks their overlays
+/// will point at; chapters without text are left out
+pub fn read_overlay_chapters(path: &Path) -> Result<Vec<OverlayChapter>, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let package = read_package(&mut archive)?;
+    let mut chapters = Vec::new();
+    for href in package.spine {
+        let (_, blocks) = mark_blocks(&read_entry(&mut archive, &href)?);
+        if !blocks.is_empty() {
+            chapters.push(OverlayChapter { href, blocks });
+        }
+    }
+    Ok(chapters)
+}
+
+/// `path` relative to the directory `dir`, both inside the container
+fn relative_to(dir: &str, path: &str) -> String {
+    let dir: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').collect();
+    let common = dir.iter().zip(&path).take_while(|(a, b)| a == b).count();
+    let mut parts = vec![".."; dir.len() - common];
+    parts.extend(&path[common..]);
+    parts.join("/")
+}
+
+/// `text` fit for an XML attribute, and an href without spaces
+fn escape_attr(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace(' ', "%20")
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit_once('/').map_or(path, |(_, name)| name)
+}
+
+/// `path` with its extension replaced by `extension`
+fn with_extension(path: &str, extension: &str) -> String {
+    let stem = match path.rsplit_once('.') {
+        Some((stem, old)) if !old.contains('/') && !stem.ends_with('/') && !stem.is_empty() => stem,
+        _ => path,
+    };
+    format!("{}.{}", stem, extension)
+}
+
+/// A duration as a SMIL clock value, `h:mm:ss.fff`
+fn clock(seconds: f32) -> String {
+    let millis = (seconds.max(0.0) as f64 * 1000.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+fn smil(chapter: &ChapterAudio) -> String {
+    let text = escape_attr(file_name(&chapter.href));
+    let audio = escape_attr(file_name(&with_extension(&chapter.href, "mp3")));
+    let mut smil = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <smil xmlns=\"http://www.w3.org/ns/SMIL\" xmlns:epub=\"http://www.idpf.org/2007/ops\" version=\"3.0\">\n\
+        <body>\n<seq id=\"seq-1\" epub:textref=\"{}\">\n",
+        text
+    );
+    for (i, clip) in chapter.clips.iter().enumerate() {
+        smil.push_str(&format!(
+            "<par id=\"par-{}\"><text src=\"{}#{}\"/><audio src=\"{}\" clipBegin=\"{:.3}s\" clipEnd=\"{:.3}s\"/></par>\n",
+            i + 1,
+            text,
+            escape_attr(&clip.id),
+            audio,
+            clip.start_sec,
+            clip.end_sec
+        ));
+    }
+    smil.push_str("</seq>\n</body>\n</smil>\n");
+    smil
+}
+
+/// The package document with the overlays and audio of `chapters` in its manifest and
+/// their durations in its metadata
+fn overlay_package(opf: &str, opf_dir: &str, chapters: &[ChapterAudio]) -> String {
+    let overlay_ids: HashMap<&str, String> = chapters
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| (chapter.href.as_str(), format!("koko-overlay-{}", i + 1)))
+        .collect();
+    let opf = ITEM_RE.replace_all(opf, |caps: &regex::Captures| {
+        let tag = &caps[0];
+        let attrs = attributes(tag);
+        match attrs
+            .get("href")
+            .and_then(|href| overlay_ids.get(resolve_href(opf_dir, href).as_str()))
+        {
+            Some(id) if !attrs.contains_key("media-overlay") => {
+                let end = tag.trim_end_matches('>').trim_end_matches('/').trim_end();
+                format!("{} media-overlay=\"{}\"{}", end, id, &tag[end.len()..])
+            }
+            _ => tag.to_string(),
+        }
+    });
+
+    let opf = MANIFEST_END_RE.replace(&opf, |caps: &regex::Captures| {
+        let prefix = caps.get(1).map_or("", |p| p.as_str());
+        let mut items = String::new();
+        for (i, chapter) in chapters.iter().enumerate() {
+            let smil = relative_to(opf_dir, &with_extension(&chapter.href, "smil"));
+            let audio = relative_to(opf_dir, &with_extension(&chapter.href, "mp3"));
+            items.push_str(&format!(
+                "<{p}item id=\"koko-overlay-{n}\" href=\"{}\" media-type=\"application/smil+xml\"/>\n\
+                <{p}item id=\"koko-audio-{n}\" href=\"{}\" media-type=\"audio/mpeg\"/>\n",
+                escape_attr(&smil),
+                escape_attr(&audio),
+                p = prefix,
+                n = i + 1
+            ));
+        }
+        format!("{}{}", items, &caps[0])
+    });
+
+    METADATA_END_RE
+        .replace(&opf, |caps: &regex::Captures| {
+            let prefix = caps.get(1).map_or("", |p| p.as_str());
+            let mut metas = String::new();
+            for (i, chapter) in chapters.iter().enumerate() {
+                metas.push_str(&format!(
+                    "<{}meta property=\"media:duration\" refines=\"#koko-overlay-{}\">{}</{0}meta>\n",
+                    prefix,
+                    i + 1,
+                    clock(chapter.duration_sec)
+                ));
+            }
+            let total = chapters.iter().map(|c| c.duration_sec).sum();
+            metas.push_str(&format!(
+                "<{p}meta property=\"media:duration\">{}</{p}meta>\n\
+                <{p}meta property=\"media:active-class\">{}</{p}meta>\n",
+                clock(total),
+                ACTIVE_CLASS,
+                p = prefix
+            ));
+            format!("{}{}", metas, &caps[0])
+        })
+        .into_owned()
+}
+
+/// Writes a copy of the EPUB at `source` to `dest` with `chapters` as its narration: the
+/// audio and a SMIL file next to each chapter, ids on the chapter's blocks, and the
+/// overlays declared in the package document
+pub fn write_overlay_epub(
+    source: &Path,
+    dest: &Path,
+    chapters: &[ChapterAudio],
+) -> Result<(), Box<dyn Error>> {
+    let mut archive = ZipArchive::new(File::open(source)?)?;
+    let package = read_package(&mut archive)?;
+
+    let mut replaced: HashMap<String, Vec<u8>> = HashMap::new();
+    replaced.insert(
+        package.path.clone(),
+        overlay_package(&package.opf, package.dir(), chapters).into_bytes(),
+    );
+    let style = format!(
+        "<style type=\"text/css\">.{} {{ background-color: #fff3a8; }}</style>\n",
+        ACTIVE_CLASS
+    );
+    for chapter in chapters {
+        let (marked, _) = mark_blocks(&read_entry(&mut archive, &chapter.href)?);
+        let marked = HEAD_END_RE.replace(&marked, |caps: &regex::Captures| {
+            format!("{}{}", style, &caps[0])
+        });
+        replaced.insert(chapter.href.clone(), marked.into_owned().into_bytes());
+    }
+    let added: Vec<(String, Vec<u8>)> = chapters
+        .iter()
+        .flat_map(|chapter| {
+            [
+                (
+                    with_extension(&chapter.href, "smil"),
+                    smil(chapter).into_bytes(),
+                ),
+                (with_extension(&chapter.href, "mp3"), chapter.mp3.clone()),
+            ]
+        })
+        .collect();
+    let added_names: HashSet<&str> = added.iter().map(|(name, _)| name.as_str()).collect();
+
+    let mut writer = ZipWriter::new(File::create(dest)?);
+    // The mimetype comes first and uncompressed, so readers can tell what the file is
+    writer.start_file(
+        "mimetype",
+        SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    writer.write_all(b"application/epub+zip")?;
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let name = entry.name().to_string();
+        if name == "mimetype" || added_names.contains(name.as_str()) {
+            continue;
+        }
+        match replaced.get(&name) {
+            Some(content) => {
+                writer.start_file(name, deflated)?;
+                writer.write_all(content)?;
+            }
+            None if entry.is_dir() => {
+                writer.add_directory(name, deflated)?;
+            }
+            None => {
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content)?;
+                writer.start_file(name, deflated)?;
+                writer.write_all(&content)?;
+            }
+        }
+    }
+    for (name, content) in &added {
+        // MP3 is compressed already
+        let options = if name.ends_with(".mp3") {
+            SimpleFileOptions::default().compression_method(CompressionMethod::Stored)
+        } else {
+            deflated
+        };
+        writer.start_file(name.as_str(), options)?;
+        writer.write_all(content)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_overlay() {
+        let html = r#"<html><head><title>One</title></head><body>
+            <h1 id="top">Chapter One</h1><p class="x">It was a <em>dark</em> night.</p>
+            <p> </p><ul><li><p>First</p></li></ul></body></html>"#;
+        let (marked, blocks) = mark_blocks(html);
+        let ids: Vec<&str> = blocks.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["top", "koko-2", "koko-3"]);
+        assert_eq!(blocks[1].text, "It was a dark night.");
+        assert!(marked.contains(r#"<p id="koko-2" class="x">"#));
+        assert!(marked.contains(r#"<li id="koko-3"><p>First"#));
+
+        // Sentences get spans, unless one ends inside inline markup
+        let (marked, blocks) = mark_blocks(
+            "<p>It was a <em>dark</em> night.\n  Rain &amp; wind! Then</p>\
+            <p>One <em>night. Two</em> days.</p>",
+        );
+        assert_eq!(blocks[0].sentences, ["koko-1-s1", "koko-1-s2", "koko-1-s3"]);
+        assert!(marked.contains(
+            r#"<p id="koko-1"><span id="koko-1-s1">It was a <em>dark</em> night.</span>"#
+        ));
+        assert!(marked.contains(
+            r#"<span id="koko-1-s2">Rain &amp; wind!</span> <span id="koko-1-s3">Then</span></p>"#
+        ));
+        assert!(blocks[1].sentences.is_empty());
+        assert!(marked.contains(r#"<p id="koko-2">One <em>night. Two</em> days.</p>"#));
+
+        assert_eq!(
+            relative_to("OEBPS", "OEBPS/Text/ch1.xhtml"),
+            "Text/ch1.xhtml"
+        );
+        assert_eq!(relative_to("OEBPS/pkg", "OEBPS/ch1.xhtml"), "../ch1.xhtml");
+        assert_eq!(clock(3725.5), "1:02:05.500");
+
+        let chapters = [ChapterAudio {
+            href: "OEBPS/Text/ch 1.xhtml".to_string(),
+            mp3: Vec::new(),
+            duration_sec: 2.0,
+            clips: vec![Clip {
+                id: "top".to_string(),
+                start_sec: 0.0,
+                end_sec: 1.25,
+            }],
+        }];
+        let opf = r#"<package version="3.0"><metadata></metadata><manifest>
+            <item id="c1" href="Text/ch%201.xhtml" media-type="application/xhtml+xml"/>
+            </manifest></package>"#;
+        let opf = overlay_package(opf, "OEBPS", &chapters);
+        assert!(
+            opf.contains(r#"media-type="application/xhtml+xml" media-overlay="koko-overlay-1"/>"#)
+        );
+        assert!(opf.contains(r#"href="Text/ch%201.smil" media-type="application/smil+xml""#));
+        assert!(opf.contains(r##"refines="#koko-overlay-1">0:00:02.000<"##));
+        assert!(smil(&chapters[0]).contains(
+            r#"<text src="ch%201.xhtml#top"/><audio src="ch%201.mp3" clipBegin="0.000s" clipEnd="1.250s"/>"#
+        ));
+    }
+}
@@ -0,0 +1,10 @@
+pub mod censor;
+pub mod dialogue;
+pub mod epub;
+pub mod html;
+pub mod markdown;
+pub mod media_overlay;
+pub mod prosody;
+pub mod rows;
+pub mod sounds;
+pub mod subtitles;
@@ -0,0 +1,115 @@
+//! Prosody around quotes and parentheticals: narration is split into the spans they set
+//! off, so each can be read a little slower or faster than the text around it and with a
+//! short pause either side, rather than as one flat run of speech.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::input::dialogue::QUOTE_PATTERN;
+
+lazy_static! {
+    /// Quotes, or parentheticals that aren't the phonemes of a pronunciation override
+    static ref SET_OFF_RE: Regex = Regex::new(&format!(r"{}|\]?\([^()]+\)", QUOTE_PATTERN)).unwrap();
+}
+
+/// What a span of text is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    Narration,
+    /// Quoted speech, without its quote marks
+    Quote,
+    /// A parenthetical, without its parentheses
+    Aside,
+}
+
+/// How quotes and parentheticals are read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prosody {
+    /// Seconds of silence before and after a quote or parenthetical
+    pub pause: f32,
+    /// Speed of quotes relative to the narration
+    pub quote_rate: f32,
+    /// Speed of parentheticals relative to the narration; asides are usually read a
+    /// little quicker
+    pub aside_rate: f32,
+}
+
+impl Default for Prosody {
+    fn default() -> Self {
+        Self {
+            pause: 0.15,
+            quote_rate: 0.97,
+            aside_rate: 1.06,
+        }
+    }
+}
+
+impl Prosody {
+    /// Speed of `kind` relative to the narration
+    pub fn rate(&self, kind: SpanKind) -> f32 {
+        match kind {
+            SpanKind::Narration => 1.0,
+            SpanKind::Quote => self.quote_rate,
+            SpanKind::Aside => self.aside_rate,
+        }
+    }
+}
+
+/// Splits prose into narration, quotes and parentheticals, in order. Like
+/// [`split_quotes`](crate::input::dialogue::split_quotes), spans without anything to say
+/// are dropped; one level is recognized, so a parenthetical inside a quote stays in it.
+pub fn prosody_spans(text: &str) -> Vec<(SpanKind, String)> {
+    let mut spans = Vec::new();
+    let mut rest = 0;
+    let mut push = |kind: SpanKind, text: &str| {
+        let text = text.trim();
+        if text.chars().any(char::is_alphanumeric) {
+            spans.push((kind, text.to_string()));
+        }
+    };
+    for found in SET_OFF_RE.find_iter(text) {
+        let span = found.as_str();
+        // `[text](/phonemes/)` belongs to the narration around it
+        if span.starts_with(']') {
+            continue;
+        }
+        push(SpanKind::Narration, &text[rest..found.start()]);
+        let open = span.chars().next().unwrap().len_utf8();
+        let close = span.chars().last().unwrap().len_utf8();
+        let kind = if span.starts_with('(') {
+            SpanKind::Aside
+        } else {
+            SpanKind::Quote
+        };
+        push(kind, &span[open..span.len() - close]);
+        rest = found.end();
+    }
+    push(SpanKind::Narration, &text[rest..]);
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prosody_spans() {
+        let spans = prosody_spans(
+            "The [Kokoro](/kˈOkəɹO/) team (all three of them) said \"Ship it (today).\" Done.",
+        );
+        assert_eq!(
+            spans,
+            vec![
+                (
+                    SpanKind::Narration,
+                    "The [Kokoro](/kˈOkəɹO/) team".to_string()
+                ),
+                (SpanKind::Aside, "all three of them".to_string()),
+                (SpanKind::Narration, "said".to_string()),
+                (SpanKind::Quote, "Ship it (today).".to_string()),
+                (SpanKind::Narration, "Done.".to_string()),
+            ]
+        );
+        assert_eq!(Prosody::default().rate(SpanKind::Narration), 1.0);
+    }
+}
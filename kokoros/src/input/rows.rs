@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde_json::Value;
+
+/// One line of a dialogue script: the text plus optional per-line overrides
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptRow {
+    /// 1-based line (JSONL) or record (CSV) number in the source file
+    pub line: usize,
+    pub text: String,
+    pub voice: Option<String>,
+    pub speed: Option<f32>,
+    pub lan: Option<String>,
+    pub output: Option<String>,
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+}
+
+/// Slowest and fastest speed a row may ask for, as the OpenAI speech API allows
+pub const SPEED_RANGE: (f32, f32) = (0.25, 4.0);
+
+fn check_speed(speed: f32, line: usize) -> Result<Option<f32>, Box<dyn Error>> {
+    if (SPEED_RANGE.0..=SPEED_RANGE.1).contains(&speed) {
+        Ok(Some(speed))
+    } else {
+        Err(format!(
+            "line {}: speed {} is not between {} and {}",
+            line, speed, SPEED_RANGE.0, SPEED_RANGE.1
+        )
+        .into())
+    }
+}
+
+fn parse_speed(value: Option<&str>, line: usize) -> Result<Option<f32>, Box<dyn Error>> {
+    match non_empty(value) {
+        Some(speed) => match speed.parse::<f32>() {
+            Ok(parsed) => check_speed(parsed, line),
+            Err(_) => Err(format!("line {}: invalid speed '{}'", line, speed).into()),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parses JSON Lines where each object has a `text` field and optional
+/// `voice`, `speed`, `lan` and `output` fields. Blank lines are ignored.
+pub fn parse_jsonl(content: &str) -> Result<Vec<ScriptRow>, Box<dyn Error>> {
+    let mut rows = Vec::new();
+    for (i, raw) in content.lines().enumerate() {
+        let line = i + 1;
+        if raw.trim().is_empty() {
+            continue;
+        }
+        let value: Value =
+            serde_json::from_str(raw).map_err(|e| format!("line {}: {}", line, e))?;
+        let field = |name: &str| value.get(name).and_then(Value::as_str);
+
+        let speed = match value.get("speed") {
+            Some(Value::Number(n)) => check_speed(n.as_f64().unwrap_or(f64::NAN) as f32, line)?,
+            Some(Value::String(s)) => parse_speed(Some(s), line)?,
+            None | Some(Value::Null) => None,
+            Some(other) => return Err(format!("line {}: invalid speed {}", line, other).into()),
+        };
+        rows.push(ScriptRow {
+            line,
+            text: non_empty(field("text")).ok_or(format!("line {}: missing 'text'", line))?,
+            voice: non_empty(field("voice")),
+            speed,
+            lan: non_empty(field("lan")),
+            output: non_empty(field("output")),
+        });
+    }
+    Ok(rows)
+}
+
+/// Parses CSV with a header row naming the `text`, `voice`, `speed`, `lan` and
+/// `output` columns (only `text` is required; column order is free).
+pub fn parse_csv(content: &str) -> Result<Vec<ScriptRow>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+
+    let headers: Vec<String> = reader
+        .headers()?
+        .iter()
+        .map(|h| h.to_ascii_lowercase())
+        .collect();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let text_col = column("text").ok_or("CSV header has no 'text' column")?;
+    let (voice_col, speed_col, lan_col, output_col) = (
+        column("voice"),
+        column("speed"),
+        column("lan"),
+        column("output"),
+    );
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        // Where the record starts, which quoted fields spanning lines push down
+        let record = record.map_err(|e| match e.position() {
+            Some(position) => format!("line {}: {}", position.line(), e),
+            None => e.to_string(),
+        })?;
+        let line = record
+            .position()
+            .map_or(0, |position| position.line() as usize);
+        let get = |col: Option<usize>| col.and_then(|c| record.get(c));
+
+        let Some(text) = non_empty(record.get(text_col)) else {
+            continue;
+        };
+        rows.push(ScriptRow {
+            line,
+            text,
+            voice: non_empty(get(voice_col)),
+            speed: parse_speed(get(speed_col), line)?,
+            lan: non_empty(get(lan_col)),
+            output: non_empty(get(output_col)),
+        });
+    }
+    Ok(rows)
+}
+
+/// Returns true if the path has an extension handled by [`read_rows`]
+pub fn is_rows_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_ascii_lowercase().as_str(), "csv" | "jsonl" | "ndjson"))
+}
+
+/// Reads a `.csv` or `.jsonl`/`.ndjson` script file
+pub fn read_rows(path: &Path) -> Result<Vec<ScriptRow>, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+    let is_csv = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+
+    if is_csv {
+        parse_csv(&content)
+    } else {
+        parse_jsonl(&content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rows() {
+        let csv = "voice,text,speed,output\naf_sky,\"Hello, world\",1.2,a.wav\n,Second line,,\n";
+        let rows = parse_csv(csv).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].text, "Hello, world");
+        assert_eq!(rows[0].voice.as_deref(), Some("af_sky"));
+        assert_eq!(rows[0].speed, Some(1.2));
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(rows[1].voice, None);
+
+        // A quoted field over two lines pushes the next record down a line
+        let csv = "text,speed\n\"One,\ntwo\",1\nThree,fast\n";
+        let error = parse_csv(csv).unwrap_err().to_string();
+        assert!(error.contains("line 4"), "{}", error);
+
+        let jsonl = "{\"text\": \"Hi\", \"speed\": 0.9, \"voice\": \"am_adam\"}\n\n{\"text\": \"There\", \"speed\": \"1.1\"}\n";
+        let rows = parse_jsonl(jsonl).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].speed, Some(0.9));
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(rows[1].speed, Some(1.1));
+
+        for bad in ["true", "[1]", "\"fast\"", "0", "9.5", "\"-1\""] {
+            let jsonl = format!(
+                "{{\"text\": \"Hi\"}}\n{{\"text\": \"Hi\", \"speed\": {}}}\n",
+                bad
+            );
+            let error = parse_jsonl(&jsonl).unwrap_err().to_string();
+            assert!(error.starts_with("line 2: "), "{}: {}", bad, error);
+        }
+        assert!(parse_csv("text,speed\nHi,5\n").is_err());
+        let null = parse_jsonl("{\"text\": \"Hi\", \"speed\": null}").unwrap();
+        assert_eq!(null[0].speed, None);
+    }
+}
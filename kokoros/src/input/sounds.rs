@@ -0,0 +1,97 @@
+//! Non-speech tokens in input text, for IVR prompts and produced narration:
+//! `[pause:800ms]` (or `[pause:1.5s]`) for silence, `[beep]` for a short tone, and
+//! `[sfx:door.wav]` for an audio clip, each put into the output where it appears.
+//!
+//! Stretches (`[stretch:1.5]word[/stretch]`) are left in the text, for synthesis to read
+//! with [`stretch`](crate::tts::stretch).
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref SOUND_RE: Regex = Regex::new(
+        r"(?is)\[(?:pause|break):\s*(\d+(?:\.\d+)?)\s*(ms|s)?\s*\]|\[beep\]|\[sfx:\s*([^\]]+?)\s*\]"
+    )
+    .unwrap();
+}
+
+/// Something to play that isn't speech
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sound {
+    Pause(Duration),
+    Beep,
+    /// An audio file, as named in the text
+    Clip(PathBuf),
+}
+
+/// A run of text to speak, or a sound between runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece {
+    Text(String),
+    Sound(Sound),
+}
+
+/// Whether `text` has any sound tokens
+pub fn has_sounds(text: &str) -> bool {
+    SOUND_RE.is_match(text)
+}
+
+/// Splits `text` at its sound tokens, in order. Runs of text with nothing to say are
+/// dropped, and a pause without a unit is in milliseconds.
+pub fn split_sounds(text: &str) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut rest = 0;
+    let push_text = |pieces: &mut Vec<Piece>, text: &str| {
+        let text = text.trim();
+        if text.chars().any(char::is_alphanumeric) {
+            pieces.push(Piece::Text(text.to_string()));
+        }
+    };
+    for caps in SOUND_RE.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        push_text(&mut pieces, &text[rest..whole.start()]);
+        rest = whole.end();
+        let sound = if let Some(amount) = caps.get(1) {
+            let amount: f64 = amount.as_str().parse().unwrap_or(0.0);
+            let seconds = match caps.get(2).map(|unit| unit.as_str().to_ascii_lowercase()) {
+                Some(unit) if unit == "s" => amount,
+                _ => amount / 1000.0,
+            };
+            Sound::Pause(Duration::from_secs_f64(seconds))
+        } else if let Some(path) = caps.get(3) {
+            Sound::Clip(PathBuf::from(path.as_str()))
+        } else {
+            Sound::Beep
+        };
+        pieces.push(Piece::Sound(sound));
+    }
+    push_text(&mut pieces, &text[rest..]);
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_sounds() {
+        let pieces =
+            split_sounds("Press one. [pause:800ms] [BEEP] [sfx: door.wav]Welcome [pause:1.5s]");
+        assert_eq!(
+            pieces,
+            vec![
+                Piece::Text("Press one.".to_string()),
+                Piece::Sound(Sound::Pause(Duration::from_millis(800))),
+                Piece::Sound(Sound::Beep),
+                Piece::Sound(Sound::Clip(PathBuf::from("door.wav"))),
+                Piece::Text("Welcome".to_string()),
+                Piece::Sound(Sound::Pause(Duration::from_millis(1500))),
+            ]
+        );
+        assert!(!has_sounds("[sarah] Hello"));
+        assert!(!has_sounds("It was [stretch:1.5]very[/stretch] big."));
+    }
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref TIMING_RE: Regex = Regex::new(
+        r"^\s*((?:\d+:)?\d{1,2}:\d{2}[.,]\d{1,3})\s*-->\s*((?:\d+:)?\d{1,2}:\d{2}[.,]\d{1,3})"
+    )
+    .unwrap();
+    static ref MARKUP_RE: Regex = Regex::new(r"<[^>]*>|\{\\[^}]*\}").unwrap();
+}
+
+/// One timed subtitle cue
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start_sec: f32,
+    pub end_sec: f32,
+    pub text: String,
+}
+
+impl Cue {
+    pub fn duration_sec(&self) -> f32 {
+        (self.end_sec - self.start_sec).max(0.0)
+    }
+}
+
+/// Parses `HH:MM:SS,mmm` (SRT) or `[HH:]MM:SS.mmm` (WebVTT) into seconds
+fn parse_timestamp(ts: &str) -> Option<f32> {
+    let ts = ts.replace(',', ".");
+    let mut parts: Vec<&str> = ts.split(':').collect();
+    let seconds: f32 = parts.pop()?.parse().ok()?;
+    let minutes: f32 = parts.pop().map_or(Some(0.0), |m| m.parse().ok())?;
+    let hours: f32 = parts.pop().map_or(Some(0.0), |h| h.parse().ok())?;
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+fn clean_cue_text(lines: &[&str]) -> String {
+    let text = lines.join(" ");
+    let text = MARKUP_RE.replace_all(&text, "");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Parses an SRT or WebVTT document into cues, sorted by start time.
+///
+/// Cue numbers, VTT headers/NOTE/STYLE blocks, cue settings and inline markup
+/// (`<i>`, `<v Speaker>`, `{\an8}`) are ignored; cues without text are dropped.
+pub fn parse_subtitles(content: &str) -> Result<Vec<Cue>, Box<dyn Error>> {
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut cues = Vec::new();
+
+    for block in content.split("\n\n") {
+        let lines: Vec<&str> = block.lines().filter(|l| !l.trim().is_empty()).collect();
+        let Some(timing_pos) = lines.iter().position(|l| TIMING_RE.is_match(l)) else {
+            continue;
+        };
+
+        let caps = TIMING_RE.captures(lines[timing_pos]).unwrap();
+        let (Some(start_sec), Some(end_sec)) =
+            (parse_timestamp(&caps[1]), parse_timestamp(&caps[2]))
+        else {
+            return Err(format!("invalid cue timing: '{}'", lines[timing_pos].trim()).into());
+        };
+
+        let text = clean_cue_text(&lines[timing_pos + 1..]);
+        if text.is_empty() {
+            continue;
+        }
+        cues.push(Cue {
+            start_sec,
+            end_sec,
+            text,
+        });
+    }
+
+    cues.sort_by(|a, b| a.start_sec.total_cmp(&b.start_sec));
+    Ok(cues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_srt() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>Hello</i> there,\r\nfriend.\r\n\r\n2\r\n00:01:00,250 --> 00:01:03,000\r\n{\\an8}Second cue\r\n";
+        let cues = parse_subtitles(srt).unwrap();
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].text, "Hello there, friend.");
+        assert_eq!(cues[0].start_sec, 1.0);
+        assert_eq!(cues[0].duration_sec(), 1.5);
+        assert_eq!(cues[1].start_sec, 60.25);
+        assert_eq!(cues[1].text, "Second cue");
+    }
+
+    #[test]
+    fn test_parse_vtt() {
+        let vtt = "WEBVTT\n\nNOTE a comment\n\nintro\n00:05.000 --> 00:07.000 align:start\n<v Tom>Hi!</v>\n\n01:00:00.000 --> 01:00:01.000\nLate\n";
+        let cues = parse_subtitles(vtt).unwrap();
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].start_sec, 5.0);
+        assert_eq!(cues[0].text, "Hi!");
+        assert_eq!(cues[1].start_sec, 3600.0);
+    }
+}
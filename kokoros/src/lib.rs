@@ -1,3 +1,6 @@
+pub mod error;
+pub mod input;
+#[cfg(feature = "tts")]
 pub mod onn;
 pub mod tts;
 pub mod utils;
@@ -0,0 +1,200 @@
+//! The float initializers of an ONNX model, read straight from its protobuf so they can be
+//! loaded once and handed to every session instead of each session parsing its own copy
+
+/// `TensorProto.DataType.FLOAT`
+const FLOAT: u64 = 1;
+/// `TensorProto.DataLocation.EXTERNAL`
+const EXTERNAL: u64 = 1;
+
+/// A weight of the model, as stored in the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Initializer<'a> {
+    pub name: &'a str,
+    pub dims: Vec<i64>,
+    /// Little-endian `f32`s, `dims` multiplied out of them
+    pub raw_data: &'a [u8],
+}
+
+impl Initializer<'_> {
+    pub fn values(&self) -> Vec<f32> {
+        self.raw_data
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect()
+    }
+}
+
+/// A field of a protobuf message
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// 32- and 64-bit fixed-size values, which no field read here uses
+    Fixed,
+}
+
+/// Reads the fields of the message in `bytes` one after another
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+fn varint(bytes: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or("truncated varint")?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint too long".to_string())
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if bytes.len() < len {
+        return Err("truncated field".to_string());
+    }
+    let (field, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(field)
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Field<'a>), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let field = (|| {
+            let key = varint(&mut self.bytes)?;
+            let field = match key & 7 {
+                0 => Field::Varint(varint(&mut self.bytes)?),
+                1 => {
+                    take(&mut self.bytes, 8)?;
+                    Field::Fixed
+                }
+                2 => {
+                    let len = varint(&mut self.bytes)? as usize;
+                    Field::Bytes(take(&mut self.bytes, len)?)
+                }
+                5 => {
+                    take(&mut self.bytes, 4)?;
+                    Field::Fixed
+                }
+                wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+            };
+            Ok((key >> 3, field))
+        })();
+        if field.is_err() {
+            self.bytes = &[];
+        }
+        Some(field)
+    }
+}
+
+fn fields(bytes: &[u8]) -> Fields<'_> {
+    Fields { bytes }
+}
+
+/// The tensor in `bytes` if it is a float tensor with its data in the file
+fn float_tensor(bytes: &[u8]) -> Result<Option<Initializer<'_>>, String> {
+    let mut dims = Vec::new();
+    let (mut data_type, mut name, mut raw_data, mut location) = (0, None, None, 0);
+    for field in fields(bytes) {
+        match field? {
+            (1, Field::Varint(dim)) => dims.push(dim as i64),
+            // Packed dims
+            (1, Field::Bytes(mut packed)) => {
+                while !packed.is_empty() {
+                    dims.push(varint(&mut packed)? as i64);
+                }
+            }
+            (2, Field::Varint(value)) => data_type = value,
+            (8, Field::Bytes(value)) => name = std::str::from_utf8(value).ok(),
+            (9, Field::Bytes(value)) => raw_data = Some(value),
+            (14, Field::Varint(value)) => location = value,
+            _ => {}
+        }
+    }
+    let (Some(name), Some(raw_data)) = (name, raw_data) else {
+        return Ok(None);
+    };
+    let len: i64 = dims.iter().product();
+    if data_type != FLOAT || location == EXTERNAL || raw_data.len() as i64 != len * 4 {
+        return Ok(None);
+    }
+    Ok(Some(Initializer {
+        name,
+        dims,
+        raw_data,
+    }))
+}
+
+/// The float initializers of the graph of the ONNX model in `model`, with their data in the
+/// file. Others, e.g. integer constants or weights in external files, are left to ORT.
+pub fn float_initializers(model: &[u8]) -> Result<Vec<Initializer<'_>>, String> {
+    let mut initializers = Vec::new();
+    for field in fields(model) {
+        // ModelProto.graph
+        let (7, Field::Bytes(graph)) = field? else {
+            continue;
+        };
+        for field in fields(graph) {
+            // GraphProto.initializer
+            if let (5, Field::Bytes(tensor)) = field?
+                && let Some(initializer) = float_tensor(tensor)?
+            {
+                initializers.push(initializer);
+            }
+        }
+    }
+    Ok(initializers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(field: u64, wire_type: u64) -> u8 {
+        (field << 3 | wire_type) as u8
+    }
+
+    fn bytes_field(field: u64, value: &[u8]) -> Vec<u8> {
+        let mut out = vec![key(field, 2), value.len() as u8];
+        out.extend_from_slice(value);
+        out
+    }
+
+    fn tensor(name: &str, data_type: u8, dims: &[u8], values: &[f32]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &dim in dims {
+            out.extend([key(1, 0), dim]);
+        }
+        out.extend([key(2, 0), data_type]);
+        out.extend(bytes_field(8, name.as_bytes()));
+        let raw: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        out.extend(bytes_field(9, &raw));
+        out
+    }
+
+    #[test]
+    fn test_float_initializers() {
+        let mut graph = bytes_field(1, b"node");
+        graph.extend(bytes_field(5, &tensor("w", 1, &[2, 1], &[0.5, -1.0])));
+        // int64, left to ORT
+        graph.extend(bytes_field(5, &tensor("shape", 7, &[1], &[0.0, 0.0])));
+        // Shorter than its dims say
+        graph.extend(bytes_field(5, &tensor("bad", 1, &[3], &[1.0])));
+        let mut model = vec![key(1, 0), 8];
+        model.extend(bytes_field(7, &graph));
+
+        let initializers = float_initializers(&model).unwrap();
+        assert_eq!(initializers.len(), 1);
+        assert_eq!(initializers[0].name, "w");
+        assert_eq!(initializers[0].dims, vec![2, 1]);
+        assert_eq!(initializers[0].values(), vec![0.5, -1.0]);
+
+        assert!(float_initializers(&model[..model.len() - 3]).is_err());
+    }
+}
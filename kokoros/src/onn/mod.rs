@@ -1,2 +1,5 @@
+pub mod initializers;
 pub mod ort_base;
 pub mod ort_koko;
+#[cfg(feature = "tract")]
+pub mod tract_koko;
@@ -1,26 +1,478 @@
-use ort::ep;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::UNIX_EPOCH;
+
+use lazy_static::lazy_static;
+use memmap2::Mmap;
+use ort::ep::{self, ExecutionProviderDispatch};
 use ort::logging::LogLevel;
 use ort::session::Session;
-use ort::session::builder::SessionBuilder;
+use ort::session::builder::{GraphOptimizationLevel, PrepackedWeights, SessionBuilder};
+use ort::value::{DynValue, Tensor};
+use serde::Deserialize;
 
-pub trait OrtBase {
-    fn load_model(&mut self, model_path: String) -> Result<(), String> {
+use crate::onn::initializers::float_initializers;
+
+lazy_static! {
+    /// Models in use, by path, so that every session created from one shares its weights
+    static ref LOADED: Mutex<HashMap<PathBuf, Weak<ModelWeights>>> = Mutex::new(HashMap::new());
+}
+
+/// Sessions profiled so far, numbering their profiles so sessions created within the same
+/// second don't write to the same file
+static PROFILED: AtomicUsize = AtomicUsize::new(0);
+
+/// A model file, memory-mapped once and shared by all sessions created from it, along with
+/// the weights ONNX Runtime pre-packs for its kernels. Models in the `.ort` format are
+/// run straight from the mapping, so their initializers exist only once in memory (and in
+/// the page cache). For `.onnx` models the float initializers are read from the file once
+/// and handed to every session, which uses them instead of its own copy; on the CPU,
+/// loading more sessions then only adds their working memory.
+pub struct ModelWeights {
+    bytes: Mmap,
+    ort_format: bool,
+    prepacked: PrepackedWeights,
+    initializers: Vec<(String, Arc<DynValue>)>,
+}
+
+/// The float initializers of the `.onnx` model in `bytes` as tensors for ORT, or none if
+/// they can't be read, leaving them to each session
+fn shared_initializers(path: &Path, bytes: &[u8]) -> Vec<(String, Arc<DynValue>)> {
+    let initializers = float_initializers(bytes).and_then(|initializers| {
+        initializers
+            .iter()
+            .map(|initializer| {
+                let tensor = Tensor::from_array((initializer.dims.clone(), initializer.values()))
+                    .map_err(|e| e.to_string())?;
+                Ok((initializer.name.to_string(), Arc::new(tensor.into_dyn())))
+            })
+            .collect::<Result<Vec<_>, String>>()
+    });
+    match initializers {
+        Ok(initializers) => {
+            tracing::debug!(
+                "Sharing {} initializers of {}",
+                initializers.len(),
+                path.display()
+            );
+            initializers
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Could not read the initializers of {}, each session loads its own: {}",
+                path.display(),
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+impl ModelWeights {
+    /// The weights of the model at `path`, shared with the sessions already using them
+    pub fn shared(path: &Path) -> Result<Arc<Self>, String> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let mut loaded = LOADED.lock().unwrap();
+        if let Some(weights) = loaded.get(&key).and_then(Weak::upgrade) {
+            tracing::debug!("Sharing the weights of {}", path.display());
+            return Ok(weights);
+        }
+        let file = File::open(path)
+            .map_err(|e| format!("Failed to open model {}: {}", path.display(), e))?;
+        // Safety: the file is only read, and models aren't expected to change while they
+        // are in use
+        let bytes = unsafe { Mmap::map(&file) }
+            .map_err(|e| format!("Failed to map model {}: {}", path.display(), e))?;
+        let ort_format = path.extension().is_some_and(|e| e == "ort");
+        let initializers = if ort_format {
+            Vec::new()
+        } else {
+            shared_initializers(path, &bytes)
+        };
+        let weights = Arc::new(ModelWeights {
+            bytes,
+            ort_format,
+            prepacked: PrepackedWeights::new(),
+            initializers,
+        });
+        loaded.retain(|_, w| w.strong_count() > 0);
+        loaded.insert(key, Arc::downgrade(&weights));
+        Ok(weights)
+    }
+
+    /// Creates a session for the model with `builder`, on the shared initializers if
+    /// `share_initializers`; ORT can't save the optimized graph of a session that has them
+    fn commit(&self, builder: SessionBuilder, share_initializers: bool) -> ort::Result<Session> {
+        let mut builder = builder.with_prepacked_weights(&self.prepacked)?;
+        if share_initializers {
+            for (name, value) in &self.initializers {
+                builder = builder.with_initializer(name, Arc::clone(value))?;
+            }
+        }
+        if self.ort_format {
+            // The mapping outlives the session, which holds on to these weights
+            builder = builder
+                .with_config_entry("session.use_ort_model_bytes_directly", "1")?
+                .with_config_entry("session.use_ort_model_bytes_for_initializers", "1")?;
+        }
+        builder.commit_from_memory(&self.bytes)
+    }
+}
+
+/// How ONNX Runtime runs a session. Fields left at `None` keep ORT's defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionOptions {
+    /// Threads that share the work of one operator; ORT uses one per physical core, so
+    /// several sessions on one machine should get a share of the cores each
+    pub intra_threads: Option<usize>,
+    /// Threads that run independent operators at once (with `parallel_execution`)
+    pub inter_threads: Option<usize>,
+    /// Whether idle pool threads busy-wait for work. ORT spins by default, which lowers
+    /// latency but keeps cores busy that other sessions could use.
+    pub spinning: Option<bool>,
+    /// Run independent branches of the graph at once instead of one operator at a time
+    pub parallel_execution: bool,
+    /// Serve CPU allocations from a memory arena that grows and is never returned to the
+    /// system; faster, but it keeps the peak of every session allocated
+    pub cpu_arena: bool,
+    /// Bytes of GPU memory the CUDA arena may take (CUDA builds); unlimited by default, so
+    /// a session grabs what it can when other models share the GPU
+    pub gpu_memory_limit: Option<usize>,
+    /// Grow the CUDA arena by exactly what is requested rather than by powers of two,
+    /// trading some allocation speed for a smaller footprint (CUDA builds)
+    pub gpu_arena_exact: bool,
+    /// CUDA device the session runs on (CUDA builds); device 0 if not given
+    pub gpu_device: Option<i32>,
+    /// Use ORT's deterministic kernels, which give the same output for the same input on
+    /// every run, where the default ones may vary in the last bits
+    pub deterministic: bool,
+    /// Provider to run on instead of the one the build prefers, e.g. the CPU in a CUDA
+    /// build. CUDA needs the cuda feature.
+    pub provider: Option<Provider>,
+    /// How far ORT rewrites the graph before running it; all the way by default. Lower
+    /// levels start faster and may run slower.
+    pub optimization: Option<OptimizationLevel>,
+    /// Directory the graph is saved to once optimized, and loaded from already optimized
+    /// on the next start, which skips most of the optimization time. Models in the `.ort`
+    /// format are optimized already and not saved.
+    pub optimized_model_dir: Option<PathBuf>,
+    /// Profile each session with ORT's profiler, writing the time every operator took to
+    /// `<prefix>-<n>_<date>.json` when the session is dropped, for `chrome://tracing`
+    pub profiling: Option<PathBuf>,
+}
+
+impl SessionOptions {
+    /// These options with every source of run-to-run variation pinned: deterministic
+    /// kernels on a single thread per session, one operator at a time, so the output
+    /// doesn't depend on how the work happened to be split up or ordered
+    pub fn reproducible(self) -> Self {
+        Self {
+            intra_threads: Some(1),
+            inter_threads: Some(1),
+            parallel_execution: false,
+            deterministic: true,
+            ..self
+        }
+    }
+
+    fn apply(&self, mut builder: SessionBuilder) -> ort::Result<SessionBuilder> {
+        if let Some(threads) = self.intra_threads {
+            builder = builder.with_intra_threads(threads)?;
+        }
+        if let Some(threads) = self.inter_threads {
+            builder = builder.with_inter_threads(threads)?;
+        }
+        if let Some(spinning) = self.spinning {
+            builder = builder
+                .with_intra_op_spinning(spinning)?
+                .with_inter_op_spinning(spinning)?;
+        }
+        if self.deterministic {
+            builder = builder.with_deterministic_compute(true)?;
+        }
+        if let Some(level) = self.optimization {
+            builder = builder.with_optimization_level(level.into())?;
+        }
+        if let Some(prefix) = &self.profiling {
+            let n = PROFILED.fetch_add(1, Ordering::Relaxed);
+            let mut prefix = prefix.clone().into_os_string();
+            prefix.push(format!("-{}", n));
+            builder = builder.with_profiling(prefix)?;
+        }
+        builder.with_parallel_execution(self.parallel_execution)
+    }
+
+    /// Where the optimized graph of the model at `model_path` is kept for `provider`, if
+    /// it is kept: named after the model's size and modification time as well, so a
+    /// changed model isn't run from the graph of the old one
+    fn optimized_model_path(&self, model_path: &Path, provider: Provider) -> Option<PathBuf> {
+        let dir = self.optimized_model_dir.as_ref()?;
+        if model_path.extension().is_some_and(|e| e == "ort") {
+            return None;
+        }
+        let metadata = std::fs::metadata(model_path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs());
+        let stem = model_path.file_stem()?.to_string_lossy();
+        let level = self.optimization.map_or("all", OptimizationLevel::name);
+        Some(dir.join(format!(
+            "{}-{:x}-{:x}-{}-{}.onnx",
+            stem,
+            metadata.len(),
+            modified,
+            provider.name().to_lowercase(),
+            level
+        )))
+    }
+}
+
+/// How far ORT optimizes a model's graph, from none to everything including the rewrites
+/// specific to the provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptimizationLevel {
+    Disable,
+    /// Constant folding and removal of redundant nodes
+    Basic,
+    /// Also fusions of common groups of nodes
+    Extended,
+    /// Also layout changes, e.g. NCHWc on the CPU
+    All,
+}
+
+impl OptimizationLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            OptimizationLevel::Disable => "disable",
+            OptimizationLevel::Basic => "basic",
+            OptimizationLevel::Extended => "extended",
+            OptimizationLevel::All => "all",
+        }
+    }
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Basic => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Extended => GraphOptimizationLevel::Level2,
+            OptimizationLevel::All => GraphOptimizationLevel::All,
+        }
+    }
+}
+
+impl std::str::FromStr for OptimizationLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "disable" | "none" | "0" => Ok(OptimizationLevel::Disable),
+            "basic" | "1" => Ok(OptimizationLevel::Basic),
+            "extended" | "2" => Ok(OptimizationLevel::Extended),
+            "all" | "3" => Ok(OptimizationLevel::All),
+            _ => Err(format!(
+                "unknown optimization level {:?}, expected disable, basic, extended or all",
+                s
+            )),
+        }
+    }
+}
+
+/// Where a session runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Cuda,
+    Cpu,
+    /// The CPU, with tract instead of ONNX Runtime (tract builds); slower, but it needs no
+    /// native library
+    Tract,
+}
+
+impl Provider {
+    /// The provider sessions are created on first, falling back to the CPU if that fails
+    pub fn preferred() -> Self {
+        if cfg!(feature = "cuda") {
+            Provider::Cuda
+        } else {
+            Provider::Cpu
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::Cuda => "CUDA",
+            Provider::Cpu => "CPU",
+            Provider::Tract => "tract",
+        }
+    }
+}
+
+impl std::str::FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cuda" => Ok(Provider::Cuda),
+            "cpu" => Ok(Provider::Cpu),
+            "tract" => Ok(Provider::Tract),
+            _ => Err(format!(
+                "unknown provider {:?}, expected cpu, cuda or tract",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(feature = "cuda")]
+fn cuda_provider(options: &SessionOptions) -> ExecutionProviderDispatch {
+    let mut cuda = ep::CUDA::default();
+    if let Some(device) = options.gpu_device {
+        cuda = cuda.with_device_id(device);
+    }
+    if let Some(limit) = options.gpu_memory_limit {
+        cuda = cuda.with_memory_limit(limit);
+    }
+    if options.gpu_arena_exact {
+        cuda = cuda.with_arena_extend_strategy(ep::ArenaExtendStrategy::SameAsRequested);
+    }
+    // Without this ORT quietly runs on the CPU when CUDA can't be registered, and we
+    // would report the wrong provider
+    cuda.build().error_on_failure()
+}
+
+fn provider_dispatch(provider: Provider, options: &SessionOptions) -> ExecutionProviderDispatch {
+    match provider {
         #[cfg(feature = "cuda")]
-        let providers = [ep::CUDA::default().build()];
+        Provider::Cuda => cuda_provider(options),
+        #[cfg(not(feature = "cuda"))]
+        Provider::Cuda => unreachable!("CUDA is only preferred with the cuda feature"),
+        Provider::Cpu => ep::CPU::default()
+            .with_arena_allocator(options.cpu_arena)
+            .build(),
+        Provider::Tract => unreachable!("tract doesn't run ONNX Runtime sessions"),
+    }
+}
 
+pub trait OrtBase {
+    /// Loads the model on the preferred provider, or on the CPU if the session can't be
+    /// created there (e.g. a missing or mismatched CUDA driver). Returns the provider the
+    /// session runs on.
+    fn load_model(
+        &mut self,
+        model_path: String,
+        options: &SessionOptions,
+    ) -> Result<Provider, String> {
+        #[cfg(not(feature = "cuda"))]
+        if options.gpu_memory_limit.is_some() || options.gpu_arena_exact {
+            tracing::warn!("GPU memory options are ignored without the cuda feature");
+        }
         #[cfg(not(feature = "cuda"))]
-        let providers = [ep::CPU::default().build()];
+        if options.gpu_device.is_some() {
+            tracing::warn!("GPU devices are ignored without the cuda feature");
+        }
 
+        let preferred = options.provider.unwrap_or_else(Provider::preferred);
+        if preferred == Provider::Cuda && !cfg!(feature = "cuda") {
+            return Err("the CUDA provider needs a build with the cuda feature".to_string());
+        }
+        if preferred == Provider::Tract {
+            return Err(if cfg!(feature = "tract") {
+                "only the Kokoro model runs on tract".to_string()
+            } else {
+                "the tract provider needs a build with the tract feature".to_string()
+            });
+        }
+        let provider = match self.load_model_on(&model_path, options, preferred) {
+            Ok(()) => preferred,
+            Err(e) if preferred != Provider::Cpu => {
+                tracing::warn!(
+                    "Could not create the session on {}, falling back to the CPU: {}",
+                    preferred,
+                    e
+                );
+                self.load_model_on(&model_path, options, Provider::Cpu)?;
+                Provider::Cpu
+            }
+            Err(e) => return Err(e),
+        };
+        match options.gpu_device {
+            Some(device) if provider == Provider::Cuda => tracing::info!(
+                "Running {} on the {} execution provider, device {}",
+                model_path,
+                provider,
+                device
+            ),
+            _ => tracing::info!(
+                "Running {} on the {} execution provider",
+                model_path,
+                provider
+            ),
+        }
+        Ok(provider)
+    }
+
+    /// Loads the model on `provider`, with no fallback
+    fn load_model_on(
+        &mut self,
+        model_path: &str,
+        options: &SessionOptions,
+        provider: Provider,
+    ) -> Result<(), String> {
         match SessionBuilder::new() {
             Ok(builder) => {
-                let session = builder
-                    .with_execution_providers(providers)
+                let builder = builder
+                    .with_execution_providers([provider_dispatch(provider, options)])
                     .map_err(|e| format!("Failed to build session: {}", e))?
                     .with_log_level(LogLevel::Warning)
-                    .map_err(|e| format!("Failed to set log level: {}", e))?
-                    .commit_from_file(model_path)
+                    .map_err(|e| format!("Failed to set log level: {}", e))?;
+                let builder = options
+                    .apply(builder)
+                    .map_err(|e| format!("Failed to set session options: {}", e))?;
+                let mut share_initializers = true;
+                let (weights, builder) =
+                    match options.optimized_model_path(Path::new(model_path), provider) {
+                        // Optimized already, so ORT needn't do it again
+                        Some(path) if path.exists() => {
+                            tracing::debug!("Loading the optimized graph {}", path.display());
+                            let builder = builder
+                                .with_optimization_level(GraphOptimizationLevel::Disable)
+                                .map_err(|e| format!("Failed to set session options: {}", e))?;
+                            (ModelWeights::shared(&path)?, builder)
+                        }
+                        Some(path) => {
+                            if let Some(dir) = path.parent() {
+                                std::fs::create_dir_all(dir).map_err(|e| {
+                                    format!("Failed to create {}: {}", dir.display(), e)
+                                })?;
+                            }
+                            tracing::info!("Saving the optimized graph to {}", path.display());
+                            share_initializers = false;
+                            let builder = builder
+                                .with_optimized_model_path(&path)
+                                .map_err(|e| format!("Failed to set session options: {}", e))?;
+                            (ModelWeights::shared(Path::new(model_path))?, builder)
+                        }
+                        None => (ModelWeights::shared(Path::new(model_path))?, builder),
+                    };
+                let session = weights
+                    .commit(builder, share_initializers)
                     .map_err(|e| format!("Failed to commit from file: {}", e))?;
-                self.set_sess(session);
+                self.set_sess(session, weights, provider);
                 Ok(())
             }
             Err(e) => Err(format!("Failed to create session builder: {}", e)),
@@ -38,16 +490,17 @@ pub trait OrtBase {
                 eprintln!("  - {}", output.name());
             }
 
-            #[cfg(feature = "cuda")]
-            eprintln!("Configured with: CUDA execution provider");
-
-            #[cfg(not(feature = "cuda"))]
-            eprintln!("Configured with: CPU execution provider");
+            eprintln!(
+                "Configured with: {} execution provider",
+                Provider::preferred()
+            );
         } else {
             eprintln!("Session is not initialized.");
         }
     }
 
-    fn set_sess(&mut self, sess: Session);
+    /// Stores the session, along with the weights it was created from, which must outlive
+    /// it, and the provider it runs on
+    fn set_sess(&mut self, sess: Session, weights: Arc<ModelWeights>, provider: Provider);
     fn sess(&self) -> Option<&Session>;
 }
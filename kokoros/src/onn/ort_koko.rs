@@ -1,14 +1,17 @@
-use std::borrow::Cow;
+use std::sync::Arc;
 
 use super::ort_base;
+use crate::error::KokorosError;
+use crate::tts::vocab::ModelVersion;
 use crate::utils::debug::format_debug_prefix;
 use model_schema::v1_0_timestamped::DURATIONS;
-use ndarray::{ArrayBase, IxDyn, OwnedRepr};
 use ort::{
-    session::{Session, SessionInputValue, SessionInputs, SessionOutputs},
-    value::{Tensor, Value},
+    io_binding::IoBinding,
+    memory::{AllocationDevice, AllocatorType, MemoryInfo, MemoryType},
+    session::Session,
+    value::{DynValue, Tensor, Value},
 };
-use ort_base::OrtBase;
+use ort_base::{ModelWeights, OrtBase, Provider, SessionOptions};
 
 mod model_schema {
     pub const STYLE: &str = "style";
@@ -34,6 +37,19 @@ pub enum ModelStrategy {
 
 pub struct OrtKoko {
     inner: Option<ModelStrategy>,
+    /// The session's inputs and outputs, bound on first use and reused for every chunk
+    binding: Option<IoBinding>,
+    provider: Provider,
+    /// Kept to recreate the session on the CPU if it fails on the GPU
+    model_path: String,
+    options: SessionOptions,
+    /// Release of the model, from its metadata or file name
+    version: Option<ModelVersion>,
+    /// The model run by tract instead of a session, with [`Provider::Tract`]
+    #[cfg(feature = "tract")]
+    tract: Option<super::tract_koko::TractKoko>,
+    /// Declared last, so the session is dropped before the model it runs from
+    weights: Option<Arc<ModelWeights>>,
 }
 
 impl ModelStrategy {
@@ -50,10 +66,41 @@ impl ModelStrategy {
             ModelStrategy::Timestamped(_) => model_schema::v1_0_timestamped::TOKENS,
         }
     }
+
+    fn sess_mut(&mut self) -> &mut Session {
+        match self {
+            ModelStrategy::Standard(sess) => sess,
+            ModelStrategy::Timestamped(sess) => sess,
+        }
+    }
+}
+
+/// Where the model writes its outputs. With CUDA that is page-locked host memory, so each
+/// chunk's audio leaves the device in one transfer without going through a staging buffer.
+/// It is still copied out of there into the chunk's `Vec`, since everything after the
+/// model (alignment, stretches, post-processing, concatenation) runs on the CPU.
+fn output_memory(provider: Provider) -> ort::Result<MemoryInfo> {
+    let (device, memory_type) = match provider {
+        Provider::Cuda => (AllocationDevice::CUDA_PINNED, MemoryType::CPUOutput),
+        Provider::Cpu | Provider::Tract => (AllocationDevice::CPU, MemoryType::Default),
+    };
+    MemoryInfo::new(device, 0, AllocatorType::Device, memory_type)
 }
 
 impl OrtBase for OrtKoko {
-    fn set_sess(&mut self, sess: Session) {
+    fn set_sess(&mut self, sess: Session, weights: Arc<ModelWeights>, provider: Provider) {
+        self.end_profiling();
+        let metadata_version = sess.metadata().ok().and_then(|metadata| {
+            metadata
+                .custom("version")
+                .or_else(|| metadata.custom("model_version"))
+        });
+        let file_name = std::path::Path::new(&self.model_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.version = ModelVersion::detect(metadata_version.as_deref(), &file_name);
+
         let output_count = sess.outputs().len();
 
         let strategy = if output_count > 1 {
@@ -71,6 +118,9 @@ impl OrtBase for OrtKoko {
         };
 
         self.inner = Some(strategy);
+        self.binding = None;
+        self.provider = provider;
+        self.weights = Some(weights);
     }
 
     fn sess(&self) -> Option<&Session> {
@@ -80,24 +130,116 @@ impl OrtBase for OrtKoko {
         })
     }
 }
+impl Drop for OrtKoko {
+    fn drop(&mut self) {
+        self.end_profiling();
+    }
+}
+
 impl OrtKoko {
-    pub fn new(model_path: String) -> Result<Self, String> {
-        let mut instance = OrtKoko { inner: None };
-        instance.load_model(model_path)?;
+    pub fn new(model_path: String, options: &SessionOptions) -> Result<Self, String> {
+        let mut instance = OrtKoko {
+            inner: None,
+            binding: None,
+            provider: Provider::preferred(),
+            model_path: model_path.clone(),
+            options: options.clone(),
+            version: None,
+            #[cfg(feature = "tract")]
+            tract: None,
+            weights: None,
+        };
+        #[cfg(feature = "tract")]
+        if options.provider == Some(Provider::Tract) {
+            let tract =
+                super::tract_koko::TractKoko::new(&model_path).map_err(|e| e.to_string())?;
+            tracing::info!("Running {} on tract", model_path);
+            instance.provider = Provider::Tract;
+            instance.version = tract.version();
+            instance.tract = Some(tract);
+            return Ok(instance);
+        }
+        instance.load_model(model_path, options)?;
         Ok(instance)
     }
 
+    /// Writes the profile of the session, when it is profiled; ORT leaves the file empty
+    /// otherwise
+    fn end_profiling(&mut self) {
+        if self.options.profiling.is_none() {
+            return;
+        }
+        if let Some(strategy) = self.inner.as_mut() {
+            match strategy.sess_mut().end_profiling() {
+                Ok(file) => tracing::info!("Wrote the ORT profile to {}", file),
+                Err(e) => tracing::warn!("Could not write the ORT profile: {}", e),
+            }
+        }
+    }
+
+    /// The provider the session runs on, which is the CPU after falling back from the GPU
+    pub fn provider(&self) -> Provider {
+        self.provider
+    }
+
+    /// The release of the model, if its metadata or file name says
+    pub fn version(&self) -> Option<ModelVersion> {
+        self.version
+    }
+
     pub fn strategy(&self) -> Option<&ModelStrategy> {
         self.inner.as_ref()
     }
 
+    /// Whether the model also returns durations, for word timestamps
+    pub fn timestamped(&self) -> bool {
+        #[cfg(feature = "tract")]
+        if let Some(tract) = &self.tract {
+            return tract.timestamped();
+        }
+        matches!(self.inner, Some(ModelStrategy::Timestamped(_)))
+    }
+
+    /// Checks that the model's style input takes vectors of `style_dim` values, as the
+    /// voices hold, so a model and voices file that don't belong together are refused at
+    /// load time rather than failing inside ORT on the first run
+    pub fn check_style_input(&self, style_dim: usize) -> Result<(), KokorosError> {
+        let Some(sess) = self.sess() else {
+            return Ok(());
+        };
+        let input = sess
+            .inputs()
+            .iter()
+            .find(|input| input.name() == model_schema::STYLE)
+            .ok_or_else(|| {
+                KokorosError::ModelLoad(format!("the model has no '{}' input", model_schema::STYLE))
+            })?;
+        // Dynamic dimensions are -1 and take any size
+        let fits = input.dtype().tensor_shape().is_some_and(|shape| {
+            shape.len() == 2 && (shape[1] < 0 || shape[1] as usize == style_dim)
+        });
+        if !fits {
+            let shape = input.dtype().tensor_shape().map_or_else(
+                || "(not a tensor)".to_string(),
+                |shape| format!("{:?}", &shape[..]),
+            );
+            return Err(KokorosError::ModelLoad(format!(
+                "the voices hold style vectors of shape [1, {}], but the model's '{}' input \
+                has shape {}; are the model and voices files from the same release?",
+                style_dim,
+                model_schema::STYLE,
+                shape
+            )));
+        }
+        Ok(())
+    }
+
     fn prepare_inputs(
         tokens_key: &'static str,
         tokens: Vec<Vec<i64>>,
         styles: Vec<Vec<f32>>,
         speed: f32,
-    ) -> Result<Vec<(Cow<'static, str>, SessionInputValue<'static>)>, Box<dyn std::error::Error>>
-    {
+    ) -> Result<[(&'static str, DynValue); 3], KokorosError> {
         let shape = [tokens.len(), tokens[0].len()];
         let tokens_tensor =
             Tensor::from_array((shape, tokens.into_iter().flatten().collect::<Vec<i64>>()))?;
@@ -110,22 +252,26 @@ impl OrtKoko {
 
         let speed_tensor = Tensor::from_array(([1], vec![speed]))?;
 
-        Ok(vec![
-            (
-                Cow::Borrowed(tokens_key),
-                SessionInputValue::Owned(Value::from(tokens_tensor)),
-            ),
-            (
-                Cow::Borrowed(model_schema::STYLE),
-                SessionInputValue::Owned(Value::from(style_tensor)),
-            ),
-            (
-                Cow::Borrowed(model_schema::SPEED),
-                SessionInputValue::Owned(Value::from(speed_tensor)),
-            ),
+        Ok([
+            (tokens_key, Value::from(tokens_tensor)),
+            (model_schema::STYLE, Value::from(style_tensor)),
+            (model_schema::SPEED, Value::from(speed_tensor)),
         ])
     }
 
+    /// Binds every output of `sess` to [`output_memory`], so runs write their results there
+    /// directly instead of into buffers ONNX Runtime allocates for each run
+    fn bind_outputs(sess: &Session, provider: Provider) -> Result<IoBinding, KokorosError> {
+        let mut binding = sess.create_binding()?;
+        let memory = output_memory(provider)?;
+        for output in sess.outputs() {
+            binding.bind_output_to_device(output.name(), &memory)?;
+        }
+        Ok(binding)
+    }
+
+    /// Runs the model on one batch of tokens, returning the audio samples and, for
+    /// timestamped models, the per-token durations in frames
     pub fn infer(
         &mut self,
         tokens: Vec<Vec<i64>>,
@@ -134,8 +280,7 @@ impl OrtKoko {
         request_id: Option<&str>,
         instance_id: Option<&str>,
         chunk_number: Option<usize>,
-    ) -> Result<(ArrayBase<OwnedRepr<f32>, IxDyn>, Option<Vec<f32>>), Box<dyn std::error::Error>>
-    {
+    ) -> Result<(Vec<f32>, Option<Vec<f32>>), KokorosError> {
         let debug_prefix = format_debug_prefix(request_id, instance_id);
         let chunk_info = chunk_number
             .map(|n| format!("Chunk: {}, ", n))
@@ -147,46 +292,91 @@ impl OrtKoko {
             tokens.len()
         );
 
-        let strategy = self.inner.as_mut().ok_or("Session is not initialized.")?;
+        #[cfg(feature = "tract")]
+        if let Some(tract) = &self.tract {
+            return tract.infer(tokens, styles, speed);
+        }
+        let tokens_key = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| KokorosError::Inference("Session is not initialized.".to_string()))?
+            .tokens_key();
+        let inputs = Self::prepare_inputs(tokens_key, tokens, styles, speed)?;
+        match self.run(&inputs) {
+            Err(e) if self.provider == Provider::Cuda => {
+                // A GPU that runs out of memory or trips over its driver mid-stream shouldn't
+                // take the whole process down with it; carry on, slower, on the CPU
+                tracing::warn!(
+                    "{} {}inference failed on {}, falling back to the CPU: {}",
+                    debug_prefix,
+                    chunk_info,
+                    self.provider,
+                    e
+                );
+                let model_path = self.model_path.clone();
+                let options = self.options.clone();
+                self.load_model_on(&model_path, &options, Provider::Cpu)
+                    .map_err(KokorosError::ModelLoad)?;
+                tracing::info!("Running {} on the CPU execution provider", model_path);
+                self.run(&inputs)
+            }
+            result => result,
+        }
+    }
+
+    /// Runs the session on prepared inputs
+    fn run(
+        &mut self,
+        inputs: &[(&'static str, DynValue); 3],
+    ) -> Result<(Vec<f32>, Option<Vec<f32>>), KokorosError> {
+        let provider = self.provider;
+        let strategy = self
+            .inner
+            .as_mut()
+            .ok_or_else(|| KokorosError::Inference("Session is not initialized.".to_string()))?;
         let audio_key = strategy.audio_key();
-        let tokens_key = strategy.tokens_key();
-        let inputs = Self::prepare_inputs(tokens_key, tokens.clone(), styles, speed)?;
-        match strategy {
-            ModelStrategy::Standard(sess) => {
-                let outputs = sess.run(SessionInputs::from(inputs))?;
+        let (fallback_key, missing_audio) = match strategy {
+            ModelStrategy::Standard(_) => {
+                ("waveforms", "Standard Model: Could not find 'audio' output")
+            }
+            ModelStrategy::Timestamped(_) => (
+                "audio",
+                "Timestamped Model: Could not find 'waveforms' or 'audio'",
+            ),
+        };
+        let timestamped = matches!(strategy, ModelStrategy::Timestamped(_));
+        let sess = strategy.sess_mut();
 
-                let (shape, data) = outputs[audio_key]
-                    .try_extract_tensor::<f32>()
-                    .or_else(|_| outputs["waveforms"].try_extract_tensor::<f32>())
-                    .map_err(|_| "Standard Model: Could not find 'audio' output")?;
+        if self.binding.is_none() {
+            self.binding = Some(Self::bind_outputs(sess, provider)?);
+        }
+        let binding = self.binding.as_mut().unwrap();
+        for (name, value) in inputs {
+            binding.bind_input(*name, value)?;
+        }
+        let outputs = sess.run_binding(binding)?;
 
-                let shape_vec: Vec<usize> = shape.into_iter().map(|&i| i as usize).collect();
-                let audio_array = ArrayBase::from_shape_vec(shape_vec, data.to_vec())?;
+        let (_, data) = outputs
+            .get(audio_key)
+            .or_else(|| outputs.get(fallback_key))
+            .and_then(|audio| audio.try_extract_tensor::<f32>().ok())
+            .ok_or_else(|| KokorosError::Inference(missing_audio.to_string()))?;
+        // Copied once, out of the bound output buffer, which the next run overwrites
+        let audio = data.to_vec();
 
-                Ok((audio_array, None))
-            }
-            ModelStrategy::Timestamped(sess) => {
-                let outputs = sess.run(SessionInputs::from(inputs))?;
-
-                let (shape, data) = outputs[audio_key]
-                    .try_extract_tensor::<f32>()
-                    .or_else(|_| outputs["audio"].try_extract_tensor::<f32>())
-                    .map_err(|_| "Timestamped Model: Could not find 'waveforms' or 'audio'")?;
-
-                let shape_vec: Vec<usize> = shape.into_iter().map(|&i| i as usize).collect();
-                let audio_array = ArrayBase::from_shape_vec(shape_vec, data.to_vec())?;
-
-                let durations_vec = outputs[DURATIONS]
-                    .try_extract_tensor::<f32>()
-                    .map(|(_, d)| d.to_vec())
-                    .map_err(|_| format!(
-                        "Timestamped Model Error: Expected output tensor '{}' of type f32. \
-                        If your model uses 'duration' (singular) or i64, please update the schema constants.",
-                        DURATIONS
-                    ))?;
-
-                Ok((audio_array, Some(durations_vec)))
-            }
+        if !timestamped {
+            return Ok((audio, None));
         }
+        let durations_vec = outputs
+            .get(DURATIONS)
+            .and_then(|d| d.try_extract_tensor::<f32>().ok())
+            .map(|(_, d)| d.to_vec())
+            .ok_or_else(|| KokorosError::Inference(format!(
+                "Timestamped Model Error: Expected output tensor '{}' of type f32. \
+                If your model uses 'duration' (singular) or i64, please update the schema constants.",
+                DURATIONS
+            )))?;
+
+        Ok((audio, Some(durations_vec)))
     }
 }
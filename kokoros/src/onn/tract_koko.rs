@@ -0,0 +1,255 @@
+//! The Kokoro model run by [tract](https://github.com/sonos/tract), a pure-Rust inference
+//! engine, for [`Provider::Tract`](super::ort_base::Provider::Tract). It is slower than
+//! ONNX Runtime, but needs no native library, e.g. for static musl builds.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use tract_onnx::pb::ModelProto;
+use tract_onnx::prelude::*;
+
+use crate::error::KokorosError;
+use crate::tts::vocab::ModelVersion;
+
+/// Names the inputs go by, in the standard and the timestamped exports of the model
+const TOKENS: [&str; 2] = ["tokens", "input_ids"];
+const STYLE: &str = "style";
+const SPEED: &str = "speed";
+const AUDIO: [&str; 3] = ["audio", "waveform", "waveforms"];
+const DURATIONS: &str = "durations";
+
+pub struct TractKoko {
+    plan: TypedSimplePlan<TypedModel>,
+    /// Position of the tokens, style and speed among the model's inputs
+    inputs: [usize; 3],
+    /// Position of the audio and, for timestamped models, the durations among its outputs
+    audio: usize,
+    durations: Option<usize>,
+    version: Option<ModelVersion>,
+}
+
+/// Position of the first of `names` in `values`
+fn position(values: &[&str], names: &[&str], what: &str) -> Result<usize, KokorosError> {
+    values
+        .iter()
+        .position(|value| names.contains(value))
+        .ok_or_else(|| KokorosError::ModelLoad(format!("the model has no {} {}", names[0], what)))
+}
+
+fn load_error(e: TractError) -> KokorosError {
+    KokorosError::ModelLoad(format!("tract could not load the model: {:#}", e))
+}
+
+impl TractKoko {
+    /// Loads and optimizes the ONNX model at `model_path`
+    pub fn new(model_path: &str) -> Result<Self, KokorosError> {
+        let proto = tract_onnx::onnx()
+            .proto_model_for_path(model_path)
+            .map_err(load_error)?;
+        let file_name = Path::new(model_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::from_proto(&proto, &file_name)
+    }
+
+    fn from_proto(proto: &ModelProto, file_name: &str) -> Result<Self, KokorosError> {
+        let graph = proto
+            .graph
+            .as_ref()
+            .ok_or_else(|| KokorosError::ModelLoad("the model has no graph".to_string()))?;
+        // Inputs with an initializer are constants, not inputs of the plan
+        let initialized: HashSet<&str> = graph.initializer.iter().map(|t| &*t.name).collect();
+        let inputs: Vec<&str> = graph
+            .input
+            .iter()
+            .map(|input| &*input.name)
+            .filter(|name| !initialized.contains(name))
+            .collect();
+        let outputs: Vec<&str> = graph.output.iter().map(|output| &*output.name).collect();
+        let inputs = [
+            position(&inputs, &TOKENS, "input")?,
+            position(&inputs, &[STYLE], "input")?,
+            position(&inputs, &[SPEED], "input")?,
+        ];
+        let audio = position(&outputs, &AUDIO, "output")?;
+        let durations = outputs.iter().position(|&output| output == DURATIONS);
+
+        let metadata_version = proto
+            .metadata_props
+            .iter()
+            .find(|prop| prop.key == "version" || prop.key == "model_version")
+            .map(|prop| prop.value.as_str());
+        let plan = tract_onnx::onnx()
+            .model_for_proto_model(proto)
+            .and_then(|model| model.into_optimized())
+            .and_then(|model| model.into_runnable())
+            .map_err(load_error)?;
+        tracing::info!(
+            "TractKoko: {} backend activated ({} outputs)",
+            if durations.is_some() {
+                "Timestamped"
+            } else {
+                "Standard"
+            },
+            outputs.len()
+        );
+        Ok(Self {
+            plan,
+            inputs,
+            audio,
+            durations,
+            version: ModelVersion::detect(metadata_version, file_name),
+        })
+    }
+
+    /// The release of the model, if its metadata or file name says
+    pub fn version(&self) -> Option<ModelVersion> {
+        self.version
+    }
+
+    /// Whether the model also returns durations, for word timestamps
+    pub fn timestamped(&self) -> bool {
+        self.durations.is_some()
+    }
+
+    /// Runs the model on one batch of tokens, as [`OrtKoko::infer`] does
+    ///
+    /// [`OrtKoko::infer`]: super::ort_koko::OrtKoko::infer
+    pub fn infer(
+        &self,
+        tokens: Vec<Vec<i64>>,
+        styles: Vec<Vec<f32>>,
+        speed: f32,
+    ) -> Result<(Vec<f32>, Option<Vec<f32>>), KokorosError> {
+        let error = |e: TractError| KokorosError::Inference(format!("{:#}", e));
+        let tokens_shape = [tokens.len(), tokens[0].len()];
+        let styles_shape = [styles.len(), styles[0].len()];
+        let mut values = [
+            Tensor::from_shape(&tokens_shape, &tokens.concat()).map_err(error)?,
+            Tensor::from_shape(&styles_shape, &styles.concat()).map_err(error)?,
+            Tensor::from_shape(&[1], &[speed]).map_err(error)?,
+        ]
+        .into_iter()
+        .zip(self.inputs)
+        .collect::<Vec<_>>();
+        values.sort_by_key(|(_, position)| *position);
+        let outputs = self
+            .plan
+            .run(
+                values
+                    .into_iter()
+                    .map(|(tensor, _)| tensor.into())
+                    .collect(),
+            )
+            .map_err(error)?;
+
+        let floats = |index: usize| -> Result<Vec<f32>, KokorosError> {
+            let tensor = outputs[index].cast_to::<f32>().map_err(error)?;
+            Ok(tensor.as_slice::<f32>().map_err(error)?.to_vec())
+        };
+        let audio = floats(self.audio)?;
+        let durations = self.durations.map(floats).transpose()?;
+        Ok((audio, durations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tract_onnx::pb::{
+        AttributeProto, GraphProto, NodeProto, OperatorSetIdProto, StringStringEntryProto,
+        TensorShapeProto, TypeProto, ValueInfoProto, attribute_proto::AttributeType,
+        tensor_proto::DataType, tensor_shape_proto::Dimension, tensor_shape_proto::dimension,
+        type_proto,
+    };
+
+    fn value(name: &str, elem_type: DataType, dims: &[Option<i64>]) -> ValueInfoProto {
+        let dim = dims
+            .iter()
+            .map(|dim| Dimension {
+                value: Some(match dim {
+                    Some(size) => dimension::Value::DimValue(*size),
+                    None => dimension::Value::DimParam("tokens".to_string()),
+                }),
+                ..Dimension::default()
+            })
+            .collect();
+        ValueInfoProto {
+            name: name.to_string(),
+            r#type: Some(TypeProto {
+                value: Some(type_proto::Value::TensorType(type_proto::Tensor {
+                    elem_type: elem_type as i32,
+                    shape: Some(TensorShapeProto { dim }),
+                })),
+                ..TypeProto::default()
+            }),
+            ..ValueInfoProto::default()
+        }
+    }
+
+    fn node(op_type: &str, input: &[&str], output: &str) -> NodeProto {
+        NodeProto {
+            op_type: op_type.to_string(),
+            input: input.iter().map(|name| name.to_string()).collect(),
+            output: vec![output.to_string()],
+            ..NodeProto::default()
+        }
+    }
+
+    /// A stand-in for the timestamped model: the waveform is the tokens times the speed,
+    /// and the durations are the tokens
+    fn model() -> ModelProto {
+        let mut cast = node("Cast", &["input_ids"], "durations");
+        cast.attribute.push(AttributeProto {
+            name: "to".to_string(),
+            r#type: AttributeType::Int as i32,
+            i: DataType::Float as i64,
+            ..AttributeProto::default()
+        });
+        ModelProto {
+            ir_version: 8,
+            opset_import: vec![OperatorSetIdProto {
+                domain: String::new(),
+                version: 13,
+            }],
+            metadata_props: vec![StringStringEntryProto {
+                key: "version".to_string(),
+                value: "0.19".to_string(),
+            }],
+            graph: Some(GraphProto {
+                node: vec![cast, node("Mul", &["durations", "speed"], "waveform")],
+                input: vec![
+                    value("input_ids", DataType::Int64, &[Some(1), None]),
+                    value("style", DataType::Float, &[Some(1), Some(256)]),
+                    value("speed", DataType::Float, &[Some(1)]),
+                ],
+                output: vec![
+                    value("waveform", DataType::Float, &[Some(1), None]),
+                    value("durations", DataType::Float, &[Some(1), None]),
+                ],
+                ..GraphProto::default()
+            }),
+            ..ModelProto::default()
+        }
+    }
+
+    #[test]
+    fn test_infer() {
+        let tract = TractKoko::from_proto(&model(), "kokoro.onnx").unwrap();
+        assert!(tract.timestamped());
+        assert_eq!(tract.version(), Some(ModelVersion::V0_19));
+        let (audio, durations) = tract
+            .infer(vec![vec![0, 2, 3, 0]], vec![vec![0.0; 256]], 0.5)
+            .unwrap();
+        assert_eq!(audio, [0.0, 1.0, 1.5, 0.0]);
+        assert_eq!(durations.unwrap(), [0.0, 2.0, 3.0, 0.0]);
+
+        let mut no_speed = model();
+        no_speed.graph.as_mut().unwrap().input.pop();
+        assert!(matches!(
+            TractKoko::from_proto(&no_speed, "kokoro.onnx"),
+            Err(KokorosError::ModelLoad(_))
+        ));
+    }
+}
@@ -0,0 +1,264 @@
+//! Construction of a [`TTSKoko`] from named options rather than positional arguments, so
+//! new options don't change the signature callers build against.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), kokoros::error::KokorosError> {
+//! use kokoros::onn::ort_base::Provider;
+//! use kokoros::tts::koko::TTSKoko;
+//!
+//! let tts = TTSKoko::builder("kokoro-v1.0.onnx", "voices-v1.0.bin")
+//!     .voices_dir("my-voices")
+//!     .provider(Provider::Cpu)
+//!     .threads(4)
+//!     .first_chunk_tokens(Some(16))
+//!     .warmup(true)
+//!     .build()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::PathBuf;
+
+use crate::error::KokorosError;
+use crate::onn::ort_base::{OptimizationLevel, Provider, SessionOptions};
+use crate::tts::chunker::MAX_CHUNK_TOKENS;
+use crate::tts::koko::{InitConfig, TTSKoko, WARMUP_TOKEN_LENGTHS};
+use crate::tts::normalize::TextOptions;
+use crate::tts::priority::{Balance, Priority};
+use crate::tts::vocab::ModelVersion;
+
+/// Options of a [`TTSKoko`], see [`TTSKoko::builder`]
+#[derive(Clone)]
+pub struct TTSKokoBuilder {
+    model_path: String,
+    voices_path: String,
+    voices_dirs: Vec<PathBuf>,
+    config: InitConfig,
+    warmup: bool,
+}
+
+impl TTSKokoBuilder {
+    /// An engine loading the model at `model_path` and the voices at `voices_path`, with
+    /// the defaults of [`InitConfig`]
+    pub fn new(model_path: impl Into<String>, voices_path: impl Into<String>) -> Self {
+        Self {
+            model_path: model_path.into(),
+            voices_path: voices_path.into(),
+            voices_dirs: Vec::new(),
+            config: InitConfig::default(),
+            warmup: false,
+        }
+    }
+
+    /// Where the model is downloaded from if `model_path` doesn't exist (with the
+    /// `download` feature)
+    pub fn model_url(mut self, url: impl Into<String>) -> Self {
+        self.config.model_url = url.into();
+        self
+    }
+
+    /// Where the voices are downloaded from if `voices_path` doesn't exist
+    pub fn voices_url(mut self, url: impl Into<String>) -> Self {
+        self.config.voices_url = url.into();
+        self
+    }
+
+    /// Also loads the voices in `dir`, as [`TTSKoko::add_voices_dir`] does; directories
+    /// added later take precedence for voices of the same name
+    pub fn voices_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.voices_dirs.push(dir.into());
+        self
+    }
+
+    /// ONNX Runtime options, replacing any set with the methods below
+    pub fn session(mut self, options: SessionOptions) -> Self {
+        self.config.session = options;
+        self
+    }
+
+    /// Runs the model on `provider` instead of the one the build prefers
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.config.session.provider = Some(provider);
+        self
+    }
+
+    /// Loads the model into `sessions` sessions sharing its weights, so clones of the
+    /// engine can infer that many chunks at once, see [`InitConfig::sessions`]
+    pub fn sessions(mut self, sessions: usize) -> Self {
+        self.config.sessions = sessions;
+        self
+    }
+
+    /// Spreads the sessions over these CUDA devices, loading the model into at least one
+    /// session on each, see [`GpuOptions`](crate::tts::koko::GpuOptions)
+    pub fn gpu_devices(mut self, devices: impl IntoIterator<Item = i32>) -> Self {
+        self.config.gpus.devices = devices.into_iter().collect();
+        self
+    }
+
+    /// How chunks are spread over the sessions, and so over the GPUs
+    pub fn balance(mut self, balance: Balance) -> Self {
+        self.config.gpus.balance = balance;
+        self
+    }
+
+    /// Threads sharing the work of one operator
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.session.intra_threads = Some(threads);
+        self
+    }
+
+    /// Threads running independent operators at once, which turns on parallel execution
+    pub fn inter_threads(mut self, threads: usize) -> Self {
+        self.config.session.inter_threads = Some(threads);
+        self.config.session.parallel_execution = true;
+        self
+    }
+
+    /// Whether CPU allocations come from an arena that is never returned to the system
+    pub fn cpu_arena(mut self, enabled: bool) -> Self {
+        self.config.session.cpu_arena = enabled;
+        self
+    }
+
+    /// Bytes of GPU memory the CUDA arena may take
+    pub fn gpu_memory_limit(mut self, bytes: usize) -> Self {
+        self.config.session.gpu_memory_limit = Some(bytes);
+        self
+    }
+
+    /// How far ORT optimizes the graph, see [`SessionOptions::optimization`]
+    pub fn optimization(mut self, level: OptimizationLevel) -> Self {
+        self.config.session.optimization = Some(level);
+        self
+    }
+
+    /// Keeps the optimized graph in `dir` and loads it from there on the next start, see
+    /// [`SessionOptions::optimized_model_dir`]
+    pub fn optimized_model_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.session.optimized_model_dir = Some(dir.into());
+        self
+    }
+
+    /// Profiles the sessions, writing their profiles to files named after `prefix`
+    pub fn profiling(mut self, prefix: impl Into<PathBuf>) -> Self {
+        self.config.session.profiling = Some(prefix.into());
+        self
+    }
+
+    /// Starts streamed synthesis with a chunk of at most `tokens` tokens, see
+    /// [`TTSKoko::with_first_chunk_tokens`]
+    pub fn first_chunk_tokens(mut self, tokens: Option<usize>) -> Self {
+        self.config.first_chunk_tokens = tokens;
+        self
+    }
+
+    /// Queues synthesis at `priority`, see [`TTSKoko::with_priority`]
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.config.priority = priority;
+        self
+    }
+
+    /// Rewrites of the text before it is phonemized, see [`TTSKoko::with_text_options`]
+    pub fn text_options(mut self, options: TextOptions) -> Self {
+        self.config.text = options;
+        self
+    }
+
+    /// Loads the model as `version` whatever its metadata says, see
+    /// [`InitConfig::model_version`]
+    pub fn model_version(mut self, version: ModelVersion) -> Self {
+        self.config.model_version = Some(version);
+        self
+    }
+
+    /// Runs the model once on dummy input of a few lengths before returning, see
+    /// [`TTSKoko::warmup`]
+    pub fn warmup(mut self, warmup: bool) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    fn validate(&self) -> Result<(), KokorosError> {
+        let session = &self.config.session;
+        if session.intra_threads == Some(0) || session.inter_threads == Some(0) {
+            return Err(KokorosError::Config(
+                "threads must be at least 1".to_string(),
+            ));
+        }
+        if self.config.sessions == 0 {
+            return Err(KokorosError::Config(
+                "sessions must be at least 1".to_string(),
+            ));
+        }
+        if let Some(tokens) = self.config.first_chunk_tokens
+            && !(1..=MAX_CHUNK_TOKENS).contains(&tokens)
+        {
+            return Err(KokorosError::Config(format!(
+                "first chunk of {} tokens, expected 1 to {}",
+                tokens, MAX_CHUNK_TOKENS
+            )));
+        }
+        if let Some(device) = self.config.gpus.devices.iter().find(|&&device| device < 0) {
+            return Err(KokorosError::Config(format!(
+                "GPU device {}, expected 0 or more",
+                device
+            )));
+        }
+        if session.provider == Some(Provider::Cuda) && !cfg!(feature = "cuda") {
+            return Err(KokorosError::Config(
+                "the CUDA provider needs a build with the cuda feature".to_string(),
+            ));
+        }
+        if session.provider == Some(Provider::Tract) && !cfg!(feature = "tract") {
+            return Err(KokorosError::Config(
+                "the tract provider needs a build with the tract feature".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the options, then loads the model and voices, downloading them first if they
+    /// don't exist
+    pub async fn build(self) -> Result<TTSKoko, KokorosError> {
+        self.validate()?;
+        let mut tts =
+            TTSKoko::try_from_config(&self.model_path, &self.voices_path, self.config).await?;
+        for dir in &self.voices_dirs {
+            tts.add_voices_dir(dir)?;
+        }
+        if self.warmup {
+            tts.warmup(&WARMUP_TOKEN_LENGTHS)?;
+        }
+        Ok(tts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let builder = TTSKokoBuilder::new("model.onnx", "voices.bin").threads(2);
+        assert!(builder.validate().is_ok());
+        assert_eq!(builder.config.session.intra_threads, Some(2));
+        assert!(matches!(
+            builder.clone().threads(0).validate(),
+            Err(KokorosError::Config(_))
+        ));
+        assert!(matches!(
+            builder.clone().sessions(0).validate(),
+            Err(KokorosError::Config(_))
+        ));
+        assert_eq!(
+            builder.clone().provider(Provider::Tract).validate().is_ok(),
+            cfg!(feature = "tract")
+        );
+        assert!(matches!(
+            builder.first_chunk_tokens(Some(0)).validate(),
+            Err(KokorosError::Config(_))
+        ));
+    }
+}
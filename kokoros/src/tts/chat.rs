@@ -0,0 +1,226 @@
+//! Voicing the answer of an OpenAI-compatible chat endpoint as it is generated (`chat`
+//! feature). The completion is requested with `stream: true`, and the content deltas of
+//! the server-sent events go through a [`StreamingSynthesizer`], so each sentence is
+//! spoken as soon as the model has finished writing it.
+//!
+//! Works with anything that speaks the chat completions API: OpenAI, llama.cpp's server,
+//! Ollama, vLLM and the like.
+
+use std::sync::mpsc as std_mpsc;
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::error::KokorosError;
+use crate::tts::koko::TTSKoko;
+use crate::tts::streaming::StreamingSynthesizer;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    /// "system", "user" or "assistant"
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.into(),
+        }
+    }
+}
+
+/// Where to get the answer from and what to ask
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    /// Base URL of the API, e.g. `https://api.openai.com/v1` or `http://localhost:11434/v1`;
+    /// `/chat/completions` is appended
+    pub endpoint: String,
+    /// Sent as a bearer token, if set
+    pub api_key: Option<String>,
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// What a [`ChatStream`] yields, in the order it happens
+#[derive(Debug)]
+pub enum ChatEvent {
+    /// A piece of the answer's text, as the endpoint sent it
+    Text(String),
+    /// A chunk of the spoken answer
+    Audio(Vec<f32>),
+}
+
+/// The answer, as text and audio; see [`voice_chat`]
+pub struct ChatStream {
+    events: mpsc::Receiver<Result<ChatEvent, KokorosError>>,
+}
+
+impl ChatStream {
+    /// The next piece of text or audio, or `None` once the answer has been spoken
+    pub async fn next(&mut self) -> Option<Result<ChatEvent, KokorosError>> {
+        self.events.recv().await
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SseLine {
+    /// A piece of the answer
+    Delta(String),
+    /// `data: [DONE]`, the end of the stream
+    Done,
+    /// Comments, other fields, and events without content (e.g. the role, or the finish
+    /// reason)
+    Other,
+}
+
+fn parse_sse_line(line: &str) -> Result<SseLine, KokorosError> {
+    let Some(data) = line.strip_prefix("data:") else {
+        return Ok(SseLine::Other);
+    };
+    let data = data.trim();
+    if data == "[DONE]" {
+        return Ok(SseLine::Done);
+    }
+    let event: serde_json::Value = serde_json::from_str(data)
+        .map_err(|e| KokorosError::Chat(format!("unreadable event {:?}: {}", data, e)))?;
+    if let Some(error) = event.get("error") {
+        return Err(KokorosError::Chat(format!(
+            "the endpoint reported {}",
+            error
+        )));
+    }
+    Ok(event["choices"][0]["delta"]["content"]
+        .as_str()
+        .filter(|content| !content.is_empty())
+        .map_or(SseLine::Other, |content| {
+            SseLine::Delta(content.to_string())
+        }))
+}
+
+/// Requests the completion and sends each piece of its text to both `events` and
+/// `fragments`, until the endpoint is done or `events` is dropped
+async fn read_completion(
+    request: ChatRequest,
+    events: &mpsc::Sender<Result<ChatEvent, KokorosError>>,
+    fragments: &std_mpsc::Sender<String>,
+) -> Result<(), KokorosError> {
+    let url = format!(
+        "{}/chat/completions",
+        request.endpoint.trim_end_matches('/')
+    );
+    let body = serde_json::json!({
+        "model": request.model,
+        "messages": request.messages,
+        "stream": true,
+    });
+    let mut http = reqwest::Client::new()
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string());
+    if let Some(key) = &request.api_key {
+        http = http.bearer_auth(key);
+    }
+    let chat_error = |e: reqwest::Error| KokorosError::Chat(format!("{}: {}", url, e));
+    let mut response = http.send().await.map_err(chat_error)?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        return Err(KokorosError::Chat(format!(
+            "{} answered {}: {}",
+            url,
+            status,
+            text.trim()
+        )));
+    }
+
+    let mut buffer = Vec::new();
+    while let Some(bytes) = response.chunk().await.map_err(chat_error)? {
+        buffer.extend_from_slice(&bytes);
+        // Events may be split anywhere, even inside a character
+        while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=newline).collect();
+            match parse_sse_line(String::from_utf8_lossy(&line).trim_end())? {
+                SseLine::Delta(text) => {
+                    if events
+                        .send(Ok(ChatEvent::Text(text.clone())))
+                        .await
+                        .is_err()
+                    {
+                        return Err(KokorosError::Cancelled);
+                    }
+                    // Fails only if synthesis stopped, which reports its own error
+                    let _ = fragments.send(text);
+                }
+                SseLine::Done => return Ok(()),
+                SseLine::Other => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Asks `request`'s endpoint for a chat completion and speaks the answer with `tts` as
+/// it streams in. The text and audio are yielded as they arrive; dropping the stream
+/// cancels both the request and the synthesis.
+pub fn voice_chat(
+    tts: &TTSKoko,
+    request: ChatRequest,
+    lan: &str,
+    style: &str,
+    speed: f32,
+) -> ChatStream {
+    let (events, receiver) = mpsc::channel(64);
+    let (fragments, fragments_receiver) = std_mpsc::channel::<String>();
+
+    let synth_events = events.clone();
+    let mut synth = StreamingSynthesizer::new(tts.clone(), lan, style, speed, move |audio| {
+        synth_events
+            .blocking_send(Ok(ChatEvent::Audio(audio)))
+            .map_err(|_| Box::new(KokorosError::Cancelled).into())
+    });
+    let error_events = events.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = fragments_receiver
+            .iter()
+            .try_for_each(|fragment| synth.push_str(&fragment))
+            .and_then(|()| synth.close());
+        if let Err(e) = result {
+            let _ = error_events.blocking_send(Err(e));
+        }
+    });
+
+    tokio::spawn(async move {
+        match read_completion(request, &events, &fragments).await {
+            Ok(()) | Err(KokorosError::Cancelled) => {}
+            Err(e) => {
+                let _ = events.send(Err(e)).await;
+            }
+        }
+        // Dropping `fragments` lets the synthesizer speak the rest and finish
+    });
+
+    ChatStream { events: receiver }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sse_line() {
+        let delta = r#"data: {"choices":[{"index":0,"delta":{"content":"Hel"}}]}"#;
+        assert_eq!(
+            parse_sse_line(delta).unwrap(),
+            SseLine::Delta("Hel".to_string())
+        );
+        let role = r#"data: {"choices":[{"index":0,"delta":{"role":"assistant"}}]}"#;
+        assert_eq!(parse_sse_line(role).unwrap(), SseLine::Other);
+        assert_eq!(parse_sse_line("data: [DONE]").unwrap(), SseLine::Done);
+        assert_eq!(parse_sse_line(": keep-alive").unwrap(), SseLine::Other);
+        assert_eq!(parse_sse_line("").unwrap(), SseLine::Other);
+        assert!(parse_sse_line(r#"data: {"error":{"message":"overloaded"}}"#).is_err());
+        assert!(parse_sse_line("data: {").is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! Splitting of long texts into chunks that fit the model's context. Chunks are packed
+//! from whole sentences where possible, then from clauses, then from words, so a chunk
+//! never ends in the middle of a word.
+
+/// Most tokens one chunk may phonemize to: the model's 512-token context less the two pad
+/// tokens, and one below the 510 style vectors a voice holds, which are indexed by length
+pub const MAX_CHUNK_TOKENS: usize = 509;
+
+/// Tokens for a short first chunk when streaming: about a second of speech, which the
+/// model synthesizes in a fraction of the time a full chunk takes
+pub const FIRST_CHUNK_TOKENS: usize = 16;
+
+/// End a sentence when followed by whitespace or the end of the text
+const TERMINATORS: &[char] = &['.', '!', '?', '…'];
+/// End a sentence even without whitespace after them, as in CJK text
+const FULL_WIDTH_TERMINATORS: &[char] = &['。', '！', '？'];
+/// Belong to the sentence they follow, e.g. `He said "no."`
+const CLOSERS: &[char] = &['"', '\'', '”', '’', ')', ']', '」', '』'];
+/// End a clause when followed by whitespace
+const CLAUSE_BREAKS: &[char] = &[',', ';', ':', '—', '–'];
+const FULL_WIDTH_CLAUSE_BREAKS: &[char] = &['，', '、', '；', '：'];
+/// Words whose trailing period doesn't end the sentence
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "mt", "vs", "e.g", "i.e", "cf", "fig",
+    "approx", "dept", "inc", "ltd", "co",
+];
+
+/// Byte offset of `piece` in `text`, which it is a slice of
+pub(crate) fn offset_in(text: &str, piece: &str) -> usize {
+    piece.as_ptr() as usize - text.as_ptr() as usize
+}
+
+fn is_abbreviation(before: &str) -> bool {
+    let word = before
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches(|c: char| !c.is_alphanumeric());
+    // Single capitals are initials, as in "J. R. R. Tolkien"
+    ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+        || (word.chars().count() == 1 && word.chars().all(char::is_uppercase))
+}
+
+/// Splits `text` after each of `breaks` that is followed by whitespace (or any of
+/// `full_width`), trimming the pieces. `end_here` may veto a break at a byte offset.
+fn split_after<'a>(
+    text: &'a str,
+    breaks: &[char],
+    full_width: &[char],
+    end_here: impl Fn(usize) -> bool,
+) -> Vec<&'a str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let mut end = i + c.len_utf8();
+        if !full_width.contains(&c) {
+            if !breaks.contains(&c) {
+                continue;
+            }
+            // Runs like "?!" or "..." and closing quotes stay together
+            while let Some(&(j, next)) = chars.peek() {
+                if !breaks.contains(&next) && !CLOSERS.contains(&next) {
+                    break;
+                }
+                end = j + next.len_utf8();
+                chars.next();
+            }
+            if chars.peek().is_some_and(|&(_, next)| !next.is_whitespace()) || !end_here(i) {
+                continue;
+            }
+        }
+        let piece = text[start..end].trim();
+        if !piece.is_empty() {
+            pieces.push(piece);
+        }
+        start = end;
+    }
+    let rest = text[start..].trim();
+    if !rest.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+/// The sentences of `text`, with their punctuation. Decimals, abbreviations and initials
+/// don't end one.
+pub fn sentences(text: &str) -> Vec<&str> {
+    split_after(text, TERMINATORS, FULL_WIDTH_TERMINATORS, |i| {
+        !text[i..].starts_with('.') || !is_abbreviation(&text[..i])
+    })
+}
+
+/// The clauses of `sentence`, split after commas, semicolons, colons and dashes
+pub fn clauses(sentence: &str) -> Vec<&str> {
+    split_after(sentence, CLAUSE_BREAKS, FULL_WIDTH_CLAUSE_BREAKS, |_| true)
+}
+
+#[derive(Clone, Copy)]
+enum Level {
+    Sentence,
+    Clause,
+    Word,
+}
+
+struct Packer<F> {
+    count: F,
+    max_tokens: usize,
+    chunks: Vec<String>,
+    current: String,
+}
+
+impl<F: FnMut(&str) -> usize> Packer<F> {
+    /// Appends `unit` to the chunk being built, or starts a new chunk with it when it
+    /// doesn't fit. A unit too long for a chunk of its own is split at the next finer
+    /// boundary.
+    fn add(&mut self, unit: &str, level: Level) {
+        if !self.current.is_empty() {
+            let kept = self.current.len();
+            self.current.push(' ');
+            self.current.push_str(unit);
+            if (self.count)(&self.current) <= self.max_tokens {
+                return;
+            }
+            self.current.truncate(kept);
+            self.chunks.push(std::mem::take(&mut self.current));
+        }
+        if (self.count)(unit) <= self.max_tokens {
+            self.current.push_str(unit);
+            return;
+        }
+
+        match level {
+            Level::Sentence => {
+                for clause in clauses(unit) {
+                    self.add(clause, Level::Clause);
+                }
+            }
+            Level::Clause => {
+                for word in unit.split_whitespace() {
+                    self.add(word, Level::Word);
+                }
+            }
+            // A run without whitespace that is too long on its own (a URL, or text in a
+            // script without spaces) can only be cut between characters
+            Level::Word => {
+                for c in unit.chars() {
+                    self.current.push(c);
+                    if self.current.chars().count() > 1
+                        && (self.count)(&self.current) > self.max_tokens
+                    {
+                        self.current.pop();
+                        self.chunks.push(std::mem::take(&mut self.current));
+                        self.current.push(c);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Splits `text` into chunks of at most `max_tokens` tokens as measured by `count`,
+/// packing as many whole sentences into each as fit
+pub fn split_into_chunks(
+    text: &str,
+    max_tokens: usize,
+    count: impl FnMut(&str) -> usize,
+) -> Vec<String> {
+    let mut packer = Packer {
+        count,
+        max_tokens,
+        chunks: Vec::new(),
+        current: String::new(),
+    };
+    for sentence in sentences(text) {
+        packer.add(sentence, Level::Sentence);
+    }
+    if !packer.current.is_empty() {
+        packer.chunks.push(packer.current);
+    }
+    packer.chunks
+}
+
+/// Like [`split_into_chunks`], but starting with a chunk of at most `first_tokens` tokens:
+/// the first clause if it is that short, or else as many of its first words as fit. The
+/// audio of a stream starts that much sooner, at the cost of a seam where the voice may
+/// not flow as it would within one chunk.
+pub fn split_with_short_start(
+    text: &str,
+    max_tokens: usize,
+    first_tokens: usize,
+    mut count: impl FnMut(&str) -> usize,
+) -> Vec<String> {
+    let Some(sentence) = sentences(text).into_iter().next() else {
+        return Vec::new();
+    };
+    let clause = clauses(sentence)[0];
+    let start = offset_in(text, clause);
+    let mut end = start + clause.len();
+    if count(clause) > first_tokens {
+        end = 0;
+        for word in clause.split_whitespace() {
+            let word_end = offset_in(text, word) + word.len();
+            if count(&text[start..word_end]) > first_tokens {
+                break;
+            }
+            end = word_end;
+        }
+    }
+    let rest = &text[end..];
+    // Nothing to gain when the whole text is short, or its first word alone is too long
+    if end == 0 || rest.trim().is_empty() {
+        return split_into_chunks(text, max_tokens, count);
+    }
+    let mut chunks = vec![text[..end].trim().to_string()];
+    chunks.extend(split_into_chunks(rest, max_tokens, count));
+    chunks
+}
+
+/// A piece of a text cut by [`split_repeats`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment<'a> {
+    pub text: &'a str,
+    /// Whether this is a sentence that occurs more than once in the text
+    pub repeated: bool,
+}
+
+/// Sentences compared the way [`split_repeats`] compares them, ignoring runs of whitespace
+pub fn sentence_key(sentence: &str) -> String {
+    sentence.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `text` cut so that sentences occurring more than once stand alone, to be synthesized
+/// once and reused, while the runs of other sentences between them stay together and are
+/// chunked as usual. A text without repeats is one segment.
+pub fn split_repeats(text: &str) -> Vec<Segment<'_>> {
+    let sentences = sentences(text);
+    let mut counts = std::collections::HashMap::new();
+    for sentence in &sentences {
+        *counts.entry(sentence_key(sentence)).or_insert(0) += 1;
+    }
+
+    let mut segments = Vec::new();
+    let mut run: Option<(usize, usize)> = None;
+    for sentence in sentences {
+        let start = offset_in(text, sentence);
+        let end = start + sentence.len();
+        if counts[&sentence_key(sentence)] == 1 {
+            run = Some((run.map_or(start, |(run_start, _)| run_start), end));
+            continue;
+        }
+        if let Some((run_start, run_end)) = run.take() {
+            segments.push(Segment {
+                text: &text[run_start..run_end],
+                repeated: false,
+            });
+        }
+        segments.push(Segment {
+            text: sentence,
+            repeated: true,
+        });
+    }
+    if let Some((run_start, run_end)) = run {
+        segments.push(Segment {
+            text: &text[run_start..run_end],
+            repeated: false,
+        });
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_into_chunks() {
+        assert_eq!(
+            sentences("Dr. Smith paid $3.50. Was it fair?! \"Yes.\" J. R. R. Tolkien wrote it…"),
+            [
+                "Dr. Smith paid $3.50.",
+                "Was it fair?!",
+                "\"Yes.\"",
+                "J. R. R. Tolkien wrote it…"
+            ]
+        );
+        assert_eq!(sentences("你好。谢谢！"), ["你好。", "谢谢！"]);
+        assert_eq!(
+            clauses("First, the good news; then: the rest"),
+            ["First,", "the good news;", "then:", "the rest"]
+        );
+
+        let count = |chunk: &str| chunk.chars().count();
+        let text = "One two. Three four five six seven, eight nine ten. Eleven.";
+        let chunks = split_into_chunks(text, 20, count);
+        assert_eq!(
+            chunks,
+            [
+                "One two.",
+                "Three four five six",
+                "seven,",
+                "eight nine ten.",
+                "Eleven."
+            ]
+        );
+        // Chunks fit and only ever break between words
+        let chunks = split_into_chunks(text, 12, count);
+        assert!(chunks.iter().all(|c| c.chars().count() <= 12));
+        assert_eq!(chunks.join(" "), text);
+        assert_eq!(split_into_chunks("abcdefg", 3, count), ["abc", "def", "g"]);
+
+        assert_eq!(
+            split_with_short_start(text, 20, 10, count),
+            [
+                "One two.",
+                "Three four five six",
+                "seven,",
+                "eight nine ten.",
+                "Eleven."
+            ]
+        );
+        assert_eq!(
+            split_with_short_start("Well, that went well. Next.", 30, 8, count),
+            ["Well,", "that went well. Next."]
+        );
+        assert_eq!(
+            split_with_short_start("So it goes on and on. Next.", 30, 8, count),
+            ["So it", "goes on and on. Next."]
+        );
+        assert_eq!(split_with_short_start("Short.", 30, 8, count), ["Short."]);
+    }
+
+    #[test]
+    fn test_split_repeats() {
+        let text = "Revenue rose. See the table.\nCosts fell. Margins held.  See the  table. End.";
+        let segments: Vec<(&str, bool)> = split_repeats(text)
+            .into_iter()
+            .map(|s| (s.text, s.repeated))
+            .collect();
+        assert_eq!(
+            segments,
+            [
+                ("Revenue rose.", false),
+                ("See the table.", true),
+                ("Costs fell. Margins held.", false),
+                ("See the  table.", true),
+                ("End.", false)
+            ]
+        );
+        assert_eq!(split_repeats("No repeats. At all."), [Segment {
+            text: "No repeats. At all.",
+            repeated: false
+        }]);
+    }
+}
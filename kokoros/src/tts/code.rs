@@ -0,0 +1,286 @@
+//! Source code read the way a developer would read it aloud: symbols as their names
+//! ("=>" as "fat arrow", "!=" as "not equals"), identifiers split into their words
+//! ("parseHttpResponse", "MAX_CHUNK_TOKENS"), changes of indentation announced, and
+//! comments read as prose. Unified diffs are read hunk by hunk, with each changed line
+//! said to be added or removed.
+
+/// Columns a tab counts for when measuring indentation
+const TAB_WIDTH: usize = 4;
+
+/// Symbols and how they are read, longest first so "===" isn't read as "==" and "="
+const SYMBOLS: [(&str, &str); 71] = [
+    ("===", "triple equals"),
+    ("!==", "not triple equals"),
+    ("...", "ellipsis"),
+    ("..=", "dot dot equals"),
+    ("<=>", "spaceship"),
+    ("<<=", "shift left equals"),
+    (">>=", "shift right equals"),
+    ("=>", "fat arrow"),
+    ("->", "arrow"),
+    ("<-", "left arrow"),
+    ("::", "double colon"),
+    ("==", "equals equals"),
+    ("!=", "not equals"),
+    ("<=", "less than or equal"),
+    (">=", "greater than or equal"),
+    ("&&", "and"),
+    ("||", "or"),
+    ("++", "plus plus"),
+    ("--", "minus minus"),
+    ("+=", "plus equals"),
+    ("-=", "minus equals"),
+    ("*=", "times equals"),
+    ("/=", "divide equals"),
+    ("%=", "mod equals"),
+    ("|=", "or equals"),
+    ("&=", "and equals"),
+    ("^=", "x or equals"),
+    ("<<", "shift left"),
+    (">>", "shift right"),
+    ("**", "power"),
+    ("?.", "optional chain"),
+    ("??", "null coalesce"),
+    (":=", "colon equals"),
+    ("|>", "pipe forward"),
+    ("..", "dot dot"),
+    ("/*", "comment start"),
+    ("*/", "comment end"),
+    ("=", "equals"),
+    ("+", "plus"),
+    ("-", "minus"),
+    ("*", "star"),
+    ("/", "slash"),
+    ("%", "percent"),
+    ("<", "less than"),
+    (">", "greater than"),
+    ("!", "bang"),
+    ("?", "question mark"),
+    ("&", "ampersand"),
+    ("|", "pipe"),
+    ("^", "caret"),
+    ("~", "tilde"),
+    ("(", "open paren"),
+    (")", "close paren"),
+    ("[", "open bracket"),
+    ("]", "close bracket"),
+    ("{", "open brace"),
+    ("}", "close brace"),
+    (":", "colon"),
+    (";", "semicolon"),
+    (".", "dot"),
+    (",", ","),
+    ("\"", "quote"),
+    ("'", "single quote"),
+    ("`", "backtick"),
+    ("@", "at"),
+    ("#", "hash"),
+    ("$", "dollar"),
+    ("\\", "backslash"),
+    ("\u{2026}", "ellipsis"),
+    ("\u{2192}", "arrow"),
+    ("\u{2260}", "not equals"),
+];
+
+/// `word` with its words apart: at underscores, between a lower-case letter and a capital
+/// ("camelCase"), before the last capital of a run followed by lower case
+/// ("HTTPServer"), and between letters and digits ("utf8")
+fn split_identifier(word: &str) -> String {
+    let mut split = String::with_capacity(word.len() + 4);
+    for part in word.split('_').filter(|part| !part.is_empty()) {
+        if !split.is_empty() {
+            split.push(' ');
+        }
+        let chars: Vec<char> = part.chars().collect();
+        for (i, &c) in chars.iter().enumerate() {
+            if i > 0 {
+                let prev = chars[i - 1];
+                let next_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+                if c.is_ascii_digit() != prev.is_ascii_digit()
+                    || (c.is_uppercase()
+                        && (prev.is_lowercase() || (prev.is_uppercase() && next_lower)))
+                {
+                    split.push(' ');
+                }
+            }
+            split.push(c);
+        }
+    }
+    split
+}
+
+/// A line of code, without its indentation, as words
+fn verbalize_line(line: &str) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let mut rest = line.trim();
+    // Statements end with one, which isn't worth hearing
+    rest = rest.strip_suffix(';').unwrap_or(rest);
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = rest.trim_start();
+        } else if let Some(comment) = rest.strip_prefix("//") {
+            let doc = comment.starts_with(['/', '!']);
+            let comment = comment.trim_start_matches(['/', '!']).trim();
+            words.push(if doc { "doc comment," } else { "comment," }.to_string());
+            if !comment.is_empty() {
+                words.push(comment.to_string());
+            }
+            break;
+        } else if c.is_alphanumeric() || c == '_' {
+            let end = rest
+                .char_indices()
+                .find(|&(i, ch)| {
+                    !(ch.is_alphanumeric()
+                        || ch == '_'
+                        // Decimal points of numbers
+                        || (c.is_ascii_digit()
+                            && ch == '.'
+                            && rest[i + 1..].starts_with(|d: char| d.is_ascii_digit())))
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            let word = &rest[..end];
+            words.push(if c.is_ascii_digit() {
+                word.replace('_', "")
+            } else {
+                split_identifier(word)
+            });
+            rest = &rest[end..];
+        } else if let Some((symbol, spoken)) =
+            SYMBOLS.iter().find(|(symbol, _)| rest.starts_with(symbol))
+        {
+            match (*spoken, words.last_mut()) {
+                (",", Some(last)) => last.push(','),
+                (",", None) => {}
+                (spoken, _) => words.push(spoken.to_string()),
+            }
+            rest = &rest[symbol.len()..];
+        } else {
+            words.push(c.to_string());
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    let mut spoken = words.join(" ");
+    if spoken.ends_with(',') {
+        spoken.pop();
+    }
+    if !spoken.is_empty() && !spoken.ends_with(['.', '!', '?', ':']) {
+        spoken.push('.');
+    }
+    spoken
+}
+
+/// Columns of indentation at the start of `line`
+fn indentation(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+/// Indentation levels opened so far, announcing the changes from one line to the next
+struct Indents(Vec<usize>);
+
+impl Indents {
+    fn new() -> Self {
+        Self(vec![0])
+    }
+
+    /// What to say before a line indented `width` columns
+    fn announce(&mut self, width: usize) -> Option<String> {
+        let top = *self.0.last().unwrap();
+        if width > top {
+            self.0.push(width);
+            return Some("Indent.".to_string());
+        }
+        let mut levels = 0;
+        while self.0.len() > 1 && *self.0.last().unwrap() > width {
+            self.0.pop();
+            levels += 1;
+        }
+        match levels {
+            0 => None,
+            1 => Some("Dedent.".to_string()),
+            levels => Some(format!("Dedent {} levels.", levels)),
+        }
+    }
+}
+
+/// The line number the new side of a hunk header like `@@ -1,3 +4,5 @@` starts at
+fn hunk_start(header: &str) -> Option<&str> {
+    let new = header
+        .split_whitespace()
+        .find(|part| part.starts_with('+'))?;
+    new[1..].split(',').next()
+}
+
+/// `text` read as code, one sentence per line, see the module documentation. Blank lines
+/// are skipped and don't change the indentation.
+pub fn verbalize_code(text: &str) -> String {
+    let diff = text.lines().any(|line| line.starts_with("@@ "));
+    let mut indents = Indents::new();
+    let mut spoken = Vec::new();
+    for line in text.lines() {
+        let (change, line) = match line.chars().next() {
+            Some(_) if !diff => (None, line),
+            Some('@') if line.starts_with("@@ ") => {
+                indents = Indents::new();
+                if let Some(start) = hunk_start(line) {
+                    spoken.push(format!("Hunk at line {}.", start));
+                }
+                continue;
+            }
+            Some('+') if line.starts_with("+++ ") => {
+                let path = line[4..].trim();
+                if path != "/dev/null" {
+                    let path = path.strip_prefix("b/").unwrap_or(path);
+                    spoken.push(format!("File {}.", path));
+                }
+                continue;
+            }
+            Some('+') => (Some("Added:"), &line[1..]),
+            Some('-') if !line.starts_with("--- ") => (Some("Removed:"), &line[1..]),
+            Some(' ') => (None, &line[1..]),
+            // Headers like `diff --git` and `index`, and `\ No newline at end of file`
+            _ => continue,
+        };
+        let words = verbalize_line(line);
+        if words.is_empty() {
+            continue;
+        }
+        let mut sentence = Vec::with_capacity(3);
+        sentence.extend(indents.announce(indentation(line)));
+        sentence.extend(change.map(str::to_string));
+        sentence.push(words);
+        spoken.push(sentence.join(" "));
+    }
+    spoken.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbalize_code() {
+        assert_eq!(
+            split_identifier("parseHTTPResponse_v2"),
+            "parse HTTP Response v 2"
+        );
+        assert_eq!(
+            verbalize_code("let isDone = count != MAX_ITEMS;"),
+            "let is Done equals count not equals MAX ITEMS."
+        );
+        assert_eq!(
+            verbalize_code("fn main() {\n    x => 1.5, // done\n\n}"),
+            "fn main open paren close paren open brace.\n\
+             Indent. x fat arrow 1.5, comment, done.\n\
+             Dedent. close brace."
+        );
+        assert_eq!(
+            verbalize_code(
+                "diff --git a/x.rs b/x.rs\n--- a/x.rs\n+++ b/x.rs\n@@ -1,2 +3,2 @@\n a\n-b\n+c_d"
+            ),
+            "File x.rs.\nHunk at line 3.\na.\nRemoved: b.\nAdded: c d."
+        );
+    }
+}
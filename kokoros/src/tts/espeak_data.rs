@@ -0,0 +1,115 @@
+//! Where espeak-ng loads its data (phoneme tables, dictionaries) from. espeak-rs reads the
+//! location once, when the first text is phonemized, so it has to be chosen before that.
+//!
+//! Without a choice espeak-rs looks for an `espeak-ng-data` directory in the working
+//! directory and next to the executable, then falls back to the system installation.
+//! With the `bundled-espeak-data` feature the data is embedded in the binary and unpacked
+//! into the cache directory on first use, so no installation is needed at all.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::KokorosError;
+
+/// Name of the data directory, as espeak-ng expects it
+pub const DATA_DIR_NAME: &str = "espeak-ng-data";
+
+/// Environment variable espeak-rs reads the directory containing `espeak-ng-data` from
+const DATA_DIR_VAR: &str = "PIPER_ESPEAKNG_DATA_DIRECTORY";
+
+#[cfg(feature = "bundled-espeak-data")]
+mod bundled {
+    include!(concat!(env!("OUT_DIR"), "/espeak_data.rs"));
+}
+
+/// The directory containing `espeak-ng-data`, given either that directory itself or the one
+/// it is in
+fn parent_of_data_dir(dir: &Path) -> Result<PathBuf, KokorosError> {
+    let data_dir = if dir.file_name().is_some_and(|name| name == DATA_DIR_NAME) {
+        dir.to_path_buf()
+    } else {
+        dir.join(DATA_DIR_NAME)
+    };
+    if !data_dir.join("phontab").is_file() {
+        return Err(KokorosError::Phonemize(format!(
+            "{} is not an espeak-ng data directory (no phontab in {})",
+            dir.display(),
+            data_dir.display()
+        )));
+    }
+    Ok(data_dir
+        .parent()
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf))
+}
+
+/// Unpacks the embedded data into the cache directory, unless a previous run already did,
+/// and returns where it is
+#[cfg(feature = "bundled-espeak-data")]
+pub fn unpack_bundled() -> std::io::Result<PathBuf> {
+    let dir = crate::utils::fileio::cache_dir()
+        .join(format!("espeak-ng-{}", bundled::VERSION))
+        .join(DATA_DIR_NAME);
+    if dir.join("phontab").is_file() {
+        return Ok(dir);
+    }
+    // Unpacked next to the final location and moved there in one step, so an interrupted
+    // run doesn't leave half the files behind for the next one to use
+    let partial = dir.with_extension(format!("partial-{}", std::process::id()));
+    for (name, bytes) in bundled::FILES {
+        let path = partial.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, bytes)?;
+    }
+    match std::fs::rename(&partial, &dir) {
+        Ok(()) => {}
+        // Another process got there first
+        Err(_) if dir.join("phontab").is_file() => {
+            let _ = std::fs::remove_dir_all(&partial);
+        }
+        Err(e) => return Err(e),
+    }
+    tracing::debug!("Unpacked the bundled espeak-ng data to {}", dir.display());
+    Ok(dir)
+}
+
+/// Points espeak-ng at `dir` (an `espeak-ng-data` directory or the one containing it), or
+/// with `None` at the bundled data if there is any and no location was set in the
+/// environment. Returns the data directory chosen, if any.
+///
+/// # Safety
+///
+/// This sets an environment variable, so no other thread may be reading or writing the
+/// environment at the same time; call it early in `main`, before starting other threads.
+pub unsafe fn set_data_dir(dir: Option<&Path>) -> Result<Option<PathBuf>, KokorosError> {
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        #[cfg(feature = "bundled-espeak-data")]
+        None if std::env::var_os(DATA_DIR_VAR).is_none() => unpack_bundled().map_err(|e| {
+            KokorosError::Phonemize(format!("could not unpack the espeak-ng data: {}", e))
+        })?,
+        None => return Ok(None),
+    };
+    let parent = parent_of_data_dir(&dir)?;
+    // Safety: upheld by the caller
+    unsafe { std::env::set_var(DATA_DIR_VAR, &parent) };
+    Ok(Some(parent.join(DATA_DIR_NAME)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parent_of_data_dir() {
+        let root = std::env::temp_dir().join(format!("kokoros-espeak-{}", std::process::id()));
+        let data_dir = root.join(DATA_DIR_NAME);
+        std::fs::create_dir_all(&data_dir).unwrap();
+        assert!(parent_of_data_dir(&root).is_err());
+
+        std::fs::write(data_dir.join("phontab"), b"").unwrap();
+        assert_eq!(parent_of_data_dir(&root).unwrap(), root);
+        assert_eq!(parent_of_data_dir(&data_dir).unwrap(), root);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! Estimates of how long synthesized audio will be, from token counts alone, so large
+//! jobs can be scheduled and costed without running the model
+
+use crate::error::KokorosError;
+use crate::tts::koko::TTSKoko;
+use crate::tts::normalize::TextOptions;
+use crate::tts::vocab::ModelVersion;
+
+/// Seconds of audio per token at speed 1.0. Kokoro predicts about three 1/40 s frames
+/// per phoneme token for English voices, a little more around punctuation.
+pub const DEFAULT_SECONDS_PER_TOKEN: f32 = 0.08;
+
+/// How many seconds of audio a token turns into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// At speed 1.0; the audio shrinks in proportion as speed goes up
+    pub seconds_per_token: f32,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            seconds_per_token: DEFAULT_SECONDS_PER_TOKEN,
+        }
+    }
+}
+
+impl Calibration {
+    /// Least-squares fit to `(tokens, seconds)` measured at speed 1.0, e.g. from
+    /// `koko bench`. `None` without any tokens to fit to.
+    pub fn from_samples(samples: &[(usize, f32)]) -> Option<Self> {
+        let (weighted, squares) = samples.iter().fold(
+            (0.0f64, 0.0f64),
+            |(weighted, squares), &(tokens, seconds)| {
+                let tokens = tokens as f64;
+                (
+                    weighted + tokens * seconds as f64,
+                    squares + tokens * tokens,
+                )
+            },
+        );
+        (squares > 0.0).then(|| Self {
+            seconds_per_token: (weighted / squares) as f32,
+        })
+    }
+
+    /// Seconds of audio for `tokens` spoken at `speed`
+    pub fn seconds(&self, tokens: usize, speed: f32) -> f32 {
+        tokens as f32 * self.seconds_per_token / speed.max(f32::EPSILON)
+    }
+}
+
+/// Expected size of the audio for a text
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DurationEstimate {
+    pub chunks: usize,
+    pub tokens: usize,
+    pub seconds: f32,
+}
+
+impl std::ops::AddAssign for DurationEstimate {
+    fn add_assign(&mut self, other: Self) {
+        self.chunks += other.chunks;
+        self.tokens += other.tokens;
+        self.seconds += other.seconds;
+    }
+}
+
+/// Estimates how long `text` will sound at `speed`, rewriting, chunking and phonemizing
+/// it like synthesis with `options` on a model of `version` does, but without loading the
+/// model. Initial silence isn't included.
+pub fn estimate_duration(
+    text: &str,
+    lan: &str,
+    speed: f32,
+    calibration: &Calibration,
+    options: &TextOptions,
+    version: ModelVersion,
+) -> Result<DurationEstimate, KokorosError> {
+    let chunks = TTSKoko::phonemize(text, lan, options, version)?;
+    let tokens = chunks.iter().map(|c| c.tokens.len()).sum();
+    Ok(DurationEstimate {
+        chunks: chunks.len(),
+        tokens,
+        seconds: calibration.seconds(tokens, speed),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibration() {
+        let calibration = Calibration::from_samples(&[(100, 7.0), (200, 15.0)]).unwrap();
+        assert!((calibration.seconds_per_token - 0.074).abs() < 1e-6);
+        assert!((calibration.seconds(100, 2.0) - 3.7).abs() < 1e-4);
+        assert_eq!(Calibration::from_samples(&[(0, 1.0)]), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Golden pronunciation files: words and sentences with the phonemes they should come out
+//! as, checked before a release so changes to the lexicon, the normalizer or espeak don't
+//! quietly change how things are read.
+//!
+//! A golden file is a TSV of `text`, `phonemes` and optionally a language, one case per
+//! line. Blank lines and lines starting with `#` are ignored, and so is a first line
+//! starting with `text<TAB>`, as a header.
+
+use crate::tts::koko::{KokorosError, TTSKoko};
+use crate::tts::normalize::TextOptions;
+
+/// One line of a golden file
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenCase {
+    /// Line number in the file, from 1
+    pub line: usize,
+    pub text: String,
+    pub phonemes: String,
+    /// Language to phonemize in, over the one the file is checked with
+    pub lan: Option<String>,
+}
+
+/// A case that phonemized to something else than expected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch<'a> {
+    pub case: &'a GoldenCase,
+    pub actual: String,
+}
+
+/// Reads the cases of a golden file
+pub fn parse_golden(content: &str) -> Result<Vec<GoldenCase>, String> {
+    let mut cases = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || (i == 0 && line.starts_with("text\t"))
+        {
+            continue;
+        }
+        let mut columns = line.split('\t');
+        let text = columns.next().unwrap_or_default().trim();
+        let Some(phonemes) = columns.next() else {
+            return Err(format!(
+                "line {}: expected text and phonemes separated by a tab",
+                i + 1
+            ));
+        };
+        let lan = columns.next().map(str::trim).filter(|lan| !lan.is_empty());
+        cases.push(GoldenCase {
+            line: i + 1,
+            text: text.to_string(),
+            phonemes: phonemes.trim().to_string(),
+            lan: lan.map(str::to_string),
+        });
+    }
+    Ok(cases)
+}
+
+/// Phonemizes every case in its language, or `lan`, with `options` applied as synthesis
+/// would, and returns those that don't match
+pub fn verify<'a>(
+    cases: &'a [GoldenCase],
+    lan: &str,
+    options: &TextOptions,
+) -> Result<Vec<Mismatch<'a>>, KokorosError> {
+    let mut mismatches = Vec::new();
+    for case in cases {
+        let actual = TTSKoko::phonemize_with_options(
+            &case.text,
+            case.lan.as_deref().unwrap_or(lan),
+            options,
+        )?;
+        let actual = actual.trim();
+        if actual != case.phonemes {
+            mismatches.push(Mismatch {
+                case,
+                actual: actual.to_string(),
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Character index of the first difference between `expected` and `actual`
+pub fn first_difference(expected: &str, actual: &str) -> Option<usize> {
+    let mut expected = expected.chars();
+    let mut actual = actual.chars();
+    let mut i = 0;
+    loop {
+        match (expected.next(), actual.next()) {
+            (None, None) => return None,
+            (a, b) if a != b => return Some(i),
+            _ => i += 1,
+        }
+    }
+}
+
+/// `content` with the phonemes of the mismatched cases replaced by what they came out as,
+/// keeping comments and everything else as they were
+pub fn update_golden(content: &str, mismatches: &[Mismatch]) -> String {
+    let mut updated = String::with_capacity(content.len());
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        match mismatches.iter().find(|m| m.case.line == i + 1) {
+            Some(mismatch) => {
+                let ending = &line[line.trim_end_matches(['\r', '\n']).len()..];
+                let mut columns: Vec<&str> =
+                    line[..line.len() - ending.len()].split('\t').collect();
+                columns[1] = &mismatch.actual;
+                updated.push_str(&columns.join("\t"));
+                updated.push_str(ending);
+            }
+            None => updated.push_str(line),
+        }
+    }
+    updated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_golden() {
+        let content = "text\tphonemes\n# heteronyms\nread\tɹˈiːd\nlive\tlˈɪv\ten-gb\n\nq\n";
+        assert_eq!(
+            parse_golden(content).unwrap_err(),
+            "line 6: expected text and phonemes separated by a tab"
+        );
+        let content = &content[..content.len() - 2];
+        let cases = parse_golden(content).unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[1].line, 4);
+        assert_eq!(cases[1].lan.as_deref(), Some("en-gb"));
+
+        assert_eq!(first_difference("ɹˈiːd", "ɹˈɛd"), Some(2));
+        assert_eq!(first_difference("ab", "abc"), Some(2));
+        assert_eq!(first_difference("ab", "ab"), None);
+
+        let mismatches = [Mismatch {
+            case: &cases[1],
+            actual: "lˈaɪv".to_string(),
+        }];
+        assert_eq!(
+            update_golden(content, &mismatches),
+            "text\tphonemes\n# heteronyms\nread\tɹˈiːd\nlive\tlˈaɪv\ten-gb\n\n"
+        );
+    }
+}
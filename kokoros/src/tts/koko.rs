@@ -1,16 +1,27 @@
-use crate::onn::ort_koko::{self, ModelStrategy};
-use crate::tts::tokenize::tokenize;
-use crate::utils;
+pub use crate::error::KokorosError;
+pub use crate::onn::ort_base::{OptimizationLevel, Provider, SessionOptions};
+use crate::onn::ort_koko;
+use crate::tts::builder::TTSKokoBuilder;
+use crate::tts::chunker::{self, MAX_CHUNK_TOKENS};
+use crate::tts::normalize::{TextOptions, fold_text};
+use crate::tts::priority::ModelQueue;
+use crate::tts::pronunciation;
+use crate::tts::stretch::{self, Chunk};
+use crate::tts::synthesis::{
+    StageTimings, SynthProgress, SynthRequest, SynthesisBuilder, SynthesisResult,
+};
+pub use crate::tts::priority::{Balance, Priority};
+use crate::tts::tokenize::{self, count_tokens};
+use crate::tts::vocab::ModelVersion;
+use crate::tts::voices;
+use crate::utils::audio::{SampleFormat, Samples};
 use crate::utils::debug::format_debug_prefix;
+use crate::utils::wav::{self, BitDepth};
 use lazy_static::lazy_static;
-use ndarray::Array3;
-use ndarray_npy::NpzReader;
-use std::collections::HashMap;
-use std::error::Error;
-use std::fs::File;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 
 use espeak_rs::text_to_phonemes;
 
@@ -20,10 +31,98 @@ lazy_static! {
     static ref ESPEAK_MUTEX: Mutex<()> = Mutex::new(());
 }
 
+/// Locks `mutex` even if a thread panicked while holding it. Neither espeak's lock nor the
+/// model guards data a panic could leave half-written, so one pathological input must not
+/// make every later request fail.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs espeak under the global lock, adding the time spent (including waiting for
+/// the lock) to `espeak_time`. Inline pronunciation overrides keep their phonemes.
+fn espeak_phonemes(
+    text: &str,
+    lan: &str,
+    espeak_time: &mut Duration,
+) -> Result<String, KokorosError> {
+    if pronunciation::has_overrides(text) {
+        return pronunciation::phonemize_with(text, |text| espeak_phonemes(text, lan, espeak_time));
+    }
+    let start = Instant::now();
+    let phonemes = {
+        let _guard = lock(&ESPEAK_MUTEX);
+        text_to_phonemes(text, lan, None, true, false)
+    };
+    *espeak_time += start.elapsed();
+    phonemes
+        .map(|parts| parts.join(""))
+        .map_err(|e| KokorosError::Phonemize(format!("'{}' ({}): {}", text, lan, e)))
+}
+
+/// Whether `model` also returns durations, for word timestamps
+fn is_timestamped(model: &Mutex<ort_koko::OrtKoko>) -> bool {
+    lock(model).timestamped()
+}
+
+/// The release `model` is taken for: `version` if given, else what its metadata or file
+/// name says, else v1.0
+fn resolve_version(
+    model: &Mutex<ort_koko::OrtKoko>,
+    version: Option<ModelVersion>,
+) -> ModelVersion {
+    if let Some(version) = version {
+        return version;
+    }
+    match lock(model).version() {
+        Some(version) => version,
+        None => {
+            tracing::info!(
+                "No model version in the metadata or file name, assuming {}",
+                ModelVersion::default()
+            );
+            ModelVersion::default()
+        }
+    }
+}
+
+/// Number of tokens `text` phonemizes to for a model of `version`, counted straight from
+/// espeak's output rather than from a joined phoneme string
+fn espeak_token_count(
+    text: &str,
+    lan: &str,
+    version: ModelVersion,
+    espeak_time: &mut Duration,
+) -> usize {
+    if pronunciation::has_overrides(text) {
+        return espeak_phonemes(text, lan, espeak_time)
+            .map(|phonemes| count_tokens(&phonemes, version))
+            .unwrap_or_default();
+    }
+    let start = Instant::now();
+    let count = {
+        let _guard = lock(&ESPEAK_MUTEX);
+        text_to_phonemes(text, lan, None, true, false)
+            .unwrap_or_default()
+            .iter()
+            .map(|phonemes| count_tokens(phonemes, version))
+            .sum()
+    };
+    *espeak_time += start.elapsed();
+    count
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Token counts [`TTSKoko::warmup`] runs the model on, from a short phrase up to a full
+/// chunk, so the kernels for common input sizes are ready before the first request
+pub const WARMUP_TOKEN_LENGTHS: [usize; 3] = [16, 128, MAX_CHUNK_TOKENS];
+
 // Flag to ensure voice styles are only logged once
 static VOICES_LOGGED: AtomicBool = AtomicBool::new(false);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct WordAlignment {
     pub word: String,
     pub start_sec: f32,
@@ -48,10 +147,32 @@ impl TtsOutput {
 }
 
 enum ExecutionMode<'a> {
-    /// Collects all data, adjusts timestamps to be global, returns it at the end.
-    Batch,
-    /// Yields chunks immediately with relative timestamps. Returns None at end.
-    Stream(&'a mut dyn FnMut(TtsOutput) -> Result<(), Box<dyn std::error::Error>>),
+    /// Collects all data, adjusts timestamps to be global, returns it at the end. Stops
+    /// between chunks once `cancelled` is set, and reports to `progress` after each one.
+    Batch {
+        cancelled: Option<&'a AtomicBool>,
+        progress: Option<&'a mut dyn FnMut(&SynthProgress)>,
+    },
+    /// Yields chunks immediately with relative timestamps, reporting to `progress` after
+    /// each one. Returns None at end.
+    Stream {
+        callback: &'a mut dyn FnMut(TtsOutput) -> Result<(), KokorosError>,
+        progress: Option<&'a mut dyn FnMut(&SynthProgress)>,
+    },
+}
+
+/// Each word of a chunk with the range of its tokens
+type WordMap = Vec<(String, usize, usize)>;
+
+/// The audio of a chunk, its phonemes, number of tokens and how long it took
+type ChunkOutput = (TtsOutput, String, usize, StageTimings);
+
+/// One synthesis chunk as the model would receive it
+#[derive(Debug, Clone)]
+pub struct PhonemizedChunk {
+    pub text: String,
+    pub phonemes: String,
+    pub tokens: Vec<i64>,
 }
 
 #[derive(Debug, Clone)]
@@ -63,24 +184,43 @@ pub struct TTSOpts<'a> {
     pub mono: bool,
     pub speed: f32,
     pub initial_silence: Option<usize>,
+    /// Sample format of the WAV file
+    pub bit_depth: BitDepth,
 }
 
+/// A loaded model and voices. Cloning is cheap and clones share the model's sessions, so
+/// one engine can be shared by every thread or task of a server as it is, without a mutex
+/// of its own: each chunk waits for a free session, interactive requests first (see
+/// [`Priority`]), and runs on it while other requests use the rest.
 #[derive(Clone)]
 pub struct TTSKoko {
     #[allow(dead_code)]
     model_path: String,
-    model: Arc<Mutex<ort_koko::OrtKoko>>,
-    styles: HashMap<String, Vec<[[f32; 256]; 1]>>,
+    /// The model loaded into [`InitConfig::sessions`] sessions
+    sessions: Arc<[Arc<Mutex<ort_koko::OrtKoko>>]>,
+    /// Turns on `sessions`, shared by every clone of this instance
+    queue: Arc<ModelQueue>,
+    /// Whether the model also returns durations, for word timestamps
+    timestamped: bool,
+    /// Release of the model, which decides the vocabulary phonemes are tokenized with
+    version: ModelVersion,
+    styles: Arc<voices::Styles>,
     init_config: InitConfig,
 }
 
+// Shared across threads as is, see above
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<TTSKoko>();
+};
+
 /// Parallel TTS with multiple ONNX instances for true concurrency
 #[derive(Clone)]
 pub struct TTSKokoParallel {
     #[allow(dead_code)]
     model_path: String,
     models: Vec<Arc<Mutex<ort_koko::OrtKoko>>>,
-    styles: HashMap<String, Vec<[[f32; 256]; 1]>>,
+    styles: Arc<voices::Styles>,
     init_config: InitConfig,
 }
 
@@ -89,6 +229,34 @@ pub struct InitConfig {
     pub model_url: String,
     pub voices_url: String,
     pub sample_rate: u32,
+    /// ONNX Runtime threading for the model's session(s)
+    pub session: SessionOptions,
+    /// Start streamed synthesis with a chunk of at most this many tokens (see
+    /// [`chunker::split_with_short_start`]), so the first audio comes out sooner
+    pub first_chunk_tokens: Option<usize>,
+    /// Whether synthesis goes before or after other requests waiting for the same model
+    pub priority: Priority,
+    /// Rewrites of the text before synthesis, e.g. spelling out identifiers
+    pub text: TextOptions,
+    /// Sessions the model is loaded into, so that many chunks can be inferred at once by
+    /// the clones of one [`TTSKoko`]; the weights are shared, but each session needs its
+    /// own working memory
+    pub sessions: usize,
+    /// Release of the model, when neither its metadata nor its file name say; otherwise
+    /// detected, falling back to v1.0
+    pub model_version: Option<ModelVersion>,
+    /// GPUs the sessions are spread over
+    pub gpus: GpuOptions,
+}
+
+/// GPUs a model's sessions are spread over, for a machine with several (CUDA builds)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GpuOptions {
+    /// CUDA devices, which get a session each at least and then the rest in turn; the
+    /// device of [`SessionOptions`] if empty
+    pub devices: Vec<i32>,
+    /// Which session, and so which device, gets a chunk when several are free
+    pub balance: Balance,
 }
 
 impl Default for InitConfig {
@@ -97,43 +265,146 @@ impl Default for InitConfig {
             model_url: "https://github.com/thewh1teagle/kokoro-onnx/releases/download/model-files-v1.0/kokoro-v1.0.onnx".into(),
             voices_url: "https://github.com/thewh1teagle/kokoro-onnx/releases/download/model-files-v1.0/voices-v1.0.bin".into(),
             sample_rate: 24000,
+            session: SessionOptions::default(),
+            first_chunk_tokens: None,
+            priority: Priority::default(),
+            text: TextOptions::default(),
+            sessions: 1,
+            model_version: None,
+            gpus: GpuOptions::default(),
         }
     }
 }
 
+/// Downloads the model and voices files if they don't exist yet. Without the `download`
+/// feature, missing files are an error instead.
+async fn ensure_model_files(
+    model_path: &str,
+    voices_path: &str,
+    cfg: &InitConfig,
+) -> Result<(), KokorosError> {
+    #[cfg(feature = "download")]
+    {
+        for (url, path) in [(&cfg.model_url, model_path), (&cfg.voices_url, voices_path)] {
+            if !Path::new(path).exists() {
+                crate::utils::fileio::download_file_from_url(url, path)
+                    .await
+                    .map_err(|e| KokorosError::ModelLoad(format!("downloading {}: {}", url, e)))?;
+            }
+        }
+    }
+
+    #[cfg(not(feature = "download"))]
+    {
+        let _ = cfg;
+        for path in [model_path, voices_path] {
+            if !Path::new(path).exists() {
+                return Err(KokorosError::ModelLoad(format!(
+                    "{} not found (kokoros was built without the download feature)",
+                    path
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
 impl TTSKoko {
+    /// Options for an engine loading the model at `model_path` and the voices at
+    /// `voices_path`; [`TTSKokoBuilder::build`] loads it
+    pub fn builder(
+        model_path: impl Into<String>,
+        voices_path: impl Into<String>,
+    ) -> TTSKokoBuilder {
+        TTSKokoBuilder::new(model_path, voices_path)
+    }
+
+    #[deprecated(note = "use TTSKoko::builder, which returns an error instead of panicking")]
     pub async fn new(model_path: &str, voices_path: &str) -> Self {
+        #[allow(deprecated)]
         Self::from_config(model_path, voices_path, InitConfig::default()).await
     }
 
+    /// Like [`TTSKoko::try_from_config`], but panics if the model or voices can't be loaded
+    #[deprecated(note = "use TTSKoko::builder, which returns an error instead of panicking")]
     pub async fn from_config(model_path: &str, voices_path: &str, cfg: InitConfig) -> Self {
-        if !Path::new(model_path).exists() {
-            utils::fileio::download_file_from_url(cfg.model_url.as_str(), model_path)
-                .await
-                .expect("download model failed.");
-        }
-
-        if !Path::new(voices_path).exists() {
-            utils::fileio::download_file_from_url(cfg.voices_url.as_str(), voices_path)
-                .await
-                .expect("download voices data file failed.");
-        }
+        Self::try_from_config(model_path, voices_path, cfg)
+            .await
+            .expect("Failed to create Kokoro TTS model")
+    }
 
-        let model = Arc::new(Mutex::new(
-            ort_koko::OrtKoko::new(model_path.to_string())
-                .expect("Failed to create Kokoro TTS model"),
-        ));
+    /// Loads the model and voices, downloading them first if they don't exist
+    pub async fn try_from_config(
+        model_path: &str,
+        voices_path: &str,
+        cfg: InitConfig,
+    ) -> Result<Self, KokorosError> {
+        ensure_model_files(model_path, voices_path, &cfg).await?;
+
+        // Session i runs on device i % n, so every device has as many sessions as the
+        // others, give or take one
+        let devices = &cfg.gpus.devices;
+        let session_devices: Vec<usize> = (0..cfg.sessions.max(devices.len()).max(1))
+            .map(|i| if devices.is_empty() { 0 } else { i % devices.len() })
+            .collect();
+        let sessions = session_devices
+            .iter()
+            .map(|&device| {
+                let options = SessionOptions {
+                    gpu_device: devices.get(device).copied().or(cfg.session.gpu_device),
+                    ..cfg.session.clone()
+                };
+                let model = ort_koko::OrtKoko::new(model_path.to_string(), &options)
+                    .map_err(KokorosError::ModelLoad)?;
+                Ok(Arc::new(Mutex::new(model)))
+            })
+            .collect::<Result<Arc<[_]>, KokorosError>>()?;
         // TODO: if(not streaming) { model.print_info(); }
         // model.print_info();
 
-        let styles = Self::load_voices(voices_path);
+        let styles = Self::load_voices(voices_path)?;
+        lock(&sessions[0]).check_style_input(voices::STYLE_DIM)?;
 
-        TTSKoko {
+        Ok(TTSKoko {
             model_path: model_path.to_string(),
-            model,
+            queue: Arc::new(ModelQueue::balanced(session_devices, cfg.gpus.balance)),
+            timestamped: is_timestamped(&sessions[0]),
+            version: resolve_version(&sessions[0], cfg.model_version),
+            sessions,
             styles,
             init_config: cfg,
+        })
+    }
+
+    /// Runs espeak once and the model once per entry of `token_lengths` with dummy input,
+    /// to pay for lazy initialization and kernel selection up front instead of on the first
+    /// real synthesis. Returns the time it took.
+    pub fn warmup(&self, token_lengths: &[usize]) -> Result<Duration, KokorosError> {
+        let start = Instant::now();
+        let mut espeak_time = Duration::ZERO;
+        espeak_phonemes("Warming up.", "en-us", &mut espeak_time)?;
+
+        let voice = self
+            .get_available_voices()
+            .into_iter()
+            .next()
+            .ok_or_else(|| KokorosError::InvalidVoice("no voices loaded".to_string()))?;
+        for session in self.sessions.iter() {
+            for &len in token_lengths {
+                // Padded like real input; any non-zero token will do
+                let mut tokens = vec![0];
+                tokens.extend(std::iter::repeat_n(16, len));
+                tokens.push(0);
+                let styles = self.mix_styles(&voice, len)?;
+                lock(session).infer(vec![tokens], styles, 1.0, None, None, None)?;
+            }
         }
+        tracing::debug!(
+            "Warmed up with {} inferences in {:.1} ms",
+            token_lengths.len() * self.sessions.len(),
+            millis(start.elapsed())
+        );
+        Ok(start.elapsed())
     }
 
     fn process_internal(
@@ -147,31 +418,76 @@ impl TTSKoko {
         instance_id: Option<&str>,
         chunk_number_start: Option<usize>,
         mut mode: ExecutionMode,
-    ) -> Result<Option<(Vec<f32>, Vec<WordAlignment>)>, Box<dyn std::error::Error>> {
-        let chunks = self.split_text_into_chunks(txt, 500, lan);
-      
+    ) -> Result<Option<SynthesisResult>, KokorosError> {
+        let normalize_start = Instant::now();
+        // Stretched runs are normalized on their own, so their marks can't be rewritten
+        let runs = stretch::split_stretches(txt)
+            .into_iter()
+            .map(|(run, factor)| {
+                let run = self.init_config.text.spoken_text(run, lan);
+                run.map(|run| (run, factor)).map_err(KokorosError::Plugin)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (txt, factors) = stretch::mark(&runs);
+        let txt = &*txt;
+        let normalize_time = normalize_start.elapsed();
+        let split_start = Instant::now();
+        // The silence tokens go into every chunk, so they come out of its budget
+        let max_tokens = tokenize::max_tokens(initial_silence);
+        let chunks = match (&mode, self.init_config.first_chunk_tokens) {
+            (ExecutionMode::Stream { .. }, Some(first_tokens)) => {
+                let text = fold_text(txt);
+                let mut espeak_time = Duration::ZERO;
+                chunker::split_with_short_start(&text, max_tokens, first_tokens, |chunk| {
+                    espeak_token_count(chunk, lan, self.version, &mut espeak_time)
+                })
+            }
+            _ => Self::split_text_into_chunks(txt, max_tokens, lan, self.version),
+        };
+        let mut chunks = stretch::unmark(chunks, &factors);
+        if !self.timestamped {
+            // Without durations to place them, stretched runs are inferred on their own
+            chunks = chunks.into_iter().flat_map(Chunk::into_runs).collect();
+        }
+
         let start_chunk_num = chunk_number_start.unwrap_or(0);
 
         let debug_prefix = format_debug_prefix(request_id, instance_id);
-
-        let process_one_chunk = |chunk: &str,
+        tracing::trace!(
+            "{} split into {} chunks: {:.1} ms",
+            debug_prefix,
+            chunks.len(),
+            millis(split_start.elapsed())
+        );
+        let split_time = split_start.elapsed();
+
+        // None for a chunk with nothing to say, e.g. only characters the model has no
+        // tokens for: the model can't run on empty input, and it would be silent anyway.
+        // Otherwise its audio, phonemes, number of tokens and how long it took.
+        let process_one_chunk = |chunk: &Chunk,
                                  chunk_num: usize|
-         -> Result<TtsOutput, Box<dyn std::error::Error>> {
+         -> Result<Option<ChunkOutput>, KokorosError> {
+            let speed = speed / chunk.factor;
+            let runs = chunk.runs();
+            let stretched = !chunk.spans.is_empty();
+            let chunk = &*chunk.text;
             let chunk_info = format!("Chunk: {}, ", chunk_num);
             tracing::debug!("{} {}text: '{}'", debug_prefix, chunk_info, chunk);
 
             // A. Tokenize
             // Only build the expensive alignment map if the loaded model supports timestamps.
-            let use_alignment = {
-                let model = self.model.lock().unwrap();
-                matches!(model.strategy(), Some(ModelStrategy::Timestamped(_)))
-            };
-
-            let (mut tokens, word_map) = if use_alignment {
-                self.tokenize_with_alignment(chunk, lan)
+            let use_alignment = self.timestamped;
+
+            let tokenize_start = Instant::now();
+            let mut espeak_time = Duration::ZERO;
+            let mut scales = Vec::new();
+            let (phonemes, tokens, word_map) = if stretched {
+                self.tokenize_stretched(&runs, lan, &mut scales, &mut espeak_time)?
+            } else if use_alignment {
+                self.tokenize_with_alignment(chunk, lan, &mut espeak_time)?
             } else {
                 // Fast path for audio-only models: single eSpeak pass, no per-item calls
-                self.tokenize_full_no_alignment(chunk, lan)
+                self.tokenize_full_no_alignment(chunk, lan, &mut espeak_time)?
             };
 
             // Log token count (helpful for debugging context limits)
@@ -182,25 +498,55 @@ impl TTSKoko {
                 tokens.len()
             );
 
-            // B. Silence
+            // B. Silence, as tokens put in front of the text's below
+            let text_tokens = tokens.len();
             let silence_count = initial_silence.unwrap_or(0);
-            for _ in 0..silence_count {
-                tokens.insert(0, 30);
+            let tokens_len = tokens.len() + silence_count;
+            if tokens_len == 0 {
+                tracing::debug!("{} {}nothing to say, skipped", debug_prefix, chunk_info);
+                return Ok(None);
+            }
+            if tokens_len > MAX_CHUNK_TOKENS {
+                return Err(KokorosError::Tokenize(format!(
+                    "{} tokens (with {} of silence) don't fit the model's context of {}",
+                    tokens_len, silence_count, MAX_CHUNK_TOKENS
+                )));
             }
 
             // C. Style
-            let styles = self.mix_styles(style_name, tokens.len())?;
+            let styles = if voices::Morph::is_morph(style_name) {
+                let morph = voices::Morph::parse(style_name)?;
+                let index = chunk_num - start_chunk_num;
+                let style = self
+                    .styles
+                    .morph_style(&morph, tokens_len, index, chunks.len())
+                    .ok_or_else(|| {
+                        KokorosError::InvalidVoice(format!("can not morph {}", style_name))
+                    })?;
+                vec![style.to_vec()]
+            } else {
+                self.mix_styles(style_name, tokens_len)?
+            };
 
-            // D. Padding
-            let mut padded_tokens = vec![0];
+            // D. Padding, built in one buffer with the silence
+            let mut padded_tokens = Vec::with_capacity(tokens_len + 2);
+            padded_tokens.push(0);
+            padded_tokens.extend(std::iter::repeat_n(30, silence_count));
             padded_tokens.extend(tokens);
             padded_tokens.push(0);
 
             let index_offset = 1 + silence_count;
+            if stretched {
+                scales.splice(0..0, std::iter::repeat_n(1.0, index_offset));
+                scales.push(1.0);
+            }
             let tokens_batch = vec![padded_tokens];
+            let tokenize_time = tokenize_start.elapsed().saturating_sub(espeak_time);
 
-            // E. Infer
-            let (chunk_audio_array, chunk_durations_opt) = self.model.lock().unwrap().infer(
+            // E. Infer, once it is this request's turn on the model
+            let turn = self.queue.wait(self.init_config.priority);
+            let infer_start = Instant::now();
+            let (chunk_audio, chunk_durations_opt) = lock(&self.sessions[turn.session()]).infer(
                 tokens_batch,
                 styles,
                 speed,
@@ -208,11 +554,36 @@ impl TTSKoko {
                 instance_id,
                 Some(chunk_num),
             )?;
+            drop(turn);
+            let (chunk_audio, chunk_durations_opt) = match chunk_durations_opt {
+                Some(mut durations) if stretched => {
+                    let audio = stretch::apply(&chunk_audio, &mut durations, &scales);
+                    (audio, Some(durations))
+                }
+                durations => (chunk_audio, durations),
+            };
 
-            let chunk_audio: Vec<f32> = chunk_audio_array.iter().cloned().collect();
+            let infer_time = infer_start.elapsed();
+            let timings = StageTimings {
+                normalize: Duration::ZERO,
+                phonemize: espeak_time + tokenize_time,
+                inference: infer_time,
+            };
+            let log_timings = |alignment_time: Duration| {
+                tracing::trace!(
+                    "{} {}timings: phonemize {:.1} ms, tokenize {:.1} ms, inference {:.1} ms, alignment {:.1} ms",
+                    debug_prefix,
+                    chunk_info,
+                    millis(espeak_time),
+                    millis(tokenize_time),
+                    millis(infer_time),
+                    millis(alignment_time)
+                );
+            };
 
             // F. Calculate Alignments
             if let Some(durations) = chunk_durations_opt {
+                let alignment_start = Instant::now();
                 let mut alignments = Vec::new();
 
                 // Model durations are in frames (hop=600 @ 24 kHz) ⇒ 40 frames/sec.
@@ -323,50 +694,75 @@ impl TTSKoko {
                     }
                 }
 
-                Ok(TtsOutput::Aligned(chunk_audio, alignments))
+                log_timings(alignment_start.elapsed());
+                Ok(Some((
+                    TtsOutput::Aligned(chunk_audio, alignments),
+                    phonemes,
+                    text_tokens,
+                    timings,
+                )))
             } else {
-                Ok(TtsOutput::Audio(chunk_audio))
+                log_timings(Duration::ZERO);
+                Ok(Some((
+                    TtsOutput::Audio(chunk_audio),
+                    phonemes,
+                    text_tokens,
+                    timings,
+                )))
             }
         };
 
         match &mut mode {
-            ExecutionMode::Stream(callback) => {
+            ExecutionMode::Stream { callback, progress } => {
+                let start = Instant::now();
+                let mut samples = 0;
                 for (i, chunk) in chunks.iter().enumerate() {
-                    let output = process_one_chunk(chunk, start_chunk_num + i)?;
-                    callback(output)?;
+                    if let Some((output, ..)) = process_one_chunk(chunk, start_chunk_num + i)? {
+                        samples += match &output {
+                            TtsOutput::Audio(audio) | TtsOutput::Aligned(audio, _) => audio.len(),
+                        };
+                        callback(output)?;
+                    }
+                    if let Some(progress) = progress {
+                        progress(&SynthProgress::new(
+                            i + 1,
+                            chunks.len(),
+                            samples as f32 / self.init_config.sample_rate as f32,
+                            start.elapsed(),
+                        ));
+                    }
                 }
                 Ok(None)
             }
 
-            ExecutionMode::Batch => {
-                let mut batch_audio = Vec::new();
-                let mut batch_alignments = Vec::new();
-                let mut global_time_offset = 0.0;
-                let sample_rate = 24000.0;
-
+            ExecutionMode::Batch {
+                cancelled,
+                progress,
+            } => {
+                let start = Instant::now();
+                let mut result = SynthesisBuilder::new(self.init_config.sample_rate);
+                result.timings.normalize = normalize_time;
+                result.timings.phonemize = split_time;
                 for (i, chunk) in chunks.iter().enumerate() {
-                    let output = process_one_chunk(chunk, start_chunk_num + i)?;
-
-                    match output {
-                        TtsOutput::Aligned(audio, alignments) => {
-                            let duration = audio.len() as f32 / sample_rate;
-                            batch_audio.extend_from_slice(&audio);
-
-                            for mut align in alignments {
-                                align.start_sec += global_time_offset;
-                                align.end_sec += global_time_offset;
-                                batch_alignments.push(align);
-                            }
-                            global_time_offset += duration;
-                        }
-                        TtsOutput::Audio(audio) => {
-                            let duration = audio.len() as f32 / sample_rate;
-                            batch_audio.extend_from_slice(&audio);
-                            global_time_offset += duration;
-                        }
+                    if cancelled.is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+                        return Err(KokorosError::Cancelled);
+                    }
+                    if let Some((output, phonemes, tokens, timings)) =
+                        process_one_chunk(chunk, start_chunk_num + i)?
+                    {
+                        result.push(output, &chunk.text, phonemes, tokens);
+                        result.timings += timings;
+                    }
+                    if let Some(progress) = progress {
+                        progress(&SynthProgress::new(
+                            i + 1,
+                            chunks.len(),
+                            result.seconds(),
+                            start.elapsed(),
+                        ));
                     }
                 }
-                Ok(Some((batch_audio, batch_alignments)))
+                Ok(Some(result.finish()))
             }
         }
     }
@@ -376,20 +772,16 @@ impl TTSKoko {
         &self,
         text: &str,
         lan: &str,
-    ) -> (Vec<i64>, Vec<(String, usize, usize)>) {
+        espeak_time: &mut Duration,
+    ) -> Result<(String, Vec<i64>, WordMap), KokorosError> {
         // We will produce tokens from the full, context-aware phonemes (best prosody)
         // and build an alignment map by estimating per-word token spans using
         // per-word phoneme tokenization. This keeps audio natural while providing
         // robust timestamps even when eSpeak merges words (e.g., "the model").
 
         // 1) Full-phrase phonemes and tokens (prosody source)
-        let full_phonemes = {
-            let _guard = ESPEAK_MUTEX.lock().unwrap();
-            text_to_phonemes(text, lan, None, true, false)
-                .unwrap_or_default()
-                .join("")
-        };
-        let all_tokens = tokenize(&full_phonemes);
+        let full_phonemes = espeak_phonemes(text, lan, espeak_time)?;
+        let all_tokens = tokenize::tokenize_for(&full_phonemes, self.version);
 
         // 2) Build a tokenization plan per original "word or punctuation" unit.
         //    We want punctuation timestamps too, so we split words and punctuation as separate items.
@@ -397,7 +789,8 @@ impl TTSKoko {
         //    for .,!?;: characters.
         fn split_words_and_punct(s: &str) -> Vec<String> {
             let mut out = Vec::new();
-            for raw in s.split_whitespace() {
+            // Overrides stay whole, even with spaces in them
+            for raw in pronunciation::split_words(s) {
                 let chars: Vec<char> = raw.chars().collect();
                 let mut start = 0usize;
                 let mut end = chars.len();
@@ -443,13 +836,7 @@ impl TTSKoko {
                 per_item_token_counts.push(0);
                 per_item_is_punct.push(true);
             } else {
-                let ph = {
-                    let _guard = ESPEAK_MUTEX.lock().unwrap();
-                    text_to_phonemes(it, lan, None, true, false)
-                        .unwrap_or_default()
-                        .join("")
-                };
-                let cnt = tokenize(&ph).len();
+                let cnt = espeak_token_count(it, lan, self.version, espeak_time);
                 per_item_token_counts.push(cnt);
                 per_item_is_punct.push(false);
             }
@@ -504,7 +891,8 @@ impl TTSKoko {
             } else {
                 let start_idx = cursor;
                 let end_idx = cursor.saturating_add(cnt);
-                word_map.push((item.clone(), start_idx, end_idx));
+                let word = pronunciation::spoken(item).into_owned();
+                word_map.push((word, start_idx, end_idx));
                 cursor = end_idx;
             }
         }
@@ -520,7 +908,42 @@ impl TTSKoko {
         }
 
         // If there are absolutely no tokens (empty text), return empty mapping
-        (all_tokens, word_map)
+        Ok((full_phonemes, all_tokens, word_map))
+    }
+
+    /// Tokenizes the runs of a chunk with stretched spans one by one, with a space between
+    /// them, and for each token the factor of its run in `scales`
+    fn tokenize_stretched(
+        &self,
+        runs: &[(&str, f32)],
+        lan: &str,
+        scales: &mut Vec<f32>,
+        espeak_time: &mut Duration,
+    ) -> Result<(String, Vec<i64>, WordMap), KokorosError> {
+        let mut phonemes = Vec::new();
+        let mut tokens = Vec::new();
+        let mut word_map = Vec::new();
+        for (run, factor) in runs {
+            let (run_phonemes, run_tokens, run_map) =
+                self.tokenize_with_alignment(run.trim(), lan, espeak_time)?;
+            if run_tokens.is_empty() {
+                continue;
+            }
+            if !tokens.is_empty() {
+                tokens.extend(tokenize::tokenize_for(" ", self.version));
+                scales.resize(tokens.len(), 1.0);
+            }
+            let offset = tokens.len();
+            word_map.extend(
+                run_map
+                    .into_iter()
+                    .map(|(word, start, end)| (word, start + offset, end + offset)),
+            );
+            phonemes.push(run_phonemes);
+            tokens.extend(run_tokens);
+            scales.resize(tokens.len(), *factor);
+        }
+        Ok((phonemes.join(" "), tokens, word_map))
     }
 
     /// Fast tokenization path for audio-only models (no timestamps)
@@ -529,102 +952,28 @@ impl TTSKoko {
         &self,
         text: &str,
         lan: &str,
-    ) -> (Vec<i64>, Vec<(String, usize, usize)>) {
-        let full_phonemes = {
-            let _guard = ESPEAK_MUTEX.lock().unwrap();
-            text_to_phonemes(text, lan, None, true, false)
-                .unwrap_or_default()
-                .join("")
-        };
-        let all_tokens = tokenize(&full_phonemes);
-        (all_tokens, Vec::new())
+        espeak_time: &mut Duration,
+    ) -> Result<(String, Vec<i64>, WordMap), KokorosError> {
+        let full_phonemes = espeak_phonemes(text, lan, espeak_time)?;
+        let all_tokens = tokenize::tokenize_for(&full_phonemes, self.version);
+        Ok((full_phonemes, all_tokens, Vec::new()))
     }
 
-    fn split_text_into_chunks(&self, text: &str, max_tokens: usize, lan: &str) -> Vec<String> {
-        let mut chunks = Vec::new();
-
-        // First split by sentences - using common sentence ending punctuation
-        let sentences: Vec<&str> = text
-            .split(|c| c == '.' || c == '?' || c == '!' || c == ';')
-            .filter(|s| !s.trim().is_empty())
-            .collect();
-
-        let mut current_chunk = String::new();
-
-        for sentence in sentences {
-            // Clean up the sentence and add back punctuation
-            let sentence = format!("{}.", sentence.trim());
-
-            // Convert to phonemes to check token count
-            let sentence_phonemes = {
-                let _guard = ESPEAK_MUTEX.lock().unwrap();
-                text_to_phonemes(&sentence, lan, None, true, false)
-                    .unwrap_or_default()
-                    .join("")
-            };
-            let token_count = tokenize(&sentence_phonemes).len();
-
-            if token_count > max_tokens {
-                // If single sentence is too long, split by words
-                let words: Vec<&str> = sentence.split_whitespace().collect();
-                let mut word_chunk = String::new();
-
-                for word in words {
-                    let test_chunk = if word_chunk.is_empty() {
-                        word.to_string()
-                    } else {
-                        format!("{} {}", word_chunk, word)
-                    };
-
-                    let test_phonemes = {
-                        let _guard = ESPEAK_MUTEX.lock().unwrap();
-                        text_to_phonemes(&test_chunk, lan, None, true, false)
-                            .unwrap_or_default()
-                            .join("")
-                    };
-                    let test_tokens = tokenize(&test_phonemes).len();
-
-                    if test_tokens > max_tokens {
-                        if !word_chunk.is_empty() {
-                            chunks.push(word_chunk);
-                        }
-                        word_chunk = word.to_string();
-                    } else {
-                        word_chunk = test_chunk;
-                    }
-                }
-
-                if !word_chunk.is_empty() {
-                    chunks.push(word_chunk);
-                }
-            } else if !current_chunk.is_empty() {
-                // Try to append to current chunk
-                let test_text = format!("{} {}", current_chunk, sentence);
-                let test_phonemes = {
-                    let _guard = ESPEAK_MUTEX.lock().unwrap();
-                    text_to_phonemes(&test_text, lan, None, true, false)
-                        .unwrap_or_default()
-                        .join("")
-                };
-                let test_tokens = tokenize(&test_phonemes).len();
-
-                if test_tokens > max_tokens {
-                    // If combining would exceed limit, start new chunk
-                    chunks.push(current_chunk);
-                    current_chunk = sentence;
-                } else {
-                    current_chunk = test_text;
-                }
-            } else {
-                current_chunk = sentence;
-            }
-        }
-
-        // Add the last chunk if not empty
-        if !current_chunk.is_empty() {
-            chunks.push(current_chunk);
-        }
-
+    /// Splits `text` into chunks of at most `max_tokens` tokens at sentence, clause or
+    /// word boundaries, counting each candidate's tokens with espeak and the vocabulary
+    /// of `version`. The chunks are folded with [`fold_text`] first.
+    pub(crate) fn split_text_into_chunks(
+        text: &str,
+        max_tokens: usize,
+        lan: &str,
+        version: ModelVersion,
+    ) -> Vec<String> {
+        let text = fold_text(text);
+        let mut espeak_time = Duration::ZERO;
+        let chunks = chunker::split_into_chunks(&text, max_tokens, |chunk| {
+            espeak_token_count(chunk, lan, version, &mut espeak_time)
+        });
+        tracing::trace!("chunking: phonemize {:.1} ms", millis(espeak_time));
         chunks
     }
 
@@ -742,6 +1091,48 @@ impl TTSKoko {
         chunks
     }
 
+    /// Rewrites `txt` with `options`, then chunks, phonemizes and tokenizes it for a model
+    /// of `version` the same way synthesis does, without touching the model. Useful for
+    /// debugging pronunciation and estimating cost.
+    pub fn phonemize(
+        txt: &str,
+        lan: &str,
+        options: &TextOptions,
+        version: ModelVersion,
+    ) -> Result<Vec<PhonemizedChunk>, KokorosError> {
+        let txt = options
+            .spoken_text(txt, lan)
+            .map_err(KokorosError::Plugin)?;
+        Self::split_text_into_chunks(&txt, MAX_CHUNK_TOKENS, lan, version)
+            .into_iter()
+            .map(|text| {
+                let mut espeak_time = Duration::ZERO;
+                let phonemes = espeak_phonemes(&text, lan, &mut espeak_time)?;
+                let tokens = tokenize::tokenize_for(&phonemes, version);
+                Ok(PhonemizedChunk {
+                    text,
+                    phonemes,
+                    tokens,
+                })
+            })
+            .collect()
+    }
+
+    /// The phonemes of `txt` after the plugins, replacements and rewrites of `options`, as
+    /// synthesis would phonemize it, in one piece rather than in chunks. This is what
+    /// golden pronunciation files are checked against.
+    pub fn phonemize_with_options(
+        txt: &str,
+        lan: &str,
+        options: &TextOptions,
+    ) -> Result<String, KokorosError> {
+        let txt = options
+            .spoken_text(txt, lan)
+            .map_err(KokorosError::Plugin)?;
+        let mut espeak_time = Duration::ZERO;
+        espeak_phonemes(&txt, lan, &mut espeak_time)
+    }
+
     pub fn tts_timestamped_raw_audio(
         &self,
         txt: &str,
@@ -752,7 +1143,7 @@ impl TTSKoko {
         request_id: Option<&str>,
         instance_id: Option<&str>,
         chunk_number: Option<usize>,
-    ) -> Result<Option<(Vec<f32>, Vec<WordAlignment>)>, Box<dyn Error>> {
+    ) -> Result<Option<(Vec<f32>, Vec<WordAlignment>)>, KokorosError> {
         self.process_internal(
             txt,
             lan,
@@ -762,10 +1153,16 @@ impl TTSKoko {
             request_id,
             instance_id,
             chunk_number,
-            ExecutionMode::Batch,
+            ExecutionMode::Batch {
+                cancelled: None,
+                progress: None,
+            },
         )
+        .map(|result| result.map(|result| (result.samples, result.word_timings)))
     }
 
+    /// The audio of `txt`, which is empty when there is nothing to say: an empty text, or
+    /// one made only of characters the model has no tokens for
     pub fn tts_raw_audio(
         &self,
         txt: &str,
@@ -776,7 +1173,7 @@ impl TTSKoko {
         request_id: Option<&str>,
         instance_id: Option<&str>,
         chunk_number: Option<usize>,
-    ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    ) -> Result<Vec<f32>, KokorosError> {
         let audio = self.process_internal(
             txt,
             lan,
@@ -786,10 +1183,109 @@ impl TTSKoko {
             request_id,
             instance_id,
             chunk_number,
-            ExecutionMode::Batch,
+            ExecutionMode::Batch {
+                cancelled: None,
+                progress: None,
+            },
         )?;
 
-        Ok(audio.unwrap().0)
+        Ok(audio.unwrap().samples)
+    }
+
+    /// Synthesizes `request` into its audio along with its duration, phonemes, chunks and,
+    /// for models that return durations, word timings
+    pub fn synthesize(&self, request: &SynthRequest) -> Result<SynthesisResult, KokorosError> {
+        self.synthesize_with(request, None, None)
+    }
+
+    /// [`Self::synthesize`], giving up with [`KokorosError::Cancelled`] before the next
+    /// chunk once `cancelled` is set
+    pub fn synthesize_cancellable(
+        &self,
+        request: &SynthRequest,
+        cancelled: Option<&AtomicBool>,
+    ) -> Result<SynthesisResult, KokorosError> {
+        self.synthesize_with(request, cancelled, None)
+    }
+
+    /// [`Self::synthesize`], calling `on_progress` after every chunk with how many are done
+    /// and how long the rest will likely take, e.g. for a progress bar
+    pub fn synthesize_with_progress(
+        &self,
+        request: &SynthRequest,
+        cancelled: Option<&AtomicBool>,
+        mut on_progress: impl FnMut(&SynthProgress),
+    ) -> Result<SynthesisResult, KokorosError> {
+        self.synthesize_with(request, cancelled, Some(&mut on_progress))
+    }
+
+    fn synthesize_with<'a>(
+        &self,
+        request: &SynthRequest,
+        cancelled: Option<&'a AtomicBool>,
+        progress: Option<&'a mut dyn FnMut(&SynthProgress)>,
+    ) -> Result<SynthesisResult, KokorosError> {
+        let mut result = self
+            .with_priority(request.priority)
+            .process_internal(
+                &request.text,
+                &request.lan,
+                &request.style,
+                request.speed,
+                request.initial_silence,
+                request.request_id.as_deref(),
+                None,
+                None,
+                ExecutionMode::Batch {
+                    cancelled,
+                    progress,
+                },
+            )?
+            .expect("batch synthesis returns its result");
+        if let Some(loudness) = request.loudness {
+            crate::utils::loudness::normalize(&mut result.samples, result.sample_rate, loudness);
+        }
+        Ok(result)
+    }
+
+    /// [`Self::tts_raw_audio`] in `format`, converted once at the end instead of by every
+    /// caller that wants integers, without initial silence
+    pub fn tts_raw_audio_as(
+        &self,
+        txt: &str,
+        lan: &str,
+        style_name: &str,
+        speed: f32,
+        format: SampleFormat,
+    ) -> Result<Samples, KokorosError> {
+        let audio = self.tts_raw_audio(txt, lan, style_name, speed, None, None, None, None)?;
+        Ok(Samples::new(audio, format))
+    }
+
+    /// [`Self::tts_raw_audio_streaming`] with every chunk in `format`
+    pub fn tts_raw_audio_streaming_as<F>(
+        &self,
+        txt: &str,
+        lan: &str,
+        style_name: &str,
+        speed: f32,
+        format: SampleFormat,
+        mut chunk_callback: F,
+    ) -> Result<(), KokorosError>
+    where
+        F: FnMut(Samples) -> Result<(), Box<dyn std::error::Error>>,
+    {
+        self.tts_raw_audio_streaming(
+            txt,
+            lan,
+            style_name,
+            speed,
+            None,
+            None,
+            None,
+            None,
+            |chunk| chunk_callback(Samples::new(chunk, format)),
+        )
     }
 
     /// Streaming version that yields audio chunks as they're generated
@@ -804,12 +1300,12 @@ impl TTSKoko {
         instance_id: Option<&str>,
         chunk_number: Option<usize>,
         mut chunk_callback: F,
-    ) -> Result<(), Box<dyn std::error::Error>>
+    ) -> Result<(), KokorosError>
     where
         F: FnMut(Vec<f32>) -> Result<(), Box<dyn std::error::Error>>,
     {
-        let mut adapter = |output: TtsOutput| -> Result<(), Box<dyn std::error::Error>> {
-            chunk_callback(output.raw_output().0)
+        let mut adapter = |output: TtsOutput| -> Result<(), KokorosError> {
+            chunk_callback(output.raw_output().0).map_err(KokorosError::from_callback)
         };
 
         self.process_internal(
@@ -822,7 +1318,44 @@ impl TTSKoko {
             instance_id,
             chunk_number,
             // Pass the ADAPTER, not the original callback
-            ExecutionMode::Stream(&mut adapter),
+            ExecutionMode::Stream {
+                callback: &mut adapter,
+                progress: None,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// [`Self::tts_raw_audio_streaming`] for `request`, calling `on_progress` after every
+    /// chunk with how many are done, e.g. for a progress bar over one long text. The audio
+    /// is sent as it comes from the model, without `request.loudness`
+    pub fn synthesize_streaming<F>(
+        &self,
+        request: &SynthRequest,
+        mut chunk_callback: F,
+        mut on_progress: impl FnMut(&SynthProgress),
+    ) -> Result<(), KokorosError>
+    where
+        F: FnMut(Vec<f32>) -> Result<(), Box<dyn std::error::Error>>,
+    {
+        let mut adapter = |output: TtsOutput| -> Result<(), KokorosError> {
+            chunk_callback(output.raw_output().0).map_err(KokorosError::from_callback)
+        };
+
+        self.with_priority(request.priority).process_internal(
+            &request.text,
+            &request.lan,
+            &request.style,
+            request.speed,
+            request.initial_silence,
+            request.request_id.as_deref(),
+            None,
+            None,
+            ExecutionMode::Stream {
+                callback: &mut adapter,
+                progress: Some(&mut on_progress),
+            },
         )?;
 
         Ok(())
@@ -841,14 +1374,14 @@ impl TTSKoko {
         instance_id: Option<&str>,
         chunk_number: Option<usize>,
         mut chunk_callback: F,
-    ) -> Result<(), Box<dyn std::error::Error>>
+    ) -> Result<(), KokorosError>
     where
         // CHANGE: Callback accepts TtsOutput instead of just Vec<f32>
         F: FnMut((Vec<f32>, Vec<WordAlignment>)) -> Result<(), Box<dyn std::error::Error>>,
     {
-        let mut adapter = |output: TtsOutput| -> Result<(), Box<dyn std::error::Error>> {
+        let mut adapter = |output: TtsOutput| -> Result<(), KokorosError> {
             let audio = output.raw_output();
-            chunk_callback((audio.0, audio.1.unwrap()))
+            chunk_callback((audio.0, audio.1.unwrap())).map_err(KokorosError::from_callback)
         };
 
         self.process_internal(
@@ -860,7 +1393,10 @@ impl TTSKoko {
             request_id,
             instance_id,
             chunk_number,
-            ExecutionMode::Stream(&mut adapter),
+            ExecutionMode::Stream {
+                callback: &mut adapter,
+                progress: None,
+            },
         )?;
 
         Ok(())
@@ -876,8 +1412,9 @@ impl TTSKoko {
             mono,
             speed,
             initial_silence,
+            bit_depth,
         }: TTSOpts,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), KokorosError> {
         let audio = self.tts_raw_audio(
             &txt,
             lan,
@@ -890,34 +1427,17 @@ impl TTSKoko {
         )?;
 
         // Save to file
-        if mono {
-            let spec = hound::WavSpec {
-                channels: 1,
-                sample_rate: self.init_config.sample_rate,
-                bits_per_sample: 32,
-                sample_format: hound::SampleFormat::Float,
-            };
-
-            let mut writer = hound::WavWriter::create(save_path, spec)?;
-            for &sample in &audio {
-                writer.write_sample(sample)?;
-            }
-            writer.finalize()?;
-        } else {
-            let spec = hound::WavSpec {
-                channels: 2,
-                sample_rate: self.init_config.sample_rate,
-                bits_per_sample: 32,
-                sample_format: hound::SampleFormat::Float,
-            };
-
-            let mut writer = hound::WavWriter::create(save_path, spec)?;
-            for &sample in &audio {
-                writer.write_sample(sample)?;
-                writer.write_sample(sample)?;
-            }
-            writer.finalize()?;
-        }
+        let encode_start = Instant::now();
+        let channels = if mono { 1 } else { 2 };
+        wav::write_wav(
+            Path::new(save_path),
+            &audio,
+            self.init_config.sample_rate,
+            channels,
+            bit_depth,
+        )
+        .map_err(|e| KokorosError::Encode(e.to_string()))?;
+        tracing::trace!("encoding: {:.1} ms", millis(encode_start.elapsed()));
         eprintln!("Audio saved to {}", save_path);
         Ok(())
     }
@@ -926,13 +1446,16 @@ impl TTSKoko {
         &self,
         style_name: &str,
         tokens_len: usize,
-    ) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+    ) -> Result<Vec<Vec<f32>>, KokorosError> {
         if !style_name.contains("+") {
-            if let Some(style) = self.styles.get(style_name) {
-                let styles = vec![style[tokens_len][0].to_vec()];
+            if let Some(style) = self.styles.style_for(style_name, tokens_len) {
+                let styles = vec![style.to_vec()];
                 Ok(styles)
             } else {
-                Err(format!("can not found from styles_map: {}", style_name).into())
+                Err(KokorosError::InvalidVoice(format!(
+                    "can not found from styles_map: {}",
+                    style_name
+                )))
             }
         } else {
             eprintln!("parsing style mix");
@@ -954,8 +1477,7 @@ impl TTSKoko {
             let mut blended_style = vec![vec![0.0; 256]; 1];
 
             for (name, portion) in style_names.iter().zip(style_portions.iter()) {
-                if let Some(style) = self.styles.get(*name) {
-                    let style_slice = &style[tokens_len][0]; // This is a [256] array
+                if let Some(style_slice) = self.styles.style_for(name, tokens_len) {
                     // Blend into the blended_style
                     for j in 0..256 {
                         blended_style[0][j] += style_slice[j] * portion;
@@ -967,27 +1489,11 @@ impl TTSKoko {
         }
     }
 
-    fn load_voices(voices_path: &str) -> HashMap<String, Vec<[[f32; 256]; 1]>> {
-        let mut npz = NpzReader::new(File::open(voices_path).unwrap()).unwrap();
-        let mut map = HashMap::new();
-
-        for voice in npz.names().unwrap() {
-            let voice_data: Result<Array3<f32>, _> = npz.by_name(&voice);
-            let voice_data = voice_data.unwrap();
-            let mut tensor = vec![[[0.0; 256]; 1]; 511];
-            for (i, inner_value) in voice_data.outer_iter().enumerate() {
-                for (j, inner_inner_value) in inner_value.outer_iter().enumerate() {
-                    for (k, number) in inner_inner_value.iter().enumerate() {
-                        tensor[i][j][k] = *number;
-                    }
-                }
-            }
-            map.insert(voice, tensor);
-        }
+    fn load_voices(voices_path: &str) -> Result<Arc<voices::Styles>, KokorosError> {
+        let styles = voices::load_voices(Path::new(voices_path))?;
 
         let _sorted_voices = {
-            let mut voices = map.keys().collect::<Vec<_>>();
-            voices.sort();
+            let voices = styles.names();
 
             // Only log voices once across all TTS instances
             if !VOICES_LOGGED.swap(true, Ordering::Relaxed) {
@@ -1040,14 +1546,106 @@ impl TTSKoko {
             voices
         };
 
-        map
+        // Room for voices registered at runtime, in front of the loaded ones
+        Ok(Arc::new(voices::Styles::Layered(vec![
+            Arc::new(voices::Styles::Added(Default::default())),
+            Arc::new(styles),
+        ])))
     }
 
     // Returns a sorted list of available voice names
     pub fn get_available_voices(&self) -> Vec<String> {
-        let mut voices: Vec<String> = self.styles.keys().cloned().collect();
-        voices.sort();
-        voices
+        self.styles.names()
+    }
+
+    /// Adds the voices in `dir` (individual voice files or packs) to the loaded ones,
+    /// replacing loaded voices of the same name
+    pub fn add_voices_dir(&mut self, dir: &Path) -> Result<(), KokorosError> {
+        let user = voices::load_voices(dir)?;
+        tracing::info!(
+            "Loaded {} voices from {}",
+            user.names().len(),
+            dir.display()
+        );
+        self.styles = Arc::new(voices::Styles::Layered(vec![
+            Arc::new(user),
+            Arc::clone(&self.styles),
+        ]));
+        Ok(())
+    }
+
+    /// Registers `voice` as `name` for subsequent requests. Clones of this instance see it
+    /// too, as they share the loaded voices.
+    pub fn add_voice(&self, name: &str, voice: voices::Voice) -> Result<(), KokorosError> {
+        self.styles.insert(name, voice)
+    }
+
+    /// The loaded voices, e.g. for blending new ones
+    pub fn styles(&self) -> &voices::Styles {
+        &self.styles
+    }
+
+    /// Sample rate of the audio synthesized, in Hz
+    pub fn sample_rate(&self) -> u32 {
+        self.init_config.sample_rate
+    }
+
+    /// This instance, sharing the model and voices, with streamed synthesis
+    /// ([`TTSKoko::tts_raw_audio_streaming`] and what builds on it) starting with a chunk of
+    /// at most `tokens` tokens, or with chunks of the usual size for `None`
+    pub fn with_first_chunk_tokens(&self, tokens: Option<usize>) -> Self {
+        let mut tts = self.clone();
+        tts.init_config.first_chunk_tokens = tokens;
+        tts
+    }
+
+    /// This instance, sharing the model and voices, with its synthesis queued at
+    /// `priority` behind or ahead of other requests for the same model
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        let mut tts = self.clone();
+        tts.init_config.priority = priority;
+        tts
+    }
+
+    /// How the text is rewritten before it is phonemized
+    pub fn text_options(&self) -> &TextOptions {
+        &self.init_config.text
+    }
+
+    /// This instance, sharing the model and voices, rewriting the text it is given with
+    /// `options`, e.g. to spell out identifiers or read out math
+    pub fn with_text_options(&self, options: TextOptions) -> Self {
+        let mut tts = self.clone();
+        tts.init_config.text = options;
+        tts
+    }
+
+    /// Chunks being synthesized or waiting for the model, across every clone of this
+    /// instance
+    pub fn queue_depth(&self) -> usize {
+        self.queue.depth()
+    }
+
+    /// The execution provider the model runs on: the CPU when a CUDA build couldn't use
+    /// the GPU, or stopped using it after a failed run
+    pub fn execution_provider(&self) -> Provider {
+        lock(&self.sessions[0]).provider()
+    }
+
+    /// Number of sessions the model is loaded into, which is how many chunks can be
+    /// inferred at once
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// The release the model was loaded as, see [`InitConfig::model_version`]
+    pub fn model_version(&self) -> ModelVersion {
+        self.version
+    }
+
+    /// Whether the model gives word timestamps along with the audio
+    pub fn timestamped(&self) -> bool {
+        self.timestamped
     }
 }
 
@@ -1072,17 +1670,9 @@ impl TTSKokoParallel {
         cfg: InitConfig,
         num_instances: usize,
     ) -> Self {
-        if !Path::new(model_path).exists() {
-            utils::fileio::download_file_from_url(cfg.model_url.as_str(), model_path)
-                .await
-                .expect("download model failed.");
-        }
-
-        if !Path::new(voices_path).exists() {
-            utils::fileio::download_file_from_url(cfg.voices_url.as_str(), voices_path)
-                .await
-                .expect("download voices data file failed.");
-        }
+        ensure_model_files(model_path, voices_path, &cfg)
+            .await
+            .expect("Failed to fetch the model files");
 
         // Create multiple ONNX model instances
         let mut models = Vec::new();
@@ -1093,14 +1683,15 @@ impl TTSKokoParallel {
                 i + 1,
                 num_instances
             );
-            let model = Arc::new(Mutex::new(
-                ort_koko::OrtKoko::new(model_path.to_string())
-                    .expect("Failed to create Kokoro TTS model"),
-            ));
-            models.push(model);
+            let model = ort_koko::OrtKoko::new(model_path.to_string(), &cfg.session)
+                .expect("Failed to create Kokoro TTS model");
+            model
+                .check_style_input(voices::STYLE_DIM)
+                .expect("The voices don't fit the model");
+            models.push(Arc::new(Mutex::new(model)));
         }
 
-        let styles = TTSKoko::load_voices(voices_path);
+        let styles = TTSKoko::load_voices(voices_path).expect("Failed to load voices");
 
         TTSKokoParallel {
             model_path: model_path.to_string(),
@@ -1120,9 +1711,11 @@ impl TTSKokoParallel {
     fn get_tts_wrapper(&self, model_instance: Arc<Mutex<ort_koko::OrtKoko>>) -> TTSKoko {
         TTSKoko {
             model_path: self.model_path.clone(),
-            model: model_instance,
-            // TODO: This clones the HashMap. In a future PR, wrap styles in Arc<>!
-            styles: self.styles.clone(),
+            timestamped: is_timestamped(&model_instance),
+            version: resolve_version(&model_instance, self.init_config.model_version),
+            sessions: Arc::new([model_instance]),
+            queue: Arc::default(),
+            styles: Arc::clone(&self.styles),
             init_config: self.init_config.clone(),
         }
     }
@@ -1139,7 +1732,7 @@ impl TTSKokoParallel {
         instance_id: Option<&str>,
         chunk_number: Option<usize>,
         model_instance: Arc<Mutex<ort_koko::OrtKoko>>,
-    ) -> Result<Option<(Vec<f32>, Vec<WordAlignment>)>, Box<dyn Error>> {
+    ) -> Result<Option<(Vec<f32>, Vec<WordAlignment>)>, KokorosError> {
         let wrapper = self.get_tts_wrapper(model_instance);
         wrapper.tts_timestamped_raw_audio(
             text,
@@ -1165,7 +1758,7 @@ impl TTSKokoParallel {
         instance_id: Option<&str>,
         chunk_number: Option<usize>,
         model_instance: Arc<Mutex<ort_koko::OrtKoko>>,
-    ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    ) -> Result<Vec<f32>, KokorosError> {
         let wrapper = self.get_tts_wrapper(model_instance);
 
         wrapper.tts_raw_audio(
@@ -1185,7 +1778,10 @@ impl TTSKokoParallel {
         // Use TTSKoko's implementation for now - create temporary instance
         let temp_tts = TTSKoko {
             model_path: self.model_path.clone(),
-            model: Arc::clone(&self.models[0]), // Just for interface compatibility
+            sessions: Arc::new([Arc::clone(&self.models[0])]), // Just for interface compatibility
+            queue: Arc::default(),
+            timestamped: is_timestamped(&self.models[0]),
+            version: resolve_version(&self.models[0], self.init_config.model_version),
             styles: self.styles.clone(),
             init_config: self.init_config.clone(),
         };
@@ -1194,8 +1790,6 @@ impl TTSKokoParallel {
 
     /// Get available voices
     pub fn get_available_voices(&self) -> Vec<String> {
-        let mut voices: Vec<String> = self.styles.keys().cloned().collect();
-        voices.sort();
-        voices
+        self.styles.names()
     }
 }
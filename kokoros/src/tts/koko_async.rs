@@ -0,0 +1,267 @@
+//! Async front end for [`TTSKoko`] (`async` feature): synthesis runs on tokio's blocking
+//! thread pool, so async servers don't stall their worker threads on inference.
+//!
+//! Dropping the returned future or stream cancels the synthesis after the chunk in
+//! progress, e.g. when a client disconnects or a `select!` picks another branch.
+//!
+//! Streams hold at most a few chunks the consumer hasn't taken yet: synthesis waits while
+//! they are full, so a slow client pauses it instead of piling up audio in memory.
+
+#[cfg(feature = "opus")]
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc;
+
+use crate::error::KokorosError;
+use crate::tts::koko::TTSKoko;
+pub use crate::tts::synthesis::SynthRequest;
+use crate::tts::synthesis::{SynthProgress, SynthesisResult};
+use crate::utils::audio::to_pcm16;
+#[cfg(feature = "opus")]
+use crate::utils::opus::OpusFrameEncoder;
+
+/// Sets the flag when dropped, telling the blocking task to stop
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Chunks of audio in order, as they are synthesized; see [`TTSKoko::synth_stream_async`]
+pub struct SynthStream {
+    chunks: mpsc::Receiver<Result<Vec<f32>, KokorosError>>,
+    sample_rate: u32,
+}
+
+impl SynthStream {
+    /// The next chunk, or `None` once the text has been spoken
+    pub async fn next(&mut self) -> Option<Result<Vec<f32>, KokorosError>> {
+        self.chunks.recv().await
+    }
+
+    /// The audio as a byte stream of 16-bit little-endian PCM, e.g. to copy into a socket
+    /// or an HTTP body
+    pub fn into_pcm_reader(self) -> PcmReader {
+        PcmReader {
+            stream: self,
+            pending: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// The audio as 20 ms Opus frames at `bitrate` bits per second (within
+    /// [`VOICE_BITRATES`](crate::utils::opus::VOICE_BITRATES)), e.g. for a satellite speaker
+    /// that decodes packet by packet (`opus` feature)
+    #[cfg(feature = "opus")]
+    pub fn into_opus_frames(self, bitrate: u32) -> Result<OpusFrames, KokorosError> {
+        let encoder = OpusFrameEncoder::new(self.sample_rate, bitrate)
+            .map_err(|e| KokorosError::Encode(e.to_string()))?;
+        Ok(OpusFrames {
+            stream: self,
+            encoder: Some(encoder),
+            frames: VecDeque::new(),
+        })
+    }
+}
+
+impl futures_core::Stream for SynthStream {
+    type Item = Result<Vec<f32>, KokorosError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.chunks.poll_recv(cx)
+    }
+}
+
+/// A [`SynthStream`] read as 16-bit PCM; see [`SynthStream::into_pcm_reader`]. Synthesis
+/// errors are read as `io::Error`s.
+pub struct PcmReader {
+    stream: SynthStream,
+    /// The chunk being read, and how much of it has been
+    pending: Vec<u8>,
+    position: usize,
+}
+
+/// A [`SynthStream`] encoded to Opus frames; see [`SynthStream::into_opus_frames`]
+#[cfg(feature = "opus")]
+pub struct OpusFrames {
+    stream: SynthStream,
+    /// `None` once the audio has ended and the last frame is out
+    encoder: Option<OpusFrameEncoder>,
+    /// Frames encoded but not yet yielded
+    frames: VecDeque<Vec<u8>>,
+}
+
+#[cfg(feature = "opus")]
+impl OpusFrames {
+    /// The next frame, or `None` once the text has been spoken
+    pub async fn next(&mut self) -> Option<Result<Vec<u8>, KokorosError>> {
+        std::future::poll_fn(|cx| futures_core::Stream::poll_next(Pin::new(&mut *self), cx)).await
+    }
+}
+
+#[cfg(feature = "opus")]
+impl futures_core::Stream for OpusFrames {
+    type Item = Result<Vec<u8>, KokorosError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(frame) = this.frames.pop_front() {
+                return Poll::Ready(Some(Ok(frame)));
+            }
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(None);
+            };
+            let encoded = match ready!(this.stream.chunks.poll_recv(cx)) {
+                Some(Ok(chunk)) => encoder.push(&chunk),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                // End of the audio
+                None => this
+                    .encoder
+                    .take()
+                    .unwrap()
+                    .finish()
+                    .map(|last| last.into_iter().collect()),
+            };
+            match encoded {
+                Ok(frames) => this.frames.extend(frames),
+                Err(e) => return Poll::Ready(Some(Err(KokorosError::Encode(e.to_string())))),
+            }
+        }
+    }
+}
+
+impl AsyncRead for PcmReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        while this.position == this.pending.len() {
+            match ready!(this.stream.chunks.poll_recv(cx)) {
+                Some(Ok(chunk)) => {
+                    this.pending = to_pcm16(&chunk);
+                    this.position = 0;
+                }
+                Some(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+                // End of the audio
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = buf.remaining().min(this.pending.len() - this.position);
+        buf.put_slice(&this.pending[this.position..this.position + n]);
+        this.position += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl TTSKoko {
+    /// Synthesizes `request` on the blocking thread pool, see [`TTSKoko::synthesize`]
+    pub async fn synth_async(
+        &self,
+        request: SynthRequest,
+    ) -> Result<SynthesisResult, KokorosError> {
+        self.synth_async_with_progress(request, |_| {}).await
+    }
+
+    /// [`Self::synth_async`], calling `on_progress` on the blocking thread after every
+    /// chunk, see [`TTSKoko::synthesize_with_progress`]
+    pub async fn synth_async_with_progress<F>(
+        &self,
+        request: SynthRequest,
+        on_progress: F,
+    ) -> Result<SynthesisResult, KokorosError>
+    where
+        F: FnMut(&SynthProgress) + Send + 'static,
+    {
+        let tts = self.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _cancel = CancelOnDrop(Arc::clone(&cancelled));
+        tokio::task::spawn_blocking(move || {
+            tts.synthesize_with_progress(&request, Some(&cancelled), on_progress)
+        })
+        .await
+        .map_err(|e| KokorosError::Inference(e.to_string()))?
+    }
+
+    /// Synthesizes `request` on the blocking thread pool, yielding each chunk's audio as
+    /// soon as it is ready. At most `buffer` chunks are synthesized ahead of the consumer.
+    pub fn synth_stream_async(&self, request: SynthRequest, buffer: usize) -> SynthStream {
+        let tts = self.with_priority(request.priority);
+        let (sender, chunks) = mpsc::channel(buffer.max(1));
+        tokio::task::spawn_blocking(move || {
+            let result = tts.tts_raw_audio_streaming(
+                &request.text,
+                &request.lan,
+                &request.style,
+                request.speed,
+                request.initial_silence,
+                request.request_id.as_deref(),
+                None,
+                None,
+                // Fails once the stream has been dropped, which ends the synthesis
+                |chunk| {
+                    sender
+                        .blocking_send(Ok(chunk))
+                        .map_err(|_| Box::new(KokorosError::Cancelled).into())
+                },
+            );
+            if let Err(e) = result {
+                let _ = sender.blocking_send(Err(e));
+            }
+        });
+        SynthStream {
+            chunks,
+            sample_rate: self.sample_rate(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tts::koko::Priority;
+
+    #[test]
+    fn test_cancel_on_drop() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let guard = CancelOnDrop(Arc::clone(&cancelled));
+        assert!(!cancelled.load(Ordering::Relaxed));
+        drop(guard);
+        assert!(cancelled.load(Ordering::Relaxed));
+
+        let request = SynthRequest::new("Hello", "af_sky");
+        assert_eq!((request.lan.as_str(), request.speed), ("en-us", 1.0));
+        assert_eq!(request.priority, Priority::Interactive);
+    }
+
+    #[test]
+    fn test_pcm_reader() {
+        use tokio::io::AsyncReadExt;
+
+        let (tx, chunks) = mpsc::channel(2);
+        tx.try_send(Ok(vec![0.0, 1.0])).unwrap();
+        tx.try_send(Ok(vec![-1.0])).unwrap();
+        drop(tx);
+        let mut reader = SynthStream {
+            chunks,
+            sample_rate: 24_000,
+        }
+        .into_pcm_reader();
+        let mut pcm = Vec::new();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(reader.read_to_end(&mut pcm)).unwrap();
+        assert_eq!(pcm, [0, 0, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}
@@ -1,5 +1,34 @@
+#[cfg(feature = "tts")]
+pub mod builder;
+#[cfg(feature = "chat")]
+pub mod chat;
+pub mod chunker;
+pub mod code;
+#[cfg(feature = "espeak")]
+pub mod espeak_data;
+#[cfg(feature = "tts")]
+pub mod estimate;
+#[cfg(feature = "tts")]
+pub mod golden;
+#[cfg(feature = "tts")]
 pub mod koko;
+#[cfg(feature = "async")]
+pub mod koko_async;
 pub mod normalize;
+pub mod ordinals;
+#[cfg(feature = "tts")]
+pub mod parallel;
 pub mod phonemizer;
+pub mod plugin;
+#[cfg(feature = "tts")]
+pub mod priority;
+pub mod pronunciation;
+pub mod readalong;
+#[cfg(feature = "tts")]
+pub mod streaming;
+pub mod stretch;
+#[cfg(feature = "tts")]
+pub mod synthesis;
 pub mod tokenize;
 pub mod vocab;
+pub mod voices;
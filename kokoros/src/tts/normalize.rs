@@ -1,71 +1,861 @@
 use lazy_static::lazy_static;
 use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::sync::{Arc, PoisonError, RwLock};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::tts::code::verbalize_code;
+use crate::tts::ordinals::expand_ordinals;
+use crate::tts::plugin::TextPlugin;
+use crate::tts::pronunciation;
 
 lazy_static! {
     static ref WHITESPACE_RE: Regex = Regex::new(r"[^\S \n]").unwrap();
     static ref MULTI_SPACE_RE: Regex = Regex::new(r"  +").unwrap();
-    static ref NEWLINE_SPACE_RE: Regex = Regex::new(r"(?<=\n) +(?=\n)").unwrap();
-    static ref DOCTOR_RE: Regex = Regex::new(r"\bD[Rr]\.(?= [A-Z])").unwrap();
-    static ref MISTER_RE: Regex = Regex::new(r"\b(?:Mr\.|MR\.(?= [A-Z]))").unwrap();
-    static ref MISS_RE: Regex = Regex::new(r"\b(?:Ms\.|MS\.(?= [A-Z]))").unwrap();
-    static ref MRS_RE: Regex = Regex::new(r"\b(?:Mrs\.|MRS\.(?= [A-Z]))").unwrap();
-    static ref ETC_RE: Regex = Regex::new(r"\betc\.(?! [A-Z])").unwrap();
+    static ref BLANK_LINE_RE: Regex = Regex::new(r"(?m)^ +$").unwrap();
     static ref YEAH_RE: Regex = Regex::new(r"(?i)\b(y)eah?\b").unwrap();
     static ref NUMBERS_RE: Regex =
         Regex::new(r"\d*\.\d+|\b\d{4}s?\b|(?<!:)\b(?:[1-9]|1[0-2]):[0-5]\d\b(?!:)").unwrap();
-    static ref COMMA_NUM_RE: Regex = Regex::new(r"(?<=\d),(?=\d)").unwrap();
+    static ref COMMA_NUM_RE: Regex = Regex::new(r"\d+(?:,\d+)+").unwrap();
     static ref MONEY_RE: Regex = Regex::new(
         r"(?i)[$£]\d+(?:\.\d+)?(?: hundred| thousand| (?:[bm]|tr)illion)*\b|[$£]\d+\.\d\d?\b"
     )
     .unwrap();
     static ref POINT_NUM_RE: Regex = Regex::new(r"\d*\.\d+").unwrap();
-    static ref RANGE_RE: Regex = Regex::new(r"(?<=\d)-(?=\d)").unwrap();
-    static ref S_AFTER_NUM_RE: Regex = Regex::new(r"(?<=\d)S").unwrap();
-    static ref POSSESSIVE_RE: Regex = Regex::new(r"(?<=[BCDFGHJ-NP-TV-Z])'?s\b").unwrap();
-    static ref X_POSSESSIVE_RE: Regex = Regex::new(r"(?<=X')S\b").unwrap();
+    static ref RANGE_RE: Regex = Regex::new(r"(\d)-(\d)").unwrap();
+    static ref S_AFTER_NUM_RE: Regex = Regex::new(r"(\d)S").unwrap();
+    static ref POSSESSIVE_RE: Regex = Regex::new(r"([BCDFGHJ-NP-TV-Z])'?s\b").unwrap();
+    static ref X_POSSESSIVE_RE: Regex = Regex::new(r"X'S\b").unwrap();
     static ref INITIALS_RE: Regex = Regex::new(r"(?:[A-Za-z]\.){2,} [a-z]").unwrap();
-    static ref ACRONYM_RE: Regex = Regex::new(r"(?i)(?<=[A-Z])\.(?=[A-Z])").unwrap();
+    static ref ACRONYM_RE: Regex = Regex::new(r"(?i)[A-Z](?:\.[A-Z])+").unwrap();
+    /// Inline pronunciation overrides, which are left alone, or words that may be
+    /// identifiers: letters and digits, possibly in groups joined by `-`, `_`, `/` or `.`
+    static ref IDENTIFIER_RE: Regex = Regex::new(&format!(
+        r"{}|\b[A-Za-z0-9]+(?:[-_/.][A-Za-z0-9]+)*\b",
+        pronunciation::OVERRIDE_PATTERN
+    ))
+    .unwrap();
+    /// Inline math in `$...$` or `\(...\)`, not touching spaces inside the dollars, so
+    /// "$5 and $10" is left alone
+    static ref MATH_SPAN_RE: Regex =
+        Regex::new(r"\$([^$\s](?:[^$]*[^$\s])?)\$|\\\((.+?)\\\)").unwrap();
+    static ref FRAC_RE: Regex = Regex::new(r"\\frac\s*\{([^{}]*)\}\s*\{([^{}]*)\}").unwrap();
+    static ref SQRT_RE: Regex = Regex::new(r"\\sqrt\s*\{([^{}]*)\}").unwrap();
+    static ref LATEX_COMMAND_RE: Regex = Regex::new(r"\\([A-Za-z]+)").unwrap();
+    static ref POWER_RE: Regex = Regex::new(r"([\w)])\s*\^\s*(\{[^{}]*\}|-?\w+)").unwrap();
+    static ref SUBSCRIPT_RE: Regex =
+        Regex::new(r"\b([A-Za-z])_(\{[^{}]*\}|[A-Za-z0-9]\b)").unwrap();
+    static ref PERCENT_RE: Regex = Regex::new(r"(\d)\s*%").unwrap();
+    static ref COMPOUND_RE: Regex = Regex::new(r"\p{L}+(?:-\p{L}+)+").unwrap();
+    /// Dashes setting off an aside: em dashes and `--` with or without spaces, and en
+    /// dashes or hyphens with spaces after a word
+    static ref ASIDE_DASH_RE: Regex =
+        Regex::new(r#"(\S)\s*(?:—|―|--)\s*(\S)|(\p{L}[,;:!?"')]*)\s+[–-]\s+(\S)"#).unwrap();
+    /// Operators between two operands; `-`, `*` and `/` only between numbers and with
+    /// spaces around them, as they have other uses in prose
+    static ref OPERATOR_RE: Regex = Regex::new(
+        r"([\w)²³])\s*(<=|>=|!=|==|[+±×÷·=≠<>≤≥≈−])\s*([\w(√−])|([\d)²³])\s+([-*/])\s+([\d(√])"
+    )
+    .unwrap();
 }
 
-pub fn normalize_text(text: &str) -> String {
+/// Longest all-caps word spelled out as an acronym; longer ones are more likely shouting
+const MAX_ACRONYM_LETTERS: usize = 5;
+
+/// Applies `re` to `text` in place; only allocates a new string if something matched
+pub(crate) fn replace_all<R: regex::Replacer>(text: &mut String, re: &Regex, rep: R) {
+    if let Cow::Owned(replaced) = re.replace_all(text, rep) {
+        *text = replaced;
+    }
+}
+
+/// Trims `text` in place, without a new allocation
+pub(crate) fn trim_in_place(text: &mut String) {
+    text.truncate(text.trim_end().len());
+    let leading = text.len() - text.trim_start().len();
+    text.drain(..leading);
+}
+
+/// Puts visually identical text into one form so it phonemizes the same: NFC, full-width
+/// ASCII folded to ASCII, typographic quotes to straight ones, and unusual spaces to a
+/// plain space (zero-width ones removed)
+pub fn fold_text(text: &str) -> String {
+    text.nfc()
+        .filter_map(|c| match c {
+            // Full-width forms of ASCII, and the ideographic space
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0),
+            '\u{3000}' => Some(' '),
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' => Some('\''),
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' => Some('"'),
+            '\u{00A0}' | '\u{1680}' | '\u{2000}'..='\u{200A}' | '\u{202F}' | '\u{205F}' => {
+                Some(' ')
+            }
+            '\u{00AD}' | '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}' => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+/// How a character is read when spelling, in English
+fn spoken_char(c: char) -> Cow<'static, str> {
+    const LETTERS: [&str; 26] = [
+        "ay",
+        "bee",
+        "see",
+        "dee",
+        "ee",
+        "eff",
+        "gee",
+        "aitch",
+        "eye",
+        "jay",
+        "kay",
+        "el",
+        "em",
+        "en",
+        "oh",
+        "pee",
+        "cue",
+        "ar",
+        "ess",
+        "tee",
+        "you",
+        "vee",
+        "double you",
+        "ex",
+        "why",
+        "zee",
+    ];
+    const DIGITS: [&str; 10] = [
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+    ];
+    Cow::Borrowed(match c {
+        'a'..='z' => LETTERS[c as usize - 'a' as usize],
+        'A'..='Z' => LETTERS[c as usize - 'A' as usize],
+        '0'..='9' => DIGITS[c as usize - '0' as usize],
+        '-' => "dash",
+        '_' => "underscore",
+        '/' => "slash",
+        '.' => "dot",
+        '@' => "at",
+        '#' => "hash",
+        '+' => "plus",
+        '&' => "and",
+        c => return Cow::Owned(c.to_string()),
+    })
+}
+
+/// `text` spelled out character by character, as for SSML's `say-as` `characters`, e.g.
+/// "AB12-XY9" as "ay bee one two, dash, ex why nine". Symbols between groups get a
+/// short pause either side, and so does whitespace.
+pub fn spell_out(text: &str) -> String {
+    let mut spelled = String::new();
+    for word in text.split_whitespace() {
+        if !spelled.is_empty() {
+            spelled.push_str(", ");
+        }
+        let mut after_symbol = false;
+        for (i, c) in word.chars().enumerate() {
+            let symbol = !c.is_alphanumeric();
+            if i > 0 {
+                spelled.push_str(if symbol || after_symbol { ", " } else { " " });
+            }
+            spelled.push_str(&spoken_char(c));
+            after_symbol = symbol;
+        }
+    }
+    spelled
+}
+
+/// Whether `word` should be spelled rather than pronounced: it mixes letters and digits,
+/// like a serial number, or it is a short all-caps acronym in text that isn't all caps
+fn is_identifier(word: &str, shouting: bool) -> bool {
+    let letters = word.chars().filter(char::is_ascii_alphabetic).count();
+    let has_digits = word.chars().any(|c| c.is_ascii_digit());
+    let all_caps = !word.chars().any(|c| c.is_ascii_lowercase());
+    (letters > 0 && has_digits)
+        || (all_caps && !shouting && (2..=MAX_ACRONYM_LETTERS).contains(&letters))
+}
+
+/// `text` with identifiers and acronyms (see [`spell_out`]) written out letter by letter so
+/// they are spelled, not given an invented pronunciation. Inline pronunciation overrides
+/// are left as they are.
+pub fn spell_out_identifiers(text: &str) -> Cow<'_, str> {
+    let shouting = !text.chars().any(|c| c.is_lowercase());
+    IDENTIFIER_RE.replace_all(text, |caps: &regex::Captures| {
+        let word = &caps[0];
+        if caps.get(1).is_none() && is_identifier(word, shouting) {
+            spell_out(word)
+        } else {
+            word.to_string()
+        }
+    })
+}
+
+/// How a math operator is read
+fn spoken_operator(operator: &str) -> &'static str {
+    match operator {
+        "+" => "plus",
+        "-" | "−" => "minus",
+        "*" | "×" | "·" => "times",
+        "/" | "÷" => "divided by",
+        "=" | "==" => "equals",
+        "!=" | "≠" => "is not equal to",
+        "<" => "is less than",
+        ">" => "is greater than",
+        "<=" | "≤" => "is less than or equal to",
+        ">=" | "≥" => "is greater than or equal to",
+        "≈" => "is approximately",
+        _ => "plus or minus",
+    }
+}
+
+/// How a LaTeX command is read, e.g. `\pi` as "pi"; unknown ones are read by name
+fn spoken_latex_command(command: &str) -> &str {
+    match command {
+        "times" | "cdot" => " times ",
+        "div" => " divided by ",
+        "pm" => " plus or minus ",
+        "neq" | "ne" => " is not equal to ",
+        "leq" | "le" => " is less than or equal to ",
+        "geq" | "ge" => " is greater than or equal to ",
+        "approx" => " is approximately ",
+        "infty" => "infinity",
+        "sum" => "the sum of ",
+        "int" => "the integral of ",
+        "sin" => "sine ",
+        "cos" => "cosine ",
+        "tan" => "tangent ",
+        "ln" => "natural log of ",
+        "log" => "log of ",
+        "left" | "right" | "displaystyle" => "",
+        command => command,
+    }
+}
+
+/// `x` in `x^{...}` or `y_{...}`, without the braces
+fn unbraced(script: &str) -> &str {
+    script.trim_start_matches('{').trim_end_matches('}').trim()
+}
+
+/// `text` with simple math read out: operators ("3 + 4 = 7" as "3 plus 4 equals 7"),
+/// powers ("x^2" as "x squared"), percentages, square roots, and basic LaTeX such as
+/// `$\frac{1}{2}$` and `\pi`. Numbers are left for espeak to read in the text's language.
+pub fn verbalize_math(text: &str) -> String {
     let mut text = text.to_string();
+    replace_all(&mut text, &MATH_SPAN_RE, |caps: &regex::Captures| {
+        caps.get(1).or(caps.get(2)).unwrap().as_str().to_string()
+    });
+    // Innermost first, so nested fractions are read from the inside out
+    loop {
+        let before = text.len();
+        replace_all(&mut text, &FRAC_RE, "$1 over $2");
+        replace_all(&mut text, &SQRT_RE, "the square root of $1");
+        if text.len() == before {
+            break;
+        }
+    }
+    replace_all(
+        &mut text,
+        &POWER_RE,
+        |caps: &regex::Captures| match unbraced(&caps[2]) {
+            "2" => format!("{} squared", &caps[1]),
+            "3" => format!("{} cubed", &caps[1]),
+            power => format!("{} to the power of {}", &caps[1], power),
+        },
+    );
+    replace_all(&mut text, &SUBSCRIPT_RE, |caps: &regex::Captures| {
+        format!("{} sub {}", &caps[1], unbraced(&caps[2]))
+    });
+    replace_all(&mut text, &LATEX_COMMAND_RE, |caps: &regex::Captures| {
+        spoken_latex_command(&caps[1]).to_string()
+    });
+    replace_all(&mut text, &PERCENT_RE, "$1 percent");
+    // Operands overlap in "1 + 2 + 3", so this takes a pass per operator at most
+    loop {
+        let before = text.len();
+        replace_all(&mut text, &OPERATOR_RE, |caps: &regex::Captures| {
+            let (left, operator, right) = match caps.get(1) {
+                Some(left) => (left.as_str(), &caps[2], &caps[3]),
+                None => (&caps[4], &caps[5], &caps[6]),
+            };
+            format!("{} {} {}", left, spoken_operator(operator), right)
+        });
+        if text.len() == before {
+            break;
+        }
+    }
+    let text = text
+        .replace('²', " squared")
+        .replace('³', " cubed")
+        .replace('√', "the square root of ")
+        .replace(['{', '}'], "");
+    MULTI_SPACE_RE.replace_all(&text, " ").into_owned()
+}
+
+/// What becomes of hyphenated compounds like "state-of-the-art", and of dashes that set
+/// off an aside
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compounds {
+    /// Phonemize each part of a compound as a word of its own, run together without a
+    /// pause, and pause at dashes as at commas
+    #[default]
+    Split,
+    /// Join the parts into one word ("e-mail" as "email"), and pause at dashes
+    Join,
+    /// Leave hyphens and dashes to espeak
+    Keep,
+}
+
+impl std::str::FromStr for Compounds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "split" => Ok(Compounds::Split),
+            "join" => Ok(Compounds::Join),
+            "keep" => Ok(Compounds::Keep),
+            _ => Err(format!(
+                "unknown compound handling {:?}, expected split, join or keep",
+                s
+            )),
+        }
+    }
+}
+
+/// `text` with its hyphenated compounds and aside dashes handled as `mode` says. Hyphens
+/// between digits, as in ranges and phone numbers, are left alone.
+pub fn rewrite_compounds(text: &str, mode: Compounds) -> Cow<'_, str> {
+    let joiner = match mode {
+        Compounds::Split => " ",
+        Compounds::Join => "",
+        Compounds::Keep => return Cow::Borrowed(text),
+    };
+    let text = COMPOUND_RE.replace_all(text, |caps: &regex::Captures| caps[0].replace('-', joiner));
+    let rewritten = ASIDE_DASH_RE.replace_all(&text, |caps: &regex::Captures| {
+        let (before, after) = match caps.get(1) {
+            Some(before) => (before.as_str(), &caps[2]),
+            None => (&caps[3], &caps[4]),
+        };
+        let digit = |s: &str, last: bool| {
+            let c = if last {
+                s.chars().last()
+            } else {
+                s.chars().next()
+            };
+            c.is_some_and(|c| c.is_ascii_digit())
+        };
+        // "1914—1918" is a range
+        if digit(before, true) && digit(after, false) {
+            return caps[0].to_string();
+        }
+        // The dash's pause, unless the aside ends in punctuation of its own
+        match before {
+            b if b.ends_with(|c: char| ",;:.!?".contains(c)) => format!("{} {}", before, after),
+            _ => format!("{}, {}", before, after),
+        }
+    });
+    match rewritten {
+        Cow::Owned(rewritten) => Cow::Owned(rewritten),
+        Cow::Borrowed(_) => text,
+    }
+}
+
+/// A user-defined rewrite, e.g. expanding internal ticket IDs or dropping boilerplate
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pattern: Regex,
+    /// With `$1`, `${name}` for the pattern's groups
+    replacement: String,
+}
+
+impl Replacement {
+    pub fn new(pattern: &str, replacement: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: Regex::new(pattern)?,
+            replacement: replacement.to_string(),
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    pub fn replacement(&self) -> &str {
+        &self.replacement
+    }
+}
+
+/// `text` with each of `replacements` applied in turn, every match replaced
+pub fn apply_replacements<'a>(text: &'a str, replacements: &[Replacement]) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    for rule in replacements {
+        if let Cow::Owned(replaced) = rule.pattern.replace_all(&text, rule.replacement.as_str()) {
+            text = Cow::Owned(replaced);
+        }
+    }
+    text
+}
+
+/// Replacement rules shared by every clone of the [`TextOptions`] holding them, so they
+/// can be swapped while synthesis is running, e.g. when the config is reloaded
+#[derive(Debug, Clone, Default)]
+pub struct Replacements(Arc<RwLock<Arc<[Replacement]>>>);
+
+impl Replacements {
+    /// The rules in use
+    pub fn get(&self) -> Arc<[Replacement]> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Uses `rules` from now on, for every clone
+    pub fn set(&self, rules: Vec<Replacement>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = rules.into();
+    }
+}
+
+impl From<Vec<Replacement>> for Replacements {
+    fn from(rules: Vec<Replacement>) -> Self {
+        Self(Arc::new(RwLock::new(rules.into())))
+    }
+}
 
-    // Replace special quotes and brackets
-    text = text.replace('\u{2018}', "'").replace('\u{2019}', "'");
-    text = text.replace('«', "\u{201C}").replace('»', "\u{201D}");
-    text = text.replace('\u{201C}', "\"").replace('\u{201D}', "\"");
-    text = text.replace('(', "«").replace(')', "»");
+/// Optional rewrites of the text before it is phonemized
+#[derive(Debug, Clone, Default)]
+pub struct TextOptions {
+    /// Run on the text before anything else, in order
+    pub plugins: Arc<[Arc<dyn TextPlugin>]>,
+    /// Applied after the plugins, see [`apply_replacements`]
+    pub replacements: Replacements,
+    /// Spell out identifiers and acronyms, see [`spell_out_identifiers`]
+    pub spell_out: bool,
+    /// Read out math, see [`verbalize_math`]
+    pub verbalize_math: bool,
+    /// Read the text as source code, see [`verbalize_code`]
+    pub verbalize_code: bool,
+    /// Hyphenated compounds and aside dashes, see [`rewrite_compounds`]
+    pub compounds: Compounds,
+    /// Normalize the text after the other rewrites, see [`normalize_text_with`]
+    pub normalize: Option<NormalizeOptions>,
+}
+
+impl TextOptions {
+    /// Hex-encoded digest of every option, with the replacement rules in use now, so
+    /// that what was synthesized with other options can be told apart
+    pub fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        for plugin in self.plugins.iter() {
+            hasher.update(format!("{:?}", plugin).as_bytes());
+            hasher.update([0]);
+        }
+        for rule in self.replacements.get().iter() {
+            for part in [rule.pattern(), rule.replacement()] {
+                hasher.update(part.as_bytes());
+                hasher.update([0]);
+            }
+        }
+        hasher.update([
+            self.spell_out as u8,
+            self.verbalize_math as u8,
+            self.verbalize_code as u8,
+        ]);
+        hasher.update(format!("{:?}\0{:?}", self.compounds, self.normalize).as_bytes());
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// `text` after the plugins and replacements, which come before normalization
+    pub fn preprocess<'a>(&self, text: &'a str) -> Result<Cow<'a, str>, String> {
+        let mut text = Cow::Borrowed(text);
+        for plugin in self.plugins.iter() {
+            text = Cow::Owned(plugin.process(&text)?);
+        }
+        let replacements = self.replacements.get();
+        Ok(match apply_replacements(&text, &replacements) {
+            Cow::Owned(replaced) => Cow::Owned(replaced),
+            Cow::Borrowed(_) => text,
+        })
+    }
+
+    /// `text` with the chosen rewrites applied
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        // Code before anything else, as its symbols all have names
+        if self.verbalize_code {
+            text = Cow::Owned(verbalize_code(&text));
+        }
+        // Then math, as its minus signs aren't dashes
+        if self.verbalize_math {
+            text = Cow::Owned(verbalize_math(&text));
+        }
+        if let Cow::Owned(rewritten) = rewrite_compounds(&text, self.compounds) {
+            text = Cow::Owned(rewritten);
+        }
+        if self.spell_out
+            && let Cow::Owned(spelled) = spell_out_identifiers(&text)
+        {
+            text = Cow::Owned(spelled);
+        }
+        if let Some(options) = &self.normalize {
+            text = Cow::Owned(normalize_text_with(&text, options));
+        }
+        text
+    }
+
+    /// `text` as it is handed to the phonemizer when read in `lan`, after every rewrite
+    /// synthesis makes, for previewing what will be spoken
+    pub fn spoken_text(&self, text: &str, lan: &str) -> Result<String, String> {
+        let text = self.preprocess(text)?;
+        // Ordinals first, so "1er" isn't taken for an identifier to spell out
+        let text = expand_ordinals(&text, lan);
+        Ok(fold_text(&self.apply(&text)))
+    }
+}
+
+/// How [`normalize_text_with`] treats numbers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Numbers {
+    /// Drop thousands separators ("1,000" as "1000"), and in English read ranges with
+    /// "to" ("3-4" as "3 to 4") and keep a plural "S" apart from its number ("1990S")
+    #[default]
+    Expand,
+    /// Leave numbers as written, for espeak to read
+    Keep,
+}
+
+impl std::str::FromStr for Numbers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "expand" => Ok(Numbers::Expand),
+            "keep" => Ok(Numbers::Keep),
+            _ => Err(format!(
+                "unknown number handling {:?}, expected expand or keep",
+                s
+            )),
+        }
+    }
+}
 
-    // Replace Chinese/Japanese punctuation
-    let from_chars = ['、', '。', '！', '，', '：', '；', '？'];
-    let to_chars = [',', '.', '!', ',', ':', ';', '?'];
+/// An abbreviation written with a period and read as a word, e.g. "Dr." as "Doctor"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Abbreviation {
+    /// As written, without the period; its all-caps form matches too if it's a title
+    pub short: String,
+    pub long: String,
+    /// Only expanded before a capitalized word, as a title before a name ("Dr. Smith").
+    /// Otherwise it is expanded unless a capitalized word follows, when its period is
+    /// taken to end the sentence as well and is kept.
+    pub title: bool,
+}
+
+impl Abbreviation {
+    pub fn title(short: &str, long: &str) -> Self {
+        Self {
+            short: short.to_string(),
+            long: long.to_string(),
+            title: true,
+        }
+    }
+
+    pub fn word(short: &str, long: &str) -> Self {
+        Self {
+            title: false,
+            ..Self::title(short, long)
+        }
+    }
+
+    /// The abbreviations expanded by default in `locale`; only English has any
+    pub fn defaults(locale: &str) -> Vec<Self> {
+        if !is_english(locale) {
+            return Vec::new();
+        }
+        vec![
+            Self::title("Dr", "Doctor"),
+            Self::title("Mr", "Mister"),
+            Self::title("Ms", "Miss"),
+            Self::title("Mrs", "Mrs"),
+            Self::word("etc", "etc"),
+        ]
+    }
+
+    fn expand(&self, text: &mut String) {
+        let short = regex::escape(&self.short);
+        if self.title {
+            let pattern = format!(
+                r"\b(?:{}|{})\. (\p{{Lu}})",
+                short,
+                regex::escape(&self.short.to_uppercase())
+            );
+            let re = Regex::new(&pattern).expect("escaped abbreviation");
+            replace_all(
+                text,
+                &re,
+                format!("{} ${{1}}", self.long.replace('$', "$$")),
+            );
+        } else {
+            let re =
+                Regex::new(&format!(r"\b{}\.( \p{{Lu}})?", short)).expect("escaped abbreviation");
+            replace_all(text, &re, |caps: &regex::Captures| match caps.get(1) {
+                Some(_) => caps[0].to_string(),
+                None => self.long.clone(),
+            });
+        }
+    }
+}
+
+fn is_english(locale: &str) -> bool {
+    locale.split(['-', '_']).next().unwrap_or_default() == "en"
+}
 
-    for (from, to) in from_chars.iter().zip(to_chars.iter()) {
-        text = text.replace(*from, &format!("{} ", to));
+/// Options of [`normalize_text_with`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizeOptions {
+    /// Language of the text, e.g. "en-us"; the rules for English are only applied to it
+    pub locale: String,
+    pub numbers: Numbers,
+    /// Expanded in order
+    pub abbreviations: Vec<Abbreviation>,
+}
+
+impl NormalizeOptions {
+    /// The default options for text in `locale`
+    pub fn for_locale(locale: &str) -> Self {
+        Self {
+            locale: locale.to_string(),
+            numbers: Numbers::default(),
+            abbreviations: Abbreviation::defaults(locale),
+        }
     }
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self::for_locale("en-us")
+    }
+}
+
+/// [`normalize_text_with`] with the default options, for American English
+pub fn normalize_text(text: &str) -> String {
+    normalize_text_with(text, &NormalizeOptions::default())
+}
+
+/// `text` made regular for reading: quotes, brackets and CJK punctuation unified,
+/// whitespace collapsed, abbreviations expanded and numbers, possessives and acronyms
+/// written the way the phonemizer reads them best, as `options` say
+pub fn normalize_text_with(text: &str, options: &NormalizeOptions) -> String {
+    // Replace special quotes and brackets, and Chinese/Japanese punctuation, in one pass
+    let text = fold_text(text);
+    let mut normalized = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{2018}' | '\u{2019}' => normalized.push('\''),
+            '«' | '»' | '\u{201C}' | '\u{201D}' => normalized.push('"'),
+            '(' => normalized.push('«'),
+            ')' => normalized.push('»'),
+            '、' | '，' => normalized.push_str(", "),
+            '。' => normalized.push_str(". "),
+            '！' => normalized.push_str("! "),
+            '：' => normalized.push_str(": "),
+            '；' => normalized.push_str("; "),
+            '？' => normalized.push_str("? "),
+            c => normalized.push(c),
+        }
+    }
+    let text = &mut normalized;
 
     // Apply regex replacements
-    text = WHITESPACE_RE.replace_all(&text, " ").to_string();
-    text = MULTI_SPACE_RE.replace_all(&text, " ").to_string();
-    text = NEWLINE_SPACE_RE.replace_all(&text, "").to_string();
-    text = DOCTOR_RE.replace_all(&text, "Doctor").to_string();
-    text = MISTER_RE.replace_all(&text, "Mister").to_string();
-    text = MISS_RE.replace_all(&text, "Miss").to_string();
-    text = MRS_RE.replace_all(&text, "Mrs").to_string();
-    text = ETC_RE.replace_all(&text, "etc").to_string();
-    text = YEAH_RE.replace_all(&text, "${1}e'a").to_string();
+    replace_all(text, &WHITESPACE_RE, " ");
+    replace_all(text, &MULTI_SPACE_RE, " ");
+    replace_all(text, &BLANK_LINE_RE, "");
+    for abbreviation in &options.abbreviations {
+        abbreviation.expand(text);
+    }
+    let english = is_english(&options.locale);
+    if english {
+        replace_all(text, &YEAH_RE, "${1}e'a");
+    }
     // Note: split_num, flip_money, and point_num functions need to be implemented
-    text = COMMA_NUM_RE.replace_all(&text, "").to_string();
-    text = RANGE_RE.replace_all(&text, " to ").to_string();
-    text = S_AFTER_NUM_RE.replace_all(&text, " S").to_string();
-    text = POSSESSIVE_RE.replace_all(&text, "'S").to_string();
-    text = X_POSSESSIVE_RE.replace_all(&text, "s").to_string();
+    if options.numbers == Numbers::Expand {
+        replace_all(text, &COMMA_NUM_RE, |caps: &regex::Captures| {
+            caps[0].replace(',', "")
+        });
+        if english {
+            replace_all(text, &RANGE_RE, "${1} to ${2}");
+            replace_all(text, &S_AFTER_NUM_RE, "${1} S");
+        }
+    }
+    if english {
+        replace_all(text, &POSSESSIVE_RE, "${1}'S");
+        replace_all(text, &X_POSSESSIVE_RE, "X's");
+    }
 
     // Handle initials and acronyms
-    text = INITIALS_RE
-        .replace_all(&text, |caps: &regex::Captures| caps[0].replace('.', "-"))
-        .to_string();
-    text = ACRONYM_RE.replace_all(&text, "-").to_string();
+    replace_all(text, &INITIALS_RE, |caps: &regex::Captures| {
+        caps[0].replace('.', "-")
+    });
+    replace_all(text, &ACRONYM_RE, |caps: &regex::Captures| {
+        caps[0].replace('.', "-")
+    });
+
+    trim_in_place(text);
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    text.trim().to_string()
+    #[test]
+    fn test_apply_replacements() {
+        let replacements = [
+            Replacement::new(r"\bJIRA-(\d+)\b", "ticket $1").unwrap(),
+            Replacement::new(r"(?m)^CONFIDENTIAL: ", "").unwrap(),
+        ];
+        assert_eq!(
+            apply_replacements("CONFIDENTIAL: JIRA-42 is fixed", &replacements),
+            "ticket 42 is fixed"
+        );
+        assert!(matches!(
+            apply_replacements("nothing to do", &replacements),
+            Cow::Borrowed(_)
+        ));
+        assert!(Replacement::new("(", "").is_err());
+
+        let options = TextOptions {
+            replacements: replacements.to_vec().into(),
+            ..TextOptions::default()
+        };
+        let reloaded = options.clone();
+        options.replacements.set(Vec::new());
+        assert_eq!(reloaded.preprocess("JIRA-42").unwrap(), "JIRA-42");
+    }
+
+    #[test]
+    fn test_digest() {
+        let options = TextOptions::default();
+        let digest = options.digest();
+        assert_eq!(options.clone().digest(), digest);
+        let spelled = TextOptions {
+            spell_out: true,
+            ..options.clone()
+        };
+        assert_ne!(spelled.digest(), digest);
+
+        options
+            .replacements
+            .set(vec![Replacement::new("JIRA", "ticket").unwrap()]);
+        assert_ne!(options.digest(), digest);
+    }
+
+    #[test]
+    fn test_fold_text() {
+        // "café" decomposed, full-width, with curly quotes and a no-break space
+        assert_eq!(
+            fold_text("\u{201C}cafe\u{301}\u{201D}\u{A0}\u{FF21}\u{FF22}\u{FF11}\u{200B}!"),
+            "\"caf\u{E9}\" AB1!"
+        );
+        assert_eq!(fold_text("\u{FF28}\u{FF49}\u{3000}there"), "Hi there");
+    }
+
+    #[test]
+    fn test_spell_out() {
+        assert_eq!(spell_out("AB12-XY9"), "ay bee one two, dash, ex why nine");
+        assert_eq!(
+            spell_out_identifiers("Order SN-4471 from the BBC, not NASA's 2024 plan."),
+            "Order ess en, dash, four four seven one from the bee bee see, not en ay ess ay's 2024 plan."
+        );
+        // All caps throughout is shouting, and overrides keep their spelling
+        assert_eq!(spell_out_identifiers("STOP NOW"), "STOP NOW");
+        assert_eq!(
+            spell_out_identifiers("[SQL](/sˈikwəl/) ok"),
+            "[SQL](/sˈikwəl/) ok"
+        );
+    }
+
+    #[test]
+    fn test_verbalize_math() {
+        assert_eq!(verbalize_math("3 + 4 = 7"), "3 plus 4 equals 7");
+        assert_eq!(
+            verbalize_math("1+2+3 ≠ 7"),
+            "1 plus 2 plus 3 is not equal to 7"
+        );
+        assert_eq!(
+            verbalize_math("x^2 and y^{10}"),
+            "x squared and y to the power of 10"
+        );
+        assert_eq!(
+            verbalize_math("Up 75% on day 3-4, 8 / 2 * 3"),
+            "Up 75 percent on day 3-4, 8 divided by 2 times 3"
+        );
+        assert_eq!(
+            verbalize_math(r"Area: $\pi r^2$, half is $\frac{1}{2} \times x_1$."),
+            "Area: pi r squared, half is 1 over 2 times x sub 1."
+        );
+        assert_eq!(
+            verbalize_math("It costs $5 and $10 - a bargain"),
+            "It costs $5 and $10 - a bargain"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_compounds() {
+        let text = "A state-of-the-art model—small but fast—on pages 3-4 and in 1914—1918.";
+        assert_eq!(
+            rewrite_compounds(text, Compounds::Split),
+            "A state of the art model, small but fast, on pages 3-4 and in 1914—1918."
+        );
+        assert_eq!(
+            rewrite_compounds("Re-enter your e-mail - now", Compounds::Join),
+            "Reenter your email, now"
+        );
+        assert_eq!(rewrite_compounds(text, Compounds::Keep), text);
+        assert_eq!("join".parse(), Ok(Compounds::Join));
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(
+            normalize_text("Dr. Smith and Mr. U.S.A. met on 1,000,000 days, 3-4 etc."),
+            "Doctor Smith and Mister U-S-A- met on 1000000 days, 3 to 4 etc"
+        );
+        // Left as a sentence end before a capital, and as an abbreviation before a name only
+        assert_eq!(
+            normalize_text("Cats etc. Dogs dr. no"),
+            "Cats etc. Dogs dr. no"
+        );
+
+        let options = NormalizeOptions {
+            numbers: Numbers::Keep,
+            abbreviations: vec![Abbreviation::title("Prof", "Professor")],
+            ..NormalizeOptions::for_locale("en-gb")
+        };
+        assert_eq!(
+            normalize_text_with("Prof. Lee, Dr. Who, 1,000", &options),
+            "Professor Lee, Dr. Who, 1,000"
+        );
+        // No English rules for other languages
+        let options = NormalizeOptions::for_locale("fr-fr");
+        assert!(options.abbreviations.is_empty());
+        assert_eq!(normalize_text_with("Mr. 3-4", &options), "Mr. 3-4");
+
+        let text = TextOptions {
+            normalize: Some(NormalizeOptions::default()),
+            ..TextOptions::default()
+        };
+        assert_eq!(
+            text.spoken_text("Mr. Hyde, 1,000", "en-us").unwrap(),
+            "Mister Hyde, 1000"
+        );
+    }
 }
@@ -0,0 +1,278 @@
+//! Ordinals in Spanish, French and Portuguese, which espeak reads digit by digit or drops
+//! the marker of: "1ª" as "primera", "1er" as "primer" (Spanish) or "premier" (French),
+//! "XXe" as "vingtième". The marker carries the gender, which the words agree with.
+
+use std::borrow::Cow;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref SPANISH_RE: Regex = Regex::new(r"\b(\d{1,3})\.?(º|ª|ᵒ|ᵃ|er\b)").unwrap();
+    static ref PORTUGUESE_RE: Regex = Regex::new(r"\b(\d{1,3})\.?(º|ª|ᵒ|ᵃ)").unwrap();
+    static ref FRENCH_RE: Regex =
+        Regex::new(r"\b(\d{1,3}|[IVXLC]+)(ᵉʳ|ʳᵉ|ᵉ|ère|er|re|ème|e|nde|nd|de|d)\b").unwrap();
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Gender {
+    Masculine,
+    Feminine,
+}
+
+/// `text` with the ordinals of `lan`'s language written out, or as it is for languages
+/// without rules here
+pub fn expand_ordinals<'a>(text: &'a str, lan: &str) -> Cow<'a, str> {
+    let language = lan.split(['-', '_']).next().unwrap_or_default();
+    match language {
+        "es" => SPANISH_RE.replace_all(text, |caps: &regex::Captures| {
+            let (n, marker) = (caps[1].parse().unwrap_or(0), &caps[2]);
+            let word = match marker {
+                // The short form before a masculine noun, as in "1.er piso"
+                "er" => match n {
+                    1 => Some("primer".to_string()),
+                    3 => Some("tercer".to_string()),
+                    n => iberian_ordinal(n, Gender::Masculine, &SPANISH),
+                },
+                "ª" | "ᵃ" => iberian_ordinal(n, Gender::Feminine, &SPANISH),
+                _ => iberian_ordinal(n, Gender::Masculine, &SPANISH),
+            };
+            word.unwrap_or_else(|| caps[1].to_string())
+        }),
+        "pt" => PORTUGUESE_RE.replace_all(text, |caps: &regex::Captures| {
+            let gender = match &caps[2] {
+                "ª" | "ᵃ" => Gender::Feminine,
+                _ => Gender::Masculine,
+            };
+            iberian_ordinal(caps[1].parse().unwrap_or(0), gender, &PORTUGUESE)
+                .unwrap_or_else(|| caps[1].to_string())
+        }),
+        "fr" => FRENCH_RE.replace_all(text, |caps: &regex::Captures| {
+            // "Le" and "Ce" are words, not the 50th and 100th
+            if matches!(&caps[1], "L" | "C") {
+                return caps[0].to_string();
+            }
+            let n = caps[1].parse().ok().or_else(|| roman_numeral(&caps[1]));
+            let gender = match &caps[2] {
+                "re" | "ère" | "ʳᵉ" | "nde" | "de" => Gender::Feminine,
+                _ => Gender::Masculine,
+            };
+            n.and_then(|n| french_ordinal(n, gender, &caps[2]))
+                .unwrap_or_else(|| caps[0].to_string())
+        }),
+        _ => Cow::Borrowed(text),
+    }
+}
+
+/// Masculine ordinals for units and tens, for Spanish and Portuguese
+struct IberianOrdinals {
+    units: [&'static str; 9],
+    tens: [&'static str; 9],
+    hundred: &'static str,
+}
+
+const SPANISH: IberianOrdinals = IberianOrdinals {
+    units: [
+        "primero", "segundo", "tercero", "cuarto", "quinto", "sexto", "séptimo", "octavo", "noveno",
+    ],
+    tens: [
+        "décimo",
+        "vigésimo",
+        "trigésimo",
+        "cuadragésimo",
+        "quincuagésimo",
+        "sexagésimo",
+        "septuagésimo",
+        "octogésimo",
+        "nonagésimo",
+    ],
+    hundred: "centésimo",
+};
+
+const PORTUGUESE: IberianOrdinals = IberianOrdinals {
+    units: [
+        "primeiro", "segundo", "terceiro", "quarto", "quinto", "sexto", "sétimo", "oitavo", "nono",
+    ],
+    tens: [
+        "décimo",
+        "vigésimo",
+        "trigésimo",
+        "quadragésimo",
+        "quinquagésimo",
+        "sexagésimo",
+        "septuagésimo",
+        "octogésimo",
+        "nonagésimo",
+    ],
+    hundred: "centésimo",
+};
+
+/// `n` (1 to 100) as an ordinal agreeing with `gender`, e.g. 21 as "vigésima primera",
+/// or `None` past 100, which are read as the cardinal
+fn iberian_ordinal(n: u32, gender: Gender, words: &IberianOrdinals) -> Option<String> {
+    let masculine = match n {
+        1..=9 => words.units[n as usize - 1].to_string(),
+        10..=99 if n.is_multiple_of(10) => words.tens[n as usize / 10 - 1].to_string(),
+        11..=99 => format!(
+            "{} {}",
+            words.tens[n as usize / 10 - 1],
+            words.units[n as usize % 10 - 1]
+        ),
+        100 => words.hundred.to_string(),
+        _ => return None,
+    };
+    Some(match gender {
+        Gender::Masculine => masculine,
+        // Every word of the ordinal agrees, and each ends in -o
+        Gender::Feminine => masculine
+            .split(' ')
+            .map(|word| format!("{}a", word.strip_suffix('o').unwrap_or(word)))
+            .collect::<Vec<_>>()
+            .join(" "),
+    })
+}
+
+/// A Roman numeral from 1 to 399, as used for centuries ("XXIe siècle")
+fn roman_numeral(numeral: &str) -> Option<u32> {
+    let value = |c| match c {
+        'I' => 1,
+        'V' => 5,
+        'X' => 10,
+        'L' => 50,
+        'C' => 100,
+        _ => 0,
+    };
+    let digits: Vec<u32> = numeral.chars().map(value).collect();
+    let n = digits
+        .iter()
+        .enumerate()
+        .map(|(i, &d)| match digits.get(i + 1) {
+            Some(&next) if next > d => -(d as i32),
+            _ => d as i32,
+        })
+        .sum::<i32>();
+    // Anything that doesn't read back the same isn't a numeral, e.g. "IC"
+    (1..400)
+        .contains(&n)
+        .then_some(n as u32)
+        .filter(|&n| to_roman(n) == numeral)
+}
+
+fn to_roman(mut n: u32) -> String {
+    let mut numeral = String::new();
+    for (value, symbol) in [
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ] {
+        while n >= value {
+            numeral.push_str(symbol);
+            n -= value;
+        }
+    }
+    numeral
+}
+
+/// `n` (1 to 999) in French words, e.g. 71 as "soixante-et-onze"
+fn french_cardinal(n: u32) -> String {
+    const UNITS: [&str; 17] = [
+        "zéro", "un", "deux", "trois", "quatre", "cinq", "six", "sept", "huit", "neuf", "dix",
+        "onze", "douze", "treize", "quatorze", "quinze", "seize",
+    ];
+    const TENS: [&str; 7] = [
+        "",
+        "dix",
+        "vingt",
+        "trente",
+        "quarante",
+        "cinquante",
+        "soixante",
+    ];
+    match n {
+        0..=16 => UNITS[n as usize].to_string(),
+        17..=19 => format!("dix-{}", UNITS[n as usize - 10]),
+        20..=69 => match n % 10 {
+            0 => TENS[n as usize / 10].to_string(),
+            1 => format!("{}-et-un", TENS[n as usize / 10]),
+            unit => format!("{}-{}", TENS[n as usize / 10], UNITS[unit as usize]),
+        },
+        70..=79 if n == 71 => "soixante-et-onze".to_string(),
+        70..=79 => format!("soixante-{}", french_cardinal(n - 60)),
+        80 => "quatre-vingts".to_string(),
+        81..=99 => format!("quatre-vingt-{}", french_cardinal(n - 80)),
+        _ => {
+            let (hundreds, rest) = (n / 100, n % 100);
+            let cent = match (hundreds, rest) {
+                (1, _) => "cent".to_string(),
+                (_, 0) => format!("{} cents", UNITS[hundreds as usize]),
+                _ => format!("{} cent", UNITS[hundreds as usize]),
+            };
+            match rest {
+                0 => cent,
+                rest => format!("{} {}", cent, french_cardinal(rest)),
+            }
+        }
+    }
+}
+
+/// `n` as a French ordinal: "premier"/"première" for 1, "second(e)" when the marker was
+/// "nd(e)", otherwise the cardinal with -ième
+fn french_ordinal(n: u32, gender: Gender, marker: &str) -> Option<String> {
+    if !(1..1000).contains(&n) {
+        return None;
+    }
+    Some(match (n, gender) {
+        (1, Gender::Masculine) => "premier".to_string(),
+        (1, Gender::Feminine) => "première".to_string(),
+        (2, _) if marker.starts_with('n') || marker.starts_with('d') => match gender {
+            Gender::Masculine => "second".to_string(),
+            Gender::Feminine => "seconde".to_string(),
+        },
+        (n, _) => {
+            let cardinal = french_cardinal(n);
+            let stem = cardinal
+                .strip_suffix('s')
+                .filter(|_| cardinal.ends_with("cents") || cardinal.ends_with("vingts"))
+                .unwrap_or(&cardinal);
+            let stem = match stem {
+                stem if stem.ends_with("cinq") => format!("{}u", stem),
+                stem if stem.ends_with("neuf") => format!("{}v", &stem[..stem.len() - 1]),
+                stem => stem.strip_suffix('e').unwrap_or(stem).to_string(),
+            };
+            format!("{}ième", stem)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_ordinals() {
+        assert_eq!(
+            expand_ordinals("La 1ª vez, el 1.er piso y el 21º puesto", "es"),
+            "La primera vez, el primer piso y el vigésimo primero puesto"
+        );
+        assert_eq!(expand_ordinals("a 3ª edição", "pt-br"), "a terceira edição");
+        assert_eq!(
+            expand_ordinals("Le 1er, la 1re, le 2nd, le 5e et le XXIe siècle", "fr-fr"),
+            "Le premier, la première, le second, le cinquième et le vingt-et-unième siècle"
+        );
+        assert_eq!(
+            french_ordinal(80, Gender::Masculine, "e").unwrap(),
+            "quatre-vingtième"
+        );
+        assert_eq!(
+            french_ordinal(9, Gender::Masculine, "e").unwrap(),
+            "neuvième"
+        );
+        assert_eq!(expand_ordinals("the 1ª", "en-us"), "the 1ª");
+    }
+}
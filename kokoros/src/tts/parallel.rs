@@ -0,0 +1,177 @@
+//! Synthesis of long texts on several TTS instances at once
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Condvar, Mutex, mpsc};
+use std::time::Instant;
+
+use crate::error::KokorosError;
+use crate::tts::koko::TTSKoko;
+use crate::tts::synthesis::{SynthProgress, SynthRequest};
+use crate::tts::tokenize;
+use crate::tts::voices::Morph;
+
+/// Work shared between the workers and the thread putting the chunks back in order
+struct Queue {
+    /// Next chunk to start on
+    next: usize,
+    /// Chunks handed to the callback so far
+    emitted: usize,
+    /// Bytes of audio finished but not handed to the callback yet
+    held_bytes: usize,
+    failed: bool,
+}
+
+/// Several TTS instances that synthesize the chunks of one text at once
+pub struct ChunkPool<'a> {
+    pub instances: &'a [TTSKoko],
+    /// Bytes of audio that may be held for chunks finished ahead of their turn; beyond
+    /// that, workers wait for the chunk that is due before starting new ones
+    pub memory_budget: usize,
+    /// Called as each chunk is handed on, in order
+    pub progress: Option<&'a (dyn Fn(&SynthProgress) + Sync)>,
+}
+
+impl ChunkPool<'_> {
+    /// Like [`TTSKoko::tts_raw_audio_streaming`], but synthesizes the chunks of `txt` on
+    /// all instances at once, handing their audio to `chunk_callback` in order. Morphs
+    /// depend on each chunk's position, so they are synthesized on the first instance
+    /// alone.
+    pub fn tts_raw_audio_streaming<F>(
+        &self,
+        txt: &str,
+        lan: &str,
+        style_name: &str,
+        speed: f32,
+        initial_silence: Option<usize>,
+        mut chunk_callback: F,
+    ) -> Result<(), KokorosError>
+    where
+        F: FnMut(Vec<f32>) -> Result<(), Box<dyn Error>>,
+    {
+        let (instances, memory_budget) = (self.instances, self.memory_budget);
+        let first = instances
+            .first()
+            .ok_or_else(|| KokorosError::ModelLoad("no TTS instances".to_string()))?;
+        if instances.len() == 1 || Morph::is_morph(style_name) {
+            if let Some(progress) = self.progress {
+                let request = SynthRequest {
+                    lan: lan.to_string(),
+                    speed,
+                    initial_silence,
+                    ..SynthRequest::new(txt, style_name)
+                };
+                return first.synthesize_streaming(&request, chunk_callback, progress);
+            }
+            return first.tts_raw_audio_streaming(
+                txt,
+                lan,
+                style_name,
+                speed,
+                initial_silence,
+                None,
+                None,
+                None,
+                chunk_callback,
+            );
+        }
+
+        let max_tokens = tokenize::max_tokens(initial_silence);
+        let chunks = TTSKoko::split_text_into_chunks(txt, max_tokens, lan, first.model_version());
+        tracing::debug!(
+            "Synthesizing {} chunks on {} instances",
+            chunks.len(),
+            instances.len()
+        );
+
+        let start = Instant::now();
+        let mut samples = 0;
+        let queue = Mutex::new(Queue {
+            next: 0,
+            emitted: 0,
+            held_bytes: 0,
+            failed: false,
+        });
+        let turn = Condvar::new();
+        let (sender, results) = mpsc::channel::<(usize, Result<Vec<f32>, KokorosError>)>();
+
+        std::thread::scope(|scope| {
+            for (id, tts) in instances.iter().enumerate() {
+                let (queue, turn, chunks, sender) = (&queue, &turn, &chunks, sender.clone());
+                scope.spawn(move || {
+                    loop {
+                        let index = {
+                            let mut state = queue.lock().unwrap();
+                            // The chunk that is due may always start, so the budget can't
+                            // stall the whole pool
+                            while !state.failed
+                                && state.next < chunks.len()
+                                && state.next != state.emitted
+                                && state.held_bytes >= memory_budget
+                            {
+                                state = turn.wait(state).unwrap();
+                            }
+                            if state.failed || state.next >= chunks.len() {
+                                return;
+                            }
+                            state.next += 1;
+                            state.next - 1
+                        };
+                        let instance_id = format!("{:02x}", id);
+                        let audio = tts.tts_raw_audio(
+                            &chunks[index],
+                            lan,
+                            style_name,
+                            speed,
+                            initial_silence,
+                            None,
+                            Some(&instance_id),
+                            Some(index),
+                        );
+                        if let Ok(audio) = &audio {
+                            queue.lock().unwrap().held_bytes += audio.len() * size_of::<f32>();
+                        }
+                        if sender.send((index, audio)).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            let fail = |e: KokorosError| {
+                queue.lock().unwrap().failed = true;
+                turn.notify_all();
+                e
+            };
+            // Finished chunks that are waiting for earlier ones
+            let mut ready: BTreeMap<usize, Vec<f32>> = BTreeMap::new();
+            for (index, audio) in results {
+                ready.insert(index, audio.map_err(fail)?);
+                loop {
+                    let due = queue.lock().unwrap().emitted;
+                    let Some(audio) = ready.remove(&due) else {
+                        break;
+                    };
+                    {
+                        let mut state = queue.lock().unwrap();
+                        state.emitted += 1;
+                        state.held_bytes -= audio.len() * size_of::<f32>();
+                    }
+                    turn.notify_all();
+                    samples += audio.len();
+                    chunk_callback(audio).map_err(|e| fail(KokorosError::from_callback(e)))?;
+                    if let Some(progress) = self.progress {
+                        progress(&SynthProgress::new(
+                            due + 1,
+                            chunks.len(),
+                            samples as f32 / first.sample_rate() as f32,
+                            start.elapsed(),
+                        ));
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
@@ -65,28 +65,36 @@ impl Phonemizer {
         };
 
         // Apply kokoro-specific replacements
-        ps = ps
-            .replace("kəkˈoːɹoʊ", "kˈoʊkəɹoʊ")
-            .replace("kəkˈɔːɹəʊ", "kˈəʊkəɹəʊ");
+        if ps.contains("kəkˈ") {
+            ps = ps
+                .replace("kəkˈoːɹoʊ", "kˈoʊkəɹoʊ")
+                .replace("kəkˈɔːɹəʊ", "kˈəʊkəɹəʊ");
+        }
 
-        // Apply character replacements
+        // Apply character replacements, in one pass
         ps = ps
-            .replace("ʲ", "j")
-            .replace("r", "ɹ")
-            .replace("x", "k")
-            .replace("ɬ", "l");
+            .chars()
+            .map(|c| match c {
+                'ʲ' => 'j',
+                'r' => 'ɹ',
+                'x' => 'k',
+                'ɬ' => 'l',
+                c => c,
+            })
+            .collect();
 
         // Apply regex patterns
-        ps = PHONEME_PATTERNS.replace_all(&ps, " ").to_string();
-        ps = Z_PATTERN.replace_all(&ps, "z").to_string();
+        normalize::replace_all(&mut ps, &PHONEME_PATTERNS, " ");
+        normalize::replace_all(&mut ps, &Z_PATTERN, "z");
 
         if self.lang == "a" {
-            ps = NINETY_PATTERN.replace_all(&ps, "di").to_string();
+            normalize::replace_all(&mut ps, &NINETY_PATTERN, "di");
         }
 
         // Filter characters present in vocabulary
-        ps = ps.chars().filter(|&c| VOCAB.contains_key(&c)).collect();
+        ps.retain(|c| VOCAB.contains_key(&c));
 
-        ps.trim().to_string()
+        normalize::trim_in_place(&mut ps);
+        ps
     }
 }
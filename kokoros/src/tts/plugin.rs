@@ -0,0 +1,218 @@
+//! Text plugins: site-specific cleanup run on the text before anything else, loaded at
+//! startup so it can change without rebuilding Kokoros. A plugin is a shared library or
+//! a WebAssembly module with one `process(text) -> text` function:
+//!
+//! - A shared library (`plugins` feature) exports `char *kokoros_process(const char *text)`,
+//!   returning a new NUL-terminated UTF-8 string or NULL on failure, and
+//!   `void kokoros_free(char *text)` to free that string. It may be called from several
+//!   threads at once.
+//! - A WebAssembly module (`wasm-plugins` feature) exports its `memory`, an
+//!   `alloc(len: i32) -> i32` for the input and `process(ptr: i32, len: i32) -> i64`,
+//!   returning the output's address in the high 32 bits and its length in the low ones.
+//!   An optional `dealloc(ptr: i32, len: i32)` is called on both afterwards. Modules get
+//!   no imports, so they can't reach the file system or the network.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Something that rewrites the text before synthesis. Loaded plugins implement it, and
+/// so can Rust code embedding Kokoros.
+pub trait TextPlugin: Send + Sync + fmt::Debug {
+    fn process(&self, text: &str) -> Result<String, String>;
+}
+
+/// Loads the plugin at `path`: a WebAssembly module if it ends in `.wasm`, a shared
+/// library otherwise
+pub fn load(path: &Path) -> Result<Arc<dyn TextPlugin>, String> {
+    let is_wasm = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("wasm"));
+    let plugin: Result<Arc<dyn TextPlugin>, String> = if is_wasm {
+        load_wasm(path)
+    } else {
+        load_native(path)
+    };
+    plugin.map_err(|e| format!("failed to load plugin {}: {}", path.display(), e))
+}
+
+#[cfg(feature = "plugins")]
+fn load_native(path: &Path) -> Result<Arc<dyn TextPlugin>, String> {
+    Ok(Arc::new(native::NativePlugin::load(path)?))
+}
+
+#[cfg(not(feature = "plugins"))]
+fn load_native(_path: &Path) -> Result<Arc<dyn TextPlugin>, String> {
+    Err("built without shared library plugins (the `plugins` feature)".to_string())
+}
+
+#[cfg(feature = "wasm-plugins")]
+fn load_wasm(path: &Path) -> Result<Arc<dyn TextPlugin>, String> {
+    Ok(Arc::new(wasm::WasmPlugin::load(path)?))
+}
+
+#[cfg(not(feature = "wasm-plugins"))]
+fn load_wasm(_path: &Path) -> Result<Arc<dyn TextPlugin>, String> {
+    Err("built without WebAssembly plugins (the `wasm-plugins` feature)".to_string())
+}
+
+#[cfg(feature = "plugins")]
+mod native {
+    use std::ffi::{CStr, CString, c_char};
+    use std::fmt;
+    use std::path::{Path, PathBuf};
+
+    use super::TextPlugin;
+
+    type ProcessFn = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+    type FreeFn = unsafe extern "C" fn(*mut c_char);
+
+    pub struct NativePlugin {
+        path: PathBuf,
+        process: ProcessFn,
+        free: FreeFn,
+        // Keeps the functions above loaded
+        _library: libloading::Library,
+    }
+
+    impl NativePlugin {
+        pub fn load(path: &Path) -> Result<Self, String> {
+            // Safety: loading runs the library's initializers, which is what loading a
+            // plugin asks for
+            let library = unsafe { libloading::Library::new(path) }.map_err(|e| e.to_string())?;
+            // Safety: the types are the documented plugin ABI
+            let (process, free) = unsafe {
+                let process = *library
+                    .get::<ProcessFn>(b"kokoros_process\0")
+                    .map_err(|e| e.to_string())?;
+                let free = *library
+                    .get::<FreeFn>(b"kokoros_free\0")
+                    .map_err(|e| e.to_string())?;
+                (process, free)
+            };
+            Ok(Self {
+                path: path.to_path_buf(),
+                process,
+                free,
+                _library: library,
+            })
+        }
+    }
+
+    impl fmt::Debug for NativePlugin {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("NativePlugin")
+                .field("path", &self.path)
+                .finish()
+        }
+    }
+
+    impl TextPlugin for NativePlugin {
+        fn process(&self, text: &str) -> Result<String, String> {
+            let input = CString::new(text).map_err(|_| "text contains a NUL byte".to_string())?;
+            // Safety: `input` is NUL-terminated and outlives the call, and the output is
+            // only read before being handed back to `free`
+            unsafe {
+                let output = (self.process)(input.as_ptr());
+                if output.is_null() {
+                    return Err(format!("{} failed", self.path.display()));
+                }
+                let processed = CStr::from_ptr(output).to_str().map(str::to_string);
+                (self.free)(output);
+                processed.map_err(|_| format!("{} returned invalid UTF-8", self.path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+mod wasm {
+    use std::fmt;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Mutex, PoisonError};
+
+    use wasmi::{Engine, Instance, Linker, Module, Store};
+
+    use super::TextPlugin;
+
+    /// A module instance, which runs one call at a time
+    pub struct WasmPlugin {
+        path: PathBuf,
+        instance: Mutex<(Store<()>, Instance)>,
+    }
+
+    impl WasmPlugin {
+        pub fn load(path: &Path) -> Result<Self, String> {
+            let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+            let engine = Engine::default();
+            let module = Module::new(&engine, &bytes[..]).map_err(|e| e.to_string())?;
+            let mut store = Store::new(&engine, ());
+            let instance = Linker::<()>::new(&engine)
+                .instantiate_and_start(&mut store, &module)
+                .map_err(|e| e.to_string())?;
+            Ok(Self {
+                path: path.to_path_buf(),
+                instance: Mutex::new((store, instance)),
+            })
+        }
+    }
+
+    impl fmt::Debug for WasmPlugin {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("WasmPlugin")
+                .field("path", &self.path)
+                .finish()
+        }
+    }
+
+    impl TextPlugin for WasmPlugin {
+        fn process(&self, text: &str) -> Result<String, String> {
+            let mut instance = self.instance.lock().unwrap_or_else(PoisonError::into_inner);
+            let (store, instance) = &mut *instance;
+            let memory = instance
+                .get_memory(&*store, "memory")
+                .ok_or("no `memory` export")?;
+            let alloc = instance
+                .get_typed_func::<i32, i32>(&*store, "alloc")
+                .map_err(|e| e.to_string())?;
+            let process = instance
+                .get_typed_func::<(i32, i32), i64>(&*store, "process")
+                .map_err(|e| e.to_string())?;
+
+            let len = i32::try_from(text.len()).map_err(|_| "text too long".to_string())?;
+            let ptr = alloc.call(&mut *store, len).map_err(|e| e.to_string())?;
+            memory
+                .write(&mut *store, ptr as u32 as usize, text.as_bytes())
+                .map_err(|e| e.to_string())?;
+            let output = process
+                .call(&mut *store, (ptr, len))
+                .map_err(|e| e.to_string())? as u64;
+            let (output_ptr, output_len) = ((output >> 32) as u32, output as u32);
+            let mut processed = vec![0; output_len as usize];
+            memory
+                .read(&*store, output_ptr as usize, &mut processed)
+                .map_err(|e| e.to_string())?;
+            if let Ok(dealloc) = instance.get_typed_func::<(i32, i32), ()>(&*store, "dealloc") {
+                for (ptr, len) in [(ptr, len), (output_ptr as i32, output_len as i32)] {
+                    dealloc
+                        .call(&mut *store, (ptr, len))
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            String::from_utf8(processed)
+                .map_err(|_| format!("{} returned invalid UTF-8", self.path.display()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_errors() {
+        let error = load(Path::new("missing/cleanup.wasm")).unwrap_err();
+        assert!(error.starts_with("failed to load plugin missing/cleanup.wasm: "));
+        assert!(load(Path::new("missing/libcleanup.so")).is_err());
+    }
+}
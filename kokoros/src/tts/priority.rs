@@ -0,0 +1,280 @@
+//! Priority classes for inference. Requests sharing a model take turns chunk by chunk on
+//! its sessions, and whenever an interactive request is waiting for its turn it goes
+//! before batch work, so a live assistant query isn't stuck behind a long audiobook on the
+//! same instance. A model loaded on several GPUs spreads the chunks over them by its
+//! [`Balance`].
+
+use std::sync::{Condvar, Mutex, PoisonError};
+
+use serde::Deserialize;
+
+/// How urgently a request's audio is wanted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Someone is waiting to hear it
+    #[default]
+    Interactive,
+    /// Long jobs nobody is listening to live, which yield to interactive requests between
+    /// chunks
+    Batch,
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "interactive" => Ok(Priority::Interactive),
+            "batch" => Ok(Priority::Batch),
+            _ => Err(format!(
+                "unknown priority {:?}, expected interactive or batch",
+                s
+            )),
+        }
+    }
+}
+
+/// Which free session the next chunk goes to, when several are free
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Balance {
+    /// The sessions in turn, so consecutive chunks go to different devices
+    #[default]
+    RoundRobin,
+    /// A session on the device running the fewest chunks, in turn among those
+    LeastLoaded,
+}
+
+impl std::str::FromStr for Balance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "round-robin" => Ok(Balance::RoundRobin),
+            "least-loaded" => Ok(Balance::LeastLoaded),
+            _ => Err(format!(
+                "unknown balance {:?}, expected round-robin or least-loaded",
+                s
+            )),
+        }
+    }
+}
+
+struct State {
+    /// Sessions no chunk is being inferred on, by index
+    free: Vec<usize>,
+    sessions: usize,
+    /// Device of each session, as an index into the devices the model is loaded on
+    devices: Vec<usize>,
+    balance: Balance,
+    /// Session after the one handed out last
+    next: usize,
+    /// Interactive requests waiting for their turn
+    interactive_waiting: usize,
+    /// Requests of either priority waiting for their turn
+    waiting: usize,
+}
+
+impl State {
+    /// Takes the free session the next chunk goes to by `balance`
+    fn take_free(&mut self) -> usize {
+        let sessions = self.sessions;
+        let turn = |session: usize| (session + sessions - self.next) % sessions;
+        let busy = |device: usize| {
+            (0..sessions)
+                .filter(|s| self.devices[*s] == device && !self.free.contains(s))
+                .count()
+        };
+        let (position, &session) = self
+            .free
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &session)| match self.balance {
+                Balance::RoundRobin => (0, turn(session)),
+                Balance::LeastLoaded => (busy(self.devices[session]), turn(session)),
+            })
+            .expect("waited for a free session");
+        self.free.swap_remove(position);
+        self.next = (session + 1) % sessions;
+        session
+    }
+}
+
+/// Turns on the sessions of one model, one chunk at a time on each
+pub(crate) struct ModelQueue {
+    state: Mutex<State>,
+    turn: Condvar,
+}
+
+/// Gives the session back when dropped
+pub(crate) struct Turn<'a> {
+    queue: &'a ModelQueue,
+    session: usize,
+}
+
+impl Turn<'_> {
+    /// Index of the session it is this chunk's turn on
+    pub(crate) fn session(&self) -> usize {
+        self.session
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        let mut state = self.queue.lock();
+        state.free.push(self.session);
+        drop(state);
+        self.queue.turn.notify_all();
+    }
+}
+
+impl Default for ModelQueue {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl ModelQueue {
+    /// A queue for a model loaded into `sessions` sessions on one device
+    pub(crate) fn new(sessions: usize) -> Self {
+        Self::balanced(vec![0; sessions.max(1)], Balance::default())
+    }
+
+    /// A queue for a model loaded into one session per entry of `devices`, each the index
+    /// of the device that session runs on
+    pub(crate) fn balanced(devices: Vec<usize>, balance: Balance) -> Self {
+        let devices = if devices.is_empty() { vec![0] } else { devices };
+        Self {
+            state: Mutex::new(State {
+                free: (0..devices.len()).collect(),
+                sessions: devices.len(),
+                devices,
+                balance,
+                next: 0,
+                interactive_waiting: 0,
+                waiting: 0,
+            }),
+            turn: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Chunks being inferred or waiting for their turn
+    pub(crate) fn depth(&self) -> usize {
+        let state = self.lock();
+        state.waiting + state.sessions - state.free.len()
+    }
+
+    /// Waits until it is `priority`'s turn on one of the sessions
+    pub(crate) fn wait(&self, priority: Priority) -> Turn<'_> {
+        let mut state = self.lock();
+        state.waiting += 1;
+        if priority == Priority::Interactive {
+            state.interactive_waiting += 1;
+        }
+        let must_wait = |state: &mut State| {
+            state.free.is_empty() || (priority == Priority::Batch && state.interactive_waiting > 0)
+        };
+        let mut state = self
+            .turn
+            .wait_while(state, must_wait)
+            .unwrap_or_else(PoisonError::into_inner);
+        state.waiting -= 1;
+        if priority == Priority::Interactive {
+            state.interactive_waiting -= 1;
+        }
+        let session = state.take_free();
+        // Batch work may go ahead now, on a session that is still free
+        if priority == Priority::Interactive && !state.free.is_empty() {
+            self.turn.notify_all();
+        }
+        Turn {
+            queue: self,
+            session,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_interactive_goes_first() {
+        let queue = Arc::new(ModelQueue::default());
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let turn = queue.wait(Priority::Batch);
+
+        // A batch chunk queues up first, then an interactive one
+        let spawn = |priority: Priority| {
+            let (queue, order) = (Arc::clone(&queue), Arc::clone(&order));
+            std::thread::spawn(move || {
+                let _turn = queue.wait(priority);
+                order.lock().unwrap().push(priority);
+            })
+        };
+        let batch = spawn(Priority::Batch);
+        std::thread::sleep(Duration::from_millis(50));
+        let interactive = spawn(Priority::Interactive);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.depth(), 3);
+
+        drop(turn);
+        batch.join().unwrap();
+        interactive.join().unwrap();
+        assert_eq!(
+            *order.lock().unwrap(),
+            [Priority::Interactive, Priority::Batch]
+        );
+        assert_eq!(queue.depth(), 0);
+        assert_eq!("batch".parse(), Ok(Priority::Batch));
+
+        // With two sessions, two chunks go at once
+        let queue = ModelQueue::new(2);
+        let first = queue.wait(Priority::Batch);
+        let second = queue.wait(Priority::Batch);
+        assert_ne!(first.session(), second.session());
+        assert_eq!(queue.depth(), 2);
+
+        // Two sessions on each of two devices, the first of device 0 busy: round-robin
+        // goes on to the next session, on the same device, least-loaded to the other one
+        let queue = ModelQueue::balanced(vec![0, 0, 1, 1], Balance::RoundRobin);
+        let _first = queue.wait(Priority::Batch);
+        assert_eq!(queue.wait(Priority::Batch).session(), 1);
+        let queue = ModelQueue::balanced(vec![0, 0, 1, 1], Balance::LeastLoaded);
+        let _first = queue.wait(Priority::Batch);
+        assert_eq!(queue.wait(Priority::Batch).session(), 2);
+        assert_eq!("least_loaded".parse(), Ok(Balance::LeastLoaded));
+    }
+
+    #[test]
+    fn test_balance_order() {
+        // Sessions 0 and 2 on device 0, 1 and 3 on device 1
+        for balance in [Balance::RoundRobin, Balance::LeastLoaded] {
+            let queue = ModelQueue::balanced(vec![0, 1, 0, 1], balance);
+            let turns: Vec<Turn> = (0..4).map(|_| queue.wait(Priority::Batch)).collect();
+            let sessions: Vec<usize> = turns.iter().map(Turn::session).collect();
+            assert_eq!(sessions, [0, 1, 2, 3], "{:?}", balance);
+            drop(turns);
+            // Back to the first once all are given back
+            assert_eq!(queue.wait(Priority::Batch).session(), 0, "{:?}", balance);
+        }
+
+        // Session 0 busy and 1 just given back: round-robin goes on to session 2, on the
+        // busy device, least-loaded to the idle device, at 3 as it comes first in turn
+        let next_after_release = |balance| {
+            let queue = ModelQueue::balanced(vec![0, 1, 0, 1], balance);
+            let _first = queue.wait(Priority::Batch);
+            drop(queue.wait(Priority::Batch));
+            queue.wait(Priority::Batch).session()
+        };
+        assert_eq!(next_after_release(Balance::RoundRobin), 2);
+        assert_eq!(next_after_release(Balance::LeastLoaded), 3);
+    }
+}
@@ -0,0 +1,145 @@
+//! Inline pronunciation overrides, written as in misaki: `[Kokoro](/kˈOkəɹO/)` speaks the
+//! bracketed text with the phonemes between the slashes instead of asking espeak, for that
+//! one occurrence, without editing a lexicon.
+
+use std::borrow::Cow;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// `[text](/phonemes/)`, capturing the text and the phonemes
+pub(crate) const OVERRIDE_PATTERN: &str = r"\[([^\[\]]+)\]\(/([^/()]+)/\)";
+
+lazy_static! {
+    static ref OVERRIDE_RE: Regex = Regex::new(OVERRIDE_PATTERN).unwrap();
+}
+
+/// A run of text for espeak, or phonemes given inline
+#[derive(Debug, PartialEq, Eq)]
+pub enum Piece<'a> {
+    Text(&'a str),
+    Phonemes(&'a str),
+}
+
+/// Whether `text` has any overrides, which is rare enough to keep the plain path cheap
+pub fn has_overrides(text: &str) -> bool {
+    text.contains("](/") && OVERRIDE_RE.is_match(text)
+}
+
+/// `text` split into runs of text and overridden phonemes, in order
+pub fn pieces(text: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for captures in OVERRIDE_RE.captures_iter(text) {
+        let whole = captures.get(0).unwrap();
+        if whole.start() > start {
+            pieces.push(Piece::Text(&text[start..whole.start()]));
+        }
+        pieces.push(Piece::Phonemes(captures.get(2).unwrap().as_str().trim()));
+        start = whole.end();
+    }
+    if start < text.len() {
+        pieces.push(Piece::Text(&text[start..]));
+    }
+    pieces
+}
+
+/// Phonemes for `text`, from `phonemize` for the runs of text and as given for overrides.
+/// Runs that were separated by whitespace in the text are separated by a space.
+pub fn phonemize_with<E>(
+    text: &str,
+    mut phonemize: impl FnMut(&str) -> Result<String, E>,
+) -> Result<String, E> {
+    let mut phonemes = String::new();
+    let mut space_before = false;
+    for piece in pieces(text) {
+        let (part, space_after) = match piece {
+            Piece::Text(text) => {
+                space_before |= text.starts_with(char::is_whitespace);
+                let part = if text.trim().is_empty() {
+                    String::new()
+                } else {
+                    phonemize(text.trim())?
+                };
+                (part, text.ends_with(char::is_whitespace))
+            }
+            Piece::Phonemes(part) => (part.to_string(), false),
+        };
+        if !part.is_empty() {
+            if space_before && !phonemes.is_empty() {
+                phonemes.push(' ');
+            }
+            phonemes.push_str(&part);
+            space_before = space_after;
+        }
+    }
+    Ok(phonemes)
+}
+
+/// `text` as it reads, with each override replaced by its bracketed text, e.g. for word
+/// timestamps
+pub fn spoken(text: &str) -> Cow<'_, str> {
+    OVERRIDE_RE.replace_all(text, "$1")
+}
+
+/// Splits `text` at whitespace like `split_whitespace`, but keeps each override in one
+/// piece even if its text or phonemes have spaces
+pub fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut overrides = OVERRIDE_RE.find_iter(text).peekable();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if let Some(found) = overrides.next_if(|found| found.start() == i) {
+            start.get_or_insert(i);
+            // Skip to the end of the override
+            for _ in text[i..found.end()].chars().skip(1) {
+                chars.next();
+            }
+        } else if c.is_whitespace() {
+            if let Some(start) = start.take() {
+                words.push(&text[start..i]);
+            }
+        } else {
+            start.get_or_insert(i);
+        }
+    }
+    if let Some(start) = start {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        let text = "Say [Kokoro](/kˈOkəɹO/) twice, [New York](/nˈu jˈɔɹk/)'s.";
+        assert!(has_overrides(text));
+        assert!(!has_overrides("[a link](https://example.com)"));
+        assert_eq!(
+            pieces(text),
+            [
+                Piece::Text("Say "),
+                Piece::Phonemes("kˈOkəɹO"),
+                Piece::Text(" twice, "),
+                Piece::Phonemes("nˈu jˈɔɹk"),
+                Piece::Text("'s."),
+            ]
+        );
+        let phonemes = phonemize_with(text, |text| Ok::<_, ()>(format!("<{}>", text))).unwrap();
+        assert_eq!(phonemes, "<Say> kˈOkəɹO <twice,> nˈu jˈɔɹk<'s.>");
+        assert_eq!(spoken(text), "Say Kokoro twice, New York's.");
+        assert_eq!(
+            split_words(text),
+            [
+                "Say",
+                "[Kokoro](/kˈOkəɹO/)",
+                "twice,",
+                "[New York](/nˈu jˈɔɹk/)'s."
+            ]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Read-along documents: the text of a recording with its sentences and words placed in
+//! the text and in the audio, so a web reader can highlight along with playback.
+//!
+//! The schema is versioned and only grows: fields are added, never renamed or removed.
+//! Positions in the text are in characters (Unicode scalar values), end exclusive, and
+//! times are in seconds from the start of the audio.
+
+use serde::Serialize;
+
+use crate::tts::chunker;
+
+/// Version of the schema written in [`ReadAlong::version`]
+pub const VERSION: u32 = 1;
+
+/// How far past the last word to look for the next one, in characters. Words that were
+/// spoken differently from how they are written (numbers, abbreviations) aren't found,
+/// and this keeps them from matching a later occurrence.
+const LOOKAHEAD_CHARS: usize = 80;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReadAlong {
+    pub version: u32,
+    pub text: String,
+    pub duration_sec: f32,
+    pub sentences: Vec<Sentence>,
+    pub words: Vec<Word>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Sentence {
+    pub text: String,
+    pub start_char: usize,
+    pub end_char: usize,
+    pub start_sec: f32,
+    pub end_sec: f32,
+    /// Index in [`ReadAlong::words`] of the sentence's first word
+    pub first_word: usize,
+    pub word_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Word {
+    /// The word as it was spoken
+    pub text: String,
+    /// Where the word is in the text, if it could be found there
+    pub start_char: Option<usize>,
+    pub end_char: Option<usize>,
+    pub start_sec: f32,
+    pub end_sec: f32,
+    /// Index in [`ReadAlong::sentences`] of the sentence the word belongs to
+    pub sentence: usize,
+}
+
+/// Whether `text` at byte `at` starts with `word`, ignoring case; the length in bytes of
+/// the match if so
+fn match_at(text: &str, at: usize, word: &str) -> Option<usize> {
+    let mut rest = text[at..].chars();
+    let mut len = 0;
+    for expected in word.chars() {
+        let found = rest.next()?;
+        if !found.to_lowercase().eq(expected.to_lowercase()) {
+            return None;
+        }
+        len += found.len_utf8();
+    }
+    Some(len)
+}
+
+/// Builds the read-along document of `text`, spoken as `words` (word, start and end in
+/// seconds, as the timestamped synthesis gives them) in audio `duration_sec` long
+pub fn read_along(text: &str, words: &[(String, f32, f32)], duration_sec: f32) -> ReadAlong {
+    let char_index = |byte: usize| text[..byte].chars().count();
+    // Byte ranges of the sentences, which are slices of `text`
+    let sentence_ranges: Vec<(usize, usize)> = chunker::sentences(text)
+        .into_iter()
+        .map(|s| {
+            let start = s.as_ptr() as usize - text.as_ptr() as usize;
+            (start, start + s.len())
+        })
+        .collect();
+    let sentence_of = |byte: usize| {
+        sentence_ranges
+            .iter()
+            .position(|&(_, end)| byte < end)
+            .unwrap_or(sentence_ranges.len().saturating_sub(1))
+    };
+
+    let mut placed = Vec::new();
+    let mut cursor = 0;
+    for (word, start_sec, end_sec) in words {
+        let token = word.trim_matches(|c: char| !c.is_alphanumeric());
+        // Pauses for punctuation come as words of their own
+        if token.is_empty() {
+            continue;
+        }
+        let found = text[cursor..]
+            .char_indices()
+            .take(LOOKAHEAD_CHARS)
+            .map(|(i, _)| cursor + i)
+            .find_map(|at| match_at(text, at, token).map(|len| (at, at + len)));
+        if let Some((_, end)) = found {
+            cursor = end;
+        }
+        placed.push((token, found, *start_sec, *end_sec));
+    }
+
+    let words: Vec<Word> = placed
+        .iter()
+        .scan(0, |last_sentence, &(token, found, start_sec, end_sec)| {
+            // A word not found in the text belongs with the one before it
+            let sentence = found.map_or(*last_sentence, |(start, _)| sentence_of(start));
+            *last_sentence = sentence;
+            Some(Word {
+                text: token.to_string(),
+                start_char: found.map(|(start, _)| char_index(start)),
+                end_char: found.map(|(_, end)| char_index(end)),
+                start_sec,
+                end_sec,
+                sentence,
+            })
+        })
+        .collect();
+
+    let mut last_end_sec = 0.0;
+    let sentences = sentence_ranges
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, end))| {
+            let first_word = words
+                .iter()
+                .position(|w| w.sentence >= i)
+                .unwrap_or(words.len());
+            let word_count = words[first_word..]
+                .iter()
+                .take_while(|w| w.sentence == i)
+                .count();
+            let spoken = &words[first_word..first_word + word_count];
+            let start_sec = spoken.first().map_or(last_end_sec, |w| w.start_sec);
+            let end_sec = spoken.last().map_or(start_sec, |w| w.end_sec);
+            last_end_sec = end_sec;
+            Sentence {
+                text: text[start..end].to_string(),
+                start_char: char_index(start),
+                end_char: char_index(end),
+                start_sec,
+                end_sec,
+                first_word,
+                word_count,
+            }
+        })
+        .collect();
+
+    ReadAlong {
+        version: VERSION,
+        text: text.to_string(),
+        duration_sec,
+        sentences,
+        words,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_along() {
+        let text = "Hello, wörld! It costs $5 today.";
+        let words: Vec<(String, f32, f32)> = [
+            ("Hello", 0.0, 0.4),
+            (",", 0.4, 0.55),
+            ("world", 0.55, 1.0),
+            ("wörld", 0.55, 1.0),
+            ("!", 1.0, 1.3),
+            ("it", 1.3, 1.4),
+            ("costs", 1.4, 1.8),
+            ("five", 1.8, 2.0),
+            ("dollars", 2.0, 2.4),
+            ("today", 2.4, 2.8),
+        ]
+        .iter()
+        .map(|&(w, s, e)| (w.to_string(), s, e))
+        .collect();
+        let doc = read_along(text, &words, 3.0);
+
+        assert_eq!(doc.sentences.len(), 2);
+        assert_eq!(doc.sentences[1].text, "It costs $5 today.");
+        assert_eq!(doc.sentences[1].start_char, 14);
+        assert_eq!(doc.sentences[1].start_sec, 1.3);
+        assert_eq!(doc.sentences[0].word_count, 3);
+
+        assert_eq!(doc.words[0].end_char, Some(5));
+        // Not in the text as spoken
+        assert_eq!(doc.words[1].start_char, None);
+        assert_eq!(
+            (doc.words[2].start_char, doc.words[2].end_char),
+            (Some(7), Some(12))
+        );
+        assert_eq!(doc.words[3].start_char, Some(14));
+        assert_eq!(doc.words[5].sentence, 1);
+        assert_eq!(doc.words[7].start_char, Some(26));
+        assert!(
+            serde_json::to_string(&doc)
+                .unwrap()
+                .contains("\"version\":1")
+        );
+    }
+}
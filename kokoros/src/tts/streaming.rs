@@ -0,0 +1,290 @@
+//! Speech from text that arrives in pieces, e.g. the tokens of an LLM's answer. Text is
+//! buffered until it holds a complete sentence, which is spoken right away, so audio
+//! starts while the rest of the text is still being generated.
+//!
+//! Synthesis happens inside [`StreamingSynthesizer::push_str`], which returns once the
+//! sentences it completed are spoken; async callers run the synthesizer on a blocking
+//! thread and feed it through a channel.
+//!
+//! When buffered text is spoken is set by a [`FlushPolicy`]: waiting for whole sentences
+//! gives the most natural prosody, while flushing at clauses or after a timeout gets audio
+//! out sooner.
+
+use std::time::{Duration, Instant};
+
+use crate::error::KokorosError;
+use crate::tts::chunker::{clauses, offset_in, sentences};
+use crate::tts::koko::TTSKoko;
+
+/// Buffered text longer than this is spoken up to its last clause break even without a
+/// complete sentence, so run-on text doesn't hold back the audio indefinitely
+pub const MAX_PENDING_CHARS: usize = 400;
+
+/// The rules for when buffered text is handed to synthesis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlushPolicy {
+    /// Speak at clause breaks (commas, semicolons, colons, dashes) as well as at the ends
+    /// of sentences: audio starts sooner, but clauses spoken on their own sound choppier
+    pub at_clauses: bool,
+    /// Text buffered beyond this many characters is spoken up to its last clause break,
+    /// or else its last word, without waiting for a boundary
+    pub max_pending_chars: usize,
+    /// Text buffered for longer than this is spoken up to its last complete word, for
+    /// sources that can stall mid-sentence. Checked whenever text is pushed, and by
+    /// [`StreamingSynthesizer::tick`] in between.
+    pub max_wait: Option<Duration>,
+}
+
+impl Default for FlushPolicy {
+    /// Whole sentences, up to [`MAX_PENDING_CHARS`], however long they take to arrive
+    fn default() -> Self {
+        Self {
+            at_clauses: false,
+            max_pending_chars: MAX_PENDING_CHARS,
+            max_wait: None,
+        }
+    }
+}
+
+/// How much of `pending` is complete sentences, in bytes. A sentence is only complete once
+/// whitespace follows its terminator, as the next fragment could still turn "3." into
+/// "3.5" or "Hello" into "Hello world".
+fn complete_prefix(pending: &str) -> usize {
+    complete_prefix_by(pending, sentences)
+}
+
+/// How much of `pending` is complete sentences and clauses, in bytes
+fn clause_prefix(pending: &str) -> usize {
+    let end = complete_prefix(pending);
+    end + complete_prefix_by(&pending[end..], clauses)
+}
+
+/// How much of `pending` is complete pieces as `split` cuts it
+fn complete_prefix_by(pending: &str, split: fn(&str) -> Vec<&str>) -> usize {
+    // Whatever follows the whitespace starts a new piece only if the piece before it is
+    // complete
+    let probe = format!("{}x", pending);
+    let pieces = split(&probe);
+    match pieces.len() {
+        0 | 1 => 0,
+        n => {
+            let last = pieces[n - 2];
+            offset_in(&probe, last) + last.len()
+        }
+    }
+}
+
+/// How much of `pending` to speak when it has grown too long without finishing a sentence:
+/// everything up to its last clause, or else its last word
+fn overflow_prefix(pending: &str) -> usize {
+    let pieces = clauses(pending);
+    if pieces.len() > 1 {
+        return offset_in(pending, pieces[pieces.len() - 1]);
+    }
+    pending.trim_end().rfind(char::is_whitespace).unwrap_or(0)
+}
+
+/// How much of `pending` is complete words, when it has waited too long for a boundary
+fn wait_prefix(pending: &str) -> usize {
+    if pending.ends_with(char::is_whitespace) {
+        return pending.len();
+    }
+    pending.rfind(char::is_whitespace).unwrap_or(0)
+}
+
+/// How much of `pending` to speak under `policy`, `due` once it has waited `max_wait`
+fn flush_prefix(pending: &str, policy: &FlushPolicy, due: bool) -> usize {
+    let mut end = if policy.at_clauses {
+        clause_prefix(pending)
+    } else {
+        complete_prefix(pending)
+    };
+    if end == 0 && pending.chars().count() > policy.max_pending_chars {
+        end = overflow_prefix(pending);
+    }
+    if end == 0 && due {
+        end = wait_prefix(pending);
+    }
+    end
+}
+
+/// Voices text pushed in fragments, sentence by sentence, handing each chunk of audio to
+/// `on_audio` as soon as it is synthesized
+pub struct StreamingSynthesizer<F> {
+    tts: TTSKoko,
+    lan: String,
+    style: String,
+    speed: f32,
+    policy: FlushPolicy,
+    pending: String,
+    /// When the oldest text in `pending` arrived
+    pending_since: Option<Instant>,
+    on_audio: F,
+}
+
+impl<F> StreamingSynthesizer<F>
+where
+    F: FnMut(Vec<f32>) -> Result<(), Box<dyn std::error::Error>>,
+{
+    pub fn new(tts: TTSKoko, lan: &str, style: &str, speed: f32, on_audio: F) -> Self {
+        Self {
+            tts,
+            lan: lan.to_string(),
+            style: style.to_string(),
+            speed,
+            policy: FlushPolicy::default(),
+            pending: String::new(),
+            pending_since: None,
+            on_audio,
+        }
+    }
+
+    /// The synthesizer with `policy` deciding when buffered text is spoken
+    pub fn with_policy(mut self, policy: FlushPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Adds `fragment` to the text and speaks what it completes
+    pub fn push_str(&mut self, fragment: &str) -> Result<(), KokorosError> {
+        if self.pending.is_empty() && !fragment.is_empty() {
+            self.pending_since = Some(Instant::now());
+        }
+        self.pending.push_str(fragment);
+        let due = self.due_in() == Some(Duration::ZERO);
+        self.speak_prefix(flush_prefix(&self.pending, &self.policy, due))
+    }
+
+    /// Speaks what has waited longer than the policy's `max_wait`, if anything. Callers
+    /// whose source can stall call this when [`StreamingSynthesizer::due_in`] has passed
+    /// without new text.
+    pub fn tick(&mut self) -> Result<(), KokorosError> {
+        if self.due_in() != Some(Duration::ZERO) {
+            return Ok(());
+        }
+        self.speak_prefix(wait_prefix(&self.pending))
+    }
+
+    /// How long until buffered text has waited `max_wait`, or `None` if nothing will be
+    /// due without more text
+    pub fn due_in(&self) -> Option<Duration> {
+        let (max_wait, since) = (self.policy.max_wait?, self.pending_since?);
+        Some(max_wait.saturating_sub(since.elapsed()))
+    }
+
+    /// Speaks whatever text is buffered, complete sentence or not, e.g. at the end of an
+    /// LLM's turn
+    pub fn flush(&mut self) -> Result<(), KokorosError> {
+        self.speak_prefix(self.pending.len())
+    }
+
+    /// Speaks the rest of the text and ends the stream
+    pub fn close(mut self) -> Result<(), KokorosError> {
+        self.flush()
+    }
+
+    /// Text pushed but not spoken yet
+    pub fn pending(&self) -> &str {
+        &self.pending
+    }
+
+    /// Speaks the first `end` bytes of the buffered text
+    fn speak_prefix(&mut self, end: usize) -> Result<(), KokorosError> {
+        if end == 0 {
+            return Ok(());
+        }
+        let text: String = self.pending.drain(..end).collect();
+        // What is left starts a new piece, which has only just begun to wait
+        self.pending_since = (!self.pending.is_empty()).then(Instant::now);
+        self.speak(&text)
+    }
+
+    fn speak(&mut self, text: &str) -> Result<(), KokorosError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+        tracing::debug!("Speaking {} characters", text.chars().count());
+        self.tts.tts_raw_audio_streaming(
+            text,
+            &self.lan,
+            &self.style,
+            self.speed,
+            None,
+            None,
+            None,
+            None,
+            &mut self.on_audio,
+        )?;
+        // Later text is synthesized while earlier audio plays, so only the first sentence
+        // needs a short start
+        self.tts = self.tts.with_first_chunk_tokens(None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_prefix() {
+        assert_eq!(complete_prefix("Hello"), 0);
+        assert_eq!(complete_prefix("It costs 3."), 0);
+        assert_eq!(complete_prefix("Hello there. "), "Hello there.".len());
+        assert_eq!(complete_prefix("One. Two! Thr"), "One. Two!".len());
+        assert_eq!(complete_prefix("Ask Dr. "), 0);
+    }
+
+    #[test]
+    fn test_clause_prefix() {
+        assert_eq!(clause_prefix("Well, I"), "Well,".len());
+        assert_eq!(clause_prefix("Yes. And, so"), "Yes. And,".len());
+        assert_eq!(clause_prefix("Yes. And"), "Yes.".len());
+        assert_eq!(clause_prefix("First; then: last"), "First; then:".len());
+        // Not complete until whitespace follows the break
+        assert_eq!(clause_prefix("Well,"), 0);
+
+        let clauses = FlushPolicy {
+            at_clauses: true,
+            ..FlushPolicy::default()
+        };
+        assert_eq!(flush_prefix("Well, I", &clauses, false), "Well,".len());
+        assert_eq!(flush_prefix("Well, I", &FlushPolicy::default(), false), 0);
+    }
+
+    #[test]
+    fn test_overflow_prefix() {
+        let run_on = "and then, after a while the";
+        assert_eq!(&run_on[..overflow_prefix(run_on)], "and then, ");
+        let words = "and then after";
+        assert_eq!(&words[..overflow_prefix(words)], "and then");
+        assert_eq!(overflow_prefix("unbroken"), 0);
+
+        let short = FlushPolicy {
+            max_pending_chars: 10,
+            ..FlushPolicy::default()
+        };
+        assert_eq!(&run_on[..flush_prefix(run_on, &short, false)], "and then, ");
+        assert_eq!(&words[..flush_prefix(words, &short, false)], "and then");
+        // At or under the limit, text waits for its sentence
+        assert_eq!(flush_prefix("and then", &short, false), 0);
+    }
+
+    #[test]
+    fn test_wait_prefix() {
+        assert_eq!(wait_prefix("the weather tod"), "the weather".len());
+        assert_eq!(wait_prefix("the weather "), "the weather ".len());
+        assert_eq!(wait_prefix("the weather\n"), "the weather\n".len());
+        assert_eq!(wait_prefix("weather"), 0);
+
+        let policy = FlushPolicy::default();
+        assert_eq!(flush_prefix("the weather ", &policy, false), 0);
+        assert_eq!(
+            flush_prefix("the weather ", &policy, true),
+            "the weather ".len()
+        );
+        // A complete sentence is spoken on its own even when the rest is due
+        assert_eq!(flush_prefix("Hi. the wea", &policy, true), "Hi.".len());
+    }
+}
@@ -0,0 +1,338 @@
+//! `[stretch:1.5]word[/stretch]` in text to synthesize: the durations the model predicts
+//! for the tokens between the tags are scaled by the factor, 1.5 times as long here, for
+//! emphasis, or shorter below 1 to tighten a phrase.
+//!
+//! The model is one graph from tokens to audio, so its durations can't be changed before
+//! the decoder from outside. With a timestamped model the sentence is inferred once, and
+//! the audio of the marked tokens, which the durations place, is time-stretched (WSOLA,
+//! keeping the pitch) and their durations scaled to match, so word timings follow. The
+//! standard model gives no durations, so there the marked text is inferred as a run of
+//! its own at the speed divided by the factor.
+
+use std::ops::Range;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref STRETCH_RE: Regex =
+        Regex::new(r"(?i)\[stretch:\s*(\d+(?:\.\d+)?)\s*\]|\[/stretch\]").unwrap();
+}
+
+/// Shortest and longest stretch, beyond which speech stops sounding like speech
+pub const STRETCH_RANGE: (f32, f32) = (0.25, 4.0);
+
+/// Where a stretch starts and ends in normalized text, until it is chunked; the factor of
+/// each start is kept apart, in order
+const OPEN: char = '\u{E000}';
+const CLOSE: char = '\u{E001}';
+
+/// WSOLA frames of 20 ms, overlapping by half, each placed within 5 ms of where it would
+/// fall at a steady rate to match the waveform before it, at 24 kHz
+const FRAME: usize = 480;
+const HOP: usize = FRAME / 2;
+const TOLERANCE: usize = 120;
+
+/// Splits `text` at its stretch tags into runs and the factor each is read at: 1 outside
+/// the tags, and kept within 0.25 and 4 inside them. An unclosed stretch runs to the end,
+/// a stray end tag is dropped, and runs of only whitespace are left out.
+pub fn split_stretches(text: &str) -> Vec<(&str, f32)> {
+    let mut runs = Vec::new();
+    let mut factor = 1.0;
+    let mut rest = 0;
+    for caps in STRETCH_RE.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        runs.push((&text[rest..whole.start()], factor));
+        rest = whole.end();
+        factor = caps.get(1).map_or(1.0, |f| {
+            f.as_str()
+                .parse::<f32>()
+                .unwrap_or(1.0)
+                .clamp(STRETCH_RANGE.0, STRETCH_RANGE.1)
+        });
+    }
+    runs.push((&text[rest..], factor));
+    runs.retain(|(run, _)| !run.trim().is_empty());
+    runs
+}
+
+/// Joins normalized runs into one text to chunk, marking the stretched ones, and returns
+/// it along with their factors. Punctuation closing a stretched run is put after its end
+/// mark, so that sentences still end there for the chunker.
+pub(crate) fn mark(runs: &[(String, f32)]) -> (String, Vec<f32>) {
+    let mut text = String::new();
+    let mut factors = Vec::new();
+    for (run, factor) in runs {
+        if *factor == 1.0 {
+            text.push_str(run);
+            continue;
+        }
+        let run = run.trim();
+        let core = run.trim_end_matches(|c: char| !c.is_alphanumeric());
+        text.push(' ');
+        text.push(OPEN);
+        text.push_str(core);
+        text.push(CLOSE);
+        text.push_str(&run[core.len()..]);
+        text.push(' ');
+        factors.push(*factor);
+    }
+    (text, factors)
+}
+
+/// A chunk of text to synthesize, with its stretched spans
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Chunk {
+    pub text: String,
+    /// Factor for the whole chunk, when it is a stretched run of its own
+    pub factor: f32,
+    /// Byte ranges of `text` to stretch, and by how much
+    pub spans: Vec<(Range<usize>, f32)>,
+}
+
+impl Chunk {
+    /// The runs of the chunk in and between its spans, each with its factor
+    pub fn runs(&self) -> Vec<(&str, f32)> {
+        let mut runs = Vec::new();
+        let mut rest = 0;
+        for (span, factor) in &self.spans {
+            runs.push((&self.text[rest..span.start], 1.0));
+            runs.push((&self.text[span.clone()], *factor));
+            rest = span.end;
+        }
+        runs.push((&self.text[rest..], 1.0));
+        runs.retain(|(run, _)| !run.trim().is_empty());
+        runs
+    }
+
+    /// The runs of the chunk as chunks of their own, each stretched as a whole
+    pub fn into_runs(self) -> Vec<Chunk> {
+        if self.spans.is_empty() {
+            return vec![self];
+        }
+        self.runs()
+            .into_iter()
+            .map(|(run, factor)| Chunk {
+                text: run.trim().to_string(),
+                factor,
+                spans: Vec::new(),
+            })
+            .collect()
+    }
+}
+
+/// Takes the marks [`mark`] put in out of `chunks`, as spans of each, with `factors` in
+/// order. A stretch the chunker split goes on into the next chunk.
+pub(crate) fn unmark(chunks: Vec<String>, factors: &[f32]) -> Vec<Chunk> {
+    let mut factors = factors.iter().copied();
+    let mut open = None;
+    chunks
+        .into_iter()
+        .map(|marked| {
+            let mut text = String::with_capacity(marked.len());
+            let mut spans = Vec::new();
+            let mut start = open.map(|factor| (0, factor));
+            for c in marked.chars() {
+                match c {
+                    OPEN => start = Some((text.len(), factors.next().unwrap_or(1.0))),
+                    CLOSE => {
+                        if let Some((from, factor)) = start.take() {
+                            spans.push((from..text.len(), factor));
+                        }
+                    }
+                    c => text.push(c),
+                }
+            }
+            open = start.map(|(_, factor)| factor);
+            if let Some((from, factor)) = start {
+                spans.push((from..text.len(), factor));
+            }
+            // Spans cover whole words, without the space around them
+            spans = spans
+                .into_iter()
+                .filter_map(|(span, factor)| {
+                    let inner = &text[span.clone()];
+                    let trimmed = inner.trim();
+                    let from = span.start + (inner.len() - inner.trim_start().len());
+                    (!trimmed.is_empty() && factor != 1.0)
+                        .then(|| (from..from + trimmed.len(), factor))
+                })
+                .collect();
+            Chunk {
+                text,
+                factor: 1.0,
+                spans,
+            }
+        })
+        .collect()
+}
+
+/// Stretches the audio of each token whose scale isn't 1 by it, placing tokens in the
+/// audio by their `durations` in frames, and scales those durations to match
+pub(crate) fn apply(audio: &[f32], durations: &mut [f32], scales: &[f32]) -> Vec<f32> {
+    let total: f32 = durations.iter().sum();
+    if total <= 0.0 || scales.iter().all(|&scale| scale == 1.0) {
+        return audio.to_vec();
+    }
+    let samples_per_frame = audio.len() as f32 / total;
+    let mut out = Vec::with_capacity(audio.len());
+    let mut frames = 0.0;
+    let mut start = 0;
+    let mut token = 0;
+    while token < durations.len() {
+        let scale = scales.get(token).copied().unwrap_or(1.0);
+        let mut end_token = token;
+        while end_token < durations.len() && scales.get(end_token).copied().unwrap_or(1.0) == scale
+        {
+            frames += durations[end_token];
+            durations[end_token] *= scale;
+            end_token += 1;
+        }
+        let end = ((frames * samples_per_frame).round() as usize).min(audio.len());
+        if scale == 1.0 {
+            out.extend_from_slice(&audio[start..end]);
+        } else {
+            out.extend(time_stretch(audio, start..end, scale));
+        }
+        start = end;
+        token = end_token;
+    }
+    out.extend_from_slice(&audio[start..]);
+    out
+}
+
+/// `range` of `audio` made `factor` times as long without changing its pitch, by
+/// overlapping and adding frames of it (WSOLA). Samples around the range are read for
+/// context, so that the stretched part joins the rest smoothly.
+fn time_stretch(audio: &[f32], range: Range<usize>, factor: f32) -> Vec<f32> {
+    let len = ((range.len() as f32) * factor).round() as usize;
+    let sample = |i: isize| -> f32 {
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| audio.get(i))
+            .copied()
+            .unwrap_or(0.0)
+    };
+    let window: Vec<f32> = (0..FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FRAME as f32).cos())
+        .collect();
+    let mut out = vec![0.0; len + FRAME];
+    let mut weight = vec![0.0; len + FRAME];
+    // Where in `audio` the previous frame was taken from
+    let mut previous: Option<isize> = None;
+    let mut at = 0;
+    while at < len {
+        let nominal = range.start as isize + (at as f32 / factor).round() as isize;
+        let from = match previous {
+            None => nominal,
+            Some(previous) => {
+                // The frame that best continues the waveform left by the previous one
+                let natural = previous + HOP as isize;
+                (-(TOLERANCE as isize)..=TOLERANCE as isize)
+                    .map(|delta| nominal + delta)
+                    .max_by(|&a, &b| {
+                        let score = |from: isize| -> f32 {
+                            (0..HOP as isize)
+                                .map(|i| sample(from + i) * sample(natural + i))
+                                .sum()
+                        };
+                        score(a).total_cmp(&score(b))
+                    })
+                    .unwrap_or(nominal)
+            }
+        };
+        for (i, w) in window.iter().enumerate() {
+            out[at + i] += w * sample(from + i as isize);
+            weight[at + i] += w;
+        }
+        previous = Some(from);
+        at += HOP;
+    }
+    out.truncate(len);
+    for (sample, weight) in out.iter_mut().zip(weight) {
+        if weight > 1e-3 {
+            *sample /= weight;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_stretches() {
+        assert_eq!(
+            split_stretches("It was [STRETCH:1.5] very [/stretch] big. [stretch:9]Go"),
+            vec![
+                ("It was ", 1.0),
+                (" very ", 1.5),
+                (" big. ", 1.0),
+                ("Go", 4.0)
+            ]
+        );
+        assert_eq!(split_stretches("Plain [/stretch]"), vec![("Plain ", 1.0)]);
+    }
+
+    #[test]
+    fn test_mark_and_unmark() {
+        let runs = [
+            ("It was".to_string(), 1.0),
+            ("very big.".to_string(), 2.0),
+            ("Then".to_string(), 1.0),
+            ("slow".to_string(), 0.5),
+        ];
+        let (text, factors) = mark(&runs);
+        assert_eq!(factors, [2.0, 0.5]);
+        // The chunker splits the first stretch
+        let (first, second) = text.split_at(text.find("big").unwrap());
+        let chunks = unmark(vec![first.to_string(), second.to_string()], &factors);
+        assert_eq!(chunks[0].text, "It was very ");
+        assert_eq!(chunks[0].spans, [(7..11, 2.0)]);
+        assert_eq!(chunks[0].runs(), [("It was ", 1.0), ("very", 2.0)]);
+        assert_eq!(chunks[1].text, "big. Then slow ");
+        assert_eq!(chunks[1].spans, [(0..3, 2.0), (10..14, 0.5)]);
+
+        let runs = chunks[1].clone().into_runs();
+        let runs: Vec<_> = runs
+            .iter()
+            .map(|run| (run.text.as_str(), run.factor))
+            .collect();
+        assert_eq!(runs, [("big", 2.0), (". Then", 1.0), ("slow", 0.5)]);
+    }
+
+    #[test]
+    fn test_apply() {
+        // Three tokens of 1, 2 and 1 frames of 600 samples, the middle one doubled
+        let audio: Vec<f32> = (0..2400).map(|i| (i as f32 * 0.05).sin()).collect();
+        let mut durations = [1.0, 2.0, 1.0];
+        let stretched = apply(&audio, &mut durations, &[1.0, 2.0, 1.0]);
+        assert_eq!(durations, [1.0, 4.0, 1.0]);
+        assert_eq!(stretched.len(), 3600);
+        assert_eq!(stretched[..600], audio[..600]);
+        assert_eq!(stretched[3000..], audio[1800..]);
+
+        let mut durations = [1.0, 2.0, 1.0];
+        assert_eq!(apply(&audio, &mut durations, &[]), audio);
+        assert_eq!(durations, [1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn test_time_stretch_keeps_pitch() {
+        // 220 Hz at 24 kHz, made twice as long, still crosses zero at 220 Hz
+        let audio: Vec<f32> = (0..12_000)
+            .map(|i| (2.0 * std::f32::consts::PI * 220.0 * i as f32 / 24_000.0).sin())
+            .collect();
+        let crossings = |samples: &[f32]| {
+            samples
+                .windows(2)
+                .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+                .count() as f32
+        };
+        let stretched = time_stretch(&audio, 0..audio.len(), 2.0);
+        assert_eq!(stretched.len(), 24_000);
+        let ratio = crossings(&stretched) / crossings(&audio);
+        assert!((ratio - 2.0).abs() < 0.1, "{}", ratio);
+    }
+}
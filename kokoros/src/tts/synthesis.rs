@@ -0,0 +1,247 @@
+//! The high-level synthesis API: a [`SynthRequest`] in, a [`SynthesisResult`] out, with
+//! the audio and what is known about it in one place, so callers don't work out the
+//! duration or the phonemes again from the samples.
+//!
+//! ```no_run
+//! # fn run(tts: &kokoros::tts::koko::TTSKoko) -> Result<(), kokoros::error::KokorosError> {
+//! use kokoros::tts::synthesis::SynthRequest;
+//!
+//! let result = tts.synthesize(&SynthRequest::new("Hello there.", "af_sky"))?;
+//! println!("{:.1}s: {}", result.duration.as_secs_f32(), result.phonemes);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ops::{AddAssign, Range};
+use std::time::Duration;
+
+use crate::tts::koko::{TtsOutput, WordAlignment};
+use crate::tts::priority::Priority;
+use crate::utils::assembler::{AudioAssembler, Join};
+
+/// What to synthesize and how
+#[derive(Debug, Clone)]
+pub struct SynthRequest {
+    pub text: String,
+    /// espeak-ng language code, e.g. "en-us"
+    pub lan: String,
+    /// Voice name, blend or morph
+    pub style: String,
+    pub speed: f32,
+    pub initial_silence: Option<usize>,
+    /// Shown in log messages
+    pub request_id: Option<String>,
+    /// Whether this goes ahead of, or yields to, other requests for the same model
+    pub priority: Priority,
+    /// Integrated loudness in LUFS to bring the audio to with
+    /// [`TTSKoko::synthesize`](crate::tts::koko::TTSKoko::synthesize); streamed audio is
+    /// sent as it comes from the model
+    pub loudness: Option<f32>,
+}
+
+impl SynthRequest {
+    pub fn new(text: impl Into<String>, style: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            lan: "en-us".to_string(),
+            style: style.into(),
+            speed: 1.0,
+            initial_silence: None,
+            request_id: None,
+            priority: Priority::default(),
+            loudness: None,
+        }
+    }
+}
+
+/// One chunk the text was synthesized in
+#[derive(Debug, Clone, PartialEq)]
+pub struct SynthesizedChunk {
+    pub text: String,
+    pub phonemes: String,
+    /// Tokens the model read, without the padding and silence
+    pub tokens: usize,
+    /// Where its audio is in [`SynthesisResult::samples`]
+    pub samples: Range<usize>,
+}
+
+/// Time a synthesis spent in each stage, summed over its chunks
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StageTimings {
+    /// Plugins, replacements and the other rewrites of the text
+    pub normalize: Duration,
+    /// Chunking, espeak-ng and tokenizing
+    pub phonemize: Duration,
+    /// The model, without the wait for a free session
+    pub inference: Duration,
+}
+
+impl AddAssign for StageTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.normalize += other.normalize;
+        self.phonemize += other.phonemize;
+        self.inference += other.inference;
+    }
+}
+
+/// Synthesized speech and what went into it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SynthesisResult {
+    /// Mono samples at `sample_rate`
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub duration: Duration,
+    /// The phonemes of every chunk, separated by spaces
+    pub phonemes: String,
+    /// Start and end of each word in the audio, for models that return durations; empty
+    /// otherwise
+    pub word_timings: Vec<WordAlignment>,
+    pub chunks: Vec<SynthesizedChunk>,
+    pub timings: StageTimings,
+}
+
+impl SynthesisResult {
+    /// Duration in seconds
+    pub fn duration_sec(&self) -> f32 {
+        self.duration.as_secs_f32()
+    }
+}
+
+/// How far a synthesis has got, reported after every chunk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SynthProgress {
+    pub chunks_done: usize,
+    pub total_chunks: usize,
+    /// Seconds of audio synthesized so far
+    pub seconds_synthesized: f32,
+    /// Time since synthesis started
+    pub elapsed: Duration,
+    /// Time the remaining chunks are likely to take, at the pace of the ones done
+    pub eta: Option<Duration>,
+}
+
+impl SynthProgress {
+    pub(crate) fn new(
+        chunks_done: usize,
+        total_chunks: usize,
+        seconds_synthesized: f32,
+        elapsed: Duration,
+    ) -> Self {
+        let eta = (chunks_done > 0).then(|| {
+            elapsed.mul_f64(total_chunks.saturating_sub(chunks_done) as f64 / chunks_done as f64)
+        });
+        Self {
+            chunks_done,
+            total_chunks,
+            seconds_synthesized,
+            elapsed,
+            eta,
+        }
+    }
+
+    /// Share of the chunks done, from 0 to 1
+    pub fn fraction(&self) -> f32 {
+        if self.total_chunks == 0 {
+            return 1.0;
+        }
+        self.chunks_done as f32 / self.total_chunks as f32
+    }
+}
+
+/// A [`SynthesisResult`] built one chunk at a time, as synthesis finishes them
+pub(crate) struct SynthesisBuilder {
+    assembler: AudioAssembler,
+    word_timings: Vec<WordAlignment>,
+    chunks: Vec<SynthesizedChunk>,
+    pub(crate) timings: StageTimings,
+}
+
+impl SynthesisBuilder {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Self {
+            assembler: AudioAssembler::new(sample_rate),
+            word_timings: Vec::new(),
+            chunks: Vec::new(),
+            timings: StageTimings::default(),
+        }
+    }
+
+    /// Appends the audio of a chunk, moving its word timings onto the whole track
+    pub(crate) fn push(&mut self, output: TtsOutput, text: &str, phonemes: String, tokens: usize) {
+        let (audio, alignments) = output.raw_output();
+        let placement = self.assembler.push(&audio, Join::Butt);
+        for mut word in alignments.into_iter().flatten() {
+            word.start_sec = placement.to_track(word.start_sec);
+            word.end_sec = placement.to_track(word.end_sec);
+            self.word_timings.push(word);
+        }
+        self.chunks.push(SynthesizedChunk {
+            text: text.to_string(),
+            phonemes,
+            tokens,
+            samples: placement.start..placement.end,
+        });
+    }
+
+    /// Seconds of audio pushed so far
+    pub(crate) fn seconds(&self) -> f32 {
+        self.assembler.duration_sec()
+    }
+
+    pub(crate) fn finish(self) -> SynthesisResult {
+        let sample_rate = self.assembler.sample_rate();
+        let duration = Duration::from_secs_f64(self.assembler.len() as f64 / sample_rate as f64);
+        let phonemes = self
+            .chunks
+            .iter()
+            .map(|chunk| chunk.phonemes.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        SynthesisResult {
+            samples: self.assembler.into_samples(),
+            sample_rate,
+            duration,
+            phonemes,
+            word_timings: self.word_timings,
+            chunks: self.chunks,
+            timings: self.timings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthesis_builder() {
+        let mut builder = SynthesisBuilder::new(1000);
+        builder.push(
+            TtsOutput::Audio(vec![0.0; 500]),
+            "Hi.",
+            "hˈI.".to_string(),
+            4,
+        );
+        let word = WordAlignment {
+            word: "there".to_string(),
+            start_sec: 0.1,
+            end_sec: 0.2,
+        };
+        builder.push(
+            TtsOutput::Aligned(vec![0.0; 250], vec![word]),
+            "there",
+            "ðˈɛɹ".to_string(),
+            4,
+        );
+        let result = builder.finish();
+        assert_eq!(result.duration, Duration::from_millis(750));
+        assert_eq!(result.phonemes, "hˈI. ðˈɛɹ");
+        assert_eq!(result.chunks[1].samples, 500..750);
+        assert_eq!(result.word_timings[0].start_sec, 0.6);
+
+        let progress = SynthProgress::new(1, 4, 2.0, Duration::from_secs(3));
+        assert_eq!(progress.eta, Some(Duration::from_secs(9)));
+        assert_eq!(progress.fraction(), 0.25);
+        assert_eq!(SynthProgress::new(0, 4, 0.0, Duration::ZERO).eta, None);
+    }
+}
@@ -1,24 +1,76 @@
-use crate::tts::vocab::VOCAB;
+//! Phonemes to the token ids the model reads, and how many of them fit in one inference.
+//!
+//! Services in front of the engine can count tokens the same way it does to reject or
+//! pre-split input before sending it: [`TTSKoko::phonemize`](crate::tts::koko::TTSKoko)
+//! gives the phonemes of each chunk a text will be split into, and [`fits`] says whether
+//! a string of phonemes can be synthesized in one go by a model of a given release.
+
+use crate::tts::chunker::MAX_CHUNK_TOKENS;
+use crate::tts::vocab::{ModelVersion, VOCAB};
+
+/// Tokens the model reads in one inference, the two pad tokens around the text included
+pub const CONTEXT_TOKENS: usize = 512;
+
+/// Pad tokens added around the text of every chunk
+pub const PAD_TOKENS: usize = 2;
+
+/// Tokens the text of one chunk may have when `initial_silence` silence tokens are put in
+/// front of it, which come out of the same budget
+pub fn max_tokens(initial_silence: Option<usize>) -> usize {
+    MAX_CHUNK_TOKENS.saturating_sub(initial_silence.unwrap_or(0))
+}
 
 /// Tokenizes the given phonemes string into a vector of token indices.
 ///
-/// This function takes a text string as input and converts it into a vector of token indices
-/// by looking up each character in the global `VOCAB` map and mapping it to the corresponding
-/// token index. The resulting vector contains the token indices for the input text.
+/// This function takes a phonemes string as input and converts it into a vector of token
+/// indices by looking up each character in the global `VOCAB` map and mapping it to the
+/// corresponding token index. Characters the vocabulary doesn't have are dropped.
 ///
 /// # Arguments
-/// * `text` - The input text string to be tokenized.
+/// * `phonemes` - The phonemes string to be tokenized.
 ///
 /// # Returns
-/// A vector of `i64` token indices representing the input text.
+/// A vector of `i64` token indices representing the input phonemes, without padding.
 pub fn tokenize(phonemes: &str) -> Vec<i64> {
+    let mut tokens = Vec::with_capacity(phonemes.len());
+    tokenize_into(phonemes, &mut tokens);
+    tokens
+}
+
+/// Like [`tokenize`], but appends the tokens to `tokens`, so one buffer can be reused
+pub fn tokenize_into(phonemes: &str, tokens: &mut Vec<i64>) {
+    tokens.extend(
+        phonemes
+            .chars()
+            .filter_map(|c| VOCAB.get(&c))
+            .map(|&idx| idx as i64),
+    );
+}
+
+/// Like [`tokenize`], with the vocabulary of `version`, leaving out the symbols it doesn't
+/// have. [`tokenize`] reads the v0.19 vocabulary, which gives the same ids as v1.0 for
+/// everything but brackets and capitals.
+pub fn tokenize_for(phonemes: &str, version: ModelVersion) -> Vec<i64> {
+    let vocab = version.vocab();
     phonemes
         .chars()
-        .filter_map(|c| VOCAB.get(&c))
+        .filter_map(|c| vocab.get(&c))
         .map(|&idx| idx as i64)
         .collect()
 }
 
+/// Number of tokens [`tokenize_for`] would return for `version`, without building them
+pub fn count_tokens(phonemes: &str, version: ModelVersion) -> usize {
+    let vocab = version.vocab();
+    phonemes.chars().filter(|c| vocab.contains_key(c)).count()
+}
+
+/// Whether `phonemes` can be synthesized as one chunk by a model of `version` after
+/// `initial_silence` silence tokens, rather than having to be split
+pub fn fits(phonemes: &str, initial_silence: Option<usize>, version: ModelVersion) -> bool {
+    count_tokens(phonemes, version) <= max_tokens(initial_silence)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -43,6 +95,37 @@ mod tests {
         let punct_tokens = tokenize(punct);
         assert_eq!(punct_tokens.len(), 3);
     }
+
+    #[test]
+    fn test_tokenize_into() {
+        let text = "heɪ ðɪs ɪz ˈlʌvliː!";
+        let mut buffer = vec![0];
+        tokenize_into(text, &mut buffer);
+        assert_eq!(buffer[1..], tokenize(text)[..]);
+        assert_eq!(count_tokens(text, ModelVersion::V0_19), buffer.len() - 1);
+
+        assert_eq!(max_tokens(None) + PAD_TOKENS + 1, CONTEXT_TOKENS);
+        let v1 = ModelVersion::V1_0;
+        assert!(fits(text, Some(4), v1));
+        assert!(!fits(&"a".repeat(max_tokens(Some(4)) + 1), Some(4), v1));
+        assert!(fits(&"a".repeat(max_tokens(Some(4))), Some(4), v1));
+    }
+
+    #[test]
+    fn test_count_tokens_by_version() {
+        // v1.0 reads brackets, which v0.19 drops, and drops most Latin capitals
+        let phonemes = "(hˈɛloʊ) B";
+        for version in [ModelVersion::V0_19, ModelVersion::V1_0] {
+            assert_eq!(
+                count_tokens(phonemes, version),
+                tokenize_for(phonemes, version).len()
+            );
+        }
+        assert_eq!(
+            count_tokens(phonemes, ModelVersion::V1_0),
+            count_tokens(phonemes, ModelVersion::V0_19) + 1
+        );
+    }
 }
 
 use crate::tts::vocab::REVERSE_VOCAB;
@@ -32,7 +32,134 @@ pub fn print_sorted_reverse_vocab() {
     }
 }
 
+/// Symbols of the Kokoro v1.0 vocabulary, with their ids. Most have the id they have in
+/// [`get_vocab`]; v1.0 takes `(` and `)` for `«` and `»`, has combining and affricate
+/// symbols and a few capitals (misaki's diphthongs) where v0.19 has the Latin capitals,
+/// and leaves out the symbols it wasn't trained on.
+#[rustfmt::skip]
+const V1_0_SYMBOLS: &[(char, usize)] = &[
+    (';', 1), (':', 2), (',', 3), ('.', 4), ('!', 5), ('?', 6), ('—', 9), ('…', 10),
+    ('"', 11), ('(', 12), (')', 13), ('“', 14), ('”', 15), (' ', 16), ('\u{303}', 17),
+    ('ʣ', 18), ('ʥ', 19), ('ʦ', 20), ('ʨ', 21), ('ᵝ', 22), ('\u{AB67}', 23), ('A', 24),
+    ('I', 25), ('O', 31), ('Q', 33), ('S', 35), ('T', 36), ('W', 39), ('Y', 41), ('ᵊ', 42),
+    ('a', 43), ('b', 44), ('c', 45), ('d', 46), ('e', 47), ('f', 48), ('h', 50), ('i', 51),
+    ('j', 52), ('k', 53), ('l', 54), ('m', 55), ('n', 56), ('o', 57), ('p', 58), ('q', 59),
+    ('r', 60), ('s', 61), ('t', 62), ('u', 63), ('v', 64), ('w', 65), ('x', 66), ('y', 67),
+    ('z', 68), ('ɑ', 69), ('ɐ', 70), ('ɒ', 71), ('æ', 72), ('β', 75), ('ɔ', 76), ('ɕ', 77),
+    ('ç', 78), ('ɖ', 80), ('ð', 81), ('ʤ', 82), ('ə', 83), ('ɚ', 85), ('ɛ', 86), ('ɜ', 87),
+    ('ɟ', 90), ('ɡ', 92), ('ɥ', 99), ('ɨ', 101), ('ɪ', 102), ('ʝ', 103), ('ɯ', 110),
+    ('ɰ', 111), ('ŋ', 112), ('ɳ', 113), ('ɲ', 114), ('ɴ', 115), ('ø', 116), ('ɸ', 118),
+    ('θ', 119), ('œ', 120), ('ɹ', 123), ('ɾ', 125), ('ɻ', 126), ('ʁ', 128), ('ɽ', 129),
+    ('ʂ', 130), ('ʃ', 131), ('ʈ', 132), ('ʧ', 133), ('ʊ', 135), ('ʋ', 136), ('ʌ', 138),
+    ('ɣ', 139), ('ɤ', 140), ('χ', 142), ('ʎ', 143), ('ʒ', 147), ('ʔ', 148), ('ˈ', 156),
+    ('ˌ', 157), ('ː', 158), ('ʰ', 162), ('ʲ', 164), ('↓', 169), ('→', 171), ('↗', 172),
+    ('↘', 173), ('ᵻ', 177),
+];
+
+/// Releases of the Kokoro model, which read different vocabularies. Both take at most
+/// 512 tokens with the padding, and their voices hold a style vector per token count (511
+/// of them for v0.19, 510 for v1.0).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+pub enum ModelVersion {
+    #[serde(rename = "0.19")]
+    V0_19,
+    #[default]
+    #[serde(rename = "1.0")]
+    V1_0,
+}
+
+impl ModelVersion {
+    /// Symbol to token id for this release
+    pub fn vocab(self) -> &'static HashMap<char, usize> {
+        match self {
+            Self::V0_19 => &VOCAB,
+            Self::V1_0 => &V1_0_VOCAB,
+        }
+    }
+
+    /// The release a model file is, from the `version` (or `model_version`) in its ONNX
+    /// metadata, or failing that from its file name, e.g. `kokoro-v0_19.onnx`
+    pub fn detect(metadata_version: Option<&str>, file_name: &str) -> Option<Self> {
+        if let Some(version) = metadata_version.and_then(|version| version.parse().ok()) {
+            return Some(version);
+        }
+        let name = file_name.to_ascii_lowercase();
+        if name.contains("v0_19") || name.contains("v0.19") {
+            Some(Self::V0_19)
+        } else if name.contains("v1_0") || name.contains("v1.0") {
+            Some(Self::V1_0)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::str::FromStr for ModelVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+            .trim()
+            .trim_start_matches(['v', 'V'])
+            .replace('_', ".")
+            .as_str()
+        {
+            "0.19" => Ok(Self::V0_19),
+            "1.0" | "1" => Ok(Self::V1_0),
+            _ => Err(format!(
+                "unknown model version {:?}, expected 0.19 or 1.0",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ModelVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::V0_19 => "0.19",
+            Self::V1_0 => "1.0",
+        })
+    }
+}
+
 lazy_static! {
     pub static ref VOCAB: HashMap<char, usize> = get_vocab();
     pub static ref REVERSE_VOCAB: HashMap<usize, char> = get_reverse_vocab();
+    static ref V1_0_VOCAB: HashMap<char, usize> = V1_0_SYMBOLS.iter().copied().collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_version() {
+        // v1.0 keeps the ids of the symbols it shares with v0.19, but for the brackets
+        for (c, id) in V1_0_SYMBOLS {
+            assert!(
+                VOCAB.get(c) == Some(id) || "()\u{303}ʣʥʦʨᵝ\u{AB67}AIOQSTWYᵊ".contains(*c),
+                "{}",
+                c
+            );
+        }
+        assert_eq!(ModelVersion::V1_0.vocab().get(&'('), Some(&12));
+        assert_eq!(ModelVersion::V0_19.vocab().get(&'«'), Some(&12));
+        assert_eq!(ModelVersion::V1_0.vocab().get(&'«'), None);
+
+        assert_eq!(
+            ModelVersion::detect(Some("v0.19"), "model.onnx"),
+            Some(ModelVersion::V0_19)
+        );
+        assert_eq!(
+            ModelVersion::detect(None, "kokoro-v0_19.onnx"),
+            Some(ModelVersion::V0_19)
+        );
+        assert_eq!(
+            ModelVersion::detect(None, "kokoro-v1.0.onnx"),
+            Some(ModelVersion::V1_0)
+        );
+        assert_eq!(ModelVersion::detect(None, "model.onnx"), None);
+        assert_eq!("1.0".parse::<ModelVersion>().unwrap().to_string(), "1.0");
+    }
 }
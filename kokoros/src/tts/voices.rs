@@ -0,0 +1,989 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use memmap2::Mmap;
+use ndarray::{Array3, ArrayD};
+use ndarray_npy::{NpzReader, ReadNpyExt, WriteNpyExt};
+use safetensors::{Dtype, SafeTensors};
+use serde::Deserialize;
+
+use crate::error::KokorosError;
+use crate::tts::chunker::MAX_CHUNK_TOKENS;
+
+/// Values in one style vector, as the model's style input takes them
+pub const STYLE_DIM: usize = 256;
+
+/// Style vectors of one voice, indexed by the number of tokens in the utterance
+pub type VoiceStyles = Vec<[[f32; 256]; 1]>;
+
+/// Style vectors a voice holds when it has one for every length of chunk the model takes
+pub const FULL_STYLE_ROWS: usize = MAX_CHUNK_TOKENS + 1;
+
+/// The style vector for an utterance of `tokens_len` tokens from a voice of `rows` rows,
+/// which `row` decodes. A full voice (see [`FULL_STYLE_ROWS`]) is indexed by the length,
+/// as the reference implementation does, with its last row for anything longer. A shorter
+/// one, such as a compact export, holds rows spread evenly over the lengths the model
+/// takes, and lengths between two rows get a linear blend of both.
+fn style_for_length(
+    rows: usize,
+    tokens_len: usize,
+    row: impl Fn(usize) -> Option<[f32; 256]>,
+) -> Option<[f32; 256]> {
+    if rows == 0 {
+        return None;
+    }
+    if rows >= FULL_STYLE_ROWS || rows == 1 {
+        return row(tokens_len.min(rows - 1));
+    }
+    let position =
+        tokens_len.min(MAX_CHUNK_TOKENS) as f32 * (rows - 1) as f32 / MAX_CHUNK_TOKENS as f32;
+    let below = position.floor() as usize;
+    let weight = position - below as f32;
+    let mut style = row(below)?;
+    if weight > 0.0 {
+        let above = row(below + 1)?;
+        for (value, above) in style.iter_mut().zip(above) {
+            *value += (above - *value) * weight;
+        }
+    }
+    Some(style)
+}
+
+/// The style vector of a decoded voice for an utterance of `tokens_len` tokens, see
+/// [`Styles::style_for`]
+pub fn voice_style(styles: &VoiceStyles, tokens_len: usize) -> Option<[f32; 256]> {
+    style_for_length(styles.len(), tokens_len, |i| {
+        styles.get(i).map(|style| style[0])
+    })
+}
+
+/// What a voice name says about the voice, following the Kokoro naming scheme
+/// `<language><gender>_<name>` (e.g. `af_sky` is an American English female voice)
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoiceInfo {
+    pub name: String,
+    pub language: &'static str,
+    /// espeak-ng language identifier to pass as `--lan` for this voice
+    pub lan: &'static str,
+    pub gender: &'static str,
+}
+
+/// Derives language and gender from a voice name; unknown prefixes are reported as such
+pub fn voice_info(name: &str) -> VoiceInfo {
+    let mut prefix = name.chars();
+    let (language, lan) = match prefix.next() {
+        Some('a') => ("American English", "en-us"),
+        Some('b') => ("British English", "en-gb"),
+        Some('e') => ("Spanish", "es"),
+        Some('f') => ("French", "fr-fr"),
+        Some('h') => ("Hindi", "hi"),
+        Some('i') => ("Italian", "it"),
+        Some('j') => ("Japanese", "ja"),
+        Some('p') => ("Brazilian Portuguese", "pt-br"),
+        Some('z') => ("Mandarin Chinese", "cmn"),
+        _ => ("Unknown", ""),
+    };
+    let gender = match prefix.next() {
+        Some('f') => "female",
+        Some('m') => "male",
+        _ => "unknown",
+    };
+
+    VoiceInfo {
+        name: name.to_string(),
+        language,
+        lan,
+        gender,
+    }
+}
+
+/// Metadata of one voice. Defaults come from the voice name (see [`voice_info`]) and can be
+/// overridden by a sidecar JSON file next to the voices file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoiceMetadata {
+    /// Human-readable language, e.g. "British English"
+    pub language: String,
+    /// espeak-ng language identifier the voice was trained on
+    pub lan: String,
+    pub gender: String,
+    pub description: Option<String>,
+    /// Speed the voice sounds best at
+    pub speed: Option<f32>,
+}
+
+/// Sidecar entry; every field is optional and overrides the default
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SidecarEntry {
+    language: Option<String>,
+    lan: Option<String>,
+    gender: Option<String>,
+    description: Option<String>,
+    speed: Option<f32>,
+}
+
+/// Metadata of every voice in a voices file
+#[derive(Debug, Default)]
+pub struct VoiceRegistry {
+    voices: HashMap<String, VoiceMetadata>,
+}
+
+/// Sidecar metadata file for a voices file or directory: `voices-v1.0.json` next to
+/// `voices-v1.0.bin`, or `voices.json` inside a directory
+pub fn sidecar_path(voices_path: &Path) -> PathBuf {
+    if voices_path.is_dir() {
+        voices_path.join("voices.json")
+    } else {
+        voices_path.with_extension("json")
+    }
+}
+
+/// Primary language of an espeak-ng identifier, so `en-us` and `en-gb` match and `cmn`
+/// matches `zh`
+fn language_family(lan: &str) -> String {
+    let primary = lan.split(['-', '_']).next().unwrap_or(lan).to_lowercase();
+    match primary.as_str() {
+        "cmn" | "yue" => "zh".to_string(),
+        _ => primary,
+    }
+}
+
+impl VoiceRegistry {
+    /// Metadata for `names`, with the sidecar next to `voices_path` applied if there is one
+    pub fn load(voices_path: &Path, names: &[String]) -> Result<Self, KokorosError> {
+        let mut registry = Self::from_names(names);
+        registry.load_sidecar(voices_path)?;
+        Ok(registry)
+    }
+
+    /// Metadata for `names` as their names tell it, without a sidecar
+    pub fn from_names(names: &[String]) -> Self {
+        let mut registry = Self::default();
+        for name in names {
+            let info = voice_info(name);
+            registry.voices.insert(
+                name.clone(),
+                VoiceMetadata {
+                    language: info.language.to_string(),
+                    lan: info.lan.to_string(),
+                    gender: info.gender.to_string(),
+                    description: None,
+                    speed: None,
+                },
+            );
+        }
+        registry
+    }
+
+    /// Applies the sidecar next to `voices_path`, if there is one
+    pub fn load_sidecar(&mut self, voices_path: &Path) -> Result<(), KokorosError> {
+        let sidecar = sidecar_path(voices_path);
+        if sidecar.exists() {
+            let entries: HashMap<String, SidecarEntry> =
+                serde_json::from_reader(File::open(&sidecar)?)
+                    .map_err(|e| invalid(format!("metadata in {}: {}", sidecar.display(), e)))?;
+            self.apply(entries);
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, entries: HashMap<String, SidecarEntry>) {
+        for (name, entry) in entries {
+            let Some(metadata) = self.voices.get_mut(&name) else {
+                tracing::warn!("Voice metadata for unknown voice {}", name);
+                continue;
+            };
+            if let Some(lan) = entry.lan {
+                metadata.lan = lan;
+            }
+            if let Some(language) = entry.language {
+                metadata.language = language;
+            }
+            if let Some(gender) = entry.gender {
+                metadata.gender = gender;
+            }
+            metadata.description = entry.description.or(metadata.description.take());
+            metadata.speed = entry.speed.or(metadata.speed);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&VoiceMetadata> {
+        self.voices.get(name)
+    }
+
+    /// A voice for `language`, a BCP 47 tag such as `en-GB` or `pt`: one trained on exactly
+    /// that language if there is one, else on another variety of it (`en-us` for `en-AU`).
+    /// Among several, `preferred` if it is one of them, else the first by name.
+    pub fn voice_for_language(&self, language: &str, preferred: &str) -> Option<&str> {
+        let language = language.replace('_', "-");
+        let family = language_family(&language);
+        let mut exact = Vec::new();
+        let mut related = Vec::new();
+        for (name, metadata) in &self.voices {
+            if metadata.lan.eq_ignore_ascii_case(&language) {
+                exact.push(name.as_str());
+            } else if !metadata.lan.is_empty() && language_family(&metadata.lan) == family {
+                related.push(name.as_str());
+            }
+        }
+        let best = if exact.is_empty() { related } else { exact };
+        best.iter()
+            .find(|&&name| name == preferred)
+            .or(best.iter().min())
+            .copied()
+    }
+
+    /// A warning if `style` (a voice or a blend) was trained on a different language than
+    /// `lan`, e.g. an English voice used with `-l zh`
+    pub fn check_language(&self, style: &str, lan: &str) -> Option<String> {
+        let morph = Morph::is_morph(style)
+            .then(|| Morph::parse(style).ok())
+            .flatten();
+        let names = match &morph {
+            Some(morph) => vec![(morph.from.as_str(), 1.0), (morph.to.as_str(), 1.0)],
+            None => parse_blend(style).unwrap_or_else(|_| vec![(style, 1.0)]),
+        };
+        let mismatched: Vec<String> = names
+            .iter()
+            .filter_map(|(name, _)| self.get(name))
+            .filter(|metadata| {
+                !metadata.lan.is_empty() && language_family(&metadata.lan) != language_family(lan)
+            })
+            .map(|metadata| metadata.language.clone())
+            .collect();
+        if mismatched.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "voice {} is for {}, but the language is '{}'; pronunciation may be poor \
+             (pass a matching -l/--lan, or pick a voice for this language)",
+            style,
+            mismatched.join("/"),
+            lan
+        ))
+    }
+}
+
+fn invalid(e: impl std::fmt::Display) -> KokorosError {
+    KokorosError::InvalidVoice(e.to_string())
+}
+
+/// Lists the voice names in a voices file or directory, without loading the style data
+/// when it is an npz archive or a safetensors pack
+pub fn list_voice_names(voices_path: &str) -> Result<Vec<String>, KokorosError> {
+    let path = Path::new(voices_path);
+    if !path.is_dir() && sniff(&mut File::open(path)?)? == Format::Npz {
+        let mut names = NpzReader::new(File::open(path)?)
+            .and_then(|mut npz| npz.names())
+            .map_err(invalid)?;
+        names.sort();
+        return Ok(names);
+    }
+    Ok(load_voices(path)?.names())
+}
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    /// A numpy `.npz` archive of one array per voice, like `voices-v1.0.bin`
+    Npz,
+    /// A single numpy `.npy` array
+    Npy,
+    /// A safetensors file of one tensor per voice, as community voice packs are shared
+    Safetensors,
+    /// Bare little-endian f32 values, like the per-voice `.bin` files of the ONNX exports
+    Raw,
+}
+
+/// Tells the formats apart by their magic bytes rather than the extension, since
+/// `.bin` is used for most of them
+fn sniff<R: Read + Seek>(reader: &mut R) -> std::io::Result<Format> {
+    let mut magic = [0u8; 9];
+    let mut read = 0;
+    while read < magic.len() {
+        match reader.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(match &magic[..read] {
+        [b'P', b'K', 3, 4, ..] => Format::Npz,
+        [0x93, b'N', b'U', b'M', b'P', b'Y', ..] => Format::Npy,
+        // A little-endian u64 header length followed by the JSON header
+        [_, _, _, _, _, _, 0, 0, b'{'] => Format::Safetensors,
+        _ => Format::Raw,
+    })
+}
+
+/// Splits a flat array into one 256-value style vector per token count, whatever shape
+/// it was stored in ((511, 1, 256), (510, 256), ...)
+fn to_styles(values: impl ExactSizeIterator<Item = f32>) -> Result<VoiceStyles, String> {
+    if values.len() == 0 || !values.len().is_multiple_of(256) {
+        return Err(format!(
+            "expected a multiple of 256 style values, got {}",
+            values.len()
+        ));
+    }
+    let mut styles = vec![[[0.0; 256]; 1]; values.len() / 256];
+    for (i, value) in values.enumerate() {
+        styles[i / 256][0][i % 256] = value;
+    }
+    Ok(styles)
+}
+
+/// Checks that an array stored with `shape` is made of style vectors, e.g. (510, 1, 256),
+/// and not of vectors of another size that merely add up to a multiple of 256 values
+fn check_shape(shape: &[usize]) -> Result<(), String> {
+    if shape.last() != Some(&STYLE_DIM) {
+        return Err(format!(
+            "expected style vectors of {} values, got shape {:?}",
+            STYLE_DIM, shape
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the style vectors of every voice in an npz archive, such as the upstream
+/// `voices-v1.0.bin` or `voices.npz`
+pub fn read_voices<R: Read + Seek>(
+    reader: R,
+) -> Result<HashMap<String, VoiceStyles>, KokorosError> {
+    let mut npz = NpzReader::new(reader).map_err(invalid)?;
+    let mut map = HashMap::new();
+
+    for voice in npz.names().map_err(invalid)? {
+        let voice_data: ArrayD<f32> = npz.by_name(&voice).map_err(invalid)?;
+        let styles = check_shape(voice_data.shape())
+            .and_then(|_| to_styles(voice_data.into_iter()))
+            .map_err(|e| invalid(format!("{}: {}", voice, e)))?;
+        map.insert(voice, styles);
+    }
+    Ok(map)
+}
+
+/// Reads a single voice from a `.npy` file or a raw f32 `.bin` file
+fn read_voice<R: Read + Seek>(mut reader: R) -> Result<VoiceStyles, String> {
+    if sniff(&mut reader).map_err(|e| e.to_string())? == Format::Npy {
+        let voice_data = ArrayD::<f32>::read_npy(reader).map_err(|e| e.to_string())?;
+        check_shape(voice_data.shape())?;
+        return to_styles(voice_data.into_iter());
+    }
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    if !bytes.len().is_multiple_of(4) {
+        return Err("raw voice file length is not a multiple of 4 bytes".to_string());
+    }
+    to_styles(
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+    )
+}
+
+/// A safetensors voice pack, memory-mapped so that only the voices actually used are read
+/// from disk. Each tensor holds one voice in f32, f16 or bf16.
+pub struct SafetensorsPack {
+    mmap: Mmap,
+    /// Data type, and byte range of each voice's tensor in the file
+    tensors: HashMap<String, (Dtype, Range<usize>)>,
+}
+
+impl SafetensorsPack {
+    pub fn open(path: &Path) -> Result<Self, KokorosError> {
+        let file = File::open(path)?;
+        // Safety: the file is only read, and voice packs aren't expected to change while
+        // they are in use
+        let mmap = unsafe { Mmap::map(&file)? };
+        let (header_len, metadata) = SafeTensors::read_metadata(&mmap)
+            .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+        let data_start = 8 + header_len;
+
+        let mut tensors = HashMap::new();
+        for (name, info) in metadata.tensors() {
+            let element_size = match info.dtype {
+                Dtype::F32 => 4,
+                Dtype::F16 | Dtype::BF16 => 2,
+                dtype => {
+                    return Err(invalid(format!("{}: unsupported dtype {:?}", name, dtype)));
+                }
+            };
+            let (start, end) = info.data_offsets;
+            let range = data_start + start..data_start + end;
+            let values = (end - start) / element_size;
+            if range.end > mmap.len() || values == 0 || !values.is_multiple_of(256) {
+                return Err(invalid(format!(
+                    "{}: expected a multiple of 256 style values, got {:?}",
+                    name, info.shape
+                )));
+            }
+            check_shape(&info.shape).map_err(|e| invalid(format!("{}: {}", name, e)))?;
+            tensors.insert(name, (info.dtype, range));
+        }
+        Ok(Self { mmap, tensors })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tensors.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Number of style vectors `name` holds
+    pub fn rows(&self, name: &str) -> Option<usize> {
+        let (dtype, range) = self.tensors.get(name)?;
+        let element_size = if *dtype == Dtype::F32 { 4 } else { 2 };
+        Some(range.len() / element_size / 256)
+    }
+
+    /// Decodes the style vector of `name` for an utterance of `tokens_len` tokens
+    pub fn style(&self, name: &str, tokens_len: usize) -> Option<[f32; 256]> {
+        let (dtype, range) = self.tensors.get(name)?;
+        let bytes = &self.mmap[range.clone()];
+        let mut style = [0.0; 256];
+        match dtype {
+            Dtype::F32 => {
+                let row = bytes.chunks_exact(4 * 256).nth(tokens_len)?;
+                for (value, b) in style.iter_mut().zip(row.chunks_exact(4)) {
+                    *value = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                }
+            }
+            Dtype::F16 | Dtype::BF16 => {
+                let row = bytes.chunks_exact(2 * 256).nth(tokens_len)?;
+                for (value, b) in style.iter_mut().zip(row.chunks_exact(2)) {
+                    let bits = u16::from_le_bytes([b[0], b[1]]);
+                    *value = if *dtype == Dtype::F16 {
+                        f16_to_f32(bits)
+                    } else {
+                        f32::from_bits(u32::from(bits) << 16)
+                    };
+                }
+            }
+            _ => unreachable!("checked in open"),
+        }
+        Some(style)
+    }
+
+    /// Decodes every voice into memory
+    fn to_map(&self) -> HashMap<String, VoiceStyles> {
+        self.tensors
+            .keys()
+            .map(|name| {
+                let rows = self.rows(name).expect("listed tensor");
+                let styles = (0..rows)
+                    .map(|i| [self.style(name, i).expect("row within the tensor")])
+                    .collect();
+                (name.clone(), styles)
+            })
+            .collect()
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f32::from(bits & 0x3ff);
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// The style vectors of all voices, either decoded into memory or looked up in a
+/// memory-mapped safetensors pack
+pub enum Styles {
+    Loaded(HashMap<String, VoiceStyles>),
+    Mapped(SafetensorsPack),
+    /// Several sources, e.g. the voices file and a user voices directory; earlier ones take
+    /// precedence for voices of the same name
+    Layered(Vec<Arc<Styles>>),
+    /// Voices registered while running, e.g. uploaded to the server
+    Added(RwLock<HashMap<String, VoiceStyles>>),
+}
+
+impl Styles {
+    /// Voice names, sorted
+    pub fn names(&self) -> Vec<String> {
+        match self {
+            Self::Loaded(map) => {
+                let mut names: Vec<String> = map.keys().cloned().collect();
+                names.sort();
+                names
+            }
+            Self::Mapped(pack) => pack.names(),
+            Self::Layered(layers) => {
+                let mut names: Vec<String> = layers.iter().flat_map(|l| l.names()).collect();
+                names.sort();
+                names.dedup();
+                names
+            }
+            Self::Added(map) => {
+                let mut names: Vec<String> = map.read().unwrap().keys().cloned().collect();
+                names.sort();
+                names
+            }
+        }
+    }
+
+    /// The style vector of `name` for an utterance of `tokens_len` tokens, if there is
+    /// such a voice and it covers that many tokens
+    pub fn style(&self, name: &str, tokens_len: usize) -> Option<[f32; 256]> {
+        match self {
+            Self::Loaded(map) => map.get(name)?.get(tokens_len).map(|style| style[0]),
+            Self::Mapped(pack) => pack.style(name, tokens_len),
+            Self::Layered(layers) => {
+                let layer = layers.iter().find(|l| l.contains(name))?;
+                layer.style(name, tokens_len)
+            }
+            Self::Added(map) => map
+                .read()
+                .unwrap()
+                .get(name)?
+                .get(tokens_len)
+                .map(|style| style[0]),
+        }
+    }
+
+    /// Number of style vectors `name` holds, if there is such a voice
+    pub fn rows(&self, name: &str) -> Option<usize> {
+        match self {
+            Self::Loaded(map) => map.get(name).map(Vec::len),
+            Self::Mapped(pack) => pack.rows(name),
+            Self::Layered(layers) => layers.iter().find(|l| l.contains(name))?.rows(name),
+            Self::Added(map) => map.read().unwrap().get(name).map(Vec::len),
+        }
+    }
+
+    /// The style vector of `name` for an utterance of `tokens_len` tokens, as synthesis
+    /// uses it: the row for that length when the voice has one for every length, otherwise
+    /// interpolated between the nearest of its rows
+    pub fn style_for(&self, name: &str, tokens_len: usize) -> Option<[f32; 256]> {
+        let rows = self.rows(name)?;
+        style_for_length(rows, tokens_len, |i| self.style(name, i))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        match self {
+            Self::Loaded(map) => map.contains_key(name),
+            Self::Mapped(pack) => pack.tensors.contains_key(name),
+            Self::Layered(layers) => layers.iter().any(|l| l.contains(name)),
+            Self::Added(map) => map.read().unwrap().contains_key(name),
+        }
+    }
+
+    /// Registers `voice` as `name` in the first `Added` source; fails if there is none
+    pub fn insert(&self, name: &str, voice: Voice) -> Result<(), KokorosError> {
+        match self {
+            Self::Added(map) => {
+                map.write().unwrap().insert(name.to_string(), voice.0);
+                Ok(())
+            }
+            Self::Layered(layers) => layers
+                .iter()
+                .find(|l| l.accepts_voices())
+                .ok_or_else(|| invalid("no source accepts new voices"))?
+                .insert(name, voice),
+            _ => Err(invalid("no source accepts new voices")),
+        }
+    }
+
+    fn accepts_voices(&self) -> bool {
+        match self {
+            Self::Added(_) => true,
+            Self::Layered(layers) => layers.iter().any(|l| l.accepts_voices()),
+            _ => false,
+        }
+    }
+}
+
+impl Styles {
+    /// One voice's style vectors for every token count, decoded
+    pub fn voice(&self, name: &str) -> Option<Voice> {
+        let styles = match self {
+            Self::Loaded(map) => map.get(name)?.clone(),
+            Self::Added(map) => map.read().unwrap().get(name)?.clone(),
+            Self::Layered(layers) => return layers.iter().find_map(|l| l.voice(name)),
+            Self::Mapped(pack) => (0..)
+                .map_while(|tokens_len| pack.style(name, tokens_len))
+                .map(|style| [style])
+                .collect(),
+        };
+        (!styles.is_empty()).then_some(Voice(styles))
+    }
+
+    /// Weighted sum of voices, e.g. `[("af_sky", 0.4), ("af_nicole", 0.6)]`. Weights are
+    /// used as given, so they normally add up to 1.
+    pub fn blend(&self, parts: &[(&str, f32)]) -> Result<Voice, KokorosError> {
+        let mut blended: Option<VoiceStyles> = None;
+        for (name, weight) in parts {
+            let voice = self
+                .voice(name)
+                .ok_or_else(|| invalid(format!("unknown voice {}", name)))?;
+            let blended = blended.get_or_insert_with(|| vec![[[0.0; 256]; 1]; voice.0.len()]);
+            // Only the token counts every voice covers
+            blended.truncate(voice.0.len());
+            for (sum, style) in blended.iter_mut().zip(&voice.0) {
+                for (sum, value) in sum[0].iter_mut().zip(&style[0]) {
+                    *sum += value * weight;
+                }
+            }
+        }
+        blended
+            .map(Voice)
+            .ok_or_else(|| invalid("nothing to blend"))
+    }
+}
+
+/// A gradual change from one voice to another over the chunks of one synthesis, written
+/// `from:to:start..end`, e.g. `af_sky:am_adam:0..1`. `start` and `end` are `to`'s share of
+/// the blend in the first and the last chunk; they default to `0..1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Morph {
+    pub from: String,
+    pub to: String,
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Morph {
+    /// Whether `style` is a morph rather than a voice or blend
+    pub fn is_morph(style: &str) -> bool {
+        style.contains(':')
+    }
+
+    pub fn parse(spec: &str) -> Result<Self, KokorosError> {
+        let mut parts = spec.splitn(3, ':');
+        let (Some(from), Some(to)) = (parts.next(), parts.next()) else {
+            return Err(invalid(format!(
+                "morph '{}', e.g. af_sky:am_adam:0..1",
+                spec
+            )));
+        };
+        let (start, end) = match parts.next() {
+            None => (0.0, 1.0),
+            Some(range) => {
+                let (start, end) = range
+                    .split_once("..")
+                    .ok_or_else(|| invalid(format!("morph range '{}', e.g. 0..1", range)))?;
+                let weight = |w: &str| {
+                    w.parse::<f32>()
+                        .ok()
+                        .filter(|w| (0.0..=1.0).contains(w))
+                        .ok_or_else(|| invalid(format!("morph weight '{}', use 0 to 1", w)))
+                };
+                (weight(start)?, weight(end)?)
+            }
+        };
+        Ok(Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            start,
+            end,
+        })
+    }
+
+    /// `to`'s share of the blend in chunk `index` of `count`
+    pub fn weight(&self, index: usize, count: usize) -> f32 {
+        if count <= 1 {
+            return self.start;
+        }
+        self.start + (self.end - self.start) * index as f32 / (count - 1) as f32
+    }
+}
+
+impl Styles {
+    /// The style vector for chunk `index` of `count` while morphing
+    pub fn morph_style(
+        &self,
+        morph: &Morph,
+        tokens_len: usize,
+        index: usize,
+        count: usize,
+    ) -> Option<[f32; 256]> {
+        let from = self.style_for(&morph.from, tokens_len)?;
+        let to = self.style_for(&morph.to, tokens_len)?;
+        let weight = morph.weight(index, count);
+        let mut style = [0.0; 256];
+        for ((value, from), to) in style.iter_mut().zip(from).zip(to) {
+            *value = from * (1.0 - weight) + to * weight;
+        }
+        Some(style)
+    }
+}
+
+/// Parses a blend in the `--style` syntax, e.g. `af_sky.4+af_nicole.6` for 40% af_sky and
+/// 60% af_nicole; a plain voice name has weight 1
+pub fn parse_blend(recipe: &str) -> Result<Vec<(&str, f32)>, KokorosError> {
+    if !recipe.contains('+') && !recipe.contains('.') {
+        return Ok(vec![(recipe, 1.0)]);
+    }
+    recipe
+        .split('+')
+        .map(|part| {
+            let (name, portion) = part
+                .split_once('.')
+                .ok_or_else(|| invalid(format!("missing weight in '{}', e.g. {}.5", part, part)))?;
+            let portion: f32 = portion
+                .parse()
+                .map_err(|_| invalid(format!("weight in '{}'", part)))?;
+            Ok((name, portion * 0.1))
+        })
+        .collect()
+}
+
+/// Whether `name` can be used as a voice name and file name: ASCII letters, digits and
+/// `_`/`-` only
+pub fn is_valid_voice_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// One voice's style vectors, for building new voices out of existing ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct Voice(pub VoiceStyles);
+
+impl Voice {
+    /// Multiplies every style value by `factor`; values above 1 exaggerate the voice's
+    /// character, values below 1 flatten it
+    pub fn scale(mut self, factor: f32) -> Self {
+        for style in &mut self.0 {
+            for value in &mut style[0] {
+                *value *= factor;
+            }
+        }
+        self
+    }
+
+    /// Writes the voice to `<dir>/<name>.npy`, creating `dir` if needed, so it can be
+    /// loaded like any other voice file
+    pub fn save_as(&self, dir: &Path, name: &str) -> Result<PathBuf, KokorosError> {
+        if !is_valid_voice_name(name) {
+            return Err(invalid(format!(
+                "name '{}', use letters, digits, _ and - only",
+                name
+            )));
+        }
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.npy", name));
+        let values: Vec<f32> = self.0.iter().flat_map(|style| style[0]).collect();
+        let array = Array3::from_shape_vec((self.0.len(), 1, 256), values)
+            .map_err(|e| KokorosError::Encode(e.to_string()))?;
+        array
+            .write_npy(File::create(&path)?)
+            .map_err(|e| KokorosError::Encode(e.to_string()))?;
+        Ok(path)
+    }
+}
+
+/// Loads voices from any of the published layouts: an npz archive of all voices, a
+/// safetensors pack (memory-mapped rather than read), a single `.npy` or raw `.bin` voice
+/// (named after the file), or a directory of those
+pub fn load_voices(path: &Path) -> Result<Styles, KokorosError> {
+    if !path.is_dir() && sniff(&mut File::open(path)?)? == Format::Safetensors {
+        return Ok(Styles::Mapped(SafetensorsPack::open(path)?));
+    }
+    Ok(Styles::Loaded(load_voice_map(path)?))
+}
+
+fn load_voice_map(path: &Path) -> Result<HashMap<String, VoiceStyles>, KokorosError> {
+    let voice_name = |path: &Path| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .ok_or_else(|| invalid(format!("file name {}", path.display())))
+    };
+
+    if path.is_dir() {
+        let mut map = HashMap::new();
+        for entry in std::fs::read_dir(path)? {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|e| e.to_str());
+            if !matches!(extension, Some("npy" | "bin" | "npz" | "safetensors")) {
+                continue;
+            }
+            let mut file = File::open(&path)?;
+            let format = sniff(&mut file)?;
+            if format == Format::Npz {
+                map.extend(read_voices(file)?);
+            } else if format == Format::Safetensors {
+                map.extend(SafetensorsPack::open(&path)?.to_map());
+            } else {
+                let styles =
+                    read_voice(file).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+                map.insert(voice_name(&path)?, styles);
+            }
+        }
+        if map.is_empty() {
+            return Err(invalid(format!("no voice files in {}", path.display())));
+        }
+        return Ok(map);
+    }
+
+    let mut file = File::open(path)?;
+    match sniff(&mut file)? {
+        Format::Npz => read_voices(file),
+        _ => {
+            let styles = read_voice(file).map_err(invalid)?;
+            Ok(HashMap::from([(voice_name(path)?, styles)]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voice_info() {
+        let info = voice_info("bm_george");
+        assert_eq!(info.language, "British English");
+        assert_eq!(info.lan, "en-gb");
+        assert_eq!(info.gender, "male");
+        assert_eq!(voice_info("custom").language, "Unknown");
+    }
+
+    #[test]
+    fn test_read_raw_voice() {
+        let values: Vec<f32> = (0..510 * 256).map(|i| i as f32).collect();
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let styles = read_voice(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(styles.len(), 510);
+        assert_eq!(styles[1][0][0], 256.0);
+
+        assert!(read_voice(std::io::Cursor::new(vec![0u8; 12])).is_err());
+
+        assert!(check_shape(&[510, 1, 256]).is_ok());
+        assert!(check_shape(&[510, 256]).is_ok());
+        assert!(check_shape(&[1020, 1, 128]).is_err());
+    }
+
+    #[test]
+    fn test_blend_scale_save() {
+        let styles = Styles::Loaded(HashMap::from([
+            ("af_a".to_string(), vec![[[1.0; 256]; 1]; 3]),
+            ("af_b".to_string(), vec![[[3.0; 256]; 1]; 2]),
+        ]));
+        let parts = parse_blend("af_a.5+af_b.5").unwrap();
+        let voice = styles.blend(&parts).unwrap().scale(2.0);
+        assert_eq!(voice.0.len(), 2);
+        assert_eq!(voice.0[1][0][7], 4.0);
+
+        let dir = std::env::temp_dir().join(format!("kokoros-voices-{}", std::process::id()));
+        let path = voice.save_as(&dir, "af_mix").unwrap();
+        let saved = load_voices(&path).unwrap().voice("af_mix").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(saved, voice);
+        assert!(voice.save_as(&dir, "../escape").is_err());
+
+        assert!(styles.insert("af_mix", voice.clone()).is_err());
+        let layered = Styles::Layered(vec![
+            Arc::new(Styles::Added(Default::default())),
+            Arc::new(styles),
+        ]);
+        layered.insert("af_mix", voice).unwrap();
+        assert!(layered.contains("af_mix"));
+        assert_eq!(layered.style("af_mix", 1), Some([4.0; 256]));
+    }
+
+    #[test]
+    fn test_morph() {
+        let morph = Morph::parse("af_a:af_b:0.5..1").unwrap();
+        assert_eq!(morph.weight(0, 3), 0.5);
+        assert_eq!(morph.weight(1, 3), 0.75);
+        assert_eq!(morph.weight(2, 3), 1.0);
+        assert_eq!(Morph::parse("af_a:af_b").unwrap().end, 1.0);
+        assert!(Morph::parse("af_a:af_b:0..2").is_err());
+
+        let styles = Styles::Loaded(HashMap::from([
+            ("af_a".to_string(), vec![[[1.0; 256]; 1]; 3]),
+            ("af_b".to_string(), vec![[[3.0; 256]; 1]; 3]),
+        ]));
+        assert_eq!(styles.morph_style(&morph, 0, 1, 3), Some([2.5; 256]));
+    }
+
+    #[test]
+    fn test_check_language() {
+        let names = vec!["af_sky".to_string(), "zf_xiaobei".to_string()];
+        let mut registry = VoiceRegistry::load(Path::new("missing.bin"), &names).unwrap();
+        assert!(registry.check_language("af_sky", "en-gb").is_none());
+        assert!(registry.check_language("zf_xiaobei", "zh").is_none());
+        assert!(registry.check_language("af_sky", "zh").is_some());
+        assert!(
+            registry
+                .check_language("af_sky.5+zf_xiaobei.5", "cmn")
+                .is_some()
+        );
+
+        let entries = serde_json::from_str(r#"{"af_sky": {"lan": "cmn", "speed": 1.1}}"#).unwrap();
+        registry.apply(entries);
+        assert!(registry.check_language("af_sky", "zh").is_none());
+        assert_eq!(registry.get("af_sky").unwrap().speed, Some(1.1));
+
+        let names = ["af_sky", "af_bella", "bf_emma", "ff_siwis", "zf_xiaobei"].map(String::from);
+        let registry = VoiceRegistry::from_names(&names);
+        assert_eq!(
+            registry.voice_for_language("en-GB", "af_sky"),
+            Some("bf_emma")
+        );
+        assert_eq!(registry.voice_for_language("en", "af_sky"), Some("af_sky"));
+        assert_eq!(
+            registry.voice_for_language("en-AU", "ff_siwis"),
+            Some("af_bella")
+        );
+        assert_eq!(
+            registry.voice_for_language("fr", "af_sky"),
+            Some("ff_siwis")
+        );
+        assert_eq!(
+            registry.voice_for_language("zh-CN", "af_sky"),
+            Some("zf_xiaobei")
+        );
+        assert_eq!(registry.voice_for_language("de", "af_sky"), None);
+    }
+
+    #[test]
+    fn test_safetensors_pack() {
+        // Two token counts of an f16 voice: all 1.0, then all -2.0
+        let header = r#"{"af_pack":{"dtype":"F16","shape":[2,1,256],"data_offsets":[0,1024]}}"#;
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend(std::iter::repeat_n(0x3c00u16.to_le_bytes(), 256).flatten());
+        bytes.extend(std::iter::repeat_n(0xc000u16.to_le_bytes(), 256).flatten());
+        let path =
+            std::env::temp_dir().join(format!("kokoros-pack-{}.safetensors", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+
+        let styles = load_voices(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(styles, Styles::Mapped(_)));
+        assert_eq!(styles.names(), vec!["af_pack"]);
+        assert_eq!(styles.style("af_pack", 0).unwrap()[255], 1.0);
+        assert_eq!(styles.style("af_pack", 1).unwrap()[0], -2.0);
+        assert!(styles.style("af_pack", 2).is_none());
+        assert_eq!(styles.rows("af_pack"), Some(2));
+    }
+
+    #[test]
+    fn test_style_for() {
+        let full: VoiceStyles = (0..FULL_STYLE_ROWS).map(|i| [[i as f32; 256]; 1]).collect();
+        assert_eq!(voice_style(&full, 7), Some([7.0; 256]));
+        assert_eq!(
+            voice_style(&full, 600),
+            Some([MAX_CHUNK_TOKENS as f32; 256])
+        );
+        // Three rows spread over every length, so a quarter of the way is between the first two
+        let compact = vec![[[0.0; 256]; 1], [[2.0; 256]; 1], [[4.0; 256]; 1]];
+        assert_eq!(voice_style(&compact, 0), Some([0.0; 256]));
+        assert_eq!(voice_style(&compact, MAX_CHUNK_TOKENS), Some([4.0; 256]));
+        let style = voice_style(&compact, MAX_CHUNK_TOKENS / 4).unwrap();
+        assert!((style[0] - 1.0).abs() < 0.01, "{}", style[0]);
+        assert_eq!(voice_style(&vec![[[3.0; 256]; 1]], 100), Some([3.0; 256]));
+        assert_eq!(voice_style(&Vec::new(), 0), None);
+    }
+}
@@ -0,0 +1,156 @@
+//! Joining synthesized segments into one track, sample for sample, while keeping track of
+//! where each one landed. Timestamps, subtitles and read-along files are placed from the
+//! same positions the audio is built from, so they can't drift apart over a long text.
+
+/// How a segment meets the audio before it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Join {
+    /// Straight after it
+    #[default]
+    Butt,
+    /// After this many seconds of silence
+    Silence(f32),
+    /// Overlapping its last this many seconds, fading one into the other. The overlap is
+    /// cut short when either side is shorter.
+    Crossfade(f32),
+}
+
+/// Where a segment is in the assembled audio, as a range of samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub start: usize,
+    pub end: usize,
+    sample_rate: u32,
+}
+
+impl Placement {
+    pub fn start_sec(&self) -> f32 {
+        (self.start as f64 / self.sample_rate as f64) as f32
+    }
+
+    pub fn end_sec(&self) -> f32 {
+        (self.end as f64 / self.sample_rate as f64) as f32
+    }
+
+    /// A time in seconds within the segment as a time in the assembled audio
+    pub fn to_track(&self, seconds: f32) -> f32 {
+        ((self.start as f64 / self.sample_rate as f64) + seconds as f64) as f32
+    }
+}
+
+/// Audio built from segments one after another
+#[derive(Debug, Clone)]
+pub struct AudioAssembler {
+    sample_rate: u32,
+    samples: Vec<f32>,
+    placements: Vec<Placement>,
+}
+
+impl AudioAssembler {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            samples: Vec::new(),
+            placements: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn to_samples(&self, seconds: f32) -> usize {
+        (seconds.max(0.0) as f64 * self.sample_rate as f64).round() as usize
+    }
+
+    /// Appends `segment`, joined to the audio so far by `join`; the first segment starts
+    /// at 0 whatever `join` is
+    pub fn push(&mut self, segment: &[f32], join: Join) -> Placement {
+        let start = match join {
+            _ if self.samples.is_empty() => 0,
+            Join::Butt => self.samples.len(),
+            Join::Silence(seconds) => {
+                let end = self.samples.len() + self.to_samples(seconds);
+                self.samples.resize(end, 0.0);
+                end
+            }
+            Join::Crossfade(seconds) => {
+                let overlap = self
+                    .to_samples(seconds)
+                    .min(self.samples.len())
+                    .min(segment.len());
+                let start = self.samples.len() - overlap;
+                for (i, (old, new)) in self.samples[start..].iter_mut().zip(segment).enumerate() {
+                    // Equal power, so speech doesn't dip in the middle of the fade
+                    let t = (i as f32 + 0.5) / overlap as f32 * std::f32::consts::FRAC_PI_2;
+                    *old = *old * t.cos() + new * t.sin();
+                }
+                self.samples.extend_from_slice(&segment[overlap..]);
+                return self.place(start);
+            }
+        };
+        self.samples.extend_from_slice(segment);
+        self.place(start)
+    }
+
+    fn place(&mut self, start: usize) -> Placement {
+        let placement = Placement {
+            start,
+            end: self.samples.len(),
+            sample_rate: self.sample_rate,
+        };
+        self.placements.push(placement);
+        placement
+    }
+
+    /// Where each segment pushed so far is, in order
+    pub fn placements(&self) -> &[Placement] {
+        &self.placements
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn duration_sec(&self) -> f32 {
+        (self.samples.len() as f64 / self.sample_rate as f64) as f32
+    }
+
+    pub fn into_samples(self) -> Vec<f32> {
+        self.samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assembler() {
+        let mut assembler = AudioAssembler::new(1000);
+        let first = assembler.push(&[1.0; 100], Join::Silence(0.5));
+        assert_eq!((first.start, first.end), (0, 100));
+        let second = assembler.push(&[1.0; 100], Join::Silence(0.05));
+        assert_eq!((second.start, second.end), (150, 250));
+        assert_eq!(assembler.samples()[120], 0.0);
+        assert_eq!(second.to_track(0.01), 0.16);
+
+        let third = assembler.push(&[1.0; 40], Join::Crossfade(0.02));
+        assert_eq!((third.start, third.end), (230, 270));
+        // Equal-power fade between two equal signals peaks in the middle
+        assert!(assembler.samples()[240] > 1.0);
+        // The overlap is capped by the segment
+        let fourth = assembler.push(&[0.5; 10], Join::Crossfade(1.0));
+        assert_eq!((fourth.start, fourth.end), (260, 270));
+        assert_eq!(assembler.placements().len(), 4);
+        assert_eq!(assembler.duration_sec(), 0.27);
+    }
+}
@@ -0,0 +1,203 @@
+//! Simple post-processing of synthesized audio
+
+use std::ops::Range;
+
+/// Samples below this level (about -40 dBFS) count as silence when trimming
+const SILENCE_THRESHOLD: f32 = 0.01;
+/// Silence kept at either end when trimming, so speech doesn't start or stop abruptly
+const TRIM_MARGIN_SECONDS: f32 = 0.05;
+/// Cutoff of [`high_pass`] with `--high-pass`: under the lowest voices, over the rumble
+/// some of them carry
+pub const HIGH_PASS_HZ: f32 = 60.0;
+
+/// The part of `audio` left after dropping leading and trailing silence, keeping a short
+/// margin; empty if it is all silence
+pub fn speech_range(audio: &[f32], sample_rate: u32) -> Range<usize> {
+    let margin = (TRIM_MARGIN_SECONDS * sample_rate as f32) as usize;
+    let loud = |s: &f32| s.abs() >= SILENCE_THRESHOLD;
+    match (audio.iter().position(loud), audio.iter().rposition(loud)) {
+        (Some(first), Some(last)) => {
+            first.saturating_sub(margin)..(last + 1 + margin).min(audio.len())
+        }
+        _ => 0..0,
+    }
+}
+
+/// Drops leading and trailing silence from `audio`, returning how many samples were
+/// removed from the start
+pub fn trim_silence(audio: &mut Vec<f32>, sample_rate: u32) -> usize {
+    let range = speech_range(audio, sample_rate);
+    audio.truncate(range.end);
+    audio.drain(..range.start);
+    range.start
+}
+
+/// RMS level of `audio` in dBFS, or `None` for silence
+pub fn rms_dbfs(audio: &[f32]) -> Option<f32> {
+    let mean_square = audio.iter().map(|s| s * s).sum::<f32>() / audio.len().max(1) as f32;
+    (mean_square > 0.0).then(|| 10.0 * mean_square.log10())
+}
+
+/// Scales `audio` to an RMS level of `target_dbfs` (e.g. -20), but never so far that it
+/// would clip
+pub fn normalize_loudness(audio: &mut [f32], target_dbfs: f32) {
+    let Some(level) = rms_dbfs(audio) else {
+        return;
+    };
+    let peak = audio.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    let gain = 10f32.powf((target_dbfs - level) / 20.0).min(1.0 / peak);
+    for sample in audio {
+        *sample *= gain;
+    }
+}
+
+/// Removes DC offset and rumble below `cutoff_hz` from `audio`, with a second-order
+/// Butterworth high-pass filter (12 dB per octave)
+pub fn high_pass(audio: &mut [f32], sample_rate: u32, cutoff_hz: f32) {
+    let w = 2.0 * std::f64::consts::PI * cutoff_hz as f64 / sample_rate as f64;
+    let alpha = w.sin() / std::f64::consts::SQRT_2;
+    let a0 = 1.0 + alpha;
+    let b0 = (1.0 + w.cos()) / 2.0 / a0;
+    let (b1, b2) = (-2.0 * b0, b0);
+    let (a1, a2) = (-2.0 * w.cos() / a0, (1.0 - alpha) / a0);
+
+    let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+    for sample in audio {
+        let x = *sample as f64;
+        let y = b0 * x + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2;
+        (x2, x1, y2, y1) = (x1, x, y1, y);
+        *sample = y as f32;
+    }
+}
+
+/// `seconds` of silence
+pub fn silence(seconds: f32, sample_rate: u32) -> Vec<f32> {
+    vec![0.0; (seconds.max(0.0) * sample_rate as f32) as usize]
+}
+
+/// A short 1 kHz tone, faded in and out so it doesn't click, e.g. for `[beep]`
+pub fn beep(sample_rate: u32) -> Vec<f32> {
+    const SECONDS: f32 = 0.25;
+    const FADE_SECONDS: f32 = 0.01;
+    let len = (SECONDS * sample_rate as f32) as usize;
+    let fade = (FADE_SECONDS * sample_rate as f32) as usize;
+    (0..len)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            let envelope = (i.min(len - 1 - i) as f32 / fade as f32).min(1.0);
+            0.3 * envelope * (2.0 * std::f32::consts::PI * 1000.0 * t).sin()
+        })
+        .collect()
+}
+
+/// `audio` at `from` Hz resampled to `to` Hz: each output sample averages the input it
+/// covers when downsampling, which keeps most aliasing out, and is interpolated when
+/// upsampling
+pub fn resample(audio: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || audio.is_empty() {
+        return audio.to_vec();
+    }
+    let step = from as f64 / to as f64;
+    let len = (audio.len() as f64 / step) as usize;
+    (0..len)
+        .map(|i| {
+            let start = i as f64 * step;
+            if step > 1.0 {
+                let (first, last) = (start as usize, ((start + step) as usize).min(audio.len()));
+                let covered = &audio[first..last.max(first + 1)];
+                covered.iter().sum::<f32>() / covered.len() as f32
+            } else {
+                let (index, fraction) = (start as usize, start.fract() as f32);
+                let next = audio.get(index + 1).copied().unwrap_or(audio[index]);
+                audio[index] + (next - audio[index]) * fraction
+            }
+        })
+        .collect()
+}
+
+/// A sample as a signed 16-bit integer, clipped to its range
+pub fn sample_to_i16(sample: f32) -> i16 {
+    (sample * 32767.0).clamp(-32768.0, 32767.0) as i16
+}
+
+/// Samples as signed 16-bit little-endian PCM, clipped to its range
+pub fn to_pcm16(audio: &[f32]) -> Vec<u8> {
+    audio
+        .iter()
+        .flat_map(|&s| sample_to_i16(s).to_le_bytes())
+        .collect()
+}
+
+/// How samples are handed to the caller: as the model's floats, or converted once to
+/// integers for players and codecs that take 16-bit PCM
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SampleFormat {
+    #[default]
+    F32,
+    I16,
+}
+
+/// Audio in a [`SampleFormat`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Samples {
+    F32(Vec<f32>),
+    I16(Vec<i16>),
+}
+
+impl Samples {
+    /// `audio` in `format`, converted only if it is not f32 already
+    pub fn new(audio: Vec<f32>, format: SampleFormat) -> Self {
+        match format {
+            SampleFormat::F32 => Self::F32(audio),
+            SampleFormat::I16 => Self::I16(audio.into_iter().map(sample_to_i16).collect()),
+        }
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        match self {
+            Self::F32(_) => SampleFormat::F32,
+            Self::I16(_) => SampleFormat::I16,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::F32(samples) => samples.len(),
+            Self::I16(samples) => samples.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trim_and_normalize() {
+        let mut audio = silence(1.0, 1000);
+        audio[400..600].fill(0.1);
+        assert_eq!(trim_silence(&mut audio, 1000), 350);
+        assert_eq!(audio.len(), 300);
+
+        normalize_loudness(&mut audio, -6.0);
+        assert!((rms_dbfs(&audio).unwrap() - -6.0).abs() < 0.5);
+        // Limited by the peak rather than clipping
+        normalize_loudness(&mut audio, 0.0);
+        assert!(audio.iter().all(|s| s.abs() <= 1.0 + 1e-6));
+
+        // A tone on a DC offset keeps the tone and loses the offset
+        let mut tone: Vec<f32> = beep(24_000).iter().map(|s| s + 0.2).collect();
+        high_pass(&mut tone, 24_000, HIGH_PASS_HZ);
+        let settled = &tone[3000..6000];
+        assert!((settled.iter().sum::<f32>() / settled.len() as f32).abs() < 0.01);
+        assert!(settled.iter().any(|s| s.abs() > 0.25));
+
+        assert_eq!(beep(24_000).len(), 6000);
+        assert_eq!(resample(&[0.0, 1.0, 0.0, 1.0], 48_000, 24_000), [0.5, 0.5]);
+        assert_eq!(resample(&[0.0, 1.0], 12_000, 24_000), [0.0, 0.5, 1.0, 1.0]);
+    }
+}
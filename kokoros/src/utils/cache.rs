@@ -0,0 +1,279 @@
+//! Cache of encoded audio for phrases that are synthesized again and again (IVR menus,
+//! alerts, ...), kept in memory and optionally on disk
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Extension of cached audio files in the cache directory
+const ENTRY_EXTENSION: &str = "audio";
+
+/// What a cached phrase was synthesized from; equal keys give the same audio
+#[derive(Debug, Clone, Copy)]
+pub struct CacheKey<'a> {
+    pub text: &'a str,
+    pub voice: &'a str,
+    /// espeak-ng language the text is phonemized in
+    pub lan: &'a str,
+    pub speed: f32,
+    pub initial_silence: Option<usize>,
+    /// Encoding of the audio, e.g. "mp3"
+    pub format: &'a str,
+    /// Loudness in LUFS the audio was leveled to
+    pub loudness: Option<f32>,
+    /// Version of the model the audio comes from, so keys change when the model does
+    pub model: &'a str,
+    /// What else the audio depends on, e.g. a server tenant's lexicon and voices or how
+    /// the audio was processed after synthesis; empty when nothing else does
+    pub scope: &'a str,
+}
+
+impl CacheKey<'_> {
+    /// Hex-encoded digest of the key. Runs of whitespace in the text don't matter, since
+    /// they don't change what is spoken.
+    pub fn digest(&self) -> String {
+        let text = self.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut hasher = Sha256::new();
+        for part in [text.as_str(), self.voice, self.lan, self.format, self.model] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(self.speed.to_le_bytes());
+        hasher.update(self.initial_silence.unwrap_or(0).to_le_bytes());
+        if let Some(loudness) = self.loudness {
+            hasher.update(loudness.to_le_bytes());
+        }
+        if !self.scope.is_empty() {
+            hasher.update(self.scope.as_bytes());
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+struct Entry {
+    audio: Arc<Vec<u8>>,
+    created: SystemTime,
+    /// Value of `State::clock` when the entry was last read or written
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    bytes: u64,
+    clock: u64,
+}
+
+/// Encoded audio by [`CacheKey`], holding at most `max_bytes`; the least recently used
+/// entries are evicted first, and entries older than the TTL are never returned
+pub struct AudioCache {
+    dir: Option<PathBuf>,
+    max_bytes: u64,
+    ttl: Option<Duration>,
+    state: Mutex<State>,
+}
+
+impl AudioCache {
+    /// A cache that only lives in memory
+    pub fn new(max_bytes: u64, ttl: Option<Duration>) -> Self {
+        Self {
+            dir: None,
+            max_bytes,
+            ttl,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// A cache that also keeps every entry as a file in `dir`, and starts with the entries
+    /// left there by earlier runs
+    pub fn persistent(dir: &Path, max_bytes: u64, ttl: Option<Duration>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let cache = Self {
+            dir: Some(dir.to_path_buf()),
+            ..Self::new(max_bytes, ttl)
+        };
+        let mut found = Vec::new();
+        for file in fs::read_dir(dir)? {
+            let path = file?.path();
+            if path.extension().is_none_or(|e| e != ENTRY_EXTENSION) {
+                continue;
+            }
+            let Some(key) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let created = fs::metadata(&path)?.modified()?;
+            found.push((created, key.to_string(), path));
+        }
+        // Oldest first, so they are the first to go if the limit has shrunk
+        found.sort();
+        {
+            let mut state = cache.state.lock().unwrap();
+            for (created, key, path) in found {
+                if cache.expired(created) {
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+                let audio = Arc::new(fs::read(&path)?);
+                cache.add(&mut state, key, audio, created);
+            }
+            tracing::debug!(
+                "Loaded {} cached phrases ({} bytes) from {}",
+                state.entries.len(),
+                state.bytes,
+                dir.display()
+            );
+        }
+        Ok(cache)
+    }
+
+    fn expired(&self, created: SystemTime) -> bool {
+        self.ttl
+            .is_some_and(|ttl| created.elapsed().unwrap_or_default() >= ttl)
+    }
+
+    fn entry_path(&self, key: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(format!("{}.{}", key, ENTRY_EXTENSION)))
+    }
+
+    fn remove(&self, state: &mut State, key: &str) {
+        if let Some(entry) = state.entries.remove(key) {
+            state.bytes -= entry.audio.len() as u64;
+            if let Some(path) = self.entry_path(key) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Adds an entry and evicts the least recently used ones until the cache fits its limit
+    fn add(&self, state: &mut State, key: String, audio: Arc<Vec<u8>>, created: SystemTime) {
+        // A replaced entry's file has just been overwritten, so it stays
+        if let Some(old) = state.entries.remove(&key) {
+            state.bytes -= old.audio.len() as u64;
+        }
+        state.clock += 1;
+        state.bytes += audio.len() as u64;
+        let last_used = state.clock;
+        state.entries.insert(
+            key,
+            Entry {
+                audio,
+                created,
+                last_used,
+            },
+        );
+        while state.bytes > self.max_bytes {
+            let Some(oldest) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove(state, &oldest);
+        }
+    }
+
+    /// The cached audio for `key`, unless there is none or it has expired
+    pub fn get(&self, key: &CacheKey) -> Option<Arc<Vec<u8>>> {
+        let key = key.digest();
+        let mut state = self.state.lock().unwrap();
+        let created = state.entries.get(&key)?.created;
+        if self.expired(created) {
+            self.remove(&mut state, &key);
+            return None;
+        }
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(&key)?;
+        entry.last_used = clock;
+        Some(Arc::clone(&entry.audio))
+    }
+
+    /// Caches `audio` for `key`, on disk too for a persistent cache. Audio larger than the
+    /// whole cache isn't kept.
+    pub fn insert(&self, key: &CacheKey, audio: Vec<u8>) -> io::Result<()> {
+        if audio.len() as u64 > self.max_bytes {
+            return Ok(());
+        }
+        let key = key.digest();
+        if let Some(path) = self.entry_path(&key) {
+            fs::write(path, &audio)?;
+        }
+        let mut state = self.state.lock().unwrap();
+        self.add(&mut state, key, Arc::new(audio), SystemTime::now());
+        Ok(())
+    }
+
+    /// Number of cached phrases
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_cache() {
+        let key = |text| CacheKey {
+            text,
+            voice: "af_sky",
+            lan: "en-us",
+            speed: 1.0,
+            initial_silence: None,
+            format: "mp3",
+            loudness: None,
+            model: "",
+            scope: "",
+        };
+        assert_eq!(key("Press  one.").digest(), key("Press one.").digest());
+        assert_ne!(
+            key("Press one.").digest(),
+            CacheKey {
+                speed: 1.1,
+                ..key("Press one.")
+            }
+            .digest()
+        );
+        assert_ne!(
+            key("Press one.").digest(),
+            CacheKey {
+                scope: "tenant",
+                ..key("Press one.")
+            }
+            .digest()
+        );
+
+        let dir = std::env::temp_dir().join(format!("kokoros-cache-{}", std::process::id()));
+        let cache = AudioCache::persistent(&dir, 10, None).unwrap();
+        cache.insert(&key("one"), vec![1; 4]).unwrap();
+        cache.insert(&key("two"), vec![2; 4]).unwrap();
+        assert!(cache.get(&key("one")).is_some());
+        // "two" is the least recently used, so it makes way
+        cache.insert(&key("three"), vec![3; 4]).unwrap();
+        assert!(cache.get(&key("two")).is_none());
+        assert_eq!(cache.len(), 2);
+
+        let reopened = AudioCache::persistent(&dir, 10, None).unwrap();
+        assert_eq!(reopened.get(&key("three")).unwrap().as_slice(), &[3; 4]);
+        assert_eq!(reopened.len(), 2);
+        let expiring = AudioCache::persistent(&dir, 10, Some(Duration::ZERO)).unwrap();
+        assert!(expiring.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Name of the lockfile written next to downloaded files, in `sha256sum` format
+pub const LOCKFILE_NAME: &str = "SHA256SUMS";
+
+/// Hex-encoded SHA-256 of a file's contents
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Short identity of a model file for content-derived keys: the first 16 hex digits of its
+/// SHA-256, taken from the lockfile next to it when that lists the file, so a model that
+/// was checked at startup isn't read a second time
+pub fn model_version(path: &Path) -> io::Result<String> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let lockfile = path.with_file_name(LOCKFILE_NAME);
+    let listed = std::fs::read_to_string(lockfile)
+        .ok()
+        .and_then(|content| parse_lockfile(&content).remove(name.as_ref()));
+    let digest = match listed {
+        Some(digest) => digest,
+        None => sha256_file(path)?,
+    };
+    Ok(digest.chars().take(16).collect())
+}
+
+/// Parses `sha256sum`-style lines (`<hex digest>  <file name>`) into file name -> digest
+pub fn parse_lockfile(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (digest, name) = line.trim().split_once(char::is_whitespace)?;
+            // `sha256sum -b` marks binary files with a leading '*'
+            let name = name.trim().trim_start_matches('*');
+            Some((name.to_string(), digest.to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+/// Writes a lockfile recording the digests of `files`, keyed by file name
+pub fn write_lockfile(lockfile: &Path, files: &[&Path]) -> io::Result<()> {
+    let mut content = String::new();
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        content.push_str(&format!("{}  {}\n", sha256_file(file)?, name));
+    }
+    std::fs::write(lockfile, content)
+}
+
+/// Checks each file that is listed in the lockfile against its recorded digest.
+/// Files the lockfile doesn't mention, or that don't exist yet, are not checked.
+pub fn verify_files(lockfile: &Path, files: &[&Path]) -> Result<(), String> {
+    let content = std::fs::read_to_string(lockfile)
+        .map_err(|e| format!("failed to read {}: {}", lockfile.display(), e))?;
+    let expected = parse_lockfile(&content);
+
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let Some(expected) = expected.get(name.as_ref()) else {
+            continue;
+        };
+        if !file.exists() {
+            continue;
+        }
+        let actual =
+            sha256_file(file).map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+        if &actual != expected {
+            return Err(format!(
+                "checksum mismatch for {}: expected {}, got {} (listed in {}). \
+                 The file is probably corrupted or only partially downloaded; \
+                 delete it and download it again.",
+                file.display(),
+                expected,
+                actual,
+                lockfile.display()
+            ));
+        }
+        tracing::debug!("Checksum OK for {}", file.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_files() {
+        let dir = std::env::temp_dir().join(format!("kokoros-checksum-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let model = dir.join("model.onnx");
+        std::fs::write(&model, b"abc").unwrap();
+        assert_eq!(
+            sha256_file(&model).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let lockfile = dir.join(LOCKFILE_NAME);
+        assert_eq!(model_version(&model).unwrap(), "ba7816bf8f01cfea");
+        write_lockfile(&lockfile, &[&model]).unwrap();
+        assert!(verify_files(&lockfile, &[&model]).is_ok());
+
+        std::fs::write(&model, b"abd").unwrap();
+        assert!(verify_files(&lockfile, &[&model]).is_err());
+        // Read from the lockfile, not the changed file
+        assert_eq!(model_version(&model).unwrap(), "ba7816bf8f01cfea");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,64 @@
 use indicatif::{ProgressBar, ProgressStyle};
 use serde_json::Value;
-use std::{io::Read, path::Path};
-use tokio::{fs::File, io::AsyncWriteExt};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
 
+/// Hugging Face repository the model and voices are downloaded from by default
+pub const DEFAULT_MODEL_REPO: &str = "fastrtc/kokoro-onnx";
+pub const MODEL_FILE: &str = "kokoro-v1.0.onnx";
+pub const VOICES_FILE: &str = "voices-v1.0.bin";
+
+/// Directory downloaded models are cached in: `$XDG_CACHE_HOME/kokoros`, falling back to
+/// `~/.cache/kokoros`
+pub fn cache_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(|| PathBuf::from(".cache"));
+    base.join("kokoros")
+}
+
+/// Directory for voices created by the user (e.g. with `koko voices mix`):
+/// `$XDG_DATA_HOME/kokoros/voices`, falling back to `~/.local/share/kokoros/voices`
+pub fn voices_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+        })
+        .unwrap_or_else(|| PathBuf::from(".local/share"));
+    base.join("kokoros").join("voices")
+}
+
+/// Returns `path` if it exists, otherwise the file of the same name in the cache
+/// directory when that exists, so `koko download` results are picked up automatically
+pub fn resolve_cached(path: &str) -> String {
+    if Path::new(path).exists() {
+        return path.to_string();
+    }
+    match Path::new(path).file_name() {
+        Some(name) if cache_dir().join(name).exists() => {
+            cache_dir().join(name).to_string_lossy().into_owned()
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// URL of a file in a Hugging Face repository at a given revision (branch, tag or commit)
+pub fn hugging_face_url(repo: &str, revision: &str, file: &str) -> String {
+    format!(
+        "https://huggingface.co/{}/resolve/{}/{}",
+        repo, revision, file
+    )
+}
+
+/// Downloads `url` to `path`. Data goes to `<path>.part` first, and an interrupted
+/// download is resumed from where it stopped when the server supports range requests.
 pub async fn download_file_from_url(
     url: &str,
     path: &str,
@@ -11,12 +67,28 @@ pub async fn download_file_from_url(
         std::fs::create_dir_all(parent)?;
     }
 
-    let mut resp = reqwest::get(url).await?;
+    let part_path = format!("{}.part", path);
+    let existing = std::fs::metadata(&part_path).map_or(0, |m| m.len());
+
+    let mut request = reqwest::Client::new().get(url);
+    if existing > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+    }
+    let mut resp = request.send().await?;
 
     if resp.status().is_success() {
-        let total_size = resp.content_length().unwrap_or(0);
+        let resumed = resp.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+        let offset = if resumed { existing } else { 0 };
+        let total_size = resp.content_length().unwrap_or(0) + offset;
 
-        eprintln!("Downloading {} - total size: {}", path, total_size);
+        if resumed {
+            eprintln!(
+                "Resuming download of {} at {} of {} bytes",
+                path, offset, total_size
+            );
+        } else {
+            eprintln!("Downloading {} - total size: {}", path, total_size);
+        }
 
         let pb = ProgressBar::new(total_size);
         pb.set_style(ProgressStyle::default_bar()
@@ -24,14 +96,23 @@ pub async fn download_file_from_url(
             .unwrap()
             .progress_chars("#>-"));
 
-        let mut file = File::create(path).await?;
-        let mut downloaded = 0;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&part_path)
+            .await?;
+        let mut downloaded = offset;
 
         while let Some(chunk) = resp.chunk().await? {
             file.write_all(&chunk).await?;
-            downloaded += chunk.len();
-            pb.set_position(downloaded.try_into()?);
+            downloaded += chunk.len() as u64;
+            pb.set_position(downloaded);
         }
+        file.flush().await?;
+        drop(file);
+        tokio::fs::rename(&part_path, path).await?;
 
         pb.finish_with_message("Download completed");
         Ok(())
@@ -0,0 +1,157 @@
+//! Integrated loudness after ITU-R BS.1770 / EBU R128, in LUFS: the level listeners
+//! perceive, which is what podcast (-16), broadcast (-23) and other delivery specs are
+//! written against. Audio is K-weighted (a high shelf for the head's effect and a
+//! high-pass below about 40 Hz), measured in overlapping 400 ms blocks, and averaged over
+//! the blocks that aren't silence or far quieter than the rest.
+
+use std::f64::consts::PI;
+
+/// Length of a measurement block
+const BLOCK_SECONDS: f64 = 0.4;
+/// Blocks start this far apart, overlapping by 75%
+const STEP_SECONDS: f64 = 0.1;
+/// Blocks below this are silence and left out
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks this far below the loudness of the others are left out too
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// A second-order IIR filter in direct form I
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The two stages of the K-weighting filter for `sample_rate`, with the coefficients of
+/// BS.1770 derived for rates other than 48 kHz
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+    [shelf, high_pass]
+}
+
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Integrated loudness of mono `audio` in LUFS, or `None` for silence. Audio shorter than
+/// a block is measured as one block.
+pub fn integrated_lufs(audio: &[f32], sample_rate: u32) -> Option<f32> {
+    let [mut shelf, mut high_pass] = k_weighting(sample_rate);
+    let squares: Vec<f64> = audio
+        .iter()
+        .map(|&s| high_pass.process(shelf.process(s as f64)).powi(2))
+        .collect();
+
+    let block = ((BLOCK_SECONDS * sample_rate as f64) as usize).clamp(1, squares.len().max(1));
+    let step = ((STEP_SECONDS * sample_rate as f64) as usize).max(1);
+    // Running sums, so every block costs the same however long it is
+    let mut sums = Vec::with_capacity(squares.len() + 1);
+    sums.push(0.0);
+    for square in &squares {
+        sums.push(sums.last().unwrap() + square);
+    }
+    let blocks: Vec<f64> = (0..=squares.len().saturating_sub(block))
+        .step_by(step)
+        .map(|start| (sums[start + block] - sums[start]) / block as f64)
+        .filter(|&power| power > 0.0 && lufs(power) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let mean = |blocks: &mut dyn Iterator<Item = f64>| {
+        let (sum, count) = blocks.fold((0.0, 0), |(sum, count), p| (sum + p, count + 1));
+        sum / count.max(1) as f64
+    };
+    let threshold = lufs(mean(&mut blocks.iter().copied())) + RELATIVE_GATE_LU;
+    let gated = mean(&mut blocks.iter().copied().filter(|&p| lufs(p) > threshold));
+    (gated > 0.0).then(|| lufs(gated) as f32)
+}
+
+/// Scales mono `audio` to an integrated loudness of `target_lufs` (e.g. -16), but never so
+/// far that it would clip
+pub fn normalize(audio: &mut [f32], sample_rate: u32, target_lufs: f32) {
+    let Some(level) = integrated_lufs(audio, sample_rate) else {
+        return;
+    };
+    let peak = audio.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    let gain = 10f32.powf((target_lufs - level) / 20.0).min(1.0 / peak);
+    for sample in audio {
+        *sample *= gain;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integrated_lufs() {
+        let tone = |rate: u32, amplitude: f32| -> Vec<f32> {
+            (0..rate * 3)
+                .map(|i| {
+                    amplitude * (2.0 * std::f32::consts::PI * 997.0 * i as f32 / rate as f32).sin()
+                })
+                .collect()
+        };
+        // A full-scale 997 Hz tone is -3.01 LUFS by definition
+        let level = integrated_lufs(&tone(48_000, 1.0), 48_000).unwrap();
+        assert!((level - -3.01).abs() < 0.05, "{}", level);
+        let level = integrated_lufs(&tone(24_000, 1.0), 24_000).unwrap();
+        assert!((level - -3.01).abs() < 0.1, "{}", level);
+        assert_eq!(integrated_lufs(&[0.0; 24_000], 24_000), None);
+
+        let mut audio = tone(24_000, 0.1);
+        // A quiet stretch is gated out rather than pulling the level down
+        audio.extend(vec![0.0; 48_000]);
+        normalize(&mut audio, 24_000, -16.0);
+        let level = integrated_lufs(&audio, 24_000).unwrap();
+        assert!((level - -16.0).abs() < 0.1, "{}", level);
+        // Limited by the peak rather than clipping
+        normalize(&mut audio, 24_000, 0.0);
+        assert!(audio.iter().all(|s| s.abs() <= 1.0 + 1e-6));
+    }
+}
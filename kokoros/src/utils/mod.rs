@@ -1,5 +1,16 @@
+pub mod assembler;
+pub mod audio;
+pub mod cache;
+pub mod checksum;
 pub mod debug;
+#[cfg(feature = "download")]
 pub mod fileio;
+pub mod loudness;
+#[cfg(feature = "mp3")]
 pub mod mp3;
+#[cfg(feature = "opus")]
 pub mod opus;
+pub mod rtp;
+pub mod watermark;
 pub mod wav;
+pub mod wer;
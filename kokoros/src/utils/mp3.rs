@@ -84,3 +84,81 @@ pub fn pcm_to_mp3(pcm_data: &[f32], sample_rate: u32) -> Result<Vec<u8>, std::io
 
     Ok(mp3_out_buffer)
 }
+
+/// Encodes mono audio to MP3 as it is synthesized, for continuous streams such as an
+/// Icecast mount, where the output of each [`Mp3StreamEncoder::encode`] call is sent on
+/// as it is
+pub struct Mp3StreamEncoder {
+    encoder: mp3lame_encoder::Encoder,
+}
+
+impl Mp3StreamEncoder {
+    /// An encoder at a constant `kbps` (8, 16, 24, 32, 40, 48, 64, 80, 96, 112, 128, 160,
+    /// 192, 224, 256 or 320)
+    pub fn new(sample_rate: u32, kbps: u32) -> Result<Self, std::io::Error> {
+        use mp3lame_encoder::Bitrate;
+        let bitrate = match kbps {
+            8 => Bitrate::Kbps8,
+            16 => Bitrate::Kbps16,
+            24 => Bitrate::Kbps24,
+            32 => Bitrate::Kbps32,
+            40 => Bitrate::Kbps40,
+            48 => Bitrate::Kbps48,
+            64 => Bitrate::Kbps64,
+            80 => Bitrate::Kbps80,
+            96 => Bitrate::Kbps96,
+            112 => Bitrate::Kbps112,
+            128 => Bitrate::Kbps128,
+            160 => Bitrate::Kbps160,
+            192 => Bitrate::Kbps192,
+            224 => Bitrate::Kbps224,
+            256 => Bitrate::Kbps256,
+            320 => Bitrate::Kbps320,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{} kbps is not an MP3 bitrate", kbps),
+                ));
+            }
+        };
+        let error = |action: &str, e: &dyn std::fmt::Debug| {
+            std::io::Error::other(format!("{} failed: {:?}", action, e))
+        };
+        let mut builder =
+            Builder::new().ok_or_else(|| std::io::Error::other("Encoder init failed"))?;
+        builder
+            .set_num_channels(1)
+            .map_err(|e| error("Set channels", &e))?;
+        builder
+            .set_sample_rate(sample_rate)
+            .map_err(|e| error("Set sample rate", &e))?;
+        builder
+            .set_brate(bitrate)
+            .map_err(|e| error("Set bitrate", &e))?;
+        let encoder = builder.build().map_err(|e| error("Build encoder", &e))?;
+        Ok(Self { encoder })
+    }
+
+    /// The MP3 data for `audio`; the encoder holds back a little audio until it has a full
+    /// frame, so this may be empty
+    pub fn encode(&mut self, audio: &[f32]) -> Result<Vec<u8>, std::io::Error> {
+        let pcm: Vec<i16> = audio
+            .iter()
+            .map(|&x| (x * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+            .collect();
+        let mut mp3 = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(pcm.len()));
+        self.encoder
+            .encode_to_vec(MonoPcm(&pcm), &mut mp3)
+            .map_err(|e| std::io::Error::other(format!("Encoding failed: {:?}", e)))?;
+        Ok(mp3)
+    }
+
+    /// The MP3 data for the audio still held back, at the end of the stream
+    pub fn finish(mut self) -> Result<Vec<u8>, std::io::Error> {
+        let mut mp3 = Vec::with_capacity(7200);
+        self.encoder
+            .flush_to_vec::<FlushNoGap>(&mut mp3)
+            .map_err(|e| std::io::Error::other(format!("Flush failed: {:?}", e)))?;
+        Ok(mp3)
+    }
+}
@@ -1,8 +1,228 @@
 use ogg::{PacketWriteEndInfo, PacketWriter};
 use opus::{Application, Bitrate, Channels, Encoder};
-use std::io::Cursor;
+use std::io::{Cursor, Write};
+use std::ops::RangeInclusive;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Bitrates for [`OpusFrameEncoder`], in bits per second: enough for intelligible speech on
+/// small satellite speakers (ESP32, Raspberry Pi) at 8 kbps, close to transparent at 24
+pub const VOICE_BITRATES: RangeInclusive<u32> = 8_000..=24_000;
+
+/// Bitrate [`OpusFrameEncoder`] is used at when the caller has no preference
+pub const DEFAULT_VOICE_BITRATE: u32 = 16_000;
+
+/// Length of the frames [`OpusFrameEncoder`] produces
+pub const FRAME_MS: usize = 20;
+
+fn opus_error(action: &str, e: opus::Error) -> std::io::Error {
+    std::io::Error::other(format!("{} failed: {:?}", action, e))
+}
+
+/// Encodes audio to raw 20 ms Opus frames as it is synthesized, for streaming to clients
+/// that decode packet by packet rather than reading an Ogg file
+pub struct OpusFrameEncoder {
+    encoder: Encoder,
+    frame_size: usize,
+    /// Samples waiting for a full frame
+    pending: Vec<f32>,
+    packet: Vec<u8>,
+}
+
+impl OpusFrameEncoder {
+    /// An encoder for mono audio at `sample_rate` (8, 12, 16, 24 or 48 kHz), at `bitrate`
+    /// bits per second within [`VOICE_BITRATES`]
+    pub fn new(sample_rate: u32, bitrate: u32) -> Result<Self, std::io::Error> {
+        if !VOICE_BITRATES.contains(&bitrate) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Opus bitrate {} is outside {} to {} bits per second",
+                    bitrate,
+                    VOICE_BITRATES.start(),
+                    VOICE_BITRATES.end()
+                ),
+            ));
+        }
+        // The VoIP mode favors intelligibility, which matters more than fidelity this low
+        let mut encoder = Encoder::new(sample_rate, Channels::Mono, Application::Voip)
+            .map_err(|e| opus_error("Encoder init", e))?;
+        encoder
+            .set_bitrate(Bitrate::Bits(bitrate as i32))
+            .map_err(|e| opus_error("Set bitrate", e))?;
+        Ok(Self {
+            encoder,
+            frame_size: sample_rate as usize * FRAME_MS / 1000,
+            pending: Vec::new(),
+            packet: vec![0u8; 4000],
+        })
+    }
+
+    /// Adds `audio` and returns the frames it completes; the rest waits for more audio
+    pub fn push(&mut self, audio: &[f32]) -> Result<Vec<Vec<u8>>, std::io::Error> {
+        self.pending.extend_from_slice(audio);
+        let mut frames = Vec::new();
+        while self.pending.len() >= self.frame_size {
+            let samples: Vec<f32> = self.pending.drain(..self.frame_size).collect();
+            frames.push(self.encode(&samples)?);
+        }
+        Ok(frames)
+    }
+
+    /// The last frame, padded with silence, if any audio is left over. The encoder can go
+    /// on with more audio afterwards.
+    pub fn finish(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let mut samples = std::mem::take(&mut self.pending);
+        samples.resize(self.frame_size, 0.0);
+        self.encode(&samples).map(Some)
+    }
+
+    fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>, std::io::Error> {
+        let len = self
+            .encoder
+            .encode_float(samples, &mut self.packet)
+            .map_err(|e| opus_error("Encoding", e))?;
+        Ok(self.packet[..len].to_vec())
+    }
+}
+
+/// Writes an Ogg/Opus file as audio comes in, for audio too long to hold in memory at once
+pub struct OggOpusWriter<W: Write> {
+    packets: PacketWriter<'static, W>,
+    encoder: Encoder,
+    serial_no: u32,
+    sample_rate: u32,
+    /// Samples the encoder adds ahead of the audio, at 48 kHz
+    pre_skip: u64,
+    frame_size: usize,
+    /// Samples waiting for a full frame
+    pending: Vec<f32>,
+    /// Frame encoded but not written yet, as the last one has to end the stream
+    held: Option<Vec<u8>>,
+    samples_written: u64,
+    packet: Vec<u8>,
+}
+
+impl<W: Write> OggOpusWriter<W> {
+    /// A writer of mono audio at `sample_rate` and `bitrate`, with `tags` as the Vorbis
+    /// comments of the file (`TITLE`, `ARTIST`, `CHAPTER001`, ...)
+    pub fn new(
+        writer: W,
+        sample_rate: u32,
+        bitrate: u32,
+        tags: &[(&str, &str)],
+    ) -> Result<Self, std::io::Error> {
+        let mut encoder = Encoder::new(sample_rate, Channels::Mono, Application::Audio)
+            .map_err(|e| opus_error("Encoder init", e))?;
+        encoder
+            .set_bitrate(Bitrate::Bits(bitrate as i32))
+            .map_err(|e| opus_error("Set bitrate", e))?;
+        let lookahead = encoder
+            .get_lookahead()
+            .map_err(|e| opus_error("Get lookahead", e))? as u64;
+        let pre_skip = lookahead * 48_000 / sample_rate as u64;
+
+        let mut packets = PacketWriter::new(writer);
+        let serial_no = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(1);
+
+        let mut id_header = Vec::new();
+        id_header.extend_from_slice(b"OpusHead");
+        id_header.push(1); // Version
+        id_header.push(1); // Channels
+        id_header.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+        id_header.extend_from_slice(&sample_rate.to_le_bytes());
+        id_header.extend_from_slice(&0u16.to_le_bytes()); // Gain
+        id_header.push(0); // Mapping family
+        packets.write_packet(id_header, serial_no, PacketWriteEndInfo::EndPage, 0)?;
+
+        let mut comment_header = Vec::new();
+        comment_header.extend_from_slice(b"OpusTags");
+        let vendor = b"Kokoros TTS";
+        comment_header.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        comment_header.extend_from_slice(vendor);
+        comment_header.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+        for (key, value) in tags {
+            let comment = format!("{}={}", key, value);
+            comment_header.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            comment_header.extend_from_slice(comment.as_bytes());
+        }
+        packets.write_packet(comment_header, serial_no, PacketWriteEndInfo::EndPage, 0)?;
+
+        Ok(Self {
+            packets,
+            encoder,
+            serial_no,
+            sample_rate,
+            pre_skip,
+            frame_size: sample_rate as usize * FRAME_MS / 1000,
+            pending: Vec::new(),
+            held: None,
+            samples_written: 0,
+            packet: vec![0u8; 4000],
+        })
+    }
+
+    /// Encodes `audio`, holding back what doesn't fill a frame yet
+    pub fn write(&mut self, audio: &[f32]) -> Result<(), std::io::Error> {
+        self.pending.extend_from_slice(audio);
+        while self.pending.len() >= self.frame_size {
+            let samples: Vec<f32> = self.pending.drain(..self.frame_size).collect();
+            self.encode(&samples, samples.len())?;
+        }
+        Ok(())
+    }
+
+    /// Encodes what is left, padded with silence, and ends the stream
+    pub fn finish(mut self) -> Result<W, std::io::Error> {
+        if !self.pending.is_empty() || self.held.is_none() {
+            let mut samples = std::mem::take(&mut self.pending);
+            let len = samples.len();
+            samples.resize(self.frame_size, 0.0);
+            self.encode(&samples, len)?;
+        }
+        if let Some(packet) = self.held.take() {
+            let granule_pos = self.granule_pos();
+            self.packets.write_packet(
+                packet,
+                self.serial_no,
+                PacketWriteEndInfo::EndStream,
+                granule_pos,
+            )?;
+        }
+        Ok(self.packets.into_inner())
+    }
+
+    /// The granule position after the samples encoded so far, at 48 kHz
+    fn granule_pos(&self) -> u64 {
+        self.pre_skip + self.samples_written * 48_000 / self.sample_rate as u64
+    }
+
+    /// Encodes a frame of which `len` samples are audio, and writes out the previous one
+    fn encode(&mut self, samples: &[f32], len: usize) -> Result<(), std::io::Error> {
+        if let Some(packet) = self.held.take() {
+            let granule_pos = self.granule_pos();
+            self.packets.write_packet(
+                packet,
+                self.serial_no,
+                PacketWriteEndInfo::NormalPacket,
+                granule_pos,
+            )?;
+        }
+        let encoded = self
+            .encoder
+            .encode_float(samples, &mut self.packet)
+            .map_err(|e| opus_error("Encoding", e))?;
+        self.held = Some(self.packet[..encoded].to_vec());
+        self.samples_written += len as u64;
+        Ok(())
+    }
+}
+
 pub fn pcm_to_opus_ogg(pcm_data: &[f32], sample_rate: u32) -> Result<Vec<u8>, std::io::Error> {
     // 1. Initialize Opus encoder with Audio application (better for high quality TTS)
     let mut encoder =
@@ -128,3 +348,45 @@ pub fn pcm_to_opus_ogg(pcm_data: &[f32], sample_rate: u32) -> Result<Vec<u8>, st
 
     Ok(ogg_buffer.into_inner())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opus_frame_encoder() {
+        assert!(OpusFrameEncoder::new(24_000, 64_000).is_err());
+
+        let mut encoder = OpusFrameEncoder::new(24_000, 8_000).unwrap();
+        // One and a half 20 ms frames at 24 kHz
+        let frames = encoder.push(&[0.1; 720]).unwrap();
+        assert_eq!(frames.len(), 1);
+        // 8 kbps is 20 bytes per 20 ms frame, give or take
+        assert!(!frames[0].is_empty() && frames[0].len() < 100);
+        assert!(encoder.push(&[0.1; 100]).unwrap().is_empty());
+        assert!(encoder.finish().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_ogg_opus_writer() {
+        let mut writer =
+            OggOpusWriter::new(Vec::new(), 24_000, 32_000, &[("CHAPTER001", "00:00:00.000")])
+                .unwrap();
+        writer.write(&[0.1; 1000]).unwrap();
+        writer.write(&[0.1; 500]).unwrap();
+        let ogg = writer.finish().unwrap();
+
+        let mut reader = ogg::PacketReader::new(Cursor::new(ogg));
+        assert!(reader.read_packet().unwrap().unwrap().data.starts_with(b"OpusHead"));
+        let tags = reader.read_packet().unwrap().unwrap().data;
+        assert!(tags.ends_with(b"CHAPTER001=00:00:00.000"));
+        let mut last = None;
+        while let Some(packet) = reader.read_packet().unwrap() {
+            last = Some(packet);
+        }
+        // 1500 samples at 24 kHz are 3000 at 48 kHz, after the pre-skip
+        let last = last.unwrap();
+        assert!(last.last_in_stream());
+        assert!(last.absgp_page() > 3000 && last.absgp_page() < 4000);
+    }
+}
@@ -0,0 +1,470 @@
+//! Synthesized audio as RTP packets over UDP, e.g. to play IVR prompts straight into an
+//! Asterisk or FreeSWITCH call leg. Payloads are G.711 (μ-law or A-law, resampled to
+//! 8 kHz) or, with the `opus` feature, Opus at 20 ms per packet.
+//!
+//! [`RtpSink`] sends at real-time pace, as a call leg expects; [`RtpPacketizer`] only
+//! builds the packets, for callers with their own transport.
+
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "opus")]
+use crate::utils::opus::OpusFrameEncoder;
+
+/// Payload format of the packets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtpCodec {
+    /// G.711 μ-law (static payload type 0), as used on North American and Japanese lines
+    Pcmu,
+    /// G.711 A-law (static payload type 8), as used elsewhere
+    Pcma,
+    /// Opus at `bitrate` bits per second, with the dynamic payload type 111
+    #[cfg(feature = "opus")]
+    Opus { bitrate: u32 },
+}
+
+impl RtpCodec {
+    fn payload_type(self) -> u8 {
+        match self {
+            RtpCodec::Pcmu => 0,
+            RtpCodec::Pcma => 8,
+            #[cfg(feature = "opus")]
+            RtpCodec::Opus { .. } => 111,
+        }
+    }
+
+    /// Rate of the RTP timestamp clock, in Hz
+    fn clock_rate(self) -> u32 {
+        match self {
+            RtpCodec::Pcmu | RtpCodec::Pcma => 8_000,
+            #[cfg(feature = "opus")]
+            RtpCodec::Opus { .. } => 48_000,
+        }
+    }
+}
+
+impl std::str::FromStr for RtpCodec {
+    type Err = String;
+
+    /// `pcmu` (or `ulaw`), `pcma` (or `alaw`), or `opus` at its default bitrate
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pcmu" | "ulaw" | "g711u" => Ok(RtpCodec::Pcmu),
+            "pcma" | "alaw" | "g711a" => Ok(RtpCodec::Pcma),
+            #[cfg(feature = "opus")]
+            "opus" => Ok(RtpCodec::Opus {
+                bitrate: crate::utils::opus::DEFAULT_VOICE_BITRATE,
+            }),
+            _ => Err(format!(
+                "unknown RTP codec {:?}, expected pcmu, pcma or opus",
+                s
+            )),
+        }
+    }
+}
+
+/// G.711 μ-law code of a 16-bit sample
+fn linear_to_ulaw(sample: i16) -> u8 {
+    const BIAS: i32 = 0x84;
+    const CLIP: i32 = 32635;
+    let mut value = sample as i32;
+    let sign = if value < 0 {
+        value = -value;
+        0x80
+    } else {
+        0
+    };
+    let value = value.min(CLIP) + BIAS;
+    let exponent = (value >> 7).ilog2() as i32;
+    let mantissa = (value >> (exponent + 3)) & 0x0F;
+    !(sign | (exponent << 4) | mantissa) as u8
+}
+
+/// G.711 A-law code of a 16-bit sample
+fn linear_to_alaw(sample: i16) -> u8 {
+    let mut value = sample as i32 >> 3;
+    let mask = if value >= 0 {
+        0xD5
+    } else {
+        value = -value - 1;
+        0x55
+    };
+    let segment = if value < 0x20 {
+        0
+    } else {
+        (value >> 4).ilog2() as i32
+    };
+    let code = if segment < 2 {
+        (segment << 4) | ((value >> 1) & 0x0F)
+    } else {
+        (segment << 4) | ((value >> segment) & 0x0F)
+    };
+    (code ^ mask) as u8
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample * 32767.0).clamp(-32768.0, 32767.0) as i16
+}
+
+/// Downsamples by a whole factor, with a windowed-sinc low-pass filter against aliasing.
+/// Keeps its state between calls, so audio can be fed in chunks of any length.
+struct Decimator {
+    factor: usize,
+    taps: Vec<f32>,
+    /// Input from the start of the next output's window on
+    history: Vec<f32>,
+}
+
+impl Decimator {
+    const TAPS: usize = 63;
+
+    fn new(factor: usize) -> Self {
+        // Cut off a little below the new Nyquist frequency
+        let cutoff = 0.45 / factor as f32;
+        let middle = (Self::TAPS / 2) as f32;
+        let mut taps: Vec<f32> = (0..Self::TAPS)
+            .map(|i| {
+                let x = i as f32 - middle;
+                let sinc = if x == 0.0 {
+                    2.0 * cutoff
+                } else {
+                    (2.0 * std::f32::consts::PI * cutoff * x).sin() / (std::f32::consts::PI * x)
+                };
+                let window = 0.54
+                    - 0.46
+                        * (2.0 * std::f32::consts::PI * i as f32 / (Self::TAPS - 1) as f32).cos();
+                sinc * window
+            })
+            .collect();
+        let sum: f32 = taps.iter().sum();
+        taps.iter_mut().for_each(|tap| *tap /= sum);
+        Self {
+            factor,
+            taps,
+            history: vec![0.0; Self::TAPS / 2],
+        }
+    }
+
+    fn push(&mut self, audio: &[f32]) -> Vec<f32> {
+        if self.factor == 1 {
+            return audio.to_vec();
+        }
+        self.history.extend_from_slice(audio);
+        let mut output = Vec::new();
+        let mut start = 0;
+        while start + self.taps.len() <= self.history.len() {
+            let window = &self.history[start..start + self.taps.len()];
+            output.push(self.taps.iter().zip(window).map(|(t, s)| t * s).sum());
+            start += self.factor;
+        }
+        self.history.drain(..start);
+        output
+    }
+
+    /// The output still held back by the filter's delay, leaving the filter ready for
+    /// unrelated audio
+    fn finish(&mut self) -> Vec<f32> {
+        if self.factor == 1 {
+            return Vec::new();
+        }
+        let output = self.push(&[0.0; Self::TAPS / 2]);
+        self.history = vec![0.0; Self::TAPS / 2];
+        output
+    }
+}
+
+enum PayloadEncoder {
+    G711 {
+        codec: RtpCodec,
+        decimator: Decimator,
+        /// 8 kHz samples per packet
+        samples: usize,
+        pending: Vec<f32>,
+    },
+    #[cfg(feature = "opus")]
+    Opus(OpusFrameEncoder),
+}
+
+impl PayloadEncoder {
+    /// Payloads for the packets `audio` completes
+    fn push(&mut self, audio: &[f32]) -> io::Result<Vec<Vec<u8>>> {
+        match self {
+            PayloadEncoder::G711 {
+                codec,
+                decimator,
+                samples,
+                pending,
+            } => {
+                pending.extend(decimator.push(audio));
+                let mut payloads = Vec::new();
+                while pending.len() >= *samples {
+                    let packet: Vec<f32> = pending.drain(..*samples).collect();
+                    payloads.push(g711(*codec, &packet));
+                }
+                Ok(payloads)
+            }
+            #[cfg(feature = "opus")]
+            PayloadEncoder::Opus(encoder) => encoder.push(audio),
+        }
+    }
+
+    /// Payloads for the rest of the audio, the last one padded with silence
+    fn finish(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        match self {
+            PayloadEncoder::G711 {
+                codec,
+                decimator,
+                samples,
+                pending,
+            } => {
+                pending.extend(decimator.finish());
+                let mut payloads = Vec::new();
+                while !pending.is_empty() {
+                    let mut packet: Vec<f32> =
+                        pending.drain(..pending.len().min(*samples)).collect();
+                    packet.resize(*samples, 0.0);
+                    payloads.push(g711(*codec, &packet));
+                }
+                Ok(payloads)
+            }
+            #[cfg(feature = "opus")]
+            PayloadEncoder::Opus(encoder) => Ok(encoder.finish()?.into_iter().collect()),
+        }
+    }
+}
+
+fn g711(codec: RtpCodec, samples: &[f32]) -> Vec<u8> {
+    let encode = match codec {
+        RtpCodec::Pcma => linear_to_alaw,
+        _ => linear_to_ulaw,
+    };
+    samples.iter().map(|&s| encode(to_i16(s))).collect()
+}
+
+/// A random number for the SSRC and initial sequence number and timestamp, which RFC 3550
+/// asks to be unpredictable
+fn random_u32() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
+}
+
+/// Builds RTP packets from audio as it is synthesized
+pub struct RtpPacketizer {
+    encoder: PayloadEncoder,
+    payload_type: u8,
+    clock_rate: u32,
+    /// Clock ticks per packet
+    ticks: u32,
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+    /// Set on the first packet of each talkspurt, so the far end resyncs its jitter buffer
+    marker: bool,
+}
+
+impl RtpPacketizer {
+    /// A packetizer for mono audio at `sample_rate`, `ptime_ms` milliseconds of it per
+    /// packet. G.711 needs a sample rate that is a multiple of 8 kHz; Opus packets are
+    /// always 20 ms.
+    pub fn new(codec: RtpCodec, sample_rate: u32, ptime_ms: u32) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        if !(10..=120).contains(&ptime_ms) || !ptime_ms.is_multiple_of(10) {
+            return Err(invalid(format!(
+                "ptime {} ms is not a multiple of 10 between 10 and 120",
+                ptime_ms
+            )));
+        }
+        let encoder = match codec {
+            RtpCodec::Pcmu | RtpCodec::Pcma => {
+                if !sample_rate.is_multiple_of(8_000) {
+                    return Err(invalid(format!(
+                        "G.711 needs a multiple of 8 kHz, not {} Hz",
+                        sample_rate
+                    )));
+                }
+                PayloadEncoder::G711 {
+                    codec,
+                    decimator: Decimator::new((sample_rate / 8_000) as usize),
+                    samples: (8 * ptime_ms) as usize,
+                    pending: Vec::new(),
+                }
+            }
+            #[cfg(feature = "opus")]
+            RtpCodec::Opus { bitrate } => {
+                if ptime_ms as usize != crate::utils::opus::FRAME_MS {
+                    return Err(invalid(format!(
+                        "Opus packets are {} ms, not {} ms",
+                        crate::utils::opus::FRAME_MS,
+                        ptime_ms
+                    )));
+                }
+                PayloadEncoder::Opus(OpusFrameEncoder::new(sample_rate, bitrate)?)
+            }
+        };
+        Ok(Self {
+            encoder,
+            payload_type: codec.payload_type(),
+            clock_rate: codec.clock_rate(),
+            ticks: codec.clock_rate() / 1000 * ptime_ms,
+            ssrc: random_u32(),
+            sequence: random_u32() as u16,
+            timestamp: random_u32(),
+            marker: true,
+        })
+    }
+
+    /// Adds `audio` and returns the packets it completes
+    pub fn push(&mut self, audio: &[f32]) -> io::Result<Vec<Vec<u8>>> {
+        let payloads = self.encoder.push(audio)?;
+        Ok(payloads.into_iter().map(|p| self.packet(&p)).collect())
+    }
+
+    /// The last packets of a talkspurt, padded with silence; the next packet is marked as
+    /// the start of a new one
+    pub fn finish(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let payloads = self.encoder.finish()?;
+        let packets = payloads.into_iter().map(|p| self.packet(&p)).collect();
+        self.marker = true;
+        Ok(packets)
+    }
+
+    /// Moves the timestamp on by `elapsed` without sending, so the far end sees the
+    /// silence between talkspurts
+    pub fn skip(&mut self, elapsed: Duration) {
+        let ticks = elapsed.as_secs_f64() * self.clock_rate as f64;
+        self.timestamp = self.timestamp.wrapping_add(ticks as u32);
+    }
+
+    fn packet(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(12 + payload.len());
+        // Version 2, no padding, extension or CSRCs
+        packet.push(0x80);
+        packet.push(self.payload_type | if self.marker { 0x80 } else { 0 });
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&self.timestamp.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        packet.extend_from_slice(payload);
+        self.marker = false;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(self.ticks);
+        packet
+    }
+}
+
+/// Sends audio as RTP packets to one destination, one packet per `ptime`
+pub struct RtpSink {
+    socket: UdpSocket,
+    packetizer: RtpPacketizer,
+    ptime: Duration,
+    /// When the next packet is due, while a talkspurt is going
+    next_send: Option<Instant>,
+    /// When the last talkspurt finished playing
+    ended: Option<Instant>,
+}
+
+impl RtpSink {
+    /// A sink sending to `destination` from an ephemeral port
+    pub fn new(
+        destination: SocketAddr,
+        codec: RtpCodec,
+        sample_rate: u32,
+        ptime_ms: u32,
+    ) -> io::Result<Self> {
+        let local: SocketAddr = if destination.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(destination)?;
+        Ok(Self {
+            socket,
+            packetizer: RtpPacketizer::new(codec, sample_rate, ptime_ms)?,
+            ptime: Duration::from_millis(ptime_ms as u64),
+            next_send: None,
+            ended: None,
+        })
+    }
+
+    /// The local address packets are sent from, e.g. for the SDP of the call
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Sends the packets `audio` completes, each when it is due
+    pub fn send(&mut self, audio: &[f32]) -> io::Result<()> {
+        for packet in self.packetizer.push(audio)? {
+            self.send_packet(&packet)?;
+        }
+        Ok(())
+    }
+
+    /// Sends what is left of the audio and ends the talkspurt
+    pub fn finish(&mut self) -> io::Result<()> {
+        for packet in self.packetizer.finish()? {
+            self.send_packet(&packet)?;
+        }
+        if let Some(next_send) = self.next_send.take() {
+            // Let the last packet play out before the caller carries on
+            std::thread::sleep(next_send.saturating_duration_since(Instant::now()));
+            self.ended = Some(next_send);
+        }
+        Ok(())
+    }
+
+    fn send_packet(&mut self, packet: &[u8]) -> io::Result<()> {
+        let now = Instant::now();
+        let due = match self.next_send {
+            Some(due) => {
+                std::thread::sleep(due.saturating_duration_since(now));
+                due
+            }
+            None => {
+                if let Some(ended) = self.ended {
+                    self.packetizer.skip(now.saturating_duration_since(ended));
+                }
+                now
+            }
+        };
+        self.socket.send(packet)?;
+        self.next_send = Some(due + self.ptime);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtp_packetizer() {
+        assert_eq!(linear_to_ulaw(0), 0xFF);
+        assert_eq!(linear_to_ulaw(-1), 0x7F);
+        assert_eq!(linear_to_ulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_alaw(0), 0xD5);
+        assert_eq!(linear_to_alaw(i16::MIN), 0x2A);
+
+        assert!(RtpPacketizer::new(RtpCodec::Pcmu, 22_050, 20).is_err());
+        assert!(RtpPacketizer::new(RtpCodec::Pcmu, 24_000, 25).is_err());
+
+        let mut packetizer = RtpPacketizer::new(RtpCodec::Pcma, 24_000, 20).unwrap();
+        // 50 ms at 24 kHz is two full 20 ms packets of 160 bytes at 8 kHz
+        let packets = packetizer.push(&[0.0; 1200]).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].len(), 12 + 160);
+        assert_eq!(packets[0][1], 0x80 | 8);
+        assert_eq!(packets[1][1], 8);
+        let sequence = |p: &[u8]| u16::from_be_bytes([p[2], p[3]]);
+        let timestamp = |p: &[u8]| u32::from_be_bytes([p[4], p[5], p[6], p[7]]);
+        assert_eq!(sequence(&packets[1]), sequence(&packets[0]).wrapping_add(1));
+        assert_eq!(
+            timestamp(&packets[1]),
+            timestamp(&packets[0]).wrapping_add(160)
+        );
+        let last = packetizer.finish().unwrap();
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].len(), 12 + 160);
+        assert_eq!(packetizer.push(&[0.0; 960]).unwrap()[0][1], 0x80 | 8);
+    }
+}
@@ -1,4 +1,69 @@
-use std::io::{self, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::utils::audio::sample_to_i16;
+
+/// Sample format of a WAV file: integer PCM at 16 or 24 bits, or the model's own 32-bit
+/// floats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitDepth {
+    Int16,
+    Int24,
+    #[default]
+    Float32,
+}
+
+impl BitDepth {
+    pub fn bits(self) -> u16 {
+        match self {
+            Self::Int16 => 16,
+            Self::Int24 => 24,
+            Self::Float32 => 32,
+        }
+    }
+
+    /// Writes `sample` in this format, little-endian and clipped for the integer ones
+    pub fn write_sample<W: Write>(self, writer: &mut W, sample: f32) -> io::Result<()> {
+        match self {
+            Self::Int16 => writer.write_all(&sample_to_i16(sample).to_le_bytes()),
+            Self::Int24 => {
+                let value = (sample * 8_388_607.0).clamp(-8_388_608.0, 8_388_607.0) as i32;
+                writer.write_all(&value.to_le_bytes()[..3])
+            }
+            Self::Float32 => writer.write_all(&sample.to_le_bytes()),
+        }
+    }
+}
+
+impl std::str::FromStr for BitDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "16" => Ok(Self::Int16),
+            "24" => Ok(Self::Int24),
+            "32" | "f32" | "float" => Ok(Self::Float32),
+            _ => Err(format!("unknown bit depth {:?}, expected 16, 24 or 32", s)),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for BitDepth {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Bits(u16),
+            Name(String),
+        }
+        let text = match Repr::deserialize(deserializer)? {
+            Repr::Bits(bits) => bits.to_string(),
+            Repr::Name(name) => name,
+        };
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
 
 pub struct WavHeader {
     pub channels: u16,
@@ -24,7 +89,9 @@ impl WavHeader {
         // Format chunk
         writer.write_all(b"fmt ")?;
         writer.write_all(&(16u32).to_le_bytes())?; // Format chunk size
-        writer.write_all(&(3u16).to_le_bytes())?; // Format = 3 (IEEE float)
+        // Format = 3 (IEEE float) at 32 bits, 1 (integer PCM) otherwise
+        let format: u16 = if self.bits_per_sample == 32 { 3 } else { 1 };
+        writer.write_all(&format.to_le_bytes())?;
         writer.write_all(&self.channels.to_le_bytes())?;
         writer.write_all(&self.sample_rate.to_le_bytes())?;
         let byte_rate =
@@ -48,3 +115,128 @@ pub fn write_audio_chunk<W: Write>(writer: &mut W, samples: &[f32]) -> io::Resul
     }
     Ok(())
 }
+
+/// Writes `samples` to `writer` at `depth`
+pub fn write_samples<W: Write>(writer: &mut W, samples: &[f32], depth: BitDepth) -> io::Result<()> {
+    for &sample in samples {
+        depth.write_sample(writer, sample)?;
+    }
+    Ok(())
+}
+
+/// Writes mono `samples` to a complete WAV file at `path`, at `depth`, with every sample
+/// repeated on each of `channels` channels
+pub fn write_wav(
+    path: &Path,
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+    depth: BitDepth,
+) -> io::Result<()> {
+    let interleaved = samples
+        .iter()
+        .flat_map(|&sample| std::iter::repeat_n(sample, channels as usize));
+    write_interleaved(path, interleaved, sample_rate, channels, depth)
+}
+
+/// Writes `samples`, one frame of `channels` samples after another, to a complete WAV
+/// file at `path`, at `depth`
+pub fn write_interleaved(
+    path: &Path,
+    samples: impl IntoIterator<Item = f32>,
+    sample_rate: u32,
+    channels: u16,
+    depth: BitDepth,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let header = WavHeader::new(channels, sample_rate, depth.bits());
+    header.write_header(&mut writer)?;
+    let mut count = 0u64;
+    for sample in samples {
+        depth.write_sample(&mut writer, sample)?;
+        count += 1;
+    }
+    let data_size = count * u64::from(depth.bits() / 8);
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+    // The header's sizes, now that they are known: RIFF at 4, data at 40
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&((36 + data_size) as u32).to_le_bytes())?;
+    file.seek(SeekFrom::Start(40))?;
+    file.write_all(&(data_size as u32).to_le_bytes())?;
+    Ok(())
+}
+
+/// Appends a `LIST`/`INFO` chunk with `tags` (INFO ids like `ISFT` or `ICMT`, and their
+/// values) to the complete WAV file at `path`, updating the RIFF size to match. Players
+/// skip chunks they don't know, so the audio is unaffected.
+pub fn append_info(path: &Path, tags: &[([u8; 4], &str)]) -> io::Result<()> {
+    let mut list = b"INFO".to_vec();
+    for (id, value) in tags {
+        // Values are NUL-terminated and chunks padded to an even length
+        let size = value.len() + 1;
+        list.extend_from_slice(id);
+        list.extend_from_slice(&(size as u32).to_le_bytes());
+        list.extend_from_slice(value.as_bytes());
+        list.push(0);
+        if size % 2 == 1 {
+            list.push(0);
+        }
+    }
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut riff = [0u8; 4];
+    file.read_exact(&mut riff)?;
+    if &riff != b"RIFF" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a WAV file", path.display()),
+        ));
+    }
+    let end = file.seek(SeekFrom::End(0))?;
+    file.write_all(b"LIST")?;
+    file.write_all(&(list.len() as u32).to_le_bytes())?;
+    file.write_all(&list)?;
+    let riff_size = end + 8 + list.len() as u64 - 8;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&(riff_size as u32).to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_info() {
+        let path = std::env::temp_dir().join(format!("kokoros-info-{}.wav", std::process::id()));
+        let mut wav = Vec::new();
+        WavHeader::new(1, 24000, 32).write_header(&mut wav).unwrap();
+        write_audio_chunk(&mut wav, &[0.0, 0.5]).unwrap();
+        let riff_size = wav.len() as u32 - 8;
+        wav[4..8].copy_from_slice(&riff_size.to_le_bytes());
+        std::fs::write(&path, &wav).unwrap();
+
+        append_info(&path, &[(*b"ISFT", "kokoros"), (*b"ICMT", "ab")]).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[8..wav.len()], &wav[8..]);
+        let riff_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        assert_eq!(riff_size as usize, bytes.len() - 8);
+        assert_eq!(&bytes[wav.len()..wav.len() + 4], b"LIST");
+        assert!(bytes.ends_with(b"ICMT\x03\0\0\0ab\0\0"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_wav() {
+        let path = std::env::temp_dir().join(format!("kokoros-depth-{}.wav", std::process::id()));
+        write_wav(&path, &[0.5, -1.0], 24000, 2, BitDepth::Int24).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len(), 44 + 2 * 2 * 3);
+        assert_eq!(u16::from_le_bytes([bytes[20], bytes[21]]), 1);
+        assert_eq!(u16::from_le_bytes([bytes[34], bytes[35]]), 24);
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 12);
+        assert_eq!(&bytes[44..47], &[0xff, 0xff, 0x3f]);
+        assert_eq!(&bytes[50..53], &[0x01, 0x00, 0x80]);
+        assert_eq!("16".parse(), Ok(BitDepth::Int16));
+    }
+}
@@ -0,0 +1,57 @@
+/// The words of `text` for comparing transcripts: lowercased, with punctuation dropped and
+/// apostrophes removed ("Don't" and "dont" are the same word)
+pub fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '’'))
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Word error rate of `hypothesis` against `reference`: the words substituted, deleted and
+/// inserted, over the number of words in the reference. It is 0 for a perfect match and can
+/// go over 1 when words are added.
+pub fn word_error_rate(reference: &str, hypothesis: &str) -> f32 {
+    let reference = words(reference);
+    let hypothesis = words(hypothesis);
+    if reference.is_empty() {
+        return if hypothesis.is_empty() { 0.0 } else { 1.0 };
+    }
+
+    // Levenshtein distance over words, one row at a time
+    let mut previous: Vec<usize> = (0..=hypothesis.len()).collect();
+    for (i, expected) in reference.iter().enumerate() {
+        let mut current = vec![i + 1; hypothesis.len() + 1];
+        for (j, heard) in hypothesis.iter().enumerate() {
+            let substitution = previous[j] + usize::from(expected != heard);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[hypothesis.len()] as f32 / reference.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_error_rate() {
+        assert_eq!(
+            words("Don't stop, Mr. Smith!"),
+            ["dont", "stop", "mr", "smith"]
+        );
+        assert_eq!(
+            word_error_rate("Hello there, world.", "hello there world"),
+            0.0
+        );
+        // One substitution and one deletion
+        assert_eq!(word_error_rate("the cat sat down", "the hat sat"), 0.5);
+        assert_eq!(word_error_rate("yes", "yes yes yes"), 2.0);
+        assert_eq!(word_error_rate("", ""), 0.0);
+    }
+}